@@ -197,6 +197,20 @@ macro_rules! make_arena {
                 }
             }
 
+            /// Run the incremental garbage collector until either the allocation debt is <= 0.0 or
+            /// roughly `work` bytes worth of collection work has been done, whichever comes first.
+            /// This bounds the length of a single collection pause.
+            #[allow(unused)]
+            #[inline]
+            pub fn collect_work(&mut self, work: f64) {
+                unsafe {
+                    let debt = self.context.allocation_debt();
+                    if debt > 0.0 {
+                        self.context.do_collection(&*self.root, debt.min(work));
+                    }
+                }
+            }
+
             /// Run the current garbage collection cycle to completion, stopping once the garbage
             /// collector has entered the sleeping phase.  If the garbage collector is currently
             /// sleeping, starts a new cycle and runs that cycle to completion.
@@ -53,6 +53,7 @@ static_collect!(isize);
 static_collect!(f32);
 static_collect!(f64);
 static_collect!(String);
+static_collect!(std::time::Duration);
 
 unsafe impl<'a, T: ?Sized> Collect for &'a T {
     #[inline]
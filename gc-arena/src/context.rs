@@ -1,11 +1,14 @@
 use std::cell::{Cell, RefCell, UnsafeCell};
 use std::marker::PhantomData;
 use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{f64, mem, usize};
 
 use crate::arena::ArenaParameters;
 use crate::collect::Collect;
 use crate::types::{GcBox, GcColor, GcFlags, Invariant};
+use crate::weak::{GcWeak, WeakSlot};
 
 /// Handle value given by arena callbacks during construction and mutation.  Allows allocating new
 /// `Gc` pointers and internally mutating values held by `Gc` pointers.
@@ -23,6 +26,17 @@ impl<'gc, 'context> MutationContext<'gc, 'context> {
     pub(crate) unsafe fn write_barrier<T: 'gc + Collect>(self, ptr: NonNull<GcBox<T>>) {
         self.context.write_barrier(ptr)
     }
+
+    pub(crate) unsafe fn downgrade<T: 'gc + Collect>(self, ptr: NonNull<GcBox<T>>) -> GcWeak {
+        self.context.downgrade(ptr)
+    }
+
+    pub(crate) unsafe fn upgrade<T: 'gc + Collect>(
+        self,
+        weak: &GcWeak,
+    ) -> Option<NonNull<GcBox<T>>> {
+        self.context.upgrade(weak)
+    }
 }
 
 /// Handle value given by arena callbacks during garbage collection, which must be passed through
@@ -55,6 +69,11 @@ pub struct Context {
 
     gray: RefCell<Vec<NonNull<GcBox<Collect>>>>,
     gray_again: RefCell<Vec<NonNull<GcBox<Collect>>>>,
+
+    // Identifies this arena, so that a `GcWeak` is never upgraded in an arena other than its own.
+    id: usize,
+    // The targets of every `GcWeak` that may still be held outside of the arena.
+    weak: RefCell<Vec<Rc<WeakSlot>>>,
 }
 
 impl Drop for Context {
@@ -76,12 +95,17 @@ impl Drop for Context {
             }
         }
 
+        for slot in self.weak.borrow().iter() {
+            slot.ptr.set(None);
+        }
         DropAll(self.all.get());
     }
 }
 
 impl Context {
     pub unsafe fn new(parameters: ArenaParameters) -> Context {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
         Context {
             parameters,
             phase: Cell::new(Phase::Wake),
@@ -94,6 +118,8 @@ impl Context {
             sweep_prev: Cell::new(None),
             gray: RefCell::new(Vec::new()),
             gray_again: RefCell::new(Vec::new()),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            weak: RefCell::new(Vec::new()),
         }
     }
 
@@ -177,7 +203,9 @@ impl Context {
                         gc_box.flags.set_color(GcColor::Black);
                     } else {
                         // If we have no objects left in the normal gray queue, we enter the sweep
-                        // phase.
+                        // phase.  Every object which is still white is about to be freed, so this is
+                        // when weak pointers to them are cleared.
+                        self.clear_weak();
                         self.phase.set(Phase::Sweep);
                         self.sweep.set(self.all.get());
                     }
@@ -291,6 +319,39 @@ impl Context {
         }
     }
 
+    unsafe fn downgrade<T: Collect>(&self, ptr: NonNull<GcBox<T>>) -> GcWeak {
+        let slot = Rc::new(WeakSlot {
+            arena: self.id,
+            ptr: Cell::new(Some(static_gc_box(ptr))),
+        });
+        self.weak.borrow_mut().push(slot.clone());
+        GcWeak(slot)
+    }
+
+    // Upgrading a weak pointer to an object which has not been reached yet is safe, because the
+    // object can only be kept alive by storing it in another object, which the write barrier makes
+    // sure is traced again.
+    unsafe fn upgrade<T: Collect>(&self, weak: &GcWeak) -> Option<NonNull<GcBox<T>>> {
+        assert_eq!(
+            weak.0.arena, self.id,
+            "weak pointer upgraded in a different arena"
+        );
+        weak.0.ptr.get().map(|ptr| ptr.cast())
+    }
+
+    // Clears every weak pointer to a white object, and forgets weak pointers which are no longer
+    // held outside of the arena.
+    unsafe fn clear_weak(&self) {
+        self.weak.borrow_mut().retain(|slot| match slot.ptr.get() {
+            Some(ptr) if ptr.as_ref().flags.color() == GcColor::White => {
+                slot.ptr.set(None);
+                false
+            }
+            Some(_) => Rc::strong_count(slot) > 1,
+            None => false,
+        });
+    }
+
     unsafe fn trace<T: Collect>(&self, ptr: NonNull<GcBox<T>>) {
         let gc_box = ptr.as_ref();
         match gc_box.flags.color() {
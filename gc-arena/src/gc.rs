@@ -6,6 +6,7 @@ use std::ptr::NonNull;
 use crate::collect::Collect;
 use crate::context::{CollectionContext, MutationContext};
 use crate::types::{GcBox, Invariant};
+use crate::weak::GcWeak;
 
 /// A garbage collected pointer to a type T.  Implements Copy, and is implemented as a plain machine
 /// pointer.  You can only allocate `Gc` pointers through an `Allocator` inside an arena type, and
@@ -70,6 +71,26 @@ impl<'gc, T: 'gc + Collect> Gc<'gc, T> {
         Gc::as_ptr(this) == Gc::as_ptr(other)
     }
 
+    /// Creates a weak pointer to this object which can be held outside of the arena, see `GcWeak`.
+    pub fn downgrade(mc: MutationContext<'gc, '_>, this: Gc<'gc, T>) -> GcWeak {
+        unsafe { mc.downgrade(this.ptr) }
+    }
+
+    /// Returns a pointer to the object of a `GcWeak`, if it is still alive.
+    ///
+    /// Panics if the `GcWeak` was created in another arena.
+    ///
+    /// # Safety
+    ///
+    /// The `GcWeak` must have been created from a `Gc<'_, T>`, where `T` is the same type as this
+    /// one (ignoring lifetimes).
+    pub unsafe fn upgrade(mc: MutationContext<'gc, '_>, weak: &GcWeak) -> Option<Gc<'gc, T>> {
+        mc.upgrade(weak).map(|ptr| Gc {
+            ptr,
+            _invariant: PhantomData,
+        })
+    }
+
     pub fn as_ptr(gc: Gc<'gc, T>) -> *const T {
         unsafe { gc.ptr.as_ref().value.get() }
     }
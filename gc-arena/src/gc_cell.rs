@@ -4,6 +4,7 @@ use std::fmt::{self, Debug};
 use crate::collect::Collect;
 use crate::context::{CollectionContext, MutationContext};
 use crate::gc::Gc;
+use crate::weak::GcWeak;
 
 /// A garbage collected pointer to a type T that may be safely mutated.  When a type that may hold
 /// `Gc` pointers is mutated, it may adopt new `Gc` pointers, and in order for this to be safe this
@@ -52,6 +53,23 @@ impl<'gc, T: 'gc + Collect> GcCell<'gc, T> {
         self.0.cell.as_ptr()
     }
 
+    /// Creates a weak pointer to this object which can be held outside of the arena, see `GcWeak`.
+    pub fn downgrade(mc: MutationContext<'gc, '_>, this: GcCell<'gc, T>) -> GcWeak {
+        Gc::downgrade(mc, this.0)
+    }
+
+    /// Returns a pointer to the object of a `GcWeak`, if it is still alive.
+    ///
+    /// Panics if the `GcWeak` was created in another arena.
+    ///
+    /// # Safety
+    ///
+    /// The `GcWeak` must have been created from a `GcCell<'_, T>`, where `T` is the same type as
+    /// this one (ignoring lifetimes).
+    pub unsafe fn upgrade(mc: MutationContext<'gc, '_>, weak: &GcWeak) -> Option<GcCell<'gc, T>> {
+        Gc::upgrade(mc, weak).map(GcCell)
+    }
+
     pub fn read<'a>(&'a self) -> Ref<'a, T> {
         self.0.cell.borrow()
     }
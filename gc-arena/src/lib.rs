@@ -9,6 +9,7 @@ mod gc;
 mod gc_cell;
 mod static_collect;
 mod types;
+mod weak;
 
 pub use self::arena::*;
 pub use self::collect::*;
@@ -16,3 +17,4 @@ pub use self::context::*;
 pub use self::gc::*;
 pub use self::gc_cell::*;
 pub use self::static_collect::*;
+pub use self::weak::*;
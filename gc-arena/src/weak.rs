@@ -0,0 +1,46 @@
+use std::cell::Cell;
+use std::fmt::{self, Debug};
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use crate::collect::Collect;
+use crate::types::GcBox;
+
+/// A weak reference to a `Gc` or `GcCell` pointer which, unlike them, is not branded with the `'gc`
+/// lifetime, so that it may be held outside of the arena in between calls to `mutate`.
+///
+/// A `GcWeak` does not keep the object it points to alive.  Once the garbage collector has found
+/// that the object is unreachable, `is_alive` returns false and upgrading returns None.  Cloning a
+/// `GcWeak` produces another reference to the same object.
+#[derive(Clone)]
+pub struct GcWeak(pub(crate) Rc<WeakSlot>);
+
+impl GcWeak {
+    /// Returns whether the object is still alive, in which case it can be upgraded back into a
+    /// pointer during `mutate`.
+    pub fn is_alive(&self) -> bool {
+        self.0.ptr.get().is_some()
+    }
+
+    pub fn ptr_eq(this: &GcWeak, other: &GcWeak) -> bool {
+        match (this.0.ptr.get(), other.0.ptr.get()) {
+            (Some(a), Some(b)) => a.cast::<u8>() == b.cast::<u8>(),
+            _ => Rc::ptr_eq(&this.0, &other.0),
+        }
+    }
+}
+
+impl Debug for GcWeak {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GcWeak")
+            .field("alive", &self.is_alive())
+            .finish()
+    }
+}
+
+// The target of a `GcWeak`, shared with the arena so that it can be cleared when the object is
+// freed.
+pub(crate) struct WeakSlot {
+    pub(crate) arena: usize,
+    pub(crate) ptr: Cell<Option<NonNull<GcBox<Collect>>>>,
+}
@@ -130,6 +130,52 @@ fn all_garbage_collected() {
     assert_eq!(Rc::strong_count(&r.0), 1);
 }
 
+#[test]
+fn weak_pointers() {
+    #[derive(Collect)]
+    #[collect(empty_drop)]
+    struct TestRoot<'gc>(GcCell<'gc, Vec<Gc<'gc, i32>>>);
+    make_arena!(TestArena, TestRoot);
+
+    let mut arena = TestArena::new(ArenaParameters::default(), |mc| {
+        TestRoot(GcCell::allocate(mc, Vec::new()))
+    });
+
+    let (kept, dropped) = arena.mutate(|mc, root| {
+        let kept = Gc::allocate(mc, 1);
+        root.0.write(mc).push(kept);
+        (
+            Gc::downgrade(mc, kept),
+            GcCell::downgrade(mc, GcCell::allocate(mc, 2)),
+        )
+    });
+    assert!(kept.is_alive() && dropped.is_alive());
+
+    arena.collect_all();
+    arena.collect_all();
+    assert!(kept.is_alive());
+    assert!(!dropped.is_alive());
+    arena.mutate(|mc, root| unsafe {
+        let kept = Gc::<i32>::upgrade(mc, &kept).unwrap();
+        assert!(Gc::ptr_eq(kept, root.0.read()[0]));
+        assert!(GcCell::<i32>::upgrade(mc, &dropped).is_none());
+    });
+
+    // Upgrading an object that is only weakly reachable and storing it keeps it alive.
+    let revived = arena.mutate(|mc, _| Gc::downgrade(mc, Gc::allocate(mc, 3)));
+    arena.collect_debt();
+    arena.mutate(|mc, root| unsafe {
+        let revived = Gc::<i32>::upgrade(mc, &revived).unwrap();
+        root.0.write(mc).push(revived);
+    });
+    arena.collect_all();
+    arena.collect_all();
+    assert!(revived.is_alive());
+
+    drop(arena);
+    assert!(!kept.is_alive());
+}
+
 #[test]
 fn derive_collect() {
     #[allow(unused)]
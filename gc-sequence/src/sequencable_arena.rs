@@ -160,6 +160,14 @@ macro_rules! make_sequencable_arena {
                     self.0.collect_debt()
                 }
 
+                /// Runs the incremental garbage collector until the allocation debt is <= 0.0 or
+                /// roughly `work` bytes worth of collection work has been done.
+                #[allow(unused)]
+                #[inline]
+                $innervis fn collect_work(&mut self, work: f64) {
+                    self.0.collect_work(work)
+                }
+
                 /// Run the current garbage collection cycle to completion, stopping once the
                 /// garbage collector has entered the sleeping phase.
                 #[allow(unused)]
@@ -221,6 +229,12 @@ macro_rules! make_sequencable_arena {
                     self.0.collect_debt()
                 }
 
+                #[allow(unused)]
+                #[inline]
+                $innervis fn collect_work(&mut self, work: f64) {
+                    self.0.collect_work(work)
+                }
+
                 #[allow(unused)]
                 $innervis fn collect_all(&mut self) {
                     self.0.collect_all()
@@ -0,0 +1,54 @@
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+
+use proc_macro2::{Literal, TokenStream};
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+use luster::{compile, dump_proto, io, Lua};
+
+/// Compiles a Lua file when the crate using it is built, and expands to its bytecode as a
+/// `&'static [u8]`, which can be run with `Lua::run_bytecode` or loaded with `load_proto`.
+///
+/// The path is relative to the directory containing the calling crate's `Cargo.toml`.  Syntax
+/// errors in the script are reported as compile errors, and the crate is rebuilt whenever the
+/// script changes.
+#[proc_macro]
+pub fn include_lua(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let path = parse_macro_input!(input as LitStr);
+    match include(&path) {
+        Ok(tokens) => tokens.into(),
+        Err(message) => syn::Error::new(path.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn include(path: &LitStr) -> Result<TokenStream, String> {
+    let mut full_path = PathBuf::from(
+        env::var("CARGO_MANIFEST_DIR").map_err(|_| "CARGO_MANIFEST_DIR is not set".to_owned())?,
+    );
+    full_path.push(path.value());
+
+    let file = File::open(&full_path)
+        .and_then(io::buffered_read)
+        .map_err(|e| format!("could not read {}: {}", full_path.display(), e))?;
+    let full_path = full_path.to_string_lossy().into_owned();
+    let bytecode = Lua::new()
+        .mutate(|mc, root| {
+            compile(mc, root.interned_strings, file)
+                .map(|proto| dump_proto(&proto))
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| format!("could not compile {}: {}", full_path, e))?;
+
+    let bytecode = Literal::byte_string(&bytecode);
+    Ok(quote! {
+        {
+            // Makes cargo rebuild the calling crate when the script changes
+            const _: &[u8] = include_bytes!(#full_path);
+            #bytecode as &'static [u8]
+        }
+    })
+}
@@ -0,0 +1,26 @@
+use luster::{load_proto, verify, Lua, OwnedValue};
+use luster_macros::include_lua;
+
+const GREETING: &[u8] = include_lua!("tests/scripts/greeting.lua");
+
+#[test]
+fn runs_embedded_script() {
+    let mut lua = Lua::new();
+    assert_eq!(
+        lua.run_bytecode(GREETING).unwrap(),
+        vec![
+            OwnedValue::String(b"hello embedded".to_vec()),
+            OwnedValue::Integer(3)
+        ]
+    );
+}
+
+#[test]
+fn embedded_script_verifies() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = load_proto(mc, root.interned_strings, GREETING).unwrap();
+        assert_eq!(verify(&proto), Ok(()));
+        assert_eq!(proto.prototypes.len(), 1);
+    });
+}
@@ -0,0 +1,150 @@
+//! Arbitrary-precision integers for Lua, backed by `num-bigint`.
+//!
+//! The VM has no userdata yet, so big integers are passed around as decimal strings.  Every
+//! `bigint` library function accepts Lua integers, integral floats and decimal strings (with an
+//! optional leading `-`) interchangeably, and returns its results as normalized decimal strings,
+//! so results can be compared with `==`, printed and stored as table keys directly.
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Error, Root, RuntimeError, String, Table, TypeError, Value};
+
+/// Converts a Lua value into a `BigInt`, accepting integers, floats with an exact integer value
+/// and decimal strings.
+pub fn to_bigint(value: Value) -> Option<BigInt> {
+    match value {
+        Value::Integer(i) => Some(BigInt::from(i)),
+        Value::Number(n) if n.fract() == 0.0 && n.is_finite() => {
+            Some(BigInt::parse_bytes(format!("{:.0}", n).as_bytes(), 10)?)
+        }
+        Value::String(s) => {
+            let digits = s.as_bytes().strip_prefix(b"-").unwrap_or(s.as_bytes());
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            BigInt::parse_bytes(s.as_bytes(), 10)
+        }
+        _ => None,
+    }
+}
+
+/// Loads the `bigint` library, with the functions:
+///
+/// * `bigint.add(a, b)`, `bigint.sub(a, b)`, `bigint.mul(a, b)`
+/// * `bigint.div(a, b)` and `bigint.mod(a, b)`: floor division and modulo, as with `//` and `%`
+/// * `bigint.pow(a, n)`: `a` to the power of a non-negative Lua integer `n`
+/// * `bigint.neg(a)`, `bigint.abs(a)`
+/// * `bigint.cmp(a, b)`: returns -1, 0 or 1
+/// * `bigint.tostring(a)`: the normalized decimal string for `a`
+/// * `bigint.tointeger(a)`: `a` as a Lua integer, or nil if it does not fit in 64 bits
+pub fn load_bigint<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let bigint = Table::new(mc);
+
+    set_fn(mc, bigint, b"add", |args| {
+        Ok(arg_bigint(&args, 0)? + arg_bigint(&args, 1)?)
+    });
+    set_fn(mc, bigint, b"sub", |args| {
+        Ok(arg_bigint(&args, 0)? - arg_bigint(&args, 1)?)
+    });
+    set_fn(mc, bigint, b"mul", |args| {
+        Ok(arg_bigint(&args, 0)? * arg_bigint(&args, 1)?)
+    });
+    set_fn(mc, bigint, b"div", |args| {
+        Ok(arg_bigint(&args, 0)?.div_floor(&arg_divisor(&args, 1)?))
+    });
+    set_fn(mc, bigint, b"mod", |args| {
+        Ok(arg_bigint(&args, 0)?.mod_floor(&arg_divisor(&args, 1)?))
+    });
+    set_fn(mc, bigint, b"pow", |args| {
+        let base = arg_bigint(&args, 0)?;
+        let exponent = match args.get(1).cloned().unwrap_or(Value::Nil) {
+            Value::Integer(n) if n >= 0 && n <= i64::from(u32::MAX) => n as u32,
+            _ => {
+                return Err(RuntimeError(Value::String(String::new_static(
+                    b"bigint exponent must be a non-negative integer",
+                )))
+                .into());
+            }
+        };
+        Ok(num_traits::pow(base, exponent as usize))
+    });
+    set_fn(mc, bigint, b"neg", |args| Ok(-arg_bigint(&args, 0)?));
+    set_fn(mc, bigint, b"abs", |args| Ok(arg_bigint(&args, 0)?.abs()));
+    set_fn(mc, bigint, b"tostring", |args| arg_bigint(&args, 0));
+
+    bigint
+        .set(
+            mc,
+            String::new_static(b"cmp"),
+            Callback::new_immediate(mc, |args| {
+                let ordering = arg_bigint(&args, 0)?.cmp(&arg_bigint(&args, 1)?);
+                Ok(CallbackResult::Return(vec![Value::Integer(
+                    ordering as i64,
+                )]))
+            }),
+        )
+        .unwrap();
+
+    bigint
+        .set(
+            mc,
+            String::new_static(b"tointeger"),
+            Callback::new_immediate(mc, |args| {
+                let value = match arg_bigint(&args, 0)?.to_i64() {
+                    Some(i) => Value::Integer(i),
+                    None => Value::Nil,
+                };
+                Ok(CallbackResult::Return(vec![value]))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"bigint"), bigint).unwrap();
+}
+
+// Sets a library function that computes a `BigInt` and returns it as a decimal string.
+fn set_fn<'gc, F>(mc: MutationContext<'gc, '_>, lib: Table<'gc>, name: &'static [u8], f: F)
+where
+    F: 'static + Fn(Vec<Value<'gc>>) -> Result<BigInt, Error<'gc>>,
+{
+    let callback = Callback::new_sequence(mc, move |args| {
+        Ok(sequence::from_fn_with(
+            f(args)?.to_string(),
+            |mc, result| {
+                Ok(CallbackResult::Return(vec![Value::String(String::new(
+                    mc,
+                    result.as_bytes(),
+                ))]))
+            },
+        ))
+    });
+    lib.set(mc, String::new_static(name), callback).unwrap();
+}
+
+fn arg_bigint<'gc>(args: &[Value<'gc>], i: usize) -> Result<BigInt, Error<'gc>> {
+    let value = args.get(i).cloned().unwrap_or(Value::Nil);
+    to_bigint(value).ok_or_else(|| {
+        TypeError {
+            expected: "integer or decimal string",
+            found: value.type_name(),
+        }
+        .into()
+    })
+}
+
+fn arg_divisor<'gc>(args: &[Value<'gc>], i: usize) -> Result<BigInt, Error<'gc>> {
+    let divisor = arg_bigint(args, i)?;
+    if divisor.is_zero() {
+        Err(RuntimeError(Value::String(String::new_static(
+            b"bigint division by zero",
+        )))
+        .into())
+    } else {
+        Ok(divisor)
+    }
+}
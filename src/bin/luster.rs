@@ -1,13 +1,22 @@
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::vec::Vec;
 
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, SubCommand};
+use rayon::prelude::*;
 use rustyline::Editor;
 
+use gc_arena::Gc;
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 use luster::{
-    compile, io, Closure, Error, Function, Lua, ParserError, StaticError, ThreadSequence,
+    compile, compile_chunk, compile_named, compile_typed, dump_compiled, html_report, io,
+    lcov_record, load_file, parse_lexer_located, parse_repl_line, ChunkName, Closure, Error,
+    Function, Lexer, LineCoverage, LineNumber, Lua, OwnedValue, Replay, StaticError, StdLib,
+    ThreadSequence,
 };
 
 fn run_repl(lua: &mut Lua) {
@@ -27,19 +36,14 @@ fn run_repl(lua: &mut Lua) {
 
             match lua.sequence(move |root| {
                 sequence::from_fn_with(root, move |mc, root| {
-                    let result = compile(mc, root.interned_strings, line_clone.as_bytes());
-                    let result = match result {
-                        Ok(res) => Ok(res),
-                        err @ Err(Error::ParserError(ParserError::EndOfStream { expected: _ })) => {
-                            err
-                        }
-                        Err(_) => compile(
-                            mc,
-                            root.interned_strings,
-                            (String::new() + "return " + &line_clone).as_bytes(),
-                        ),
-                    };
-                    Ok(Closure::new(mc, result?, Some(root.globals))?)
+                    let chunk = parse_repl_line(line_clone.as_bytes(), |s| {
+                        root.interned_strings.new_string(mc, s)
+                    })?;
+                    Ok(Closure::new(
+                        mc,
+                        compile_chunk(mc, &chunk)?,
+                        Some(root.globals),
+                    )?)
                 })
                 .and_chain_with(root, |mc, root, closure| {
                     Ok(ThreadSequence::call_function(
@@ -62,12 +66,12 @@ fn run_repl(lua: &mut Lua) {
                 })
                 .boxed()
             }) {
-                err @ Err(StaticError::ParserError(ParserError::EndOfStream { expected: _ })) => {
+                Err(StaticError::ParserError(ref err)) if err.is_incomplete() => {
                     match line.chars().last() {
                         Some(c) => {
                             if c == '\n' {
                                 editor.add_history_entry(line);
-                                eprintln!("error: {}", err.err().unwrap());
+                                eprintln!("error: {}", err);
                                 break;
                             }
                             prompt = ">> ";
@@ -91,6 +95,290 @@ fn run_repl(lua: &mut Lua) {
     }
 }
 
+// Finds every file under the given path whose name ends with `suffix`, in sorted order
+fn find_files(
+    path: &Path,
+    suffix: &str,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn StdError>> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            find_files(&entry, suffix, files)?;
+        }
+    } else if path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(suffix))
+    {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CoverageFormat {
+    Lcov,
+    Html,
+}
+
+type TestReport = Result<Vec<OwnedValue>, StaticError>;
+type LineCounts = BTreeMap<LineNumber, u64>;
+
+// Runs a single test file in a new interpreter, returning the result of `luster.test.report` and
+// the line counts for the file if coverage is enabled
+fn run_test_file(source: Vec<u8>, coverage: bool) -> (TestReport, Option<LineCounts>) {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            test: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    let result = lua.sequence(move |root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            let proto = Gc::allocate(mc, compile(mc, root.interned_strings, &source[..])?);
+            if coverage {
+                let line_coverage = LineCoverage::new(mc);
+                line_coverage.track(mc, proto);
+                root.main_thread.set_line_coverage(mc, Some(line_coverage));
+            }
+            Ok(Closure::new_with_proto(mc, proto, Some(root.globals))?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|_| ())
+        .map_err(|e| e.to_static())
+        .boxed()
+    });
+    let lines = lua.mutate(|_, root| root.main_thread.line_coverage().map(|c| c.lines()));
+
+    (
+        result.and_then(|_| lua.run_string(b"return luster.test.report()")),
+        lines,
+    )
+}
+
+// Runs every test file under the given path in its own interpreter, returns whether every test
+// passed
+fn run_tests(
+    path: &Path,
+    coverage: Option<(CoverageFormat, &Path)>,
+) -> Result<bool, Box<dyn StdError>> {
+    let mut files = Vec::new();
+    find_files(path, "_test.lua", &mut files)?;
+
+    let mut total_passed = 0;
+    let mut total_failed = 0;
+    let mut lcov = String::new();
+    for file in &files {
+        let source = fs::read(file)?;
+        let (report, lines) = run_test_file(source.clone(), coverage.is_some());
+
+        if let (Some((format, output)), Some(lines)) = (coverage, &lines) {
+            let name = file.display().to_string();
+            match format {
+                CoverageFormat::Lcov => lcov.push_str(&lcov_record(&name, lines)),
+                CoverageFormat::Html => {
+                    fs::create_dir_all(output)?;
+                    let page = html_report(&name, &String::from_utf8_lossy(&source), lines);
+                    let file_name = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                    fs::write(output.join(file_name + ".html"), page)?;
+                }
+            }
+        }
+
+        match report.as_ref().map(|values| values.as_slice()) {
+            Ok(
+                [OwnedValue::Integer(passed), OwnedValue::Integer(failed), OwnedValue::String(failures)],
+            ) => {
+                total_passed += passed;
+                total_failed += failed;
+                if *failed == 0 {
+                    println!("PASS {} ({} passed)", file.display(), passed);
+                } else {
+                    println!(
+                        "FAIL {} ({} passed, {} failed)",
+                        file.display(),
+                        passed,
+                        failed
+                    );
+                    for line in String::from_utf8_lossy(failures).lines() {
+                        println!("    {}", line);
+                    }
+                }
+            }
+            Ok(_) => unreachable!("luster.test.report returns two integers and a string"),
+            Err(err) => {
+                total_failed += 1;
+                println!("FAIL {}", file.display());
+                println!("    error: {}", err);
+            }
+        }
+    }
+
+    if let Some((CoverageFormat::Lcov, output)) = coverage {
+        fs::write(output, lcov)?;
+    }
+
+    println!(
+        "{} files, {} passed, {} failed",
+        files.len(),
+        total_passed,
+        total_failed
+    );
+    Ok(total_failed == 0)
+}
+
+// Parses and compiles a file without running it, returning the error message, prefixed with the
+// file name and the line and column for syntax errors
+fn check_file(path: &Path) -> Result<(), String> {
+    let source = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut lua = Lua::builder().stdlib(StdLib::none()).build();
+    lua.mutate(|mc, root| {
+        let mut lexer = Lexer::new(&source[..], |s| root.interned_strings.new_string(mc, s));
+        lexer.set_chunk_name(ChunkName::file(&path.to_string_lossy()));
+        let chunk = parse_lexer_located(lexer).map_err(|err| err.to_string())?;
+        compile_chunk(mc, &chunk).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(())
+    })
+}
+
+// Checks every `.lua` file under the given paths in parallel, printing every error, returns whether
+// every file compiled
+fn run_check(paths: &[&Path]) -> Result<bool, Box<dyn StdError>> {
+    let mut files = Vec::new();
+    for path in paths {
+        find_files(path, ".lua", &mut files)?;
+    }
+
+    let errors = files
+        .par_iter()
+        .filter_map(|file| check_file(file).err())
+        .collect::<Vec<_>>();
+    for error in &errors {
+        println!("{}", error);
+    }
+
+    println!("{} files, {} with errors", files.len(), errors.len());
+    Ok(errors.is_empty())
+}
+
+// Compiles a file to a `.lusterc` file next to it, returning the error message prefixed with the
+// file name on failure
+fn compile_file(path: &Path, strip: bool, typed: bool) -> Result<(), String> {
+    let source = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut lua = Lua::builder().stdlib(StdLib::none()).build();
+    let compiled = lua.mutate(|mc, root| {
+        let reader =
+            io::buffered_read(&source[..]).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let proto = if typed {
+            compile_typed(mc, root.interned_strings, reader)
+        } else {
+            let chunk_name = ChunkName::file(&path.to_string_lossy());
+            compile_named(mc, root.interned_strings, chunk_name, reader)
+        };
+        match proto {
+            Ok(proto) => Ok(dump_compiled(&proto, &source, strip)),
+            // Syntax errors in named chunks already include the file name.
+            Err(err @ Error::SyntaxError(_)) => Err(err.to_string()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    });
+    fs::write(path.with_extension("lusterc"), compiled?)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Compiles every `.lua` file under the given paths in parallel, printing every error, returns
+// whether every file compiled
+fn run_compile(paths: &[&Path], strip: bool, typed: bool) -> Result<bool, Box<dyn StdError>> {
+    let mut files = Vec::new();
+    for path in paths {
+        find_files(path, ".lua", &mut files)?;
+    }
+
+    let errors = files
+        .par_iter()
+        .filter_map(|file| compile_file(file, strip, typed).err())
+        .collect::<Vec<_>>();
+    for error in &errors {
+        println!("{}", error);
+    }
+
+    println!(
+        "{} files, {} compiled, {} with errors",
+        files.len(),
+        files.len() - errors.len(),
+        errors.len()
+    );
+    Ok(errors.is_empty())
+}
+
+// Prints the tokens of a file, one per line with their location, or as a JSON array of objects with
+// `token` and `span` fields.  Names, strings and comments are converted to UTF-8 lossily.
+fn run_lex(path: &Path, json: bool, comments: bool) -> Result<(), Box<dyn StdError>> {
+    let source = io::buffered_read(File::open(path)?)?;
+    let create_string = |s: &[u8]| String::from_utf8_lossy(s).into_owned();
+    let mut lexer = if comments {
+        Lexer::with_comments(source, create_string)
+    } else {
+        Lexer::new(source, create_string)
+    };
+
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.read_spanned_token()? {
+        tokens.push(token);
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if json {
+        write_json_tokens(&mut out, &tokens)?;
+    } else {
+        for (token, span) in &tokens {
+            writeln!(out, "{}:{}\t{:?}", span.line_number.0, span.column, token)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serialize")]
+fn write_json_tokens(
+    out: &mut dyn Write,
+    tokens: &[(luster::Token<String>, luster::Span)],
+) -> Result<(), Box<dyn StdError>> {
+    #[derive(serde::Serialize)]
+    struct LexedToken<'a> {
+        token: &'a luster::Token<String>,
+        span: &'a luster::Span,
+    }
+
+    let tokens = tokens
+        .iter()
+        .map(|(token, span)| LexedToken { token, span })
+        .collect::<Vec<_>>();
+    serde_json::to_writer(&mut *out, &tokens)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serialize"))]
+fn write_json_tokens(
+    _: &mut dyn Write,
+    _: &[(luster::Token<String>, luster::Span)],
+) -> Result<(), Box<dyn StdError>> {
+    Err("--json requires luster to be built with the `serialize` feature".into())
+}
+
 fn main() -> Result<(), Box<StdError>> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
@@ -103,22 +391,178 @@ fn main() -> Result<(), Box<StdError>> {
                 .help("Load into REPL after loading file, if any"),
         )
         .arg(Arg::with_name("file").help("File to interpret").index(1))
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("REPLAY")
+                .conflicts_with("replay")
+                .help("Record the random inputs of the run to a replay file, for bug reports"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("REPLAY")
+                .help("Feed the inputs recorded with --record back into the run"),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Runs every *_test.lua file in a directory using luster.test")
+                .arg(
+                    Arg::with_name("path")
+                        .help("Directory or file to test")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::with_name("coverage")
+                        .long("coverage")
+                        .takes_value(true)
+                        .possible_values(&["lcov", "html"])
+                        .help("Collect line coverage of the test files"),
+                )
+                .arg(
+                    Arg::with_name("coverage-output")
+                        .long("coverage-output")
+                        .takes_value(true)
+                        .help(
+                            "LCOV file or HTML directory to write coverage to, defaults to \
+                             lcov.info or coverage/",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks that every .lua file in a directory compiles, without running them")
+                .arg(
+                    Arg::with_name("paths")
+                        .help("Directories or files to check")
+                        .multiple(true)
+                        .default_value("."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compile")
+                .about(
+                    "Compiles every .lua file in a directory to a .lusterc file next to it, which \
+                     is loaded instead of the source while the source is unchanged",
+                )
+                .arg(
+                    Arg::with_name("paths")
+                        .help("Directories or files to compile")
+                        .multiple(true)
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::with_name("strip")
+                        .long("strip")
+                        .help("Leave out line numbers and upvalue names"),
+                )
+                .arg(
+                    Arg::with_name("dialect")
+                        .long("dialect")
+                        .takes_value(true)
+                        .possible_values(&["lua", "typed"])
+                        .default_value("lua")
+                        .help("Plain Lua, or Lua with Luau-style type annotations"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lex")
+                .about("Prints the tokens of a file")
+                .arg(
+                    Arg::with_name("file")
+                        .help("File to lex")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the tokens and their spans as a JSON array"),
+                )
+                .arg(
+                    Arg::with_name("comments")
+                        .long("comments")
+                        .help("Include comments in the tokens"),
+                ),
+        )
         .get_matches();
 
-    let mut lua = Lua::new();
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let paths = matches
+            .values_of("paths")
+            .unwrap()
+            .map(Path::new)
+            .collect::<Vec<_>>();
+        let passed = run_check(&paths)?;
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        let paths = matches
+            .values_of("paths")
+            .unwrap()
+            .map(Path::new)
+            .collect::<Vec<_>>();
+        let passed = run_compile(
+            &paths,
+            matches.is_present("strip"),
+            matches.value_of("dialect") == Some("typed"),
+        )?;
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("lex") {
+        run_lex(
+            Path::new(matches.value_of("file").unwrap()),
+            matches.is_present("json"),
+            matches.is_present("comments"),
+        )?;
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("test") {
+        let coverage = match matches.value_of("coverage") {
+            Some("lcov") => Some(CoverageFormat::Lcov),
+            Some("html") => Some(CoverageFormat::Html),
+            _ => None,
+        };
+        let output = Path::new(
+            matches
+                .value_of("coverage-output")
+                .unwrap_or(match coverage {
+                    Some(CoverageFormat::Html) => "coverage",
+                    _ => "lcov.info",
+                }),
+        );
+        let passed = run_tests(
+            Path::new(matches.value_of("path").unwrap()),
+            coverage.map(|format| (format, output)),
+        )?;
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    let replay = match (matches.value_of("record"), matches.value_of("replay")) {
+        (Some(_), _) => Some(Replay::record()),
+        (_, Some(path)) => Some(Replay::load(&fs::read(path)?)?),
+        _ => None,
+    };
+    let mut lua = match &replay {
+        Some(replay) => Lua::builder().replay(replay.clone()).build(),
+        None => Lua::new(),
+    };
 
     if !matches.is_present("file") {
         run_repl(&mut lua);
         return Ok(());
     }
 
-    let file = io::buffered_read(File::open(matches.value_of("file").unwrap())?)?;
+    let file = PathBuf::from(matches.value_of("file").unwrap());
 
-    lua.sequence(|root| {
-        sequence::from_fn_with(root, |mc, root| {
+    let result = lua.sequence(|root| {
+        sequence::from_fn_with(root, move |mc, root| {
             Ok(Closure::new(
                 mc,
-                compile(mc, root.interned_strings, file)?,
+                load_file(mc, root.interned_strings, &file)?,
                 Some(root.globals),
             )?)
         })
@@ -133,7 +577,13 @@ fn main() -> Result<(), Box<StdError>> {
         .map_ok(|_| ())
         .map_err(|e| e.to_static())
         .boxed()
-    })?;
+    });
+
+    // The recording is saved even if the script fails, as that is usually what is being reported.
+    if let (Some(path), Some(replay)) = (matches.value_of("record"), &replay) {
+        fs::write(path, replay.to_bytes())?;
+    }
+    result?;
 
     if matches.is_present("repl") {
         run_repl(&mut lua);
@@ -0,0 +1,720 @@
+//! A binary format for compiled prototypes, so that scripts can be compiled ahead of time (see
+//! the `include_lua!` macro in `luster-macros`) and loaded without parsing.
+//!
+//! The format is only meant to be read by the same version of luster that wrote it, and is checked
+//! with `verify` when loaded, so that malformed bytecode is rejected instead of misbehaving when
+//! run.
+//!
+//! The `.lusterc` files written by `luster compile` wrap the same format with a hash of the source
+//! they were compiled from, see `dump_compiled` and `load_file`.
+
+use std::convert::TryInto;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gc_arena::{Collect, Gc, MutationContext};
+
+use crate::{
+    compile_named, verify, ChunkName, Constant, ConstantIndex16, ConstantIndex8, Error,
+    FunctionProto, InternedStringSet, LineNumber, LocalVariable, OpCode, Opt254, PrototypeIndex,
+    RegisterIndex, UpValueDescriptor, UpValueIndex, VarCount, VerifyError,
+};
+
+const MAGIC: &[u8] = b"\x1bLuster";
+const VERSION: u8 = 3;
+const COMPILED_MAGIC: &[u8] = b"\x1bLusterc";
+
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum BytecodeError {
+    BadHeader,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadTag,
+    TrailingData,
+    Invalid(VerifyError),
+}
+
+impl StdError for BytecodeError {}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::BadHeader => write!(fmt, "not luster bytecode"),
+            BytecodeError::UnsupportedVersion(v) => {
+                write!(fmt, "unsupported bytecode version {}", v)
+            }
+            BytecodeError::Truncated => write!(fmt, "bytecode is truncated"),
+            BytecodeError::BadTag => write!(fmt, "bytecode contains an unknown tag"),
+            BytecodeError::TrailingData => write!(fmt, "unexpected data after bytecode"),
+            BytecodeError::Invalid(error) => write!(fmt, "invalid bytecode: {}", error),
+        }
+    }
+}
+
+impl From<VerifyError> for BytecodeError {
+    fn from(error: VerifyError) -> BytecodeError {
+        BytecodeError::Invalid(error)
+    }
+}
+
+/// Serializes a prototype and all of its nested prototypes.
+pub fn dump_proto(proto: &FunctionProto) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.push(VERSION);
+    write_chunk_name(&mut buf, proto.chunk_name.as_ref());
+    write_proto(&mut buf, proto, false);
+    buf
+}
+
+/// The same as `dump_proto`, but leaves out the debug information: the source lines of opcodes,
+/// the names of upvalues and the chunk name.  Errors raised by the loaded prototype will have no
+/// line numbers.
+pub fn dump_stripped_proto(proto: &FunctionProto) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.push(VERSION);
+    write_chunk_name(&mut buf, None);
+    write_proto(&mut buf, proto, true);
+    buf
+}
+
+/// Serializes a prototype compiled from `source` into the contents of a `.lusterc` file, which
+/// records a hash of `source` so that `load_compiled` can tell whether the source has changed
+/// since.
+pub fn dump_compiled(proto: &FunctionProto, source: &[u8], strip: bool) -> Vec<u8> {
+    let mut buf = COMPILED_MAGIC.to_vec();
+    buf.extend_from_slice(&source_hash(source).to_le_bytes());
+    buf.extend(if strip {
+        dump_stripped_proto(proto)
+    } else {
+        dump_proto(proto)
+    });
+    buf
+}
+
+/// Loads the prototype in the contents of a `.lusterc` file written by `dump_compiled`, if it was
+/// compiled from `source`.  Returns `None` if it was compiled from a different source.
+pub fn load_compiled<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    compiled: &[u8],
+    source: &[u8],
+) -> Result<Option<FunctionProto<'gc>>, BytecodeError> {
+    if !compiled.starts_with(COMPILED_MAGIC) {
+        return Err(BytecodeError::BadHeader);
+    }
+    let mut reader = Reader(&compiled[COMPILED_MAGIC.len()..]);
+    if u64::from_le_bytes(reader.array()?) != source_hash(source) {
+        return Ok(None);
+    }
+    Ok(Some(load_proto(mc, interned_strings, reader.0)?))
+}
+
+/// Loads the Lua source file at `path`, preferring the `.lusterc` file next to it (the same path
+/// with its extension replaced) when that was compiled from the current contents of the file.
+/// Otherwise, the source is compiled with `compile_named`, naming the chunk after `path`, and a
+/// stale or unreadable `.lusterc` file is ignored.
+pub fn load_file<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    path: &Path,
+) -> Result<FunctionProto<'gc>, Error<'gc>> {
+    let source = fs::read(path)?;
+    let compiled = match fs::read(path.with_extension("lusterc")) {
+        Ok(compiled) => Some(compiled),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(compiled) = compiled {
+        if let Ok(Some(proto)) = load_compiled(mc, interned_strings, &compiled, &source) {
+            return Ok(proto);
+        }
+    }
+    compile_named(
+        mc,
+        interned_strings,
+        ChunkName::file(&path.to_string_lossy()),
+        crate::io::buffered_read(&source[..])?,
+    )
+}
+
+// The 64-bit FNV-1a hash, which unlike the hashers in `std` is fixed across platforms and
+// releases.
+fn source_hash(source: &[u8]) -> u64 {
+    source.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Deserializes a prototype written by `dump_proto`, and checks it with `verify`.
+///
+/// String constants are interned in `interned_strings`.
+pub fn load_proto<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    bytes: &[u8],
+) -> Result<FunctionProto<'gc>, BytecodeError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(BytecodeError::BadHeader);
+    }
+    let mut reader = Reader(&bytes[MAGIC.len()..]);
+    match reader.u8()? {
+        VERSION => {}
+        v => return Err(BytecodeError::UnsupportedVersion(v)),
+    }
+
+    let chunk_name = match reader.u8()? {
+        0 => None,
+        1 => Some(ChunkName::new(&String::from_utf8_lossy(reader.bytes()?))),
+        _ => return Err(BytecodeError::BadTag),
+    };
+    let proto = read_proto(mc, interned_strings, &chunk_name, &mut reader)?;
+    if !reader.0.is_empty() {
+        return Err(BytecodeError::TrailingData);
+    }
+    verify(&proto)?;
+    Ok(proto)
+}
+
+/// A single operand of an `OpCode`, see `OpCode::operands`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(RegisterIndex),
+    Constant8(ConstantIndex8),
+    Constant16(ConstantIndex16),
+    UpValue(UpValueIndex),
+    Prototype(PrototypeIndex),
+    Count(VarCount),
+    CloseUpValues(Opt254),
+    Jump(i16),
+    Bool(bool),
+    Byte(u8),
+}
+
+trait Encode: Sized {
+    fn encode(self, buf: &mut Vec<u8>);
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError>;
+    fn operand(self) -> Operand;
+
+    // Replaces the operand with `f` of it if it is a constant index, see `OpCode::map_constants`.
+    fn map_constant(self, _f: &mut dyn FnMut(usize) -> usize) -> Self {
+        self
+    }
+}
+
+impl Encode for RegisterIndex {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.0);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(RegisterIndex(reader.u8()?))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Register(self)
+    }
+}
+
+impl Encode for ConstantIndex8 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.0);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(ConstantIndex8(reader.u8()?))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Constant8(self)
+    }
+
+    fn map_constant(self, f: &mut dyn FnMut(usize) -> usize) -> Self {
+        ConstantIndex8(
+            f(self.0 as usize)
+                .try_into()
+                .expect("constant index out of range"),
+        )
+    }
+}
+
+impl Encode for ConstantIndex16 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(ConstantIndex16(u16::from_le_bytes(reader.array()?)))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Constant16(self)
+    }
+
+    fn map_constant(self, f: &mut dyn FnMut(usize) -> usize) -> Self {
+        ConstantIndex16(
+            f(self.0 as usize)
+                .try_into()
+                .expect("constant index out of range"),
+        )
+    }
+}
+
+impl Encode for UpValueIndex {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.0);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(UpValueIndex(reader.u8()?))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::UpValue(self)
+    }
+}
+
+impl Encode for PrototypeIndex {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.0);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(PrototypeIndex(reader.u8()?))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Prototype(self)
+    }
+}
+
+impl Encode for VarCount {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.to_constant().unwrap_or(255));
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(match reader.u8()? {
+            255 => VarCount::variable(),
+            c => VarCount::constant(c),
+        })
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Count(self)
+    }
+}
+
+impl Encode for Opt254 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self.to_u8().unwrap_or(255));
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(match reader.u8()? {
+            255 => Opt254::none(),
+            v => Opt254::some(v),
+        })
+    }
+
+    fn operand(self) -> Operand {
+        Operand::CloseUpValues(self)
+    }
+}
+
+impl Encode for i16 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(i16::from_le_bytes(reader.array()?))
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Jump(self)
+    }
+}
+
+impl Encode for bool {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self as u8);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        match reader.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(BytecodeError::BadTag),
+        }
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Bool(self)
+    }
+}
+
+impl Encode for u8 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        reader.u8()
+    }
+
+    fn operand(self) -> Operand {
+        Operand::Byte(self)
+    }
+}
+
+// Lists every opcode with its tag in the binary format and its fields in encoding order, and
+// generates the encoding, decoding, `OpCode::operands` and `OpCode::map_constants`.  Tags must never be reused for a
+// different opcode without changing `VERSION`.
+macro_rules! opcode_table {
+    ($($tag:literal => $name:ident { $($field:ident),* },)*) => {
+        fn write_opcode(buf: &mut Vec<u8>, opcode: OpCode) {
+            match opcode {
+                $(OpCode::$name { $($field),* } => {
+                    buf.push($tag);
+                    $($field.encode(buf);)*
+                })*
+            }
+        }
+
+        fn read_opcode(reader: &mut Reader) -> Result<OpCode, BytecodeError> {
+            Ok(match reader.u8()? {
+                $($tag => OpCode::$name { $($field: Encode::decode(reader)?),* },)*
+                _ => return Err(BytecodeError::BadTag),
+            })
+        }
+
+        impl OpCode {
+            /// Every operand of this opcode, in declaration order.
+            pub fn operands(self) -> Vec<Operand> {
+                match self {
+                    $(OpCode::$name { $($field),* } => vec![$($field.operand()),*],)*
+                }
+            }
+
+            // Returns this opcode with every constant index `c` it refers to replaced by `f(c)`.
+            // Panics if a new index does not fit in the operand.
+            pub(crate) fn map_constants(self, f: &mut dyn FnMut(usize) -> usize) -> OpCode {
+                match self {
+                    $(OpCode::$name { $($field),* } => OpCode::$name {
+                        $($field: $field.map_constant(f)),*
+                    },)*
+                }
+            }
+        }
+    };
+}
+
+opcode_table! {
+    0 => Move { dest, source },
+    1 => LoadConstant { dest, constant },
+    2 => LoadBool { dest, value, skip_next },
+    3 => LoadNil { dest, count },
+    4 => NewTable { dest },
+    5 => GetTableR { dest, table, key },
+    6 => GetTableC { dest, table, key },
+    7 => SetTableRR { table, key, value },
+    8 => SetTableRC { table, key, value },
+    9 => SetTableCR { table, key, value },
+    10 => SetTableCC { table, key, value },
+    11 => GetUpTableR { dest, table, key },
+    12 => GetUpTableC { dest, table, key },
+    13 => SetUpTableRR { table, key, value },
+    14 => SetUpTableRC { table, key, value },
+    15 => SetUpTableCR { table, key, value },
+    16 => SetUpTableCC { table, key, value },
+    17 => Call { func, args, returns },
+    18 => TailCall { func, args },
+    19 => Return { start, count },
+    20 => VarArgs { dest, count },
+    21 => Jump { offset, close_upvalues },
+    22 => Test { value, is_true },
+    23 => TestSet { dest, value, is_true },
+    24 => Closure { dest, proto },
+    25 => NumericForPrep { base, jump },
+    26 => NumericForLoop { base, jump },
+    27 => GenericForCall { base, var_count },
+    28 => GenericForLoop { base, jump },
+    29 => SelfR { base, table, key },
+    30 => SelfC { base, table, key },
+    31 => Concat { dest, source, count },
+    32 => GetUpValue { dest, source },
+    33 => SetUpValue { dest, source },
+    34 => Length { dest, source },
+    35 => EqRR { skip_if, left, right },
+    36 => EqRC { skip_if, left, right },
+    37 => EqCR { skip_if, left, right },
+    38 => EqCC { skip_if, left, right },
+    39 => LessRR { skip_if, left, right },
+    40 => LessRC { skip_if, left, right },
+    41 => LessCR { skip_if, left, right },
+    42 => LessCC { skip_if, left, right },
+    43 => LessEqRR { skip_if, left, right },
+    44 => LessEqRC { skip_if, left, right },
+    45 => LessEqCR { skip_if, left, right },
+    46 => LessEqCC { skip_if, left, right },
+    47 => Not { dest, source },
+    48 => Minus { dest, source },
+    49 => AddRR { dest, left, right },
+    50 => AddRC { dest, left, right },
+    51 => AddCR { dest, left, right },
+    52 => AddCC { dest, left, right },
+    53 => SubRR { dest, left, right },
+    54 => SubRC { dest, left, right },
+    55 => SubCR { dest, left, right },
+    56 => SubCC { dest, left, right },
+    57 => MulRR { dest, left, right },
+    58 => MulRC { dest, left, right },
+    59 => MulCR { dest, left, right },
+    60 => MulCC { dest, left, right },
+    61 => DivRR { dest, left, right },
+    62 => DivRC { dest, left, right },
+    63 => DivCR { dest, left, right },
+    64 => DivCC { dest, left, right },
+    65 => IDivRR { dest, left, right },
+    66 => IDivRC { dest, left, right },
+    67 => IDivCR { dest, left, right },
+    68 => IDivCC { dest, left, right },
+    69 => ModRR { dest, left, right },
+    70 => ModRC { dest, left, right },
+    71 => ModCR { dest, left, right },
+    72 => ModCC { dest, left, right },
+    73 => PowRR { dest, left, right },
+    74 => PowRC { dest, left, right },
+    75 => PowCR { dest, left, right },
+    76 => PowCC { dest, left, right },
+    77 => BitAndRR { dest, left, right },
+    78 => BitAndRC { dest, left, right },
+    79 => BitAndCR { dest, left, right },
+    80 => BitAndCC { dest, left, right },
+    81 => BitOrRR { dest, left, right },
+    82 => BitOrRC { dest, left, right },
+    83 => BitOrCR { dest, left, right },
+    84 => BitOrCC { dest, left, right },
+    85 => BitXorRR { dest, left, right },
+    86 => BitXorRC { dest, left, right },
+    87 => BitXorCR { dest, left, right },
+    88 => BitXorCC { dest, left, right },
+    89 => ShiftLeftRR { dest, left, right },
+    90 => ShiftLeftRC { dest, left, right },
+    91 => ShiftLeftCR { dest, left, right },
+    92 => ShiftLeftCC { dest, left, right },
+    93 => ShiftRightRR { dest, left, right },
+    94 => ShiftRightRC { dest, left, right },
+    95 => ShiftRightCR { dest, left, right },
+    96 => ShiftRightCC { dest, left, right },
+    97 => BitNot { dest, source },
+}
+
+// Writes `proto`, without line information or upvalue names if `strip` is set.
+fn write_proto(buf: &mut Vec<u8>, proto: &FunctionProto, strip: bool) {
+    buf.push(proto.fixed_params);
+    buf.push(proto.has_varargs as u8);
+    buf.extend_from_slice(&proto.stack_size.to_le_bytes());
+
+    write_len(buf, proto.constants.len());
+    for constant in &proto.constants {
+        match *constant {
+            Constant::Nil => buf.push(0),
+            Constant::Boolean(b) => {
+                buf.push(1);
+                buf.push(b as u8);
+            }
+            Constant::Integer(i) => {
+                buf.push(2);
+                buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Constant::Number(n) => {
+                buf.push(3);
+                buf.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Constant::String(s) => {
+                buf.push(4);
+                write_bytes(buf, s.as_bytes());
+            }
+        }
+    }
+
+    write_len(buf, proto.opcodes.len());
+    for &opcode in &proto.opcodes {
+        write_opcode(buf, opcode);
+    }
+
+    let opcode_lines: &[_] = if strip { &[] } else { &proto.opcode_lines };
+    write_len(buf, opcode_lines.len());
+    for &(pc, LineNumber(line)) in opcode_lines {
+        write_len(buf, pc);
+        buf.extend_from_slice(&line.to_le_bytes());
+    }
+
+    write_len(buf, proto.upvalues.len());
+    for (&upvalue, name) in proto.upvalues.iter().zip(&proto.upvalue_names) {
+        match upvalue {
+            UpValueDescriptor::Environment => buf.push(0),
+            UpValueDescriptor::ParentLocal(r) => {
+                buf.push(1);
+                buf.push(r.0);
+            }
+            UpValueDescriptor::Outer(u) => {
+                buf.push(2);
+                buf.push(u.0);
+            }
+        }
+        write_bytes(buf, if strip { b"" } else { name.as_bytes() });
+    }
+
+    let local_variables: &[_] = if strip { &[] } else { &proto.local_variables };
+    write_len(buf, local_variables.len());
+    for local in local_variables {
+        write_bytes(buf, local.name.as_bytes());
+        buf.push(local.register.0);
+        write_len(buf, local.start_pc);
+        write_len(buf, local.end_pc);
+    }
+
+    write_len(buf, proto.prototypes.len());
+    for proto in &proto.prototypes {
+        write_proto(buf, proto, strip);
+    }
+}
+
+// Writes the name shared by every prototype in the chunk, once before the main prototype.
+fn write_chunk_name(buf: &mut Vec<u8>, chunk_name: Option<&ChunkName>) {
+    match chunk_name {
+        Some(chunk_name) => {
+            buf.push(1);
+            write_bytes(buf, chunk_name.as_str().as_bytes());
+        }
+        None => buf.push(0),
+    }
+}
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    let len = len as u32;
+    buf.extend_from_slice(&len.to_le_bytes());
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+fn read_proto<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    chunk_name: &Option<ChunkName>,
+    reader: &mut Reader,
+) -> Result<FunctionProto<'gc>, BytecodeError> {
+    let fixed_params = reader.u8()?;
+    let has_varargs = bool::decode(reader)?;
+    let stack_size = u16::from_le_bytes(reader.array()?);
+
+    let mut constants = Vec::new();
+    for _ in 0..reader.len()? {
+        constants.push(match reader.u8()? {
+            0 => Constant::Nil,
+            1 => Constant::Boolean(bool::decode(reader)?),
+            2 => Constant::Integer(i64::from_le_bytes(reader.array()?)),
+            3 => Constant::Number(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            4 => Constant::String(interned_strings.new_string(mc, reader.bytes()?)),
+            _ => return Err(BytecodeError::BadTag),
+        });
+    }
+
+    let mut opcodes = Vec::new();
+    for _ in 0..reader.len()? {
+        opcodes.push(read_opcode(reader)?);
+    }
+
+    let mut opcode_lines = Vec::new();
+    for _ in 0..reader.len()? {
+        let pc = reader.len()?;
+        let line = u64::from_le_bytes(reader.array()?);
+        opcode_lines.push((pc, LineNumber(line)));
+    }
+
+    let mut upvalues = Vec::new();
+    let mut upvalue_names = Vec::new();
+    for _ in 0..reader.len()? {
+        upvalues.push(match reader.u8()? {
+            0 => UpValueDescriptor::Environment,
+            1 => UpValueDescriptor::ParentLocal(RegisterIndex(reader.u8()?)),
+            2 => UpValueDescriptor::Outer(UpValueIndex(reader.u8()?)),
+            _ => return Err(BytecodeError::BadTag),
+        });
+        upvalue_names.push(interned_strings.new_string(mc, reader.bytes()?));
+    }
+
+    let mut local_variables = Vec::new();
+    for _ in 0..reader.len()? {
+        local_variables.push(LocalVariable {
+            name: interned_strings.new_string(mc, reader.bytes()?),
+            register: RegisterIndex(reader.u8()?),
+            start_pc: reader.len()?,
+            end_pc: reader.len()?,
+        });
+    }
+
+    let mut prototypes = Vec::new();
+    for _ in 0..reader.len()? {
+        prototypes.push(Gc::allocate(
+            mc,
+            read_proto(mc, interned_strings, chunk_name, reader)?,
+        ));
+    }
+
+    Ok(FunctionProto {
+        fixed_params,
+        has_varargs,
+        stack_size,
+        constants,
+        opcodes,
+        opcode_lines,
+        upvalues,
+        upvalue_names,
+        local_variables,
+        prototypes,
+        chunk_name: chunk_name.clone(),
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        let (&b, rest) = self.0.split_first().ok_or(BytecodeError::Truncated)?;
+        self.0 = rest;
+        Ok(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        if self.0.len() < N {
+            return Err(BytecodeError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn len(&mut self) -> Result<usize, BytecodeError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], BytecodeError> {
+        let len = self.len()?;
+        if self.0.len() < len {
+            return Err(BytecodeError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+}
@@ -1,8 +1,10 @@
+use std::cell::RefCell;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
 use gc_arena::{Collect, Gc, MutationContext, StaticCollect};
-use gc_sequence::{Sequence, SequenceExt};
+use gc_sequence::{self as sequence, Sequence, SequenceExt};
 
 use crate::{Error, Function, Value};
 
@@ -230,6 +232,36 @@ impl<'gc> Callback<'gc> {
         })
     }
 
+    /// Creates a callback that pulls the next item from `iter` each time it is called and returns
+    /// it converted to Lua values by `convert`, or returns nothing once `iter` is exhausted.  The
+    /// callback can be used directly as the iterator function of a generic `for` loop, so a large
+    /// host collection can be looped over from Lua without first copying it into a table.
+    ///
+    /// A generic `for` loop stops at the first nil control value, so `convert` should never return
+    /// nil as its first value.
+    pub fn new_iterator<I, F>(mc: MutationContext<'gc, '_>, iter: I, convert: F) -> Callback<'gc>
+    where
+        I: 'static + Iterator,
+        I::Item: 'static,
+        F: 'static + Fn(MutationContext<'gc, '_>, I::Item) -> Vec<Value<'gc>>,
+    {
+        let iter = RefCell::new(iter.fuse());
+        let convert = Rc::new(convert);
+        Callback::new(mc, move |_| {
+            let item = iter.borrow_mut().next();
+            match item {
+                Some(item) => {
+                    let convert = convert.clone();
+                    CallbackReturn::Sequence(
+                        sequence::from_fn(move |mc| Ok(CallbackResult::Return(convert(mc, item))))
+                            .boxed(),
+                    )
+                }
+                None => CallbackReturn::Immediate(Ok(CallbackResult::Return(Vec::new()))),
+            }
+        })
+    }
+
     pub fn call(&self, args: Vec<Value<'gc>>) -> CallbackReturn<'gc> {
         self.0.call(args)
     }
@@ -4,7 +4,10 @@ use std::hash::{Hash, Hasher};
 
 use gc_arena::{Collect, Gc, GcCell, MutationContext};
 
-use crate::{Constant, OpCode, RegisterIndex, Table, Thread, UpValueIndex, Value};
+use crate::{
+    ChunkName, Constant, LineNumber, OpCode, RegisterIndex, String, Table, Thread, UpValueIndex,
+    Value,
+};
 
 #[derive(Debug, Collect, Clone, Copy, PartialEq, Eq)]
 #[collect(require_static)]
@@ -22,8 +25,81 @@ pub struct FunctionProto<'gc> {
     pub stack_size: u16,
     pub constants: Vec<Constant<'gc>>,
     pub opcodes: Vec<OpCode>,
+    /// Source line information for `opcodes`, sorted by opcode index.  Each entry gives the line
+    /// for every opcode from its index up to the index of the next entry.
+    pub opcode_lines: Vec<(usize, LineNumber)>,
     pub upvalues: Vec<UpValueDescriptor>,
+    /// The name of each upvalue in `upvalues`, as it was written in the source.
+    pub upvalue_names: Vec<String<'gc>>,
+    /// Every local variable declared in the function, in order of declaration.
+    pub local_variables: Vec<LocalVariable<'gc>>,
     pub prototypes: Vec<Gc<'gc, FunctionProto<'gc>>>,
+    /// The name of the chunk this function was compiled from, shared by every function in it.
+    pub chunk_name: Option<ChunkName>,
+}
+
+impl<'gc> FunctionProto<'gc> {
+    /// Returns the source line that the opcode at index `pc` was compiled from, if known.
+    pub fn opcode_line(&self, pc: usize) -> Option<LineNumber> {
+        match self.opcode_lines.binary_search_by_key(&pc, |&(i, _)| i) {
+            Ok(i) => Some(self.opcode_lines[i].1),
+            Err(0) => None,
+            Err(i) => Some(self.opcode_lines[i - 1].1),
+        }
+    }
+
+    /// Returns the local variables in scope at the opcode at index `pc`, in order of declaration.
+    pub fn active_locals(&self, pc: usize) -> impl Iterator<Item = LocalVariable<'gc>> + '_ {
+        self.local_variables
+            .iter()
+            .copied()
+            .filter(move |local| local.start_pc <= pc && pc < local.end_pc)
+    }
+
+    /// Returns a summary of this prototype's signature and debug information.
+    pub fn info(&self) -> FunctionInfo<'gc> {
+        let mut lines = self.opcode_lines.iter().map(|&(_, line)| line);
+        let lines = lines.next().map(|first| {
+            lines.fold((first, first), |(min, max), line| {
+                (min.min(line), max.max(line))
+            })
+        });
+
+        FunctionInfo {
+            fixed_params: self.fixed_params,
+            has_varargs: self.has_varargs,
+            lines,
+            upvalue_names: self.upvalue_names.clone(),
+        }
+    }
+}
+
+/// A named local variable of a function, including its parameters.
+#[derive(Debug, Collect, Clone, Copy, PartialEq, Eq)]
+#[collect(require_copy)]
+pub struct LocalVariable<'gc> {
+    pub name: String<'gc>,
+    /// The register that holds the variable while it is in scope.
+    pub register: RegisterIndex,
+    /// The variable is in scope from the opcode at `start_pc` up to, but not including, the opcode
+    /// at `end_pc`.
+    pub start_pc: usize,
+    pub end_pc: usize,
+}
+
+/// Metadata about a compiled Lua function, as returned by `Function::info`.
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(empty_drop)]
+pub struct FunctionInfo<'gc> {
+    /// The number of named parameters.
+    pub fixed_params: u8,
+    /// Whether the function also accepts `...`.
+    pub has_varargs: bool,
+    /// The first and last source lines of the function body, or `None` if the body is empty.
+    pub lines: Option<(LineNumber, LineNumber)>,
+    /// The names of the variables the function captures from enclosing scopes, including `_ENV`
+    /// if it accesses any globals.
+    pub upvalue_names: Vec<String<'gc>>,
 }
 
 #[derive(Debug, Collect, Copy, Clone)]
@@ -37,6 +113,18 @@ pub enum UpValueState<'gc> {
 #[collect(require_copy)]
 pub struct UpValue<'gc>(pub GcCell<'gc, UpValueState<'gc>>);
 
+impl<'gc> UpValue<'gc> {
+    /// Returns the current value of this upvalue, whether it is open or closed.
+    ///
+    /// Must not be called while the thread of an open upvalue is being stepped.
+    pub fn get(self) -> Value<'gc> {
+        match *self.0.read() {
+            UpValueState::Open(thread, ind) => thread.stack_value(ind),
+            UpValueState::Closed(v) => v,
+        }
+    }
+}
+
 #[derive(Debug, Collect)]
 #[collect(empty_drop)]
 pub struct ClosureState<'gc> {
@@ -93,7 +181,15 @@ impl<'gc> Closure<'gc> {
         proto: FunctionProto<'gc>,
         environment: Option<Table<'gc>>,
     ) -> Result<Closure<'gc>, ClosureError> {
-        let proto = Gc::allocate(mc, proto);
+        Closure::new_with_proto(mc, Gc::allocate(mc, proto), environment)
+    }
+
+    /// The same as `Closure::new`, but for an already allocated (and possibly shared) prototype.
+    pub fn new_with_proto(
+        mc: MutationContext<'gc, '_>,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+        environment: Option<Table<'gc>>,
+    ) -> Result<Closure<'gc>, ClosureError> {
         let mut upvalues = Vec::new();
 
         if !proto.upvalues.is_empty() {
@@ -0,0 +1,204 @@
+//! A thin API layer shaped like `rlua`, to ease porting existing embedding code to luster.
+//!
+//! Unlike the rest of luster, nothing in this module is "stackless": calling a Lua function through
+//! `Function::call` runs it to completion inside a single `Lua::context` call, so no garbage
+//! collection can take place while a call is in progress, and functions called this way are not
+//! allowed to yield.  For long running scripts, prefer the `Sequence` based API.
+
+use gc_arena::MutationContext;
+use gc_sequence::Sequence;
+
+use crate::{
+    compile_named, ChunkName, Closure, Error, Function as LuaFunction, Lua, Root, RuntimeError,
+    String, Table as LuaTable, Thread, ThreadSequence, Value,
+};
+
+impl Lua {
+    /// Enters the Lua arena with an rlua-style `Context` handle.
+    pub fn context<F, R>(&mut self, f: F) -> R
+    where
+        R: 'static,
+        F: for<'gc, 'a> FnOnce(Context<'gc, 'a>) -> R,
+    {
+        let instruction_budget = self.configured_instruction_budget();
+        self.mutate(move |mc, root| {
+            f(Context {
+                mc,
+                root,
+                instruction_budget,
+            })
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Context<'gc, 'a> {
+    mc: MutationContext<'gc, 'a>,
+    root: Root<'gc>,
+    // The budget of each `Function::call`, see `LusterBuilder::instruction_budget`.
+    instruction_budget: Option<u64>,
+}
+
+impl<'gc, 'a> Context<'gc, 'a> {
+    pub fn mutation_context(self) -> MutationContext<'gc, 'a> {
+        self.mc
+    }
+
+    pub fn root(self) -> Root<'gc> {
+        self.root
+    }
+
+    pub fn globals(self) -> Table<'gc, 'a> {
+        Table {
+            context: self,
+            table: self.root.globals,
+        }
+    }
+
+    pub fn create_table(self) -> Table<'gc, 'a> {
+        Table {
+            context: self,
+            table: LuaTable::new(self.mc),
+        }
+    }
+
+    pub fn create_string(self, s: &[u8]) -> String<'gc> {
+        self.root.interned_strings.new_string(self.mc, s)
+    }
+
+    /// Prepares a chunk of Lua source for loading, it is not compiled until it is used.
+    pub fn load<'s, S: AsRef<[u8]> + ?Sized>(self, source: &'s S) -> Chunk<'gc, 'a, 's> {
+        Chunk {
+            context: self,
+            source: source.as_ref(),
+            name: None,
+        }
+    }
+}
+
+pub struct Chunk<'gc, 'a, 's> {
+    context: Context<'gc, 'a>,
+    source: &'s [u8],
+    name: Option<ChunkName>,
+}
+
+impl<'gc, 'a, 's> Chunk<'gc, 'a, 's> {
+    /// Names the chunk, see `ChunkName`.  Unnamed chunks are named after their source, like chunks
+    /// given to `load`.
+    pub fn set_name(mut self, name: &str) -> Chunk<'gc, 'a, 's> {
+        self.name = Some(ChunkName::new(name));
+        self
+    }
+
+    /// Compiles the chunk into a function with the globals table as its environment.
+    pub fn into_function(self) -> Result<Function<'gc, 'a>, Error<'gc>> {
+        let Chunk {
+            context,
+            source,
+            name,
+        } = self;
+        let Context { mc, root, .. } = context;
+        let closure = Closure::new(
+            mc,
+            compile_named(
+                mc,
+                root.interned_strings,
+                name.unwrap_or_else(|| ChunkName::from_source(source)),
+                source,
+            )?,
+            Some(root.globals),
+        )?;
+        Ok(Function {
+            context,
+            function: LuaFunction::Closure(closure),
+        })
+    }
+
+    /// Compiles and runs the chunk, discarding any returned values.
+    pub fn exec(self) -> Result<(), Error<'gc>> {
+        self.into_function()?.call(&[])?;
+        Ok(())
+    }
+
+    /// Compiles and runs the chunk, returning every value it returns.
+    pub fn eval(self) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+        self.into_function()?.call(&[])
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Table<'gc, 'a> {
+    context: Context<'gc, 'a>,
+    table: LuaTable<'gc>,
+}
+
+impl<'gc, 'a> Table<'gc, 'a> {
+    pub fn get<K: Into<Value<'gc>>>(self, key: K) -> Value<'gc> {
+        self.table.get(key)
+    }
+
+    pub fn set<K: Into<Value<'gc>>, V: Into<Value<'gc>>>(
+        self,
+        key: K,
+        value: V,
+    ) -> Result<(), Error<'gc>> {
+        self.table.set(self.context.mc, key, value)?;
+        Ok(())
+    }
+
+    pub fn len(self) -> i64 {
+        self.table.length()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_inner(self) -> LuaTable<'gc> {
+        self.table
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Function<'gc, 'a> {
+    context: Context<'gc, 'a>,
+    function: LuaFunction<'gc>,
+}
+
+impl<'gc, 'a> Function<'gc, 'a> {
+    pub fn new(context: Context<'gc, 'a>, function: LuaFunction<'gc>) -> Function<'gc, 'a> {
+        Function { context, function }
+    }
+
+    /// Calls this function on a new, non-yieldable thread with the settings of the main thread, and
+    /// runs it to completion.  The call is stopped with an error if it exceeds the instruction
+    /// budget of the `Lua` instance, see `LusterBuilder::instruction_budget`.
+    pub fn call(self, args: &[Value<'gc>]) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+        let Context {
+            mc,
+            root,
+            instruction_budget,
+        } = self.context;
+        let thread = Thread::new_inheriting(mc, root.main_thread, false);
+        let started = thread.executed_instructions();
+        let mut sequence = ThreadSequence::call_function(mc, thread, self.function, args)?;
+        loop {
+            if let Some(res) = sequence.step(mc) {
+                return res;
+            }
+            if instruction_budget
+                .is_some_and(|budget| thread.executed_instructions().wrapping_sub(started) > budget)
+            {
+                thread.cancel(mc);
+                return Err(RuntimeError(Value::String(String::new_static(
+                    b"instruction budget exceeded",
+                )))
+                .into());
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> LuaFunction<'gc> {
+        self.function
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, VecDeque};
 use std::error::Error as StdError;
+use std::rc::Rc;
 use std::{fmt, iter, mem};
 
 use num_traits::cast;
@@ -11,21 +12,29 @@ use crate::parser::{
     ConstructorField, Expression, FieldSuffix, ForStatement, FunctionCallStatement,
     FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalFunctionStatement,
     LocalStatement, PrimaryExpression, RecordKey, RepeatStatement, ReturnStatement,
-    SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor, UnaryOperator,
-    WhileStatement,
+    SimpleExpression, Spanned, Statement, SuffixPart, SuffixedExpression, TableConstructor,
+    UnaryOperator, WhileStatement,
 };
 use crate::{
-    Constant, ConstantIndex16, ConstantIndex8, FunctionProto, OpCode, Opt254, PrototypeIndex,
-    RegisterIndex, String, UpValueDescriptor, UpValueIndex, VarCount,
+    ChunkName, Constant, ConstantIndex16, ConstantIndex8, FunctionProto, LineNumber, LocalVariable,
+    OpCode, Opt254, PrototypeIndex, RegisterIndex, String, UpValueDescriptor, UpValueIndex,
+    VarCount,
 };
 
+use super::escape::{closure_assignments, function_names, inlinable_names, only_called, Scope};
 use super::operators::{
     categorize_binop, comparison_binop_const_fold, comparison_binop_opcode,
     simple_binop_const_fold, simple_binop_opcode, unop_const_fold, unop_opcode, BinOpCategory,
     ComparisonBinOp, RegisterOrConstant, ShortCircuitBinOp, SimpleBinOp,
 };
+use super::optimize::Optimizations;
 use super::register_allocator::RegisterAllocator;
 
+// Local functions are only inlined if their compiled bodies are at most this many opcodes and
+// registers.
+const MAX_INLINE_OPCODES: usize = 24;
+const MAX_INLINE_REGISTERS: u16 = 8;
+
 #[derive(Debug, Collect)]
 #[collect(require_static)]
 pub enum CompilerError {
@@ -63,20 +72,42 @@ impl fmt::Display for CompilerError {
 pub fn compile_chunk<'gc>(
     mc: MutationContext<'gc, '_>,
     chunk: &Chunk<String<'gc>>,
+) -> Result<FunctionProto<'gc>, CompilerError> {
+    compile_chunk_with(mc, chunk, Optimizations::default())
+}
+
+// Compiles a chunk, applying the optimizations which must be done while compiling, such as
+// `Optimizations::lift_local_functions`.  The rest are ignored.
+pub(super) fn compile_chunk_with<'gc>(
+    mc: MutationContext<'gc, '_>,
+    chunk: &Chunk<String<'gc>>,
+    optimizations: Optimizations,
 ) -> Result<FunctionProto<'gc>, CompilerError> {
     let mut compiler = Compiler {
         mutation_context: mc,
         current_function: CompilerFunction::start(&[], true)?,
         upper_functions: Vec::new(),
+        optimizations,
+        inlining: false,
+        chunk_name: chunk.chunk_name.clone(),
     };
+    if optimizations.lift_local_functions {
+        compiler.current_function.closure_assignments = closure_assignments(&chunk.block);
+    }
     compiler.block(&chunk.block)?;
-    compiler.current_function.finish(mc)
+    compiler
+        .current_function
+        .finish(mc, compiler.chunk_name.clone())
 }
 
 struct Compiler<'gc, 'a> {
     mutation_context: MutationContext<'gc, 'a>,
     current_function: CompilerFunction<'gc>,
     upper_functions: Vec<CompilerFunction<'gc>>,
+    optimizations: Optimizations,
+    // True while compiling the body of an inlined function, which is never inlined into again.
+    inlining: bool,
+    chunk_name: Option<ChunkName>,
 }
 
 #[derive(Default)]
@@ -92,6 +123,15 @@ struct CompilerFunction<'gc> {
     has_varargs: bool,
     fixed_params: u8,
     locals: Vec<(String<'gc>, RegisterIndex)>,
+    // Every local declared so far, those still in `locals` have an `end_pc` of `usize::MAX`.
+    local_variables: Vec<LocalVariable<'gc>>,
+
+    // Names assigned to inside nested functions, only computed when lifting local functions.
+    closure_assignments: Vec<String<'gc>>,
+    // The registers of lifted local functions in scope, along with the registers of the locals
+    // that must be passed before the arguments of every call.
+    lifted_functions: Vec<(RegisterIndex, Vec<RegisterIndex>)>,
+    inline_functions: Vec<InlineFunction<'gc>>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
@@ -99,6 +139,7 @@ struct CompilerFunction<'gc> {
     pending_jumps: Vec<PendingJump<'gc>>,
 
     opcodes: Vec<OpCode>,
+    opcode_lines: Vec<(usize, LineNumber)>,
 }
 
 #[derive(Debug)]
@@ -150,6 +191,16 @@ enum VariableDescriptor<'gc> {
     Global(String<'gc>),
 }
 
+// Where a value is stored by a multiple assignment, once any table and key expressions have been
+// evaluated.
+enum AssignmentDestination<'gc> {
+    Variable(VariableDescriptor<'gc>),
+    Field {
+        table: RegisterIndex,
+        key: ExprDescriptor<'gc>,
+    },
+}
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 enum ExprDestination {
     // Evaluate the expression in an existing register
@@ -160,6 +211,15 @@ enum ExprDestination {
     PushNew,
 }
 
+// A local function in scope whose calls may be replaced by its body.
+struct InlineFunction<'gc> {
+    register: RegisterIndex,
+    definition: Rc<FunctionDefinition<String<'gc>>>,
+    // The local that each name used in the body referred to where the function was declared.  A
+    // call is only inlined if these are the same at the call site.
+    names: Vec<(String<'gc>, Option<RegisterIndex>)>,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum JumpLabel<'gc> {
     Unique(u64),
@@ -222,14 +282,20 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
     fn exit_block(&mut self) -> Result<(), CompilerError> {
         let last_block = self.current_function.blocks.pop().unwrap();
 
-        while let Some((_, last)) = self.current_function.locals.last() {
+        while let Some(&(_, last)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
-                self.current_function.register_allocator.free(*last);
-                self.current_function.locals.pop();
+                self.current_function.register_allocator.free(last);
+                self.current_function.pop_local();
             } else {
                 break;
             }
         }
+        self.current_function
+            .lifted_functions
+            .retain(|(r, _)| (r.0 as u16) < last_block.stack_bottom);
+        self.current_function
+            .inline_functions
+            .retain(|f| (f.register.0 as u16) < last_block.stack_bottom);
         self.current_function
             .jump_targets
             .drain(last_block.bottom_jump_target..);
@@ -269,14 +335,21 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
     // `do end` around the inside of the block not including the trailing labels.
     fn block_statements(&mut self, block: &Block<String<'gc>>) -> Result<(), CompilerError> {
         if let Some(return_statement) = &block.return_statement {
-            for statement in &block.statements {
-                self.statement(statement)?;
+            for (i, statement) in block.statements.iter().enumerate() {
+                self.statement(
+                    statement,
+                    Scope {
+                        statements: &block.statements[i + 1..],
+                        return_statement: Some(return_statement),
+                        until: None,
+                    },
+                )?;
             }
             self.return_statement(return_statement)?;
         } else {
             let mut last = block.statements.len();
             for i in (0..block.statements.len()).rev() {
-                match &block.statements[i] {
+                match &block.statements[i].node {
                     Statement::Label(_) => {}
                     _ => break,
                 }
@@ -285,20 +358,34 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             let trailing_labels = &block.statements[last..block.statements.len()];
 
             self.enter_block();
-            for i in 0..block.statements.len() - trailing_labels.len() {
-                self.statement(&block.statements[i])?;
+            for i in 0..last {
+                self.statement(
+                    &block.statements[i],
+                    Scope {
+                        statements: &block.statements[i + 1..last],
+                        return_statement: None,
+                        until: None,
+                    },
+                )?;
             }
             self.exit_block()?;
 
             for label_statement in trailing_labels {
-                self.statement(&label_statement)?;
+                self.statement(label_statement, Scope::empty())?;
             }
         }
         Ok(())
     }
 
-    fn statement(&mut self, statement: &Statement<String<'gc>>) -> Result<(), CompilerError> {
-        match statement {
+    // Compiles a statement, where `scope` is the rest of the code that can see any locals it
+    // declares.
+    fn statement(
+        &mut self,
+        statement: &Spanned<Statement<String<'gc>>>,
+        scope: Scope<String<'gc>>,
+    ) -> Result<(), CompilerError> {
+        self.current_function.set_line(statement.span.line_number);
+        match &statement.node {
             Statement::If(if_statement) => self.if_statement(if_statement),
             Statement::While(while_statement) => self.while_statement(while_statement),
             Statement::Do(block) => self.block(block),
@@ -306,7 +393,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             Statement::Repeat(repeat_statement) => self.repeat_statement(repeat_statement),
             Statement::Function(function_statement) => self.function_statement(function_statement),
             Statement::LocalFunction(local_function) => {
-                self.local_function_statement(local_function)
+                self.local_function_statement(local_function, scope)
             }
             Statement::LocalStatement(local_statement) => self.local_statement(local_statement),
             Statement::Label(label_statement) => {
@@ -321,9 +408,12 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
 
     fn return_statement(
         &mut self,
-        return_statement: &ReturnStatement<String<'gc>>,
+        return_statement: &Spanned<ReturnStatement<String<'gc>>>,
     ) -> Result<(), CompilerError> {
+        self.current_function
+            .set_line(return_statement.span.line_number);
         let mut returns = return_statement
+            .node
             .returns
             .iter()
             .map(|arg| self.expression(arg))
@@ -434,7 +524,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompilerError::Registers)?;
-                self.current_function.locals.push((*name, loop_var));
+                self.current_function.declare_local(*name, loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
@@ -511,8 +601,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .ok_or(CompilerError::Registers)?;
                 for i in 0..name_count {
                     self.current_function
-                        .locals
-                        .push((names[i as usize], RegisterIndex(names_reg.0 + i)));
+                        .declare_local(names[i as usize], RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label)?;
@@ -580,8 +669,16 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
 
         // `repeat` statements do not follow the trailing label rule, because the variables inside
         // the block are in scope for the `until` condition at the end.
-        for statement in &repeat_statement.body.statements {
-            self.statement(statement)?;
+        let statements = &repeat_statement.body.statements;
+        for (i, statement) in statements.iter().enumerate() {
+            self.statement(
+                statement,
+                Scope {
+                    statements: &statements[i + 1..],
+                    return_statement: repeat_statement.body.return_statement.as_ref(),
+                    until: Some(&repeat_statement.until),
+                },
+            )?;
         }
         if let Some(return_statement) = &repeat_statement.body.return_statement {
             self.return_statement(return_statement)?;
@@ -672,8 +769,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                 .push(OpCode::LoadNil { dest, count });
             for i in 0..name_len {
                 self.current_function
-                    .locals
-                    .push((local_statement.names[i], RegisterIndex(dest.0 + i as u8)));
+                    .declare_local(local_statement.names[i], RegisterIndex(dest.0 + i as u8));
             }
         } else {
             for i in 0..val_len {
@@ -688,16 +784,15 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.current_function.declare_local(
                             local_statement.names[val_len - 1 + j as usize],
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
                     self.current_function
-                        .locals
-                        .push((local_statement.names[i], reg));
+                        .declare_local(local_statement.names[i], reg);
                 }
             }
         }
@@ -712,10 +807,10 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         let head_expr = self.suffixed_expression(&function_call.head)?;
         match &function_call.call {
             CallSuffix::Function(args) => {
-                let arg_exprs = args
-                    .iter()
-                    .map(|arg| self.expression(arg))
-                    .collect::<Result<_, CompilerError>>()?;
+                let mut arg_exprs = self.lifted_arguments(&head_expr);
+                for arg in args {
+                    arg_exprs.push(self.expression(arg)?);
+                }
                 self.call_function(head_expr, arg_exprs, VarCount::constant(0))?;
             }
             CallSuffix::Method(method, args) => {
@@ -738,44 +833,156 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         &mut self,
         assignment: &AssignmentStatement<String<'gc>>,
     ) -> Result<(), CompilerError> {
-        for (i, target) in assignment.targets.iter().enumerate() {
-            let expr = if i < assignment.values.len() {
-                self.expression(&assignment.values[i])?
-            } else {
-                ExprDescriptor::Constant(Constant::Nil)
-            };
+        if let ([target], [value]) = (&assignment.targets[..], &assignment.values[..]) {
+            let expr = self.expression(value)?;
+            return self.assign_target(target, expr);
+        }
 
-            match target {
-                AssignmentTarget::Name(name) => match self.find_variable(*name)? {
-                    VariableDescriptor::Local(dest) => {
-                        self.expr_discharge(expr, ExprDestination::Register(dest))?;
-                    }
-                    VariableDescriptor::UpValue(dest) => {
-                        let (source, source_is_temp) = self.expr_any_register(expr)?;
-                        self.current_function
-                            .opcodes
-                            .push(OpCode::SetUpValue { source, dest });
-                        if source_is_temp {
-                            self.current_function.register_allocator.free(source);
-                        }
-                    }
-                    VariableDescriptor::Global(name) => {
-                        let env = self.get_environment()?;
-                        let key = ExprDescriptor::Constant(Constant::String(name));
-                        self.set_table(env, key, expr)?;
-                    }
-                },
+        // Every right hand side is evaluated into a new register before anything is assigned, so
+        // that assignments cannot affect the values of the other targets (as in `a, b = b, a`).
+        // Table and key expressions of field targets are evaluated before the right hand sides.
+        //
+        // A local which is assigned to must be copied if it is also used as a table or key, so
+        // that the field assignment sees the value it had before the statement.
+        let mut assigned_locals = Vec::new();
+        for target in &assignment.targets {
+            if let AssignmentTarget::Name(name) = target {
+                if let VariableDescriptor::Local(r) = self.find_variable(*name)? {
+                    assigned_locals.push(r);
+                }
+            }
+        }
 
+        let mut targets = Vec::with_capacity(assignment.targets.len());
+        let mut temporaries = Vec::new();
+        for target in &assignment.targets {
+            targets.push(match target {
+                AssignmentTarget::Name(name) => {
+                    AssignmentDestination::Variable(self.find_variable(*name)?)
+                }
                 AssignmentTarget::Field(table, field) => {
-                    let table = self.suffixed_expression(table)?;
+                    let table = match self.suffixed_expression(table)? {
+                        ExprDescriptor::Variable(VariableDescriptor::Local(r))
+                            if !assigned_locals.contains(&r) =>
+                        {
+                            r
+                        }
+                        table => {
+                            let r = self.expr_discharge(table, ExprDestination::AllocateNew)?;
+                            temporaries.push(r);
+                            r
+                        }
+                    };
                     let key = match field {
                         FieldSuffix::Named(name) => {
                             ExprDescriptor::Constant(Constant::String(*name))
                         }
-                        FieldSuffix::Indexed(idx) => self.expression(idx)?,
+                        FieldSuffix::Indexed(idx) => match self.expression(idx)? {
+                            key @ ExprDescriptor::Constant(_) => key,
+                            ExprDescriptor::Variable(VariableDescriptor::Local(r))
+                                if !assigned_locals.contains(&r) =>
+                            {
+                                ExprDescriptor::Variable(VariableDescriptor::Local(r))
+                            }
+                            key => {
+                                let r = self.expr_discharge(key, ExprDestination::AllocateNew)?;
+                                temporaries.push(r);
+                                ExprDescriptor::Variable(VariableDescriptor::Local(r))
+                            }
+                        },
                     };
-                    self.set_table(table, key, expr)?;
+                    AssignmentDestination::Field { table, key }
+                }
+            });
+        }
+
+        let target_len = targets.len();
+        let value_len = assignment.values.len();
+        let mut values = Vec::with_capacity(target_len);
+        for (i, value) in assignment.values.iter().enumerate() {
+            let expr = self.expression(value)?;
+            if i >= target_len {
+                let reg = self.expr_discharge(expr, ExprDestination::AllocateNew)?;
+                self.current_function.register_allocator.free(reg);
+            } else if i == value_len - 1 {
+                let count = cast(1 + target_len - value_len).ok_or(CompilerError::Registers)?;
+                let dest = self.expr_push_count(expr, count)?;
+                for j in 0..count {
+                    values.push(RegisterIndex(dest.0 + j));
+                }
+            } else {
+                values.push(self.expr_discharge(expr, ExprDestination::PushNew)?);
+            }
+        }
+
+        // Assign from the last target to the first, as PUC-Rio Lua does.
+        for (target, &value) in targets.into_iter().zip(&values).rev() {
+            let value = ExprDescriptor::Variable(VariableDescriptor::Local(value));
+            match target {
+                AssignmentDestination::Variable(VariableDescriptor::Local(dest)) => {
+                    self.expr_discharge(value, ExprDestination::Register(dest))?;
+                }
+                AssignmentDestination::Variable(VariableDescriptor::UpValue(dest)) => {
+                    let (source, _) = self.expr_any_register(value)?;
+                    self.current_function
+                        .opcodes
+                        .push(OpCode::SetUpValue { source, dest });
+                }
+                AssignmentDestination::Variable(VariableDescriptor::Global(name)) => {
+                    let env = self.get_environment()?;
+                    let key = ExprDescriptor::Constant(Constant::String(name));
+                    self.set_table(env, key, value)?;
+                }
+                AssignmentDestination::Field { table, key } => {
+                    self.set_rtable(table, key, value)?;
+                }
+            }
+        }
+
+        for reg in values
+            .into_iter()
+            .rev()
+            .chain(temporaries.into_iter().rev())
+        {
+            self.current_function.register_allocator.free(reg);
+        }
+
+        Ok(())
+    }
+
+    fn assign_target(
+        &mut self,
+        target: &AssignmentTarget<String<'gc>>,
+        expr: ExprDescriptor<'gc>,
+    ) -> Result<(), CompilerError> {
+        match target {
+            AssignmentTarget::Name(name) => match self.find_variable(*name)? {
+                VariableDescriptor::Local(dest) => {
+                    self.expr_discharge(expr, ExprDestination::Register(dest))?;
+                }
+                VariableDescriptor::UpValue(dest) => {
+                    let (source, source_is_temp) = self.expr_any_register(expr)?;
+                    self.current_function
+                        .opcodes
+                        .push(OpCode::SetUpValue { source, dest });
+                    if source_is_temp {
+                        self.current_function.register_allocator.free(source);
+                    }
+                }
+                VariableDescriptor::Global(name) => {
+                    let env = self.get_environment()?;
+                    let key = ExprDescriptor::Constant(Constant::String(name));
+                    self.set_table(env, key, expr)?;
                 }
+            },
+
+            AssignmentTarget::Field(table, field) => {
+                let table = self.suffixed_expression(table)?;
+                let key = match field {
+                    FieldSuffix::Named(name) => ExprDescriptor::Constant(Constant::String(*name)),
+                    FieldSuffix::Indexed(idx) => self.expression(idx)?,
+                };
+                self.set_table(table, key, expr)?;
             }
         }
 
@@ -785,12 +992,26 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
     fn local_function_statement(
         &mut self,
         local_function: &LocalFunctionStatement<String<'gc>>,
+        scope: Scope<String<'gc>>,
     ) -> Result<(), CompilerError> {
-        let proto = self.new_prototype(
-            &local_function.definition.parameters,
-            local_function.definition.has_varargs,
-            &local_function.definition.body,
-        )?;
+        let definition = &local_function.definition;
+        let captured = if self.optimizations.lift_local_functions {
+            self.liftable_locals(local_function, scope)
+        } else {
+            Vec::new()
+        };
+
+        let proto = if captured.is_empty() {
+            self.new_prototype(
+                &definition.parameters,
+                definition.has_varargs,
+                &definition.body,
+            )?
+        } else {
+            let mut parameters: Vec<_> = captured.iter().map(|&(name, _)| name).collect();
+            parameters.extend(&definition.parameters);
+            self.new_prototype(&parameters, definition.has_varargs, &definition.body)?
+        };
 
         let dest = self
             .current_function
@@ -801,12 +1022,110 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             .opcodes
             .push(OpCode::Closure { proto, dest });
         self.current_function
-            .locals
-            .push((local_function.name, dest));
+            .declare_local(local_function.name, dest);
+        if !captured.is_empty() {
+            self.current_function
+                .lifted_functions
+                .push((dest, captured.into_iter().map(|(_, r)| r).collect()));
+        }
+        if self.optimizations.inline_local_functions {
+            if let Some(inline) = self.inline_function(local_function, proto, dest, scope) {
+                self.current_function.inline_functions.push(inline);
+            }
+        }
 
         Ok(())
     }
 
+    // Returns how to inline the function declared by `local_function`, if it can be inlined.  It
+    // must be small, not recursive, not variadic, only called directly by name so that it is
+    // never reassigned, and suitable for `inlinable_names`.
+    fn inline_function(
+        &self,
+        local_function: &LocalFunctionStatement<String<'gc>>,
+        proto: PrototypeIndex,
+        register: RegisterIndex,
+        scope: Scope<String<'gc>>,
+    ) -> Option<InlineFunction<'gc>> {
+        let definition = &local_function.definition;
+        let compiled = &self.current_function.prototypes[proto.0 as usize];
+        if definition.has_varargs
+            || compiled.opcodes.len() > MAX_INLINE_OPCODES
+            || compiled.stack_size > MAX_INLINE_REGISTERS
+        {
+            return None;
+        }
+
+        let mut names = inlinable_names(definition)?;
+        if names.contains(&local_function.name) || !only_called(&local_function.name, scope) {
+            return None;
+        }
+        names.push(String::new_static(b"_ENV"));
+        names.retain(|name| !definition.parameters.contains(name));
+
+        Some(InlineFunction {
+            register,
+            definition: Rc::new(definition.clone()),
+            names: names
+                .into_iter()
+                .map(|name| (name, self.find_local(name)))
+                .collect(),
+        })
+    }
+
+    // Returns the register of the innermost local in the current function with the given name.
+    fn find_local(&self, name: String<'gc>) -> Option<RegisterIndex> {
+        self.current_function
+            .locals
+            .iter()
+            .rev()
+            .find(|&&(local_name, _)| local_name == name)
+            .map(|&(_, register)| register)
+    }
+
+    // If the function declared by `local_function` can be lifted, returns the locals of the current
+    // function that it uses, which become its leading parameters instead of upvalues.  Returns an
+    // empty list if it cannot be lifted or would not capture anything.
+    //
+    // A function can be lifted if the only uses of it are direct calls in the current function,
+    // so that every call can pass the captured values, and if none of those values can change
+    // while it is running.  It must not define any functions which could outlive the call with
+    // copies of the captured values, and nothing may assign to them from inside a function.
+    fn liftable_locals(
+        &self,
+        local_function: &LocalFunctionStatement<String<'gc>>,
+        scope: Scope<String<'gc>>,
+    ) -> Vec<(String<'gc>, RegisterIndex)> {
+        let definition = &local_function.definition;
+        let mut names = match function_names(definition) {
+            Some(names) => names,
+            None => return Vec::new(),
+        };
+        if names.contains(&local_function.name) || !only_called(&local_function.name, scope) {
+            return Vec::new();
+        }
+        // Global accesses use `_ENV`, which may be a local.
+        names.push(String::new_static(b"_ENV"));
+
+        let mut captured = Vec::new();
+        for name in names {
+            if definition.parameters.contains(&name) || captured.iter().any(|&(n, _)| n == name) {
+                continue;
+            }
+            if let Some(register) = self.find_local(name) {
+                if self.current_function.closure_assignments.contains(&name) {
+                    return Vec::new();
+                }
+                captured.push((name, register));
+            }
+        }
+
+        if captured.len() + definition.parameters.len() > u8::MAX as usize {
+            return Vec::new();
+        }
+        captured
+    }
+
     fn expression(
         &mut self,
         expression: &Expression<String<'gc>>,
@@ -892,9 +1211,9 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         &mut self,
         suffixed_expression: &SuffixedExpression<String<'gc>>,
     ) -> Result<ExprDescriptor<'gc>, CompilerError> {
-        let mut expr = self.primary_expression(&suffixed_expression.primary)?;
+        let mut expr = self.primary_expression(&suffixed_expression.primary.node)?;
         for suffix in &suffixed_expression.suffixes {
-            match suffix {
+            match &suffix.node {
                 SuffixPart::Field(field) => {
                     let key = match field {
                         FieldSuffix::Named(name) => {
@@ -909,13 +1228,13 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                 }
                 SuffixPart::Call(call_suffix) => match call_suffix {
                     CallSuffix::Function(args) => {
-                        let args = args
-                            .iter()
-                            .map(|arg| self.expression(arg))
-                            .collect::<Result<_, CompilerError>>()?;
+                        let mut arg_exprs = self.lifted_arguments(&expr);
+                        for arg in args {
+                            arg_exprs.push(self.expression(arg)?);
+                        }
                         expr = ExprDescriptor::FunctionCall {
                             func: Box::new(expr),
-                            args,
+                            args: arg_exprs,
                         };
                     }
                     CallSuffix::Method(method, args) => {
@@ -947,6 +1266,22 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         }
     }
 
+    // Returns the captured locals to pass before the arguments of a call to `func`, if it is a
+    // lifted local function.
+    fn lifted_arguments(&self, func: &ExprDescriptor<'gc>) -> Vec<ExprDescriptor<'gc>> {
+        if let ExprDescriptor::Variable(VariableDescriptor::Local(func)) = func {
+            for (register, captured) in &self.current_function.lifted_functions {
+                if register == func {
+                    return captured
+                        .iter()
+                        .map(|&r| ExprDescriptor::Variable(VariableDescriptor::Local(r)))
+                        .collect();
+                }
+            }
+        }
+        Vec::new()
+    }
+
     fn unary_operator_expression(
         &mut self,
         unop: UnaryOperator,
@@ -1041,12 +1376,15 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             CompilerFunction::start(parameters, has_varargs)?,
         );
         self.upper_functions.push(old_current);
+        if self.optimizations.lift_local_functions {
+            self.current_function.closure_assignments = closure_assignments(body);
+        }
         self.block(body)?;
         let proto = mem::replace(
             &mut self.current_function,
             self.upper_functions.pop().unwrap(),
         )
-        .finish(self.mutation_context)?;
+        .finish(self.mutation_context, self.chunk_name.clone())?;
         self.current_function.prototypes.push(proto);
         Ok(PrototypeIndex(
             cast(self.current_function.prototypes.len() - 1).ok_or(CompilerError::Functions)?,
@@ -1379,6 +1717,13 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         args: Vec<ExprDescriptor<'gc>>,
         returns: VarCount,
     ) -> Result<RegisterIndex, CompilerError> {
+        if let Some(returns) = returns.to_constant() {
+            if let Some((definition, lifted)) = self.inline_target(&func) {
+                let args = args.into_iter().skip(lifted).collect();
+                return self.inline_call(&definition, args, returns);
+            }
+        }
+
         let func = self.expr_discharge(func, ExprDestination::PushNew)?;
         let args = self.push_arguments(args)?;
 
@@ -1393,6 +1738,160 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         Ok(func)
     }
 
+    // If `func` is an inlinable local function that refers to the same variables here as where it
+    // was declared, returns its definition along with the number of lifted arguments which are
+    // passed before the arguments of every call to it.
+    fn inline_target(
+        &self,
+        func: &ExprDescriptor<'gc>,
+    ) -> Option<(Rc<FunctionDefinition<String<'gc>>>, usize)> {
+        let func = match func {
+            ExprDescriptor::Variable(VariableDescriptor::Local(func)) => *func,
+            _ => return None,
+        };
+        if self.inlining {
+            return None;
+        }
+        let inline = self
+            .current_function
+            .inline_functions
+            .iter()
+            .find(|f| f.register == func)?;
+        if inline
+            .names
+            .iter()
+            .any(|&(name, local)| self.find_local(name) != local)
+        {
+            return None;
+        }
+        let lifted = self
+            .current_function
+            .lifted_functions
+            .iter()
+            .find(|(r, _)| *r == func)
+            .map(|(_, captured)| captured.len())
+            .unwrap_or(0);
+        Some((inline.definition.clone(), lifted))
+    }
+
+    // Compiles the body of a function in place of a call to it, with the parameters as locals in
+    // a new block.  Places the results like `call_function`, and keeps the line numbers of the
+    // body so that errors in it are reported where they are in the source.
+    fn inline_call(
+        &mut self,
+        definition: &FunctionDefinition<String<'gc>>,
+        args: Vec<ExprDescriptor<'gc>>,
+        returns: u8,
+    ) -> Result<RegisterIndex, CompilerError> {
+        let call_line = self
+            .current_function
+            .opcode_lines
+            .last()
+            .map(|&(_, line)| line);
+        let base = if returns > 0 {
+            self.current_function
+                .register_allocator
+                .push(returns)
+                .ok_or(CompilerError::Registers)?
+        } else {
+            RegisterIndex(
+                cast(self.current_function.register_allocator.stack_top())
+                    .ok_or(CompilerError::Registers)?,
+            )
+        };
+
+        self.enter_block();
+        let params_len = cast(definition.parameters.len()).ok_or(CompilerError::Registers)?;
+        if let Some(params) = self.push_values(args, params_len)? {
+            for (i, &name) in definition.parameters.iter().enumerate() {
+                self.current_function
+                    .declare_local(name, RegisterIndex(params.0 + i as u8));
+            }
+        }
+
+        self.inlining = true;
+        let body = &definition.body;
+        for (i, statement) in body.statements.iter().enumerate() {
+            self.statement(
+                statement,
+                Scope {
+                    statements: &body.statements[i + 1..],
+                    return_statement: body.return_statement.as_ref(),
+                    until: None,
+                },
+            )?;
+        }
+        let mut values = Vec::new();
+        if let Some(return_statement) = &body.return_statement {
+            self.current_function
+                .set_line(return_statement.span.line_number);
+            for value in &return_statement.node.returns {
+                values.push(self.expression(value)?);
+            }
+        }
+        if let Some(results) = self.push_values(values, returns)? {
+            for i in 0..returns {
+                self.current_function.opcodes.push(OpCode::Move {
+                    dest: RegisterIndex(base.0 + i),
+                    source: RegisterIndex(results.0 + i),
+                });
+            }
+            for i in (0..returns).rev() {
+                self.current_function
+                    .register_allocator
+                    .free(RegisterIndex(results.0 + i));
+            }
+        }
+        self.inlining = false;
+        self.exit_block()?;
+
+        for i in (0..returns).rev() {
+            self.current_function
+                .register_allocator
+                .free(RegisterIndex(base.0 + i));
+        }
+        if let Some(line) = call_line {
+            self.current_function.set_line(line);
+        }
+        Ok(base)
+    }
+
+    // Evaluates the given expressions in order and pushes exactly `count` values from them onto
+    // the stack, the same way values are assigned to the names of a `local` statement.  Returns
+    // the first register pushed, if any.
+    fn push_values(
+        &mut self,
+        exprs: Vec<ExprDescriptor<'gc>>,
+        count: u8,
+    ) -> Result<Option<RegisterIndex>, CompilerError> {
+        let len = exprs.len();
+        let mut first = None;
+        for (i, expr) in exprs.into_iter().enumerate() {
+            if i >= count as usize {
+                let reg = self.expr_discharge(expr, ExprDestination::AllocateNew)?;
+                self.current_function.register_allocator.free(reg);
+            } else if i == len - 1 {
+                let reg = self.expr_push_count(expr, count - i as u8)?;
+                first = first.or(Some(reg));
+            } else {
+                let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
+                first = first.or(Some(reg));
+            }
+        }
+        if len == 0 && count > 0 {
+            let dest = self
+                .current_function
+                .register_allocator
+                .push(count)
+                .ok_or(CompilerError::Registers)?;
+            self.current_function
+                .opcodes
+                .push(OpCode::LoadNil { dest, count });
+            first = Some(dest);
+        }
+        Ok(first)
+    }
+
     // Performs a method call similarly to how `call_function` works.  Method calls have a special
     // opcode that make them more efficient than executing them in a naive way.
     fn call_method(
@@ -1465,6 +1964,14 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     self.call_function(*func, args, VarCount::variable())?;
                     VarCount::variable()
                 }
+                ExprDescriptor::MethodCall {
+                    table,
+                    method,
+                    args,
+                } => {
+                    self.call_method(*table, *method, args, VarCount::variable())?;
+                    VarCount::variable()
+                }
                 ExprDescriptor::VarArgs => {
                     self.current_function.opcodes.push(OpCode::VarArgs {
                         dest: RegisterIndex(
@@ -1826,6 +2333,11 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             }
 
             ExprDescriptor::Concat(mut exprs) => {
+                // Operands are evaluated left to right into consecutive registers.  If registers
+                // run out, the operands so far are concatenated into the first register and
+                // evaluation continues after it.  Lua concatenates right to left, but without a
+                // `__concat` metamethod concatenation is associative, so only the order the
+                // operands are evaluated in is observable.
                 assert!(!exprs.is_empty());
                 let dest = new_destination(self, dest)?;
                 let source =
@@ -1836,6 +2348,10 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                         let next = exprs.pop_front().unwrap();
                         self.expr_discharge(next, ExprDestination::Register(new))?;
                         count += 1;
+                    } else if count == 1 {
+                        // The first operand is in the last register, so there is no room for
+                        // any other.
+                        return Err(CompilerError::Registers);
                     } else {
                         self.current_function.opcodes.push(OpCode::Concat {
                             dest: source,
@@ -1884,6 +2400,23 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .ok_or(CompilerError::Registers)?;
                 dest
             }
+            ExprDescriptor::MethodCall {
+                table,
+                method,
+                args,
+            } => {
+                let dest = self.call_method(
+                    *table,
+                    *method,
+                    args,
+                    VarCount::try_constant(count).ok_or(CompilerError::Registers)?,
+                )?;
+                self.current_function
+                    .register_allocator
+                    .push(count)
+                    .ok_or(CompilerError::Registers)?;
+                dest
+            }
             ExprDescriptor::VarArgs => {
                 let dest = self
                     .current_function
@@ -2009,20 +2542,59 @@ impl<'gc> CompilerFunction<'gc> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize], RegisterIndex(i)));
+            function.declare_local(parameters[i as usize], RegisterIndex(i));
         }
         Ok(function)
     }
 
-    fn finish(mut self, mc: MutationContext<'gc, '_>) -> Result<FunctionProto<'gc>, CompilerError> {
+    // Brings a new local into scope, starting from the next opcode pushed.
+    fn declare_local(&mut self, name: String<'gc>, register: RegisterIndex) {
+        self.locals.push((name, register));
+        self.local_variables.push(LocalVariable {
+            name,
+            register,
+            start_pc: self.opcodes.len(),
+            end_pc: usize::MAX,
+        });
+    }
+
+    // Takes the innermost local out of scope after the last opcode pushed, and returns its
+    // register.
+    fn pop_local(&mut self) -> Option<RegisterIndex> {
+        let (_, register) = self.locals.pop()?;
+        let end_pc = self.opcodes.len();
+        if let Some(local) = self
+            .local_variables
+            .iter_mut()
+            .rev()
+            .find(|local| local.register == register && local.end_pc == usize::MAX)
+        {
+            local.end_pc = end_pc;
+        }
+        Some(register)
+    }
+
+    // Marks every opcode pushed from this point on as coming from the given source line.
+    fn set_line(&mut self, line_number: LineNumber) {
+        let pc = self.opcodes.len();
+        match self.opcode_lines.last_mut() {
+            Some((_, last_line)) if *last_line == line_number => {}
+            Some((last_pc, last_line)) if *last_pc == pc => *last_line = line_number,
+            _ => self.opcode_lines.push((pc, line_number)),
+        }
+    }
+
+    fn finish(
+        mut self,
+        mc: MutationContext<'gc, '_>,
+        chunk_name: Option<ChunkName>,
+    ) -> Result<FunctionProto<'gc>, CompilerError> {
         self.opcodes.push(OpCode::Return {
             start: RegisterIndex(0),
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
+        while let Some(r) = self.pop_local() {
             self.register_allocator.free(r);
         }
         assert_eq!(
@@ -2041,12 +2613,16 @@ impl<'gc> CompilerFunction<'gc> {
             stack_size: self.register_allocator.stack_size(),
             constants: self.constants,
             opcodes: self.opcodes,
+            opcode_lines: self.opcode_lines,
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
+            upvalue_names: self.upvalues.iter().map(|(n, _)| *n).collect(),
+            local_variables: self.local_variables,
             prototypes: self
                 .prototypes
                 .into_iter()
                 .map(|f| Gc::allocate(mc, f))
                 .collect(),
+            chunk_name,
         })
     }
 }
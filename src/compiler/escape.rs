@@ -0,0 +1,328 @@
+use crate::parser::{
+    AssignmentTarget, Block, CallSuffix, ConstructorField, Expression, FieldSuffix, ForStatement,
+    FunctionDefinition, HeadExpression, PrimaryExpression, RecordKey, ReturnStatement,
+    SimpleExpression, Spanned, Statement, SuffixPart, SuffixedExpression,
+};
+
+// The statements and expressions following a statement that are in the scope of any local it
+// declares.
+#[derive(Clone, Copy)]
+pub(super) struct Scope<'a, S> {
+    pub statements: &'a [Spanned<Statement<S>>],
+    pub return_statement: Option<&'a Spanned<ReturnStatement<S>>>,
+    // The condition of a `repeat` loop, which can see the locals of its body.
+    pub until: Option<&'a Expression<S>>,
+}
+
+impl<'a, S> Scope<'a, S> {
+    pub fn empty() -> Scope<'a, S> {
+        Scope {
+            statements: &[],
+            return_statement: None,
+            until: None,
+        }
+    }
+}
+
+// Returns every name assigned to inside a function defined anywhere in `block`.  Locals in the
+// function containing `block` that are never assigned to this way can only change while that
+// function is running.
+pub(super) fn closure_assignments<S: Copy + PartialEq>(block: &Block<S>) -> Vec<S> {
+    let mut names = Vec::new();
+    walk_block(block, 0, &mut |event, depth| {
+        if let Event::Name(name, NameUse::Assign) = event {
+            if depth > 0 && !names.contains(name) {
+                names.push(*name);
+            }
+        }
+    });
+    names
+}
+
+// Returns every name read or assigned to in the body of `definition`, or None if it defines any
+// functions of its own.
+pub(super) fn function_names<S: Copy + PartialEq>(
+    definition: &FunctionDefinition<S>,
+) -> Option<Vec<S>> {
+    let mut names = Vec::new();
+    let mut defines_functions = false;
+    walk_block(&definition.body, 0, &mut |event, _| match event {
+        Event::Name(name, _) => {
+            if !names.contains(name) {
+                names.push(*name);
+            }
+        }
+        Event::Function => defines_functions = true,
+        Event::Return | Event::Goto | Event::VarArgs => {}
+    });
+    if defines_functions {
+        None
+    } else {
+        Some(names)
+    }
+}
+
+// Returns every name read or assigned to in the body of `definition` if it can be compiled in place
+// of a call, which requires that it defines no functions, only returns at the end of its body and
+// contains no `goto` statements, labels or `...`, which could refer to those of the caller.
+pub(super) fn inlinable_names<S: Copy + PartialEq>(
+    definition: &FunctionDefinition<S>,
+) -> Option<Vec<S>> {
+    let mut names = Vec::new();
+    let mut inlinable = true;
+    let mut visit = |event: Event<S>, _| match event {
+        Event::Name(name, _) => {
+            if !names.contains(name) {
+                names.push(*name);
+            }
+        }
+        // The return statement at the end of the body is walked without an event below, so any
+        // other is nested in a block.
+        Event::Function | Event::Return | Event::Goto | Event::VarArgs => inlinable = false,
+    };
+    for statement in &definition.body.statements {
+        walk_statement(&statement.node, 0, &mut visit);
+    }
+    if let Some(return_statement) = &definition.body.return_statement {
+        for expression in &return_statement.node.returns {
+            walk_expression(expression, 0, &mut visit);
+        }
+    }
+    if inlinable {
+        Some(names)
+    } else {
+        None
+    }
+}
+
+// Whether every use of `name` in `scope` is a direct call of the form `name(...)`, outside of any
+// nested function.  Shadowing locals are not tracked, so uses of any variable with the same name
+// count.
+pub(super) fn only_called<S: PartialEq>(name: &S, scope: Scope<S>) -> bool {
+    let mut only_called = true;
+    let mut check = |event: Event<S>, depth| {
+        if let Event::Name(n, name_use) = event {
+            if n == name && (name_use != NameUse::Call || depth > 0) {
+                only_called = false;
+            }
+        }
+    };
+    for statement in scope.statements {
+        walk_statement(&statement.node, 0, &mut check);
+    }
+    if let Some(return_statement) = scope.return_statement {
+        for expression in &return_statement.node.returns {
+            walk_expression(expression, 0, &mut check);
+        }
+    }
+    if let Some(until) = scope.until {
+        walk_expression(until, 0, &mut check);
+    }
+    only_called
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NameUse {
+    Read,
+    // Read as the function of a call with no method, as in `name(...)`.
+    Call,
+    Assign,
+}
+
+enum Event<'a, S> {
+    Name(&'a S, NameUse),
+    // The start of a function definition, whose body is then walked one level deeper.
+    Function,
+    // A return statement at the end of a block.
+    Return,
+    // A `goto` statement or a label.
+    Goto,
+    VarArgs,
+}
+
+type Visit<'v, 'a, S> = &'v mut dyn FnMut(Event<'a, S>, usize);
+
+fn walk_block<'a, S>(block: &'a Block<S>, depth: usize, visit: Visit<'_, 'a, S>) {
+    for statement in &block.statements {
+        walk_statement(&statement.node, depth, visit);
+    }
+    if let Some(return_statement) = &block.return_statement {
+        visit(Event::Return, depth);
+        for expression in &return_statement.node.returns {
+            walk_expression(expression, depth, visit);
+        }
+    }
+}
+
+fn walk_statement<'a, S>(statement: &'a Statement<S>, depth: usize, visit: Visit<'_, 'a, S>) {
+    match statement {
+        Statement::If(if_statement) => {
+            let (condition, block) = &if_statement.if_part;
+            walk_expression(condition, depth, visit);
+            walk_block(block, depth, visit);
+            for (condition, block) in &if_statement.else_if_parts {
+                walk_expression(condition, depth, visit);
+                walk_block(block, depth, visit);
+            }
+            if let Some(block) = &if_statement.else_part {
+                walk_block(block, depth, visit);
+            }
+        }
+        Statement::While(while_statement) => {
+            walk_expression(&while_statement.condition, depth, visit);
+            walk_block(&while_statement.block, depth, visit);
+        }
+        Statement::Do(block) => walk_block(block, depth, visit),
+        Statement::For(ForStatement::Numeric {
+            initial,
+            limit,
+            step,
+            body,
+            ..
+        }) => {
+            walk_expression(initial, depth, visit);
+            walk_expression(limit, depth, visit);
+            if let Some(step) = step {
+                walk_expression(step, depth, visit);
+            }
+            walk_block(body, depth, visit);
+        }
+        Statement::For(ForStatement::Generic {
+            arguments, body, ..
+        }) => {
+            for argument in arguments {
+                walk_expression(argument, depth, visit);
+            }
+            walk_block(body, depth, visit);
+        }
+        Statement::Repeat(repeat_statement) => {
+            walk_block(&repeat_statement.body, depth, visit);
+            walk_expression(&repeat_statement.until, depth, visit);
+        }
+        Statement::Function(function_statement) => {
+            // `function name()` assigns to `name`, `function name.field()` only reads it.
+            let name_use =
+                if function_statement.fields.is_empty() && function_statement.method.is_none() {
+                    NameUse::Assign
+                } else {
+                    NameUse::Read
+                };
+            visit(Event::Name(&function_statement.name, name_use), depth);
+            walk_function(&function_statement.definition, depth, visit);
+        }
+        Statement::LocalFunction(local_function) => {
+            walk_function(&local_function.definition, depth, visit)
+        }
+        Statement::LocalStatement(local_statement) => {
+            for value in &local_statement.values {
+                walk_expression(value, depth, visit);
+            }
+        }
+        Statement::Label(_) | Statement::Goto(_) => visit(Event::Goto, depth),
+        Statement::Break => {}
+        Statement::FunctionCall(function_call) => {
+            walk_suffixed(&function_call.head, Some(&function_call.call), depth, visit);
+        }
+        Statement::Assignment(assignment) => {
+            for target in &assignment.targets {
+                match target {
+                    AssignmentTarget::Name(name) => {
+                        visit(Event::Name(name, NameUse::Assign), depth)
+                    }
+                    AssignmentTarget::Field(table, field) => {
+                        walk_suffixed(table, None, depth, visit);
+                        walk_field(field, depth, visit);
+                    }
+                }
+            }
+            for value in &assignment.values {
+                walk_expression(value, depth, visit);
+            }
+        }
+    }
+}
+
+fn walk_function<'a, S>(
+    definition: &'a FunctionDefinition<S>,
+    depth: usize,
+    visit: Visit<'_, 'a, S>,
+) {
+    visit(Event::Function, depth);
+    walk_block(&definition.body, depth + 1, visit);
+}
+
+fn walk_expression<'a, S>(expression: &'a Expression<S>, depth: usize, visit: Visit<'_, 'a, S>) {
+    match &*expression.head {
+        HeadExpression::Simple(simple) => match simple {
+            SimpleExpression::TableConstructor(constructor) => {
+                for field in &constructor.fields {
+                    match field {
+                        ConstructorField::Array(value) => walk_expression(value, depth, visit),
+                        ConstructorField::Record(key, value) => {
+                            if let RecordKey::Indexed(key) = key {
+                                walk_expression(key, depth, visit);
+                            }
+                            walk_expression(value, depth, visit);
+                        }
+                    }
+                }
+            }
+            SimpleExpression::Function(definition) => walk_function(definition, depth, visit),
+            SimpleExpression::Suffixed(suffixed) => walk_suffixed(suffixed, None, depth, visit),
+            SimpleExpression::VarArgs => visit(Event::VarArgs, depth),
+            _ => {}
+        },
+        HeadExpression::UnaryOperator(_, operand) => walk_expression(operand, depth, visit),
+    }
+    for (_, right) in &expression.tail {
+        walk_expression(right, depth, visit);
+    }
+}
+
+// Walks a suffixed expression, followed by the call of a function call statement if there is one.
+fn walk_suffixed<'a, S>(
+    suffixed: &'a SuffixedExpression<S>,
+    call: Option<&'a CallSuffix<S>>,
+    depth: usize,
+    visit: Visit<'_, 'a, S>,
+) {
+    match &suffixed.primary.node {
+        PrimaryExpression::Name(name) => {
+            let called = match suffixed.suffixes.first().map(|suffix| &suffix.node) {
+                Some(SuffixPart::Call(CallSuffix::Function(_))) => true,
+                Some(_) => false,
+                None => matches!(call, Some(CallSuffix::Function(_))),
+            };
+            let name_use = if called { NameUse::Call } else { NameUse::Read };
+            visit(Event::Name(name, name_use), depth);
+        }
+        PrimaryExpression::GroupedExpression(expression) => {
+            walk_expression(expression, depth, visit)
+        }
+    }
+    for suffix in &suffixed.suffixes {
+        match &suffix.node {
+            SuffixPart::Field(field) => walk_field(field, depth, visit),
+            SuffixPart::Call(call) => walk_call(call, depth, visit),
+        }
+    }
+    if let Some(call) = call {
+        walk_call(call, depth, visit);
+    }
+}
+
+fn walk_call<'a, S>(call: &'a CallSuffix<S>, depth: usize, visit: Visit<'_, 'a, S>) {
+    match call {
+        CallSuffix::Function(args) | CallSuffix::Method(_, args) => {
+            for arg in args {
+                walk_expression(arg, depth, visit);
+            }
+        }
+    }
+}
+
+fn walk_field<'a, S>(field: &'a FieldSuffix<S>, depth: usize, visit: Visit<'_, 'a, S>) {
+    if let FieldSuffix::Indexed(key) = field {
+        walk_expression(key, depth, visit);
+    }
+}
@@ -2,14 +2,29 @@ use std::io::Read;
 
 use gc_arena::MutationContext;
 
-use crate::{parse_chunk, Error, FunctionProto, InternedStringSet};
+use crate::{
+    parse_chunk, parse_lexer_located, parse_tokens, parse_typed_chunk, ChunkName, Error,
+    FunctionProto, InternedStringSet, Lexer, Span, String, Token,
+};
 
 mod compiler;
+mod escape;
 mod operators;
+mod optimize;
 mod register_allocator;
 
 pub use self::compiler::{compile_chunk, CompilerError};
 
+use self::compiler::compile_chunk_with;
+pub use self::optimize::{optimize, Optimizations};
+
+/// Parses and compiles a chunk of Lua source.
+///
+/// Compiling only allocates in the arena and never touches the state of a thread, so it may be
+/// done in any mutation, including while a script is running.  Callbacks are not given a
+/// `MutationContext`, so a callback that compiles code does so in the `Sequence` it returns, and
+/// can then call the result with `CallbackResult::TailCall`.  The base library's `load` works this
+/// way.
 pub fn compile<'gc, R: Read>(
     mc: MutationContext<'gc, '_>,
     interned_strings: InternedStringSet<'gc>,
@@ -20,3 +35,59 @@ pub fn compile<'gc, R: Read>(
         &parse_chunk(source, |s| interned_strings.new_string(mc, s))?,
     )?)
 }
+
+/// The same as `compile`, but gives the chunk a name, which is shown before the line number of its
+/// syntax errors and in tracebacks through its functions, see `ChunkName`.  Syntax errors are
+/// returned as an `Error::SyntaxError` rather than an `Error::ParserError`, so that they carry
+/// their location.
+pub fn compile_named<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    chunk_name: ChunkName,
+    source: R,
+) -> Result<FunctionProto<'gc>, Error<'gc>> {
+    let mut lexer = Lexer::new(source, |s| interned_strings.new_string(mc, s));
+    lexer.set_chunk_name(chunk_name);
+    Ok(compile_chunk(mc, &parse_lexer_located(lexer)?)?)
+}
+
+/// The same as `compile`, but accepts and discards Luau-style type annotations, see
+/// `parse_typed_chunk`.
+pub fn compile_typed<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    source: R,
+) -> Result<FunctionProto<'gc>, Error<'gc>> {
+    Ok(compile_chunk(
+        mc,
+        &parse_typed_chunk(source, |s| interned_strings.new_string(mc, s))?,
+    )?)
+}
+
+/// The same as `compile`, but compiles an already lexed stream of tokens instead of source code,
+/// see `parse_tokens`.  Strings in the tokens must be created in the same arena, for example by
+/// lexing with `Lexer::new(source, |s| interned_strings.new_string(mc, s))`.
+pub fn compile_tokens<'gc, I>(
+    mc: MutationContext<'gc, '_>,
+    tokens: I,
+) -> Result<FunctionProto<'gc>, Error<'gc>>
+where
+    I: IntoIterator<Item = (Token<String<'gc>>, Span)>,
+{
+    Ok(compile_chunk(mc, &parse_tokens(tokens)?)?)
+}
+
+/// The same as `compile`, but applies the given optimizations to the result, see `Optimizations`.
+pub fn compile_optimized<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    source: R,
+    optimizations: Optimizations,
+) -> Result<FunctionProto<'gc>, Error<'gc>> {
+    let proto = compile_chunk_with(
+        mc,
+        &parse_chunk(source, |s| interned_strings.new_string(mc, s))?,
+        optimizations,
+    )?;
+    Ok(optimize(mc, &proto, optimizations))
+}
@@ -260,6 +260,11 @@ pub fn simple_binop_const_fold<'gc>(
     left: Constant<'gc>,
     right: Constant<'gc>,
 ) -> Option<Constant<'gc>> {
+    // Whether strings are converted to numbers is only known at runtime, see
+    // `Thread::set_string_coercion`.
+    if is_string(left) || is_string(right) {
+        return None;
+    }
     let left = left.to_value();
     let right = right.to_value();
     match simple_binop {
@@ -500,6 +505,7 @@ pub fn unop_const_fold<'gc>(unop: UnaryOperator, cons: Constant<'gc>) -> Option<
             _ => None,
         },
         UnaryOperator::Not => Some(Constant::Boolean(!cons.to_value().to_bool())),
+        UnaryOperator::BitNot if is_string(cons) => None,
         UnaryOperator::BitNot => match cons.to_value().bitwise_not() {
             Some(a) => Constant::from_value(a),
             _ => None,
@@ -507,3 +513,7 @@ pub fn unop_const_fold<'gc>(unop: UnaryOperator, cons: Constant<'gc>) -> Option<
         _ => None,
     }
 }
+
+fn is_string(cons: Constant) -> bool {
+    matches!(cons, Constant::String(_))
+}
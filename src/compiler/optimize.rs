@@ -0,0 +1,351 @@
+use std::convert::TryInto;
+
+use rustc_hash::FxHashMap;
+
+use gc_arena::{Gc, MutationContext};
+
+use crate::{
+    Constant, ConstantIndex8, FunctionProto, OpCode, RegisterIndex, UpValueIndex, VarCount,
+};
+
+/// Optional bytecode optimizations, applied by `compile_optimized` or `optimize`.
+///
+/// None of these are enabled by default, because each either relies on an assumption about how
+/// scripts are run that the VM cannot check, or changes the compiled prototypes in ways that
+/// tools inspecting them may not expect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Optimizations {
+    /// Loads each global read inside a loop into a register once before the loop starts, if the
+    /// loop contains no calls and no writes to any table or upvalue.  This is the "localize your
+    /// globals" optimization done automatically.
+    ///
+    /// Assumes that nothing outside of the loop changes the globals while it runs, which a count
+    /// hook or the host could do between VM steps (for example, a `while not done do end` loop
+    /// waiting for a timer task to set `done` would never finish).  Also assumes that `_ENV` is
+    /// always a table, as a global read before a loop that runs zero times is now an error if it
+    /// is not.
+    pub hoist_global_lookups: bool,
+    /// Passes the locals used by a local function as extra leading parameters instead of
+    /// capturing them as upvalues, if the function is only ever called directly by name from the
+    /// function declaring it, defines no functions itself, and none of the locals are assigned to
+    /// from inside any function.  This avoids allocating and closing an upvalue for each captured
+    /// local, which is most noticeable for helpers declared inside loops.
+    ///
+    /// This is always safe, but the lifted functions have more parameters and fewer upvalues than
+    /// their source.  It is applied while compiling, so it is ignored by `optimize`.
+    pub lift_local_functions: bool,
+    /// Compiles the body of a small local function in place of each call to it, if it is only
+    /// ever called directly by name from the function declaring it, is not recursive or variadic,
+    /// defines no functions, has no `goto` statements or labels and only returns at the end of its
+    /// body.  The inlined opcodes keep the line numbers of the function body.
+    ///
+    /// This is always safe, but calls that are inlined no longer appear as calls to hooks or in
+    /// tracebacks, and a call with a variable number of results is never inlined.  It is applied
+    /// while compiling, so it is ignored by `optimize`.
+    pub inline_local_functions: bool,
+    /// Removes repeated entries from the constants of each prototype, and makes identical string
+    /// constants in different prototypes of the chunk share one string, so machine generated code
+    /// that repeats the same literals across many functions only keeps each of them once.  The
+    /// compiler never repeats a constant within one prototype, but prototypes built or rewritten
+    /// by other tools may, and strings that were not created through one `InternedStringSet`
+    /// (such as those in tokens given to `compile_tokens`) are not otherwise shared.
+    ///
+    /// This is always safe, but the constants of the optimized prototypes may be in a different
+    /// order than before.
+    pub deduplicate_constants: bool,
+}
+
+impl Optimizations {
+    pub fn all() -> Optimizations {
+        Optimizations {
+            hoist_global_lookups: true,
+            lift_local_functions: true,
+            inline_local_functions: true,
+            deduplicate_constants: true,
+        }
+    }
+}
+
+/// Returns a copy of `proto` and all of its nested prototypes with the given optimizations applied.
+pub fn optimize<'gc>(
+    mc: MutationContext<'gc, '_>,
+    proto: &FunctionProto<'gc>,
+    optimizations: Optimizations,
+) -> FunctionProto<'gc> {
+    optimize_with(mc, proto, optimizations, &mut FxHashMap::default())
+}
+
+// `shared_constants` maps every constant seen so far in the chunk to the copy of it that the
+// optimized prototypes use, when deduplicating constants.
+fn optimize_with<'gc>(
+    mc: MutationContext<'gc, '_>,
+    proto: &FunctionProto<'gc>,
+    optimizations: Optimizations,
+    shared_constants: &mut FxHashMap<Constant<'gc>, Constant<'gc>>,
+) -> FunctionProto<'gc> {
+    let mut optimized = FunctionProto {
+        fixed_params: proto.fixed_params,
+        has_varargs: proto.has_varargs,
+        stack_size: proto.stack_size,
+        constants: proto.constants.clone(),
+        opcodes: proto.opcodes.clone(),
+        opcode_lines: proto.opcode_lines.clone(),
+        upvalues: proto.upvalues.clone(),
+        upvalue_names: proto.upvalue_names.clone(),
+        local_variables: proto.local_variables.clone(),
+        prototypes: proto
+            .prototypes
+            .iter()
+            .map(|p| Gc::allocate(mc, optimize_with(mc, p, optimizations, shared_constants)))
+            .collect(),
+        chunk_name: proto.chunk_name.clone(),
+    };
+
+    if optimizations.hoist_global_lookups {
+        hoist_global_lookups(&mut optimized);
+    }
+    if optimizations.deduplicate_constants {
+        deduplicate_constants(&mut optimized, shared_constants);
+    }
+
+    optimized
+}
+
+fn deduplicate_constants<'gc>(
+    proto: &mut FunctionProto<'gc>,
+    shared_constants: &mut FxHashMap<Constant<'gc>, Constant<'gc>>,
+) {
+    let mut constants = Vec::new();
+    let mut indexes = FxHashMap::default();
+    // Entries are only ever removed, so every new index is at most the old one and still fits in
+    // the opcode that refers to it.
+    let remap = proto
+        .constants
+        .iter()
+        .map(|&constant| {
+            let constant = *shared_constants.entry(constant).or_insert(constant);
+            *indexes.entry(constant).or_insert_with(|| {
+                constants.push(constant);
+                constants.len() - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    proto.constants = constants;
+    for op in &mut proto.opcodes {
+        *op = op.map_constants(&mut |c| remap[c]);
+    }
+}
+
+// A loop found by `find_hoist`, spanning the opcodes from `start` to the backwards jump at `end`
+// inclusive, with the distinct global lookups inside it.
+struct Hoist {
+    start: usize,
+    end: usize,
+    lookups: Vec<(UpValueIndex, ConstantIndex8)>,
+}
+
+fn hoist_global_lookups(proto: &mut FunctionProto) {
+    // Each hoist moves lookups out of one loop, possibly into an enclosing loop which may then be
+    // hoisted in turn, so repeat until there is nothing left to do.
+    while let Some(hoist) = find_hoist(proto) {
+        if !apply_hoist(proto, &hoist) {
+            break;
+        }
+    }
+}
+
+fn find_hoist(proto: &FunctionProto) -> Option<Hoist> {
+    let env = proto
+        .upvalue_names
+        .iter()
+        .position(|name| name.as_bytes() == b"_ENV")?;
+
+    for (end, &op) in proto.opcodes.iter().enumerate() {
+        let target = match jump_target(end, op) {
+            Some(target) if target <= end => target,
+            _ => continue,
+        };
+
+        // Numeric for loops are entered through the `NumericForPrep` just before the loop body,
+        // which must run after the hoisted lookups.  Generic for loops are never hoisted, as they
+        // call their iterator.
+        let start = match target.checked_sub(1).map(|i| proto.opcodes[i]) {
+            Some(OpCode::NumericForPrep { .. }) => target - 1,
+            _ => target,
+        };
+        // The loop must not be entered by skipping over its first opcode.
+        if start > 0 && can_skip(proto.opcodes[start - 1]) {
+            continue;
+        }
+
+        let mut lookups = Vec::new();
+        let mut valid = true;
+        for &op in &proto.opcodes[start..=end] {
+            match op {
+                OpCode::Call { .. }
+                | OpCode::TailCall { .. }
+                | OpCode::GenericForCall { .. }
+                | OpCode::SetTableRR { .. }
+                | OpCode::SetTableRC { .. }
+                | OpCode::SetTableCR { .. }
+                | OpCode::SetTableCC { .. }
+                | OpCode::SetUpTableRR { .. }
+                | OpCode::SetUpTableRC { .. }
+                | OpCode::SetUpTableCR { .. }
+                | OpCode::SetUpTableCC { .. }
+                | OpCode::SetUpValue { .. } => valid = false,
+                // Variable results are placed past the end of the stack frame, where the hoisted
+                // registers are.
+                OpCode::VarArgs { count, .. } if count == VarCount::variable() => valid = false,
+                OpCode::GetUpTableC { table, key, .. }
+                    if table.0 as usize == env && !lookups.contains(&(table, key)) =>
+                {
+                    lookups.push((table, key));
+                }
+                _ => {}
+            }
+        }
+
+        // Nothing outside of the loop may jump past its start.
+        valid = valid
+            && proto.opcodes.iter().enumerate().all(|(pc, &op)| {
+                (pc >= start && pc <= end)
+                    || jump_target(pc, op).map_or(true, |t| t <= start || t > end)
+            });
+
+        if valid && !lookups.is_empty() && proto.stack_size as usize + lookups.len() <= 256 {
+            return Some(Hoist {
+                start,
+                end,
+                lookups,
+            });
+        }
+    }
+
+    None
+}
+
+// Inserts the lookups of `hoist` before its loop and replaces them inside the loop with moves.
+// Returns false and leaves the prototype unchanged if a jump offset would overflow.
+fn apply_hoist(proto: &mut FunctionProto, hoist: &Hoist) -> bool {
+    let Hoist {
+        start,
+        end,
+        ref lookups,
+    } = *hoist;
+    let count = lookups.len();
+    let first_register = proto.stack_size as usize;
+
+    let mut opcodes = Vec::with_capacity(proto.opcodes.len() + count);
+    opcodes.extend_from_slice(&proto.opcodes[..start]);
+    for (i, &(table, key)) in lookups.iter().enumerate() {
+        opcodes.push(OpCode::GetUpTableC {
+            dest: RegisterIndex((first_register + i) as u8),
+            table,
+            key,
+        });
+    }
+    for (pc, &op) in proto.opcodes.iter().enumerate().skip(start) {
+        let mut op = match op {
+            OpCode::GetUpTableC { dest, table, key } if pc <= end => {
+                match lookups.iter().position(|&l| l == (table, key)) {
+                    Some(i) => OpCode::Move {
+                        dest,
+                        source: RegisterIndex((first_register + i) as u8),
+                    },
+                    None => op,
+                }
+            }
+            op => op,
+        };
+
+        // Jumps to the start of the loop from inside it continue to skip the hoisted lookups,
+        // every other jump to or past the start moves with the opcodes.
+        if let Some(target) = jump_target(pc, op) {
+            let target = if target > start || (target == start && pc <= end) {
+                target + count
+            } else {
+                target
+            };
+            match jump_offset(pc + count, target) {
+                Some(offset) => set_jump_offset(&mut op, offset),
+                None => return false,
+            }
+        }
+        opcodes.push(op);
+    }
+    for (pc, op) in opcodes.iter_mut().enumerate().take(start) {
+        if let Some(target) = jump_target(pc, *op) {
+            if target > start {
+                match jump_offset(pc, target + count) {
+                    Some(offset) => set_jump_offset(op, offset),
+                    None => return false,
+                }
+            }
+        }
+    }
+
+    proto.opcodes = opcodes;
+    proto.stack_size += count as u16;
+    for (pc, _) in &mut proto.opcode_lines {
+        if *pc > start {
+            *pc += count;
+        }
+    }
+    for local in &mut proto.local_variables {
+        for pc in [&mut local.start_pc, &mut local.end_pc] {
+            if *pc > start {
+                *pc += count;
+            }
+        }
+    }
+    true
+}
+
+// Whether the opcode can skip the opcode after it.
+fn can_skip(op: OpCode) -> bool {
+    match op {
+        OpCode::LoadBool { skip_next, .. } => skip_next,
+        OpCode::Test { .. }
+        | OpCode::TestSet { .. }
+        | OpCode::EqRR { .. }
+        | OpCode::EqRC { .. }
+        | OpCode::EqCR { .. }
+        | OpCode::EqCC { .. }
+        | OpCode::LessRR { .. }
+        | OpCode::LessRC { .. }
+        | OpCode::LessCR { .. }
+        | OpCode::LessCC { .. }
+        | OpCode::LessEqRR { .. }
+        | OpCode::LessEqRC { .. }
+        | OpCode::LessEqCR { .. }
+        | OpCode::LessEqCC { .. } => true,
+        _ => false,
+    }
+}
+
+fn jump_target(pc: usize, op: OpCode) -> Option<usize> {
+    let offset = match op {
+        OpCode::Jump { offset, .. } => offset,
+        OpCode::NumericForPrep { jump, .. }
+        | OpCode::NumericForLoop { jump, .. }
+        | OpCode::GenericForLoop { jump, .. } => jump,
+        _ => return None,
+    };
+    (pc as isize + 1 + offset as isize).try_into().ok()
+}
+
+fn jump_offset(pc: usize, target: usize) -> Option<i16> {
+    (target as isize - (pc as isize + 1)).try_into().ok()
+}
+
+fn set_jump_offset(op: &mut OpCode, new_offset: i16) {
+    match op {
+        OpCode::Jump { offset, .. } => *offset = new_offset,
+        OpCode::NumericForPrep { jump, .. }
+        | OpCode::NumericForLoop { jump, .. }
+        | OpCode::GenericForLoop { jump, .. } => *jump = new_offset,
+        _ => unreachable!("opcode is not a jump"),
+    }
+}
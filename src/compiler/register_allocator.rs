@@ -83,7 +83,8 @@ impl RegisterAllocator {
             None
         } else if size as u16 <= 256 - self.stack_top {
             let rbegin = self.stack_top as u8;
-            for i in rbegin..rbegin + size {
+            // Counted in u16, as the block may end at register 255.
+            for i in self.stack_top..self.stack_top + size as u16 {
                 self.registers[i as usize] = true;
             }
             if self.first_free == self.stack_top {
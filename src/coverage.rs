@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use rustc_hash::FxHashMap;
+
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
+
+use crate::{FunctionProto, LineNumber};
+
+/// Records which source lines of a set of tracked function prototypes have been executed.
+///
+/// Set on a thread with `Thread::set_line_coverage`, threads created by `coroutine.create` share
+/// the recorder of the main thread.  A line is counted once each time execution enters it from a
+/// different line or resumes in it after a call, so the counts are approximate but any executed
+/// line has a count of at least one.
+#[derive(Debug, Collect, Clone, Copy)]
+#[collect(require_copy)]
+pub struct LineCoverage<'gc>(GcCell<'gc, CoverageState<'gc>>);
+
+#[derive(Debug, Collect)]
+#[collect(empty_drop)]
+struct CoverageState<'gc> {
+    protos: Vec<Gc<'gc, FunctionProto<'gc>>>,
+    // Keyed by prototype address, the prototypes are kept alive by `protos`.
+    hits: FxHashMap<usize, BTreeMap<LineNumber, u64>>,
+}
+
+impl<'gc> LineCoverage<'gc> {
+    pub fn new(mc: MutationContext<'gc, '_>) -> LineCoverage<'gc> {
+        LineCoverage(GcCell::allocate(
+            mc,
+            CoverageState {
+                protos: Vec::new(),
+                hits: FxHashMap::default(),
+            },
+        ))
+    }
+
+    /// Starts recording coverage for the given prototype and every prototype nested inside it.
+    /// Every line with an instruction starts with a count of zero.
+    pub fn track(&self, mc: MutationContext<'gc, '_>, proto: Gc<'gc, FunctionProto<'gc>>) {
+        let mut state = self.0.write(mc);
+        let mut stack = vec![proto];
+        while let Some(proto) = stack.pop() {
+            let lines = state.hits.entry(Gc::as_ptr(proto) as usize).or_default();
+            for &(_, line) in &proto.opcode_lines {
+                lines.entry(line).or_insert(0);
+            }
+            state.protos.push(proto);
+            stack.extend(proto.prototypes.iter().copied());
+        }
+    }
+
+    /// Returns the execution count of every line in every tracked prototype.
+    pub fn lines(&self) -> BTreeMap<LineNumber, u64> {
+        let mut lines = BTreeMap::new();
+        for proto_lines in self.0.read().hits.values() {
+            for (&line, &count) in proto_lines {
+                *lines.entry(line).or_insert(0) += count;
+            }
+        }
+        lines
+    }
+
+    // Called by the VM when execution of a prototype reaches a new line
+    pub(crate) fn record(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+        line: LineNumber,
+    ) {
+        let key = Gc::as_ptr(proto) as usize;
+        if !self.0.read().hits.contains_key(&key) {
+            return;
+        }
+        if let Some(count) = self
+            .0
+            .write(mc)
+            .hits
+            .get_mut(&key)
+            .and_then(|lines| lines.get_mut(&line))
+        {
+            *count += 1;
+        }
+    }
+}
+
+/// Formats line counts as an LCOV tracefile record for the given source file.
+pub fn lcov_record(source_file: &str, lines: &BTreeMap<LineNumber, u64>) -> String {
+    let mut record = String::new();
+    writeln!(record, "SF:{}", source_file).unwrap();
+    for (line, count) in lines {
+        writeln!(record, "DA:{},{}", line, count).unwrap();
+    }
+    writeln!(record, "LF:{}", lines.len()).unwrap();
+    writeln!(
+        record,
+        "LH:{}",
+        lines.values().filter(|&&count| count > 0).count()
+    )
+    .unwrap();
+    writeln!(record, "end_of_record").unwrap();
+    record
+}
+
+/// Formats a standalone HTML page showing the given source with covered lines in green and
+/// uncovered lines in red.
+pub fn html_report(source_file: &str, source: &str, lines: &BTreeMap<LineNumber, u64>) -> String {
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\n.hit {{ background: #cfc; }}\n.miss {{ background: #fcc; }}\n\
+         td {{ padding: 0 0.5em; font-family: monospace; white-space: pre; }}\n</style>\n\
+         </head>\n<body>\n<h1>{}</h1>\n<p>{} of {} lines covered</p>\n<table>",
+        escape_html(source_file),
+        escape_html(source_file),
+        lines.values().filter(|&&count| count > 0).count(),
+        lines.len(),
+    )
+    .unwrap();
+    for (i, text) in source.lines().enumerate() {
+        let line = LineNumber(i as u64 + 1);
+        let (class, count) = match lines.get(&line) {
+            Some(0) => ("miss", "0".to_owned()),
+            Some(count) => ("hit", count.to_string()),
+            None => ("", String::new()),
+        };
+        writeln!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td></tr>",
+            class,
+            line,
+            count,
+            escape_html(text)
+        )
+        .unwrap();
+    }
+    writeln!(html, "</table>\n</body>\n</html>").unwrap();
+    html
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,464 @@
+//! A Debug Adapter Protocol server, so that editors such as VS Code can attach to an embedded
+//! interpreter over TCP.
+//!
+//! The server never blocks and never spawns threads, the embedder creates a `DapServer` and calls
+//! `DapServer::poll` with the thread being debugged wherever it would otherwise step that thread.
+//! While no client is attached, the thread simply runs.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use gc_arena::MutationContext;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    ChunkName, DebugStep, Debugger, LineNumber, PauseReason, StepCommand, Thread, ThreadMode,
+};
+
+// DAP requires a thread id, and the debugger only ever debugs a single thread.
+const THREAD_ID: i64 = 1;
+
+/// The state of the debugged thread after a call to `DapServer::poll`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DapStatus {
+    /// The thread ran for a while and should be polled again.
+    Running,
+    /// The thread is paused or the attached client has not finished configuration yet.
+    Waiting,
+    /// The thread is no longer in `Running` mode.
+    Finished,
+}
+
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    debugger: Debugger,
+}
+
+struct Client {
+    stream: TcpStream,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    seq: i64,
+    configured: bool,
+    paused: bool,
+    terminated: bool,
+    // DAP clients expect a source for every stack frame, for chunks without a name we echo back
+    // whatever source the client last told us about.
+    source: Option<Json>,
+}
+
+impl DapServer {
+    /// Listens for a DAP client on the given address.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let mut debugger = Debugger::new();
+        debugger.resume(StepCommand::Continue);
+        Ok(DapServer {
+            listener,
+            client: None,
+            debugger,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accepts a pending client, handles any requests it has sent, and then steps the given thread
+    /// unless it is paused.
+    pub fn poll<'gc>(
+        &mut self,
+        mc: MutationContext<'gc, '_>,
+        thread: Thread<'gc>,
+    ) -> io::Result<DapStatus> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(Client {
+                        stream,
+                        read_buffer: Vec::new(),
+                        write_buffer: Vec::new(),
+                        seq: 1,
+                        configured: false,
+                        paused: false,
+                        terminated: false,
+                        source: None,
+                    });
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if let Some(client) = &mut self.client {
+            let mut connected = client.receive()?;
+            loop {
+                match client.next_message() {
+                    Ok(Some(request)) => {
+                        handle_request(client, &mut self.debugger, thread, &request)
+                    }
+                    Ok(None) => break,
+                    // Without a valid header there is no telling where the next message starts.
+                    Err(_) => {
+                        connected = false;
+                        break;
+                    }
+                }
+            }
+            client.flush()?;
+            if !connected {
+                self.detach();
+            }
+        }
+
+        if thread.mode() != ThreadMode::Running {
+            if let Some(client) = &mut self.client {
+                if !client.terminated {
+                    client.terminated = true;
+                    client.send_event("exited", json!({ "exitCode": 0 }));
+                    client.send_event("terminated", json!({}));
+                    client.flush()?;
+                }
+            }
+            return Ok(DapStatus::Finished);
+        }
+
+        if let Some(client) = &self.client {
+            if !client.configured || client.paused {
+                return Ok(DapStatus::Waiting);
+            }
+        }
+
+        match self
+            .debugger
+            .step(mc, thread)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+        {
+            DebugStep::Running => Ok(DapStatus::Running),
+            DebugStep::Paused(paused) => {
+                if let Some(client) = &mut self.client {
+                    client.paused = true;
+                    let reason = match paused.reason {
+                        PauseReason::Breakpoint => "breakpoint",
+                        PauseReason::Step => "step",
+                    };
+                    client.send_event(
+                        "stopped",
+                        json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+                    );
+                    client.flush()?;
+                    Ok(DapStatus::Waiting)
+                } else {
+                    // The client detached in between steps, so there is no one to pause for.
+                    self.debugger.resume(StepCommand::Continue);
+                    Ok(DapStatus::Running)
+                }
+            }
+            DebugStep::Finished => Ok(DapStatus::Running),
+        }
+    }
+
+    fn detach(&mut self) {
+        self.client = None;
+        self.debugger.clear_breakpoints();
+        self.debugger.resume(StepCommand::Continue);
+    }
+}
+
+impl Client {
+    // Reads everything currently available, returns false if the client has disconnected.
+    fn receive(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.read_buffer.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref err) if err.kind() == io::ErrorKind::ConnectionReset => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Parses the next complete message out of the read buffer, if there is one.  Messages whose
+    // content is not valid JSON are skipped, an error is only returned for a malformed header.
+    fn next_message(&mut self) -> io::Result<Option<Json>> {
+        while let Some(content) = self.next_content()? {
+            if let Ok(message) = serde_json::from_slice(&content) {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    // Takes the content of the next complete message out of the read buffer, if there is one.
+    fn next_content(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let header_end = match self.read_buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        let header = std::str::from_utf8(&self.read_buffer[..header_end])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut content_length = None;
+        for line in header.split("\r\n") {
+            let mut parts = line.splitn(2, ':');
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                if name.trim().eq_ignore_ascii_case("Content-Length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let content_length = content_length.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing DAP Content-Length header",
+            )
+        })?;
+
+        let content_start = header_end + 4;
+        if self.read_buffer.len() < content_start + content_length {
+            return Ok(None);
+        }
+        let content = self.read_buffer[content_start..content_start + content_length].to_vec();
+        self.read_buffer.drain(..content_start + content_length);
+        Ok(Some(content))
+    }
+
+    fn send(&mut self, mut message: Json) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let content = message.to_string();
+        write!(
+            self.write_buffer,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )
+        .unwrap();
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, &str>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    // Writes as much of the write buffer as the socket will currently accept.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.write_buffer.drain(..n);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_request<'gc>(
+    client: &mut Client,
+    debugger: &mut Debugger,
+    thread: Thread<'gc>,
+    request: &Json,
+) {
+    let arguments = &request["arguments"];
+    match request["command"].as_str().unwrap_or("") {
+        "initialize" => {
+            client.respond(
+                request,
+                Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            );
+            client.send_event("initialized", json!({}));
+        }
+        "launch" | "attach" => {
+            if let Some(program) = arguments["program"].as_str() {
+                client.source = Some(json!({ "path": program }));
+            }
+            let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+            debugger.resume(if stop_on_entry {
+                StepCommand::StepInto
+            } else {
+                StepCommand::Continue
+            });
+            client.respond(request, Ok(json!({})));
+        }
+        "setBreakpoints" => {
+            let chunk = match source_chunk(&arguments["source"]) {
+                Some(chunk) => chunk,
+                None => {
+                    client.respond(request, Err("breakpoint source has no path or name"));
+                    return;
+                }
+            };
+            client.source = Some(arguments["source"].clone());
+            // The request replaces every breakpoint in its source.
+            debugger.clear_chunk_breakpoints(&chunk);
+            let mut breakpoints = Vec::new();
+            if let Some(requested) = arguments["breakpoints"].as_array() {
+                for breakpoint in requested {
+                    if let Some(line) = breakpoint["line"].as_u64() {
+                        debugger.set_breakpoint(chunk.clone(), LineNumber(line));
+                        breakpoints.push(json!({ "verified": true, "line": line }));
+                    }
+                }
+            }
+            client.respond(request, Ok(json!({ "breakpoints": breakpoints })));
+        }
+        "configurationDone" => {
+            client.configured = true;
+            client.respond(request, Ok(json!({})));
+        }
+        "threads" => {
+            client.respond(
+                request,
+                Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            );
+        }
+        "stackTrace" => {
+            let frames = if client.paused {
+                thread.stack_frames()
+            } else {
+                Vec::new()
+            };
+            let stack_frames = frames
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, frame)| {
+                    let mut stack_frame = json!({
+                        "id": frames.len() - 1 - i,
+                        "name": if i == 0 { "main chunk" } else { "function" },
+                        "line": frame.line.map(|l| l.0).unwrap_or(0),
+                        "column": 0,
+                    });
+                    let source = frame
+                        .closure
+                        .0
+                        .proto
+                        .chunk_name
+                        .as_ref()
+                        .map(chunk_source)
+                        .or_else(|| client.source.clone());
+                    if let Some(source) = source {
+                        stack_frame["source"] = source;
+                    }
+                    stack_frame
+                })
+                .collect::<Vec<_>>();
+            client.respond(
+                request,
+                Ok(json!({ "stackFrames": stack_frames, "totalFrames": stack_frames.len() })),
+            );
+        }
+        "scopes" => {
+            // Variable references encode the frame level and which scope of the frame they refer
+            // to, references must be non-zero.
+            let level = arguments["frameId"].as_u64().unwrap_or(0);
+            client.respond(
+                request,
+                Ok(json!({ "scopes": [
+                    { "name": "Locals", "variablesReference": level * 2 + 1, "expensive": false },
+                    { "name": "Upvalues", "variablesReference": level * 2 + 2, "expensive": false },
+                ] })),
+            );
+        }
+        "variables" => {
+            let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+            let mut variables = Vec::new();
+            if client.paused && reference != 0 {
+                let level = ((reference - 1) / 2) as usize;
+                let frames = thread.stack_frames();
+                if let Some(frame) = frames.iter().rev().nth(level) {
+                    let values = if reference % 2 == 1 {
+                        frame.locals()
+                    } else {
+                        let names = &frame.closure.0.proto.upvalue_names;
+                        names.iter().copied().zip(frame.upvalues.clone()).collect()
+                    };
+                    for (name, value) in values {
+                        variables.push(json!({
+                            "name": String::from_utf8_lossy(name.as_bytes()),
+                            "value": value.debug_fmt(1),
+                            "type": value.type_name(),
+                            "variablesReference": 0,
+                        }));
+                    }
+                }
+            }
+            client.respond(request, Ok(json!({ "variables": variables })));
+        }
+        "continue" | "next" | "stepIn" | "stepOut" => {
+            let command = match request["command"].as_str() {
+                Some("continue") => StepCommand::Continue,
+                Some("next") => StepCommand::StepOver,
+                Some("stepIn") => StepCommand::StepInto,
+                _ => StepCommand::StepOut,
+            };
+            debugger.resume(command);
+            client.paused = false;
+            let body = if command == StepCommand::Continue {
+                json!({ "allThreadsContinued": true })
+            } else {
+                json!({})
+            };
+            client.respond(request, Ok(body));
+        }
+        "pause" => {
+            debugger.resume(StepCommand::StepInto);
+            client.respond(request, Ok(json!({})));
+        }
+        "disconnect" => {
+            debugger.clear_breakpoints();
+            debugger.resume(StepCommand::Continue);
+            client.paused = false;
+            client.configured = true;
+            client.respond(request, Ok(json!({})));
+        }
+        _ => client.respond(request, Err("unsupported request")),
+    }
+}
+
+// The chunk a DAP source refers to, files are named by their path as with `load_file` and other
+// sources by their name.
+fn source_chunk(source: &Json) -> Option<ChunkName> {
+    if let Some(path) = source["path"].as_str() {
+        Some(ChunkName::file(path))
+    } else {
+        let name = source["name"].as_str()?;
+        Some(ChunkName::new(&format!("={}", name)))
+    }
+}
+
+// The DAP source for a named chunk, the reverse of `source_chunk`.
+fn chunk_source(chunk: &ChunkName) -> Json {
+    match chunk.as_str().strip_prefix('@') {
+        Some(path) => json!({ "path": path }),
+        None => json!({ "name": chunk.to_string() }),
+    }
+}
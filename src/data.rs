@@ -0,0 +1,146 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::Read;
+
+use gc_arena::MutationContext;
+
+use crate::parser::{
+    ConstructorField, Expression, HeadExpression, PrimaryExpression, RecordKey, SimpleExpression,
+    TableConstructor, UnaryOperator,
+};
+use crate::{parse_chunk, InternedStringSet, InvalidTableKey, ParserError, String, Table, Value};
+
+#[derive(Debug)]
+pub enum DataError {
+    ParserError(ParserError),
+    /// The chunk contains something other than a single `return` of a table constructor.
+    NotATable,
+    /// The table contains an expression that is not a literal, described by the given string.
+    NotLiteral(&'static str),
+    InvalidTableKey(InvalidTableKey),
+}
+
+impl StdError for DataError {}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::ParserError(error) => write!(fmt, "parser error: {}", error),
+            DataError::NotATable => write!(fmt, "data chunk must only return a table constructor"),
+            DataError::NotLiteral(found) => {
+                write!(
+                    fmt,
+                    "data tables may only contain literals, found {}",
+                    found
+                )
+            }
+            DataError::InvalidTableKey(error) => write!(fmt, "invalid table key: {}", error),
+        }
+    }
+}
+
+impl From<ParserError> for DataError {
+    fn from(error: ParserError) -> DataError {
+        DataError::ParserError(error)
+    }
+}
+
+impl From<InvalidTableKey> for DataError {
+    fn from(error: InvalidTableKey) -> DataError {
+        DataError::InvalidTableKey(error)
+    }
+}
+
+/// Builds the table returned by a chunk of the form `return { ... }` directly from the parsed
+/// source, without compiling or running it.
+///
+/// This is meant for large generated data files, which are much faster to load this way and are
+/// not subject to the compiler's limits on constants and registers.  The table may only contain
+/// nil, booleans, numbers (optionally negated), strings and nested tables of those, as keys or
+/// values.  Fields are set in source order, exactly as a table constructor would set them.
+pub fn load_data_table<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    source: R,
+) -> Result<Table<'gc>, DataError> {
+    let chunk = parse_chunk(source, |s| interned_strings.new_string(mc, s))?;
+    if !chunk.block.statements.is_empty() {
+        return Err(DataError::NotATable);
+    }
+    let returns = match &chunk.block.return_statement {
+        Some(return_statement) => &return_statement.node.returns,
+        None => return Err(DataError::NotATable),
+    };
+    match returns.as_slice() {
+        [expression] if expression.tail.is_empty() => match &*expression.head {
+            HeadExpression::Simple(SimpleExpression::TableConstructor(constructor)) => {
+                table(mc, constructor)
+            }
+            _ => Err(DataError::NotATable),
+        },
+        _ => Err(DataError::NotATable),
+    }
+}
+
+fn table<'gc>(
+    mc: MutationContext<'gc, '_>,
+    constructor: &TableConstructor<String<'gc>>,
+) -> Result<Table<'gc>, DataError> {
+    let table = Table::new(mc);
+    let mut array_index = 0;
+    for field in &constructor.fields {
+        match field {
+            ConstructorField::Array(value) => {
+                array_index += 1;
+                table.set(mc, Value::Integer(array_index), literal(mc, value)?)?;
+            }
+            ConstructorField::Record(key, value) => {
+                let key = match key {
+                    RecordKey::Named(key) => Value::String(*key),
+                    RecordKey::Indexed(key) => literal(mc, key)?,
+                };
+                table.set(mc, key, literal(mc, value)?)?;
+            }
+        }
+    }
+    Ok(table)
+}
+
+fn literal<'gc>(
+    mc: MutationContext<'gc, '_>,
+    expression: &Expression<String<'gc>>,
+) -> Result<Value<'gc>, DataError> {
+    if !expression.tail.is_empty() {
+        return Err(DataError::NotLiteral("binary operator"));
+    }
+
+    match &*expression.head {
+        HeadExpression::Simple(simple) => match simple {
+            SimpleExpression::Float(f) => Ok(Value::Number(*f)),
+            SimpleExpression::Integer(i) => Ok(Value::Integer(*i)),
+            SimpleExpression::String(s) => Ok(Value::String(*s)),
+            SimpleExpression::Nil => Ok(Value::Nil),
+            SimpleExpression::True => Ok(Value::Boolean(true)),
+            SimpleExpression::False => Ok(Value::Boolean(false)),
+            SimpleExpression::TableConstructor(constructor) => {
+                Ok(Value::Table(table(mc, constructor)?))
+            }
+            SimpleExpression::VarArgs => Err(DataError::NotLiteral("'...'")),
+            SimpleExpression::Function(_) => Err(DataError::NotLiteral("function")),
+            SimpleExpression::Suffixed(suffixed) => match &suffixed.primary.node {
+                PrimaryExpression::GroupedExpression(inner) if suffixed.suffixes.is_empty() => {
+                    literal(mc, inner)
+                }
+                _ => Err(DataError::NotLiteral("variable or call")),
+            },
+        },
+        HeadExpression::UnaryOperator(UnaryOperator::Minus, operand) => {
+            match literal(mc, operand)? {
+                Value::Integer(i) => Ok(Value::Integer(i.wrapping_neg())),
+                Value::Number(n) => Ok(Value::Number(-n)),
+                _ => Err(DataError::NotLiteral("negated non-number")),
+            }
+        }
+        HeadExpression::UnaryOperator(_, _) => Err(DataError::NotLiteral("unary operator")),
+    }
+}
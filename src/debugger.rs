@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use gc_arena::MutationContext;
+
+use crate::{
+    thread::LuaPosition, BadThreadMode, ChunkName, LineNumber, StackFrame, Thread, ThreadMode,
+};
+
+/// How execution should continue after the debugger has paused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepCommand {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Pause at the next new line, including lines inside called functions.
+    StepInto,
+    /// Pause at the next new line in the current function or in any function it returns to.
+    StepOver,
+    /// Pause at the next new line after the current function has returned.
+    StepOut,
+}
+
+/// Why the debugger paused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+}
+
+/// The state of a thread that the debugger has paused, before the first opcode of `line` has been
+/// executed.
+#[derive(Debug, Clone)]
+pub struct Paused<'gc> {
+    pub reason: PauseReason,
+    pub line: LineNumber,
+    /// Every active Lua function call, from the outermost to the innermost.
+    pub frames: Vec<StackFrame<'gc>>,
+}
+
+impl<'gc> Paused<'gc> {
+    /// The innermost active Lua function call, the one that is paused.
+    pub fn current_frame(&self) -> &StackFrame<'gc> {
+        self.frames.last().expect("paused thread has no lua frames")
+    }
+
+    /// Returns the stack frame `level` calls up from the paused frame, where level 0 is the
+    /// paused frame itself.
+    pub fn frame(&self, level: usize) -> Option<&StackFrame<'gc>> {
+        self.frames.iter().rev().nth(level)
+    }
+}
+
+#[derive(Debug)]
+pub enum DebugStep<'gc> {
+    /// The thread has not paused or finished yet and should be stepped again.
+    Running,
+    Paused(Paused<'gc>),
+    /// The thread is no longer in `Running` mode, its results (if any) can be taken as usual.
+    Finished,
+}
+
+/// Drives a `Thread` one opcode at a time, pausing at line breakpoints and for stepping.
+///
+/// Breakpoints are set on a line of a named chunk, see `ChunkName`, so code compiled without a
+/// chunk name only pauses for stepping.  Only the given thread is debugged, Lua code running inside
+/// of coroutines that the thread resumes is run without pausing.
+#[derive(Debug)]
+pub struct Debugger {
+    breakpoints: BTreeMap<ChunkName, BTreeSet<LineNumber>>,
+    mode: StepMode,
+    // The last (pc, line) seen for each active Lua frame depth.
+    positions: Vec<(usize, LineNumber)>,
+    // Set when we have paused, so that resuming does not immediately pause again in the same
+    // place.
+    paused: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger with no breakpoints that will pause on the first line it executes.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            mode: StepMode::StepInto,
+            positions: Vec::new(),
+            paused: false,
+        }
+    }
+
+    /// Returns true if the breakpoint was not already set.
+    pub fn set_breakpoint(&mut self, chunk: ChunkName, line: LineNumber) -> bool {
+        self.breakpoints.entry(chunk).or_default().insert(line)
+    }
+
+    /// Returns true if the breakpoint was set.
+    pub fn clear_breakpoint(&mut self, chunk: &ChunkName, line: LineNumber) -> bool {
+        match self.breakpoints.get_mut(chunk) {
+            Some(lines) => {
+                let removed = lines.remove(&line);
+                if lines.is_empty() {
+                    self.breakpoints.remove(chunk);
+                }
+                removed
+            }
+            None => false,
+        }
+    }
+
+    /// Clears every breakpoint in the given chunk.
+    pub fn clear_chunk_breakpoints(&mut self, chunk: &ChunkName) {
+        self.breakpoints.remove(chunk);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (&ChunkName, LineNumber)> + '_ {
+        self.breakpoints
+            .iter()
+            .flat_map(|(chunk, lines)| lines.iter().map(move |&line| (chunk, line)))
+    }
+
+    /// Sets how execution should continue from the current position.
+    pub fn resume(&mut self, command: StepCommand) {
+        let depth = self.positions.len();
+        self.mode = match command {
+            StepCommand::Continue => StepMode::Continue,
+            StepCommand::StepInto => StepMode::StepInto,
+            StepCommand::StepOver => StepMode::StepOver(depth),
+            StepCommand::StepOut => StepMode::StepOut(depth),
+        };
+    }
+
+    /// Steps the given `Running` thread until it pauses, is no longer running, or a bounded
+    /// amount of work has been done.
+    pub fn step<'gc>(
+        &mut self,
+        mc: MutationContext<'gc, '_>,
+        thread: Thread<'gc>,
+    ) -> Result<DebugStep<'gc>, BadThreadMode> {
+        const DEBUG_GRANULARITY: u32 = 256;
+
+        for _ in 0..DEBUG_GRANULARITY {
+            if thread.mode() != ThreadMode::Running {
+                self.positions.clear();
+                return Ok(DebugStep::Finished);
+            }
+
+            if !self.paused {
+                if let Some(position) = thread.lua_position() {
+                    if let Some(reason) = self.check_position(position) {
+                        self.paused = true;
+                        let frames = thread.stack_frames();
+                        let line = frames
+                            .last()
+                            .and_then(|f| f.line)
+                            .expect("paused frame has no line");
+                        return Ok(DebugStep::Paused(Paused {
+                            reason,
+                            line,
+                            frames,
+                        }));
+                    }
+                }
+            }
+            self.paused = false;
+
+            thread.step_instructions(mc, 1)?;
+        }
+
+        Ok(DebugStep::Running)
+    }
+
+    // Records the position that the thread is about to execute, and returns whether we should
+    // pause before executing it.
+    fn check_position(&mut self, position: LuaPosition) -> Option<PauseReason> {
+        let proto = position.closure.0.proto;
+        let line = proto.opcode_line(position.pc)?;
+
+        // A new line is reached on entering a function, on changing lines, or on jumping
+        // backwards.  Returning to a caller on the same line as its call is not a new line.
+        self.positions.truncate(position.depth);
+        let new_line = if self.positions.len() == position.depth {
+            let last = self.positions.last_mut().unwrap();
+            let new_line = last.1 != line || position.pc < last.0;
+            *last = (position.pc, line);
+            new_line
+        } else {
+            self.positions.resize(position.depth, (position.pc, line));
+            true
+        };
+
+        let breakpoint = proto
+            .chunk_name
+            .as_ref()
+            .and_then(|chunk| self.breakpoints.get(chunk))
+            .is_some_and(|lines| lines.contains(&line));
+        if !new_line {
+            None
+        } else if breakpoint {
+            Some(PauseReason::Breakpoint)
+        } else {
+            match self.mode {
+                StepMode::Continue => None,
+                StepMode::StepInto => Some(PauseReason::Step),
+                StepMode::StepOver(depth) if position.depth <= depth => Some(PauseReason::Step),
+                StepMode::StepOut(depth) if position.depth < depth => Some(PauseReason::Step),
+                StepMode::StepOver(_) | StepMode::StepOut(_) => None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum StepMode {
+    Continue,
+    StepInto,
+    StepOver(usize),
+    StepOut(usize),
+}
@@ -5,8 +5,9 @@ use std::{fmt, io};
 use gc_arena::{Collect, MutationContext, StaticCollect};
 
 use crate::{
-    BadThreadMode, BinaryOperatorError, ClosureError, CompilerError, InternedStringSet,
-    InvalidTableKey, ParserError, StringError, ThreadError, Value,
+    BadThreadMode, BinaryOperatorError, BytecodeError, ClosureError, CompilerError, ForLoopError,
+    InternedStringSet, InvalidTableKey, ParserError, ReplayError, StringError, SyntaxError,
+    ThreadError, Value,
 };
 
 #[derive(Debug, Clone, Copy, Collect)]
@@ -49,7 +50,9 @@ impl<'gc> fmt::Display for RuntimeError<'gc> {
 pub enum Error<'gc> {
     IoError(StaticCollect<io::Error>),
     ParserError(ParserError),
+    SyntaxError(SyntaxError),
     CompilerError(CompilerError),
+    BytecodeError(BytecodeError),
     ClosureError(ClosureError),
     InvalidTableKey(InvalidTableKey),
     StringError(StringError),
@@ -57,6 +60,8 @@ pub enum Error<'gc> {
     BadThreadMode(BadThreadMode),
     TypeError(TypeError),
     BinaryOperatorError(BinaryOperatorError),
+    ForLoopError(ForLoopError),
+    ReplayError(ReplayError),
     RuntimeError(RuntimeError<'gc>),
 }
 
@@ -67,7 +72,9 @@ impl<'gc> fmt::Display for Error<'gc> {
         match self {
             Error::IoError(error) => write!(fmt, "i/o error: {}", error.0),
             Error::ParserError(error) => write!(fmt, "parser error: {}", error),
+            Error::SyntaxError(error) => write!(fmt, "parser error: {}", error),
             Error::CompilerError(error) => write!(fmt, "compiler error: {}", error),
+            Error::BytecodeError(error) => write!(fmt, "bytecode error: {}", error),
             Error::ClosureError(error) => write!(fmt, "closure error: {}", error),
             Error::InvalidTableKey(error) => write!(fmt, "invalid table key: {}", error),
             Error::StringError(error) => write!(fmt, "string error: {}", error),
@@ -75,6 +82,8 @@ impl<'gc> fmt::Display for Error<'gc> {
             Error::BadThreadMode(error) => write!(fmt, "bad thread mode: {}", error),
             Error::TypeError(error) => write!(fmt, "type error: {}", error),
             Error::BinaryOperatorError(error) => write!(fmt, "operator error: {}", error),
+            Error::ForLoopError(error) => write!(fmt, "for loop error: {}", error),
+            Error::ReplayError(error) => write!(fmt, "replay error: {}", error),
             Error::RuntimeError(error) => write!(fmt, "runtime error: {}", error),
         }
     }
@@ -92,12 +101,24 @@ impl<'gc> From<ParserError> for Error<'gc> {
     }
 }
 
+impl<'gc> From<SyntaxError> for Error<'gc> {
+    fn from(error: SyntaxError) -> Error<'gc> {
+        Error::SyntaxError(error)
+    }
+}
+
 impl<'gc> From<CompilerError> for Error<'gc> {
     fn from(error: CompilerError) -> Error<'gc> {
         Error::CompilerError(error)
     }
 }
 
+impl<'gc> From<BytecodeError> for Error<'gc> {
+    fn from(error: BytecodeError) -> Error<'gc> {
+        Error::BytecodeError(error)
+    }
+}
+
 impl<'gc> From<ClosureError> for Error<'gc> {
     fn from(error: ClosureError) -> Error<'gc> {
         Error::ClosureError(error)
@@ -140,6 +161,18 @@ impl<'gc> From<BinaryOperatorError> for Error<'gc> {
     }
 }
 
+impl<'gc> From<ForLoopError> for Error<'gc> {
+    fn from(error: ForLoopError) -> Error<'gc> {
+        Error::ForLoopError(error)
+    }
+}
+
+impl<'gc> From<ReplayError> for Error<'gc> {
+    fn from(error: ReplayError) -> Error<'gc> {
+        Error::ReplayError(error)
+    }
+}
+
 impl<'gc> From<RuntimeError<'gc>> for Error<'gc> {
     fn from(error: RuntimeError<'gc>) -> Error<'gc> {
         Error::RuntimeError(error)
@@ -151,7 +184,9 @@ impl<'gc> Error<'gc> {
         match self {
             Error::IoError(error) => StaticError::IoError(error.0),
             Error::ParserError(error) => StaticError::ParserError(error),
+            Error::SyntaxError(error) => StaticError::SyntaxError(error),
             Error::CompilerError(error) => StaticError::CompilerError(error),
+            Error::BytecodeError(error) => StaticError::BytecodeError(error),
             Error::ClosureError(error) => StaticError::ClosureError(error),
             Error::InvalidTableKey(error) => StaticError::InvalidTableKey(error),
             Error::StringError(error) => StaticError::StringError(error),
@@ -159,6 +194,8 @@ impl<'gc> Error<'gc> {
             Error::BadThreadMode(error) => StaticError::BadThreadMode(error),
             Error::TypeError(error) => StaticError::TypeError(error),
             Error::BinaryOperatorError(error) => StaticError::BinaryOperatorError(error),
+            Error::ForLoopError(error) => StaticError::ForLoopError(error),
+            Error::ReplayError(error) => StaticError::ReplayError(error),
             Error::RuntimeError(error) => {
                 let mut buf = Vec::new();
                 error.0.display(&mut buf).unwrap();
@@ -187,7 +224,9 @@ impl<'gc> Error<'gc> {
 pub enum StaticError {
     IoError(io::Error),
     ParserError(ParserError),
+    SyntaxError(SyntaxError),
     CompilerError(CompilerError),
+    BytecodeError(BytecodeError),
     ClosureError(ClosureError),
     InvalidTableKey(InvalidTableKey),
     StringError(StringError),
@@ -195,6 +234,8 @@ pub enum StaticError {
     BadThreadMode(BadThreadMode),
     TypeError(TypeError),
     BinaryOperatorError(BinaryOperatorError),
+    ForLoopError(ForLoopError),
+    ReplayError(ReplayError),
     RuntimeError(String),
 }
 
@@ -205,7 +246,9 @@ impl fmt::Display for StaticError {
         match self {
             StaticError::IoError(error) => write!(fmt, "i/o error: {}", error),
             StaticError::ParserError(error) => write!(fmt, "parser error: {}", error),
+            StaticError::SyntaxError(error) => write!(fmt, "parser error: {}", error),
             StaticError::CompilerError(error) => write!(fmt, "compiler error: {}", error),
+            StaticError::BytecodeError(error) => write!(fmt, "bytecode error: {}", error),
             StaticError::ClosureError(error) => write!(fmt, "closure error: {}", error),
             StaticError::InvalidTableKey(error) => write!(fmt, "invalid table key: {}", error),
             StaticError::StringError(error) => write!(fmt, "string error: {}", error),
@@ -213,6 +256,8 @@ impl fmt::Display for StaticError {
             StaticError::BadThreadMode(error) => write!(fmt, "bad thread mode: {}", error),
             StaticError::TypeError(error) => write!(fmt, "type error: {}", error),
             StaticError::BinaryOperatorError(error) => write!(fmt, "operator error: {}", error),
+            StaticError::ForLoopError(error) => write!(fmt, "for loop error: {}", error),
+            StaticError::ReplayError(error) => write!(fmt, "replay error: {}", error),
             StaticError::RuntimeError(error) => write!(fmt, "runtime error: {}", error),
         }
     }
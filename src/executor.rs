@@ -0,0 +1,210 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use gc_arena::{Collect, GcCell, MutationContext};
+
+use crate::{Error, Function, Thread, ThreadMode, Value};
+
+/// A cooperative scheduler for Lua tasks, driven by a clock that only moves when the host calls
+/// `Executor::advance`.
+///
+/// Each task runs on its own yieldable `Thread`.  When a task yields, a single number yielded is
+/// the number of seconds it wants to sleep for (this is what `timer.sleep` does), anything else
+/// reschedules it for the next call to `Executor::run`.  Tasks never run in parallel, and nothing
+/// happens between calls to `Executor::run`.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(require_copy)]
+pub struct Executor<'gc>(GcCell<'gc, ExecutorState<'gc>>);
+
+/// Identifies a task spawned on an `Executor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(pub i64);
+
+/// A task that raised an error, which removes it from the executor.
+#[derive(Debug)]
+pub struct TaskError<'gc> {
+    pub task: TaskId,
+    pub error: Error<'gc>,
+}
+
+impl<'gc> StdError for TaskError<'gc> {}
+
+impl<'gc> fmt::Display for TaskError<'gc> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "task {} failed: {}", self.task.0, self.error)
+    }
+}
+
+#[derive(Debug, Collect)]
+#[collect(empty_drop)]
+pub struct ExecutorState<'gc> {
+    // The thread whose settings every task thread is created with
+    main_thread: Thread<'gc>,
+    now: f64,
+    next_id: i64,
+    tasks: Vec<Task<'gc>>,
+}
+
+#[derive(Debug, Collect)]
+#[collect(empty_drop)]
+struct Task<'gc> {
+    id: i64,
+    thread: Thread<'gc>,
+    function: Function<'gc>,
+    wake_at: f64,
+    // Set for tasks that restart every given number of seconds once their function returns
+    every: Option<f64>,
+}
+
+impl<'gc> Executor<'gc> {
+    /// Creates an executor whose tasks run on threads with the settings of `main_thread`, see
+    /// `Thread::new_inheriting`.
+    pub fn new(mc: MutationContext<'gc, '_>, main_thread: Thread<'gc>) -> Executor<'gc> {
+        Executor(GcCell::allocate(
+            mc,
+            ExecutorState {
+                main_thread,
+                now: 0.0,
+                next_id: 1,
+                tasks: Vec::new(),
+            },
+        ))
+    }
+
+    /// The current time of the executor's clock, in seconds.
+    pub fn now(self) -> f64 {
+        self.0.read().now
+    }
+
+    /// Moves the clock forward by `dt` seconds.  Tasks whose deadline has now passed are run on
+    /// the next call to `Executor::run`.
+    pub fn advance(self, mc: MutationContext<'gc, '_>, dt: f64) {
+        assert!(dt >= 0.0, "the executor clock cannot go backwards");
+        self.0.write(mc).now += dt;
+    }
+
+    /// Spawns a task that calls `function` on the next call to `Executor::run`.
+    pub fn spawn(self, mc: MutationContext<'gc, '_>, function: Function<'gc>) -> TaskId {
+        self.schedule(mc, function, 0.0, None)
+    }
+
+    /// Spawns a task that calls `function` once `delay` seconds have passed.  If `every` is given,
+    /// the function is called again each `every` seconds after that until it returns false or the
+    /// task is cancelled.
+    pub fn schedule(
+        self,
+        mc: MutationContext<'gc, '_>,
+        function: Function<'gc>,
+        delay: f64,
+        every: Option<f64>,
+    ) -> TaskId {
+        let thread = Thread::new_inheriting(mc, self.0.read().main_thread, true);
+        thread
+            .start_suspended(mc, function)
+            .expect("new threads are always stopped");
+
+        let mut state = self.0.write(mc);
+        let id = state.next_id;
+        state.next_id += 1;
+        let wake_at = state.now + delay;
+        state.tasks.push(Task {
+            id,
+            thread,
+            function,
+            wake_at,
+            every,
+        });
+        TaskId(id)
+    }
+
+    /// Removes a task, returning false if it had already finished or been cancelled.
+    pub fn cancel(self, mc: MutationContext<'gc, '_>, task: TaskId) -> bool {
+        let mut state = self.0.write(mc);
+        let len = state.tasks.len();
+        state.tasks.retain(|t| t.id != task.0);
+        state.tasks.len() != len
+    }
+
+    /// The number of tasks that have not finished or been cancelled.
+    pub fn task_count(self) -> usize {
+        self.0.read().tasks.len()
+    }
+
+    /// Runs every task whose deadline has passed until it yields, finishes or has been stepped
+    /// `max_steps` times, in which case it continues on the next call.  Tasks spawned while
+    /// running are not run until the next call.
+    ///
+    /// Tasks that raise an error are removed and their errors returned.
+    pub fn run(self, mc: MutationContext<'gc, '_>, max_steps: usize) -> Vec<TaskError<'gc>> {
+        let (now, ready) = {
+            let state = self.0.read();
+            let ready = state
+                .tasks
+                .iter()
+                .filter(|t| t.wake_at <= state.now)
+                .map(|t| (t.id, t.thread))
+                .collect::<Vec<_>>();
+            (state.now, ready)
+        };
+
+        let mut errors = Vec::new();
+        for (id, thread) in ready {
+            if thread.mode() == ThreadMode::Suspended {
+                thread
+                    .resume(mc, &[])
+                    .expect("thread mode was just checked");
+            }
+            for _ in 0..max_steps {
+                if thread.mode() != ThreadMode::Running {
+                    break;
+                }
+                thread.step(mc).expect("thread mode was just checked");
+            }
+
+            let results = match thread.take_results(mc) {
+                Some(results) => results,
+                None => continue,
+            };
+
+            let mut state = self.0.write(mc);
+            let index = match state.tasks.iter().position(|t| t.id == id) {
+                Some(index) => index,
+                // The task cancelled itself
+                None => continue,
+            };
+            match results {
+                Err(error) => {
+                    state.tasks.remove(index);
+                    errors.push(TaskError {
+                        task: TaskId(id),
+                        error,
+                    });
+                }
+                Ok(values) => {
+                    let task = &mut state.tasks[index];
+                    if thread.mode() == ThreadMode::Suspended {
+                        task.wake_at = match values.as_slice() {
+                            [Value::Integer(i)] => now + *i as f64,
+                            [Value::Number(n)] => now + n,
+                            _ => now,
+                        };
+                    } else {
+                        let stopped = values.first() == Some(&Value::Boolean(false));
+                        match task.every {
+                            Some(every) if !stopped => {
+                                task.wake_at = (task.wake_at + every).max(now);
+                                thread
+                                    .start_suspended(mc, task.function)
+                                    .expect("finished threads are always stopped");
+                            }
+                            _ => {
+                                state.tasks.remove(index);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        errors
+    }
+}
@@ -0,0 +1,216 @@
+//! A standard `fetch(url, opts)` Lua API for HTTP-style requests, implemented by the embedder.
+//!
+//! luster does not do any networking itself.  Instead the host passes an async function to
+//! `load_fetch`, which is called with a `FetchRequest` for every call to `fetch` from Lua.  The
+//! returned future is polled once each time the calling thread is stepped, and the calling Lua
+//! function is suspended inside `fetch` until it completes, so tasks on an `Executor` keep
+//! running while a request is in flight.
+//!
+//! Futures are polled with a waker that does nothing, so they must make progress when polled
+//! rather than waiting to be woken.  Futures that check a channel or a shared flag work well,
+//! while futures tied to a particular async runtime generally do not.
+//!
+//! From Lua, `fetch(url [, opts])` takes an optional table with the fields:
+//!
+//! * `method`: the request method, "GET" by default
+//! * `headers`: a table mapping header names to values
+//! * `body`: the request body as a string
+//!
+//! It returns a response table with the fields `status`, `headers` and `body`, or nil and an error
+//! message if the request failed.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::rc::Rc;
+use std::string::String as StdString;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence::Sequence;
+
+use crate::{Callback, CallbackResult, Error, String, Table, TypeError, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    pub url: Vec<u8>,
+    pub method: Vec<u8>,
+    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResponse {
+    pub status: u16,
+    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+/// The future returned by a fetch implementation, resolving to a response or an error message.
+pub type FetchFuture = Pin<Box<dyn Future<Output = Result<FetchResponse, StdString>>>>;
+
+/// Sets the global `fetch` function in `env`, using `handler` to perform each request.
+pub fn load_fetch<'gc, F, Fut>(mc: MutationContext<'gc, '_>, env: Table<'gc>, handler: F)
+where
+    F: 'static + Fn(FetchRequest) -> Fut,
+    Fut: 'static + Future<Output = Result<FetchResponse, StdString>>,
+{
+    let handler = Rc::new(handler);
+    env.set(
+        mc,
+        String::new_static(b"fetch"),
+        Callback::new_sequence(mc, move |args| {
+            let request = fetch_request(&args)?;
+            Ok(PendingFetch {
+                future: Box::pin(handler(request)),
+            })
+        }),
+    )
+    .unwrap();
+}
+
+// A waker that does nothing when woken, as `Waker::noop` needs a newer Rust than we support.
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    // None of the vtable functions use the data pointer.
+    unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) }
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct PendingFetch {
+    future: FetchFuture,
+}
+
+impl<'gc> Sequence<'gc> for PendingFetch {
+    type Output = Result<CallbackResult<'gc>, Error<'gc>>;
+
+    fn step(&mut self, mc: MutationContext<'gc, '_>) -> Option<Self::Output> {
+        let waker = noop_waker();
+        let mut context = Context::from_waker(&waker);
+        match self.future.as_mut().poll(&mut context) {
+            Poll::Pending => None,
+            Poll::Ready(Ok(response)) => {
+                let headers = Table::new(mc);
+                for (name, value) in response.headers {
+                    headers
+                        .set(mc, String::new(mc, &name), String::new(mc, &value))
+                        .unwrap();
+                }
+                let table = Table::new(mc);
+                table
+                    .set(
+                        mc,
+                        String::new_static(b"status"),
+                        Value::Integer(response.status.into()),
+                    )
+                    .unwrap();
+                table
+                    .set(mc, String::new_static(b"headers"), headers)
+                    .unwrap();
+                table
+                    .set(
+                        mc,
+                        String::new_static(b"body"),
+                        String::new(mc, &response.body),
+                    )
+                    .unwrap();
+                Some(Ok(CallbackResult::Return(vec![Value::Table(table)])))
+            }
+            Poll::Ready(Err(message)) => Some(Ok(CallbackResult::Return(vec![
+                Value::Nil,
+                Value::String(String::new(mc, message.as_bytes())),
+            ]))),
+        }
+    }
+}
+
+fn fetch_request<'gc>(args: &[Value<'gc>]) -> Result<FetchRequest, Error<'gc>> {
+    let url = match args.first().cloned().unwrap_or(Value::Nil) {
+        Value::String(url) => url.as_bytes().to_vec(),
+        value => {
+            return Err(TypeError {
+                expected: "string",
+                found: value.type_name(),
+            }
+            .into());
+        }
+    };
+
+    let mut request = FetchRequest {
+        url,
+        method: b"GET".to_vec(),
+        headers: Vec::new(),
+        body: None,
+    };
+
+    let opts = match args.get(1).cloned().unwrap_or(Value::Nil) {
+        Value::Nil => return Ok(request),
+        Value::Table(opts) => opts,
+        value => {
+            return Err(TypeError {
+                expected: "table",
+                found: value.type_name(),
+            }
+            .into());
+        }
+    };
+
+    match opts.get(String::new_static(b"method")) {
+        Value::Nil => {}
+        Value::String(method) => request.method = method.as_bytes().to_vec(),
+        value => {
+            return Err(TypeError {
+                expected: "string",
+                found: value.type_name(),
+            }
+            .into());
+        }
+    }
+
+    match opts.get(String::new_static(b"headers")) {
+        Value::Nil => {}
+        Value::Table(headers) => {
+            for (name, value) in headers.0.read().iter() {
+                match (name, value) {
+                    (Value::String(name), Value::String(value)) => request
+                        .headers
+                        .push((name.as_bytes().to_vec(), value.as_bytes().to_vec())),
+                    _ => {
+                        return Err(TypeError {
+                            expected: "string header",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                }
+            }
+        }
+        value => {
+            return Err(TypeError {
+                expected: "table",
+                found: value.type_name(),
+            }
+            .into());
+        }
+    }
+
+    match opts.get(String::new_static(b"body")) {
+        Value::Nil => {}
+        Value::String(body) => request.body = Some(body.as_bytes().to_vec()),
+        value => {
+            return Err(TypeError {
+                expected: "string",
+                found: value.type_name(),
+            }
+            .into());
+        }
+    }
+
+    Ok(request)
+}
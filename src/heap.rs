@@ -0,0 +1,412 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::mem;
+use std::string::String as StdString;
+
+use gc_arena::{Gc, MutationContext};
+
+use crate::{
+    Callback, Closure, ClosureState, Constant, Function, FunctionProto, OpCode, Root, String,
+    Table, UpValue, Value,
+};
+
+/// The number of objects of one type and an estimate of the memory they use.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ObjectStats {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSize {
+    /// The reference path from the root to this table, as returned by `reference_path`.
+    pub path: Vec<StdString>,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// A count of every object reachable from a `Root`, see `heap_census`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeapCensus {
+    pub tables: ObjectStats,
+    pub strings: ObjectStats,
+    pub closures: ObjectStats,
+    pub prototypes: ObjectStats,
+    pub callbacks: ObjectStats,
+    pub threads: ObjectStats,
+    /// The largest tables by estimated size, largest first.
+    pub largest_tables: Vec<TableSize>,
+}
+
+/// Walks every object reachable from the given root and counts them by type, also recording the
+/// `largest` biggest tables.
+///
+/// Byte sizes are estimates of the memory owned by each object, not including the memory of the
+/// objects that it references.  Strings in the interned string set that are not referenced from
+/// anywhere else are not counted.
+pub fn heap_census<'gc>(root: Root<'gc>, largest: usize) -> HeapCensus {
+    let walk = walk(root, None);
+    let mut census = HeapCensus::default();
+    let mut tables = Vec::new();
+
+    for &(id, node) in &walk.order {
+        match node {
+            Node::Value(Value::Table(table)) => {
+                let state = table.0.read();
+                let bytes = state.heap_size();
+                add(&mut census.tables, bytes);
+                tables.push((bytes, state.iter().count(), id));
+            }
+            Node::Value(Value::String(string)) => add(&mut census.strings, string_size(string)),
+            Node::Value(Value::Function(Function::Closure(closure))) => {
+                add(
+                    &mut census.closures,
+                    mem::size_of::<ClosureState>()
+                        + closure.0.upvalues.capacity() * mem::size_of::<UpValue>(),
+                );
+            }
+            Node::Value(Value::Function(Function::Callback(_))) => {
+                add(&mut census.callbacks, mem::size_of::<Callback>())
+            }
+            Node::Value(Value::Thread(thread)) => add(&mut census.threads, thread.heap_size()),
+            Node::Value(_) => {}
+            Node::Proto(proto) => add(&mut census.prototypes, proto_size(&proto)),
+        }
+    }
+
+    tables.sort_by_key(|&(bytes, _, _)| Reverse(bytes));
+    census.largest_tables = tables
+        .into_iter()
+        .take(largest)
+        .map(|(bytes, entries, id)| TableSize {
+            path: walk.path(id),
+            entries,
+            bytes,
+        })
+        .collect();
+    census
+}
+
+/// Finds a shortest chain of references from the given root to `target`.
+///
+/// Each element of the returned path describes one reference: `globals` or `main_thread` from the
+/// root, `[key]` for a table entry and `(key)` for a table key (formatted as by `Value::debug_fmt`),
+/// `upvalue N` and `prototype` from a closure, `constant N` and `prototype N` from a function
+/// prototype, and `stack N` from a thread.  Returns `None` if the target is not an object or is
+/// unreachable.
+pub fn reference_path<'gc>(root: Root<'gc>, target: Value<'gc>) -> Option<Vec<StdString>> {
+    let target = object_id(Node::Value(target))?;
+    let walk = walk(root, Some(target));
+    if walk.parents.contains_key(&target) {
+        Some(walk.path(target))
+    } else {
+        None
+    }
+}
+
+impl HeapCensus {
+    pub fn total(&self) -> ObjectStats {
+        let mut total = ObjectStats::default();
+        for stats in &[
+            self.tables,
+            self.strings,
+            self.closures,
+            self.prototypes,
+            self.callbacks,
+            self.threads,
+        ] {
+            total.count += stats.count;
+            total.bytes += stats.bytes;
+        }
+        total
+    }
+
+    /// Converts this census into a Lua table of the same shape, with `count` and `bytes` fields
+    /// for each object type, and `largest_tables` as a sequence of tables with `path`, `entries`
+    /// and `bytes` fields.
+    pub fn to_table<'gc>(&self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let table = Table::new(mc);
+        for (name, stats) in self.stats() {
+            table
+                .set(
+                    mc,
+                    String::new_static(name.as_bytes()),
+                    stats_table(mc, stats),
+                )
+                .unwrap();
+        }
+
+        let largest_tables = Table::new(mc);
+        for (i, table_size) in self.largest_tables.iter().enumerate() {
+            let entry = Table::new(mc);
+            let path = Table::new(mc);
+            for (j, segment) in table_size.path.iter().enumerate() {
+                path.set(mc, j as i64 + 1, String::new(mc, segment.as_bytes()))
+                    .unwrap();
+            }
+            entry.set(mc, String::new_static(b"path"), path).unwrap();
+            entry
+                .set(
+                    mc,
+                    String::new_static(b"entries"),
+                    table_size.entries as i64,
+                )
+                .unwrap();
+            entry
+                .set(mc, String::new_static(b"bytes"), table_size.bytes as i64)
+                .unwrap();
+            largest_tables.set(mc, i as i64 + 1, entry).unwrap();
+        }
+        table
+            .set(mc, String::new_static(b"largest_tables"), largest_tables)
+            .unwrap();
+        table
+    }
+
+    /// Formats this census as JSON, with the same shape as `HeapCensus::to_table`.
+    pub fn to_json(&self) -> StdString {
+        let mut out = StdString::from("{");
+        for (name, stats) in self.stats() {
+            write!(
+                out,
+                "\"{}\":{{\"count\":{},\"bytes\":{}}},",
+                name, stats.count, stats.bytes
+            )
+            .unwrap();
+        }
+        out.push_str("\"largest_tables\":[");
+        for (i, table_size) in self.largest_tables.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            out.push_str("{\"path\":[");
+            for (j, segment) in table_size.path.iter().enumerate() {
+                if j != 0 {
+                    out.push(',');
+                }
+                write_json_string(&mut out, segment);
+            }
+            write!(
+                out,
+                "],\"entries\":{},\"bytes\":{}}}",
+                table_size.entries, table_size.bytes
+            )
+            .unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+
+    fn stats(&self) -> [(&'static str, ObjectStats); 6] {
+        [
+            ("tables", self.tables),
+            ("strings", self.strings),
+            ("closures", self.closures),
+            ("prototypes", self.prototypes),
+            ("callbacks", self.callbacks),
+            ("threads", self.threads),
+        ]
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Node<'gc> {
+    Value(Value<'gc>),
+    Proto(Gc<'gc, FunctionProto<'gc>>),
+}
+
+struct Walk<'gc> {
+    // Every object reached, in breadth first order
+    order: Vec<(usize, Node<'gc>)>,
+    // The object each object was first reached from and a description of the reference, objects
+    // referenced directly from the root have no parent.
+    parents: HashMap<usize, (Option<usize>, StdString)>,
+}
+
+impl<'gc> Walk<'gc> {
+    fn path(&self, mut id: usize) -> Vec<StdString> {
+        let mut path = Vec::new();
+        while let Some((parent, label)) = self.parents.get(&id) {
+            path.push(label.clone());
+            match parent {
+                Some(parent) => id = *parent,
+                None => break,
+            }
+        }
+        path.reverse();
+        path
+    }
+}
+
+// Breadth first search of every object reachable from the root, stopping early if `target` is
+// reached.
+fn walk<'gc>(root: Root<'gc>, target: Option<usize>) -> Walk<'gc> {
+    let mut walk = Walk {
+        order: Vec::new(),
+        parents: HashMap::new(),
+    };
+    let visit = |walk: &mut Walk<'gc>, parent: Option<usize>, label: StdString, node| {
+        if let Some(id) = object_id(node) {
+            if let Entry::Vacant(vacant) = walk.parents.entry(id) {
+                vacant.insert((parent, label));
+                walk.order.push((id, node));
+            }
+        }
+    };
+
+    visit(
+        &mut walk,
+        None,
+        "globals".to_owned(),
+        Node::Value(Value::Table(root.globals)),
+    );
+    visit(
+        &mut walk,
+        None,
+        "main_thread".to_owned(),
+        Node::Value(Value::Thread(root.main_thread)),
+    );
+
+    // `walk.order` doubles as the search queue
+    let mut next = 0;
+    while next < walk.order.len() {
+        let (id, node) = walk.order[next];
+        next += 1;
+        if Some(id) == target {
+            break;
+        }
+        let parent = Some(id);
+        match node {
+            Node::Value(Value::Table(table)) => {
+                for (key, value) in table.0.read().iter() {
+                    let key_desc = key.debug_fmt(0);
+                    visit(
+                        &mut walk,
+                        parent,
+                        format!("({})", key_desc),
+                        Node::Value(key),
+                    );
+                    visit(
+                        &mut walk,
+                        parent,
+                        format!("[{}]", key_desc),
+                        Node::Value(value),
+                    );
+                }
+            }
+            Node::Value(Value::Function(Function::Closure(closure))) => {
+                for (i, upvalue) in closure.0.upvalues.iter().enumerate() {
+                    visit(
+                        &mut walk,
+                        parent,
+                        format!("upvalue {}", i),
+                        Node::Value(upvalue.get()),
+                    );
+                }
+                visit(
+                    &mut walk,
+                    parent,
+                    "prototype".to_owned(),
+                    Node::Proto(closure.0.proto),
+                );
+            }
+            Node::Value(Value::Thread(thread)) => {
+                for (i, value) in thread.referenced_values().into_iter().enumerate() {
+                    visit(
+                        &mut walk,
+                        parent,
+                        format!("stack {}", i),
+                        Node::Value(value),
+                    );
+                }
+            }
+            Node::Value(_) => {}
+            Node::Proto(proto) => {
+                for (i, constant) in proto.constants.iter().enumerate() {
+                    if let Constant::String(s) = constant {
+                        visit(
+                            &mut walk,
+                            parent,
+                            format!("constant {}", i),
+                            Node::Value(Value::String(*s)),
+                        );
+                    }
+                }
+                for (i, prototype) in proto.prototypes.iter().enumerate() {
+                    visit(
+                        &mut walk,
+                        parent,
+                        format!("prototype {}", i),
+                        Node::Proto(*prototype),
+                    );
+                }
+            }
+        }
+    }
+
+    walk
+}
+
+// Returns a unique identifier for heap allocated objects, and None for everything else.
+fn object_id(node: Node) -> Option<usize> {
+    match node {
+        Node::Value(Value::String(s)) => Some(s.as_bytes().as_ptr() as usize),
+        Node::Value(Value::Table(t)) => Some(t.0.as_ptr() as usize),
+        Node::Value(Value::Function(Function::Closure(Closure(c)))) => Some(Gc::as_ptr(c) as usize),
+        Node::Value(Value::Function(Function::Callback(Callback(c)))) => {
+            Some(Gc::as_ptr(c) as *const u8 as usize)
+        }
+        Node::Value(Value::Thread(t)) => Some(t.0.as_ptr() as usize),
+        Node::Value(_) => None,
+        Node::Proto(p) => Some(Gc::as_ptr(p) as usize),
+    }
+}
+
+fn add(stats: &mut ObjectStats, bytes: usize) {
+    stats.count += 1;
+    stats.bytes += bytes;
+}
+
+fn string_size(string: String) -> usize {
+    match string {
+        String::Short8(_, _) => 8,
+        String::Short32(_, _) => 32,
+        String::Long(s) => mem::size_of::<Box<[u8]>>() + s.len(),
+        String::Static(_) => 0,
+    }
+}
+
+fn proto_size(proto: &FunctionProto) -> usize {
+    mem::size_of::<FunctionProto>()
+        + proto.constants.capacity() * mem::size_of::<Constant>()
+        + proto.opcodes.capacity() * mem::size_of::<OpCode>()
+        + proto.opcode_lines.capacity() * mem::size_of::<(usize, crate::LineNumber)>()
+        + proto.upvalues.capacity() * mem::size_of::<crate::UpValueDescriptor>()
+        + proto.prototypes.capacity() * mem::size_of::<Gc<FunctionProto>>()
+}
+
+fn stats_table<'gc>(mc: MutationContext<'gc, '_>, stats: ObjectStats) -> Table<'gc> {
+    let table = Table::new(mc);
+    table
+        .set(mc, String::new_static(b"count"), stats.count as i64)
+        .unwrap();
+    table
+        .set(mc, String::new_static(b"bytes"), stats.bytes as i64)
+        .unwrap();
+    table
+}
+
+fn write_json_string(out: &mut StdString, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,1145 @@
+//! An experimental baseline JIT compiler, translating the function prototypes a thread runs most
+//! often to native code through Cranelift.
+//!
+//! A `Jit` is set on a thread with `Thread::set_jit`, threads created by `coroutine.create` share
+//! the `Jit` of the main thread.  It counts how many times the VM starts running each prototype,
+//! either by calling it or by returning to it from a call, and once a prototype is hot it compiles
+//! every instruction in it from a simple subset: moves, loads of non-string constants, `+`, `-`,
+//! `*`, `/`, unary minus, `not`, comparisons, tests, jumps that close no upvalues and the step of
+//! numeric `for` loops.
+//!
+//! Whenever the VM reaches a compiled instruction it runs the native code instead, which continues
+//! until it reaches an instruction that was not compiled, runs out of instructions in the current
+//! VM step, or finds an operand of a type it does not handle, such as a string being added to a
+//! number.  The VM then runs that instruction as usual, so the native code never changes the
+//! results of a script, only how quickly it gets them.
+//!
+//! Native code is not used while line coverage, type feedback or opcode tracing is set on the
+//! thread, as it does not report the instructions it runs.  Every prototype run while a `Jit` is
+//! set is kept alive by it, and on hosts that Cranelift does not support nothing is compiled.
+
+use std::cell::Cell;
+use std::mem;
+use std::rc::Rc;
+
+use cranelift_codegen::ir::{
+    condcodes::{FloatCC, IntCC},
+    types, AbiParam, Block, InstBuilder, MemFlags, Type, Value as IrValue,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    Constant, ConstantIndex16, ConstantIndex8, FunctionProto, OpCode, RegisterIndex, Value,
+};
+
+/// Compiles hot function prototypes to native code, see the module documentation.
+#[derive(Clone, Copy, Collect)]
+#[collect(require_copy)]
+pub struct Jit<'gc>(GcCell<'gc, JitState<'gc>>);
+
+#[derive(Collect)]
+#[collect(empty_drop)]
+struct JitState<'gc> {
+    threshold: u32,
+    // Keyed by prototype address, the prototypes are kept alive by the entries.
+    protos: FxHashMap<usize, ProtoEntry<'gc>>,
+    module: CodeModule,
+}
+
+#[derive(Collect)]
+#[collect(empty_drop)]
+struct ProtoEntry<'gc> {
+    proto: Gc<'gc, FunctionProto<'gc>>,
+    starts: u32,
+    code: Compiled,
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+enum Compiled {
+    Pending,
+    Native(Rc<NativeCode>),
+    // The prototype has no instructions that can be compiled.
+    Unsupported,
+}
+
+// Owns the memory of all the native code compiled by a `Jit`, None if the host is not supported.
+#[derive(Collect)]
+#[collect(require_static)]
+struct CodeModule(Option<JITModule>);
+
+impl Drop for CodeModule {
+    fn drop(&mut self) {
+        if let Some(module) = self.0.take() {
+            // Native code only runs inside the VM, which holds the `Jit` while it does, so none
+            // of it can be running once the `Jit` is collected.
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+impl<'gc> Jit<'gc> {
+    /// Compiles each prototype the next time it is started after it has been started `threshold`
+    /// times, so a threshold of 0 compiles every prototype the first time it runs.
+    pub fn new(mc: MutationContext<'gc, '_>, threshold: u32) -> Jit<'gc> {
+        let module = JITBuilder::with_flags(&[("opt_level", "speed")], default_libcall_names())
+            .ok()
+            .map(JITModule::new);
+        Jit(GcCell::allocate(
+            mc,
+            JitState {
+                threshold,
+                protos: FxHashMap::default(),
+                module: CodeModule(module),
+            },
+        ))
+    }
+
+    /// Returns every prototype that has been compiled to native code, in no particular order.
+    pub fn compiled(&self) -> Vec<Gc<'gc, FunctionProto<'gc>>> {
+        self.0
+            .read()
+            .protos
+            .values()
+            .filter(|entry| matches!(entry.code, Compiled::Native(_)))
+            .map(|entry| entry.proto)
+            .collect()
+    }
+
+    // Called by the VM each time it starts running `proto`, returns its native code if it has
+    // been compiled.
+    pub(crate) fn start(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+    ) -> Option<Rc<NativeCode>> {
+        let key = Gc::as_ptr(proto) as usize;
+        match self.0.read().protos.get(&key).map(|entry| &entry.code) {
+            Some(Compiled::Native(code)) => return Some(code.clone()),
+            Some(Compiled::Unsupported) => return None,
+            Some(Compiled::Pending) | None => {}
+        }
+
+        let mut state = self.0.write(mc);
+        let state = &mut *state;
+        let entry = state.protos.entry(key).or_insert_with(|| ProtoEntry {
+            proto,
+            starts: 0,
+            code: Compiled::Pending,
+        });
+        if entry.starts < state.threshold {
+            entry.starts += 1;
+            return None;
+        }
+        let code = state
+            .module
+            .0
+            .as_mut()
+            .and_then(|module| compile(module, &proto));
+        match code {
+            Some(code) => {
+                let code = Rc::new(code);
+                entry.code = Compiled::Native(code.clone());
+                Some(code)
+            }
+            None => {
+                entry.code = Compiled::Unsupported;
+                None
+            }
+        }
+    }
+}
+
+type NativeFunction = unsafe extern "C" fn(*mut Slot, usize, *mut u32) -> usize;
+
+// The native code for one prototype.
+pub(crate) struct NativeCode {
+    function: NativeFunction,
+    // Whether each instruction of the prototype was compiled.
+    compiled: Vec<bool>,
+    // The stack size of the prototype, every register the native code accesses is below it.
+    registers: usize,
+    // The slots of the registers, kept between runs so that entering native code does not
+    // allocate.
+    slots: Cell<Vec<Slot>>,
+}
+
+impl NativeCode {
+    pub(crate) fn compiles(&self, pc: usize) -> bool {
+        self.compiled.get(pc).copied().unwrap_or(false)
+    }
+
+    // Runs the native code from the instruction at `pc` until it stops, leaving `pc` at the next
+    // instruction for the VM to run and subtracting the instructions it ran from `instructions`.
+    // `moved` is an empty buffer for the values the native code moved between registers.
+    pub(crate) fn run<'gc>(
+        &self,
+        stack_frame: &mut [Value<'gc>],
+        moved: &mut Vec<Value<'gc>>,
+        pc: &mut usize,
+        instructions: &mut u32,
+    ) {
+        let registers = &mut stack_frame[..self.registers];
+        let mut slots = self.slots.take();
+        slots.clear();
+        slots.extend(
+            registers
+                .iter()
+                .enumerate()
+                .map(|(register, &value)| Slot::from_value(register, value)),
+        );
+        // The native code only accesses the slots of registers below `self.registers`, which are
+        // all present.
+        *pc = unsafe { (self.function)(slots.as_mut_ptr(), *pc, instructions) };
+
+        // Values of other types that were moved are read before any register is overwritten, the
+        // ones still in the register they were read from are left alone.
+        moved.extend(
+            slots
+                .iter()
+                .enumerate()
+                .filter(|&(register, slot)| slot.tag == OTHER && slot.bits as usize != register)
+                .map(|(_, slot)| registers[slot.bits as usize]),
+        );
+        let mut moved_values = moved.drain(..);
+        for (register, (value, slot)) in registers.iter_mut().zip(&slots).enumerate() {
+            match slot.to_value() {
+                Some(native) => *value = native,
+                None if slot.bits as usize != register => {
+                    *value = moved_values.next().expect("missing moved value");
+                }
+                None => {}
+            }
+        }
+        self.slots.set(slots);
+    }
+}
+
+// A register as seen by native code.  Values of types that native code does not operate on are
+// only ever moved, so they are represented by the register they were read from.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Slot {
+    tag: i64,
+    bits: i64,
+}
+
+const NIL: i64 = 0;
+const BOOLEAN: i64 = 1;
+const INTEGER: i64 = 2;
+const NUMBER: i64 = 3;
+const OTHER: i64 = 4;
+
+const SLOT_SIZE: i32 = mem::size_of::<Slot>() as i32;
+
+impl Slot {
+    fn from_value(register: usize, value: Value<'_>) -> Slot {
+        match value {
+            Value::Nil => Slot { tag: NIL, bits: 0 },
+            Value::Boolean(b) => Slot {
+                tag: BOOLEAN,
+                bits: b as i64,
+            },
+            Value::Integer(i) => Slot {
+                tag: INTEGER,
+                bits: i,
+            },
+            Value::Number(n) => Slot {
+                tag: NUMBER,
+                bits: n.to_bits() as i64,
+            },
+            _ => Slot {
+                tag: OTHER,
+                bits: register as i64,
+            },
+        }
+    }
+
+    // The value of a slot of a type native code operates on, None for values of other types.
+    fn to_value<'gc>(self) -> Option<Value<'gc>> {
+        match self.tag {
+            NIL => Some(Value::Nil),
+            BOOLEAN => Some(Value::Boolean(self.bits != 0)),
+            INTEGER => Some(Value::Integer(self.bits)),
+            NUMBER => Some(Value::Number(f64::from_bits(self.bits as u64))),
+            _ => None,
+        }
+    }
+
+    fn constant(constant: Constant<'_>) -> Option<Slot> {
+        match constant {
+            Constant::String(_) => None,
+            constant => Some(Slot::from_value(0, constant.to_value())),
+        }
+    }
+}
+
+// An instruction that can be compiled, with absolute jump targets.
+#[derive(Clone, Copy)]
+enum Instruction {
+    Load {
+        dest: u8,
+        source: Operand,
+    },
+    LoadNil {
+        dest: u8,
+        count: u8,
+    },
+    Arithmetic {
+        op: Arithmetic,
+        dest: u8,
+        left: Operand,
+        right: Operand,
+    },
+    Minus {
+        dest: u8,
+        source: u8,
+    },
+    Not {
+        dest: u8,
+        source: u8,
+    },
+    Compare {
+        op: Comparison,
+        skip_if: bool,
+        left: Operand,
+        right: Operand,
+    },
+    Test {
+        // The register the value is copied to if the test does not skip, for `TestSet`.
+        dest: Option<u8>,
+        value: u8,
+        is_true: bool,
+    },
+    Jump {
+        target: usize,
+    },
+    LoadBool {
+        dest: u8,
+        value: bool,
+        skip_next: bool,
+    },
+    NumericForLoop {
+        base: u8,
+        target: usize,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Constant(Slot),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    Less,
+    LessEqual,
+}
+
+// Returns the instruction at `pc` if it can be compiled.
+fn decode(proto: &FunctionProto, pc: usize) -> Option<Instruction> {
+    let registers = proto.stack_size as usize;
+    let reg = |r: RegisterIndex| Some(r.0).filter(|&r| (r as usize) < registers);
+    let r = |r: RegisterIndex| reg(r).map(Operand::Register);
+    let c = |c: ConstantIndex8| constant(proto, c.0 as usize).map(Operand::Constant);
+    let target = |offset: i16| {
+        let target = pc as isize + 1 + offset as isize;
+        if target >= 0 {
+            Some(target as usize)
+        } else {
+            None
+        }
+    };
+    let arithmetic = |op, dest, left: Option<Operand>, right: Option<Operand>| {
+        Some(Instruction::Arithmetic {
+            op,
+            dest: reg(dest)?,
+            left: left?,
+            right: right?,
+        })
+    };
+    let compare = |op, skip_if, left: Option<Operand>, right: Option<Operand>| {
+        Some(Instruction::Compare {
+            op,
+            skip_if,
+            left: left?,
+            right: right?,
+        })
+    };
+
+    use Arithmetic::*;
+    use Comparison::*;
+    match proto.opcodes[pc] {
+        OpCode::Move { dest, source } => Some(Instruction::Load {
+            dest: reg(dest)?,
+            source: r(source)?,
+        }),
+        OpCode::LoadConstant {
+            dest,
+            constant: ConstantIndex16(index),
+        } => Some(Instruction::Load {
+            dest: reg(dest)?,
+            source: Operand::Constant(constant(proto, index as usize)?),
+        }),
+        OpCode::LoadBool {
+            dest,
+            value,
+            skip_next,
+        } => Some(Instruction::LoadBool {
+            dest: reg(dest)?,
+            value,
+            skip_next,
+        }),
+        OpCode::LoadNil { dest, count } => {
+            if dest.0 as usize + count as usize <= registers {
+                Some(Instruction::LoadNil {
+                    dest: dest.0,
+                    count,
+                })
+            } else {
+                None
+            }
+        }
+        OpCode::Jump {
+            offset,
+            close_upvalues,
+        } if close_upvalues.is_none() => Some(Instruction::Jump {
+            target: target(offset)?,
+        }),
+        OpCode::Test { value, is_true } => Some(Instruction::Test {
+            dest: None,
+            value: reg(value)?,
+            is_true,
+        }),
+        OpCode::TestSet {
+            dest,
+            value,
+            is_true,
+        } => Some(Instruction::Test {
+            dest: Some(reg(dest)?),
+            value: reg(value)?,
+            is_true,
+        }),
+        OpCode::NumericForLoop { base, jump } => {
+            if base.0 as usize + 4 <= registers {
+                Some(Instruction::NumericForLoop {
+                    base: base.0,
+                    target: target(jump)?,
+                })
+            } else {
+                None
+            }
+        }
+        OpCode::Not { dest, source } => Some(Instruction::Not {
+            dest: reg(dest)?,
+            source: reg(source)?,
+        }),
+        OpCode::Minus { dest, source } => Some(Instruction::Minus {
+            dest: reg(dest)?,
+            source: reg(source)?,
+        }),
+
+        OpCode::AddRR { dest, left, right } => arithmetic(Add, dest, r(left), r(right)),
+        OpCode::AddRC { dest, left, right } => arithmetic(Add, dest, r(left), c(right)),
+        OpCode::AddCR { dest, left, right } => arithmetic(Add, dest, c(left), r(right)),
+        OpCode::AddCC { dest, left, right } => arithmetic(Add, dest, c(left), c(right)),
+        OpCode::SubRR { dest, left, right } => arithmetic(Subtract, dest, r(left), r(right)),
+        OpCode::SubRC { dest, left, right } => arithmetic(Subtract, dest, r(left), c(right)),
+        OpCode::SubCR { dest, left, right } => arithmetic(Subtract, dest, c(left), r(right)),
+        OpCode::SubCC { dest, left, right } => arithmetic(Subtract, dest, c(left), c(right)),
+        OpCode::MulRR { dest, left, right } => arithmetic(Multiply, dest, r(left), r(right)),
+        OpCode::MulRC { dest, left, right } => arithmetic(Multiply, dest, r(left), c(right)),
+        OpCode::MulCR { dest, left, right } => arithmetic(Multiply, dest, c(left), r(right)),
+        OpCode::MulCC { dest, left, right } => arithmetic(Multiply, dest, c(left), c(right)),
+        OpCode::DivRR { dest, left, right } => arithmetic(Divide, dest, r(left), r(right)),
+        OpCode::DivRC { dest, left, right } => arithmetic(Divide, dest, r(left), c(right)),
+        OpCode::DivCR { dest, left, right } => arithmetic(Divide, dest, c(left), r(right)),
+        OpCode::DivCC { dest, left, right } => arithmetic(Divide, dest, c(left), c(right)),
+
+        OpCode::EqRR {
+            skip_if,
+            left,
+            right,
+        } => compare(Equal, skip_if, r(left), r(right)),
+        OpCode::EqRC {
+            skip_if,
+            left,
+            right,
+        } => compare(Equal, skip_if, r(left), c(right)),
+        OpCode::EqCR {
+            skip_if,
+            left,
+            right,
+        } => compare(Equal, skip_if, c(left), r(right)),
+        OpCode::EqCC {
+            skip_if,
+            left,
+            right,
+        } => compare(Equal, skip_if, c(left), c(right)),
+        OpCode::LessRR {
+            skip_if,
+            left,
+            right,
+        } => compare(Less, skip_if, r(left), r(right)),
+        OpCode::LessRC {
+            skip_if,
+            left,
+            right,
+        } => compare(Less, skip_if, r(left), c(right)),
+        OpCode::LessCR {
+            skip_if,
+            left,
+            right,
+        } => compare(Less, skip_if, c(left), r(right)),
+        OpCode::LessCC {
+            skip_if,
+            left,
+            right,
+        } => compare(Less, skip_if, c(left), c(right)),
+        OpCode::LessEqRR {
+            skip_if,
+            left,
+            right,
+        } => compare(LessEqual, skip_if, r(left), r(right)),
+        OpCode::LessEqRC {
+            skip_if,
+            left,
+            right,
+        } => compare(LessEqual, skip_if, r(left), c(right)),
+        OpCode::LessEqCR {
+            skip_if,
+            left,
+            right,
+        } => compare(LessEqual, skip_if, c(left), r(right)),
+        OpCode::LessEqCC {
+            skip_if,
+            left,
+            right,
+        } => compare(LessEqual, skip_if, c(left), c(right)),
+
+        _ => None,
+    }
+}
+
+fn constant(proto: &FunctionProto, index: usize) -> Option<Slot> {
+    proto.constants.get(index).copied().and_then(Slot::constant)
+}
+
+// Compiles every instruction of `proto` that can be compiled, returns None if there are none or
+// Cranelift fails.
+fn compile(module: &mut JITModule, proto: &FunctionProto) -> Option<NativeCode> {
+    let instructions = (0..proto.opcodes.len())
+        .map(|pc| decode(proto, pc))
+        .collect::<Vec<_>>();
+    if instructions.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    let signature = &mut context.func.signature;
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(pointer));
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    Translator::new(&mut builder, pointer, instructions.len()).translate(&instructions);
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let function =
+        unsafe { mem::transmute::<*const u8, NativeFunction>(module.get_finalized_function(id)) };
+
+    Some(NativeCode {
+        function,
+        compiled: instructions.iter().map(Option::is_some).collect(),
+        registers: proto.stack_size as usize,
+        slots: Cell::new(Vec::new()),
+    })
+}
+
+// Builds the native function for a prototype, which takes a pointer to the slots of its registers,
+// the pc to start at and a pointer to the remaining instruction budget, and returns the pc of the
+// next instruction for the VM to run.
+//
+// Each instruction has its own block, which first checks the budget and the types of its operands
+// and exits without changing anything if either check fails.
+struct Translator<'a, 'b> {
+    builder: &'a mut FunctionBuilder<'b>,
+    pointer: Type,
+    slots: IrValue,
+    budget: Variable,
+    // Takes the pc to return.
+    exit: Block,
+    blocks: Vec<Block>,
+}
+
+impl<'a, 'b> Translator<'a, 'b> {
+    fn new(builder: &'a mut FunctionBuilder<'b>, pointer: Type, len: usize) -> Translator<'a, 'b> {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let params = builder.block_params(entry).to_vec();
+        let (slots, pc, budget_ptr) = (params[0], params[1], params[2]);
+
+        let budget = Variable::from_u32(0);
+        builder.declare_var(budget, types::I32);
+        let initial = builder
+            .ins()
+            .load(types::I32, MemFlags::trusted(), budget_ptr, 0);
+        builder.def_var(budget, initial);
+
+        let exit = builder.create_block();
+        builder.append_block_param(exit, pointer);
+        let blocks = (0..len).map(|_| builder.create_block()).collect::<Vec<_>>();
+
+        let unknown = builder.create_block();
+        let mut switch = Switch::new();
+        for (pc, &block) in blocks.iter().enumerate() {
+            switch.set_entry(pc as u128, block);
+        }
+        switch.emit(builder, pc, unknown);
+
+        builder.switch_to_block(unknown);
+        builder.ins().jump(exit, &[pc]);
+
+        builder.switch_to_block(exit);
+        let exit_pc = builder.block_params(exit)[0];
+        let remaining = builder.use_var(budget);
+        builder
+            .ins()
+            .store(MemFlags::trusted(), remaining, budget_ptr, 0);
+        builder.ins().return_(&[exit_pc]);
+
+        Translator {
+            builder,
+            pointer,
+            slots,
+            budget,
+            exit,
+            blocks,
+        }
+    }
+
+    fn translate(mut self, instructions: &[Option<Instruction>]) {
+        for (pc, &instruction) in instructions.iter().enumerate() {
+            self.builder.switch_to_block(self.blocks[pc]);
+            let instruction = match instruction {
+                Some(instruction) => instruction,
+                None => {
+                    let pc = self.builder.ins().iconst(self.pointer, pc as i64);
+                    self.builder.ins().jump(self.exit, &[pc]);
+                    continue;
+                }
+            };
+
+            let budget = self.builder.use_var(self.budget);
+            let exhausted = self.builder.ins().icmp_imm(IntCC::Equal, budget, 0);
+            self.exit_if(exhausted, pc);
+
+            match instruction {
+                Instruction::Load { dest, source } => {
+                    let (tag, bits) = self.load(source);
+                    self.store(dest, tag, bits);
+                    self.next(pc + 1);
+                }
+                Instruction::LoadNil { dest, count } => {
+                    let (tag, bits) = self.load(Operand::Constant(Slot { tag: NIL, bits: 0 }));
+                    for register in dest..dest + count {
+                        self.store(register, tag, bits);
+                    }
+                    self.next(pc + 1);
+                }
+                Instruction::LoadBool {
+                    dest,
+                    value,
+                    skip_next,
+                } => {
+                    let (tag, bits) = self.load(Operand::Constant(Slot {
+                        tag: BOOLEAN,
+                        bits: value as i64,
+                    }));
+                    self.store(dest, tag, bits);
+                    self.next(if skip_next { pc + 2 } else { pc + 1 });
+                }
+                Instruction::Jump { target } => self.next(target),
+                Instruction::Arithmetic {
+                    op,
+                    dest,
+                    left,
+                    right,
+                } => self.arithmetic(pc, op, dest, left, right),
+                Instruction::Minus { dest, source } => self.minus(pc, dest, source),
+                Instruction::Not { dest, source } => {
+                    let (tag, bits) = self.load(Operand::Register(source));
+                    let falsy = self.falsy(tag, bits);
+                    let falsy = self.builder.ins().uextend(types::I64, falsy);
+                    let boolean = self.builder.ins().iconst(types::I64, BOOLEAN);
+                    self.store(dest, boolean, falsy);
+                    self.next(pc + 1);
+                }
+                Instruction::Compare {
+                    op,
+                    skip_if,
+                    left,
+                    right,
+                } => self.compare(pc, op, skip_if, left, right),
+                Instruction::Test {
+                    dest,
+                    value,
+                    is_true,
+                } => self.test(pc, dest, value, is_true),
+                Instruction::NumericForLoop { base, target } => {
+                    self.numeric_for_loop(pc, base, target)
+                }
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, pc: usize, op: Arithmetic, dest: u8, left: Operand, right: Operand) {
+        let (left_tag, left_bits) = self.load(left);
+        let (right_tag, right_bits) = self.load(right);
+        let done = self.builder.create_block();
+
+        // Division always produces a float, everything else stays an integer for integers.
+        if op != Arithmetic::Divide {
+            let integers = self.builder.create_block();
+            let floats = self.builder.create_block();
+            let both = self.both_integers(left_tag, right_tag);
+            self.builder.ins().brif(both, integers, &[], floats, &[]);
+
+            self.builder.switch_to_block(integers);
+            let result = match op {
+                Arithmetic::Add => self.builder.ins().iadd(left_bits, right_bits),
+                Arithmetic::Subtract => self.builder.ins().isub(left_bits, right_bits),
+                Arithmetic::Multiply => self.builder.ins().imul(left_bits, right_bits),
+                Arithmetic::Divide => unreachable!(),
+            };
+            let integer = self.builder.ins().iconst(types::I64, INTEGER);
+            self.store(dest, integer, result);
+            self.builder.ins().jump(done, &[]);
+
+            self.builder.switch_to_block(floats);
+        }
+
+        let left = self.float_value(pc, left_tag, left_bits);
+        let right = self.float_value(pc, right_tag, right_bits);
+        let result = match op {
+            Arithmetic::Add => self.builder.ins().fadd(left, right),
+            Arithmetic::Subtract => self.builder.ins().fsub(left, right),
+            Arithmetic::Multiply => self.builder.ins().fmul(left, right),
+            Arithmetic::Divide => self.builder.ins().fdiv(left, right),
+        };
+        let result = self
+            .builder
+            .ins()
+            .bitcast(types::I64, MemFlags::new(), result);
+        let number = self.builder.ins().iconst(types::I64, NUMBER);
+        self.store(dest, number, result);
+        self.builder.ins().jump(done, &[]);
+
+        self.builder.switch_to_block(done);
+        self.next(pc + 1);
+    }
+
+    fn minus(&mut self, pc: usize, dest: u8, source: u8) {
+        let (tag, bits) = self.load(Operand::Register(source));
+        let integer = self.builder.create_block();
+        let not_integer = self.builder.create_block();
+        let done = self.builder.create_block();
+        let is_integer = self.builder.ins().icmp_imm(IntCC::Equal, tag, INTEGER);
+        self.builder
+            .ins()
+            .brif(is_integer, integer, &[], not_integer, &[]);
+
+        self.builder.switch_to_block(integer);
+        let negated = self.builder.ins().ineg(bits);
+        self.store(dest, tag, negated);
+        self.builder.ins().jump(done, &[]);
+
+        self.builder.switch_to_block(not_integer);
+        let is_number = self.builder.ins().icmp_imm(IntCC::Equal, tag, NUMBER);
+        self.exit_unless(is_number, pc);
+        // Flipping the sign bit negates a float.
+        let negated = self.builder.ins().bxor_imm(bits, i64::MIN);
+        self.store(dest, tag, negated);
+        self.builder.ins().jump(done, &[]);
+
+        self.builder.switch_to_block(done);
+        self.next(pc + 1);
+    }
+
+    fn compare(&mut self, pc: usize, op: Comparison, skip_if: bool, left: Operand, right: Operand) {
+        let (left_tag, left_bits) = self.load(left);
+        let (right_tag, right_bits) = self.load(right);
+        let done = self.builder.create_block();
+        self.builder.append_block_param(done, types::I8);
+
+        if op == Comparison::Equal {
+            // Values of other types are never equal to the types native code handles, but may be
+            // equal to each other.
+            let left_other = self.builder.ins().icmp_imm(IntCC::Equal, left_tag, OTHER);
+            let right_other = self.builder.ins().icmp_imm(IntCC::Equal, right_tag, OTHER);
+            let both_other = self.builder.ins().band(left_other, right_other);
+            self.exit_if(both_other, pc);
+
+            let numbers = self.builder.create_block();
+            let not_numbers = self.builder.create_block();
+            let left_number = self.is_number(left_tag);
+            let right_number = self.is_number(right_tag);
+            let both_numbers = self.builder.ins().band(left_number, right_number);
+            self.builder
+                .ins()
+                .brif(both_numbers, numbers, &[], not_numbers, &[]);
+
+            // Any other value is only equal to one with the same type and bits.
+            self.builder.switch_to_block(not_numbers);
+            let same_tag = self.builder.ins().icmp(IntCC::Equal, left_tag, right_tag);
+            let same_bits = self.builder.ins().icmp(IntCC::Equal, left_bits, right_bits);
+            let equal = self.builder.ins().band(same_tag, same_bits);
+            self.builder.ins().jump(done, &[equal]);
+
+            self.builder.switch_to_block(numbers);
+        }
+
+        let integers = self.builder.create_block();
+        let floats = self.builder.create_block();
+        let both = self.both_integers(left_tag, right_tag);
+        self.builder.ins().brif(both, integers, &[], floats, &[]);
+
+        self.builder.switch_to_block(integers);
+        let condition = match op {
+            Comparison::Equal => IntCC::Equal,
+            Comparison::Less => IntCC::SignedLessThan,
+            Comparison::LessEqual => IntCC::SignedLessThanOrEqual,
+        };
+        let result = self.builder.ins().icmp(condition, left_bits, right_bits);
+        self.builder.ins().jump(done, &[result]);
+
+        self.builder.switch_to_block(floats);
+        if op == Comparison::Equal {
+            // Like `Value::eq`, an integer is only equal to a float that converts to it exactly,
+            // not to every float it rounds to.
+            let mixed = self.builder.create_block();
+            let both_floats = self.builder.create_block();
+            let left_integer = self.builder.ins().icmp_imm(IntCC::Equal, left_tag, INTEGER);
+            let right_integer = self
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, right_tag, INTEGER);
+            let any_integer = self.builder.ins().bor(left_integer, right_integer);
+            self.builder
+                .ins()
+                .brif(any_integer, mixed, &[], both_floats, &[]);
+
+            self.builder.switch_to_block(mixed);
+            let integer = self
+                .builder
+                .ins()
+                .select(left_integer, left_bits, right_bits);
+            let float = self
+                .builder
+                .ins()
+                .select(left_integer, right_bits, left_bits);
+            let float = self
+                .builder
+                .ins()
+                .bitcast(types::F64, MemFlags::new(), float);
+            let result = self.float_equals_integer(float, integer);
+            self.builder.ins().jump(done, &[result]);
+
+            self.builder.switch_to_block(both_floats);
+        }
+        let left = self.float_value(pc, left_tag, left_bits);
+        let right = self.float_value(pc, right_tag, right_bits);
+        let condition = match op {
+            Comparison::Equal => FloatCC::Equal,
+            Comparison::Less => FloatCC::LessThan,
+            Comparison::LessEqual => FloatCC::LessThanOrEqual,
+        };
+        let result = self.builder.ins().fcmp(condition, left, right);
+        self.builder.ins().jump(done, &[result]);
+
+        self.builder.switch_to_block(done);
+        let result = self.builder.block_params(done)[0];
+        let skip = if skip_if {
+            result
+        } else {
+            self.builder.ins().icmp_imm(IntCC::Equal, result, 0)
+        };
+        self.branch(skip, pc + 2, pc + 1);
+    }
+
+    fn test(&mut self, pc: usize, dest: Option<u8>, value: u8, is_true: bool) {
+        let (tag, bits) = self.load(Operand::Register(value));
+        let falsy = self.falsy(tag, bits);
+        let skip = if is_true {
+            self.builder.ins().icmp_imm(IntCC::Equal, falsy, 0)
+        } else {
+            falsy
+        };
+        match dest {
+            None => self.branch(skip, pc + 2, pc + 1),
+            Some(dest) => {
+                let set = self.builder.create_block();
+                self.decrement_budget();
+                let (skipped, skipped_args) = self.target(pc + 2);
+                self.builder
+                    .ins()
+                    .brif(skip, skipped, &skipped_args, set, &[]);
+
+                self.builder.switch_to_block(set);
+                self.store(dest, tag, bits);
+                self.jump(pc + 1);
+            }
+        }
+    }
+
+    fn numeric_for_loop(&mut self, pc: usize, base: u8, target: usize) {
+        let (index_tag, index_bits) = self.load(Operand::Register(base));
+        let (limit_tag, limit_bits) = self.load(Operand::Register(base + 1));
+        let (step_tag, step_bits) = self.load(Operand::Register(base + 2));
+
+        let integers = self.builder.create_block();
+        let floats = self.builder.create_block();
+        let index_and_limit = self.both_integers(index_tag, limit_tag);
+        let step_integer = self.builder.ins().icmp_imm(IntCC::Equal, step_tag, INTEGER);
+        let all = self.builder.ins().band(index_and_limit, step_integer);
+        self.builder.ins().brif(all, integers, &[], floats, &[]);
+
+        self.builder.switch_to_block(integers);
+        let index = self.builder.ins().iadd(index_bits, step_bits);
+        let counting_down = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::SignedLessThan, step_bits, 0);
+        let below = self
+            .builder
+            .ins()
+            .icmp(IntCC::SignedLessThan, index, limit_bits);
+        let above = self
+            .builder
+            .ins()
+            .icmp(IntCC::SignedLessThan, limit_bits, index);
+        let past_end = self.builder.ins().select(counting_down, below, above);
+        let integer = self.builder.ins().iconst(types::I64, INTEGER);
+        self.loop_step(pc, base, integer, index, past_end, target);
+
+        self.builder.switch_to_block(floats);
+        let index = self.float_value(pc, index_tag, index_bits);
+        let limit = self.float_value(pc, limit_tag, limit_bits);
+        let step = self.float_value(pc, step_tag, step_bits);
+        let index = self.builder.ins().fadd(index, step);
+        let zero = self.builder.ins().f64const(0.0);
+        let counting_down = self.builder.ins().fcmp(FloatCC::LessThan, step, zero);
+        let below = self.builder.ins().fcmp(FloatCC::LessThan, index, limit);
+        let above = self.builder.ins().fcmp(FloatCC::LessThan, limit, index);
+        let past_end = self.builder.ins().select(counting_down, below, above);
+        let index = self
+            .builder
+            .ins()
+            .bitcast(types::I64, MemFlags::new(), index);
+        let number = self.builder.ins().iconst(types::I64, NUMBER);
+        self.loop_step(pc, base, number, index, past_end, target);
+    }
+
+    // Stores the next index of a numeric for loop and either continues the loop or leaves it.
+    fn loop_step(
+        &mut self,
+        pc: usize,
+        base: u8,
+        tag: IrValue,
+        index: IrValue,
+        past_end: IrValue,
+        target: usize,
+    ) {
+        self.store(base, tag, index);
+        let continues = self.builder.create_block();
+        self.decrement_budget();
+        let (after, after_args) = self.target(pc + 1);
+        self.builder
+            .ins()
+            .brif(past_end, after, &after_args, continues, &[]);
+
+        self.builder.switch_to_block(continues);
+        self.store(base + 3, tag, index);
+        self.jump(target);
+    }
+
+    fn load(&mut self, operand: Operand) -> (IrValue, IrValue) {
+        match operand {
+            Operand::Register(register) => {
+                let offset = register as i32 * SLOT_SIZE;
+                let tag =
+                    self.builder
+                        .ins()
+                        .load(types::I64, MemFlags::trusted(), self.slots, offset);
+                let bits = self.builder.ins().load(
+                    types::I64,
+                    MemFlags::trusted(),
+                    self.slots,
+                    offset + 8,
+                );
+                (tag, bits)
+            }
+            Operand::Constant(slot) => (
+                self.builder.ins().iconst(types::I64, slot.tag),
+                self.builder.ins().iconst(types::I64, slot.bits),
+            ),
+        }
+    }
+
+    fn store(&mut self, register: u8, tag: IrValue, bits: IrValue) {
+        let offset = register as i32 * SLOT_SIZE;
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), tag, self.slots, offset);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), bits, self.slots, offset + 8);
+    }
+
+    fn both_integers(&mut self, left_tag: IrValue, right_tag: IrValue) -> IrValue {
+        let left = self.builder.ins().icmp_imm(IntCC::Equal, left_tag, INTEGER);
+        let right = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, right_tag, INTEGER);
+        self.builder.ins().band(left, right)
+    }
+
+    fn is_number(&mut self, tag: IrValue) -> IrValue {
+        let integer = self.builder.ins().icmp_imm(IntCC::Equal, tag, INTEGER);
+        let number = self.builder.ins().icmp_imm(IntCC::Equal, tag, NUMBER);
+        self.builder.ins().bor(integer, number)
+    }
+
+    // Whether a value is nil or false.
+    fn falsy(&mut self, tag: IrValue, bits: IrValue) -> IrValue {
+        let nil = self.builder.ins().icmp_imm(IntCC::Equal, tag, NIL);
+        let boolean = self.builder.ins().icmp_imm(IntCC::Equal, tag, BOOLEAN);
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, bits, 0);
+        let false_ = self.builder.ins().band(boolean, zero);
+        self.builder.ins().bor(nil, false_)
+    }
+
+    // Whether a float has an exact integer value equal to the given integer.  Saturating the
+    // conversion maps 2^63 to the largest integer, so that case must be ruled out separately.
+    fn float_equals_integer(&mut self, float: IrValue, integer: IrValue) -> IrValue {
+        let converted = self.builder.ins().fcvt_to_sint_sat(types::I64, float);
+        let back = self.builder.ins().fcvt_from_sint(types::F64, converted);
+        let exact = self.builder.ins().fcmp(FloatCC::Equal, back, float);
+        let limit = self.builder.ins().f64const(9223372036854775808.0);
+        let in_range = self.builder.ins().fcmp(FloatCC::LessThan, float, limit);
+        let same = self.builder.ins().icmp(IntCC::Equal, converted, integer);
+        let representable = self.builder.ins().band(exact, in_range);
+        self.builder.ins().band(representable, same)
+    }
+
+    // Converts an integer or float to a float, exiting at `pc` for any other type.
+    fn float_value(&mut self, pc: usize, tag: IrValue, bits: IrValue) -> IrValue {
+        let integer = self.builder.create_block();
+        let not_integer = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.append_block_param(done, types::F64);
+        let is_integer = self.builder.ins().icmp_imm(IntCC::Equal, tag, INTEGER);
+        self.builder
+            .ins()
+            .brif(is_integer, integer, &[], not_integer, &[]);
+
+        self.builder.switch_to_block(integer);
+        let float = self.builder.ins().fcvt_from_sint(types::F64, bits);
+        self.builder.ins().jump(done, &[float]);
+
+        self.builder.switch_to_block(not_integer);
+        let is_number = self.builder.ins().icmp_imm(IntCC::Equal, tag, NUMBER);
+        self.exit_unless(is_number, pc);
+        let float = self
+            .builder
+            .ins()
+            .bitcast(types::F64, MemFlags::new(), bits);
+        self.builder.ins().jump(done, &[float]);
+
+        self.builder.switch_to_block(done);
+        self.builder.block_params(done)[0]
+    }
+
+    fn exit_if(&mut self, condition: IrValue, pc: usize) {
+        let continues = self.builder.create_block();
+        let pc = self.builder.ins().iconst(self.pointer, pc as i64);
+        self.builder
+            .ins()
+            .brif(condition, self.exit, &[pc], continues, &[]);
+        self.builder.switch_to_block(continues);
+    }
+
+    fn exit_unless(&mut self, condition: IrValue, pc: usize) {
+        let continues = self.builder.create_block();
+        let pc = self.builder.ins().iconst(self.pointer, pc as i64);
+        self.builder
+            .ins()
+            .brif(condition, continues, &[], self.exit, &[pc]);
+        self.builder.switch_to_block(continues);
+    }
+
+    fn decrement_budget(&mut self) {
+        let budget = self.builder.use_var(self.budget);
+        let budget = self.builder.ins().iadd_imm(budget, -1);
+        self.builder.def_var(self.budget, budget);
+    }
+
+    // Finishes an instruction by continuing at `target`.
+    fn next(&mut self, target: usize) {
+        self.decrement_budget();
+        self.jump(target);
+    }
+
+    // Finishes an instruction by continuing at `then` if `condition` is true and at `otherwise` if
+    // not.
+    fn branch(&mut self, condition: IrValue, then: usize, otherwise: usize) {
+        self.decrement_budget();
+        let (then, then_args) = self.target(then);
+        let (otherwise, otherwise_args) = self.target(otherwise);
+        self.builder
+            .ins()
+            .brif(condition, then, &then_args, otherwise, &otherwise_args);
+    }
+
+    fn jump(&mut self, pc: usize) {
+        let (block, args) = self.target(pc);
+        self.builder.ins().jump(block, &args);
+    }
+
+    // The block that runs the instruction at `pc`, which exits if it is past the end.
+    fn target(&mut self, pc: usize) -> (Block, Vec<IrValue>) {
+        match self.blocks.get(pc) {
+            Some(&block) => (block, Vec::new()),
+            None => {
+                let pc = self.builder.ins().iconst(self.pointer, pc as i64);
+                (self.exit, vec![pc])
+            }
+        }
+    }
+}
@@ -1,9 +1,20 @@
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt::Debug;
 use std::io::{self, Read};
 use std::{char, fmt, i32, i64, str};
 
 use gc_arena::Collect;
 
+use crate::{ChunkName, LineNumber, ParserError};
+
+/// A Lua token.
+///
+/// With the `serialize` feature, tokens implement `serde::Serialize`, with keywords and symbols as
+/// their variant name and the rest as a map from the variant name to the contents, such as
+/// `{"Integer": 1}`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
 pub enum Token<S> {
     Break,
     Do,
@@ -60,12 +71,88 @@ pub enum Token<S> {
     RightBracket,
     LeftBrace,
     RightBrace,
+    /// The `?` of an optional type, only produced by lexers with type annotations enabled, see
+    /// `Lexer::set_type_annotations`.
+    Question,
     /// Numerals are only lexed as integers in the range [-(2^63-1), 2^63-1], otherwise they will be
     /// lexed as floats.
     Integer(i64),
     Float(f64),
     Name(S),
     String(S),
+    /// The text of a comment after the leading `--`, without the brackets of a long comment.  Only
+    /// produced by lexers created with `Lexer::with_comments`.
+    Comment(S),
+}
+
+/// A token that owns the contents of its names, strings and comments, for callers that have no
+/// string interner of their own.
+pub type OwnedToken = Token<Box<[u8]>>;
+
+/// The location of a piece of source code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[collect(require_static)]
+pub struct Span {
+    /// The line the piece of source code starts on, 1-indexed.
+    pub line_number: LineNumber,
+    /// The column the piece of source code starts at, 1-indexed.  Columns count characters rather
+    /// than bytes (every byte that does not continue a UTF-8 sequence starts a new one), and a tab
+    /// advances to the next tab stop, every 8 columns.
+    pub column: u64,
+    /// The offset in bytes from the start of the source of the first byte of the piece of source
+    /// code.
+    pub start: usize,
+    /// The offset in bytes of the byte after the last byte of the piece of source code.
+    pub end: usize,
+}
+
+/// Bounds on the resources a `Lexer` may use, for sources that come from untrusted users.  Every
+/// limit defaults to `usize::MAX`, which is effectively unlimited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LexerLimits {
+    /// The longest token allowed, in bytes of source (including any quotes, escape sequences or
+    /// long brackets).  This also bounds the memory used to read a single token.
+    pub max_token_len: usize,
+    /// The longest string literal allowed, in bytes of its contents.
+    pub max_string_len: usize,
+    /// The highest level of long brackets allowed in long strings and comments, the level of
+    /// `[==[` is 2.
+    pub max_long_bracket_level: usize,
+    /// The most tokens a single source may contain.
+    pub max_tokens: usize,
+}
+
+impl Default for LexerLimits {
+    fn default() -> LexerLimits {
+        LexerLimits {
+            max_token_len: usize::MAX,
+            max_string_len: usize::MAX,
+            max_long_bracket_level: usize::MAX,
+            max_tokens: usize::MAX,
+        }
+    }
+}
+
+/// Which of the `LexerLimits` was exceeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum LexerLimit {
+    TokenLength,
+    StringLength,
+    LongBracketLevel,
+    TokenCount,
+}
+
+impl fmt::Display for LexerLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexerLimit::TokenLength => write!(f, "token too long"),
+            LexerLimit::StringLength => write!(f, "string literal too long"),
+            LexerLimit::LongBracketLevel => write!(f, "long bracket level too high"),
+            LexerLimit::TokenCount => write!(f, "too many tokens"),
+        }
+    }
 }
 
 #[derive(Debug, Collect)]
@@ -80,11 +167,34 @@ pub enum LexerError {
     EscapeDecimalTooLarge,
     InvalidEscape,
     InvalidLongStringDelimiter,
-    UnfinishedLongString,
+    /// The source ended inside of a short string, long string or long comment, which more input
+    /// could finish.
+    UnexpectedEof,
+    /// A string literal contains bytes that are not valid UTF-8, only an error in strict UTF-8
+    /// mode.
+    InvalidUtf8,
     BadNumber,
+    /// One of the `LexerLimits` was exceeded.
+    LimitExceeded(LexerLimit),
+    /// The source returned `io::ErrorKind::WouldBlock` before a whole token could be read.  The
+    /// lexer is left where it was before the token, so reading again once more input is available
+    /// carries on as if the source had never blocked, see `PushInput`.
+    WouldBlock,
     IOError(io::Error),
 }
 
+impl LexerError {
+    // Errors in the source itself can be recovered from, see `Lexer::set_error_recovery`.
+    fn is_recoverable(&self) -> bool {
+        !matches!(
+            self,
+            LexerError::LimitExceeded(_) | LexerError::WouldBlock | LexerError::IOError(_)
+        )
+    }
+}
+
+impl StdError for LexerError {}
+
 impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fn print_char(c: u8) -> char {
@@ -109,44 +219,203 @@ impl fmt::Display for LexerError {
             LexerError::EscapeDecimalTooLarge => write!(f, "\\ddd escape out of 0-255 range"),
             LexerError::InvalidEscape => write!(f, "invalid escape sequence"),
             LexerError::InvalidLongStringDelimiter => write!(f, "invalid long string delimiter"),
-            LexerError::UnfinishedLongString => write!(f, "unfinished long string"),
+            LexerError::UnexpectedEof => {
+                write!(f, "unexpected end of source in string or comment")
+            }
+            LexerError::InvalidUtf8 => write!(f, "invalid UTF-8 in string literal"),
             LexerError::BadNumber => write!(f, "malformed number"),
+            LexerError::LimitExceeded(limit) => write!(f, "lexer limit exceeded: {}", limit),
+            LexerError::WouldBlock => write!(f, "source would block"),
             LexerError::IOError(err) => write!(f, "IO Error: {}", err),
         }
     }
 }
 
-pub struct Lexer<R, CS> {
+pub struct Lexer<R, S, CS> {
     source: Option<R>,
     create_string: CS,
+    // Tokens that have been read ahead by `Lexer::peek`, in order, along with their source text if
+    // raw text capture was enabled when they were read.
+    lookahead: VecDeque<(Token<S>, Span, Option<Vec<u8>>)>,
+    // Bytes read from the source that have not been lexed yet, preceded by the bytes of a partly
+    // read token, see `Lexer::commit`.
     peek_buffer: Vec<u8>,
+    // The number of bytes at the start of the peek buffer that have been advanced over.
+    cursor: usize,
     string_buffer: Vec<u8>,
     line_number: u64,
+    column: u64,
+    byte_offset: usize,
+    comments: bool,
+    recover: bool,
+    errors: Vec<(LexerError, Span)>,
+    strict_utf8: bool,
+    extended_unicode_escapes: bool,
+    type_annotations: bool,
+    raw_text: bool,
+    // The source bytes of the token being read, if raw text capture is enabled.
+    raw_buffer: Vec<u8>,
+    limits: LexerLimits,
+    token_count: usize,
+    // The byte offset of the start of the token being read, if any.
+    token_start: Option<usize>,
+    chunk_name: Option<ChunkName>,
 }
 
-impl<R, S, CS> Lexer<R, CS>
+impl<R, S, CS> Lexer<R, S, CS>
 where
     R: Read,
     CS: FnMut(&[u8]) -> S,
 {
-    pub fn new(source: R, create_string: CS) -> Lexer<R, CS> {
+    pub fn new(source: R, create_string: CS) -> Lexer<R, S, CS> {
         Lexer {
             source: Some(source),
             create_string,
+            lookahead: VecDeque::new(),
             peek_buffer: Vec::new(),
+            cursor: 0,
             string_buffer: Vec::new(),
             line_number: 0,
+            column: 0,
+            byte_offset: 0,
+            comments: false,
+            recover: false,
+            errors: Vec::new(),
+            strict_utf8: false,
+            extended_unicode_escapes: false,
+            type_annotations: false,
+            raw_text: false,
+            raw_buffer: Vec::new(),
+            limits: LexerLimits::default(),
+            token_count: 0,
+            token_start: None,
+            chunk_name: None,
+        }
+    }
+
+    /// Creates a lexer that produces a `Token::Comment` for every comment instead of skipping it,
+    /// for tools such as documentation extractors and formatters.  Only `parse_lexer_with_comments`
+    /// accepts comment tokens, the other parsing functions do not.
+    pub fn with_comments(source: R, create_string: CS) -> Lexer<R, S, CS> {
+        Lexer {
+            comments: true,
+            ..Lexer::new(source, create_string)
         }
     }
 
-    /// Current line number of the source file, 0-indexed
+    /// Sets whether this lexer recovers from errors in the source, disabled by default.  Instead of
+    /// returning an error and stopping, a recovering lexer records the error along with its
+    /// location, skips ahead to the next whitespace or `;` and carries on with the next token, so
+    /// that tools can report every lexical error in a source in a single pass.  Errors reading the
+    /// source are never recovered from.
+    pub fn set_error_recovery(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    /// Sets whether string literals must be valid UTF-8, disabled by default.  In strict mode, a
+    /// string literal containing source bytes that are not valid UTF-8 is a
+    /// `LexerError::InvalidUtf8`.  Bytes produced by escape sequences such as `\xff` are still
+    /// allowed, since those are clearly intentional.
+    pub fn set_strict_utf8(&mut self, strict_utf8: bool) {
+        self.strict_utf8 = strict_utf8;
+    }
+
+    /// Sets whether `\u{XXX}` escapes accept any value up to 2^31 - 1, as in Lua 5.4, disabled by
+    /// default.  Values that are not Unicode scalar values (surrogates and values above U+10FFFF)
+    /// are then encoded with the same extended UTF-8 encoding of up to 6 bytes that Lua 5.4 uses,
+    /// rather than being an `EscapeUnicodeInvalid` error.
+    pub fn set_extended_unicode_escapes(&mut self, extended_unicode_escapes: bool) {
+        self.extended_unicode_escapes = extended_unicode_escapes;
+    }
+
+    /// Sets whether this lexer accepts the extra characters used by Luau-style type annotations,
+    /// disabled by default.  Currently this only lexes `?` as `Token::Question`, every other
+    /// character of the type syntax is already a Lua token.  See `parse_typed_chunk`.
+    pub fn set_type_annotations(&mut self, type_annotations: bool) {
+        self.type_annotations = type_annotations;
+    }
+
+    /// Sets whether this lexer captures the exact source bytes of every token it reads, disabled by
+    /// default.  The captured text is returned by `Lexer::read_raw_token`, and lets tools such as
+    /// formatters and minifiers reproduce the original spelling of string literals and numerals.
+    pub fn set_raw_text(&mut self, raw_text: bool) {
+        self.raw_text = raw_text;
+    }
+
+    /// Sets the resource limits for this lexer, see `LexerLimits`.  Exceeding a limit is a
+    /// `LexerError::LimitExceeded`, which is never recovered from.
+    pub fn set_limits(&mut self, limits: LexerLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> LexerLimits {
+        self.limits
+    }
+
+    /// Sets the name of the chunk this lexer reads, unnamed by default.  The parser passes the name
+    /// on to the `Chunk` it parses and to the `SyntaxError`s it locates, and the compiler to every
+    /// `FunctionProto` of the chunk.
+    pub fn set_chunk_name(&mut self, chunk_name: ChunkName) {
+        self.chunk_name = Some(chunk_name);
+    }
+
+    pub fn chunk_name(&self) -> Option<&ChunkName> {
+        self.chunk_name.as_ref()
+    }
+
+    /// The errors recovered from so far, in the order they were found.
+    pub fn errors(&self) -> &[(LexerError, Span)] {
+        &self.errors
+    }
+
+    /// Takes the errors recovered from so far.
+    pub fn take_errors(&mut self) -> Vec<(LexerError, Span)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Current line number of the source file, 0-indexed.  Like `Lexer::column` and
+    /// `Lexer::byte_offset`, this is the position after any tokens read ahead by `Lexer::peek`.
     pub fn line_number(&self) -> u64 {
         self.line_number
     }
 
+    /// Current column in the current line, 0-indexed and counted as described in `Span::column`
+    pub fn column(&self) -> u64 {
+        self.column
+    }
+
+    /// Current offset in bytes from the start of the source file
+    pub fn byte_offset(&self) -> usize {
+        self.byte_offset
+    }
+
+    /// Skips whitespace and comments.  At the very start of the source, this also skips a UTF-8
+    /// byte order mark and then a first line starting with `#`, such as a `#!/usr/bin/env lua`
+    /// shebang line, as the standard Lua interpreter does.
     pub fn skip_whitespace(&mut self) -> Result<(), LexerError> {
+        let (line_number, column, byte_offset) = (self.line_number, self.column, self.byte_offset);
         let mut do_skip_whitespace = || {
-            while let Some(c) = self.peek(0)? {
+            let at_start = self.byte_offset == 0;
+            if at_start
+                && self.peek_byte(0)? == Some(0xef)
+                && self.peek_byte(1)? == Some(0xbb)
+                && self.peek_byte(2)? == Some(0xbf)
+            {
+                self.advance(3);
+                // The byte order mark does not take up a column.
+                self.column = 0;
+            }
+
+            if at_start && self.peek_byte(0)? == Some(b'#') {
+                while let Some(c) = self.peek_byte(0)? {
+                    if is_newline(c) {
+                        break;
+                    }
+                    self.advance(1);
+                }
+            }
+
+            while let Some(c) = self.peek_byte(0)? {
                 match c {
                     b' ' | b'\t' | VERTICAL_TAB | FORM_FEED => {
                         self.advance(1);
@@ -157,27 +426,10 @@ where
                     }
 
                     b'-' => {
-                        if self.peek(1)? != Some(b'-') {
+                        if self.peek_byte(1)? != Some(b'-') || self.comments {
                             break;
                         } else {
-                            self.advance(2);
-
-                            match (self.peek(0)?, self.peek(1)?) {
-                                (Some(b'['), Some(b'=')) | (Some(b'['), Some(b'[')) => {
-                                    // long comment
-                                    self.read_long_string(false)?;
-                                }
-                                _ => {
-                                    // Short comment, read until end of line
-                                    while let Some(c) = self.peek(0)? {
-                                        if is_newline(c) {
-                                            break;
-                                        } else {
-                                            self.advance(1);
-                                        }
-                                    }
-                                }
-                            }
+                            self.read_comment(false)?;
                         }
                     }
 
@@ -189,7 +441,14 @@ where
         };
 
         match do_skip_whitespace() {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                self.commit();
+                Ok(())
+            }
+            Err(LexerError::WouldBlock) => {
+                self.rewind(line_number, column, byte_offset);
+                Err(LexerError::WouldBlock)
+            }
             Err(err) => {
                 self.reset();
                 Err(err)
@@ -197,30 +456,199 @@ where
         }
     }
 
+    /// Turns this lexer into an iterator over the remaining tokens and their spans.
+    pub fn into_tokens(self) -> Tokens<R, S, CS> {
+        Tokens {
+            lexer: self,
+            failed: false,
+        }
+    }
+
+    /// Returns the token `n` tokens ahead of the next one to be read (so `peek(0)` is the next
+    /// token) without consuming it, or None if the source ends before then.  Tokens are read
+    /// ahead into an internal buffer as needed, so a recursive descent parser can be written
+    /// directly against the lexer.
+    pub fn peek(&mut self, n: usize) -> Result<Option<&Token<S>>, LexerError> {
+        while self.lookahead.len() <= n {
+            match self.lex_spanned_token()? {
+                Some((token, span)) => {
+                    let raw = if self.raw_text {
+                        Some(std::mem::take(&mut self.raw_buffer))
+                    } else {
+                        None
+                    };
+                    self.lookahead.push_back((token, span, raw));
+                }
+                None => break,
+            }
+        }
+        Ok(self.lookahead.get(n).map(|(token, _, _)| token))
+    }
+
+    /// Consumes the next token if it is equal to `token` and returns its span, otherwise returns
+    /// the same error the parser would and leaves the next token unread.
+    pub fn expect(&mut self, token: &Token<S>) -> Result<Span, ParserError>
+    where
+        S: Debug + PartialEq,
+    {
+        match self.peek(0).map_err(ParserError::LexerError)? {
+            None => Err(ParserError::EndOfStream {
+                expected: Some(format!("{:?}", token)),
+            }),
+            Some(next_token) if next_token == token => {
+                let (_, span, _) = self.lookahead.pop_front().unwrap();
+                Ok(span)
+            }
+            Some(next_token) => Err(ParserError::Unexpected {
+                unexpected: format!("{:?}", next_token),
+                expected: Some(format!("{:?}", token)),
+            }),
+        }
+    }
+
+    /// Reads the next token along with its location in the source, or None if the end of the
+    /// source has been reached.
+    pub fn read_spanned_token(&mut self) -> Result<Option<(Token<S>, Span)>, LexerError> {
+        match self.lookahead.pop_front() {
+            Some((token, span, _)) => Ok(Some((token, span))),
+            None => self.lex_spanned_token(),
+        }
+    }
+
+    /// Reads the next token along with its location and its exact text in the source, including
+    /// any quotes, long brackets and escape sequences of strings and the original spelling of
+    /// numerals, or None if the end of the source has been reached.  The text does not include the
+    /// whitespace or comments before the token, which lie between the spans of adjacent tokens.
+    ///
+    /// The text is only captured if `Lexer::set_raw_text` was enabled before the token was read or
+    /// peeked, otherwise it is empty.
+    pub fn read_raw_token(&mut self) -> Result<Option<(Token<S>, Span, S)>, LexerError> {
+        Ok(match self.lookahead.pop_front() {
+            Some((token, span, raw)) => {
+                let raw = (self.create_string)(raw.as_deref().unwrap_or(&[]));
+                Some((token, span, raw))
+            }
+            None => match self.lex_spanned_token()? {
+                Some((token, span)) => {
+                    let raw = (self.create_string)(&self.raw_buffer);
+                    self.raw_buffer.clear();
+                    Some((token, span, raw))
+                }
+                None => None,
+            },
+        })
+    }
+
     /// Reads the next token, or None if the end of the source has been reached.
     pub fn read_token(&mut self) -> Result<Option<Token<S>>, LexerError> {
-        self.skip_whitespace()?;
+        Ok(self.read_spanned_token()?.map(|(token, _)| token))
+    }
+
+    fn lex_spanned_token(&mut self) -> Result<Option<(Token<S>, Span)>, LexerError> {
+        loop {
+            self.skip_whitespace()?;
+            let (line_number, column, start) = (self.line_number, self.column, self.byte_offset);
+            self.token_start = Some(start);
+            self.raw_buffer.clear();
+            let mut res = self
+                .lex_token()
+                .and_then(|token| self.check_token_limits(start, token));
+            self.token_start = None;
+            let mut recovered = false;
+            if let Err(err) = &res {
+                if self.recover && err.is_recoverable() {
+                    match self.skip_invalid(start) {
+                        Ok(()) => recovered = true,
+                        Err(err) => res = Err(err),
+                    }
+                }
+            }
+            if let Err(LexerError::WouldBlock) = res {
+                self.rewind(line_number, column, start);
+                return Err(LexerError::WouldBlock);
+            }
+            self.commit();
+
+            let span = Span {
+                line_number: LineNumber(line_number + 1),
+                column: column + 1,
+                start,
+                end: self.byte_offset,
+            };
+            match res {
+                Err(err) if recovered => self.errors.push((err, span)),
+                res => return Ok(res?.map(|token| (token, span))),
+            }
+        }
+    }
+
+    // Checks the limits that can only be checked once a whole token has been read.
+    fn check_token_limits(
+        &mut self,
+        start: usize,
+        token: Option<Token<S>>,
+    ) -> Result<Option<Token<S>>, LexerError> {
+        if token.is_none() {
+            return Ok(None);
+        }
+        self.token_count += 1;
+        let limit = if self.byte_offset - start > self.limits.max_token_len {
+            Some(LexerLimit::TokenLength)
+        } else if self.token_count > self.limits.max_tokens {
+            Some(LexerLimit::TokenCount)
+        } else {
+            None
+        };
+        match limit {
+            Some(limit) => {
+                self.reset();
+                Err(LexerError::LimitExceeded(limit))
+            }
+            None => Ok(token),
+        }
+    }
+
+    // Skips the rest of an invalid token that started at the byte offset `start`, up to the next
+    // whitespace or `;`.
+    fn skip_invalid(&mut self, start: usize) -> Result<(), LexerError> {
+        self.string_buffer.clear();
+        if self.byte_offset == start {
+            self.advance(1);
+        }
+        while let Some(c) = self.peek_byte(0)? {
+            if c == b';' || is_space(c) {
+                break;
+            }
+            self.advance(1);
+        }
+        Ok(())
+    }
 
+    fn lex_token(&mut self) -> Result<Option<Token<S>>, LexerError> {
         let mut do_read_token = || {
-            if let Some(c) = self.peek(0)? {
+            if let Some(c) = self.peek_byte(0)? {
                 Ok(Some(match c {
                     b' ' | b'\t' | VERTICAL_TAB | FORM_FEED | b'\n' | b'\r' => {
                         unreachable!("whitespace should have been skipped");
                     }
 
                     b'-' => {
-                        if self.peek(1)? != Some(b'-') {
+                        if self.peek_byte(1)? != Some(b'-') {
                             self.advance(1);
                             Token::Minus
                         } else {
-                            unreachable!("whitespace should have been skipped");
+                            assert!(self.comments, "whitespace should have been skipped");
+                            self.read_comment(true)?;
+                            Token::Comment(self.take_string())
                         }
                     }
 
                     b'[' => {
-                        let next = self.peek(1)?;
+                        let next = self.peek_byte(1)?;
                         if next == Some(b'=') || next == Some(b'[') {
                             self.read_long_string(true)?;
+                            self.check_utf8(0)?;
+                            self.check_string_len()?;
                             Token::String(self.take_string())
                         } else {
                             self.advance(1);
@@ -230,7 +658,7 @@ where
 
                     b'=' => {
                         self.advance(1);
-                        if self.peek(0)? == Some(b'=') {
+                        if self.peek_byte(0)? == Some(b'=') {
                             self.advance(1);
                             Token::Equal
                         } else {
@@ -240,7 +668,7 @@ where
 
                     b'<' => {
                         self.advance(1);
-                        let next = self.peek(0)?;
+                        let next = self.peek_byte(0)?;
                         if next == Some(b'=') {
                             self.advance(1);
                             Token::LessEqual
@@ -254,7 +682,7 @@ where
 
                     b'>' => {
                         self.advance(1);
-                        let next = self.peek(0)?;
+                        let next = self.peek_byte(0)?;
                         if next == Some(b'=') {
                             self.advance(1);
                             Token::GreaterEqual
@@ -268,7 +696,7 @@ where
 
                     b'/' => {
                         self.advance(1);
-                        if self.peek(0)? == Some(b'/') {
+                        if self.peek_byte(0)? == Some(b'/') {
                             self.advance(1);
                             Token::IDiv
                         } else {
@@ -278,7 +706,7 @@ where
 
                     b'~' => {
                         self.advance(1);
-                        if self.peek(0)? == Some(b'=') {
+                        if self.peek_byte(0)? == Some(b'=') {
                             self.advance(1);
                             Token::NotEqual
                         } else {
@@ -288,7 +716,7 @@ where
 
                     b':' => {
                         self.advance(1);
-                        if self.peek(0)? == Some(b':') {
+                        if self.peek_byte(0)? == Some(b':') {
                             self.advance(1);
                             Token::DoubleColon
                         } else {
@@ -298,19 +726,20 @@ where
 
                     b'"' | b'\'' => {
                         self.read_short_string()?;
+                        self.check_string_len()?;
                         Token::String(self.take_string())
                     }
 
                     b'.' => {
-                        if self.peek(1)? == Some(b'.') {
-                            if self.peek(2)? == Some(b'.') {
+                        if self.peek_byte(1)? == Some(b'.') {
+                            if self.peek_byte(2)? == Some(b'.') {
                                 self.advance(3);
                                 Token::Dots
                             } else {
                                 self.advance(2);
                                 Token::Concat
                             }
-                        } else if self.peek(1)?.map(is_digit).unwrap_or(false) {
+                        } else if self.peek_byte(1)?.map(is_digit).unwrap_or(false) {
                             self.read_numeral()?
                         } else {
                             self.advance(1);
@@ -324,12 +753,15 @@ where
                         } else if let Some(t) = get_char_token(c) {
                             self.advance(1);
                             t
+                        } else if c == b'?' && self.type_annotations {
+                            self.advance(1);
+                            Token::Question
                         } else if is_alpha(c) {
                             self.string_buffer.clear();
                             self.string_buffer.push(c);
                             self.advance(1);
 
-                            while let Some(c) = self.peek(0)? {
+                            while let Some(c) = self.peek_byte(0)? {
                                 if is_alpha(c) || is_digit(c) {
                                     self.string_buffer.push(c);
                                     self.advance(1);
@@ -356,6 +788,10 @@ where
 
         match do_read_token() {
             Ok(Some(token)) => Ok(Some(token)),
+            // The lexer can carry on after an error in the source, see `Lexer::set_error_recovery`.
+            Err(err) if self.recover && err.is_recoverable() => Err(err),
+            // The token is read again once the source has more input.
+            Err(LexerError::WouldBlock) => Err(LexerError::WouldBlock),
             res => {
                 self.reset();
                 res
@@ -367,20 +803,22 @@ where
     fn reset(&mut self) {
         self.source = None;
         self.peek_buffer.clear();
+        self.cursor = 0;
         self.string_buffer.clear();
+        self.raw_buffer.clear();
     }
 
     // Read any of "\n", "\r", "\n\r", or "\r\n" as a single newline, and increment the current line
     // number.  If `append_buffer` is true, then appends the read newline to the string buffer.
     fn read_line_end(&mut self, append_string: bool) -> Result<(), LexerError> {
-        let newline = self.peek(0).unwrap().unwrap();
+        let newline = self.peek_byte(0).unwrap().unwrap();
         assert!(is_newline(newline));
         self.advance(1);
         if append_string {
             self.string_buffer.push(newline);
         }
 
-        if let Some(next_newline) = self.peek(0)? {
+        if let Some(next_newline) = self.peek_byte(0)? {
             if is_newline(next_newline) && next_newline != newline {
                 self.advance(1);
                 if append_string {
@@ -390,23 +828,57 @@ where
         }
 
         self.line_number += 1;
+        self.column = 0;
+        Ok(())
+    }
+
+    // Read a short or long comment starting with "--".  If `into_string` is true, reads the text
+    // of the comment into the string buffer.
+    fn read_comment(&mut self, into_string: bool) -> Result<(), LexerError> {
+        self.advance(2);
+
+        match (self.peek_byte(0)?, self.peek_byte(1)?) {
+            (Some(b'['), Some(b'=')) | (Some(b'['), Some(b'[')) => {
+                // long comment
+                self.read_long_string(into_string)?;
+            }
+            _ => {
+                // Short comment, read until end of line
+                if into_string {
+                    self.string_buffer.clear();
+                }
+                while let Some(c) = self.peek_byte(0)? {
+                    if is_newline(c) {
+                        break;
+                    } else {
+                        if into_string {
+                            self.string_buffer.push(c);
+                        }
+                        self.advance(1);
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
     // Read a string on a single line delimited by ' or " that allows for \ escaping of certain
     // characters.  Always reads the contained string into the string buffer.
     fn read_short_string(&mut self) -> Result<(), LexerError> {
-        let start_quote = self.peek(0).unwrap().unwrap();
+        let start_quote = self.peek_byte(0).unwrap().unwrap();
         assert!(start_quote == b'\'' || start_quote == b'"');
         self.advance(1);
 
         self.string_buffer.clear();
+        // The start of the bytes read directly from the source since the last escape sequence.
+        let mut run_start = 0;
 
         loop {
-            let c = if let Some(c) = self.peek(0)? {
+            let c = if let Some(c) = self.peek_byte(0)? {
                 c
             } else {
-                return Err(LexerError::UnfinishedShortString(start_quote));
+                return Err(LexerError::UnexpectedEof);
             };
 
             if is_newline(c) {
@@ -415,10 +887,8 @@ where
 
             self.advance(1);
             if c == b'\\' {
-                match self
-                    .peek(0)?
-                    .ok_or_else(|| LexerError::UnfinishedShortString(start_quote))?
-                {
+                self.check_utf8(run_start)?;
+                match self.peek_byte(0)?.ok_or(LexerError::UnexpectedEof)? {
                     b'a' => {
                         self.advance(1);
                         self.string_buffer.push(ALERT_BEEP);
@@ -476,11 +946,11 @@ where
                     b'x' => {
                         self.advance(1);
                         let first = self
-                            .peek(0)?
+                            .peek_byte(0)?
                             .and_then(from_hex_digit)
                             .ok_or(LexerError::HexDigitExpected)?;
                         let second = self
-                            .peek(1)?
+                            .peek_byte(1)?
                             .and_then(from_hex_digit)
                             .ok_or(LexerError::HexDigitExpected)?;
                         self.string_buffer.push(first << 4 | second);
@@ -488,18 +958,21 @@ where
                     }
 
                     b'u' => {
-                        if self.peek(1)? != Some(b'{') {
+                        if self.peek_byte(1)? != Some(b'{') {
                             return Err(LexerError::EscapeUnicodeStart);
                         }
                         self.advance(2);
 
                         let mut u: u32 = 0;
                         loop {
-                            if let Some(c) = self.peek(0)? {
+                            if let Some(c) = self.peek_byte(0)? {
                                 if c == b'}' {
                                     self.advance(1);
                                     break;
                                 } else if let Some(h) = from_hex_digit(c) {
+                                    if u > MAX_EXTENDED_UTF8 >> 4 {
+                                        return Err(LexerError::EscapeUnicodeInvalid);
+                                    }
                                     u = (u << 4) | h as u32;
                                     self.advance(1);
                                 } else {
@@ -510,16 +983,20 @@ where
                             }
                         }
 
-                        let c = char::from_u32(u).ok_or(LexerError::EscapeUnicodeInvalid)?;
-                        let mut buf = [0; 4];
-                        for &b in c.encode_utf8(&mut buf).as_bytes() {
-                            self.string_buffer.push(b);
+                        if self.extended_unicode_escapes {
+                            encode_extended_utf8(u, &mut self.string_buffer);
+                        } else {
+                            let c = char::from_u32(u).ok_or(LexerError::EscapeUnicodeInvalid)?;
+                            let mut buf = [0; 4];
+                            for &b in c.encode_utf8(&mut buf).as_bytes() {
+                                self.string_buffer.push(b);
+                            }
                         }
                     }
 
                     b'z' => {
                         self.advance(1);
-                        while let Some(c) = self.peek(0)? {
+                        while let Some(c) = self.peek_byte(0)? {
                             if is_newline(c) {
                                 self.read_line_end(false)?;
                             } else if is_space(c) {
@@ -534,7 +1011,7 @@ where
                         if is_digit(c) {
                             let mut u: u16 = 0;
                             for _ in 0..3 {
-                                if let Some(d) = self.peek(0)?.and_then(from_digit) {
+                                if let Some(d) = self.peek_byte(0)?.and_then(from_digit) {
                                     u = 10 * u + d as u16;
                                     self.advance(1);
                                 } else {
@@ -551,7 +1028,9 @@ where
                         }
                     }
                 }
+                run_start = self.string_buffer.len();
             } else if c == start_quote {
+                self.check_utf8(run_start)?;
                 break;
             } else {
                 self.string_buffer.push(c);
@@ -564,7 +1043,7 @@ where
     // Read a [=*[...]=*] sequence with matching numbers of '='.  If `into_string` is true, writes
     // the contained string into the string buffer.
     fn read_long_string(&mut self, into_string: bool) -> Result<(), LexerError> {
-        assert_eq!(self.peek(0).unwrap().unwrap(), b'[');
+        assert_eq!(self.peek_byte(0).unwrap().unwrap(), b'[');
         self.advance(1);
 
         if into_string {
@@ -572,21 +1051,24 @@ where
         }
 
         let mut open_sep_length = 0;
-        while self.peek(0)? == Some(b'=') {
+        while self.peek_byte(0)? == Some(b'=') {
             self.advance(1);
             open_sep_length += 1;
+            if open_sep_length > self.limits.max_long_bracket_level {
+                return Err(LexerError::LimitExceeded(LexerLimit::LongBracketLevel));
+            }
         }
 
-        if self.peek(0)? != Some(b'[') {
+        if self.peek_byte(0)? != Some(b'[') {
             return Err(LexerError::InvalidLongStringDelimiter);
         }
         self.advance(1);
 
         loop {
-            let c = if let Some(c) = self.peek(0)? {
+            let c = if let Some(c) = self.peek_byte(0)? {
                 c
             } else {
-                return Err(LexerError::UnfinishedLongString);
+                return Err(LexerError::UnexpectedEof);
             };
 
             match c {
@@ -597,12 +1079,12 @@ where
                 b']' => {
                     let mut close_sep_length = 0;
                     self.advance(1);
-                    while self.peek(0)? == Some(b'=') {
+                    while self.peek_byte(0)? == Some(b'=') {
                         self.advance(1);
                         close_sep_length += 1;
                     }
 
-                    if open_sep_length == close_sep_length && self.peek(0)? == Some(b']') {
+                    if open_sep_length == close_sep_length && self.peek_byte(0)? == Some(b']') {
                         self.advance(1);
                         break;
                     } else {
@@ -631,14 +1113,16 @@ where
 
     // Reads a hex or decimal integer or floating point identifier.  Allows decimal integers (123),
     // hex integers (0xdeadbeef), decimal floating point with optional exponent and exponent sign
-    // (3.21e+1), and hex floats with optional exponent and exponent sign (0xe.2fp-1c).
+    // (3.21e+1), and hex floats with optional exponent and exponent sign (0xe.2fp-1c).  As in Lua
+    // 5.3, decimal integers that do not fit in an i64 become floats while hex integers wrap around,
+    // and a numeral directly followed by a letter, digit or '.' is malformed.
     fn read_numeral(&mut self) -> Result<Token<S>, LexerError> {
-        let p1 = self.peek(0).unwrap().unwrap();
+        let p1 = self.peek_byte(0).unwrap().unwrap();
         assert!(p1 == b'.' || is_digit(p1));
 
         self.string_buffer.clear();
 
-        let p2 = self.peek(1)?;
+        let p2 = self.peek_byte(1)?;
         let is_hex = p1 == b'0' && (p2 == Some(b'x') || p2 == Some(b'X'));
         if is_hex {
             self.string_buffer.push(p1);
@@ -647,7 +1131,7 @@ where
         }
 
         let mut has_radix = false;
-        while let Some(c) = self.peek(0)? {
+        while let Some(c) = self.peek_byte(0)? {
             if c == b'.' && !has_radix {
                 self.string_buffer.push(b'.');
                 has_radix = true;
@@ -661,7 +1145,7 @@ where
         }
 
         let mut has_exp = false;
-        if let Some(exp_begin) = self.peek(0)? {
+        if let Some(exp_begin) = self.peek_byte(0)? {
             if (is_hex && (exp_begin == b'p' || exp_begin == b'P'))
                 || (!is_hex && (exp_begin == b'e' || exp_begin == b'E'))
             {
@@ -669,14 +1153,14 @@ where
                 has_exp = true;
                 self.advance(1);
 
-                if let Some(sign) = self.peek(0)? {
+                if let Some(sign) = self.peek_byte(0)? {
                     if sign == b'+' || sign == b'-' {
                         self.string_buffer.push(sign);
                         self.advance(1);
                     }
                 }
 
-                while let Some(c) = self.peek(0)? {
+                while let Some(c) = self.peek_byte(0)? {
                     if is_digit(c) {
                         self.string_buffer.push(c);
                         self.advance(1);
@@ -687,6 +1171,12 @@ where
             }
         }
 
+        if let Some(c) = self.peek_byte(0)? {
+            if is_alpha(c) || is_digit(c) || c == b'.' {
+                return Err(LexerError::BadNumber);
+            }
+        }
+
         if !has_exp && !has_radix {
             if is_hex {
                 if let Some(i) = read_hex_integer(&self.string_buffer) {
@@ -708,9 +1198,16 @@ where
         ))
     }
 
-    fn peek(&mut self, n: usize) -> Result<Option<u8>, LexerError> {
+    fn peek_byte(&mut self, n: usize) -> Result<Option<u8>, LexerError> {
+        // Checked here as well as once the token is read so that reading a long token stops as soon
+        // as it is too long.
+        if let Some(start) = self.token_start {
+            if self.byte_offset - start > self.limits.max_token_len {
+                return Err(LexerError::LimitExceeded(LexerLimit::TokenLength));
+            }
+        }
         if let Some(source) = self.source.as_mut() {
-            while self.peek_buffer.len() <= n {
+            while self.peek_buffer.len() <= self.cursor + n {
                 let mut c = [0];
                 match source.read(&mut c) {
                     Ok(0) => {
@@ -720,6 +1217,9 @@ where
                     Ok(_) => {
                         self.peek_buffer.push(c[0]);
                     }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        return Err(LexerError::WouldBlock);
+                    }
                     Err(e) => {
                         if e.kind() != io::ErrorKind::Interrupted {
                             self.source = None;
@@ -730,15 +1230,62 @@ where
             }
         }
 
-        Ok(self.peek_buffer.get(n).cloned())
+        Ok(self.peek_buffer.get(self.cursor + n).cloned())
     }
 
     fn advance(&mut self, n: usize) {
+        let end = self.cursor + n;
         assert!(
-            n <= self.peek_buffer.len(),
+            end <= self.peek_buffer.len(),
             "cannot advance over un-peeked characters"
         );
-        self.peek_buffer.drain(0..n);
+        let advanced = &self.peek_buffer[self.cursor..end];
+        if self.raw_text && self.token_start.is_some() {
+            self.raw_buffer.extend_from_slice(advanced);
+        }
+        for &c in advanced {
+            if c == b'\t' {
+                self.column = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+            } else if c & 0xc0 != 0x80 {
+                self.column += 1;
+            }
+        }
+        self.cursor = end;
+        self.byte_offset += n;
+    }
+
+    // Discards the bytes advanced over, once they have been lexed into whitespace or a whole token.
+    // Until then they are kept, so that the lexer can `rewind` if the source would block.
+    fn commit(&mut self) {
+        self.peek_buffer.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    // Goes back to the given position, which must be where the lexer was at the last `commit`.
+    fn rewind(&mut self, line_number: u64, column: u64, byte_offset: usize) {
+        self.line_number = line_number;
+        self.column = column;
+        self.byte_offset = byte_offset;
+        self.cursor = 0;
+        self.string_buffer.clear();
+        self.raw_buffer.clear();
+    }
+
+    fn check_string_len(&self) -> Result<(), LexerError> {
+        if self.string_buffer.len() > self.limits.max_string_len {
+            Err(LexerError::LimitExceeded(LexerLimit::StringLength))
+        } else {
+            Ok(())
+        }
+    }
+
+    // In strict UTF-8 mode, checks that the string buffer from `start` onwards is valid UTF-8.
+    fn check_utf8(&self, start: usize) -> Result<(), LexerError> {
+        if self.strict_utf8 && str::from_utf8(&self.string_buffer[start..]).is_err() {
+            Err(LexerError::InvalidUtf8)
+        } else {
+            Ok(())
+        }
     }
 
     fn take_string(&mut self) -> S {
@@ -748,9 +1295,110 @@ where
     }
 }
 
+impl<S, CS> Lexer<PushInput, S, CS> {
+    /// Pushes more input to the source of this lexer, see `PushInput::fill`.  Does nothing if the
+    /// lexer has already stopped at the end of its source or at an error.
+    pub fn fill(&mut self, bytes: &[u8]) {
+        if let Some(source) = self.source.as_mut() {
+            source.fill(bytes);
+        }
+    }
+
+    /// Marks the end of the source of this lexer, see `PushInput::finish`.
+    pub fn finish(&mut self) {
+        if let Some(source) = self.source.as_mut() {
+            source.finish();
+        }
+    }
+}
+
+/// A `Lexer` source that input is pushed to as it arrives, such as from a socket or an async
+/// stream, rather than one that blocks until input is available.  Until `PushInput::finish` is
+/// called, reading past the end of the input pushed so far is an `io::ErrorKind::WouldBlock` error,
+/// so a lexer reading a token that is not complete yet returns `LexerError::WouldBlock` and picks
+/// up from the start of that token after the next `Lexer::fill`.
+#[derive(Debug, Default)]
+pub struct PushInput {
+    buffer: VecDeque<u8>,
+    finished: bool,
+}
+
+impl PushInput {
+    pub fn new() -> PushInput {
+        PushInput::default()
+    }
+
+    /// Appends bytes to the end of the input.
+    pub fn fill(&mut self, bytes: &[u8]) {
+        assert!(!self.finished, "input pushed after finish");
+        self.buffer.extend(bytes);
+    }
+
+    /// Marks the end of the input, after which reading past the input pushed so far is the end of
+    /// the source.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Read for PushInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() && !self.finished {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.buffer.read(buf)
+    }
+}
+
+/// An iterator over the tokens of a `Lexer` and their spans, created by `Lexer::into_tokens`.
+/// Iteration ends after the first error.
+pub struct Tokens<R, S, CS> {
+    lexer: Lexer<R, S, CS>,
+    failed: bool,
+}
+
+impl<R, S, CS> Tokens<R, S, CS> {
+    pub fn lexer(&self) -> &Lexer<R, S, CS> {
+        &self.lexer
+    }
+}
+
+impl<R, S, CS> Iterator for Tokens<R, S, CS>
+where
+    R: Read,
+    CS: FnMut(&[u8]) -> S,
+{
+    type Item = Result<(Token<S>, Span), LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let res = self.lexer.read_spanned_token();
+        self.failed = res.is_err();
+        res.transpose()
+    }
+}
+
+/// The iterator returned by `owned_tokens`.
+pub type OwnedTokens<R> = Tokens<R, Box<[u8]>, fn(&[u8]) -> Box<[u8]>>;
+
+/// Returns an iterator over the tokens of `source` as `OwnedToken`s, along with their spans.
+pub fn owned_tokens<R: Read>(source: R) -> OwnedTokens<R> {
+    Lexer::new(source, (|s| s.into()) as fn(&[u8]) -> Box<[u8]>).into_tokens()
+}
+
 pub fn read_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
 
+    if s.is_empty() {
+        return None;
+    }
+
     let mut i: i64 = 0;
     for &c in s {
         let d = from_digit(c)? as i64;
@@ -771,25 +1419,30 @@ pub fn read_integer(s: &[u8]) -> Option<i64> {
 pub fn read_hex_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
 
+    if s.len() < 2 {
+        return None;
+    }
+
     if s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
         return None;
     }
 
+    if s.len() == 2 {
+        return None;
+    }
+
+    // Hex integers wrap around rather than overflowing, so 0xffffffffffffffff is -1.
     let mut i: i64 = 0;
     for &c in &s[2..] {
         let d = from_hex_digit(c)? as i64;
-        i = i.checked_mul(16)?.checked_add(d)?;
+        i = i.wrapping_mul(16).wrapping_add(d);
     }
 
     if is_neg {
-        i = i.checked_neg()?;
+        i = i.wrapping_neg();
     }
 
-    if i == i64::MIN {
-        None
-    } else {
-        Some(i)
-    }
+    Some(i)
 }
 
 pub fn read_float(s: &[u8]) -> Option<f64> {
@@ -870,6 +1523,32 @@ pub fn read_hex_float(s: &[u8]) -> Option<f64> {
     Some(base * (exp as f64).exp2())
 }
 
+// The largest value allowed in a `\u{XXX}` escape.
+const MAX_EXTENDED_UTF8: u32 = 0x7fff_ffff;
+
+// Appends `u` to `buf` in the extended UTF-8 encoding used by Lua 5.4, which is the same as UTF-8
+// for Unicode scalar values but continues the pattern for every value up to 2^31 - 1.
+fn encode_extended_utf8(mut u: u32, buf: &mut Vec<u8>) {
+    assert!(u <= MAX_EXTENDED_UTF8);
+    if u < 0x80 {
+        buf.push(u as u8);
+        return;
+    }
+
+    let mut bytes = [0; 6];
+    let mut n = 0;
+    // The largest value that fits in the first byte
+    let mut first_max = 0x3f;
+    while u > first_max {
+        bytes[n] = 0x80 | (u & 0x3f) as u8;
+        n += 1;
+        u >>= 6;
+        first_max >>= 1;
+    }
+    buf.push((!first_max << 1) as u8 | u as u8);
+    buf.extend(bytes[..n].iter().rev());
+}
+
 fn read_neg(s: &[u8]) -> (bool, &[u8]) {
     if s.len() > 0 {
         if s[0] == b'-' {
@@ -884,11 +1563,17 @@ fn read_neg(s: &[u8]) -> (bool, &[u8]) {
     }
 }
 
+// The number of columns between tab stops, see `Span::column`.
+const TAB_WIDTH: u64 = 8;
+
 const ALERT_BEEP: u8 = 0x07;
 const BACKSPACE: u8 = 0x08;
 const VERTICAL_TAB: u8 = 0x0b;
 const FORM_FEED: u8 = 0x0c;
 
+// The tokens made of a single character that is not the start of any longer token.  These lookups
+// are plain `match`es rather than tables so that the compiler can turn them into jump tables and
+// the lexer needs no lazily initialized statics.
 fn get_char_token<S>(c: u8) -> Option<Token<S>> {
     match c {
         b'-' => Some(Token::Minus),
@@ -910,6 +1595,7 @@ fn get_char_token<S>(c: u8) -> Option<Token<S>> {
     }
 }
 
+// The token for a reserved word, or None if `word` is a name.
 fn get_reserved_word_token<S>(word: &[u8]) -> Option<Token<S>> {
     match word {
         b"break" => Some(Token::Break),
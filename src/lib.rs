@@ -1,40 +1,125 @@
+#[cfg(feature = "bigint")]
+pub mod bigint;
+mod bytecode;
 #[macro_use]
 mod callback;
 mod closure;
+#[cfg(feature = "compat")]
+pub mod compat;
 mod compiler;
 mod constant;
+mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+mod data;
+mod debugger;
 mod error;
+mod executor;
+#[cfg(feature = "fetch")]
+pub mod fetch;
+mod heap;
 pub mod io;
+#[cfg(feature = "jit")]
+pub mod jit;
 mod lexer;
+#[cfg(feature = "log")]
+pub mod log;
 #[macro_use]
 mod lua;
+mod module;
 mod opcode;
+mod output;
+mod owned_value;
 pub mod parser;
+pub mod prelude;
+pub mod print;
+mod profile;
+mod proto_cache;
+#[cfg(feature = "re")]
+pub mod re;
+mod registry;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod replay;
 mod string;
+pub mod sync;
 mod table;
 mod thread;
+#[cfg(feature = "trace")]
+pub mod trace;
+mod type_feedback;
 mod types;
+mod validate;
 mod value;
+#[cfg(feature = "vecmath")]
+pub mod vecmath;
+mod verifier;
+pub mod visit;
 
 mod stdlib;
 
+pub use bytecode::{
+    dump_compiled, dump_proto, dump_stripped_proto, load_compiled, load_file, load_proto,
+    BytecodeError, Operand,
+};
 pub use callback::{Callback, CallbackResult, CallbackReturn, Continuation};
 pub use closure::{
-    Closure, ClosureError, ClosureState, FunctionProto, UpValue, UpValueDescriptor, UpValueState,
+    Closure, ClosureError, FunctionInfo, FunctionProto, LocalVariable, UpValue, UpValueDescriptor,
+};
+pub use compiler::{
+    compile, compile_chunk, compile_named, compile_optimized, compile_tokens, compile_typed,
+    optimize, CompilerError, Optimizations,
 };
-pub use compiler::{compile, compile_chunk, CompilerError};
 pub use constant::Constant;
+pub use coverage::{html_report, lcov_record, LineCoverage};
+pub use data::{load_data_table, DataError};
+pub use debugger::{DebugStep, Debugger, PauseReason, Paused, StepCommand};
 pub use error::{Error, RuntimeError, StaticError, TypeError};
-pub use lexer::{Lexer, LexerError, Token};
-pub use lua::{Lua, Root};
+pub use executor::{Executor, TaskError, TaskId};
+pub use heap::{heap_census, reference_path, HeapCensus, ObjectStats, TableSize};
+pub use lexer::{
+    owned_tokens, Lexer, LexerError, LexerLimit, LexerLimits, OwnedToken, OwnedTokens, PushInput,
+    Span, Token, Tokens,
+};
+pub use lua::{Dialect, GcTimeSlice, Lua, LusterBuilder, Root, StdLib};
+pub use module::CompiledModule;
 pub use opcode::OpCode;
-pub use parser::{parse_chunk, ParserError};
+pub use output::{Output, Warnings};
+pub use owned_value::{OwnedValue, MAX_SNAPSHOT_DEPTH};
+pub use parser::{
+    parse_chunk, parse_lexer, parse_lexer_located, parse_lexer_recovering,
+    parse_lexer_with_comments, parse_lexer_with_limits, parse_repl_line, parse_tokens,
+    parse_typed_chunk, Comment, CommentPlacement, ParserError, ParserLimits, StatementStream,
+    StreamedStatement, SyntaxError,
+};
+pub use profile::{CallStats, ProfileEntry, ProfiledFunction, Profiler};
+pub use proto_cache::PrototypeCache;
+pub use registry::WeakRegistryKey;
+pub use replay::{Replay, ReplayError, ReplayEvent};
+pub use stdlib::{
+    load_package_with_loader, path_candidates, search_path, CharClass, CharClasses, ChunkSource,
+    DEFAULT_PACKAGE_PATH,
+};
 pub use string::{InternedStringSet, String, StringError};
-pub use table::{InvalidTableKey, Table, TableState};
+pub use table::{InvalidTableKey, Table};
 pub use thread::{
-    BadThreadMode, BinaryOperatorError, Thread, ThreadError, ThreadMode, ThreadSequence,
+    BadThreadMode, BinaryOperatorError, CallResult, CallSequence, CountHook, ErrorFormat,
+    ForLoopError, Operation, StackFrame, StackSnapshot, Thread, ThreadError, ThreadMode,
+    ThreadSequence, Traceback, TracebackFrame, DEFAULT_MAX_STRING_LEN,
 };
+pub use type_feedback::{InstructionFeedback, TypeCounts, TypeFeedback};
 pub use types::{
-    ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueIndex, VarCount,
+    ChunkName, ConstantIndex16, ConstantIndex8, LineNumber, Opt254, PrototypeIndex, RegisterIndex,
+    UpValueIndex, VarCount,
 };
-pub use value::{Function, Value};
+pub use validate::{validate_api, ArgType, Param, SchemaError, Signature};
+pub use value::{values_deep_equal, walk, Function, Value};
+pub use verifier::{verify, VerifyError};
+
+// The contents of the public handle types, which are only of interest to luster itself.
+#[doc(hidden)]
+pub use closure::{ClosureState, UpValueState};
+#[doc(hidden)]
+pub use executor::ExecutorState;
+#[doc(hidden)]
+pub use table::TableState;
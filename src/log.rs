@@ -0,0 +1,100 @@
+//! A Lua `log` library that forwards records to the host's `tracing` subscriber.
+//!
+//! `log.trace`, `log.debug`, `log.info`, `log.warn` and `log.error` each take a message and an
+//! optional table of fields, for example `log.warn("low health", {player = name, health = hp})`.
+//! Every call emits one event at the matching level with the target `luster::script` and the
+//! fields:
+//!
+//! * `message`: the message, converted as with `print`
+//! * `line`: the source line of the call, or 0 if unknown
+//! * `fields`: the fields table as space separated `key=value` pairs, sorted by key
+//!
+//! Field names in `tracing` are fixed at compile time, so the fields from the table are folded
+//! into the single `fields` string rather than becoming event fields of their own.
+
+use std::string::String as StdString;
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+use tracing::Level;
+
+use crate::thread::caller_line;
+use crate::{Callback, CallbackResult, Error, Root, String, Table, TypeError, Value};
+
+pub fn load_log<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let log = Table::new(mc);
+    for &(name, level) in &[
+        (&b"trace"[..], Level::TRACE),
+        (b"debug", Level::DEBUG),
+        (b"info", Level::INFO),
+        (b"warn", Level::WARN),
+        (b"error", Level::ERROR),
+    ] {
+        let callback = Callback::new_sequence(mc, move |args| {
+            let message = display(args.first().cloned().unwrap_or(Value::Nil))?;
+            let fields = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => StdString::new(),
+                Value::Table(fields) => format_fields(fields)?,
+                value => {
+                    return Err(TypeError {
+                        expected: "table",
+                        found: value.type_name(),
+                    }
+                    .into());
+                }
+            };
+            // The caller's line is only known while the sequence is being stepped.
+            Ok(sequence::from_fn(move |_| {
+                let line = caller_line().map(|l| l.0).unwrap_or(0);
+                emit(level, &message, line, &fields);
+                Ok(CallbackResult::Return(Vec::new()))
+            }))
+        });
+        log.set(mc, String::new_static(name), callback).unwrap();
+    }
+    env.set(mc, String::new_static(b"log"), log).unwrap();
+}
+
+fn emit(level: Level, message: &str, line: u64, fields: &str) {
+    macro_rules! event {
+        ($level:expr) => {
+            tracing::event!(
+                target: "luster::script",
+                $level,
+                line,
+                fields,
+                "{}",
+                message
+            )
+        };
+    }
+
+    match level {
+        Level::TRACE => event!(Level::TRACE),
+        Level::DEBUG => event!(Level::DEBUG),
+        Level::INFO => event!(Level::INFO),
+        Level::WARN => event!(Level::WARN),
+        _ => event!(Level::ERROR),
+    }
+}
+
+fn format_fields<'gc>(fields: Table<'gc>) -> Result<StdString, Error<'gc>> {
+    let mut pairs = fields
+        .0
+        .read()
+        .iter()
+        .map(|(key, value)| Ok((display(key)?, display(value)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    pairs.sort();
+    Ok(pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+fn display<'gc>(value: Value<'gc>) -> Result<StdString, Error<'gc>> {
+    let mut buf = Vec::new();
+    value.display(&mut buf)?;
+    Ok(StdString::from_utf8_lossy(&buf).into_owned())
+}
@@ -1,9 +1,19 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
 use gc_arena::{ArenaParameters, Collect, MutationContext};
-use gc_sequence::{make_sequencable_arena, Sequence};
+use gc_sequence::{
+    self as sequence, make_sequencable_arena, Sequence, SequenceExt, SequenceResultExt,
+};
 
 use crate::{
-    stdlib::{load_base, load_coroutine, load_math},
-    InternedStringSet, Table, Thread,
+    compile, compile_typed, load_proto,
+    stdlib::{
+        load_base, load_buffer, load_coroutine, load_debug, load_inspect, load_math,
+        load_math_with_replay, load_package, load_string, load_table, load_test, load_timer,
+    },
+    CharClasses, Closure, Error, Executor, Function, FunctionProto, InternedStringSet, Output,
+    OwnedValue, Replay, StaticError, Table, Thread, ThreadSequence, DEFAULT_MAX_STRING_LEN,
 };
 
 #[derive(Collect, Clone, Copy)]
@@ -12,21 +22,19 @@ pub struct Root<'gc> {
     pub main_thread: Thread<'gc>,
     pub globals: Table<'gc>,
     pub interned_strings: InternedStringSet<'gc>,
+    /// Runs the tasks scheduled by the `timer` library, see `Executor`.
+    pub executor: Executor<'gc>,
 }
 
 impl<'gc> Root<'gc> {
     pub fn new(mc: MutationContext<'gc, '_>) -> Root<'gc> {
-        let root = Root {
-            main_thread: Thread::new(mc, false),
-            globals: Table::new(mc),
-            interned_strings: InternedStringSet::new(mc),
-        };
-
-        load_base(mc, root, root.globals);
-        load_coroutine(mc, root, root.globals);
-        load_math(mc, root, root.globals);
+        LusterBuilder::new().build_root(mc)
+    }
 
-        root
+    /// Creates a new `Root`, loading only the given parts of the standard library into the globals
+    /// table.  Every other setting is made with `LusterBuilder::build_root`.
+    pub fn with_stdlib(mc: MutationContext<'gc, '_>, stdlib: StdLib) -> Root<'gc> {
+        LusterBuilder::new().stdlib(stdlib).build_root(mc)
     }
 }
 
@@ -35,16 +43,340 @@ make_sequencable_arena!(pub lua_arena, Root);
 pub use lua_arena::Arena;
 pub use lua_arena::Sequencer;
 
-/// Simpler wrapper for `Arena` that automatically garbage collects at reasonable intervals.
-pub struct Lua(Option<lua_arena::Arena>);
+/// Selects which parts of the standard library are loaded into a new `Lua` instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StdLib {
+    pub base: bool,
+    pub coroutine: bool,
+    pub math: bool,
+    /// The `string` library, which is also made the `__index` table of the main thread's string
+    /// metatable.
+    pub string: bool,
+    /// The `table` library, currently only the `table.compact` extension.
+    pub table: bool,
+    /// The `buffer` library of string buffers for building strings incrementally.
+    pub buffer: bool,
+    /// Debugging helpers such as `inspect`, not loaded by default.
+    pub inspect: bool,
+    /// The `debug` library, currently only `debug.sethook` and `debug.gethook` with count hooks.
+    /// Not loaded by default.
+    pub debug: bool,
+    /// The `luster.test` framework used by `luster test`, not loaded by default.
+    pub test: bool,
+    /// The `timer` library, which schedules tasks on `Root::executor` and so does nothing unless
+    /// the host runs it.  Not loaded by default.
+    pub timer: bool,
+    /// The `package` library, whose `package.searchpath` reads the file system of the host.  Not
+    /// loaded by default.
+    pub package: bool,
+}
+
+impl StdLib {
+    /// Every implemented part of the standard library.
+    pub fn all() -> StdLib {
+        StdLib {
+            base: true,
+            coroutine: true,
+            math: true,
+            string: true,
+            table: true,
+            buffer: true,
+            inspect: true,
+            debug: true,
+            test: true,
+            timer: true,
+            package: true,
+        }
+    }
+
+    /// No standard library at all, the globals table starts empty.
+    pub fn none() -> StdLib {
+        StdLib {
+            base: false,
+            coroutine: false,
+            math: false,
+            string: false,
+            table: false,
+            buffer: false,
+            inspect: false,
+            debug: false,
+            test: false,
+            timer: false,
+            package: false,
+        }
+    }
+}
+
+/// The default is every part of the standard library except the debugging helpers, `timer` and
+/// `package`.
+impl Default for StdLib {
+    fn default() -> StdLib {
+        StdLib {
+            inspect: false,
+            debug: false,
+            test: false,
+            timer: false,
+            package: false,
+            ..StdLib::all()
+        }
+    }
+}
+
+/// The language that `Lua::run_string` accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Lua,
+    /// Lua with Luau-style type annotations, which are parsed and discarded, see
+    /// `parse_typed_chunk`.
+    Typed,
+}
 
 const COLLECTOR_GRANULARITY: f64 = 1024.0;
 
+/// Spreads garbage collection work evenly over script execution, see
+/// `LusterBuilder::gc_time_slice`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcTimeSlice {
+    /// The number of VM instructions between collection slices.
+    pub interval: u32,
+    /// The maximum amount of collection work done in each slice, in the same units as allocation
+    /// debt.
+    pub work: f64,
+}
+
+/// Gathers all of the configuration for a new `Lua` instance in one place.
+///
+/// `Lua::new()` is equivalent to `LusterBuilder::new().build()`.  Hosts that manage their own arena
+/// can create a configured `Root` inside it with `build_root` instead.  Scripts are made
+/// deterministic by giving them a `Replay`, which records or replays everything they read from the
+/// host.
+#[derive(Debug, Clone)]
+pub struct LusterBuilder {
+    arena_parameters: ArenaParameters,
+    collector_granularity: f64,
+    stdlib: StdLib,
+    output: Output,
+    gc_time_slice: Option<GcTimeSlice>,
+    max_string_len: usize,
+    char_classes: CharClasses,
+    string_coercion: bool,
+    replay: Option<Replay>,
+    memory_limit: Option<usize>,
+    instruction_budget: Option<u64>,
+    dialect: Dialect,
+}
+
+impl Default for LusterBuilder {
+    fn default() -> LusterBuilder {
+        LusterBuilder {
+            arena_parameters: ArenaParameters::default(),
+            collector_granularity: COLLECTOR_GRANULARITY,
+            stdlib: StdLib::default(),
+            output: Output::stdout(),
+            gc_time_slice: None,
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            char_classes: CharClasses::default(),
+            string_coercion: true,
+            replay: None,
+            memory_limit: None,
+            instruction_budget: None,
+            dialect: Dialect::default(),
+        }
+    }
+}
+
+impl LusterBuilder {
+    pub fn new() -> LusterBuilder {
+        LusterBuilder::default()
+    }
+
+    /// Garbage collector pacing parameters for the underlying arena.
+    pub fn arena_parameters(mut self, arena_parameters: ArenaParameters) -> LusterBuilder {
+        self.arena_parameters = arena_parameters;
+        self
+    }
+
+    /// The amount of allocation debt that must accumulate before `Lua` performs any collection
+    /// work.  Lower values make individual pauses shorter but more frequent.  Must be >= 0.0.
+    pub fn collector_granularity(mut self, collector_granularity: f64) -> LusterBuilder {
+        assert!(collector_granularity >= 0.0);
+        self.collector_granularity = collector_granularity;
+        self
+    }
+
+    /// Which parts of the standard library to load into the globals table.
+    pub fn stdlib(mut self, stdlib: StdLib) -> LusterBuilder {
+        self.stdlib = stdlib;
+        self
+    }
+
+    /// Where script output goes and how much of it is allowed, see `Output`.
+    pub fn output(mut self, output: Output) -> LusterBuilder {
+        self.output = output;
+        self
+    }
+
+    /// Instead of collecting all outstanding allocation debt whenever it passes the collector
+    /// granularity, do at most `work` units of collection work after every `interval` executed VM
+    /// instructions.  This keeps individual pauses short and predictable, but `work` must be large
+    /// enough to keep up with the script's allocation rate or memory use will grow.
+    pub fn gc_time_slice(mut self, interval: u32, work: f64) -> LusterBuilder {
+        assert!(interval > 0 && work > 0.0);
+        self.gc_time_slice = Some(GcTimeSlice { interval, work });
+        self
+    }
+
+    /// The length in bytes of the longest string a script may create, see
+    /// `Thread::set_max_string_len`.
+    pub fn max_string_len(mut self, max_string_len: usize) -> LusterBuilder {
+        self.max_string_len = max_string_len;
+        self
+    }
+
+    /// The bytes in each character class of `string` library patterns, see
+    /// `Thread::set_char_classes`.
+    pub fn char_classes(mut self, char_classes: CharClasses) -> LusterBuilder {
+        self.char_classes = char_classes;
+        self
+    }
+
+    /// Whether strings and numbers are implicitly converted to each other by arithmetic, `..` and
+    /// the standard library, see `Thread::set_string_coercion`.
+    pub fn string_coercion(mut self, string_coercion: bool) -> LusterBuilder {
+        self.string_coercion = string_coercion;
+        self
+    }
+
+    /// Records the nondeterministic inputs of the standard library to `replay`, or replays them
+    /// from it, see the `replay` module.  Host callbacks and time queries are recorded through the
+    /// same `Replay` by the host.
+    pub fn replay(mut self, replay: Replay) -> LusterBuilder {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// The most memory in bytes that the arena may use while running a chunk with
+    /// `Lua::run_string` or `Lua::run_bytecode`, see `Lua::total_allocated`.  A chunk that is
+    /// still over the limit after a full collection is stopped with an error.
+    pub fn memory_limit(mut self, bytes: usize) -> LusterBuilder {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// The most VM instructions that a chunk run with `Lua::run_string` or `Lua::run_bytecode`
+    /// may execute before it is stopped with an error.  The budget is checked every few hundred
+    /// instructions, so a chunk may slightly exceed it.
+    pub fn instruction_budget(mut self, instructions: u64) -> LusterBuilder {
+        self.instruction_budget = Some(instructions);
+        self
+    }
+
+    /// The language of the source given to `Lua::run_string`, standard Lua by default.
+    pub fn dialect(mut self, dialect: Dialect) -> LusterBuilder {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn build(self) -> Lua {
+        let mut arena = Arena::new(self.arena_parameters.clone(), |mc| self.build_root(mc));
+        let instructions = arena.mutate(|_, root| root.main_thread.instruction_counter());
+        Lua {
+            arena: Some(arena),
+            collector_granularity: self.collector_granularity,
+            output: self.output,
+            gc_time_slice: self.gc_time_slice,
+            last_instructions: instructions.get(),
+            instructions,
+            pending_instructions: 0,
+            memory_limit: self.memory_limit,
+            instruction_budget: self.instruction_budget,
+            dialect: self.dialect,
+        }
+    }
+
+    /// Creates a `Root` with the standard library, output, replay and main thread settings of this
+    /// builder, for hosts that create their own arena.  Settings that belong to `Lua`, such as
+    /// garbage collector pacing and limits, are ignored.
+    pub fn build_root<'gc>(&self, mc: MutationContext<'gc, '_>) -> Root<'gc> {
+        let main_thread = Thread::new(mc, false);
+        let root = Root {
+            main_thread,
+            globals: Table::new(mc),
+            interned_strings: InternedStringSet::new(mc),
+            executor: Executor::new(mc, main_thread),
+        };
+        root.main_thread.set_max_string_len(mc, self.max_string_len);
+        root.main_thread.set_char_classes(mc, self.char_classes);
+        root.main_thread
+            .set_string_coercion(mc, self.string_coercion);
+
+        let stdlib = self.stdlib;
+        if stdlib.base {
+            load_base(mc, root, root.globals, self.output.clone());
+        }
+        if stdlib.coroutine {
+            load_coroutine(mc, root, root.globals);
+        }
+        if stdlib.math {
+            match &self.replay {
+                Some(replay) => load_math_with_replay(mc, root, root.globals, replay.clone()),
+                None => load_math(mc, root, root.globals),
+            }
+        }
+        if stdlib.string {
+            load_string(mc, root, root.globals);
+        }
+        if stdlib.table {
+            load_table(mc, root, root.globals);
+        }
+        if stdlib.buffer {
+            load_buffer(mc, root, root.globals);
+        }
+        if stdlib.inspect {
+            load_inspect(mc, root, root.globals);
+        }
+        if stdlib.debug {
+            load_debug(mc, root, root.globals);
+        }
+        if stdlib.test {
+            load_test(mc, root, root.globals);
+        }
+        if stdlib.timer {
+            load_timer(mc, root, root.globals);
+        }
+        if stdlib.package {
+            load_package(mc, root, root.globals);
+        }
+
+        root
+    }
+}
+
+/// Simpler wrapper for `Arena` that automatically garbage collects at reasonable intervals.
+pub struct Lua {
+    arena: Option<lua_arena::Arena>,
+    collector_granularity: f64,
+    output: Output,
+    gc_time_slice: Option<GcTimeSlice>,
+    // The instructions executed in this arena so far, see `Thread::executed_instructions`
+    instructions: Rc<Cell<u64>>,
+    // The value of `instructions` when garbage collection was last scheduled
+    last_instructions: u64,
+    // Instructions executed since the last collection slice
+    pending_instructions: u64,
+    memory_limit: Option<usize>,
+    instruction_budget: Option<u64>,
+    dialect: Dialect,
+}
+
 impl Lua {
     pub fn new() -> Lua {
-        Lua(Some(Arena::new(ArenaParameters::default(), |mc| {
-            Root::new(mc)
-        })))
+        LusterBuilder::new().build()
+    }
+
+    pub fn builder() -> LusterBuilder {
+        LusterBuilder::new()
     }
 
     /// Runs a single action inside the Lua arena, during which no garbage collection may take place.
@@ -53,14 +385,62 @@ impl Lua {
         R: 'static,
         F: for<'gc> FnOnce(MutationContext<'gc, '_>, Root<'gc>) -> R,
     {
-        let arena = self.0.as_mut().unwrap();
-        let r = arena.mutate(move |mc, root| f(mc, *root));
-        if arena.allocation_debt() > COLLECTOR_GRANULARITY {
-            arena.collect_debt();
+        let r = self
+            .arena
+            .as_mut()
+            .unwrap()
+            .mutate(move |mc, root| f(mc, *root));
+        let slices = self.due_gc_slices();
+        let arena = self.arena.as_mut().unwrap();
+        match self.gc_time_slice {
+            Some(slice) => {
+                for _ in 0..slices {
+                    arena.collect_work(slice.work);
+                }
+            }
+            None => {
+                if arena.allocation_debt() > self.collector_granularity {
+                    arena.collect_debt();
+                }
+            }
         }
         r
     }
 
+    /// Runs the garbage collector until every value that is unreachable at the time of the call has
+    /// been freed, which finishes the collection cycle in progress and then runs a whole new one.
+    pub fn collect_all(&mut self) {
+        let arena = self.arena.as_mut().unwrap();
+        arena.collect_all();
+        arena.collect_all();
+    }
+
+    /// Returns the total memory currently used by the arena, in bytes.
+    pub fn total_allocated(&self) -> usize {
+        self.arena.as_ref().unwrap().total_allocated()
+    }
+
+    // The budget set with `LusterBuilder::instruction_budget`, for the `compat` layer.
+    #[cfg(feature = "compat")]
+    pub(crate) fn configured_instruction_budget(&self) -> Option<u64> {
+        self.instruction_budget
+    }
+
+    // Returns the number of garbage collection slices that are due since this was last called
+    fn due_gc_slices(&mut self) -> u64 {
+        let executed = self.instructions.get();
+        self.pending_instructions += executed.wrapping_sub(self.last_instructions);
+        self.last_instructions = executed;
+        match self.gc_time_slice {
+            Some(slice) => {
+                let slices = self.pending_instructions / slice.interval as u64;
+                self.pending_instructions %= slice.interval as u64;
+                slices
+            }
+            None => 0,
+        }
+    }
+
     /// Runs a sequence of actions inside the Lua arena and return the result.  Garbage collection
     /// may take place in-between sequence steps.
     pub fn sequence<F, R>(&mut self, f: F) -> R
@@ -68,20 +448,121 @@ impl Lua {
         R: 'static,
         F: for<'gc> FnOnce(Root<'gc>) -> Box<dyn Sequence<'gc, Output = R> + 'gc>,
     {
-        let mut sequencer = self.0.take().unwrap().sequence(move |root| f(*root));
+        let mut sequencer = self.arena.take().unwrap().sequence(move |root| f(*root));
         loop {
             match sequencer.step() {
                 Ok((arena, output)) => {
-                    self.0 = Some(arena);
+                    self.arena = Some(arena);
                     return output;
                 }
                 Err(s) => {
                     sequencer = s;
-                    if sequencer.allocation_debt() > COLLECTOR_GRANULARITY {
-                        sequencer.collect_debt();
+                    self.collect_between_steps(&mut sequencer);
+                }
+            }
+        }
+    }
+
+    /// Compiles and runs the given source on the main thread with the globals table as its
+    /// environment, returning a snapshot of everything it returned.  The source is in the dialect
+    /// set with `LusterBuilder::dialect`, and the chunk is stopped if it exceeds the memory limit
+    /// or instruction budget.
+    pub fn run_string(&mut self, source: &[u8]) -> Result<Vec<OwnedValue>, StaticError> {
+        let source = source.to_vec();
+        let dialect = self.dialect;
+        self.run_main(move |mc, root| match dialect {
+            Dialect::Lua => compile(mc, root.interned_strings, &source[..]),
+            Dialect::Typed => compile_typed(mc, root.interned_strings, &source[..]),
+        })
+    }
+
+    /// The same as `Lua::run_string`, but for bytecode written by `dump_proto` (such as the output
+    /// of the `include_lua!` macro), which is verified rather than compiled.
+    pub fn run_bytecode(&mut self, bytecode: &[u8]) -> Result<Vec<OwnedValue>, StaticError> {
+        let bytecode = bytecode.to_vec();
+        self.run_main(move |mc, root| Ok(load_proto(mc, root.interned_strings, &bytecode)?))
+    }
+
+    // Runs the function prototype returned by `load` on the main thread, enforcing the memory
+    // limit and instruction budget.
+    fn run_main<F>(&mut self, load: F) -> Result<Vec<OwnedValue>, StaticError>
+    where
+        F: 'static
+            + for<'gc> FnOnce(
+                MutationContext<'gc, '_>,
+                Root<'gc>,
+            ) -> Result<FunctionProto<'gc>, Error<'gc>>,
+    {
+        let mut sequencer = self.arena.take().unwrap().sequence(move |root| {
+            sequence::from_fn_with(*root, move |mc, root| {
+                Ok(Closure::new(mc, load(mc, root)?, Some(root.globals))?)
+            })
+            .and_chain_with(*root, |mc, root, closure| {
+                Ok(ThreadSequence::call_function(
+                    mc,
+                    root.main_thread,
+                    Function::Closure(closure),
+                    &[],
+                )?)
+            })
+            .map(|res: Result<Vec<_>, Error>| match res {
+                Ok(values) => Ok(values.into_iter().map(OwnedValue::from_value).collect()),
+                Err(err) => Err(err.to_static()),
+            })
+            .boxed()
+        });
+
+        let started = self.instructions.get();
+        loop {
+            match sequencer.step() {
+                Ok((arena, output)) => {
+                    self.arena = Some(arena);
+                    return output.map_err(|err| self.output.limit_error(err));
+                }
+                Err(s) => {
+                    sequencer = s;
+                    self.collect_between_steps(&mut sequencer);
+
+                    let exceeded = if self.instruction_budget.is_some_and(|budget| {
+                        self.instructions.get().wrapping_sub(started) > budget
+                    }) {
+                        Some("instruction budget exceeded")
+                    } else if self.memory_limit.is_some_and(|limit| {
+                        sequencer.total_allocated() > limit && {
+                            // Only what is still reachable counts towards the limit.
+                            sequencer.collect_all();
+                            sequencer.collect_all();
+                            sequencer.total_allocated() > limit
+                        }
+                    }) {
+                        Some("memory limit exceeded")
+                    } else {
+                        None
+                    };
+                    if let Some(message) = exceeded {
+                        let mut arena = sequencer.abort();
+                        arena.mutate(|mc, root| root.main_thread.cancel(mc));
+                        self.arena = Some(arena);
+                        return Err(StaticError::RuntimeError(message.to_owned()));
                     }
                 }
             }
         }
     }
+
+    // Does the garbage collection work that is due in between two steps of a sequence.
+    fn collect_between_steps<R: 'static>(&mut self, sequencer: &mut Sequencer<R>) {
+        match self.gc_time_slice {
+            Some(slice) => {
+                for _ in 0..self.due_gc_slices() {
+                    sequencer.collect_work(slice.work);
+                }
+            }
+            None => {
+                if sequencer.allocation_debt() > self.collector_granularity {
+                    sequencer.collect_debt();
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,107 @@
+use std::io::Read;
+use std::string::String as StdString;
+
+use rustc_hash::FxHashSet;
+
+use gc_arena::{Collect, Gc, MutationContext};
+
+use crate::{
+    compile, Closure, ClosureError, Constant, Error, FunctionProto, InternedStringSet,
+    PrototypeCache, Table,
+};
+
+/// Several chunks compiled into one module, such as all of the scripts of one plugin.
+///
+/// Every chunk is compiled against the same `InternedStringSet` and passed through one
+/// `PrototypeCache`, so a string constant or function that appears in several chunks is only kept
+/// in memory once.  The distinct constants of all of the chunks form the constant pool of the
+/// module, and each chunk is still available as its own entry point through
+/// `CompiledModule::closure`.
+#[derive(Collect)]
+#[collect(empty_drop)]
+pub struct CompiledModule<'gc> {
+    interned_strings: InternedStringSet<'gc>,
+    cache: PrototypeCache<'gc>,
+    constants: Vec<Constant<'gc>>,
+    // The contents of `constants`, to keep them distinct.
+    constant_set: FxHashSet<Constant<'gc>>,
+    chunks: Vec<(StdString, Gc<'gc, FunctionProto<'gc>>)>,
+}
+
+impl<'gc> CompiledModule<'gc> {
+    pub fn new(
+        mc: MutationContext<'gc, '_>,
+        interned_strings: InternedStringSet<'gc>,
+    ) -> CompiledModule<'gc> {
+        CompiledModule {
+            interned_strings,
+            cache: PrototypeCache::new(mc),
+            constants: Vec::new(),
+            constant_set: FxHashSet::default(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Compiles a chunk and adds it to the module under the given name, replacing any chunk
+    /// already added under that name.  Returns the prototype of the main function of the chunk.
+    pub fn add_chunk<R: Read>(
+        &mut self,
+        mc: MutationContext<'gc, '_>,
+        name: &str,
+        source: R,
+    ) -> Result<Gc<'gc, FunctionProto<'gc>>, Error<'gc>> {
+        let proto = compile(mc, self.interned_strings, source)?;
+        let proto = self
+            .cache
+            .share(mc, self.interned_strings, Gc::allocate(mc, proto));
+        self.add_constants(proto);
+
+        match self.chunks.iter_mut().find(|(n, _)| n == name) {
+            Some((_, chunk)) => *chunk = proto,
+            None => self.chunks.push((name.to_owned(), proto)),
+        }
+        Ok(proto)
+    }
+
+    /// Returns the prototype of the main function of the named chunk.
+    pub fn chunk(&self, name: &str) -> Option<Gc<'gc, FunctionProto<'gc>>> {
+        self.chunks
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, proto)| proto)
+    }
+
+    /// The names of every chunk in the module, in the order they were first added.
+    pub fn chunk_names(&self) -> Vec<&str> {
+        self.chunks.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Every distinct constant used by any function in the module, in the order they were first
+    /// found.
+    pub fn constants(&self) -> &[Constant<'gc>] {
+        &self.constants
+    }
+
+    /// Creates a closure that runs the named chunk with the given environment, or returns None if
+    /// there is no such chunk.
+    pub fn closure(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        name: &str,
+        environment: Option<Table<'gc>>,
+    ) -> Option<Result<Closure<'gc>, ClosureError>> {
+        let proto = self.chunk(name)?;
+        Some(Closure::new_with_proto(mc, proto, environment))
+    }
+
+    fn add_constants(&mut self, proto: Gc<'gc, FunctionProto<'gc>>) {
+        for &constant in &proto.constants {
+            if self.constant_set.insert(constant) {
+                self.constants.push(constant);
+            }
+        }
+        for &proto in &proto.prototypes {
+            self.add_constants(proto);
+        }
+    }
+}
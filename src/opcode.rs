@@ -4,7 +4,17 @@ use crate::{
     ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueIndex, VarCount,
 };
 
-#[derive(Debug, Copy, Clone, Collect)]
+/// A single VM instruction.
+///
+/// In the descriptions of each opcode, `R(x)` is the register `x` of the current frame, `C(x)` is
+/// the constant `x` of the current prototype and `U(x)` is its upvalue `x`.  Every opcode in a
+/// prototype must only refer to registers below its `stack_size`, constants, upvalues and
+/// prototypes that exist, and jump to an opcode within the prototype.  Opcodes that operate on a
+/// range of registers must fit the whole range below `stack_size`, except for a variable count,
+/// which extends the stack as needed, and `stack_size` must be at most 256.  A prototype must end
+/// with a `Return`.  The compiler always upholds these, and `verify` checks them for prototypes
+/// from elsewhere.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
 #[collect(require_static)]
 pub enum OpCode {
     Move {
@@ -89,23 +99,35 @@ pub enum OpCode {
         key: ConstantIndex8,
         value: ConstantIndex8,
     },
+    // Calls R(func) with the `args` arguments starting at R(func + 1), placing `returns` results
+    // starting at R(func).  Uses the registers R(func) to R(func + args) and R(func) to
+    // R(func + returns - 1).
+    //
+    // A variable `args` passes every value from R(func + 1) to the top of the stack, left there by
+    // the previous opcode with variable results.
     Call {
         func: RegisterIndex,
         args: VarCount,
         returns: VarCount,
     },
+    // Calls R(func) with the `args` arguments starting at R(func + 1) in place of the current
+    // function, returning its results.
     TailCall {
         func: RegisterIndex,
         args: VarCount,
     },
+    // Returns the `count` values from R(start) to R(start + count - 1).
     Return {
         start: RegisterIndex,
         count: VarCount,
     },
+    // Places `count` of the current function's variable arguments starting at R(dest), padded with
+    // nil if there are fewer.
     VarArgs {
         dest: RegisterIndex,
         count: VarCount,
     },
+    // pc += offset, where pc is already the index of the next opcode
     Jump {
         offset: i16,
         // If set, close upvalues >= `close_upvalues`
@@ -125,6 +147,8 @@ pub enum OpCode {
         value: RegisterIndex,
         is_true: bool,
     },
+    // Creates a closure of the nested prototype `proto`, whose upvalue descriptors must refer to
+    // registers or upvalues of the current prototype that exist.
     Closure {
         dest: RegisterIndex,
         proto: PrototypeIndex,
@@ -154,6 +178,8 @@ pub enum OpCode {
     // Used to set up for a generic for loop:
     //
     // R(base + 3), ..., R(base + 2 + var_count) = R(base)(R(base + 1), R(base + 2))
+    //
+    // Returns beyond `var_count` are discarded, and missing returns are set to nil.
     GenericForCall {
         base: RegisterIndex,
         var_count: u8,
@@ -184,7 +210,9 @@ pub enum OpCode {
         table: RegisterIndex,
         key: ConstantIndex8,
     },
-    // Concatenate the given arguments into a string
+    // Concatenate the given arguments into a string:
+    //
+    // R(dest) = R(source) .. ... .. R(source + count - 1)
     Concat {
         dest: RegisterIndex,
         source: RegisterIndex,
@@ -0,0 +1,277 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::{Error, RuntimeError, StaticError, String, Value};
+
+/// Where script output (currently everything written by `print`) goes, and how much of it a
+/// script may produce.
+///
+/// `Output` is a shared handle, clones of it write to the same destination and count against the
+/// same quota.  Once the quota would be exceeded, the write that would exceed it is discarded and
+/// raises a Lua error instead, as does every later write.
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<OutputState>>);
+
+struct OutputState {
+    sink: Sink,
+    limit: Option<usize>,
+    written: usize,
+    warnings: Warnings,
+}
+
+enum Sink {
+    Stdout,
+    Capture(Vec<u8>),
+}
+
+impl Output {
+    /// Writes output to the process's stdout, without any limit.  This is the default.
+    pub fn stdout() -> Output {
+        Output::new(Sink::Stdout)
+    }
+
+    /// Keeps output in memory, to be retrieved with `Output::take_captured`.
+    pub fn capture() -> Output {
+        Output::new(Sink::Capture(Vec::new()))
+    }
+
+    /// Limits the total number of bytes that may be written to `limit`.  Error messages returned
+    /// to the host by `Lua::run_string` are also truncated to this length.
+    pub fn with_limit(self, limit: usize) -> Output {
+        self.0.borrow_mut().limit = Some(limit);
+        self
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.0.borrow().limit
+    }
+
+    /// The total number of bytes written so far.
+    pub fn written(&self) -> usize {
+        self.0.borrow().written
+    }
+
+    /// Returns and clears everything captured so far, always empty for `Output::stdout`.
+    pub fn take_captured(&self) -> Vec<u8> {
+        match &mut self.0.borrow_mut().sink {
+            Sink::Stdout => Vec::new(),
+            Sink::Capture(buf) => mem::take(buf),
+        }
+    }
+
+    /// Sends warnings raised by `warn` to the given `Warnings` instead of the default, which is
+    /// `Warnings::stderr()`.
+    pub fn with_warnings(self, warnings: Warnings) -> Output {
+        self.0.borrow_mut().warnings = warnings;
+        self
+    }
+
+    pub fn warnings(&self) -> Warnings {
+        self.0.borrow().warnings.clone()
+    }
+
+    /// Writes all of the given bytes, or fails with a Lua error if that would exceed the limit.
+    pub fn write<'gc>(&self, bytes: &[u8]) -> Result<(), Error<'gc>> {
+        let mut state = self.0.borrow_mut();
+        let written = state.written + bytes.len();
+        if let Some(limit) = state.limit {
+            if written > limit {
+                state.written = limit;
+                return Err(RuntimeError(Value::String(String::new_static(
+                    b"output limit exceeded",
+                )))
+                .into());
+            }
+        }
+        state.written = written;
+        match &mut state.sink {
+            Sink::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(bytes)?;
+                stdout.flush()?;
+            }
+            Sink::Capture(buf) => buf.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+
+    /// Truncates the message of a runtime error to the output limit, if there is one.
+    pub fn limit_error(&self, error: StaticError) -> StaticError {
+        match (error, self.limit()) {
+            (StaticError::RuntimeError(mut message), Some(limit)) if message.len() > limit => {
+                let mut end = limit;
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                message.truncate(end);
+                message.push_str("...");
+                StaticError::RuntimeError(message)
+            }
+            (error, _) => error,
+        }
+    }
+
+    fn new(sink: Sink) -> Output {
+        Output(Rc::new(RefCell::new(OutputState {
+            sink,
+            limit: None,
+            written: 0,
+            warnings: Warnings::stderr(),
+        })))
+    }
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output::stdout()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.0.borrow();
+        fmt.debug_struct("Output")
+            .field(
+                "sink",
+                &match state.sink {
+                    Sink::Stdout => "stdout",
+                    Sink::Capture(_) => "capture",
+                },
+            )
+            .field("limit", &state.limit)
+            .field("written", &state.written)
+            .field("warnings", &state.warnings)
+            .finish()
+    }
+}
+
+/// Where warnings raised by the `warn` function (or by the host, through `Warnings::warn`) go.
+///
+/// As in PUC-Rio Lua, warnings start out disabled and are turned on and off by the control
+/// messages `warn("@on")` and `warn("@off")`, or by the host with `Warnings::set_enabled`.
+/// Warnings emitted while disabled are discarded.  Like `Output`, this is a shared handle.
+#[derive(Clone)]
+pub struct Warnings(Rc<RefCell<WarningState>>);
+
+struct WarningState {
+    sink: WarningSink,
+    enabled: bool,
+}
+
+enum WarningSink {
+    Stderr,
+    Capture(Vec<Vec<u8>>),
+    Callback(WarningCallback),
+}
+
+type WarningCallback = Rc<dyn Fn(&[u8])>;
+
+impl Warnings {
+    /// Writes every warning to the process's stderr as a line prefixed with "Lua warning: ".
+    pub fn stderr() -> Warnings {
+        Warnings::new(WarningSink::Stderr)
+    }
+
+    /// Keeps warnings in memory, to be retrieved with `Warnings::take_captured`.
+    pub fn capture() -> Warnings {
+        Warnings::new(WarningSink::Capture(Vec::new()))
+    }
+
+    /// Calls the given function with the text of every warning.
+    pub fn callback(callback: impl Fn(&[u8]) + 'static) -> Warnings {
+        Warnings::new(WarningSink::Callback(Rc::new(callback)))
+    }
+
+    /// Sets whether warnings start out enabled.
+    pub fn with_enabled(self, enabled: bool) -> Warnings {
+        self.set_enabled(enabled);
+        self
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.borrow_mut().enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.borrow().enabled
+    }
+
+    /// Emits a single warning, if warnings are enabled.
+    pub fn warn(&self, message: &[u8]) {
+        let mut state = self.0.borrow_mut();
+        if !state.enabled {
+            return;
+        }
+        match &mut state.sink {
+            WarningSink::Stderr => {
+                let mut stderr = io::stderr();
+                let mut line = b"Lua warning: ".to_vec();
+                line.extend_from_slice(message);
+                line.push(b'\n');
+                // Like PUC-Rio Lua, a warning that cannot be written is silently lost.
+                let _ = stderr.write_all(&line);
+            }
+            WarningSink::Capture(warnings) => warnings.push(message.to_vec()),
+            WarningSink::Callback(callback) => {
+                // The callback may itself emit warnings, so it must not be called while the state
+                // is borrowed.
+                let callback = callback.clone();
+                drop(state);
+                callback(message);
+            }
+        }
+    }
+
+    /// Handles a message given to `warn`: a control message ("@on", "@off", or any other message
+    /// starting with '@', which is ignored) or a warning to emit.
+    pub fn message(&self, message: &[u8]) {
+        match message {
+            b"@on" => self.set_enabled(true),
+            b"@off" => self.set_enabled(false),
+            [b'@', ..] => {}
+            message => self.warn(message),
+        }
+    }
+
+    /// Returns and clears every warning captured so far, always empty unless created with
+    /// `Warnings::capture`.
+    pub fn take_captured(&self) -> Vec<Vec<u8>> {
+        match &mut self.0.borrow_mut().sink {
+            WarningSink::Capture(warnings) => mem::take(warnings),
+            _ => Vec::new(),
+        }
+    }
+
+    fn new(sink: WarningSink) -> Warnings {
+        Warnings(Rc::new(RefCell::new(WarningState {
+            sink,
+            enabled: false,
+        })))
+    }
+}
+
+impl Default for Warnings {
+    fn default() -> Warnings {
+        Warnings::stderr()
+    }
+}
+
+impl fmt::Debug for Warnings {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.0.borrow();
+        fmt.debug_struct("Warnings")
+            .field(
+                "sink",
+                &match state.sink {
+                    WarningSink::Stderr => "stderr",
+                    WarningSink::Capture(_) => "capture",
+                    WarningSink::Callback(_) => "callback",
+                },
+            )
+            .field("enabled", &state.enabled)
+            .finish()
+    }
+}
@@ -0,0 +1,107 @@
+use gc_arena::{Gc, GcCell};
+
+use crate::walk::VisitedSet;
+use crate::{Function, Table, Value};
+
+/// The deepest that tables are nested in an `OwnedValue`, tables below this are replaced with
+/// `OwnedValue::DeepTable` so that a snapshot of any value can be taken (and dropped) without
+/// overflowing the stack.
+pub const MAX_SNAPSHOT_DEPTH: usize = 200;
+
+/// A snapshot of a Lua value that does not borrow from the arena, and so can be returned from
+/// `Lua::mutate` or `Lua::sequence` and consumed outside of them.
+///
+/// Strings and tables are copied at the time of the snapshot.  Functions and threads cannot be
+/// copied out of the arena, so only their identity (the address they had during the snapshot) is
+/// kept.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    /// Every non-nil entry in the table, array part first in index order.
+    Table(Vec<(OwnedValue, OwnedValue)>),
+    /// A reference to a table that contains itself (directly or indirectly), which cannot be
+    /// represented as a tree.
+    RecursiveTable,
+    /// A table nested more than `MAX_SNAPSHOT_DEPTH` tables deep, whose contents are not copied.
+    DeepTable,
+    Function(usize),
+    Thread(usize),
+}
+
+impl OwnedValue {
+    pub fn from_value<'gc>(value: Value<'gc>) -> OwnedValue {
+        fn snapshot<'gc>(
+            value: Value<'gc>,
+            parents: &mut VisitedSet<Table<'gc>>,
+            depth: usize,
+        ) -> OwnedValue {
+            match value {
+                Value::Nil => OwnedValue::Nil,
+                Value::Boolean(b) => OwnedValue::Boolean(b),
+                Value::Integer(i) => OwnedValue::Integer(i),
+                Value::Number(n) => OwnedValue::Number(n),
+                Value::String(s) => OwnedValue::String(s.as_bytes().to_vec()),
+                Value::Table(t) => {
+                    if depth >= MAX_SNAPSHOT_DEPTH {
+                        return OwnedValue::DeepTable;
+                    }
+                    if !parents.insert(t) {
+                        return OwnedValue::RecursiveTable;
+                    }
+                    let entries =
+                        t.0.read()
+                            .iter()
+                            .map(|(k, v)| {
+                                (
+                                    snapshot(k, parents, depth + 1),
+                                    snapshot(v, parents, depth + 1),
+                                )
+                            })
+                            .collect();
+                    parents.remove(&t);
+                    OwnedValue::Table(entries)
+                }
+                Value::Function(Function::Closure(c)) => {
+                    OwnedValue::Function(Gc::as_ptr(c.0) as *const u8 as usize)
+                }
+                Value::Function(Function::Callback(c)) => {
+                    OwnedValue::Function(Gc::as_ptr(c.0) as *const u8 as usize)
+                }
+                Value::Thread(t) => OwnedValue::Thread(GcCell::as_ptr(t.0) as *const u8 as usize),
+            }
+        }
+
+        snapshot(value, &mut VisitedSet::new(), 0)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            OwnedValue::Nil => "nil",
+            OwnedValue::Boolean(_) => "boolean",
+            OwnedValue::Integer(_) | OwnedValue::Number(_) => "number",
+            OwnedValue::String(_) => "string",
+            OwnedValue::Table(_) | OwnedValue::RecursiveTable | OwnedValue::DeepTable => "table",
+            OwnedValue::Function(_) => "function",
+            OwnedValue::Thread(_) => "thread",
+        }
+    }
+
+    /// Looks up a key in a table snapshot, returns `None` if this is not a table or the key is not
+    /// present.
+    pub fn get(&self, key: &OwnedValue) -> Option<&OwnedValue> {
+        match self {
+            OwnedValue::Table(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl<'gc> From<Value<'gc>> for OwnedValue {
+    fn from(value: Value<'gc>) -> OwnedValue {
+        OwnedValue::from_value(value)
+    }
+}
@@ -1,21 +1,42 @@
 use std::error::Error as StdError;
 use std::fmt;
 use std::io::Read;
+use std::mem;
 use std::rc::Rc;
 
 use gc_arena::Collect;
 
-use crate::{Lexer, LexerError, Token};
+use crate::visit::{walk_return_statement, walk_statement, Visitor};
+use crate::{ChunkName, Lexer, LexerError, LineNumber, PushInput, Span, Token};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Chunk<S> {
     pub block: Block<S>,
+    /// The name of the chunk, from `Lexer::set_chunk_name`.
+    pub chunk_name: Option<ChunkName>,
+}
+
+/// An AST node along with the location in the source it was parsed from.
+///
+/// Statements, suffixes and primary expressions are wrapped in a `Spanned`, while the expression,
+/// function definition and table constructor nodes, which are always parsed from a contiguous
+/// piece of source, carry their span in a `span` field.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub node: T,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(span: Span, node: T) -> Spanned<T> {
+        Spanned { span, node }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Block<S> {
-    pub statements: Vec<Statement<S>>,
-    pub return_statement: Option<ReturnStatement<S>>,
+    pub statements: Vec<Spanned<Statement<S>>>,
+    pub return_statement: Option<Spanned<ReturnStatement<S>>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -140,6 +161,7 @@ pub enum UnaryOperator {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Expression<S> {
+    pub span: Span,
     pub head: Box<HeadExpression<S>>,
     pub tail: Vec<(BinaryOperator, Expression<S>)>,
 }
@@ -190,12 +212,28 @@ pub enum SuffixPart<S> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct SuffixedExpression<S> {
-    pub primary: PrimaryExpression<S>,
-    pub suffixes: Vec<SuffixPart<S>>,
+    pub primary: Spanned<PrimaryExpression<S>>,
+    pub suffixes: Vec<Spanned<SuffixPart<S>>>,
+}
+
+impl<S> SuffixedExpression<S> {
+    /// The span of the primary expression and every suffix.
+    pub fn span(&self) -> Span {
+        Span {
+            end: self
+                .suffixes
+                .last()
+                .map(|suffix| suffix.span.end)
+                .unwrap_or(self.primary.span.end),
+            ..self.primary.span
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionDefinition<S> {
+    /// The span from the `function` keyword to the final `end`.
+    pub span: Span,
     pub parameters: Vec<S>,
     pub has_varargs: bool,
     pub body: Block<S>,
@@ -221,6 +259,7 @@ pub enum AssignmentTarget<S> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TableConstructor<S> {
+    pub span: Span,
     pub fields: Vec<ConstructorField<S>>,
 }
 
@@ -248,6 +287,10 @@ pub enum ParserError {
     },
     AssignToExpression,
     ExpressionNotStatement,
+    /// A label with the same name as another label in the same block.  Labels in nested blocks may
+    /// reuse the name of a label in an enclosing block.
+    DuplicateLabel,
+    /// Statements or expressions nested more deeply than `ParserLimits::max_depth`.
     RecursionLimit,
     LexerError(LexerError),
 }
@@ -280,44 +323,628 @@ impl fmt::Display for ParserError {
             }
             ParserError::AssignToExpression => write!(f, "cannot assign to expression"),
             ParserError::ExpressionNotStatement => write!(f, "expression is not a statement"),
+            ParserError::DuplicateLabel => write!(f, "label defined multiple times"),
             ParserError::RecursionLimit => write!(f, "recursion limit reached"),
             ParserError::LexerError(lexer_error) => write!(f, "{}", lexer_error),
         }
     }
 }
 
+impl ParserError {
+    /// Whether the source ended before it was finished, either in the middle of a statement or
+    /// inside of a string or comment, so that more input could make it valid.  A REPL uses this to
+    /// decide whether to read a continuation line.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParserError::EndOfStream { .. } | ParserError::LexerError(LexerError::UnexpectedEof)
+        )
+    }
+}
+
+/// Bounds on the parser, for sources that come from untrusted users.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// How deeply statements and expressions may nest, such as in `((((x))))` or a chain of
+    /// unary operators, before parsing fails with `ParserError::RecursionLimit` instead of
+    /// overflowing the stack.  Defaults to 200, the limit on nested C calls (`LUAI_MAXCCALLS`) that
+    /// bounds the same nesting in PUC-Rio Lua.  The compiler recurses over the same nesting, so a
+    /// higher limit needs a larger stack for compiling as well as parsing.
+    pub max_depth: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> ParserLimits {
+        ParserLimits { max_depth: 200 }
+    }
+}
+
+/// A `ParserError` along with where in the source it was found.
+#[derive(Debug, Collect)]
+#[collect(require_static)]
+pub struct SyntaxError {
+    /// For errors from the lexer, an empty span at the point the lexer stopped.  Otherwise, the
+    /// span of the last token the parser read, which is the token it did not expect.
+    pub span: Span,
+    pub error: ParserError,
+    /// The name of the chunk the error is in, from `Lexer::set_chunk_name`.
+    pub chunk_name: Option<ChunkName>,
+}
+
+impl StdError for SyntaxError {}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(chunk_name) = &self.chunk_name {
+            write!(f, "{}:", chunk_name)?;
+        }
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line_number.0, self.span.column, self.error
+        )
+    }
+}
+
+/// Whether a comment comes before the statement it is attached to or after it, see `Comment`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommentPlacement {
+    Leading,
+    Trailing,
+}
+
+/// A comment collected by `parse_lexer_with_comments`, along with the statement it most likely
+/// describes.
+///
+/// A comment is a trailing comment of a statement when it starts on the same line as the token
+/// before it, such as `x = 1 -- note` or `if x then -- note`, and is attached to the innermost
+/// statement containing that token.  Otherwise, it is a leading comment of the statement that
+/// starts right after it (comments in between are allowed).  A comment that is followed by
+/// something other than a statement, such as one at the end of a block, is a trailing comment of
+/// the last statement that ends before it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment<S> {
+    /// The text of the comment, as in `Token::Comment`.
+    pub text: S,
+    pub span: Span,
+    /// The span of the `Spanned` statement (or return statement) the comment is attached to.  None
+    /// only if no statement starts after the comment or ends before it.
+    pub statement: Option<Span>,
+    pub placement: CommentPlacement,
+}
+
 pub fn parse_chunk<R, S, CS>(source: R, create_string: CS) -> Result<Chunk<S>, ParserError>
 where
     R: Read,
     S: fmt::Debug + PartialEq,
     CS: FnMut(&[u8]) -> S,
 {
-    Parser {
-        lexer: Lexer::new(source, create_string),
-        read_buffer: Vec::new(),
-        recursion_guard: Rc::new(()),
-    }
-    .parse_chunk()
+    parse_lexer(Lexer::new(source, create_string))
 }
 
-struct Parser<R, S, CS> {
-    lexer: Lexer<R, CS>,
-    read_buffer: Vec<Token<S>>,
-    recursion_guard: Rc<()>,
+/// The same as `parse_chunk`, but reads the chunk from a `Lexer` that has already been configured,
+/// for example with `Lexer::set_limits` for untrusted sources.  Tokens that were peeked at before
+/// are not lost.  The lexer must not produce comment tokens, which the parser does not accept.
+pub fn parse_lexer<R, S, CS>(mut lexer: Lexer<R, S, CS>) -> Result<Chunk<S>, ParserError>
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let chunk_name = lexer.chunk_name().cloned();
+    let mut parser = Parser::new(move || lexer.read_spanned_token());
+    parser.chunk_name = chunk_name;
+    parser.parse_chunk()
 }
 
-impl<R, S, CS> Parser<R, S, CS>
+/// The same as `parse_lexer`, but with the given limits rather than the defaults.
+pub fn parse_lexer_with_limits<R, S, CS>(
+    mut lexer: Lexer<R, S, CS>,
+    limits: ParserLimits,
+) -> Result<Chunk<S>, ParserError>
 where
     R: Read,
     S: fmt::Debug + PartialEq,
     CS: FnMut(&[u8]) -> S,
 {
+    let chunk_name = lexer.chunk_name().cloned();
+    let mut parser = Parser::new(move || lexer.read_spanned_token());
+    parser.max_depth = limits.max_depth;
+    parser.chunk_name = chunk_name;
+    parser.parse_chunk()
+}
+
+/// The same as `parse_lexer`, but locates the error on failure.
+pub fn parse_lexer_located<R, S, CS>(mut lexer: Lexer<R, S, CS>) -> Result<Chunk<S>, SyntaxError>
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let chunk_name = lexer.chunk_name().cloned();
+    let mut parser = Parser::new(|| lexer.read_spanned_token());
+    parser.chunk_name = chunk_name.clone();
+    let error = match parser.parse_chunk() {
+        Ok(chunk) => return Ok(chunk),
+        Err(error) => error,
+    };
+    let last_read = parser.last_read;
+    drop(parser);
+
+    let span = match (&error, last_read) {
+        (ParserError::LexerError(_), _) | (_, None) => Span {
+            line_number: LineNumber(lexer.line_number() + 1),
+            column: lexer.column() + 1,
+            start: lexer.byte_offset(),
+            end: lexer.byte_offset(),
+        },
+        (_, Some(span)) => span,
+    };
+    Err(SyntaxError {
+        span,
+        error,
+        chunk_name,
+    })
+}
+
+/// The same as `parse_lexer`, but accepts a lexer created with `Lexer::with_comments`, and returns
+/// every comment in the source attached to the statement it most likely describes (see `Comment`),
+/// in source order.  This is what documentation tools and formatters that keep comments need.
+pub fn parse_lexer_with_comments<R, S, CS>(
+    mut lexer: Lexer<R, S, CS>,
+) -> Result<(Chunk<S>, Vec<Comment<S>>), ParserError>
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let chunk_name = lexer.chunk_name().cloned();
+    let mut parser = Parser::new(move || lexer.read_spanned_token());
+    parser.trivia = Some(Vec::new());
+    parser.chunk_name = chunk_name;
+    let chunk = parser.parse_chunk()?;
+    let trivia = parser.trivia.take().unwrap_or_default();
+    let comments = attach_comments(&chunk, trivia);
+    Ok((chunk, comments))
+}
+
+/// The same as `parse_lexer_located`, but rather than stopping at the first syntax error, records
+/// it, skips ahead to the next token that can begin or end a statement (such as `local`,
+/// `function` or `end`) and carries on.  Returns whatever statements could be parsed along with
+/// every error found, in source order, which is what editors and linters want to show.
+///
+/// Errors in the source found by the lexer are recovered from as in `Lexer::set_error_recovery`.
+/// Those the lexer cannot recover from, such as exceeding its limits, end the chunk.  The returned
+/// chunk is only meaningful when there are no errors, and should not be compiled otherwise.
+pub fn parse_lexer_recovering<R, S, CS>(mut lexer: Lexer<R, S, CS>) -> (Chunk<S>, Vec<SyntaxError>)
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    lexer.set_error_recovery(true);
+    let chunk_name = lexer.chunk_name().cloned();
+    let mut parser = Parser::new(|| lexer.read_spanned_token());
+    parser.recover = true;
+    parser.chunk_name = chunk_name.clone();
+    let chunk = parser.parse_chunk_recovering();
+    let mut errors = mem::take(&mut parser.errors);
+    drop(parser);
+
+    errors.extend(
+        lexer
+            .take_errors()
+            .into_iter()
+            .map(|(error, span)| SyntaxError {
+                span,
+                error: ParserError::LexerError(error),
+                chunk_name: chunk_name.clone(),
+            }),
+    );
+    errors.sort_by_key(|e| e.span.start);
+    (chunk, errors)
+}
+
+/// The same as `parse_chunk`, but accepts and discards Luau-style type annotations, so that sources
+/// written for gradually typed tooling can be run unchanged.  Annotations are accepted on local
+/// variables (`local x: number`), loop variables, function parameters (`function(a: T, ...: U)`),
+/// function return types (`function(): (number, string)`) and as casts (`value :: T`).  Functions
+/// may declare generic parameters, as in `function id<T>(x: T): T`.
+///
+/// The types themselves are only checked for syntax: names (`number`, `Map<K, V>`,
+/// `module.Type`), string and boolean singletons, `nil`, tables (`{T}`, `{[K]: V, name: T}`),
+/// functions (`(A, B) -> R`), optionals (`T?`), unions (`A | B`) and intersections (`A & B`).
+/// Type declarations (`type T = ...`) are not supported.
+///
+/// Since `::` begins a cast after an expression, a label directly following an expression
+/// statement that ends in a value, such as `local x = y ::top::`, needs a `;` before it.
+pub fn parse_typed_chunk<R, S, CS>(source: R, create_string: CS) -> Result<Chunk<S>, ParserError>
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let mut lexer = Lexer::new(source, create_string);
+    lexer.set_type_annotations(true);
+    let mut parser = Parser::new(move || lexer.read_spanned_token());
+    parser.type_annotations = true;
+    parser.parse_chunk()
+}
+
+/// Parses a line entered at a REPL the way the standalone `lua` interpreter does.  The line is
+/// first parsed as a list of expressions, which becomes the `return` statement of the chunk so
+/// that the REPL can print their values.  If that fails, the line is parsed as an ordinary chunk,
+/// and any error from that is the one returned, so `ParserError::is_incomplete` can still decide
+/// whether to read a continuation line.
+pub fn parse_repl_line<S, CS>(line: &[u8], mut create_string: CS) -> Result<Chunk<S>, ParserError>
+where
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let mut lexer = Lexer::new(line, &mut create_string);
+    if let Ok(chunk) = Parser::new(|| lexer.read_spanned_token()).parse_expression_chunk() {
+        return Ok(chunk);
+    }
+    parse_chunk(line, create_string)
+}
+
+/// Parses a chunk from an already lexed stream of tokens, such as one produced by
+/// `Lexer::read_spanned_token` and then rewritten by a preprocessor.  The spans are only used to
+/// locate statements and need not match any real source.
+pub fn parse_tokens<I, S>(tokens: I) -> Result<Chunk<S>, ParserError>
+where
+    I: IntoIterator<Item = (Token<S>, Span)>,
+    S: fmt::Debug + PartialEq,
+{
+    let mut tokens = tokens.into_iter();
+    Parser::new(move || Ok(tokens.next())).parse_chunk()
+}
+
+/// A parser for chunks that arrive in pieces, such as the lines typed at an interactive shell or a
+/// very large chunk streamed from a socket, which yields each top-level statement as soon as it is
+/// known to be complete, so it can be compiled and run before the rest of the chunk has arrived.
+///
+/// Input is pushed with `fill` and ended with `finish`, and statements are pulled with
+/// `next_statement`.  A statement is only yielded once more input can no longer continue it, which
+/// for statements such as `x = f` (that may become `x = f(1)`) means once the token after it has
+/// arrived or the input has finished.
+pub struct StatementStream<S, CS> {
+    lexer: Lexer<PushInput, S, CS>,
+    finished: bool,
+    // Tokens read from the lexer that are not part of a yielded statement yet.
+    tokens: Vec<(Token<S>, Span)>,
+    // The number of blocks and brackets left open by `tokens`, and the fewest that were open after
+    // any of them.  While that is above zero, the first statement cannot have ended.
+    depth: i64,
+    shallowest: i64,
+    // The number of tokens there were when the last attempt to parse a statement ran out of them.
+    attempted: Option<usize>,
+    // The names of the top-level labels yielded so far, to find duplicates.
+    labels: Vec<S>,
+    returned: bool,
+    ended: bool,
+}
+
+/// The result of `StatementStream::next_statement`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum StreamedStatement<S> {
+    Statement(Box<Spanned<Statement<S>>>),
+    /// The return statement of the chunk, which must be the last thing in it.
+    Return(Spanned<ReturnStatement<S>>),
+    /// No statement is complete yet, but more input could complete one.
+    Incomplete,
+    /// The input has finished and every statement has been yielded.
+    End,
+}
+
+impl<S, CS> StatementStream<S, CS>
+where
+    S: fmt::Debug + PartialEq + Clone,
+    CS: FnMut(&[u8]) -> S,
+{
+    pub fn new(create_string: CS) -> StatementStream<S, CS> {
+        StatementStream::from_lexer(Lexer::new(PushInput::new(), create_string))
+    }
+
+    /// Parses the input of a lexer that has already been configured, for example with
+    /// `Lexer::set_limits`.  The lexer must not produce comment tokens.
+    pub fn from_lexer(lexer: Lexer<PushInput, S, CS>) -> StatementStream<S, CS> {
+        StatementStream {
+            lexer,
+            finished: false,
+            tokens: Vec::new(),
+            depth: 0,
+            shallowest: i64::MAX,
+            attempted: None,
+            labels: Vec::new(),
+            returned: false,
+            ended: false,
+        }
+    }
+
+    /// Appends bytes to the end of the input.
+    pub fn fill(&mut self, bytes: &[u8]) {
+        self.lexer.fill(bytes);
+    }
+
+    /// Marks the end of the input, after which whatever has not been parsed yet must be complete.
+    pub fn finish(&mut self) {
+        self.lexer.finish();
+        self.finished = true;
+    }
+
+    /// Parses the next top-level statement, if enough input has arrived to complete it.
+    ///
+    /// Once an error is returned, or `StreamedStatement::End`, the stream yields nothing but
+    /// `StreamedStatement::End`.  Errors that more input could fix are not returned until the
+    /// input has finished, and are `Incomplete` until then.
+    pub fn next_statement(&mut self) -> Result<StreamedStatement<S>, SyntaxError> {
+        if self.ended {
+            return Ok(StreamedStatement::End);
+        }
+        let result = self.parse_next();
+        if !matches!(result, Ok(StreamedStatement::Incomplete)) {
+            self.ended = matches!(result, Err(_) | Ok(StreamedStatement::End));
+        }
+        result.map_err(|(error, span)| SyntaxError {
+            span,
+            error,
+            chunk_name: self.lexer.chunk_name().cloned(),
+        })
+    }
+
+    // Errors are returned along with their span, and located in `next_statement`.
+    fn parse_next(&mut self) -> Result<StreamedStatement<S>, (ParserError, Span)> {
+        loop {
+            match self.lexer.read_spanned_token() {
+                Ok(Some(token)) => {
+                    self.depth += nesting(&token.0);
+                    self.shallowest = self.shallowest.min(self.depth);
+                    self.tokens.push(token);
+                }
+                Ok(None) | Err(LexerError::WouldBlock) => break,
+                Err(error) => return Err((ParserError::LexerError(error), self.lexer_span())),
+            }
+        }
+
+        if !self.finished && (self.shallowest > 0 || self.attempted == Some(self.tokens.len())) {
+            return Ok(StreamedStatement::Incomplete);
+        }
+
+        if self.returned {
+            return match self.tokens.first() {
+                Some(&(_, span)) => Err((ParserError::EndOfStream { expected: None }, span)),
+                None => Ok(StreamedStatement::End),
+            };
+        }
+
+        let mut pulled = 0;
+        let mut exhausted = false;
+        let tokens = &self.tokens;
+        let mut parser = Parser::new(|| {
+            Ok(match tokens.get(pulled) {
+                Some(token) => {
+                    pulled += 1;
+                    Some(token.clone())
+                }
+                None => {
+                    exhausted = true;
+                    None
+                }
+            })
+        });
+        let mut statements = Vec::new();
+        let mut return_statement = None;
+        let result = loop {
+            match parser.parse_block_statement(&mut statements, &mut return_statement) {
+                // A `;` on its own, which is not a statement.
+                Ok(true) if statements.is_empty() => {}
+                result => break result,
+            }
+        };
+        let last_read = parser.last_read;
+        let unread = parser.read_buffer.len();
+        let next = parser.read_buffer.first().map(|&(_, span)| span);
+        drop(parser);
+
+        if exhausted && !self.finished {
+            self.attempted = Some(self.tokens.len());
+            return Ok(StreamedStatement::Incomplete);
+        }
+        let locate = |error| {
+            let span = last_read.unwrap_or(Span {
+                line_number: LineNumber(1),
+                column: 1,
+                start: 0,
+                end: 0,
+            });
+            (error, span)
+        };
+        result.map_err(locate)?;
+
+        let streamed = if let Some(statement) = statements.pop() {
+            if let Statement::Label(label) = &statement.node {
+                if self.labels.contains(&label.name) {
+                    return Err(locate(ParserError::DuplicateLabel));
+                }
+                self.labels.push(label.name.clone());
+            }
+            StreamedStatement::Statement(Box::new(statement))
+        } else if let Some(statement) = return_statement {
+            self.returned = true;
+            StreamedStatement::Return(statement)
+        } else if let Some(span) = next {
+            // A stray `end`, `else`, `elseif` or `until`.
+            return Err((ParserError::EndOfStream { expected: None }, span));
+        } else {
+            return Ok(StreamedStatement::End);
+        };
+
+        self.tokens.drain(..pulled - unread);
+        self.attempted = None;
+        self.depth = 0;
+        self.shallowest = i64::MAX;
+        for (token, _) in &self.tokens {
+            self.depth += nesting(token);
+            self.shallowest = self.shallowest.min(self.depth);
+        }
+        Ok(streamed)
+    }
+
+    fn lexer_span(&self) -> Span {
+        Span {
+            line_number: LineNumber(self.lexer.line_number() + 1),
+            column: self.lexer.column() + 1,
+            start: self.lexer.byte_offset(),
+            end: self.lexer.byte_offset(),
+        }
+    }
+}
+
+// How a token changes the number of open blocks and brackets.
+fn nesting<S>(token: &Token<S>) -> i64 {
+    match token {
+        Token::Function
+        | Token::If
+        | Token::Do
+        | Token::Repeat
+        | Token::LeftParen
+        | Token::LeftBracket
+        | Token::LeftBrace => 1,
+        Token::End | Token::Until | Token::RightParen | Token::RightBracket | Token::RightBrace => {
+            -1
+        }
+        _ => 0,
+    }
+}
+
+struct Parser<S, T> {
+    // Returns the next token, or None at the end of the stream.
+    tokens: T,
+    read_buffer: Vec<(Token<S>, Span)>,
+    // The end of the span of the last token consumed.
+    last_end: usize,
+    // The span of the last token read from `tokens`, consumed or not.
+    last_read: Option<Span>,
+    recursion_guard: Rc<()>,
+    // The most recursion guards that may be live at once, see `ParserLimits::max_depth`.
+    max_depth: usize,
+    // Whether to accept (and discard) type annotations, see `parse_typed_chunk`.
+    type_annotations: bool,
+    // Whether to record errors in statements and skip past them, see `parse_lexer_recovering`.
+    recover: bool,
+    errors: Vec<SyntaxError>,
+    // Set when recovering from an error is impossible, after which the stream appears to end.
+    fatal: bool,
+    // The number of tokens consumed so far, to tell whether recovering made any progress.
+    consumed: usize,
+    // Comments read so far, if collecting them for `parse_lexer_with_comments`.
+    trivia: Option<Vec<Trivia<S>>>,
+    // The span of the last token read from `tokens` that was not a comment.
+    last_token: Option<Span>,
+    chunk_name: Option<ChunkName>,
+}
+
+// A comment read by the parser, along with the tokens around it.
+struct Trivia<S> {
+    text: S,
+    span: Span,
+    // The span of the token before the comment.
+    previous: Option<Span>,
+    // The start of the token after the comment, set once that token is read.
+    next_start: Option<usize>,
+}
+
+impl<S, T> Parser<S, T>
+where
+    S: fmt::Debug + PartialEq,
+    T: FnMut() -> Result<Option<(Token<S>, Span)>, LexerError>,
+{
+    fn new(tokens: T) -> Parser<S, T> {
+        Parser {
+            tokens,
+            read_buffer: Vec::new(),
+            last_end: 0,
+            last_read: None,
+            recursion_guard: Rc::new(()),
+            max_depth: ParserLimits::default().max_depth,
+            type_annotations: false,
+            recover: false,
+            errors: Vec::new(),
+            fatal: false,
+            consumed: 0,
+            trivia: None,
+            last_token: None,
+            chunk_name: None,
+        }
+    }
+
     fn parse_chunk(&mut self) -> Result<Chunk<S>, ParserError> {
         let block = self.parse_block()?;
         if self.look_ahead(0)? != None {
             Err(ParserError::EndOfStream { expected: None })
         } else {
-            Ok(Chunk { block })
+            Ok(Chunk {
+                block,
+                chunk_name: self.chunk_name.clone(),
+            })
+        }
+    }
+
+    // Parses the whole stream as a list of expressions, which are returned by the chunk.
+    fn parse_expression_chunk(&mut self) -> Result<Chunk<S>, ParserError> {
+        let start = self.next_span()?;
+        let returns = self.parse_expression_list()?;
+        let span = self.span_from(start);
+        if self.look_ahead(0)?.is_some() {
+            Err(ParserError::EndOfStream { expected: None })
+        } else {
+            Ok(Chunk {
+                block: Block {
+                    statements: Vec::new(),
+                    return_statement: Some(Spanned::new(span, ReturnStatement { returns })),
+                },
+                chunk_name: self.chunk_name.clone(),
+            })
+        }
+    }
+
+    fn parse_chunk_recovering(&mut self) -> Chunk<S> {
+        let mut block = Block {
+            statements: Vec::new(),
+            return_statement: None,
+        };
+        loop {
+            // When recovering, `parse_block` records its errors rather than returning them.
+            if let Ok(mut parsed) = self.parse_block() {
+                block.statements.append(&mut parsed.statements);
+                if parsed.return_statement.is_some() {
+                    block.return_statement = parsed.return_statement;
+                }
+            }
+
+            // Only a stray `end`, `else`, `elseif` or `until` stops a block short of the end.  After
+            // an error, this is most likely left over from the statement that failed, such as the
+            // `end` of an `if` with a bad condition, and is skipped without another report.
+            let unexpected = match self.look_ahead(0) {
+                Ok(Some(token)) => format!("{:?}", token),
+                Ok(None) => break,
+                Err(error) => {
+                    self.record_error(error);
+                    break;
+                }
+            };
+            if self.errors.is_empty() {
+                self.record_error(ParserError::Unexpected {
+                    unexpected,
+                    expected: None,
+                });
+            }
+            self.pop_token();
+        }
+        Chunk {
+            block,
+            chunk_name: self.chunk_name.clone(),
         }
     }
 
@@ -326,19 +953,25 @@ where
         let mut return_statement = None;
 
         loop {
-            match self.look_ahead(0)? {
-                Some(&Token::Else) | Some(&Token::ElseIf) | Some(&Token::End)
-                | Some(&Token::Until) => break,
-                Some(&Token::SemiColon) => {
-                    self.take_next()?;
-                }
-                Some(&Token::Return) => {
-                    return_statement = Some(self.parse_return_statement()?);
+            let consumed = self.consumed;
+            match self.parse_block_statement(&mut statements, &mut return_statement) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) if !self.recover => return Err(error),
+                Err(error @ ParserError::LexerError(_)) => {
+                    self.fatal = true;
+                    self.record_error(error);
                     break;
                 }
-                None => break,
-                _ => {
-                    statements.push(self.parse_statement()?);
+                // Errors that follow from the stream being cut short are not worth reporting.
+                Err(_) if self.fatal => break,
+                Err(error) => {
+                    self.record_error(error);
+                    if let Err(error) = self.synchronize(consumed) {
+                        self.fatal = true;
+                        self.record_error(error);
+                        break;
+                    }
                 }
             }
         }
@@ -349,6 +982,94 @@ where
         })
     }
 
+    // Parses the next statement of a block into `statements` or `return_statement`, returning
+    // false if the block has ended.
+    fn parse_block_statement(
+        &mut self,
+        statements: &mut Vec<Spanned<Statement<S>>>,
+        return_statement: &mut Option<Spanned<ReturnStatement<S>>>,
+    ) -> Result<bool, ParserError> {
+        match self.look_ahead(0)? {
+            Some(&Token::Else) | Some(&Token::ElseIf) | Some(&Token::End) | Some(&Token::Until) => {
+                Ok(false)
+            }
+            Some(&Token::SemiColon) => {
+                self.take_next()?;
+                Ok(true)
+            }
+            Some(&Token::Return) => {
+                let start = self.next_span()?;
+                let statement = self.parse_return_statement()?;
+                *return_statement = Some(Spanned::new(self.span_from(start), statement));
+                Ok(false)
+            }
+            None => Ok(false),
+            _ => {
+                let start = self.next_span()?;
+                let statement = self.parse_statement()?;
+                if let Statement::Label(label) = &statement {
+                    let duplicate = statements
+                        .iter()
+                        .any(|s| matches!(&s.node, Statement::Label(l) if l.name == label.name));
+                    if duplicate {
+                        if !self.recover {
+                            return Err(ParserError::DuplicateLabel);
+                        }
+                        // The label itself parsed fine, so there is nothing to skip past.
+                        self.record_error(ParserError::DuplicateLabel);
+                    }
+                }
+                statements.push(Spanned::new(self.span_from(start), statement));
+                Ok(true)
+            }
+        }
+    }
+
+    // Skips to the next token that can begin or end a statement, skipping at least one token if
+    // none have been consumed since `consumed` so that recovering always makes progress.
+    fn synchronize(&mut self, consumed: usize) -> Result<(), ParserError> {
+        if self.consumed == consumed && self.look_ahead(0)?.is_some() {
+            self.pop_token();
+        }
+        while let Some(token) = self.look_ahead(0)? {
+            match token {
+                Token::Do
+                | Token::End
+                | Token::Else
+                | Token::ElseIf
+                | Token::For
+                | Token::Function
+                | Token::Goto
+                | Token::If
+                | Token::Local
+                | Token::Repeat
+                | Token::Return
+                | Token::Until
+                | Token::While
+                | Token::DoubleColon
+                | Token::SemiColon => break,
+                _ => {
+                    self.pop_token();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_error(&mut self, error: ParserError) {
+        let span = self.last_read.unwrap_or(Span {
+            line_number: LineNumber(1),
+            column: 1,
+            start: 0,
+            end: 0,
+        });
+        self.errors.push(SyntaxError {
+            span,
+            error,
+            chunk_name: self.chunk_name.clone(),
+        });
+    }
+
     fn parse_statement(&mut self) -> Result<Statement<S>, ParserError> {
         let _recursion_guard = self.recursion_guard()?;
 
@@ -443,6 +1164,7 @@ where
     fn parse_for_statement(&mut self) -> Result<ForStatement<S>, ParserError> {
         self.expect_next(Token::For)?;
         let name = self.expect_name()?;
+        self.skip_annotation()?;
 
         match self.get_next()? {
             Token::Assign => {
@@ -476,6 +1198,7 @@ where
                 while self.check_ahead(0, Token::Comma)? {
                     self.take_next()?;
                     names.push(self.expect_name()?);
+                    self.skip_annotation()?;
                 }
                 self.expect_next(Token::In)?;
                 let arguments = self.parse_expression_list()?;
@@ -507,6 +1230,7 @@ where
     }
 
     fn parse_function_statement(&mut self) -> Result<FunctionStatement<S>, ParserError> {
+        let start = self.next_span()?;
         self.expect_next(Token::Function)?;
 
         let name = self.expect_name()?;
@@ -527,7 +1251,7 @@ where
             }
         }
 
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(start)?;
 
         Ok(FunctionStatement {
             name,
@@ -538,10 +1262,11 @@ where
     }
 
     fn parse_local_function_statement(&mut self) -> Result<LocalFunctionStatement<S>, ParserError> {
+        let start = self.next_span()?;
         self.expect_next(Token::Function)?;
 
         let name = self.expect_name()?;
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(start)?;
 
         Ok(LocalFunctionStatement { name, definition })
     }
@@ -550,9 +1275,11 @@ where
         self.expect_next(Token::Local)?;
         let mut names = Vec::new();
         names.push(self.expect_name()?);
+        self.skip_annotation()?;
         while self.check_ahead(0, Token::Comma)? {
             self.take_next()?;
             names.push(self.expect_name()?);
+            self.skip_annotation()?;
         }
 
         let values = if self.check_ahead(0, Token::Assign)? {
@@ -584,7 +1311,7 @@ where
             let mut targets = Vec::new();
             loop {
                 let assignment_target = if let Some(suffix) = suffixed_expression.suffixes.pop() {
-                    match suffix {
+                    match suffix.node {
                         SuffixPart::Field(field_suffix) => {
                             AssignmentTarget::Field(suffixed_expression, field_suffix)
                         }
@@ -593,7 +1320,7 @@ where
                         }
                     }
                 } else {
-                    match suffixed_expression.primary {
+                    match suffixed_expression.primary.node {
                         PrimaryExpression::Name(name) => AssignmentTarget::Name(name),
                         _ => return Err(ParserError::AssignToExpression),
                     }
@@ -616,7 +1343,7 @@ where
                 values,
             }))
         } else if let Some(suffix) = suffixed_expression.suffixes.pop() {
-            match suffix {
+            match suffix.node {
                 SuffixPart::Call(call_suffix) => {
                     Ok(Statement::FunctionCall(FunctionCallStatement {
                         head: suffixed_expression,
@@ -646,12 +1373,20 @@ where
 
     fn parse_sub_expression(&mut self, priority_limit: u8) -> Result<Expression<S>, ParserError> {
         let _recursion_guard = self.recursion_guard()?;
+        let start = self.next_span()?;
 
         let head = if let Some(unary_op) = get_unary_operator(self.get_next()?) {
             self.take_next()?;
             HeadExpression::UnaryOperator(unary_op, self.parse_sub_expression(UNARY_PRIORITY)?)
         } else {
-            HeadExpression::Simple(self.parse_simple_expression()?)
+            let simple = self.parse_simple_expression()?;
+            if self.type_annotations {
+                while self.check_ahead(0, Token::DoubleColon)? {
+                    self.take_next()?;
+                    self.skip_type()?;
+                }
+            }
+            HeadExpression::Simple(simple)
         };
 
         let mut tail = Vec::new();
@@ -667,6 +1402,7 @@ where
         }
 
         Ok(Expression {
+            span: self.span_from(start),
             head: Box::new(head),
             tail,
         })
@@ -701,26 +1437,31 @@ where
             }
             Token::LeftBrace => SimpleExpression::TableConstructor(self.parse_table_constructor()?),
             Token::Function => {
+                let start = self.next_span()?;
                 self.take_next()?;
-                SimpleExpression::Function(self.parse_function_definition()?)
+                SimpleExpression::Function(self.parse_function_definition(start)?)
             }
             _ => SimpleExpression::Suffixed(self.parse_suffixed_expression()?),
         })
     }
 
-    fn parse_primary_expression(&mut self) -> Result<PrimaryExpression<S>, ParserError> {
-        match self.take_next()? {
+    fn parse_primary_expression(&mut self) -> Result<Spanned<PrimaryExpression<S>>, ParserError> {
+        let start = self.next_span()?;
+        let primary = match self.take_next()? {
             Token::LeftParen => {
                 let expr = self.parse_expression()?;
                 self.expect_next(Token::RightParen)?;
-                Ok(PrimaryExpression::GroupedExpression(expr))
+                PrimaryExpression::GroupedExpression(expr)
             }
-            Token::Name(n) => Ok(PrimaryExpression::Name(n)),
-            token => Err(ParserError::Unexpected {
-                unexpected: format!("{:?}", token),
-                expected: Some("grouped expression or name".to_owned()),
-            }),
-        }
+            Token::Name(n) => PrimaryExpression::Name(n),
+            token => {
+                return Err(ParserError::Unexpected {
+                    unexpected: format!("{:?}", token),
+                    expected: Some("grouped expression or name".to_owned()),
+                });
+            }
+        };
+        Ok(Spanned::new(self.span_from(start), primary))
     }
 
     fn parse_field_suffix(&mut self) -> Result<FieldSuffix<S>, ParserError> {
@@ -762,18 +1503,25 @@ where
                 self.expect_next(Token::RightParen)?;
                 args
             }
-            Token::LeftBrace => vec![Expression {
-                head: Box::new(HeadExpression::Simple(SimpleExpression::TableConstructor(
-                    self.parse_table_constructor()?,
-                ))),
-                tail: vec![],
-            }],
-            Token::String(_) => vec![Expression {
-                head: Box::new(HeadExpression::Simple(SimpleExpression::String(
-                    self.expect_string()?,
-                ))),
-                tail: vec![],
-            }],
+            Token::LeftBrace => {
+                let table_constructor = self.parse_table_constructor()?;
+                vec![Expression {
+                    span: table_constructor.span,
+                    head: Box::new(HeadExpression::Simple(SimpleExpression::TableConstructor(
+                        table_constructor,
+                    ))),
+                    tail: vec![],
+                }]
+            }
+            Token::String(_) => {
+                let start = self.next_span()?;
+                let string = self.expect_string()?;
+                vec![Expression {
+                    span: self.span_from(start),
+                    head: Box::new(HeadExpression::Simple(SimpleExpression::String(string))),
+                    tail: vec![],
+                }]
+            }
             token => {
                 return Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -789,17 +1537,21 @@ where
         })
     }
 
-    fn parse_suffix_part(&mut self) -> Result<SuffixPart<S>, ParserError> {
-        match self.get_next()? {
-            Token::Dot | Token::LeftBracket => Ok(SuffixPart::Field(self.parse_field_suffix()?)),
+    fn parse_suffix_part(&mut self) -> Result<Spanned<SuffixPart<S>>, ParserError> {
+        let start = self.next_span()?;
+        let suffix = match self.get_next()? {
+            Token::Dot | Token::LeftBracket => SuffixPart::Field(self.parse_field_suffix()?),
             Token::Colon | Token::LeftParen | Token::LeftBrace | Token::String(_) => {
-                Ok(SuffixPart::Call(self.parse_call_suffix()?))
+                SuffixPart::Call(self.parse_call_suffix()?)
             }
-            token => Err(ParserError::Unexpected {
-                unexpected: format!("{:?}", token),
-                expected: Some("expression suffix".to_owned()),
-            }),
-        }
+            token => {
+                return Err(ParserError::Unexpected {
+                    unexpected: format!("{:?}", token),
+                    expected: Some("expression suffix".to_owned()),
+                });
+            }
+        };
+        Ok(Spanned::new(self.span_from(start), suffix))
     }
 
     fn parse_suffixed_expression(&mut self) -> Result<SuffixedExpression<S>, ParserError> {
@@ -822,7 +1574,15 @@ where
         Ok(SuffixedExpression { primary, suffixes })
     }
 
-    fn parse_function_definition(&mut self) -> Result<FunctionDefinition<S>, ParserError> {
+    // Parses the rest of a function definition, after the `function` keyword at `start` and the name
+    // of a function statement.
+    fn parse_function_definition(
+        &mut self,
+        start: Span,
+    ) -> Result<FunctionDefinition<S>, ParserError> {
+        if self.type_annotations && self.check_ahead(0, Token::LessThan)? {
+            self.skip_generic_parameters()?;
+        }
         self.expect_next(Token::LeftParen)?;
 
         let mut parameters = Vec::new();
@@ -830,9 +1590,13 @@ where
         if !self.check_ahead(0, Token::RightParen)? {
             loop {
                 match self.take_next()? {
-                    Token::Name(name) => parameters.push(name),
+                    Token::Name(name) => {
+                        parameters.push(name);
+                        self.skip_annotation()?;
+                    }
                     Token::Dots => {
                         has_varargs = true;
+                        self.skip_annotation()?;
                         break;
                     }
                     token => {
@@ -850,11 +1614,13 @@ where
             }
         }
         self.expect_next(Token::RightParen)?;
+        self.skip_annotation()?;
 
         let body = self.parse_block()?;
         self.expect_next(Token::End)?;
 
         Ok(FunctionDefinition {
+            span: self.span_from(start),
             parameters,
             has_varargs,
             body,
@@ -862,6 +1628,7 @@ where
     }
 
     fn parse_table_constructor(&mut self) -> Result<TableConstructor<S>, ParserError> {
+        let start = self.next_span()?;
         self.expect_next(Token::LeftBrace)?;
         let mut fields = Vec::new();
         loop {
@@ -877,7 +1644,10 @@ where
             }
         }
         self.expect_next(Token::RightBrace)?;
-        Ok(TableConstructor { fields })
+        Ok(TableConstructor {
+            span: self.span_from(start),
+            fields,
+        })
     }
 
     fn parse_constructor_field(&mut self) -> Result<ConstructorField<S>, ParserError> {
@@ -904,10 +1674,178 @@ where
         })
     }
 
-    // Error if we have more than MAX_RECURSION guards live, otherwise return a new recursion guard
+    // If type annotations are enabled, skips a `: Type` annotation if there is one.
+    fn skip_annotation(&mut self) -> Result<(), ParserError> {
+        if self.type_annotations && self.check_ahead(0, Token::Colon)? {
+            self.take_next()?;
+            self.skip_type()?;
+        }
+        Ok(())
+    }
+
+    // Skips a type, made of simple types joined by `|` or `&`, each optionally followed by `?`.
+    fn skip_type(&mut self) -> Result<(), ParserError> {
+        let _recursion_guard = self.recursion_guard()?;
+        loop {
+            self.skip_simple_type()?;
+            while self.check_ahead(0, Token::Question)? {
+                self.take_next()?;
+            }
+            match self.look_ahead(0)? {
+                Some(Token::BitOr) | Some(Token::BitAnd) => {
+                    self.take_next()?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn skip_simple_type(&mut self) -> Result<(), ParserError> {
+        match self.take_next()? {
+            Token::Nil | Token::True | Token::False | Token::String(_) => {}
+            Token::Name(_) => {
+                while self.check_ahead(0, Token::Dot)? {
+                    self.take_next()?;
+                    self.expect_name()?;
+                }
+                if self.check_ahead(0, Token::LessThan)? {
+                    self.take_next()?;
+                    self.skip_type_list(Token::GreaterThan)?;
+                }
+            }
+            // A variadic type, only meaningful in a parameter or return list.
+            Token::Dots => self.skip_simple_type()?,
+            Token::LeftBrace => {
+                while !self.check_ahead(0, Token::RightBrace)? {
+                    if self.check_ahead(0, Token::LeftBracket)? {
+                        self.take_next()?;
+                        self.skip_type()?;
+                        self.expect_next(Token::RightBracket)?;
+                        self.expect_next(Token::Colon)?;
+                    } else if self.check_named_type()? {
+                        self.take_next()?;
+                        self.take_next()?;
+                    }
+                    self.skip_type()?;
+                    match *self.get_next()? {
+                        Token::Comma | Token::SemiColon => {
+                            self.take_next()?;
+                        }
+                        _ => break,
+                    }
+                }
+                self.expect_next(Token::RightBrace)?;
+            }
+            // Either a parenthesized type, or the parameters of a function type.
+            Token::LeftParen => {
+                self.skip_type_list(Token::RightParen)?;
+                self.skip_return_type()?;
+            }
+            // The generic parameters of a function type.
+            Token::LessThan => {
+                self.skip_type_list(Token::GreaterThan)?;
+                self.expect_next(Token::LeftParen)?;
+                self.skip_type_list(Token::RightParen)?;
+                self.skip_return_type()?;
+            }
+            token => {
+                return Err(ParserError::Unexpected {
+                    unexpected: format!("{:?}", token),
+                    expected: Some("type".to_owned()),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Skips the `-> Type` of a function type, if present.
+    fn skip_return_type(&mut self) -> Result<(), ParserError> {
+        if self.check_ahead(0, Token::Minus)? && self.check_ahead(1, Token::GreaterThan)? {
+            self.take_next()?;
+            self.take_next()?;
+            self.skip_type()?;
+        }
+        Ok(())
+    }
+
+    // Skips a comma separated list of types, which may be named as in `(name: T)`, up to and
+    // including the given closing token.
+    fn skip_type_list(&mut self, close: Token<S>) -> Result<(), ParserError> {
+        if !self.check_type_close(&close)? {
+            loop {
+                if self.check_named_type()? {
+                    self.take_next()?;
+                    self.take_next()?;
+                }
+                self.skip_type()?;
+                if self.check_ahead(0, Token::Comma)? {
+                    self.take_next()?;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.check_type_close(&close)?;
+        self.expect_next(close)
+    }
+
+    // Returns whether the next tokens are the `name:` before the type of a table field or of a
+    // function type parameter.
+    fn check_named_type(&mut self) -> Result<bool, ParserError> {
+        Ok(matches!(self.look_ahead(0)?, Some(Token::Name(_)))
+            && self.check_ahead(1, Token::Colon)?)
+    }
+
+    // Returns whether the next token is the given closing token.  When closing a generic type list,
+    // a `>>` or `>=` token is split so that `Map<K, Array<V>>` can be parsed.
+    fn check_type_close(&mut self, close: &Token<S>) -> Result<bool, ParserError> {
+        if *close == Token::GreaterThan {
+            self.read_ahead(1)?;
+            if let Some((token, span)) = self.read_buffer.get_mut(0) {
+                let rest = match token {
+                    Token::ShiftRight => Token::GreaterThan,
+                    Token::GreaterEqual => Token::Assign,
+                    _ => return Ok(*token == Token::GreaterThan),
+                };
+                *token = Token::GreaterThan;
+                let rest_span = Span {
+                    column: span.column + 1,
+                    start: span.start + 1,
+                    ..*span
+                };
+                span.end = span.start + 1;
+                self.read_buffer.insert(1, (rest, rest_span));
+                return Ok(true);
+            }
+            Ok(false)
+        } else {
+            Ok(self.look_ahead(0)? == Some(close))
+        }
+    }
+
+    // Skips the generic parameters of a function definition, such as `<T, U...>`.
+    fn skip_generic_parameters(&mut self) -> Result<(), ParserError> {
+        self.expect_next(Token::LessThan)?;
+        loop {
+            self.expect_name()?;
+            if self.check_ahead(0, Token::Dots)? {
+                self.take_next()?;
+            }
+            if self.check_ahead(0, Token::Comma)? {
+                self.take_next()?;
+            } else {
+                break;
+            }
+        }
+        self.check_type_close(&Token::GreaterThan)?;
+        self.expect_next(Token::GreaterThan)
+    }
+
+    // Error if we have more than `max_depth` guards live, otherwise return a new recursion guard
     // (a recursion guard is just an Rc used solely for its live count).
     fn recursion_guard(&self) -> Result<Rc<()>, ParserError> {
-        if Rc::strong_count(&self.recursion_guard) < MAX_RECURSION {
+        // The parser itself holds one reference.
+        if Rc::strong_count(&self.recursion_guard) <= self.max_depth {
             Ok(self.recursion_guard.clone())
         } else {
             Err(ParserError::RecursionLimit)
@@ -917,7 +1855,7 @@ where
     // Return a reference to the next token in the stream, erroring if we are at the end.
     fn get_next(&mut self) -> Result<&Token<S>, ParserError> {
         self.read_ahead(1)?;
-        if let Some(token) = self.read_buffer.get(0) {
+        if let Some((token, _)) = self.read_buffer.get(0) {
             Ok(token)
         } else {
             Err(ParserError::EndOfStream { expected: None })
@@ -932,7 +1870,7 @@ where
                 expected: Some(format!("{:?}", token)),
             })
         } else {
-            let next_token = self.read_buffer.remove(0);
+            let (next_token, _) = self.pop_token();
             if next_token == token {
                 Ok(())
             } else {
@@ -952,7 +1890,7 @@ where
                 expected: Some("name".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.pop_token().0 {
                 Token::Name(name) => Ok(name),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -970,7 +1908,7 @@ where
                 expected: Some("string".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.pop_token().0 {
                 Token::String(string) => Ok(string),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -986,51 +1924,165 @@ where
         if self.read_buffer.is_empty() {
             Err(ParserError::EndOfStream { expected: None })
         } else {
-            Ok(self.read_buffer.remove(0))
+            Ok(self.pop_token().0)
         }
     }
 
     // Return the nth token ahead in the stream, if it is not past the end.
     fn look_ahead(&mut self, n: usize) -> Result<Option<&Token<S>>, ParserError> {
         self.read_ahead(n + 1)?;
-        Ok(self.read_buffer.get(n))
+        Ok(self.read_buffer.get(n).map(|(t, _)| t))
     }
 
     // Return true if the nth token ahead in the stream matches the given token.  If this would read
     // past the end of the stream, this will simply return false.
     fn check_ahead(&mut self, n: usize, token: Token<S>) -> Result<bool, ParserError> {
-        self.read_ahead(n)?;
-        Ok(if let Some(t) = self.read_buffer.get(n) {
+        self.read_ahead(n + 1)?;
+        Ok(if let Some((t, _)) = self.read_buffer.get(n) {
             *t == token
         } else {
             false
         })
     }
 
+    // Return the location of the next token in the stream, erroring if we are at the end.
+    fn next_span(&mut self) -> Result<Span, ParserError> {
+        self.read_ahead(1)?;
+        if let Some((_, span)) = self.read_buffer.get(0) {
+            Ok(*span)
+        } else {
+            Err(ParserError::EndOfStream { expected: None })
+        }
+    }
+
+    // Return the span from the start of the given span to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.last_end,
+            ..start
+        }
+    }
+
+    // Remove the next token from the read buffer, which must not be empty.
+    fn pop_token(&mut self) -> (Token<S>, Span) {
+        let (token, span) = self.read_buffer.remove(0);
+        self.last_end = span.end;
+        self.consumed += 1;
+        (token, span)
+    }
+
     // Read at least `n` tokens ahead in the stream, filling the read buffer up to size `n` (if
     // possible).
     fn read_ahead(&mut self, n: usize) -> Result<(), ParserError> {
-        while self.read_buffer.len() <= n {
-            if let Some(token) = self.lexer.read_token().map_err(ParserError::LexerError)? {
-                self.read_buffer.push(token);
-            } else {
-                break;
+        while self.read_buffer.len() < n && !self.fatal {
+            match (self.tokens)().map_err(ParserError::LexerError)? {
+                Some((Token::Comment(text), span)) if self.trivia.is_some() => {
+                    self.last_read = Some(span);
+                    let previous = self.last_token;
+                    self.trivia.as_mut().unwrap().push(Trivia {
+                        text,
+                        span,
+                        previous,
+                        next_start: None,
+                    });
+                }
+                Some(token) => {
+                    self.last_read = Some(token.1);
+                    self.last_token = Some(token.1);
+                    if let Some(trivia) = &mut self.trivia {
+                        for comment in trivia.iter_mut().rev() {
+                            if comment.next_start.is_some() {
+                                break;
+                            }
+                            comment.next_start = Some(token.1.start);
+                        }
+                    }
+                    self.read_buffer.push(token);
+                }
+                None => break,
             }
         }
         Ok(())
     }
 }
 
-const MAX_RECURSION: usize = 200;
+// Attaches each comment to a statement of the chunk, following the rules described on `Comment`.
+fn attach_comments<S>(chunk: &Chunk<S>, trivia: Vec<Trivia<S>>) -> Vec<Comment<S>> {
+    struct Statements(Vec<Span>);
+
+    impl<S> Visitor<S> for Statements {
+        fn visit_statement(&mut self, statement: &Spanned<Statement<S>>) {
+            self.0.push(statement.span);
+            walk_statement(self, statement);
+        }
+
+        fn visit_return_statement(&mut self, statement: &Spanned<ReturnStatement<S>>) {
+            self.0.push(statement.span);
+            walk_return_statement(self, statement);
+        }
+    }
+
+    let mut statements = Statements(Vec::new());
+    statements.visit_chunk(chunk);
+    let statements = statements.0;
+
+    // The innermost statement containing the given offset, which is the one starting last.
+    let containing = |offset: usize| {
+        statements
+            .iter()
+            .filter(|s| s.start < offset && offset <= s.end)
+            .max_by_key(|s| s.start)
+            .copied()
+    };
+    // The statement ending last before the given offset, the outermost of any that end together.
+    let preceding = |offset: usize| {
+        statements
+            .iter()
+            .filter(|s| s.end <= offset)
+            .max_by_key(|s| (s.end, usize::MAX - s.start))
+            .copied()
+    };
+
+    trivia
+        .into_iter()
+        .map(|comment| {
+            let same_line = comment
+                .previous
+                .filter(|previous| previous.line_number == comment.span.line_number);
+            let following = comment
+                .next_start
+                .and_then(|next| statements.iter().find(|s| s.start == next).copied());
+
+            let (statement, placement) = match (same_line, following) {
+                (Some(previous), _) => (
+                    containing(previous.end).or_else(|| preceding(comment.span.start)),
+                    CommentPlacement::Trailing,
+                ),
+                (None, Some(following)) => (Some(following), CommentPlacement::Leading),
+                (None, None) => (preceding(comment.span.start), CommentPlacement::Trailing),
+            };
+            Comment {
+                text: comment.text,
+                span: comment.span,
+                statement,
+                placement,
+            }
+        })
+        .collect()
+}
 
 // Priority lower than any unary or binary operator.
 const MIN_PRIORITY: u8 = 0;
 
 // Priority of all unary operators.
-const UNARY_PRIORITY: u8 = 12;
-
-// Returns the left and right priority of the given binary operator.
-fn binary_priority(operator: BinaryOperator) -> (u8, u8) {
+pub(crate) const UNARY_PRIORITY: u8 = 12;
+
+// Returns the left and right priority of the given binary operator.  From lowest to highest, the
+// priorities are those of the reference manual: `or`, `and`, comparisons, `|`, `~`, `&`, shifts,
+// `..`, `+` and `-`, `*`, `/`, `//` and `%`, unary operators, then `^`.  Right-associative
+// operators (`..` and `^`) have a lower right priority than left priority, so that
+// `parse_sub_expression` continues the right operand through another use of the same operator.
+pub(crate) fn binary_priority(operator: BinaryOperator) -> (u8, u8) {
     match operator {
         BinaryOperator::Add => (10, 10),
         BinaryOperator::Sub => (10, 10),
@@ -1045,7 +2097,7 @@ fn binary_priority(operator: BinaryOperator) -> (u8, u8) {
         BinaryOperator::ShiftLeft => (7, 7),
         BinaryOperator::ShiftRight => (7, 7),
         BinaryOperator::Concat => (9, 8),
-        BinaryOperator::NotEqual => (10, 10),
+        BinaryOperator::NotEqual => (3, 3),
         BinaryOperator::Equal => (3, 3),
         BinaryOperator::LessThan => (3, 3),
         BinaryOperator::LessEqual => (3, 3),
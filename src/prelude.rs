@@ -0,0 +1,18 @@
+//! The types and traits needed by most code embedding luster, for glob importing with
+//! `use luster::prelude::*;`.
+//!
+//! Lua strings are exported as `LuaString` so that they do not shadow `std::string::String`.
+//! Rust values convert into a `Value` through its `From` impls and back through `Value::to_bool`,
+//! `Value::to_integer` and `Value::to_number`, or into an `OwnedValue` that can outlive the arena.
+//! The `gc_arena` and `gc_sequence` items are the ones that appear in the signatures of
+//! `Lua::mutate`, `Lua::sequence` and `Callback`, with the sequence constructors such as
+//! `sequence::from_fn_with` under `sequence`.
+
+pub use gc_arena::{Collect, Gc, GcCell, MutationContext};
+pub use gc_sequence::{self as sequence, Sequence, SequenceExt, SequenceResultExt};
+
+pub use crate::{
+    compile, CallResult, CallSequence, Callback, CallbackResult, CallbackReturn, Closure, Error,
+    Function, Lua, LusterBuilder, OwnedValue, Root, RuntimeError, StaticError, StdLib,
+    String as LuaString, Table, Thread, ThreadSequence, TypeError, Value,
+};
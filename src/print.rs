@@ -0,0 +1,623 @@
+//! Printing of the AST produced by the `parser` module back into Lua source.
+//!
+//! Printing a parsed chunk gives source that parses back into the same AST (apart from spans), so
+//! that a chunk can be parsed, rewritten with a `VisitorMut` and printed again.  Comments and the original formatting
+//! are not kept: every statement is printed on its own line, indented by its nesting.  Parentheses
+//! are only printed where the AST has a grouped expression, or where they are needed to keep the
+//! structure of an expression that was built or rewritten by hand, such as a sum used as an
+//! operand of a product.
+//!
+//! ```
+//! use luster::parse_chunk;
+//! use luster::print::{print_chunk, PrintOptions};
+//!
+//! let chunk = parse_chunk(&b"local t={1,'a'} if #t>1 then print(t[2]) end"[..], |s| {
+//!     s.to_vec()
+//! })
+//! .unwrap();
+//! assert_eq!(
+//!     print_chunk(&chunk, &PrintOptions::default()),
+//!     b"local t = {1, \"a\"}\nif #t > 1 then\n    print(t[2])\nend\n".to_vec()
+//! );
+//! ```
+
+use std::cmp;
+use std::string::String as StdString;
+
+use crate::parser::{
+    binary_priority, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk, ConstructorField,
+    Expression, FieldSuffix, ForStatement, FunctionDefinition, HeadExpression, PrimaryExpression,
+    RecordKey, SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor,
+    UnaryOperator, UNARY_PRIORITY,
+};
+
+/// How string literals are quoted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Quote {
+    /// `"..."`
+    Double,
+    /// `'...'`
+    Single,
+    /// Whichever of the two quotes appears less often in each string, so that fewer need to be
+    /// escaped.  Ties are double quoted.
+    Fewest,
+}
+
+/// Options for `print_chunk` and `print_expression`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintOptions {
+    /// The text printed once for each level of nesting at the start of a line.  Defaults to four
+    /// spaces.
+    pub indent: StdString,
+    /// Defaults to `Quote::Double`.
+    pub quote: Quote,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            indent: "    ".to_owned(),
+            quote: Quote::Double,
+        }
+    }
+}
+
+/// Prints a chunk as Lua source, with each statement on its own line.
+pub fn print_chunk<S: AsRef<[u8]>>(chunk: &Chunk<S>, options: &PrintOptions) -> Vec<u8> {
+    let mut printer = Printer::new(options);
+    printer.block(&chunk.block);
+    printer.out
+}
+
+/// Prints a single expression as Lua source.  Function bodies inside of it are printed on their
+/// own lines, indented as if the expression started a line.
+pub fn print_expression<S: AsRef<[u8]>>(
+    expression: &Expression<S>,
+    options: &PrintOptions,
+) -> Vec<u8> {
+    let mut printer = Printer::new(options);
+    printer.expression(expression);
+    printer.out
+}
+
+// The lowest left and right priorities of the operators in a printed expression which are not
+// inside parentheses.  The left priority decides whether the expression can be the right operand
+// of an operator without being absorbed into it, and the right priority whether the expression
+// would absorb an operator following it as a left operand.  A unary operator has a right priority
+// of `UNARY_PRIORITY`, as its operand continues through a following `^`.
+#[derive(Copy, Clone)]
+struct Shape {
+    left: u8,
+    right: u8,
+}
+
+impl Shape {
+    const ATOM: Shape = Shape {
+        left: u8::MAX,
+        right: u8::MAX,
+    };
+}
+
+struct Printer<'a> {
+    options: &'a PrintOptions,
+    out: Vec<u8>,
+    depth: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(options: &'a PrintOptions) -> Printer<'a> {
+        Printer {
+            options,
+            out: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn start_line(&mut self) {
+        for _ in 0..self.depth {
+            self.out.extend_from_slice(self.options.indent.as_bytes());
+        }
+    }
+
+    fn block<S: AsRef<[u8]>>(&mut self, block: &Block<S>) {
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.start_line();
+            // Without a separator, a statement starting with a parenthesis would continue a call or
+            // expression at the end of the previous statement.
+            if i != 0 && starts_with_parenthesis(&statement.node) {
+                self.write(b";");
+            }
+            self.statement(&statement.node);
+            self.write(b"\n");
+        }
+
+        if let Some(return_statement) = &block.return_statement {
+            self.start_line();
+            self.write(b"return");
+            if !return_statement.node.returns.is_empty() {
+                self.write(b" ");
+                self.expression_list(&return_statement.node.returns);
+            }
+            self.write(b"\n");
+        }
+    }
+
+    // Prints a block nested inside of a statement, leaving the line indented for the keyword that
+    // ends it.
+    fn body<S: AsRef<[u8]>>(&mut self, block: &Block<S>) {
+        self.write(b"\n");
+        self.depth += 1;
+        self.block(block);
+        self.depth -= 1;
+        self.start_line();
+    }
+
+    fn statement<S: AsRef<[u8]>>(&mut self, statement: &Statement<S>) {
+        match statement {
+            Statement::If(if_statement) => {
+                self.write(b"if ");
+                self.expression(&if_statement.if_part.0);
+                self.write(b" then");
+                self.body(&if_statement.if_part.1);
+                for (condition, block) in &if_statement.else_if_parts {
+                    self.write(b"elseif ");
+                    self.expression(condition);
+                    self.write(b" then");
+                    self.body(block);
+                }
+                if let Some(block) = &if_statement.else_part {
+                    self.write(b"else");
+                    self.body(block);
+                }
+                self.write(b"end");
+            }
+            Statement::While(while_statement) => {
+                self.write(b"while ");
+                self.expression(&while_statement.condition);
+                self.write(b" do");
+                self.body(&while_statement.block);
+                self.write(b"end");
+            }
+            Statement::Do(block) => {
+                self.write(b"do");
+                self.body(block);
+                self.write(b"end");
+            }
+            Statement::For(ForStatement::Numeric {
+                name,
+                initial,
+                limit,
+                step,
+                body,
+            }) => {
+                self.write(b"for ");
+                self.write(name.as_ref());
+                self.write(b" = ");
+                self.expression(initial);
+                self.write(b", ");
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.write(b", ");
+                    self.expression(step);
+                }
+                self.write(b" do");
+                self.body(body);
+                self.write(b"end");
+            }
+            Statement::For(ForStatement::Generic {
+                names,
+                arguments,
+                body,
+            }) => {
+                self.write(b"for ");
+                self.name_list(names);
+                self.write(b" in ");
+                self.expression_list(arguments);
+                self.write(b" do");
+                self.body(body);
+                self.write(b"end");
+            }
+            Statement::Repeat(repeat_statement) => {
+                self.write(b"repeat");
+                self.body(&repeat_statement.body);
+                self.write(b"until ");
+                self.expression(&repeat_statement.until);
+            }
+            Statement::Function(function_statement) => {
+                self.write(b"function ");
+                self.write(function_statement.name.as_ref());
+                for field in &function_statement.fields {
+                    self.write(b".");
+                    self.write(field.as_ref());
+                }
+                if let Some(method) = &function_statement.method {
+                    self.write(b":");
+                    self.write(method.as_ref());
+                }
+                self.function_body(&function_statement.definition);
+            }
+            Statement::LocalFunction(local_function) => {
+                self.write(b"local function ");
+                self.write(local_function.name.as_ref());
+                self.function_body(&local_function.definition);
+            }
+            Statement::LocalStatement(local_statement) => {
+                self.write(b"local ");
+                self.name_list(&local_statement.names);
+                if !local_statement.values.is_empty() {
+                    self.write(b" = ");
+                    self.expression_list(&local_statement.values);
+                }
+            }
+            Statement::Label(label_statement) => {
+                self.write(b"::");
+                self.write(label_statement.name.as_ref());
+                self.write(b"::");
+            }
+            Statement::Break => self.write(b"break"),
+            Statement::Goto(goto_statement) => {
+                self.write(b"goto ");
+                self.write(goto_statement.name.as_ref());
+            }
+            Statement::FunctionCall(function_call) => {
+                self.suffixed_expression(&function_call.head);
+                self.call_suffix(&function_call.call);
+            }
+            Statement::Assignment(assignment) => {
+                for (i, target) in assignment.targets.iter().enumerate() {
+                    if i != 0 {
+                        self.write(b", ");
+                    }
+                    match target {
+                        AssignmentTarget::Name(name) => self.write(name.as_ref()),
+                        AssignmentTarget::Field(head, field) => {
+                            self.suffixed_expression(head);
+                            self.field_suffix(field);
+                        }
+                    }
+                }
+                self.write(b" = ");
+                self.expression_list(&assignment.values);
+            }
+        }
+    }
+
+    fn name_list<S: AsRef<[u8]>>(&mut self, names: &[S]) {
+        for (i, name) in names.iter().enumerate() {
+            if i != 0 {
+                self.write(b", ");
+            }
+            self.write(name.as_ref());
+        }
+    }
+
+    fn expression_list<S: AsRef<[u8]>>(&mut self, expressions: &[Expression<S>]) {
+        for (i, expression) in expressions.iter().enumerate() {
+            if i != 0 {
+                self.write(b", ");
+            }
+            self.expression(expression);
+        }
+    }
+
+    // An expression is its head followed by a chain of operators which are applied from left to
+    // right, each to the result so far and its own operand.
+    fn expression<S: AsRef<[u8]>>(&mut self, expression: &Expression<S>) -> Shape {
+        let start = self.out.len();
+        let mut shape = self.head_expression(&expression.head);
+        for (operator, operand) in &expression.tail {
+            let (left_priority, right_priority) = binary_priority(*operator);
+            if shape.right < left_priority {
+                shape = self.parenthesize(start);
+            }
+
+            self.write(b" ");
+            self.write(binary_operator(*operator));
+            self.write(b" ");
+
+            let operand_start = self.out.len();
+            let mut operand_shape = self.expression(operand);
+            if operand_shape.left <= right_priority {
+                operand_shape = self.parenthesize(operand_start);
+            }
+
+            shape = Shape {
+                left: cmp::min(cmp::min(shape.left, operand_shape.left), left_priority),
+                right: cmp::min(cmp::min(shape.right, operand_shape.right), right_priority),
+            };
+        }
+        shape
+    }
+
+    fn parenthesize(&mut self, start: usize) -> Shape {
+        self.out.insert(start, b'(');
+        self.out.push(b')');
+        Shape::ATOM
+    }
+
+    fn head_expression<S: AsRef<[u8]>>(&mut self, head: &HeadExpression<S>) -> Shape {
+        match head {
+            HeadExpression::Simple(simple) => {
+                self.simple_expression(simple);
+                Shape::ATOM
+            }
+            HeadExpression::UnaryOperator(operator, operand) => {
+                self.write(match operator {
+                    UnaryOperator::Not => b"not ",
+                    UnaryOperator::Minus => b"-",
+                    UnaryOperator::BitNot => b"~",
+                    UnaryOperator::Len => b"#",
+                });
+
+                let start = self.out.len();
+                let mut shape = self.expression(operand);
+                if shape.left <= UNARY_PRIORITY {
+                    shape = self.parenthesize(start);
+                }
+                // Two minus signs in a row would start a comment.
+                if *operator == UnaryOperator::Minus && self.out.get(start) == Some(&b'-') {
+                    self.out.insert(start, b' ');
+                }
+
+                Shape {
+                    left: shape.left,
+                    right: cmp::min(shape.right, UNARY_PRIORITY),
+                }
+            }
+        }
+    }
+
+    fn simple_expression<S: AsRef<[u8]>>(&mut self, simple: &SimpleExpression<S>) {
+        match simple {
+            SimpleExpression::Float(f) => self.float(*f),
+            SimpleExpression::Integer(i) => {
+                // Negative literals are never parsed, so they are printed as a negated expression.
+                if *i == i64::MIN {
+                    self.write(b"(-9223372036854775807 - 1)");
+                } else if *i < 0 {
+                    self.write(format!("(-{})", -i).as_bytes());
+                } else {
+                    self.write(i.to_string().as_bytes());
+                }
+            }
+            SimpleExpression::String(s) => self.string(s.as_ref()),
+            SimpleExpression::Nil => self.write(b"nil"),
+            SimpleExpression::True => self.write(b"true"),
+            SimpleExpression::False => self.write(b"false"),
+            SimpleExpression::VarArgs => self.write(b"..."),
+            SimpleExpression::TableConstructor(table) => self.table_constructor(table),
+            SimpleExpression::Function(definition) => {
+                self.write(b"function");
+                self.function_body(definition);
+            }
+            SimpleExpression::Suffixed(suffixed) => self.suffixed_expression(suffixed),
+        }
+    }
+
+    fn float(&mut self, f: f64) {
+        if f.is_nan() {
+            self.write(b"(0 / 0)");
+        } else if f.is_infinite() {
+            self.write(if f > 0.0 { b"1e999" } else { b"(-1e999)" });
+        } else if f.is_sign_negative() {
+            // The `Debug` format is the shortest that reads back as the same float, and always
+            // has a `.` or an exponent so that it is not read as an integer.
+            self.write(format!("(-{:?})", -f).as_bytes());
+        } else {
+            self.write(format!("{:?}", f).as_bytes());
+        }
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        let quote = match self.options.quote {
+            Quote::Double => b'"',
+            Quote::Single => b'\'',
+            Quote::Fewest => {
+                let doubles = s.iter().filter(|&&c| c == b'"').count();
+                let singles = s.iter().filter(|&&c| c == b'\'').count();
+                if singles < doubles {
+                    b'\''
+                } else {
+                    b'"'
+                }
+            }
+        };
+
+        self.out.push(quote);
+        for &c in s {
+            match c {
+                b'\\' => self.write(b"\\\\"),
+                b'\n' => self.write(b"\\n"),
+                b'\r' => self.write(b"\\r"),
+                b'\t' => self.write(b"\\t"),
+                c if c == quote => self.write(&[b'\\', c]),
+                // Always three digits, so that a digit following the escape is not read as part
+                // of it.
+                c if c < 0x20 || c == 0x7f => self.write(format!("\\{:03}", c).as_bytes()),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push(quote);
+    }
+
+    fn table_constructor<S: AsRef<[u8]>>(&mut self, table: &TableConstructor<S>) {
+        self.write(b"{");
+        for (i, field) in table.fields.iter().enumerate() {
+            if i != 0 {
+                self.write(b", ");
+            }
+            match field {
+                ConstructorField::Array(value) => {
+                    self.expression(value);
+                }
+                ConstructorField::Record(key, value) => {
+                    match key {
+                        RecordKey::Named(name) if is_name(name.as_ref()) => {
+                            self.write(name.as_ref())
+                        }
+                        RecordKey::Named(name) => {
+                            self.write(b"[");
+                            self.string(name.as_ref());
+                            self.write(b"]");
+                        }
+                        RecordKey::Indexed(key) => {
+                            self.write(b"[");
+                            self.expression(key);
+                            self.write(b"]");
+                        }
+                    }
+                    self.write(b" = ");
+                    self.expression(value);
+                }
+            }
+        }
+        self.write(b"}");
+    }
+
+    fn function_body<S: AsRef<[u8]>>(&mut self, definition: &FunctionDefinition<S>) {
+        self.write(b"(");
+        self.name_list(&definition.parameters);
+        if definition.has_varargs {
+            if !definition.parameters.is_empty() {
+                self.write(b", ");
+            }
+            self.write(b"...");
+        }
+        self.write(b")");
+        self.body(&definition.body);
+        self.write(b"end");
+    }
+
+    fn suffixed_expression<S: AsRef<[u8]>>(&mut self, suffixed: &SuffixedExpression<S>) {
+        match &suffixed.primary.node {
+            PrimaryExpression::Name(name) => self.write(name.as_ref()),
+            PrimaryExpression::GroupedExpression(expression) => {
+                self.write(b"(");
+                self.expression(expression);
+                self.write(b")");
+            }
+        }
+
+        for suffix in &suffixed.suffixes {
+            match &suffix.node {
+                SuffixPart::Field(field) => self.field_suffix(field),
+                SuffixPart::Call(call) => self.call_suffix(call),
+            }
+        }
+    }
+
+    fn field_suffix<S: AsRef<[u8]>>(&mut self, field: &FieldSuffix<S>) {
+        match field {
+            FieldSuffix::Named(name) if is_name(name.as_ref()) => {
+                self.write(b".");
+                self.write(name.as_ref());
+            }
+            FieldSuffix::Named(name) => {
+                self.write(b"[");
+                self.string(name.as_ref());
+                self.write(b"]");
+            }
+            FieldSuffix::Indexed(key) => {
+                self.write(b"[");
+                self.expression(key);
+                self.write(b"]");
+            }
+        }
+    }
+
+    fn call_suffix<S: AsRef<[u8]>>(&mut self, call: &CallSuffix<S>) {
+        let arguments = match call {
+            CallSuffix::Method(name, arguments) => {
+                self.write(b":");
+                self.write(name.as_ref());
+                arguments
+            }
+            CallSuffix::Function(arguments) => arguments,
+        };
+        self.write(b"(");
+        self.expression_list(arguments);
+        self.write(b")");
+    }
+}
+
+fn starts_with_parenthesis<S>(statement: &Statement<S>) -> bool {
+    let head = match statement {
+        Statement::FunctionCall(function_call) => &function_call.head,
+        Statement::Assignment(assignment) => match assignment.targets.first() {
+            Some(AssignmentTarget::Field(head, _)) => head,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    match head.primary.node {
+        PrimaryExpression::GroupedExpression(_) => true,
+        PrimaryExpression::Name(_) => false,
+    }
+}
+
+fn binary_operator(operator: BinaryOperator) -> &'static [u8] {
+    match operator {
+        BinaryOperator::Add => b"+",
+        BinaryOperator::Sub => b"-",
+        BinaryOperator::Mul => b"*",
+        BinaryOperator::Mod => b"%",
+        BinaryOperator::Pow => b"^",
+        BinaryOperator::Div => b"/",
+        BinaryOperator::IDiv => b"//",
+        BinaryOperator::BitAnd => b"&",
+        BinaryOperator::BitOr => b"|",
+        BinaryOperator::BitXor => b"~",
+        BinaryOperator::ShiftLeft => b"<<",
+        BinaryOperator::ShiftRight => b">>",
+        BinaryOperator::Concat => b"..",
+        BinaryOperator::NotEqual => b"~=",
+        BinaryOperator::Equal => b"==",
+        BinaryOperator::LessThan => b"<",
+        BinaryOperator::LessEqual => b"<=",
+        BinaryOperator::GreaterThan => b">",
+        BinaryOperator::GreaterEqual => b">=",
+        BinaryOperator::And => b"and",
+        BinaryOperator::Or => b"or",
+    }
+}
+
+// Whether a field or key name can be written as a name rather than as a string in brackets.
+fn is_name(name: &[u8]) -> bool {
+    const KEYWORDS: &[&[u8]] = &[
+        b"and",
+        b"break",
+        b"do",
+        b"else",
+        b"elseif",
+        b"end",
+        b"false",
+        b"for",
+        b"function",
+        b"goto",
+        b"if",
+        b"in",
+        b"local",
+        b"nil",
+        b"not",
+        b"or",
+        b"repeat",
+        b"return",
+        b"then",
+        b"true",
+        b"until",
+        b"while",
+    ];
+
+    match name.split_first() {
+        Some((&first, rest)) => {
+            (first.is_ascii_alphabetic() || first == b'_')
+                && rest.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+                && !KEYWORDS.contains(&name)
+        }
+        None => false,
+    }
+}
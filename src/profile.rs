@@ -0,0 +1,283 @@
+use std::fmt::Write;
+use std::rc::Rc;
+use std::string::String as StdString;
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxHashMap;
+
+use gc_arena::{Collect, Gc, GcCell, MutationContext, StaticCollect};
+use gc_sequence::Sequence;
+
+use crate::{
+    Callback, CallbackResult, CallbackReturn, Error, Function, FunctionProto, Table, Value,
+};
+
+/// Records how many times every Lua function and Rust callback is called and the wall time spent
+/// in those calls, to find which functions a script spends its time in.
+///
+/// Set on a thread with `Thread::set_profiler`, threads created by `coroutine.create` share the
+/// profiler of the main thread.  The time of a call runs from when it starts until it returns or
+/// raises an error, so it includes the time spent in the functions it calls, while its thread is
+/// suspended and between steps of its thread.  Lua functions are counted per prototype, so every
+/// closure created from the same function expression shares one entry.
+#[derive(Clone, Copy, Collect)]
+#[collect(require_copy)]
+pub struct Profiler<'gc>(GcCell<'gc, ProfilerState<'gc>>);
+
+#[derive(Collect)]
+#[collect(empty_drop)]
+struct ProfilerState<'gc> {
+    clock: StaticCollect<Rc<dyn Fn() -> Duration>>,
+    entries: Vec<(ProfiledFunction<'gc>, CallStats)>,
+    // Indexes into `entries`, keyed by prototype or callback address.
+    index: FxHashMap<usize, usize>,
+}
+
+/// A function whose calls are recorded by a `Profiler`.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(require_copy)]
+pub enum ProfiledFunction<'gc> {
+    Lua(Gc<'gc, FunctionProto<'gc>>),
+    Callback(Callback<'gc>),
+}
+
+impl<'gc> ProfiledFunction<'gc> {
+    fn key(self) -> usize {
+        match self {
+            ProfiledFunction::Lua(proto) => Gc::as_ptr(proto) as usize,
+            ProfiledFunction::Callback(callback) => Gc::as_ptr(callback.0) as usize,
+        }
+    }
+
+    fn is(self, value: Value<'gc>) -> bool {
+        match (self, value) {
+            (ProfiledFunction::Lua(proto), Value::Function(Function::Closure(closure))) => {
+                Gc::ptr_eq(proto, closure.0.proto)
+            }
+            (ProfiledFunction::Callback(callback), Value::Function(Function::Callback(c))) => {
+                callback == c
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The number of calls of one function and the total time spent in them.  Calls that have not
+/// finished yet are counted, but their time is not.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct CallStats {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+/// One row of `Profiler::report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileEntry {
+    /// The global name of the function, such as `print` or `string.format`, or a description of
+    /// it if it is not reachable that way.
+    pub name: StdString,
+    pub calls: u64,
+    pub time: Duration,
+}
+
+impl<'gc> Profiler<'gc> {
+    /// Creates a profiler measuring time with `std::time::Instant`.
+    pub fn new(mc: MutationContext<'gc, '_>) -> Profiler<'gc> {
+        let start = Instant::now();
+        Profiler::with_clock(mc, move || start.elapsed())
+    }
+
+    /// Creates a profiler measuring time with the given clock, which must never go backwards.
+    pub fn with_clock(
+        mc: MutationContext<'gc, '_>,
+        clock: impl Fn() -> Duration + 'static,
+    ) -> Profiler<'gc> {
+        Profiler(GcCell::allocate(
+            mc,
+            ProfilerState {
+                clock: StaticCollect(Rc::new(clock)),
+                entries: Vec::new(),
+                index: FxHashMap::default(),
+            },
+        ))
+    }
+
+    /// Returns every function called since the profiler was created, in the order of their first
+    /// calls.
+    pub fn entries(&self) -> Vec<(ProfiledFunction<'gc>, CallStats)> {
+        self.0.read().entries.clone()
+    }
+
+    /// Returns the statistics of the given function, if it has been called.
+    pub fn stats(&self, function: ProfiledFunction<'gc>) -> Option<CallStats> {
+        let state = self.0.read();
+        let &index = state.index.get(&function.key())?;
+        Some(state.entries[index].1)
+    }
+
+    /// Returns a row for every called function, with the most time first.
+    ///
+    /// Functions are named after the field of `globals` they are stored in, or a field of a table
+    /// stored in `globals`, such as a library table.  Other Lua functions are described by the line
+    /// their body starts on.
+    pub fn report(&self, globals: Table<'gc>) -> Vec<ProfileEntry> {
+        let mut named = Vec::new();
+        for (key, value) in globals.0.read().iter() {
+            match value {
+                Value::Function(_) => named.extend(field_name(None, key).map(|n| (n, value))),
+                Value::Table(table) if table != globals => {
+                    for (field, value) in table.0.read().iter() {
+                        if let Value::Function(_) = value {
+                            named.extend(field_name(Some(key), field).map(|n| (n, value)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut report: Vec<ProfileEntry> = self
+            .0
+            .read()
+            .entries
+            .iter()
+            .map(|&(function, stats)| {
+                let name = named
+                    .iter()
+                    .filter(|(_, value)| function.is(*value))
+                    .map(|(name, _)| name.clone())
+                    .min()
+                    .unwrap_or_else(|| match function {
+                        ProfiledFunction::Lua(proto) => match proto.info().lines {
+                            Some((first, _)) => format!("function at line {}", first),
+                            None => "function".to_owned(),
+                        },
+                        ProfiledFunction::Callback(_) => "callback".to_owned(),
+                    });
+                ProfileEntry {
+                    name,
+                    calls: stats.calls,
+                    time: stats.time,
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+        report
+    }
+
+    /// Formats `report` as tab separated text, with a header line followed by one line per
+    /// function:
+    ///
+    /// `name calls microseconds`
+    pub fn dump(&self, globals: Table<'gc>) -> StdString {
+        let mut out = StdString::new();
+        writeln!(out, "name\tcalls\tmicroseconds").unwrap();
+        for entry in self.report(globals) {
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                entry.name,
+                entry.calls,
+                entry.time.as_micros()
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // Counts a call of the given function and returns its start time.
+    pub(crate) fn enter(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        function: ProfiledFunction<'gc>,
+    ) -> Duration {
+        let mut state = self.0.write(mc);
+        let key = function.key();
+        let index = match state.index.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = state.entries.len();
+                state.entries.push((function, CallStats::default()));
+                state.index.insert(key, index);
+                index
+            }
+        };
+        state.entries[index].1.calls += 1;
+        (state.clock.0)()
+    }
+
+    // Adds the time since `started` to a call of the given function.
+    pub(crate) fn exit(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        function: ProfiledFunction<'gc>,
+        started: Duration,
+    ) {
+        let mut state = self.0.write(mc);
+        let now = (state.clock.0)();
+        if let Some(&index) = state.index.get(&function.key()) {
+            let time = &mut state.entries[index].1.time;
+            *time += now.saturating_sub(started);
+        }
+    }
+}
+
+// Calls a callback, recording the call if there is a profiler.  Callbacks that return a sequence
+// are timed until the sequence finishes.
+pub(crate) fn call_callback<'gc>(
+    profiler: Option<Profiler<'gc>>,
+    mc: MutationContext<'gc, '_>,
+    callback: Callback<'gc>,
+    args: Vec<Value<'gc>>,
+) -> CallbackReturn<'gc> {
+    let profiler = match profiler {
+        Some(profiler) => profiler,
+        None => return callback.call(args),
+    };
+
+    let function = ProfiledFunction::Callback(callback);
+    let started = profiler.enter(mc, function);
+    match callback.call(args) {
+        CallbackReturn::Immediate(res) => {
+            profiler.exit(mc, function, started);
+            CallbackReturn::Immediate(res)
+        }
+        CallbackReturn::Sequence(sequence) => CallbackReturn::Sequence(Box::new(TimedSequence {
+            sequence,
+            profiler,
+            function,
+            started,
+        })),
+    }
+}
+
+#[derive(Collect)]
+#[collect(empty_drop)]
+struct TimedSequence<'gc> {
+    sequence: Box<dyn Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>> + 'gc>,
+    profiler: Profiler<'gc>,
+    function: ProfiledFunction<'gc>,
+    started: Duration,
+}
+
+impl<'gc> Sequence<'gc> for TimedSequence<'gc> {
+    type Output = Result<CallbackResult<'gc>, Error<'gc>>;
+
+    fn step(&mut self, mc: MutationContext<'gc, '_>) -> Option<Self::Output> {
+        let res = self.sequence.step(mc)?;
+        self.profiler.exit(mc, self.function, self.started);
+        Some(res)
+    }
+}
+
+fn field_name<'gc>(table: Option<Value<'gc>>, key: Value<'gc>) -> Option<StdString> {
+    let name = |value: Value<'gc>| match value {
+        Value::String(s) => std::str::from_utf8(s.as_bytes()).ok().map(str::to_owned),
+        _ => None,
+    };
+    match table {
+        Some(table) => Some(format!("{}.{}", name(table)?, name(key)?)),
+        None => name(key),
+    }
+}
@@ -0,0 +1,140 @@
+use std::hash::{Hash, Hasher};
+
+use rustc_hash::{FxHashMap, FxHasher};
+
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
+
+use crate::{Constant, FunctionProto, InternedStringSet, LocalVariable, String};
+
+/// Shares identical function prototypes between separately compiled or loaded chunks.
+///
+/// Luster does not yet have a binary chunk format, but embedders that build many similar chunks
+/// (for example from generated source) can pass each top level prototype through
+/// `PrototypeCache::share` so that identical nested prototypes are only kept in memory once.
+/// String constants are re-interned against the given `InternedStringSet` along the way, so
+/// prototypes built outside of `compile` also end up sharing their strings.
+///
+/// Two prototypes are only considered identical if their opcodes, constants, upvalues, line
+/// information, chunk names and nested prototypes all match, so sharing a prototype is never observable from
+/// Lua.
+#[derive(Collect, Clone, Copy)]
+#[collect(require_copy)]
+pub struct PrototypeCache<'gc>(GcCell<'gc, FxHashMap<u64, Vec<Gc<'gc, FunctionProto<'gc>>>>>);
+
+impl<'gc> PrototypeCache<'gc> {
+    pub fn new(mc: MutationContext<'gc, '_>) -> PrototypeCache<'gc> {
+        PrototypeCache(GcCell::allocate(mc, FxHashMap::default()))
+    }
+
+    /// Returns a prototype equivalent to the given one, whose string constants are interned and
+    /// which shares itself and its nested prototypes with any identical prototypes already in the
+    /// cache.
+    pub fn share(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        interned_strings: InternedStringSet<'gc>,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+    ) -> Gc<'gc, FunctionProto<'gc>> {
+        let prototypes = proto
+            .prototypes
+            .iter()
+            .map(|&p| self.share(mc, interned_strings, p))
+            .collect::<Vec<_>>();
+
+        let constants = proto
+            .constants
+            .iter()
+            .map(|&c| match c {
+                Constant::String(s @ String::Static(_)) => Constant::String(s),
+                Constant::String(s) => Constant::String(interned_strings.new_string(mc, &s)),
+                c => c,
+            })
+            .collect::<Vec<_>>();
+
+        let shared = FunctionProto {
+            fixed_params: proto.fixed_params,
+            has_varargs: proto.has_varargs,
+            stack_size: proto.stack_size,
+            constants,
+            opcodes: proto.opcodes.clone(),
+            opcode_lines: proto.opcode_lines.clone(),
+            upvalues: proto.upvalues.clone(),
+            upvalue_names: proto
+                .upvalue_names
+                .iter()
+                .map(|&n| match n {
+                    String::Static(_) => n,
+                    n => interned_strings.new_string(mc, &n),
+                })
+                .collect(),
+            local_variables: proto
+                .local_variables
+                .iter()
+                .map(|&local| LocalVariable {
+                    name: match local.name {
+                        String::Static(_) => local.name,
+                        n => interned_strings.new_string(mc, &n),
+                    },
+                    ..local
+                })
+                .collect(),
+            prototypes,
+            chunk_name: proto.chunk_name.clone(),
+        };
+
+        let hash = proto_hash(&shared);
+        if let Some(bucket) = self.0.read().get(&hash) {
+            if let Some(&found) = bucket.iter().find(|&&p| proto_eq(&p, &shared)) {
+                return found;
+            }
+        }
+
+        let shared = Gc::allocate(mc, shared);
+        self.0.write(mc).entry(hash).or_default().push(shared);
+        shared
+    }
+
+    /// The number of distinct prototypes held by this cache.
+    pub fn len(&self) -> usize {
+        self.0.read().values().map(|bucket| bucket.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().is_empty()
+    }
+}
+
+fn proto_hash(proto: &FunctionProto) -> u64 {
+    let mut hasher = FxHasher::default();
+    proto.fixed_params.hash(&mut hasher);
+    proto.has_varargs.hash(&mut hasher);
+    proto.stack_size.hash(&mut hasher);
+    proto.constants.hash(&mut hasher);
+    proto.opcodes.len().hash(&mut hasher);
+    proto.opcode_lines.hash(&mut hasher);
+    proto.upvalues.len().hash(&mut hasher);
+    for &p in &proto.prototypes {
+        Gc::as_ptr(p).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// Nested prototypes are compared by identity, which is enough since they have already been shared
+// when this is called.
+fn proto_eq<'gc>(a: &FunctionProto<'gc>, b: &FunctionProto<'gc>) -> bool {
+    a.fixed_params == b.fixed_params
+        && a.has_varargs == b.has_varargs
+        && a.stack_size == b.stack_size
+        && a.constants == b.constants
+        && a.opcodes == b.opcodes
+        && a.opcode_lines == b.opcode_lines
+        && a.upvalues == b.upvalues
+        && a.upvalue_names == b.upvalue_names
+        && a.local_variables == b.local_variables
+        && a.chunk_name == b.chunk_name
+        && a.prototypes.len() == b.prototypes.len()
+        && a.prototypes
+            .iter()
+            .zip(&b.prototypes)
+            .all(|(&a, &b)| Gc::ptr_eq(a, b))
+}
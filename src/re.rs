@@ -0,0 +1,268 @@
+//! Regular expressions for Lua through the `regex` crate, as an alternative to Lua patterns.
+//!
+//! Patterns use the `regex` crate's syntax and are matched against strings as raw bytes, so
+//! subjects do not need to be valid UTF-8 (use `(?-u)` in a pattern to match arbitrary bytes with
+//! classes such as `.`).  Compiled patterns are cached per `Lua` state, keyed by their source, so
+//! calling `re.match` or `re.gsub` with the same pattern string in a loop only compiles it once.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use regex::bytes::Regex;
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence as sequence;
+
+use crate::stdlib::string_too_large;
+use crate::{
+    Callback, CallbackResult, Error, Root, RuntimeError, String, Table, Thread, TypeError, Value,
+};
+
+// The cache is simply cleared once it holds this many patterns, so that scripts building
+// patterns dynamically cannot grow it without bound.
+const CACHE_LIMIT: usize = 256;
+
+type RegexCache = Rc<RefCell<HashMap<Vec<u8>, Rc<Regex>>>>;
+
+/// Loads the `re` library, with the functions:
+///
+/// * `re.compile(pattern)`: checks and compiles `pattern`, returning an object with `pattern`
+///   field and `match` and `gsub` methods that behave like the functions below
+/// * `re.match(pattern, subject [, init])`: finds the first match of `pattern` in `subject`
+///   starting at byte `init` (which may be negative to count from the end), returning the
+///   captures or the whole match if the pattern has no capture groups, or nil if there is no match
+/// * `re.gsub(pattern, subject, replacement [, n])`: replaces the first `n` (or every) match with
+///   `replacement`, where `$1` or `${name}` refer to captures, returning the new string and the
+///   number of replacements made, or raises an error if the new string would be longer than the
+///   main thread's `Thread::max_string_len`
+///
+/// Each function accepts either a pattern string or a compiled pattern object.
+pub fn load_re<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let cache: RegexCache = Rc::new(RefCell::new(HashMap::new()));
+    let re = Table::new(mc);
+
+    let match_fn = {
+        let cache = cache.clone();
+        Callback::new_sequence_with(mc, root.main_thread, move |&main_thread, args| {
+            let init = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => 1,
+                value => integer_arg(value)?,
+            };
+            request(main_thread, &cache, &args, Operation::Match { init })
+        })
+    };
+    re.set(mc, String::new_static(b"match"), match_fn).unwrap();
+
+    let gsub_fn = {
+        let cache = cache.clone();
+        Callback::new_sequence_with(mc, root.main_thread, move |&main_thread, args| {
+            let replacement = string_arg(&args, 2)?;
+            let limit = match args.get(3).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => None,
+                value => Some(integer_arg(value)?.max(0) as usize),
+            };
+            let operation = Operation::Gsub { replacement, limit };
+            request(main_thread, &cache, &args, operation)
+        })
+    };
+    re.set(mc, String::new_static(b"gsub"), gsub_fn).unwrap();
+
+    // Compiled pattern objects share the library functions as their methods, which works because
+    // a method call passes the object itself as the pattern argument.
+    let compile = Callback::new_sequence_with(mc, (match_fn, gsub_fn), move |&methods, args| {
+        let pattern = pattern_arg(&args, 0)?;
+        Ok(sequence::from_fn_with(
+            (cache.clone(), pattern, methods),
+            |mc, (cache, pattern, methods)| {
+                lookup(mc, &cache, &pattern)?;
+                let compiled = Table::new(mc);
+                compiled.set(
+                    mc,
+                    String::new_static(b"pattern"),
+                    String::new(mc, &pattern),
+                )?;
+                compiled.set(mc, String::new_static(b"match"), methods.0)?;
+                compiled.set(mc, String::new_static(b"gsub"), methods.1)?;
+                Ok(CallbackResult::Return(vec![Value::Table(compiled)]))
+            },
+        ))
+    });
+    re.set(mc, String::new_static(b"compile"), compile).unwrap();
+
+    env.set(mc, String::new_static(b"re"), re).unwrap();
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+enum Operation {
+    Match {
+        init: i64,
+    },
+    Gsub {
+        replacement: Vec<u8>,
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct Request {
+    cache: RegexCache,
+    pattern: Vec<u8>,
+    subject: Vec<u8>,
+    operation: Operation,
+}
+
+fn request<'gc>(
+    main_thread: Thread<'gc>,
+    cache: &RegexCache,
+    args: &[Value<'gc>],
+    operation: Operation,
+) -> Result<
+    impl sequence::Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>>,
+    Error<'gc>,
+> {
+    let request = Request {
+        cache: cache.clone(),
+        pattern: pattern_arg(args, 0)?,
+        subject: string_arg(args, 1)?,
+        operation,
+    };
+    Ok(sequence::from_fn_with(
+        (main_thread, request),
+        |mc, (main_thread, request)| {
+            // The main thread is not borrowed while a callback sequence is stepped.
+            run(mc, request, main_thread.max_string_len())
+        },
+    ))
+}
+
+fn run<'gc>(
+    mc: MutationContext<'gc, '_>,
+    request: Request,
+    max_len: usize,
+) -> Result<CallbackResult<'gc>, Error<'gc>> {
+    let regex = lookup(mc, &request.cache, &request.pattern)?;
+    let subject = &request.subject[..];
+
+    match request.operation {
+        Operation::Match { init } => {
+            let start = if init > 0 {
+                (init - 1) as usize
+            } else if init == 0 {
+                0
+            } else {
+                subject.len().saturating_sub(init.unsigned_abs() as usize)
+            };
+            if start > subject.len() {
+                return Ok(CallbackResult::Return(vec![Value::Nil]));
+            }
+
+            let captures = match regex.captures_at(subject, start) {
+                Some(captures) => captures,
+                None => return Ok(CallbackResult::Return(vec![Value::Nil])),
+            };
+            let to_value = |m: Option<regex::bytes::Match>| match m {
+                Some(m) => Value::String(String::new(mc, m.as_bytes())),
+                None => Value::Nil,
+            };
+            let values = if captures.len() == 1 {
+                vec![to_value(captures.get(0))]
+            } else {
+                captures.iter().skip(1).map(to_value).collect()
+            };
+            Ok(CallbackResult::Return(values))
+        }
+        Operation::Gsub { replacement, limit } => {
+            let mut result = Vec::with_capacity(subject.len());
+            let mut last = 0;
+            let mut count = 0;
+            for captures in regex
+                .captures_iter(subject)
+                .take(limit.unwrap_or(usize::MAX))
+            {
+                let m = captures.get(0).unwrap();
+                result.extend_from_slice(&subject[last..m.start()]);
+                captures.expand(&replacement, &mut result);
+                if result.len() > max_len {
+                    return Err(string_too_large());
+                }
+                last = m.end();
+                count += 1;
+            }
+            if result.len() + (subject.len() - last) > max_len {
+                return Err(string_too_large());
+            }
+            result.extend_from_slice(&subject[last..]);
+            Ok(CallbackResult::Return(vec![
+                Value::String(String::new(mc, &result)),
+                Value::Integer(count),
+            ]))
+        }
+    }
+}
+
+fn lookup<'gc>(
+    mc: MutationContext<'gc, '_>,
+    cache: &RegexCache,
+    pattern: &[u8],
+) -> Result<Rc<Regex>, Error<'gc>> {
+    if let Some(regex) = cache.borrow().get(pattern) {
+        return Ok(regex.clone());
+    }
+
+    let regex = std::str::from_utf8(pattern)
+        .map_err(|e| e.to_string())
+        .and_then(|pattern| Regex::new(pattern).map_err(|e| e.to_string()))
+        .map_err(|message| {
+            RuntimeError(Value::String(String::new(
+                mc,
+                format!("bad regular expression: {}", message).as_bytes(),
+            )))
+        })?;
+    let regex = Rc::new(regex);
+
+    let mut cache = cache.borrow_mut();
+    if cache.len() >= CACHE_LIMIT {
+        cache.clear();
+    }
+    cache.insert(pattern.to_vec(), regex.clone());
+    Ok(regex)
+}
+
+// A pattern argument may be either a pattern string or a compiled pattern object.
+fn pattern_arg<'gc>(args: &[Value<'gc>], i: usize) -> Result<Vec<u8>, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::Table(compiled) => match compiled.get(String::new_static(b"pattern")) {
+            Value::String(pattern) => Ok(pattern.as_bytes().to_vec()),
+            _ => Err(TypeError {
+                expected: "pattern",
+                found: "table",
+            }
+            .into()),
+        },
+        _ => string_arg(args, i),
+    }
+}
+
+fn string_arg<'gc>(args: &[Value<'gc>], i: usize) -> Result<Vec<u8>, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::String(s) => Ok(s.as_bytes().to_vec()),
+        value => Err(TypeError {
+            expected: "string",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
+
+fn integer_arg<'gc>(value: Value<'gc>) -> Result<i64, Error<'gc>> {
+    value.to_integer().ok_or_else(|| {
+        TypeError {
+            expected: "integer",
+            found: value.type_name(),
+        }
+        .into()
+    })
+}
@@ -0,0 +1,80 @@
+use gc_arena::{Gc, GcCell, GcWeak, MutationContext};
+
+use crate::{Callback, Closure, Function, String, Table, Thread, Value};
+
+/// A key to a Lua value which can be held outside of the arena in between mutations, without
+/// keeping the value alive.  This is meant for host-side caches of Lua objects, such as a map from
+/// game entities to their script tables, which should not leak the objects scripts have dropped.
+///
+/// Tables, functions and threads are referred to weakly: once the garbage collector finds that
+/// nothing in the arena refers to the value any more, `is_alive` returns false and `upgrade`
+/// returns None.  Other values, including strings, are copied into the key and never die, as with
+/// the entries of a weak Lua table.
+///
+/// A key must only be upgraded in the `Lua` instance it was created in, `upgrade` panics
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct WeakRegistryKey(Target);
+
+#[derive(Debug, Clone)]
+enum Target {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Box<[u8]>),
+    Table(GcWeak),
+    Closure(GcWeak),
+    Callback(GcWeak),
+    Thread(GcWeak),
+}
+
+impl WeakRegistryKey {
+    pub fn new<'gc>(mc: MutationContext<'gc, '_>, value: Value<'gc>) -> WeakRegistryKey {
+        WeakRegistryKey(match value {
+            Value::Nil => Target::Nil,
+            Value::Boolean(b) => Target::Boolean(b),
+            Value::Integer(i) => Target::Integer(i),
+            Value::Number(n) => Target::Number(n),
+            Value::String(s) => Target::String(s.as_bytes().into()),
+            Value::Table(t) => Target::Table(GcCell::downgrade(mc, t.0)),
+            Value::Function(Function::Closure(c)) => Target::Closure(Gc::downgrade(mc, c.0)),
+            Value::Function(Function::Callback(c)) => Target::Callback(Gc::downgrade(mc, c.0)),
+            Value::Thread(t) => Target::Thread(GcCell::downgrade(mc, t.0)),
+        })
+    }
+
+    /// Returns whether the value is still alive.  This can be called in between mutations, but a
+    /// key that is alive may still be found dead by the next collection.
+    pub fn is_alive(&self) -> bool {
+        match &self.0 {
+            Target::Table(weak)
+            | Target::Closure(weak)
+            | Target::Callback(weak)
+            | Target::Thread(weak) => weak.is_alive(),
+            _ => true,
+        }
+    }
+
+    /// Returns the value of this key, if it is still alive.
+    pub fn upgrade<'gc>(&self, mc: MutationContext<'gc, '_>) -> Option<Value<'gc>> {
+        // Each kind of weak pointer is only ever created from the matching kind of value in `new`.
+        unsafe {
+            Some(match &self.0 {
+                Target::Nil => Value::Nil,
+                Target::Boolean(b) => Value::Boolean(*b),
+                Target::Integer(i) => Value::Integer(*i),
+                Target::Number(n) => Value::Number(*n),
+                Target::String(s) => Value::String(String::new(mc, s)),
+                Target::Table(weak) => Value::Table(Table(GcCell::upgrade(mc, weak)?)),
+                Target::Closure(weak) => {
+                    Value::Function(Function::Closure(Closure(Gc::upgrade(mc, weak)?)))
+                }
+                Target::Callback(weak) => {
+                    Value::Function(Function::Callback(Callback(Gc::upgrade(mc, weak)?)))
+                }
+                Target::Thread(weak) => Value::Thread(Thread(GcCell::upgrade(mc, weak)?)),
+            })
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! A remote REPL for inspecting a running, embedded interpreter over a local TCP socket.
+//!
+//! The protocol is line based: a client first sends the configured auth token on its own line,
+//! then sends Lua source a line at a time.  As in the `luster` REPL, a line is first compiled as a
+//! statement and then as an expression, and input that ends early is continued on the next line.
+//! Each evaluated chunk produces one line of output, either the returned values separated by tabs
+//! or a line starting with `error: `.
+//!
+//! Like `dap::DapServer`, the server never blocks, the embedder calls `RemoteRepl::poll` between
+//! its own ticks, and chunks are run to completion (or until they hit their instruction budget)
+//! inside that call.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::string::String as StdString;
+
+use gc_arena::MutationContext;
+
+use crate::{
+    compile, Closure, Error, Function, Root, RuntimeError, String, Table, Thread, ThreadMode, Value,
+};
+
+/// Configuration for a `RemoteRepl`.
+#[derive(Debug, Clone)]
+pub struct RemoteReplConfig {
+    /// Clients must send this token as their first line before anything is evaluated.
+    pub auth_token: StdString,
+    pub sandbox: Sandbox,
+    /// The maximum number of VM steps a single chunk may take before it is aborted.
+    pub max_steps: usize,
+    /// The depth that returned tables are printed to.
+    pub print_depth: usize,
+    /// The longest line a client may send, in bytes.  Clients that send a longer line, including
+    /// before they have authenticated, are disconnected.
+    pub max_line_len: usize,
+}
+
+/// Controls what evaluated chunks may change.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    /// Chunks run directly against the environment given to `RemoteRepl::poll`.
+    Live,
+    /// Each chunk runs against a fresh shallow copy of the environment, so assignments to globals
+    /// are discarded.  Tables reachable from the environment can still be modified.
+    Copy,
+}
+
+impl RemoteReplConfig {
+    pub fn new(auth_token: impl Into<StdString>) -> RemoteReplConfig {
+        RemoteReplConfig {
+            auth_token: auth_token.into(),
+            sandbox: Sandbox::Copy,
+            max_steps: 1024,
+            print_depth: 2,
+            max_line_len: 64 * 1024,
+        }
+    }
+}
+
+pub struct RemoteRepl {
+    listener: TcpListener,
+    config: RemoteReplConfig,
+    clients: Vec<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    read_buffer: Vec<u8>,
+    // The length of the last, unfinished line in `read_buffer`
+    line_len: usize,
+    write_buffer: Vec<u8>,
+    authenticated: bool,
+    pending_source: Vec<u8>,
+    disconnected: bool,
+}
+
+impl RemoteRepl {
+    /// Listens for clients on the given address, which should almost always be a loopback
+    /// address.
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: RemoteReplConfig) -> io::Result<RemoteRepl> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(RemoteRepl {
+            listener,
+            config,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts any pending clients and evaluates every complete chunk they have sent against the
+    /// given environment.
+    pub fn poll<'gc>(
+        &mut self,
+        mc: MutationContext<'gc, '_>,
+        root: Root<'gc>,
+        environment: Table<'gc>,
+    ) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.clients.push(Client {
+                        stream,
+                        read_buffer: Vec::new(),
+                        line_len: 0,
+                        write_buffer: Vec::new(),
+                        authenticated: false,
+                        pending_source: Vec::new(),
+                        disconnected: false,
+                    });
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        for client in &mut self.clients {
+            client.receive(self.config.max_line_len);
+            while let Some(line) = client.next_line() {
+                if !client.authenticated {
+                    if constant_time_eq(&line, self.config.auth_token.as_bytes()) {
+                        client.authenticated = true;
+                    } else {
+                        client
+                            .write_buffer
+                            .extend_from_slice(b"error: bad auth token\n");
+                        client.disconnected = true;
+                        break;
+                    }
+                    continue;
+                }
+
+                if !client.pending_source.is_empty() {
+                    client.pending_source.push(b'\n');
+                }
+                client.pending_source.extend_from_slice(&line);
+                let force = line.is_empty();
+                if let Some(output) = evaluate(
+                    mc,
+                    root,
+                    environment,
+                    &self.config,
+                    &client.pending_source,
+                    force,
+                ) {
+                    client.write_buffer.extend_from_slice(output.as_bytes());
+                    client.write_buffer.push(b'\n');
+                    client.pending_source.clear();
+                }
+            }
+            client.flush();
+        }
+        self.clients.retain(|c| !c.disconnected);
+
+        Ok(())
+    }
+}
+
+impl Client {
+    // Reads everything the client has sent so far.  A client whose connection fails or that sends
+    // a line longer than `max_line_len` is disconnected.
+    fn receive(&mut self, max_line_len: usize) {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.disconnected = true;
+                    return;
+                }
+                Ok(n) => {
+                    for &b in &buf[..n] {
+                        if b == b'\n' {
+                            self.line_len = 0;
+                        } else {
+                            self.line_len += 1;
+                        }
+                        if self.line_len > max_line_len {
+                            self.read_buffer.clear();
+                            self.write_buffer
+                                .extend_from_slice(b"error: line too long\n");
+                            self.disconnected = true;
+                            return;
+                        }
+                    }
+                    self.read_buffer.extend_from_slice(&buf[..n]);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.disconnected = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self.read_buffer.iter().position(|&b| b == b'\n')?;
+        let mut line = self.read_buffer.drain(..=end).collect::<Vec<_>>();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    // Writes as much pending output as the client will take without blocking, disconnecting it if
+    // the connection fails.
+    fn flush(&mut self) {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.write_buffer.drain(..n);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Returns the output line for the given source, or None if the source is incomplete and more
+// input should be read first (unless `force` is set).
+fn evaluate<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    environment: Table<'gc>,
+    config: &RemoteReplConfig,
+    source: &[u8],
+    force: bool,
+) -> Option<StdString> {
+    let proto = match compile(mc, root.interned_strings, source) {
+        Ok(proto) => Ok(proto),
+        Err(Error::ParserError(err)) if err.is_incomplete() => {
+            if !force {
+                return None;
+            }
+            Err(Error::ParserError(err))
+        }
+        Err(_) => {
+            let mut expression = b"return ".to_vec();
+            expression.extend_from_slice(source);
+            compile(mc, root.interned_strings, &expression[..])
+        }
+    };
+
+    let environment = match config.sandbox {
+        Sandbox::Live => environment,
+        Sandbox::Copy => {
+            let copy = Table::new(mc);
+            for (key, value) in environment.0.read().iter() {
+                copy.set(mc, key, value)
+                    .expect("copied table keys are always valid");
+            }
+            copy
+        }
+    };
+
+    let result = proto
+        .and_then(|proto| Ok(Closure::new(mc, proto, Some(environment))?))
+        .and_then(|closure| run(mc, root, closure, config));
+
+    Some(match result {
+        Ok(values) => values
+            .iter()
+            .map(|value| value.debug_fmt(config.print_depth))
+            .collect::<Vec<_>>()
+            .join("\t"),
+        Err(err) => format!("error: {}", err),
+    })
+}
+
+fn run<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    closure: Closure<'gc>,
+    config: &RemoteReplConfig,
+) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+    let thread = Thread::new_inheriting(mc, root.main_thread, false);
+    thread.start(mc, Function::Closure(closure), &[])?;
+    for _ in 0..config.max_steps {
+        if thread.mode() != ThreadMode::Running {
+            break;
+        }
+        thread.step(mc)?;
+    }
+    if let Some(results) = thread.take_results(mc) {
+        return results;
+    }
+
+    // Output is one line per chunk, so only the innermost call and its locals are reported.
+    let snapshot = thread.snapshot(config.print_depth);
+    let mut message = "remote chunk exceeded its instruction budget".to_owned();
+    if let Some(frame) = snapshot.traceback.frames.first() {
+        match frame.line {
+            Some(line) => message.push_str(&format!(" at line {}", line)),
+            None => message.push_str(" at line ?"),
+        }
+    }
+    if !snapshot.locals.is_empty() {
+        message.push_str(&format!(" (locals: {})", snapshot.locals.join(", ")));
+    }
+    Err(RuntimeError(Value::String(String::new(mc, message.as_bytes()))).into())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,484 @@
+//! Recording and replaying the nondeterministic inputs of a script run, so that a bug seen by a
+//! user can be reproduced exactly by someone else.
+//!
+//! A recording `Replay` is given to `LusterBuilder::replay`, and every input that could differ
+//! between two runs of the same script is added to it as it is used: the seed of the random number
+//! generator of the `math` library, the times the host reads through `Replay::time`, and the
+//! results of the host callbacks created with `Replay::callback`, numbered in the order they are
+//! called.  The recording is saved with `Replay::to_bytes`, and a `Replay` loaded from those bytes
+//! with `Replay::load` feeds the same inputs back in the same order, without calling the host
+//! callbacks or reading the clock.
+//!
+//! If a replayed run asks for a different input than the recorded run did at the same point, the
+//! script or the host have changed since the recording was made, and the input fails with a
+//! `ReplayError` rather than guessing.
+
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::error::Error as StdError;
+use std::fmt;
+use std::rc::Rc;
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Error, OwnedValue, RuntimeError, String, Table, Value};
+
+const MAGIC: &[u8] = b"\x1bLusterReplay";
+const VERSION: u8 = 1;
+
+/// A single recorded input, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayEvent {
+    /// The seed of the random number generator of the `math` library.
+    RandomSeed(u64),
+    /// A time read through `Replay::time`.
+    Time(f64),
+    /// What the `sequence`th call (counting from 0) of any callback created with
+    /// `Replay::callback` returned, or the error it raised.
+    Callback {
+        sequence: u64,
+        results: Result<Vec<OwnedValue>, OwnedValue>,
+    },
+}
+
+impl ReplayEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            ReplayEvent::RandomSeed(_) => "random seed",
+            ReplayEvent::Time(_) => "time",
+            ReplayEvent::Callback { .. } => "callback",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum ReplayError {
+    BadHeader,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadTag,
+    /// The replayed run asked for an input of the kind `expected`, but the next recorded input is
+    /// of the kind `found`.
+    Diverged {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The replayed run made the `expected`th call of a callback, but the next recorded callback
+    /// result is from the `found`th call.
+    OutOfSequence {
+        expected: u64,
+        found: u64,
+    },
+    /// The replayed run asked for more inputs than were recorded.
+    Exhausted,
+    /// A recorded callback result was a function, a thread or a table containing itself, which
+    /// cannot be recreated.
+    Unreplayable(&'static str),
+}
+
+impl StdError for ReplayError {}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::BadHeader => write!(fmt, "not a luster replay"),
+            ReplayError::UnsupportedVersion(v) => write!(fmt, "unsupported replay version {}", v),
+            ReplayError::Truncated => write!(fmt, "replay is truncated"),
+            ReplayError::BadTag => write!(fmt, "replay contains an unknown tag"),
+            ReplayError::Diverged { expected, found } => write!(
+                fmt,
+                "replay diverged, expected a {} input but the recording has a {} input",
+                expected, found
+            ),
+            ReplayError::OutOfSequence { expected, found } => write!(
+                fmt,
+                "replay diverged, expected the result of callback call {} but the recording has \
+                 call {}",
+                expected, found
+            ),
+            ReplayError::Exhausted => write!(fmt, "replay diverged, the recording has ended"),
+            ReplayError::Unreplayable(type_name) => {
+                write!(fmt, "cannot replay a {} returned by a callback", type_name)
+            }
+        }
+    }
+}
+
+/// A recording of the nondeterministic inputs of a run that is either being made or being
+/// replayed, see the module documentation.
+///
+/// `Replay` is a shared handle, clones of it record to and replay from the same recording.
+#[derive(Clone)]
+pub struct Replay(Rc<RefCell<ReplayState>>);
+
+struct ReplayState {
+    replaying: bool,
+    events: Vec<ReplayEvent>,
+    // The next event to replay
+    position: usize,
+    // The number of calls made so far to callbacks created by `Replay::callback`
+    calls: u64,
+}
+
+impl Replay {
+    /// Starts a new, empty recording.
+    pub fn record() -> Replay {
+        Replay::new(false, Vec::new())
+    }
+
+    /// Replays the given events in order.
+    pub fn from_events(events: Vec<ReplayEvent>) -> Replay {
+        Replay::new(true, events)
+    }
+
+    /// Replays a recording saved with `Replay::to_bytes`.
+    pub fn load(bytes: &[u8]) -> Result<Replay, ReplayError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ReplayError::BadHeader);
+        }
+        let mut reader = Reader(&bytes[MAGIC.len()..]);
+        match reader.u8()? {
+            VERSION => {}
+            v => return Err(ReplayError::UnsupportedVersion(v)),
+        }
+
+        let mut events = Vec::new();
+        while !reader.0.is_empty() {
+            events.push(reader.event()?);
+        }
+        Ok(Replay::from_events(events))
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.0.borrow().replaying
+    }
+
+    /// Every event recorded so far, or every event being replayed.
+    pub fn events(&self) -> Vec<ReplayEvent> {
+        self.0.borrow().events.clone()
+    }
+
+    /// Saves the recording, to be loaded again with `Replay::load`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        for event in &self.0.borrow().events {
+            write_event(&mut buf, event);
+        }
+        buf
+    }
+
+    /// Records the seed returned by `seed`, or returns the recorded seed instead of calling it.
+    pub fn random_seed(&self, seed: impl FnOnce() -> u64) -> Result<u64, ReplayError> {
+        if !self.is_replaying() {
+            let seed = seed();
+            self.push(ReplayEvent::RandomSeed(seed));
+            return Ok(seed);
+        }
+        match self.next("random seed")? {
+            ReplayEvent::RandomSeed(seed) => Ok(seed),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Records the time returned by `now`, or returns the recorded time instead of calling it.  A
+    /// host driving an `Executor` should read the time it advances the executor by through this.
+    pub fn time(&self, now: impl FnOnce() -> f64) -> Result<f64, ReplayError> {
+        if !self.is_replaying() {
+            let time = now();
+            self.push(ReplayEvent::Time(time));
+            return Ok(time);
+        }
+        match self.next("time")? {
+            ReplayEvent::Time(time) => Ok(time),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Creates a callback which calls `f` and records what it returns.  While replaying, `f` is
+    /// never called, and the callback returns what the call with the same sequence number returned
+    /// in the recording (or raises the same error).
+    ///
+    /// The results are recorded as `OwnedValue`s, so tables are replayed as new tables with the same
+    /// contents, and a callback that returns functions or threads cannot be replayed.
+    pub fn callback<'gc, F>(&self, mc: MutationContext<'gc, '_>, f: F) -> Callback<'gc>
+    where
+        F: 'static
+            + for<'a> Fn(
+                MutationContext<'gc, 'a>,
+                Vec<Value<'gc>>,
+            ) -> Result<Vec<Value<'gc>>, Error<'gc>>,
+    {
+        let replay = self.clone();
+        let f = Rc::new(f);
+        Callback::new_sequence(mc, move |args| {
+            let replay = replay.clone();
+            let f = f.clone();
+            Ok(sequence::from_fn_with(args, move |mc, args| {
+                let sequence = {
+                    let mut state = replay.0.borrow_mut();
+                    state.calls += 1;
+                    state.calls - 1
+                };
+
+                let results = if replay.is_replaying() {
+                    replayed_results(mc, &replay, sequence)?
+                } else {
+                    let results = f(mc, args).map_err(|error| match error {
+                        Error::RuntimeError(RuntimeError(value)) => value,
+                        error => Value::String(String::new(mc, error.to_string().as_bytes())),
+                    });
+                    replay.push(ReplayEvent::Callback {
+                        sequence,
+                        results: match &results {
+                            Ok(values) => Ok(values.iter().map(|&v| OwnedValue::from(v)).collect()),
+                            Err(value) => Err(OwnedValue::from(*value)),
+                        },
+                    });
+                    results
+                };
+
+                match results {
+                    Ok(values) => Ok(CallbackResult::Return(values)),
+                    Err(value) => Err(RuntimeError(value).into()),
+                }
+            }))
+        })
+    }
+
+    fn new(replaying: bool, events: Vec<ReplayEvent>) -> Replay {
+        Replay(Rc::new(RefCell::new(ReplayState {
+            replaying,
+            events,
+            position: 0,
+            calls: 0,
+        })))
+    }
+
+    fn push(&self, event: ReplayEvent) {
+        self.0.borrow_mut().events.push(event);
+    }
+
+    // Returns the next recorded event, if it is of the expected kind.
+    fn next(&self, kind: &'static str) -> Result<ReplayEvent, ReplayError> {
+        let mut state = self.0.borrow_mut();
+        let event = state
+            .events
+            .get(state.position)
+            .cloned()
+            .ok_or(ReplayError::Exhausted)?;
+        if event.kind() != kind {
+            return Err(ReplayError::Diverged {
+                expected: kind,
+                found: event.kind(),
+            });
+        }
+        state.position += 1;
+        Ok(event)
+    }
+}
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.0.borrow();
+        fmt.debug_struct("Replay")
+            .field("replaying", &state.replaying)
+            .field("events", &state.events.len())
+            .field("position", &state.position)
+            .finish()
+    }
+}
+
+fn replayed_results<'gc>(
+    mc: MutationContext<'gc, '_>,
+    replay: &Replay,
+    sequence: u64,
+) -> Result<Result<Vec<Value<'gc>>, Value<'gc>>, ReplayError> {
+    match replay.next("callback")? {
+        ReplayEvent::Callback {
+            sequence: recorded,
+            results,
+        } => {
+            if recorded != sequence {
+                return Err(ReplayError::OutOfSequence {
+                    expected: sequence,
+                    found: recorded,
+                });
+            }
+            Ok(match results {
+                Ok(values) => Ok(values
+                    .iter()
+                    .map(|value| to_value(mc, value))
+                    .collect::<Result<_, _>>()?),
+                Err(value) => Err(to_value(mc, &value)?),
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn to_value<'gc>(
+    mc: MutationContext<'gc, '_>,
+    value: &OwnedValue,
+) -> Result<Value<'gc>, ReplayError> {
+    Ok(match value {
+        OwnedValue::Nil => Value::Nil,
+        OwnedValue::Boolean(b) => Value::Boolean(*b),
+        OwnedValue::Integer(i) => Value::Integer(*i),
+        OwnedValue::Number(n) => Value::Number(*n),
+        OwnedValue::String(s) => Value::String(String::new(mc, s)),
+        OwnedValue::Table(entries) => {
+            let table = Table::new(mc);
+            for (key, value) in entries {
+                table
+                    .set(mc, to_value(mc, key)?, to_value(mc, value)?)
+                    .map_err(|_| ReplayError::BadTag)?;
+            }
+            Value::Table(table)
+        }
+        other => return Err(ReplayError::Unreplayable(other.type_name())),
+    })
+}
+
+fn write_event(buf: &mut Vec<u8>, event: &ReplayEvent) {
+    match event {
+        ReplayEvent::RandomSeed(seed) => {
+            buf.push(0);
+            buf.extend_from_slice(&seed.to_le_bytes());
+        }
+        ReplayEvent::Time(time) => {
+            buf.push(1);
+            buf.extend_from_slice(&time.to_bits().to_le_bytes());
+        }
+        ReplayEvent::Callback { sequence, results } => {
+            buf.push(2);
+            buf.extend_from_slice(&sequence.to_le_bytes());
+            match results {
+                Ok(values) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                    for value in values {
+                        write_value(buf, value);
+                    }
+                }
+                Err(value) => {
+                    buf.push(0);
+                    write_value(buf, value);
+                }
+            }
+        }
+    }
+}
+
+// Functions and threads are written without their identity, which means nothing to another
+// process, so that they can still be reported as unreplayable.
+fn write_value(buf: &mut Vec<u8>, value: &OwnedValue) {
+    match value {
+        OwnedValue::Nil => buf.push(0),
+        OwnedValue::Boolean(false) => buf.push(1),
+        OwnedValue::Boolean(true) => buf.push(2),
+        OwnedValue::Integer(i) => {
+            buf.push(3);
+            buf.extend_from_slice(&i.to_le_bytes());
+        }
+        OwnedValue::Number(n) => {
+            buf.push(4);
+            buf.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        OwnedValue::String(s) => {
+            buf.push(5);
+            buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            buf.extend_from_slice(s);
+        }
+        OwnedValue::Table(entries) => {
+            buf.push(6);
+            buf.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+            for (key, value) in entries {
+                write_value(buf, key);
+                write_value(buf, value);
+            }
+        }
+        OwnedValue::RecursiveTable => buf.push(7),
+        OwnedValue::Function(_) => buf.push(8),
+        OwnedValue::Thread(_) => buf.push(9),
+        OwnedValue::DeepTable => buf.push(10),
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ReplayError> {
+        if self.0.len() < len {
+            return Err(ReplayError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ReplayError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ReplayError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ReplayError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn event(&mut self) -> Result<ReplayEvent, ReplayError> {
+        Ok(match self.u8()? {
+            0 => ReplayEvent::RandomSeed(self.u64()?),
+            1 => ReplayEvent::Time(f64::from_bits(self.u64()?)),
+            2 => {
+                let sequence = self.u64()?;
+                let results = match self.u8()? {
+                    0 => Err(self.value()?),
+                    1 => {
+                        let len = self.u32()?;
+                        let mut values = Vec::new();
+                        for _ in 0..len {
+                            values.push(self.value()?);
+                        }
+                        Ok(values)
+                    }
+                    _ => return Err(ReplayError::BadTag),
+                };
+                ReplayEvent::Callback { sequence, results }
+            }
+            _ => return Err(ReplayError::BadTag),
+        })
+    }
+
+    fn value(&mut self) -> Result<OwnedValue, ReplayError> {
+        Ok(match self.u8()? {
+            0 => OwnedValue::Nil,
+            1 => OwnedValue::Boolean(false),
+            2 => OwnedValue::Boolean(true),
+            3 => OwnedValue::Integer(self.u64()? as i64),
+            4 => OwnedValue::Number(f64::from_bits(self.u64()?)),
+            5 => {
+                let len = self.u32()? as usize;
+                OwnedValue::String(self.bytes(len)?.to_vec())
+            }
+            6 => {
+                let len = self.u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..len {
+                    entries.push((self.value()?, self.value()?));
+                }
+                OwnedValue::Table(entries)
+            }
+            7 => OwnedValue::RecursiveTable,
+            8 => OwnedValue::Function(0),
+            9 => OwnedValue::Thread(0),
+            10 => OwnedValue::DeepTable,
+            _ => return Err(ReplayError::BadTag),
+        })
+    }
+}
@@ -1,26 +1,69 @@
-use std::io::{self, Write};
+use std::io::Write;
+use std::string::String as StdString;
 
 use gc_arena::MutationContext;
 use gc_sequence as sequence;
 
 use crate::{
-    Callback, CallbackResult, Continuation, Root, RuntimeError, String, Table, TypeError, Value,
+    compile_named, load_proto, Callback, CallbackResult, ChunkName, Closure, Continuation, Error,
+    Function, Output, Root, RuntimeError, String, Table, TypeError, Value,
 };
 
-pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+pub fn load_base<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    env: Table<'gc>,
+    output: Output,
+) {
+    let warnings = output.warnings();
     env.set(
         mc,
         String::new_static(b"print"),
-        Callback::new_immediate(mc, |args| {
-            let mut stdout = io::stdout();
+        Callback::new_immediate(mc, move |args| {
+            let mut line = Vec::new();
             for i in 0..args.len() {
-                args[i].display(&mut stdout)?;
+                args[i].display(&mut line)?;
                 if i != args.len() - 1 {
-                    stdout.write_all(&b"\t"[..])?;
+                    line.write_all(&b"\t"[..])?;
+                }
+            }
+            line.write_all(&b"\n"[..])?;
+            output.write(&line)?;
+            Ok(CallbackResult::Return(vec![]))
+        }),
+    )
+    .unwrap();
+
+    env.set(
+        mc,
+        String::new_static(b"warn"),
+        Callback::new_immediate(mc, move |args| {
+            if args.is_empty() {
+                return Err(TypeError {
+                    expected: "string",
+                    found: "no value",
+                }
+                .into());
+            }
+            let mut message = Vec::new();
+            for arg in &args {
+                match arg {
+                    Value::String(s) => message.extend_from_slice(s.as_bytes()),
+                    value => {
+                        return Err(TypeError {
+                            expected: "string",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
                 }
             }
-            stdout.write_all(&b"\n"[..])?;
-            stdout.flush()?;
+            // Only a single argument can be a control message.
+            if args.len() == 1 {
+                warnings.message(&message);
+            } else {
+                warnings.warn(&message);
+            }
             Ok(CallbackResult::Return(vec![]))
         }),
     )
@@ -80,6 +123,95 @@ pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<
     )
     .unwrap();
 
+    // Chunks are compiled in the sequence returned by the callback, which is given the
+    // `MutationContext` that compiling needs.  This is the way to compile from within a running
+    // script.
+    env.set(
+        mc,
+        String::new_static(b"load"),
+        Callback::new_sequence_with(
+            mc,
+            (root.interned_strings, root.globals),
+            |&(interned_strings, globals), args| {
+                let chunk = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::String(chunk) => chunk,
+                    value => {
+                        return Err(TypeError {
+                            expected: "string",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                // Chunks are named after their source unless given a name, as in PUC-Rio Lua.
+                let chunk_name = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => ChunkName::from_source(chunk.as_bytes()),
+                    Value::String(name) => {
+                        ChunkName::new(&StdString::from_utf8_lossy(name.as_bytes()))
+                    }
+                    value => {
+                        return Err(TypeError {
+                            expected: "string",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                let mode = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    Value::String(mode) => Some(mode),
+                    value => {
+                        return Err(TypeError {
+                            expected: "string",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                // A `nil` environment means the global table, since a closure needs a table for
+                // `_ENV`.
+                let env = match args.get(3).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => globals,
+                    Value::Table(env) => env,
+                    value => {
+                        return Err(TypeError {
+                            expected: "table",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+
+                Ok(sequence::from_fn_with(
+                    (interned_strings, chunk, chunk_name, mode, env),
+                    |mc, (interned_strings, chunk, chunk_name, mode, env)| {
+                        let mode = match &mode {
+                            Some(mode) => mode.as_bytes(),
+                            None => &b"bt"[..],
+                        };
+                        let binary = chunk.as_bytes().starts_with(b"\x1b");
+                        let loaded = if binary && !mode.contains(&b'b') {
+                            Err(load_mode_error(mc, b"binary", mode))
+                        } else if !binary && !mode.contains(&b't') {
+                            Err(load_mode_error(mc, b"text", mode))
+                        } else if binary {
+                            load_proto(mc, interned_strings, chunk.as_bytes()).map_err(Error::from)
+                        } else {
+                            compile_named(mc, interned_strings, chunk_name, chunk.as_bytes())
+                        };
+                        let closure =
+                            loaded.and_then(|proto| Ok(Closure::new(mc, proto, Some(env))?));
+                        Ok(CallbackResult::Return(match closure {
+                            Ok(closure) => vec![Value::Function(Function::Closure(closure))],
+                            Err(err) => vec![Value::Nil, err.to_value(mc, interned_strings)],
+                        }))
+                    },
+                ))
+            },
+        ),
+    )
+    .unwrap();
+
     env.set(
         mc,
         String::new_static(b"type"),
@@ -100,14 +232,20 @@ pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<
     env.set(
         mc,
         String::new_static(b"select"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |mut args| {
+            // The selected values are shifted down in place, so that the argument buffer can be
+            // reused for the results.
             match args.get(0).cloned().unwrap_or(Value::Nil).to_integer() {
-                Some(n) if n >= 1 && (n as usize) <= args.len() => Ok(CallbackResult::Return(
-                    args[n as usize..args.len()].to_vec(),
-                )),
+                Some(n) if n >= 1 && (n as usize) <= args.len() => {
+                    args.drain(..n as usize);
+                    Ok(CallbackResult::Return(args))
+                }
                 // This is required because Rust will panic if the starting slice index is out of
                 // range by more than one
-                Some(n) if n as usize > args.len() => Ok(CallbackResult::Return(vec![])),
+                Some(n) if n as usize > args.len() => {
+                    args.clear();
+                    Ok(CallbackResult::Return(args))
+                }
                 _ => Err(RuntimeError(Value::String(String::new_static(
                     b"Bad argument to select",
                 )))
@@ -117,3 +255,12 @@ pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<
     )
     .unwrap();
 }
+
+fn load_mode_error<'gc>(mc: MutationContext<'gc, '_>, kind: &[u8], mode: &[u8]) -> Error<'gc> {
+    let mut message = b"attempt to load a ".to_vec();
+    message.extend_from_slice(kind);
+    message.extend_from_slice(b" chunk (mode is '");
+    message.extend_from_slice(mode);
+    message.extend_from_slice(b"')");
+    RuntimeError(Value::String(String::new(mc, &message))).into()
+}
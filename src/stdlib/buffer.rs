@@ -0,0 +1,383 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use super::string_too_large;
+use crate::{Callback, CallbackResult, Error, Root, RuntimeError, String, Table, TypeError, Value};
+
+/// Loads the `buffer` library, where `buffer.new()` returns a string buffer object with the
+/// methods:
+///
+/// * `buf:put(...)`: appends each string or number argument
+/// * `buf:putf(format, ...)`: appends a formatted string, supporting the `%d %i %u %c %x %X %o %e
+///   %E %f %F %g %G %s %%` conversions with optional flags, width and precision
+/// * `buf:tostring()`: returns the contents as a string
+/// * `buf:reset()`: empties the buffer
+/// * `buf:len()`: returns the length of the contents in bytes
+///
+/// `put`, `putf` and `reset` return the buffer so that calls can be chained.  Appending is
+/// amortized O(1), so building a large string this way avoids the quadratic cost of repeated
+/// concatenation.  A buffer may not grow past the main thread's `Thread::max_string_len` at the
+/// time it was created, `put` and `putf` raise an error and leave the buffer unchanged instead.
+pub fn load_buffer<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let buffer = Table::new(mc);
+
+    buffer
+        .set(
+            mc,
+            String::new_static(b"new"),
+            Callback::new_sequence_with(mc, root.main_thread, |main_thread, _| {
+                Ok(sequence::from_fn_with(*main_thread, |mc, main_thread| {
+                    // The main thread is not borrowed while a callback sequence is stepped.
+                    let max_len = main_thread.max_string_len();
+                    Ok(CallbackResult::Return(vec![Value::Table(new_buffer(
+                        mc, max_len,
+                    ))]))
+                }))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"buffer"), buffer).unwrap();
+}
+
+fn new_buffer<'gc>(mc: MutationContext<'gc, '_>, max_len: usize) -> Table<'gc> {
+    let data = Rc::new(RefCell::new(Vec::new()));
+    let buffer = Table::new(mc);
+
+    let put = {
+        let data = data.clone();
+        Callback::new_immediate_with(mc, buffer, move |buffer, args| {
+            let mut data = data.borrow_mut();
+            let len = data.len();
+            for &arg in args.iter().skip(1) {
+                put_value(&mut data, arg)?;
+                check_len(&mut data, len, max_len)?;
+            }
+            Ok(CallbackResult::Return(vec![Value::Table(*buffer)]))
+        })
+    };
+    buffer.set(mc, String::new_static(b"put"), put).unwrap();
+
+    let putf = {
+        let data = data.clone();
+        Callback::new_immediate_with(mc, buffer, move |buffer, args| {
+            let format = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                Value::String(format) => format,
+                value => {
+                    return Err(TypeError {
+                        expected: "string",
+                        found: value.type_name(),
+                    }
+                    .into());
+                }
+            };
+            let mut data = data.borrow_mut();
+            let len = data.len();
+            format_into(&mut data, &format, args.get(2..).unwrap_or(&[]), max_len)?;
+            check_len(&mut data, len, max_len)?;
+            Ok(CallbackResult::Return(vec![Value::Table(*buffer)]))
+        })
+    };
+    buffer.set(mc, String::new_static(b"putf"), putf).unwrap();
+
+    let tostring = {
+        let data = data.clone();
+        Callback::new_sequence(mc, move |_| {
+            Ok(sequence::from_fn_with(
+                data.borrow().clone(),
+                |mc, contents| {
+                    Ok(CallbackResult::Return(vec![Value::String(String::new(
+                        mc, &contents,
+                    ))]))
+                },
+            ))
+        })
+    };
+    buffer
+        .set(mc, String::new_static(b"tostring"), tostring)
+        .unwrap();
+
+    let reset = {
+        let data = data.clone();
+        Callback::new_immediate_with(mc, buffer, move |buffer, _| {
+            data.borrow_mut().clear();
+            Ok(CallbackResult::Return(vec![Value::Table(*buffer)]))
+        })
+    };
+    buffer.set(mc, String::new_static(b"reset"), reset).unwrap();
+
+    let len = Callback::new_immediate(mc, move |_| {
+        Ok(CallbackResult::Return(vec![Value::Integer(
+            data.borrow().len() as i64,
+        )]))
+    });
+    buffer.set(mc, String::new_static(b"len"), len).unwrap();
+
+    buffer
+}
+
+fn put_value<'gc>(data: &mut Vec<u8>, value: Value<'gc>) -> Result<(), Error<'gc>> {
+    match value {
+        Value::String(_) | Value::Integer(_) | Value::Number(_) => Ok(value.display(data)?),
+        value => Err(TypeError {
+            expected: "string or number",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
+
+// Undoes an append that left `data` longer than `max_len`, by truncating it back to `len`.
+fn check_len<'gc>(data: &mut Vec<u8>, len: usize, max_len: usize) -> Result<(), Error<'gc>> {
+    if data.len() > max_len {
+        data.truncate(len);
+        data.shrink_to(max_len);
+        return Err(string_too_large());
+    }
+    Ok(())
+}
+
+fn format_error<'gc>(message: &'static [u8]) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(message))).into()
+}
+
+// Appends the given `printf` style format string to `out`, in the style of Lua's `string.format`.
+// Raises an error before padding `out` past `max_len` bytes, the caller must still check the final
+// length.
+pub(super) fn format_into<'gc>(
+    out: &mut Vec<u8>,
+    format: &[u8],
+    args: &[Value<'gc>],
+    max_len: usize,
+) -> Result<(), Error<'gc>> {
+    let mut args = args.iter().copied();
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            out.push(format[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+
+        let mut left_align = false;
+        let mut zero_pad = false;
+        let mut plus_sign = false;
+        let mut space_sign = false;
+        let mut alternate = false;
+        while let Some(&flag) = format.get(i) {
+            match flag {
+                b'-' => left_align = true,
+                b'0' => zero_pad = true,
+                b'+' => plus_sign = true,
+                b' ' => space_sign = true,
+                b'#' => alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let mut width: usize = 0;
+        while let Some(&d) = format.get(i).filter(|d| d.is_ascii_digit()) {
+            width = width.saturating_mul(10).saturating_add((d - b'0') as usize);
+            i += 1;
+        }
+
+        let mut precision = None;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            let mut p: usize = 0;
+            while let Some(&d) = format.get(i).filter(|d| d.is_ascii_digit()) {
+                p = p.saturating_mul(10).saturating_add((d - b'0') as usize);
+                i += 1;
+            }
+            precision = Some(p);
+        }
+        if out.len().saturating_add(width.max(precision.unwrap_or(0))) > max_len {
+            return Err(string_too_large());
+        }
+
+        let conversion = *format
+            .get(i)
+            .ok_or_else(|| format_error(b"invalid conversion at end of format"))?;
+        i += 1;
+        if conversion == b'%' {
+            out.push(b'%');
+            continue;
+        }
+
+        let arg = args
+            .next()
+            .ok_or_else(|| format_error(b"bad argument to format, no value"))?;
+        let integer = || {
+            arg.to_integer().ok_or_else(|| {
+                format_error(b"bad argument to format, number has no integer representation")
+            })
+        };
+        let number = || {
+            arg.to_number()
+                .ok_or_else(|| format_error(b"bad argument to format, number expected"))
+        };
+
+        let mut sign = Vec::new();
+        let body = match conversion {
+            b'd' | b'i' => {
+                let n = integer()?;
+                if n < 0 {
+                    sign.push(b'-');
+                }
+                let mut body = n.unsigned_abs().to_string().into_bytes();
+                if let Some(p) = precision {
+                    while body.len() < p {
+                        body.insert(0, b'0');
+                    }
+                }
+                body
+            }
+            b'u' => (integer()? as u64).to_string().into_bytes(),
+            b'c' => vec![integer()? as u8],
+            b'x' => format!("{:x}", integer()?).into_bytes(),
+            b'X' => format!("{:X}", integer()?).into_bytes(),
+            b'o' => format!("{:o}", integer()?).into_bytes(),
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let n = number()?;
+                if n.is_sign_negative() && !n.is_nan() {
+                    sign.push(b'-');
+                }
+                let body = format_float(n.abs(), conversion, precision.unwrap_or(6), alternate);
+                body.into_bytes()
+            }
+            b's' => {
+                let mut body = Vec::new();
+                arg.display(&mut body)?;
+                if let Some(p) = precision {
+                    body.truncate(p);
+                }
+                body
+            }
+            _ => return Err(format_error(b"invalid conversion in format")),
+        };
+
+        if sign.is_empty()
+            && matches!(
+                conversion,
+                b'd' | b'i' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G'
+            )
+        {
+            if plus_sign {
+                sign.push(b'+');
+            } else if space_sign {
+                sign.push(b' ');
+            }
+        }
+        if alternate && !body.is_empty() {
+            match conversion {
+                b'x' => sign.extend_from_slice(b"0x"),
+                b'X' => sign.extend_from_slice(b"0X"),
+                b'o' => sign.push(b'0'),
+                _ => {}
+            }
+        }
+
+        let len = sign.len() + body.len();
+        let padding = width.saturating_sub(len);
+        if left_align {
+            out.extend_from_slice(&sign);
+            out.extend_from_slice(&body);
+            out.resize(out.len() + padding, b' ');
+        } else if zero_pad && conversion != b's' && conversion != b'c' {
+            out.extend_from_slice(&sign);
+            out.resize(out.len() + padding, b'0');
+            out.extend_from_slice(&body);
+        } else {
+            out.resize(out.len() + padding, b' ');
+            out.extend_from_slice(&sign);
+            out.extend_from_slice(&body);
+        }
+    }
+    Ok(())
+}
+
+// Formats a non-negative float for the `e`, `f` and `g` conversions
+fn format_float(n: f64, conversion: u8, precision: usize, alternate: bool) -> StdString {
+    if n.is_infinite() {
+        return if conversion.is_ascii_uppercase() {
+            "INF"
+        } else {
+            "inf"
+        }
+        .to_owned();
+    }
+    if n.is_nan() {
+        return if conversion.is_ascii_uppercase() {
+            "NAN"
+        } else {
+            "nan"
+        }
+        .to_owned();
+    }
+
+    let s = match conversion.to_ascii_lowercase() {
+        b'f' => format!("{:.*}", precision, n),
+        b'e' => exponent_form(n, precision),
+        _ => {
+            // %g uses the shortest of %e and %f with `precision` significant digits
+            let precision = precision.max(1);
+            let exponent = if n == 0.0 {
+                0
+            } else {
+                n.log10().floor() as i32
+            };
+            let s = if exponent < -4 || exponent >= precision as i32 {
+                exponent_form(n, precision - 1)
+            } else {
+                format!(
+                    "{:.*}",
+                    (precision as i32 - 1 - exponent).max(0) as usize,
+                    n
+                )
+            };
+            if alternate {
+                s
+            } else {
+                strip_trailing_zeros(s)
+            }
+        }
+    };
+
+    if conversion.is_ascii_uppercase() {
+        s.to_ascii_uppercase()
+    } else {
+        s
+    }
+}
+
+// Rust's `{:e}` omits the exponent sign and padding that C uses
+fn exponent_form(n: f64, precision: usize) -> StdString {
+    let s = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+fn strip_trailing_zeros(s: StdString) -> StdString {
+    let (number, exponent) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s.as_str(), ""),
+    };
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{}{}", number, exponent)
+}
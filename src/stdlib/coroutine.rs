@@ -2,8 +2,8 @@ use gc_arena::MutationContext;
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 
 use crate::{
-    Callback, CallbackResult, Root, RuntimeError, String, Table, Thread, ThreadMode,
-    ThreadSequence, TypeError, Value,
+    thread::is_yieldable, Callback, CallbackResult, Root, RuntimeError, String, Table, Thread,
+    ThreadMode, ThreadSequence, TypeError, Value,
 };
 
 pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
@@ -13,7 +13,7 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
         .set(
             mc,
             String::new_static(b"create"),
-            Callback::new_sequence(mc, |args| {
+            Callback::new_sequence_with(mc, root.main_thread, |main_thread, args| {
                 let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
                     Value::Function(function) => function,
                     value => {
@@ -25,11 +25,14 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
                     }
                 };
 
-                Ok(sequence::from_fn_with(function, |mc, function| {
-                    let thread = Thread::new(mc, true);
-                    thread.start_suspended(mc, function).unwrap();
-                    Ok(CallbackResult::Return(vec![Value::Thread(thread)]))
-                }))
+                Ok(sequence::from_fn_with(
+                    (*main_thread, function),
+                    |mc, (main_thread, function)| {
+                        let thread = Thread::new_inheriting(mc, main_thread, true);
+                        thread.start_suspended(mc, function).unwrap();
+                        Ok(CallbackResult::Return(vec![Value::Thread(thread)]))
+                    },
+                ))
             }),
         )
         .unwrap();
@@ -111,6 +114,60 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
         )
         .unwrap();
 
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"reset"),
+            Callback::new_sequence(mc, |args| {
+                let thread = match args.first().cloned().unwrap_or(Value::Nil) {
+                    Value::Thread(thread) => thread,
+                    value => {
+                        return Err(TypeError {
+                            expected: "thread",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                let function = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                    Value::Function(function) => function,
+                    value => {
+                        return Err(TypeError {
+                            expected: "function",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+
+                // The thread may be the one calling `reset`, so it is only borrowed once stepped.
+                Ok(sequence::from_fn_with(
+                    (thread, function),
+                    |mc, (thread, function)| {
+                        if let Ok(()) = thread.reset(mc, function) {
+                            Ok(CallbackResult::Return(vec![Value::Thread(thread)]))
+                        } else {
+                            Err(RuntimeError(Value::String(String::new_static(
+                                b"cannot reset non-dead thread",
+                            )))
+                            .into())
+                        }
+                    },
+                ))
+            }),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"isyieldable"),
+            Callback::new_immediate(mc, |_| {
+                Ok(CallbackResult::Return(vec![Value::Boolean(is_yieldable())]))
+            }),
+        )
+        .unwrap();
+
     coroutine
         .set(
             mc,
@@ -0,0 +1,102 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{
+    Callback, CallbackResult, CountHook, Root, RuntimeError, String, Table, Thread, TypeError,
+    Value,
+};
+
+pub fn load_debug<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let debug = Table::new(mc);
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"sethook"),
+            Callback::new_sequence_with(mc, root.main_thread, |main_thread, mut args| {
+                let thread = match args.get(0) {
+                    Some(Value::Thread(thread)) => {
+                        let thread = *thread;
+                        args.remove(0);
+                        thread
+                    }
+                    _ => *main_thread,
+                };
+
+                let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    Value::Function(function) => Some(function),
+                    value => {
+                        return Err(TypeError {
+                            expected: "function or nil",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+
+                if let Some(Value::String(mask)) = args.get(1) {
+                    if mask.as_bytes().iter().any(|b| b"crl".contains(b)) {
+                        return Err(RuntimeError(Value::String(String::new_static(
+                            b"only count hooks are supported",
+                        )))
+                        .into());
+                    }
+                }
+
+                let count = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => 0,
+                    value => match value.to_integer() {
+                        Some(count) if count >= 0 => count.min(u32::MAX as i64) as u32,
+                        _ => {
+                            return Err(TypeError {
+                                expected: "non-negative integer",
+                                found: value.type_name(),
+                            }
+                            .into());
+                        }
+                    },
+                };
+
+                let hook = match function {
+                    Some(function) if count > 0 => Some(CountHook { function, count }),
+                    _ => None,
+                };
+
+                Ok(sequence::from_fn_with(
+                    (thread, hook),
+                    |mc, (thread, hook)| {
+                        thread.set_count_hook(mc, hook);
+                        Ok(CallbackResult::Return(vec![]))
+                    },
+                ))
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"gethook"),
+            Callback::new_sequence_with(mc, root.main_thread, |main_thread, args| {
+                let thread = match args.get(0) {
+                    Some(Value::Thread(thread)) => *thread,
+                    _ => *main_thread,
+                };
+
+                Ok(sequence::from_fn_with(thread, |_, thread: Thread| {
+                    Ok(CallbackResult::Return(match thread.count_hook() {
+                        Some(hook) => vec![
+                            Value::Function(hook.function),
+                            Value::String(String::new_static(b"")),
+                            Value::Integer(hook.count.into()),
+                        ],
+                        None => vec![Value::Nil],
+                    }))
+                }))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"debug"), debug).unwrap();
+}
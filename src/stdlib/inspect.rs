@@ -0,0 +1,42 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Root, RuntimeError, String, Table, Value};
+
+/// The default number of nested table levels printed by `inspect`.
+const DEFAULT_DEPTH: i64 = 8;
+
+/// Loads the debugging helper `inspect(value [, depth])`, which returns the same string as
+/// `Value::debug_fmt`.  This is not loaded by default.
+pub fn load_inspect<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    env.set(
+        mc,
+        String::new_static(b"inspect"),
+        Callback::new_sequence_with(mc, root.interned_strings, |interned_strings, args| {
+            let value = args.get(0).cloned().unwrap_or(Value::Nil);
+            let depth = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => DEFAULT_DEPTH,
+                depth => match depth.to_integer() {
+                    Some(depth) if depth >= 0 => depth,
+                    _ => {
+                        return Err(RuntimeError(Value::String(String::new_static(
+                            b"Bad argument to inspect",
+                        )))
+                        .into());
+                    }
+                },
+            };
+
+            Ok(sequence::from_fn_with(
+                (*interned_strings, value),
+                move |mc, (interned_strings, value)| {
+                    let s = value.debug_fmt(depth as usize);
+                    Ok(CallbackResult::Return(vec![Value::String(
+                        interned_strings.new_string(mc, s.as_bytes()),
+                    )]))
+                },
+            ))
+        }),
+    )
+    .unwrap();
+}
@@ -1,20 +1,49 @@
 use gc_arena::MutationContext;
 
-use crate::{Callback, CallbackResult, Root, RuntimeError, String, Table, Value};
+use crate::{
+    Callback, CallbackResult, Error, Replay, Root, RuntimeError, String, Table, TypeError, Value,
+};
 
 use rand::{FromEntropy, Rng, SeedableRng};
 use rand_xoshiro::Xoshiro256StarStar;
-use std::{cell::RefCell, ops::DerefMut, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
-pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    load_math_inner(mc, root, env, None)
+}
+
+/// The same as `load_math`, but the random number generator is seeded through `replay` the first
+/// time `math.random` is called, so that a replayed run draws the same numbers as the recorded
+/// one.
+pub fn load_math_with_replay<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    env: Table<'gc>,
+    replay: Replay,
+) {
+    load_math_inner(mc, root, env, Some(replay))
+}
+
+fn load_math_inner<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    env: Table<'gc>,
+    replay: Option<Replay>,
+) {
     let math = Table::new(mc);
-    let seeded_rng: Rc<RefCell<Xoshiro256StarStar>> =
-        Rc::new(RefCell::new(Xoshiro256StarStar::from_entropy()));
+    let string_coercion = root.main_thread.string_coercion_setting();
+    // With a `Replay`, the generator is only created once it is needed, so that a script which
+    // never calls `math.random` records no seed.
+    let seeded_rng: Rc<RefCell<Option<Xoshiro256StarStar>>> = Rc::new(RefCell::new(match replay {
+        Some(_) => None,
+        None => Some(Xoshiro256StarStar::from_entropy()),
+    }));
 
     math.set(
         mc,
         String::new_static(b"abs"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil) {
                 Value::Integer(a) => Ok(CallbackResult::Return(vec![Value::Integer(a.abs())])),
                 a => match a.to_number() {
@@ -32,7 +61,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"acos"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.acos())])),
                 _ => Err(
@@ -46,7 +76,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"asin"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.asin())])),
                 _ => Err(
@@ -60,7 +91,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"atan"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.atan())])),
                 _ => Err(
@@ -74,7 +106,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"atan2"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
@@ -92,7 +125,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"ceil"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![
                     Value::Integer(f.ceil() as i64),
@@ -108,7 +142,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"cos"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.cos())])),
                 _ => Err(
@@ -122,7 +157,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"cosh"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.cosh())])),
                 _ => Err(
@@ -136,7 +172,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"deg"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.to_degrees())])),
                 _ => Err(
@@ -150,7 +187,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"exp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(
                     std::f64::consts::E.powf(f),
@@ -166,7 +204,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"floor"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Integer(
                     f.floor() as i64
@@ -183,7 +222,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"fmod"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
@@ -207,7 +247,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"frexp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) if f.is_finite() => {
                     let bits = f.to_bits();
@@ -246,7 +287,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"ldexp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
@@ -266,7 +308,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"log"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.ln())])),
                 _ => Err(
@@ -280,7 +323,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"log10"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.log10())])),
                 _ => Err(
@@ -295,11 +339,13 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"max"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             if args.len() == 0 {
-                return Err(
-                    RuntimeError(Value::String(String::new_static(b"Bad argument to max"))).into(),
-                );
+                return Err(RuntimeError(Value::String(String::new_static(
+                    b"Bad argument to max",
+                )))
+                .into());
             }
 
             args.iter()
@@ -326,11 +372,13 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"min"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             if args.len() == 0 {
-                return Err(
-                    RuntimeError(Value::String(String::new_static(b"Bad argument to min"))).into(),
-                );
+                return Err(RuntimeError(Value::String(String::new_static(
+                    b"Bad argument to min",
+                )))
+                .into());
             }
 
             args.iter()
@@ -358,7 +406,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"modf"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![
                     Value::Integer(f as i64 / 1),
@@ -382,7 +431,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"rad"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.to_radians())])),
                 _ => Err(
@@ -393,28 +443,33 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     )
     .unwrap();
 
-    // TODO: Random and Randomseed
     let random_rng = seeded_rng.clone();
     math.set(
         mc,
         String::new_static(b"random"),
-        Callback::new_immediate(mc, move |args| {
-            let rng = &random_rng;
+        Callback::new_immediate_with(mc, string_coercion, move |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
+            if random_rng.borrow().is_none() {
+                let seed = replay.as_ref().unwrap().random_seed(rand::random)?;
+                *random_rng.borrow_mut() = Some(Xoshiro256StarStar::seed_from_u64(seed));
+            }
+            let mut rng = random_rng.borrow_mut();
+            let rng = rng.as_mut().unwrap();
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil),
                 args.get(1).cloned().unwrap_or(Value::Nil),
             ) {
                 (Value::Nil, Value::Nil) => Ok(CallbackResult::Return(vec![Value::Number(
-                    rng.borrow_mut().gen::<f64>(),
+                    rng.gen::<f64>(),
                 )])),
                 (a, b) => {
                     if let (Some(first), Value::Nil) = (a.to_integer(), b) {
                         Ok(CallbackResult::Return(vec![Value::Integer(
-                            rng.borrow_mut().gen_range(1, first + 1),
+                            rng.gen_range(1, first + 1),
                         )]))
                     } else if let (Some(first), Some(second)) = (a.to_integer(), b.to_integer()) {
                         Ok(CallbackResult::Return(vec![Value::Integer(
-                            rng.borrow_mut().gen_range(first, second + 1),
+                            rng.gen_range(first, second + 1),
                         )]))
                     } else {
                         Err(RuntimeError(Value::String(String::new_static(
@@ -432,11 +487,12 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"randomseed"),
-        Callback::new_immediate(mc, move |args| {
+        Callback::new_immediate_with(mc, string_coercion, move |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             let rng = &randomseed_rng;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => {
-                    *(rng.borrow_mut().deref_mut()) = Xoshiro256StarStar::seed_from_u64(f as u64);
+                    *rng.borrow_mut() = Some(Xoshiro256StarStar::seed_from_u64(f as u64));
                     Ok(CallbackResult::Return(vec![]))
                 }
                 _ => Err(RuntimeError(Value::String(String::new_static(
@@ -451,7 +507,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"sin"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.sin())])),
                 _ => Err(
@@ -465,7 +522,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"sqrt"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.sqrt())])),
                 _ => Err(
@@ -479,7 +537,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"tan"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.tan())])),
                 _ => Err(
@@ -493,10 +552,15 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"tointeger"),
-        Callback::new_immediate(mc, |args| {
-            match args.get(0).cloned().unwrap_or(Value::Nil).to_integer() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Integer(f)])),
-                _ => Ok(CallbackResult::Return(vec![Value::Nil])),
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            match args.first().cloned().unwrap_or(Value::Nil) {
+                Value::String(_) if !string_coercion.get() => {
+                    Ok(CallbackResult::Return(vec![Value::Nil]))
+                }
+                value => match value.to_integer() {
+                    Some(f) => Ok(CallbackResult::Return(vec![Value::Integer(f)])),
+                    _ => Ok(CallbackResult::Return(vec![Value::Nil])),
+                },
             }
         }),
     )
@@ -522,7 +586,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"ult"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+            check_number_args(string_coercion.get(), &args)?;
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_integer(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_integer(),
@@ -540,3 +605,15 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
 
     env.set(mc, String::new_static(b"math"), math).unwrap();
 }
+
+// Unless the main thread allows string coercion, strings are not accepted as numbers.
+fn check_number_args<'gc>(string_coercion: bool, args: &[Value<'gc>]) -> Result<(), Error<'gc>> {
+    if !string_coercion && args.iter().any(|v| matches!(v, Value::String(_))) {
+        return Err(TypeError {
+            expected: "number",
+            found: "string",
+        }
+        .into());
+    }
+    Ok(())
+}
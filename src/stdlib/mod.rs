@@ -1,7 +1,39 @@
 mod base;
+mod buffer;
 mod coroutine;
+mod debug;
+mod inspect;
 mod math;
+mod package;
+mod pattern;
+mod string;
+mod table;
+mod test;
+mod timer;
 
 pub use base::load_base;
+pub use buffer::load_buffer;
 pub use coroutine::load_coroutine;
-pub use math::load_math;
+pub use debug::load_debug;
+pub use inspect::load_inspect;
+pub use math::{load_math, load_math_with_replay};
+pub use package::{
+    load_package, load_package_with_loader, path_candidates, search_path, ChunkSource,
+    DEFAULT_PACKAGE_PATH,
+};
+pub use pattern::{CharClass, CharClasses};
+pub use string::load_string;
+pub use table::load_table;
+pub use test::load_test;
+pub use timer::load_timer;
+
+use crate::{Error, RuntimeError, String, Value};
+
+// The error raised by library functions that would create a string longer than the main thread's
+// `Thread::max_string_len`.
+pub(crate) fn string_too_large<'gc>() -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(
+        b"resulting string too large",
+    )))
+    .into()
+}
@@ -0,0 +1,317 @@
+use std::fs::{self, File};
+use std::path::{MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence as sequence;
+
+use crate::{
+    compile_named, load_proto, Callback, CallbackResult, ChunkName, Closure, Continuation, Error,
+    Function, Root, RuntimeError, String, Table, TypeError, Value,
+};
+
+/// The default for `package.path`, modules are looked up relative to the current directory.
+pub const DEFAULT_PACKAGE_PATH: &str = "./?.lua;./?/init.lua";
+
+/// A module supplied by the host through `load_package_with_loader`.
+#[derive(Collect)]
+#[collect(require_static)]
+pub enum ChunkSource {
+    /// Lua source code, which is compiled and run like a file found on `package.path`.
+    Source(Vec<u8>),
+    /// A prototype written by `dump_proto`, such as the output of the `include_lua!` macro, which
+    /// is verified and run.
+    Bytecode(Vec<u8>),
+    /// A module implemented in Rust, called to create the value of the module.
+    Rust(for<'gc> fn(MutationContext<'gc, '_>, Root<'gc>) -> Value<'gc>),
+}
+
+/// Loads the `package` library and the global `require` function:
+///
+/// * `require(name)`: loads the module `name` if it is not already in `package.loaded`, by running
+///   the first file found on `package.path` with `name` as its argument.  The value the file
+///   returns, or true if it returns nothing, is stored in `package.loaded` and returned.
+/// * `package.loaded`: every module loaded by `require`, by name
+/// * `package.path`: the templates searched for Lua modules, `DEFAULT_PACKAGE_PATH` to begin with
+/// * `package.config`: the separators used in paths, as in PUC-Rio Lua
+/// * `package.searchpath(name, path, sep, rep)`: the first file named by a template in `path`
+///   that can be opened for reading, or nil and a message listing every file tried
+///
+/// `require` and `package.searchpath` read the file system of the host, so this is not loaded by
+/// default.
+pub fn load_package<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    load_package_inner(mc, root, env, None)
+}
+
+/// The same as `load_package`, but `require` asks `loader` for each module before searching
+/// `package.path`, so that the host can serve modules from an asset pack, over the network or
+/// from Rust without touching the file system.  Modules that `loader` returns None for are
+/// searched for as usual, so a `package.path` of the empty string limits `require` to the modules
+/// of the host.
+pub fn load_package_with_loader<'gc, F>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    env: Table<'gc>,
+    loader: F,
+) where
+    F: 'static + Fn(&str) -> Option<ChunkSource>,
+{
+    load_package_inner(mc, root, env, Some(Rc::new(loader)))
+}
+
+// Asks the host for the source of a module by name, before `package.path` is searched.
+type ModuleLoader = dyn Fn(&str) -> Option<ChunkSource>;
+
+fn load_package_inner<'gc>(
+    mc: MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    env: Table<'gc>,
+    loader: Option<Rc<ModuleLoader>>,
+) {
+    let package = Table::new(mc);
+    let loaded = Table::new(mc);
+
+    package
+        .set(mc, String::new_static(b"loaded"), loaded)
+        .unwrap();
+
+    package
+        .set(
+            mc,
+            String::new_static(b"path"),
+            String::new_static(DEFAULT_PACKAGE_PATH.as_bytes()),
+        )
+        .unwrap();
+
+    package
+        .set(
+            mc,
+            String::new_static(b"config"),
+            Value::String(String::new_static(if MAIN_SEPARATOR == '\\' {
+                b"\\\n;\n?\n!\n-\n"
+            } else {
+                b"/\n;\n?\n!\n-\n"
+            })),
+        )
+        .unwrap();
+
+    package
+        .set(
+            mc,
+            String::new_static(b"searchpath"),
+            Callback::new_sequence(mc, |args| {
+                let name = string_arg(&args, 0, None)?;
+                let path = string_arg(&args, 1, None)?;
+                let sep = string_arg(&args, 2, Some("."))?;
+                let rep = string_arg(&args, 3, Some(MAIN_SEPARATOR_STR))?;
+                let found = search_path(&name, &path, &sep, &rep);
+                Ok(sequence::from_fn_with(found, |mc, found| {
+                    Ok(CallbackResult::Return(match found {
+                        Ok(file) => vec![Value::String(String::new(mc, file.as_bytes()))],
+                        Err(tried) => {
+                            let message = tried
+                                .iter()
+                                .map(|file| format!("no file '{}'", file))
+                                .collect::<Vec<_>>()
+                                .join("\n\t");
+                            vec![
+                                Value::Nil,
+                                Value::String(String::new(mc, message.as_bytes())),
+                            ]
+                        }
+                    }))
+                }))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"package"), package)
+        .unwrap();
+
+    env.set(
+        mc,
+        String::new_static(b"require"),
+        Callback::new_sequence_with(mc, (root, package), move |&(root, package), args| {
+            let name = match args.first().cloned().unwrap_or(Value::Nil) {
+                Value::String(name) => name,
+                value => {
+                    return Err(TypeError {
+                        expected: "string",
+                        found: value.type_name(),
+                    }
+                    .into());
+                }
+            };
+            let loaded = match package.get(String::new_static(b"loaded")) {
+                Value::Table(loaded) => loaded,
+                _ => return Err(runtime_error(b"'package.loaded' must be a table")),
+            };
+            let module = loaded.get(name);
+            let found = if module == Value::Nil {
+                Some(find_module(loader.as_deref(), package, name)?)
+            } else {
+                None
+            };
+
+            Ok(sequence::from_fn_with(
+                (root, loaded, name, module, found),
+                |mc, (root, loaded, name, module, found)| {
+                    let proto = match found {
+                        None => return Ok(CallbackResult::Return(vec![module])),
+                        Some(Ok((ChunkSource::Source(source), chunk_name))) => {
+                            compile_named(mc, root.interned_strings, chunk_name, &source[..])?
+                        }
+                        Some(Ok((ChunkSource::Bytecode(bytecode), _))) => {
+                            load_proto(mc, root.interned_strings, &bytecode)?
+                        }
+                        Some(Ok((ChunkSource::Rust(open), _))) => {
+                            let module = open(mc, root);
+                            return Ok(CallbackResult::Return(vec![store_module(
+                                mc, loaded, name, module,
+                            )?]));
+                        }
+                        Some(Err(message)) => {
+                            return Err(RuntimeError(Value::String(String::new(
+                                mc,
+                                message.as_bytes(),
+                            )))
+                            .into());
+                        }
+                    };
+                    let closure = Closure::new(mc, proto, Some(root.globals))?;
+                    Ok(CallbackResult::TailCall {
+                        function: Function::Closure(closure),
+                        args: vec![Value::String(name)],
+                        continuation: Continuation::new_sequence_with(
+                            (loaded, name),
+                            |(loaded, name), res| {
+                                let module = res?.into_iter().next().unwrap_or(Value::Nil);
+                                Ok(sequence::from_fn_with(
+                                    (loaded, name, module),
+                                    |mc, (loaded, name, module)| {
+                                        Ok(CallbackResult::Return(vec![store_module(
+                                            mc, loaded, name, module,
+                                        )?]))
+                                    },
+                                ))
+                            },
+                        ),
+                    })
+                },
+            ))
+        }),
+    )
+    .unwrap();
+}
+
+// Finds the named module, first by asking the host loader (if there is one) and then by searching
+// `package.path`, along with the name of its chunk: the file it was found in, or the name of the
+// module if it came from the host.  If the module cannot be found, returns the message of the error
+// to raise.
+fn find_module<'gc>(
+    loader: Option<&ModuleLoader>,
+    package: Table<'gc>,
+    name: String<'gc>,
+) -> Result<Result<(ChunkSource, ChunkName), StdString>, Error<'gc>> {
+    let name = StdString::from_utf8_lossy(name.as_bytes()).into_owned();
+    if let Some(source) = loader.and_then(|loader| loader(&name)) {
+        return Ok(Ok((source, ChunkName::new(&format!("={}", name)))));
+    }
+
+    let path = match package.get(String::new_static(b"path")) {
+        Value::String(path) => StdString::from_utf8_lossy(path.as_bytes()).into_owned(),
+        _ => return Err(runtime_error(b"'package.path' must be a string")),
+    };
+    match search_path(&name, &path, ".", MAIN_SEPARATOR_STR) {
+        Ok(file) => Ok(Ok((
+            ChunkSource::Source(fs::read(&file)?),
+            ChunkName::file(&file),
+        ))),
+        Err(tried) => {
+            let mut message = format!("module '{}' not found:", name);
+            for file in tried {
+                message.push_str(&format!("\n\tno file '{}'", file));
+            }
+            Ok(Err(message))
+        }
+    }
+}
+
+// Records the value returned by a module in `package.loaded`, returning what `require` returns.  A
+// module that returns nothing may have stored itself in `package.loaded`, otherwise it is recorded
+// as true.
+fn store_module<'gc>(
+    mc: MutationContext<'gc, '_>,
+    loaded: Table<'gc>,
+    name: String<'gc>,
+    module: Value<'gc>,
+) -> Result<Value<'gc>, Error<'gc>> {
+    let module = match (module, loaded.get(name)) {
+        (Value::Nil, Value::Nil) => Value::Boolean(true),
+        (Value::Nil, stored) => stored,
+        (module, _) => module,
+    };
+    loaded.set(mc, name, module)?;
+    Ok(module)
+}
+
+fn runtime_error<'gc>(message: &'static [u8]) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(message))).into()
+}
+
+/// Expands the `;`-separated templates of a search path such as `package.path` for the module
+/// `name`, in order.  Every occurrence of `sep` in `name` is first replaced with `rep`, so that
+/// with the defaults of `package.searchpath` (`.` and the directory separator) the module `a.b`
+/// becomes `a/b`, and then every `?` in each template is replaced with the result.  Empty
+/// templates are skipped.
+pub fn path_candidates(name: &str, path: &str, sep: &str, rep: &str) -> Vec<StdString> {
+    let name = if sep.is_empty() {
+        name.to_owned()
+    } else {
+        name.replace(sep, rep)
+    };
+    path.split(';')
+        .filter(|template| !template.is_empty())
+        .map(|template| template.replace('?', &name))
+        .collect()
+}
+
+/// Returns the first of the `path_candidates` that names a file which can be opened for reading,
+/// as `package.searchpath` does.  If there is none, returns every candidate that was tried.
+pub fn search_path(
+    name: &str,
+    path: &str,
+    sep: &str,
+    rep: &str,
+) -> Result<StdString, Vec<StdString>> {
+    let candidates = path_candidates(name, path, sep, rep);
+    match candidates.iter().find(|file| is_readable(file)) {
+        Some(file) => Ok(file.clone()),
+        None => Err(candidates),
+    }
+}
+
+// Directories can be opened on some platforms, but not read.
+fn is_readable(file: &str) -> bool {
+    match File::open(file) {
+        Ok(file) => file.metadata().is_ok_and(|m| !m.is_dir()),
+        Err(_) => false,
+    }
+}
+
+fn string_arg<'gc>(
+    args: &[Value<'gc>],
+    i: usize,
+    default: Option<&str>,
+) -> Result<StdString, Error<'gc>> {
+    match (args.get(i).cloned().unwrap_or(Value::Nil), default) {
+        (Value::String(s), _) => Ok(StdString::from_utf8_lossy(s.as_bytes()).into_owned()),
+        (Value::Nil, Some(default)) => Ok(default.to_owned()),
+        (value, _) => Err(TypeError {
+            expected: "string",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
@@ -0,0 +1,474 @@
+use std::ops::{Range, RangeInclusive};
+
+use gc_arena::Collect;
+
+/// A character class that bytes can be added to, see `CharClasses::extend`.
+///
+/// The pattern classes are made of these: `%a` is `Upper` and `Lower`, `%w` is letters and
+/// `Digit`, and `%g` is letters, digits and `Punct`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CharClass {
+    Upper,
+    Lower,
+    Digit,
+    HexDigit,
+    Space,
+    Punct,
+    Control,
+}
+
+impl CharClass {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The bytes in each character class of `string` library patterns, such as `%a` and `%s`.
+///
+/// C Lua takes its classes from the C locale of the host process, so the same pattern can match
+/// differently on different machines.  These classes are fixed instead: by default they hold
+/// exactly the characters of the "C" locale (`%a` is `isalpha`, `%s` is `isspace`, and so on), and
+/// bytes above 127 are in no class.  Scripts written for another single byte locale can have its
+/// bytes added with `CharClasses::extend`, or use `CharClasses::latin1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct CharClasses {
+    // The `CharClass` bits of every byte.
+    classes: [u8; 256],
+}
+
+impl Default for CharClasses {
+    fn default() -> CharClasses {
+        CharClasses::ascii()
+    }
+}
+
+impl CharClasses {
+    /// The classes of the "C" locale, the default.
+    pub fn ascii() -> CharClasses {
+        let mut classes = [0; 256];
+        for (c, bits) in classes.iter_mut().enumerate().take(128) {
+            let c = c as u8;
+            let in_classes = [
+                (CharClass::Upper, c.is_ascii_uppercase()),
+                (CharClass::Lower, c.is_ascii_lowercase()),
+                (CharClass::Digit, c.is_ascii_digit()),
+                (CharClass::HexDigit, c.is_ascii_hexdigit()),
+                // Unlike `u8::is_ascii_whitespace`, this includes the vertical tab.
+                (CharClass::Space, c == b' ' || (b'\t'..=b'\r').contains(&c)),
+                (CharClass::Punct, c.is_ascii_punctuation()),
+                (CharClass::Control, c.is_ascii_control()),
+            ];
+            for &(class, is_in) in &in_classes {
+                if is_in {
+                    *bits |= class.bit();
+                }
+            }
+        }
+        CharClasses { classes }
+    }
+
+    /// The classes of a Latin-1 (ISO 8859-1) locale, which adds the accented letters, the
+    /// punctuation from `¡` to `¿` along with `×` and `÷`, and the C1 control characters.
+    pub fn latin1() -> CharClasses {
+        CharClasses::ascii()
+            .extend(CharClass::Upper, 0xc0..=0xd6)
+            .extend(CharClass::Upper, 0xd8..=0xde)
+            .extend(CharClass::Lower, 0xdf..=0xf6)
+            .extend(CharClass::Lower, 0xf8..=0xff)
+            .extend(CharClass::Punct, 0xa1..=0xbf)
+            .extend(CharClass::Punct, 0xd7..=0xd7)
+            .extend(CharClass::Punct, 0xf7..=0xf7)
+            .extend(CharClass::Control, 0x80..=0x9f)
+    }
+
+    /// Adds every byte in `range` to `class`.
+    pub fn extend(mut self, class: CharClass, range: RangeInclusive<u8>) -> CharClasses {
+        for c in range {
+            self.classes[c as usize] |= class.bit();
+        }
+        self
+    }
+
+    pub fn contains(&self, class: CharClass, c: u8) -> bool {
+        self.classes[c as usize] & class.bit() != 0
+    }
+
+    // Whether `c` matches the pattern class `%cl`, or is `cl` itself if that is not a class letter.
+    fn matches(&self, c: u8, cl: u8) -> bool {
+        let bits = self.classes[c as usize];
+        let letter = CharClass::Upper.bit() | CharClass::Lower.bit();
+        let mask = match cl.to_ascii_lowercase() {
+            b'a' => letter,
+            b'c' => CharClass::Control.bit(),
+            b'd' => CharClass::Digit.bit(),
+            b'g' => letter | CharClass::Digit.bit() | CharClass::Punct.bit(),
+            b'l' => CharClass::Lower.bit(),
+            b'p' => CharClass::Punct.bit(),
+            b's' => CharClass::Space.bit(),
+            b'u' => CharClass::Upper.bit(),
+            b'w' => letter | CharClass::Digit.bit(),
+            b'x' => CharClass::HexDigit.bit(),
+            _ => return cl == c,
+        };
+        ((bits & mask) != 0) != cl.is_ascii_uppercase()
+    }
+}
+
+/// A capture of a successful match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Capture {
+    /// A position capture `()`, holding a 0-based byte index.
+    Position(usize),
+    Bytes(Range<usize>),
+}
+
+/// A successful match: the range of the whole match and its captures.
+pub(crate) type PatternMatch = (Range<usize>, Vec<Capture>);
+
+/// Finds the first match of the Lua pattern `pattern` in `s` that starts at or after the byte index
+/// `init`, returning the range of the match and its captures, or an error message if the pattern
+/// is malformed.
+pub(crate) fn find_pattern(
+    s: &[u8],
+    pattern: &[u8],
+    init: usize,
+    classes: &CharClasses,
+) -> Result<Option<PatternMatch>, String> {
+    let (anchor, pattern_start) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut state = MatchState {
+        src: s,
+        pattern,
+        classes,
+        depth: MAX_MATCH_DEPTH,
+        captures: Vec::new(),
+    };
+    let mut start = init;
+    while start <= s.len() {
+        state.captures.clear();
+        if let Some(end) = state.do_match(start, pattern_start)? {
+            let captures = state
+                .captures
+                .iter()
+                .map(|&(capture_start, len)| match len {
+                    CaptureLen::Position => Ok(Capture::Position(capture_start)),
+                    CaptureLen::Len(len) => Ok(Capture::Bytes(capture_start..capture_start + len)),
+                    CaptureLen::Unfinished => Err("unfinished capture".to_owned()),
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(Some((start..end, captures)));
+        }
+        if anchor {
+            break;
+        }
+        start += 1;
+    }
+    Ok(None)
+}
+
+/// Whether `pattern` contains any characters with a special meaning in patterns, so that it cannot
+/// be searched for as a plain string.
+pub(crate) fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| SPECIALS.contains(c))
+}
+
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+const MAX_CAPTURES: usize = 32;
+const MAX_MATCH_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CaptureLen {
+    Len(usize),
+    Position,
+    Unfinished,
+}
+
+// A port of the matcher in the reference implementation's `lstrlib.c`, working with indexes into
+// the subject and the pattern rather than pointers.
+struct MatchState<'a> {
+    src: &'a [u8],
+    pattern: &'a [u8],
+    classes: &'a CharClasses,
+    // The recursion left before the pattern is considered too complex.
+    depth: usize,
+    captures: Vec<(usize, CaptureLen)>,
+}
+
+impl<'a> MatchState<'a> {
+    // Matches the pattern from index `p` against the subject from index `s`, returning the end of
+    // the match.
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if self.depth == 0 {
+            return Err("pattern too complex".to_owned());
+        }
+        self.depth -= 1;
+        let res = self.match_here(s, p);
+        self.depth += 1;
+        res
+    }
+
+    fn match_here(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        loop {
+            if p == self.pattern.len() {
+                return Ok(Some(s));
+            }
+
+            match self.pattern[p] {
+                b'(' => {
+                    return if self.pattern.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pattern.len() => {
+                    return Ok(if s == self.src.len() { Some(s) } else { None });
+                }
+                b'%' if p + 1 < self.pattern.len() => match self.pattern[p + 1] {
+                    b'b' => match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    },
+                    b'f' => {
+                        p += 2;
+                        if self.pattern.get(p) != Some(&b'[') {
+                            return Err("missing '[' after '%f' in pattern".to_owned());
+                        }
+                        let ep = self.class_end(p)?;
+                        let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                        let current = self.src.get(s).cloned().unwrap_or(0);
+                        if !self.match_bracket_class(previous, p, ep - 1)
+                            && self.match_bracket_class(current, p, ep - 1)
+                        {
+                            p = ep;
+                            continue;
+                        }
+                        return Ok(None);
+                    }
+                    d if d.is_ascii_digit() => match self.match_capture(s, d)? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    },
+                    _ => {}
+                },
+                _ => {}
+            }
+
+            let ep = self.class_end(p)?;
+            let matched = s < self.src.len() && self.single_match(self.src[s], p, ep);
+            match self.pattern.get(ep) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                            return Ok(Some(end));
+                        }
+                    }
+                    p = ep + 1;
+                }
+                Some(b'+') => {
+                    return if matched {
+                        self.max_expand(s + 1, p, ep)
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Some(b'*') => return self.max_expand(s, p, ep),
+                Some(b'-') => return self.min_expand(s, p, ep),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    // Matches as many repetitions of the single character class at `p` as possible.
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        let mut i = 0;
+        while s + i < self.src.len() && self.single_match(self.src[s + i], p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    // Matches as few repetitions of the single character class at `p` as possible.
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if s < self.src.len() && self.single_match(self.src[s], p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, String> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err("too many captures".to_owned());
+        }
+        self.captures.push((s, len));
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures.pop();
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let l = self
+            .captures
+            .iter()
+            .rposition(|&(_, len)| len == CaptureLen::Unfinished)
+            .ok_or_else(|| "invalid pattern capture".to_owned())?;
+        self.captures[l].1 = CaptureLen::Len(s - self.captures[l].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures[l].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    // Matches `%bxy` where `x` and `y` are at `p`.
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if p + 1 >= self.pattern.len() {
+            return Err("malformed pattern (missing arguments to '%b')".to_owned());
+        }
+        let (open, close) = (self.pattern[p], self.pattern[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // Matches a back reference `%d` to an earlier capture.
+    fn match_capture(&self, s: usize, d: u8) -> Result<Option<usize>, String> {
+        let l = (d as usize).wrapping_sub(b'1' as usize);
+        let (start, len) = match self.captures.get(l) {
+            Some(&(start, len)) if len != CaptureLen::Unfinished => (start, len),
+            _ => return Err(format!("invalid capture index %{}", l.wrapping_add(1))),
+        };
+        // A position capture never matches, as in the reference implementation.
+        Ok(match len {
+            CaptureLen::Len(len)
+                if self.src.len() - s >= len
+                    && self.src[start..start + len] == self.src[s..s + len] =>
+            {
+                Some(s + len)
+            }
+            _ => None,
+        })
+    }
+
+    // Returns the index just past the single character class starting at `p`.
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pattern[p];
+        p += 1;
+        match c {
+            b'%' => {
+                if p >= self.pattern.len() {
+                    return Err("malformed pattern (ends with '%')".to_owned());
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // The first character of a set is never its end, so `[]]` is the set of `]`.
+                loop {
+                    if p >= self.pattern.len() {
+                        return Err("malformed pattern (missing ']')".to_owned());
+                    }
+                    let c = self.pattern[p];
+                    p += 1;
+                    if c == b'%' && p < self.pattern.len() {
+                        p += 1;
+                    }
+                    if self.pattern.get(p) == Some(&b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, c: u8, p: usize, ep: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => self.classes.matches(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // Matches the set from the `[` at `p` to the `]` at `ec`.
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut found = true;
+        if self.pattern[p + 1] == b'^' {
+            found = false;
+            p += 1;
+        }
+        loop {
+            p += 1;
+            if p >= ec {
+                return !found;
+            }
+            if self.pattern[p] == b'%' {
+                p += 1;
+                if self.classes.matches(c, self.pattern[p]) {
+                    return found;
+                }
+            } else if self.pattern[p + 1] == b'-' && p + 2 < ec {
+                p += 2;
+                if self.pattern[p - 2] <= c && c <= self.pattern[p] {
+                    return found;
+                }
+            } else if self.pattern[p] == c {
+                return found;
+            }
+        }
+    }
+}
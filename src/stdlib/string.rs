@@ -0,0 +1,425 @@
+use std::convert::TryFrom;
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use super::buffer::format_into;
+use super::pattern::{find_pattern, has_specials, Capture};
+use super::string_too_large;
+use crate::{
+    Callback, CallbackResult, Error, Root, RuntimeError, String, Table, Thread, TypeError, Value,
+};
+
+/// Loads the `string` library, and sets it as the `__index` table of the main thread's string
+/// metatable so that it can be used with method syntax, as in `("%d"):format(1)`.
+///
+/// * `string.len(s)`: the length of `s` in bytes
+/// * `string.sub(s, i, j)`: the bytes of `s` from `i` to `j` inclusive, where negative indexes count
+///   back from the end
+/// * `string.upper(s)`, `string.lower(s)`: `s` with ASCII letters converted to upper or lower case
+/// * `string.byte(s, i, j)`: the values of the bytes of `s` from `i` to `j`
+/// * `string.char(...)`: a string made of the given byte values
+/// * `string.format(format, ...)`: formats its arguments like `buf:putf` in the `buffer` library
+/// * `string.rep(s, n, sep)`: `n` copies of `s` separated by `sep`, which defaults to the empty
+///   string
+/// * `string.find(s, pattern, init, plain)`: the positions of the first match of `pattern` in `s`
+///   starting from `init`, followed by its captures, where `plain` searches for `pattern` as a
+///   plain string
+/// * `string.match(s, pattern, init)`: the captures of the first match of `pattern` in `s`, or the
+///   whole match if the pattern has no captures
+///
+/// Patterns are those of Lua 5.3, except that character classes such as `%a` do not depend on the
+/// locale of the host.  They only contain ASCII characters, unless the main thread is given other
+/// classes with `Thread::set_char_classes`.
+///
+/// `string.format` and `string.rep` raise an error instead of creating a string longer than the
+/// main thread's `Thread::max_string_len`.
+///
+/// Numbers are accepted wherever a string is expected and converted as `tostring` would, unless
+/// string coercion is disabled, see `Thread::set_string_coercion`.
+pub fn load_string<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let string = Table::new(mc);
+    let string_coercion = root.main_thread.string_coercion_setting();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"len"),
+            Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+                let s = string_arg(&args, 0, string_coercion.get())?;
+                Ok(CallbackResult::Return(vec![Value::Integer(s.len() as i64)]))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"sub"),
+            Callback::new_sequence_with(mc, string_coercion, |string_coercion, args| {
+                let s = string_arg(&args, 0, string_coercion.get())?;
+                let i = integer_arg(&args, 1, 1, string_coercion.get())?;
+                let j = integer_arg(&args, 2, -1, string_coercion.get())?;
+                let range = byte_range(s.len(), i, j);
+                Ok(new_string(s[range].to_vec()))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"upper"),
+            Callback::new_sequence_with(mc, string_coercion, |string_coercion, args| {
+                Ok(new_string(
+                    string_arg(&args, 0, string_coercion.get())?.to_ascii_uppercase(),
+                ))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"lower"),
+            Callback::new_sequence_with(mc, string_coercion, |string_coercion, args| {
+                Ok(new_string(
+                    string_arg(&args, 0, string_coercion.get())?.to_ascii_lowercase(),
+                ))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"byte"),
+            Callback::new_immediate_with(mc, string_coercion, |string_coercion, args| {
+                let s = string_arg(&args, 0, string_coercion.get())?;
+                let i = integer_arg(&args, 1, 1, string_coercion.get())?;
+                let j = integer_arg(&args, 2, i, string_coercion.get())?;
+                Ok(CallbackResult::Return(
+                    s[byte_range(s.len(), i, j)]
+                        .iter()
+                        .map(|&b| Value::Integer(b as i64))
+                        .collect(),
+                ))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"char"),
+            Callback::new_sequence_with(mc, string_coercion, |string_coercion, args| {
+                let mut bytes = Vec::with_capacity(args.len());
+                for i in 0..args.len() {
+                    match integer_arg(&args, i, 0, string_coercion.get())? {
+                        b @ 0..=255 => bytes.push(b as u8),
+                        _ => {
+                            return Err(RuntimeError(Value::String(String::new_static(
+                                b"bad argument to 'char' (value out of range)",
+                            )))
+                            .into());
+                        }
+                    }
+                }
+                Ok(new_string(bytes))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"format"),
+            Callback::new_sequence_with(
+                mc,
+                (root.main_thread, string_coercion),
+                |(main_thread, string_coercion), args| {
+                    let format = string_arg(&args, 0, string_coercion.get())?;
+                    Ok(sequence::from_fn_with(
+                        (*main_thread, format, args),
+                        |mc, (main_thread, format, args)| {
+                            let max_len = main_thread.max_string_len();
+                            let mut out = Vec::new();
+                            format_into(&mut out, &format, args.get(1..).unwrap_or(&[]), max_len)?;
+                            if out.len() > max_len {
+                                return Err(string_too_large());
+                            }
+                            Ok(CallbackResult::Return(vec![Value::String(String::new(
+                                mc, &out,
+                            ))]))
+                        },
+                    ))
+                },
+            ),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"rep"),
+            Callback::new_sequence_with(
+                mc,
+                (root.main_thread, string_coercion),
+                |(main_thread, string_coercion), args| {
+                    let s = string_arg(&args, 0, string_coercion.get())?;
+                    let n = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                        Value::Nil => {
+                            return Err(TypeError {
+                                expected: "integer",
+                                found: "nil",
+                            }
+                            .into());
+                        }
+                        _ => integer_arg(&args, 1, 0, string_coercion.get())?,
+                    };
+                    let sep = match args.get(2) {
+                        None | Some(Value::Nil) => Vec::new(),
+                        Some(_) => string_arg(&args, 2, string_coercion.get())?,
+                    };
+                    Ok(sequence::from_fn_with(
+                        (*main_thread, s, n, sep),
+                        |mc, (main_thread, s, n, sep)| {
+                            // The main thread is not borrowed while a callback sequence is stepped.
+                            let len = match repeated_len(s.len(), sep.len(), n) {
+                                Some(len) if len <= main_thread.max_string_len() => len,
+                                _ => return Err(string_too_large()),
+                            };
+                            if len == 0 {
+                                return Ok(CallbackResult::Return(vec![Value::String(
+                                    String::new_static(b""),
+                                )]));
+                            }
+                            let mut out = Vec::with_capacity(len);
+                            for i in 0..n {
+                                if i > 0 {
+                                    out.extend_from_slice(&sep);
+                                }
+                                out.extend_from_slice(&s);
+                            }
+                            Ok(CallbackResult::Return(vec![Value::String(String::new(
+                                mc, &out,
+                            ))]))
+                        },
+                    ))
+                },
+            ),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"find"),
+            Callback::new_sequence_with(
+                mc,
+                (root.main_thread, string_coercion),
+                |(main_thread, string_coercion), args| {
+                    let s = string_arg(&args, 0, string_coercion.get())?;
+                    let pattern = string_arg(&args, 1, string_coercion.get())?;
+                    let init = integer_arg(&args, 2, 1, string_coercion.get())?;
+                    let plain = args.get(3).cloned().unwrap_or(Value::Nil).to_bool();
+                    Ok(sequence::from_fn_with(
+                        (*main_thread, s, pattern, init, plain),
+                        |mc, (main_thread, s, pattern, init, plain)| {
+                            find_or_match(mc, main_thread, &s, &pattern, init, true, plain)
+                        },
+                    ))
+                },
+            ),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"match"),
+            Callback::new_sequence_with(
+                mc,
+                (root.main_thread, string_coercion),
+                |(main_thread, string_coercion), args| {
+                    let s = string_arg(&args, 0, string_coercion.get())?;
+                    let pattern = string_arg(&args, 1, string_coercion.get())?;
+                    let init = integer_arg(&args, 2, 1, string_coercion.get())?;
+                    Ok(sequence::from_fn_with(
+                        (*main_thread, s, pattern, init),
+                        |mc, (main_thread, s, pattern, init)| {
+                            find_or_match(mc, main_thread, &s, &pattern, init, false, false)
+                        },
+                    ))
+                },
+            ),
+        )
+        .unwrap();
+
+    let metatable = Table::new(mc);
+    metatable
+        .set(mc, String::new_static(b"__index"), string)
+        .unwrap();
+    root.main_thread.set_string_metatable(mc, Some(metatable));
+
+    env.set(mc, String::new_static(b"string"), string).unwrap();
+}
+
+// Returns a sequence which creates a string with the given contents, for callbacks that must
+// allocate their result.
+fn new_string<'gc>(
+    contents: Vec<u8>,
+) -> impl sequence::Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>> {
+    sequence::from_fn_with(contents, |mc, contents| {
+        Ok(CallbackResult::Return(vec![Value::String(String::new(
+            mc, &contents,
+        ))]))
+    })
+}
+
+// Implements `string.find` if `find` is true, which returns the positions of the match before its
+// captures, and otherwise `string.match`, which returns the whole match if there are no captures.
+fn find_or_match<'gc>(
+    mc: MutationContext<'gc, '_>,
+    main_thread: Thread<'gc>,
+    s: &[u8],
+    pattern: &[u8],
+    init: i64,
+    find: bool,
+    plain: bool,
+) -> Result<CallbackResult<'gc>, Error<'gc>> {
+    let init = match init_index(s.len(), init) {
+        Some(init) => init,
+        None => return Ok(CallbackResult::Return(vec![Value::Nil])),
+    };
+
+    if find && (plain || !has_specials(pattern)) {
+        let found = if pattern.is_empty() {
+            Some(0)
+        } else {
+            s[init..]
+                .windows(pattern.len())
+                .position(|window| window == pattern)
+        };
+        return Ok(CallbackResult::Return(match found {
+            Some(i) => vec![
+                Value::Integer((init + i + 1) as i64),
+                Value::Integer((init + i + pattern.len()) as i64),
+            ],
+            None => vec![Value::Nil],
+        }));
+    }
+
+    // The main thread is not borrowed while a callback sequence is stepped.
+    let classes = main_thread.char_classes();
+    let (range, captures) = match find_pattern(s, pattern, init, &classes) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(CallbackResult::Return(vec![Value::Nil])),
+        Err(message) => {
+            return Err(RuntimeError(Value::String(String::new(mc, message.as_bytes()))).into());
+        }
+    };
+    let mut results = Vec::new();
+    if find {
+        results.push(Value::Integer(range.start as i64 + 1));
+        results.push(Value::Integer(range.end as i64));
+    } else if captures.is_empty() {
+        results.push(Value::String(String::new(mc, &s[range])));
+    }
+    for capture in captures {
+        results.push(match capture {
+            Capture::Position(i) => Value::Integer(i as i64 + 1),
+            Capture::Bytes(range) => Value::String(String::new(mc, &s[range])),
+        });
+    }
+    Ok(CallbackResult::Return(results))
+}
+
+// Converts the 1-based and possibly negative Lua index `init` of `string.find` and `string.match`
+// into a byte index into a string of the given length, or None if it is past the end of the
+// string.
+fn init_index(len: usize, init: i64) -> Option<usize> {
+    let len = len as i64;
+    let init = if init < 0 {
+        (len + init + 1).max(1)
+    } else {
+        init.max(1)
+    };
+    if init > len + 1 {
+        None
+    } else {
+        Some(init as usize - 1)
+    }
+}
+
+// The length of `n` copies of a string of length `len` separated by a string of length
+// `sep_len`, or None if it does not fit in a `usize`.
+fn repeated_len(len: usize, sep_len: usize, n: i64) -> Option<usize> {
+    if n <= 0 {
+        return Some(0);
+    }
+    let n = usize::try_from(n).ok()?;
+    len.checked_mul(n)?.checked_add(sep_len.checked_mul(n - 1)?)
+}
+
+// Numbers are only accepted as strings, and strings as integers, with string coercion enabled, see
+// `Thread::set_string_coercion`.
+fn string_arg<'gc>(
+    args: &[Value<'gc>],
+    i: usize,
+    string_coercion: bool,
+) -> Result<Vec<u8>, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::String(s) => Ok(s.as_bytes().to_vec()),
+        value @ Value::Integer(_) | value @ Value::Number(_) if string_coercion => {
+            let mut s = Vec::new();
+            value.display(&mut s)?;
+            Ok(s)
+        }
+        value => Err(TypeError {
+            expected: "string",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
+
+fn integer_arg<'gc>(
+    args: &[Value<'gc>],
+    i: usize,
+    default: i64,
+    string_coercion: bool,
+) -> Result<i64, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::Nil => Ok(default),
+        Value::String(_) if !string_coercion => Err(TypeError {
+            expected: "integer",
+            found: "string",
+        }
+        .into()),
+        value => value.to_integer().ok_or_else(|| {
+            TypeError {
+                expected: "integer",
+                found: value.type_name(),
+            }
+            .into()
+        }),
+    }
+}
+
+// Converts the inclusive, 1-based and possibly negative Lua indexes `i` and `j` into a range of
+// bytes of a string of the given length, which is empty if they do not overlap the string.
+fn byte_range(len: usize, i: i64, j: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = match i {
+        i if i < 0 => (len + i + 1).max(1),
+        0 => 1,
+        i => i,
+    };
+    let end = if j < 0 { len + j + 1 } else { j.min(len) };
+    if start > end {
+        0..0
+    } else {
+        start as usize - 1..end as usize
+    }
+}
@@ -0,0 +1,36 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Root, String, Table, TypeError, Value};
+
+/// Loads the `table` library, which currently only has an extension of its own:
+///
+/// * `table.compact(t)`: releases the storage of `t` that is not in use, see `Table::shrink_to_fit`
+pub fn load_table<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let table = Table::new(mc);
+
+    table
+        .set(
+            mc,
+            String::new_static(b"compact"),
+            Callback::new_sequence(mc, |args| {
+                let table = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Table(table) => table,
+                    value => {
+                        return Err(TypeError {
+                            expected: "table",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                Ok(sequence::from_fn_with(table, |mc, table| {
+                    table.shrink_to_fit(mc);
+                    Ok(CallbackResult::Return(vec![]))
+                }))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"table"), table).unwrap();
+}
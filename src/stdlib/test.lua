@@ -0,0 +1,59 @@
+-- The Lua half of the `luster.test` library, run once when the library is loaded with the native
+-- helpers table as its only argument.
+
+local test, equal, format = ...
+local type, pcall, error = type, pcall, error
+local passed, failed = 0, 0
+local failures = {}
+local prefix = ""
+
+local function message_of(err)
+    if type(err) == "string" then
+        return err
+    end
+    return format(err)
+end
+
+function test.describe(name, body)
+    local outer = prefix
+    prefix = prefix .. name .. " "
+    local ok, err = pcall(body)
+    prefix = outer
+    if not ok then
+        failed = failed + 1
+        failures[#failures + 1] = outer .. name .. ": " .. message_of(err)
+    end
+end
+
+function test.it(name, body)
+    local ok, err = pcall(body)
+    if ok then
+        passed = passed + 1
+    else
+        failed = failed + 1
+        failures[#failures + 1] = prefix .. name .. ": " .. message_of(err)
+    end
+end
+
+function test.assert_eq(actual, expected, message)
+    if not equal(actual, expected) then
+        error((message or "values are not equal") .. ", expected " .. format(expected) ..
+            " but got " .. format(actual))
+    end
+end
+
+function test.assert_error(f, ...)
+    local ok = pcall(f, ...)
+    if ok then
+        error("expected an error but none was raised")
+    end
+end
+
+-- Returns the number of passed and failed tests followed by one line per failure
+function test.report()
+    local lines = ""
+    for i = 1, #failures do
+        lines = lines .. failures[i] .. "\n"
+    end
+    return passed, failed, lines
+end
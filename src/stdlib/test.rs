@@ -0,0 +1,78 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{
+    compile, values_deep_equal, Callback, CallbackResult, Closure, Function, Root, String, Table,
+    Thread, ThreadMode, Value,
+};
+
+/// The depth that tables are printed to in assertion failure messages.
+const FORMAT_DEPTH: usize = 2;
+
+/// Loads the `luster.test` library, a minimal test framework with `describe`, `it`, `assert_eq`,
+/// `assert_error` and `report`, used by the `luster test` runner.  This is not loaded by default.
+pub fn load_test<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let test = Table::new(mc);
+
+    let equal = Callback::new_immediate(mc, |args| {
+        let a = args.get(0).cloned().unwrap_or(Value::Nil);
+        let b = args.get(1).cloned().unwrap_or(Value::Nil);
+        Ok(CallbackResult::Return(vec![Value::Boolean(
+            values_deep_equal(a, b),
+        )]))
+    });
+
+    let format =
+        Callback::new_sequence_with(mc, root.interned_strings, |interned_strings, args| {
+            let value = args.get(0).cloned().unwrap_or(Value::Nil);
+            Ok(sequence::from_fn_with(
+                (*interned_strings, value),
+                |mc, (interned_strings, value)| {
+                    let s = value.debug_fmt(FORMAT_DEPTH);
+                    Ok(CallbackResult::Return(vec![Value::String(
+                        interned_strings.new_string(mc, s.as_bytes()),
+                    )]))
+                },
+            ))
+        });
+
+    let closure = Closure::new(
+        mc,
+        compile(mc, root.interned_strings, &include_bytes!("test.lua")[..])
+            .expect("test library must compile"),
+        Some(env),
+    )
+    .unwrap();
+
+    // The library only defines functions, so it always finishes without calling back into the
+    // host.
+    let thread = Thread::new_inheriting(mc, root.main_thread, false);
+    thread
+        .start(
+            mc,
+            Function::Closure(closure),
+            &[
+                Value::Table(test),
+                Value::Function(Function::Callback(equal)),
+                Value::Function(Function::Callback(format)),
+            ],
+        )
+        .unwrap();
+    while thread.mode() == ThreadMode::Running {
+        thread.step(mc).unwrap();
+    }
+    thread
+        .take_results(mc)
+        .unwrap()
+        .expect("test library must load");
+
+    let luster = match env.get(String::new_static(b"luster")) {
+        Value::Table(luster) => luster,
+        _ => {
+            let luster = Table::new(mc);
+            env.set(mc, String::new_static(b"luster"), luster).unwrap();
+            luster
+        }
+    };
+    luster.set(mc, String::new_static(b"test"), test).unwrap();
+}
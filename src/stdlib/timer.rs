@@ -0,0 +1,130 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{
+    Callback, CallbackResult, Error, Function, Root, String, Table, TaskId, TypeError, Value,
+};
+
+/// Loads the `timer` library, which schedules tasks on `root.executor`:
+///
+/// * `timer.after(seconds, f)`: calls `f` once `seconds` have passed, returning a task id
+/// * `timer.every(seconds, f)`: calls `f` every `seconds` until it returns false, returning a task
+///   id
+/// * `timer.cancel(id)`: cancels a task, returning whether it was still scheduled
+/// * `timer.sleep(seconds)`: suspends the calling task until `seconds` have passed
+/// * `timer.now()`: the current time of the executor's clock
+///
+/// Time only passes when the host calls `Executor::advance`, and tasks only run inside
+/// `Executor::run`.  `timer.sleep` yields, so it can only be called from an executor task (and
+/// not from inside a nested coroutine, which it would yield instead).
+pub fn load_timer<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let timer = Table::new(mc);
+    let executor = root.executor;
+
+    timer
+        .set(
+            mc,
+            String::new_static(b"after"),
+            Callback::new_sequence_with(mc, executor, |&executor, args| {
+                let seconds = seconds_arg(&args)?;
+                let function = function_arg(&args)?;
+                Ok(sequence::from_fn_with(
+                    (executor, function),
+                    move |mc, (executor, function)| {
+                        let TaskId(id) = executor.schedule(mc, function, seconds, None);
+                        Ok(CallbackResult::Return(vec![Value::Integer(id)]))
+                    },
+                ))
+            }),
+        )
+        .unwrap();
+
+    timer
+        .set(
+            mc,
+            String::new_static(b"every"),
+            Callback::new_sequence_with(mc, executor, |&executor, args| {
+                let seconds = seconds_arg(&args)?;
+                let function = function_arg(&args)?;
+                Ok(sequence::from_fn_with(
+                    (executor, function),
+                    move |mc, (executor, function)| {
+                        let TaskId(id) = executor.schedule(mc, function, seconds, Some(seconds));
+                        Ok(CallbackResult::Return(vec![Value::Integer(id)]))
+                    },
+                ))
+            }),
+        )
+        .unwrap();
+
+    timer
+        .set(
+            mc,
+            String::new_static(b"cancel"),
+            Callback::new_sequence_with(mc, executor, |&executor, args| {
+                let id = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Integer(id) => id,
+                    value => {
+                        return Err(TypeError {
+                            expected: "task id",
+                            found: value.type_name(),
+                        }
+                        .into());
+                    }
+                };
+                Ok(sequence::from_fn_with(executor, move |mc, executor| {
+                    Ok(CallbackResult::Return(vec![Value::Boolean(
+                        executor.cancel(mc, TaskId(id)),
+                    )]))
+                }))
+            }),
+        )
+        .unwrap();
+
+    timer
+        .set(
+            mc,
+            String::new_static(b"sleep"),
+            Callback::new_immediate(mc, |args| {
+                Ok(CallbackResult::Yield(vec![Value::Number(seconds_arg(
+                    &args,
+                )?)]))
+            }),
+        )
+        .unwrap();
+
+    timer
+        .set(
+            mc,
+            String::new_static(b"now"),
+            Callback::new_immediate_with(mc, executor, |executor, _| {
+                Ok(CallbackResult::Return(vec![Value::Number(executor.now())]))
+            }),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"timer"), timer).unwrap();
+}
+
+fn seconds_arg<'gc>(args: &[Value<'gc>]) -> Result<f64, Error<'gc>> {
+    let value = args.get(0).cloned().unwrap_or(Value::Nil);
+    match value.to_number() {
+        Some(seconds) if seconds >= 0.0 => Ok(seconds),
+        _ => Err(TypeError {
+            expected: "non-negative number",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
+
+fn function_arg<'gc>(args: &[Value<'gc>]) -> Result<Function<'gc>, Error<'gc>> {
+    match args.get(1).cloned().unwrap_or(Value::Nil) {
+        Value::Function(function) => Ok(function),
+        value => Err(TypeError {
+            expected: "function",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
@@ -15,6 +15,7 @@ use crate::Value;
 #[collect(require_static)]
 pub enum StringError {
     Concat { bad_type: &'static str },
+    TooLarge,
 }
 
 impl StdError for StringError {}
@@ -23,6 +24,7 @@ impl fmt::Display for StringError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             StringError::Concat { bad_type } => write!(fmt, "cannot concat {}", bad_type),
+            StringError::TooLarge => write!(fmt, "resulting string too large"),
         }
     }
 }
@@ -56,9 +58,12 @@ impl<'gc> String<'gc> {
         String::Static(s)
     }
 
+    /// Concatenates the given values, raising `StringError::TooLarge` rather than creating a string
+    /// longer than `max_len` bytes.
     pub fn concat(
         mc: MutationContext<'gc, '_>,
         values: &[Value<'gc>],
+        max_len: usize,
     ) -> Result<String<'gc>, StringError> {
         let mut bytes = Vec::new();
         for value in values {
@@ -67,7 +72,13 @@ impl<'gc> String<'gc> {
                 Value::Boolean(b) => write!(&mut bytes, "{}", b).unwrap(),
                 Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
                 Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
-                Value::String(s) => bytes.extend(s.as_bytes()),
+                Value::String(s) => {
+                    // Checked before copying, so that a long result is never allocated.
+                    if bytes.len().saturating_add(s.len()) > max_len {
+                        return Err(StringError::TooLarge);
+                    }
+                    bytes.extend(s.as_bytes());
+                }
                 Value::Table(_) => return Err(StringError::Concat { bad_type: "table" }),
                 Value::Function(_) => {
                     return Err(StringError::Concat {
@@ -79,6 +90,9 @@ impl<'gc> String<'gc> {
                 }
             }
         }
+        if bytes.len() > max_len {
+            return Err(StringError::TooLarge);
+        }
         Ok(String::Long(Gc::allocate(mc, bytes.into_boxed_slice())))
     }
 
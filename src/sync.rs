@@ -0,0 +1,251 @@
+//! Message passing between interpreters running on different OS threads.
+//!
+//! Each `Lua` instance is confined to the thread that created it, but the two halves of a
+//! `channel()` are `Send`, so they can be moved into interpreters on other threads and exposed to
+//! scripts there with `Sender::to_table` and `Receiver::into_table`.  Values are copied into an
+//! owned `Message` when they are sent and rebuilt inside the receiving interpreter, so nothing is
+//! ever shared between arenas.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence as sequence;
+
+use crate::walk::VisitedSet;
+use crate::{Callback, CallbackResult, Error, RuntimeError, String, Table, Value};
+
+/// An owned copy of a Lua value that can be sent between threads.
+///
+/// Only nil, booleans, numbers, strings and tables of those can be sent.  Tables are copied
+/// entry by entry, so a table reachable by two paths in the sent value arrives as two separate
+/// tables.
+#[derive(Debug, Clone, PartialEq, Collect)]
+#[collect(require_static)]
+pub enum Message {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(Message, Message)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    Function,
+    Thread,
+    RecursiveTable,
+}
+
+impl StdError for MessageError {}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Function => write!(fmt, "cannot send a function"),
+            MessageError::Thread => write!(fmt, "cannot send a thread"),
+            MessageError::RecursiveTable => write!(fmt, "cannot send a recursive table"),
+        }
+    }
+}
+
+impl Message {
+    pub fn from_value(value: Value) -> Result<Message, MessageError> {
+        fn copy<'gc>(
+            value: Value<'gc>,
+            parents: &mut VisitedSet<Table<'gc>>,
+        ) -> Result<Message, MessageError> {
+            Ok(match value {
+                Value::Nil => Message::Nil,
+                Value::Boolean(b) => Message::Boolean(b),
+                Value::Integer(i) => Message::Integer(i),
+                Value::Number(n) => Message::Number(n),
+                Value::String(s) => Message::String(s.as_bytes().to_vec()),
+                Value::Table(t) => {
+                    if !parents.insert(t) {
+                        return Err(MessageError::RecursiveTable);
+                    }
+                    let entries =
+                        t.0.read()
+                            .iter()
+                            .map(|(k, v)| Ok((copy(k, parents)?, copy(v, parents)?)))
+                            .collect::<Result<_, _>>()?;
+                    parents.remove(&t);
+                    Message::Table(entries)
+                }
+                Value::Function(_) => return Err(MessageError::Function),
+                Value::Thread(_) => return Err(MessageError::Thread),
+            })
+        }
+
+        copy(value, &mut VisitedSet::new())
+    }
+
+    pub fn into_value<'gc>(self, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+        match self {
+            Message::Nil => Value::Nil,
+            Message::Boolean(b) => Value::Boolean(b),
+            Message::Integer(i) => Value::Integer(i),
+            Message::Number(n) => Value::Number(n),
+            Message::String(s) => Value::String(String::new(mc, &s)),
+            Message::Table(entries) => {
+                let table = Table::new(mc);
+                for (key, value) in entries {
+                    table
+                        .set(mc, key.into_value(mc), value.into_value(mc))
+                        .expect("keys of a sent table are always valid");
+                }
+                Value::Table(table)
+            }
+        }
+    }
+}
+
+/// Creates a new channel, returning the sending and receiving halves.
+///
+/// There may be any number of senders (`Sender` is `Clone`) but only one receiver.
+pub fn channel() -> (Sender, Receiver) {
+    let (sender, receiver) = mpsc::channel();
+    (Sender(sender), Receiver(receiver))
+}
+
+#[derive(Debug, Clone)]
+pub struct Sender(mpsc::Sender<Message>);
+
+#[derive(Debug)]
+pub struct Receiver(mpsc::Receiver<Message>);
+
+impl Sender {
+    /// Sends a message, giving it back if the receiver has been dropped.
+    pub fn send(&self, message: Message) -> Result<(), Message> {
+        self.0.send(message).map_err(|err| err.0)
+    }
+
+    /// Creates a Lua object for this sender with a single method:
+    ///
+    /// * `sender:send(value)`: sends `value`, returning true if the receiver still exists and
+    ///   false otherwise.  Raises an error if `value` cannot be sent.
+    pub fn to_table<'gc>(&self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let table = Table::new(mc);
+        let sender = self.clone();
+        table
+            .set(
+                mc,
+                String::new_static(b"send"),
+                Callback::new_immediate(mc, move |args| {
+                    let value = args.get(1).cloned().unwrap_or(Value::Nil);
+                    let message = Message::from_value(value).map_err(message_error)?;
+                    Ok(CallbackResult::Return(vec![Value::Boolean(
+                        sender.send(message).is_ok(),
+                    )]))
+                }),
+            )
+            .unwrap();
+        table
+    }
+}
+
+impl Receiver {
+    /// Waits for the next message, returning `None` once every sender has been dropped.
+    pub fn recv(&self) -> Option<Message> {
+        self.0.recv().ok()
+    }
+
+    /// Returns the next message if there is one waiting.
+    pub fn try_recv(&self) -> Option<Message> {
+        self.0.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Message> {
+        self.0.recv_timeout(timeout).ok()
+    }
+
+    /// Creates a Lua object owning this receiver, with the methods:
+    ///
+    /// * `receiver:recv([timeout])`: waits for the next message, for at most `timeout` seconds if
+    ///   given
+    /// * `receiver:try_recv()`: receives the next message only if one is already waiting
+    ///
+    /// Both return true followed by the received value, or false followed by "timeout", "empty" or
+    /// "closed".  Waiting in `recv` blocks the whole interpreter.
+    pub fn into_table<'gc>(self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let receiver = Rc::new(self.0);
+        let table = Table::new(mc);
+
+        let recv = {
+            let receiver = receiver.clone();
+            Callback::new_sequence(mc, move |args| {
+                let result = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => receiver.recv().map_err(|_| Failure::Closed),
+                    timeout => {
+                        let seconds = timeout
+                            .to_number()
+                            .filter(|&s| s >= 0.0 && s.is_finite())
+                            .ok_or_else(|| {
+                                RuntimeError(Value::String(String::new_static(b"bad recv timeout")))
+                            })?;
+                        receiver
+                            .recv_timeout(Duration::from_secs_f64(seconds))
+                            .map_err(|err| match err {
+                                mpsc::RecvTimeoutError::Timeout => Failure::Timeout,
+                                mpsc::RecvTimeoutError::Disconnected => Failure::Closed,
+                            })
+                    }
+                };
+                Ok(sequence::from_fn_with(result, received))
+            })
+        };
+        table.set(mc, String::new_static(b"recv"), recv).unwrap();
+
+        let try_recv = Callback::new_sequence(mc, move |_| {
+            let result = receiver.try_recv().map_err(|err| match err {
+                mpsc::TryRecvError::Empty => Failure::Empty,
+                mpsc::TryRecvError::Disconnected => Failure::Closed,
+            });
+            Ok(sequence::from_fn_with(result, received))
+        });
+        table
+            .set(mc, String::new_static(b"try_recv"), try_recv)
+            .unwrap();
+
+        table
+    }
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+enum Failure {
+    Timeout,
+    Empty,
+    Closed,
+}
+
+fn received<'gc>(
+    mc: MutationContext<'gc, '_>,
+    result: Result<Message, Failure>,
+) -> Result<CallbackResult<'gc>, Error<'gc>> {
+    Ok(CallbackResult::Return(match result {
+        Ok(message) => vec![Value::Boolean(true), message.into_value(mc)],
+        Err(failure) => vec![
+            Value::Boolean(false),
+            Value::String(String::new_static(match failure {
+                Failure::Timeout => b"timeout",
+                Failure::Empty => b"empty",
+                Failure::Closed => b"closed",
+            })),
+        ],
+    }))
+}
+
+fn message_error<'gc>(error: MessageError) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(match error {
+        MessageError::Function => b"cannot send a function",
+        MessageError::Thread => b"cannot send a thread",
+        MessageError::RecursiveTable => b"cannot send a recursive table",
+    })))
+    .into()
+}
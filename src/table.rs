@@ -66,13 +66,47 @@ impl<'gc> Table<'gc> {
     pub fn length(&self) -> i64 {
         self.0.read().length()
     }
+
+    /// Returns a counter that increases every time the table changes in a way that invalidates
+    /// cached knowledge about how lookups through it resolve, for embedders that cache such lookups.
+    ///
+    /// Tables do not carry their own metatables, so currently the version only changes when the
+    /// storage of the table is reorganized.  Overwriting or removing existing entries and inserting
+    /// new entries that fit in the current storage leave it unchanged.
+    pub fn metatable_version(&self) -> u64 {
+        self.0.read().metatable_version()
+    }
+
+    /// Releases the storage of the table that is not in use, moving entries between the array and
+    /// map parts as if the table was built from scratch.
+    ///
+    /// Tables also shrink on their own once most of their entries have been removed, but only
+    /// after a number of removals proportional to their size, and less often every time they grow
+    /// back after shrinking.  This is for when the caller knows that a table will stay small.
+    pub fn shrink_to_fit(&self, mc: MutationContext<'gc, '_>) {
+        self.0.write(mc).shrink_to_fit();
+    }
 }
 
+// Tables with less storage than this are never shrunk automatically.
+const MIN_SHRINK_CAPACITY: usize = 32;
+// The most times the number of removals between automatic shrinks is doubled.
+const MAX_SHRINK_BACKOFF: u32 = 16;
+
+const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
+
 #[derive(Debug, Collect, Default)]
 #[collect(empty_drop)]
 pub struct TableState<'gc> {
     array: Vec<Value<'gc>>,
     map: FxHashMap<TableKey<'gc>, Value<'gc>>,
+    version: u64,
+    // Entries removed since the table last checked whether to shrink.
+    removals: usize,
+    // The number of removals between checks is doubled this many times.
+    shrink_backoff: u32,
+    // Whether the table has shrunk automatically since it last grew.
+    shrunk: bool,
 }
 
 impl<'gc> TableState<'gc> {
@@ -98,62 +132,38 @@ impl<'gc> TableState<'gc> {
         let index_key = to_array_index(key);
         if let Some(index) = index_key {
             if index < self.array.len() {
-                return Ok(mem::replace(&mut self.array[index], value));
+                let old = mem::replace(&mut self.array[index], value);
+                if value == Value::Nil && old != Value::Nil {
+                    self.note_removal();
+                }
+                return Ok(old);
             }
         }
 
         let hash_key = TableKey::new(key)?;
         if value == Value::Nil {
-            Ok(self.map.remove(&hash_key).unwrap_or(Value::Nil))
+            let old = self.map.remove(&hash_key);
+            if old.is_some() {
+                self.note_removal();
+            }
+            Ok(old.unwrap_or(Value::Nil))
         } else if self.map.len() < self.map.capacity() {
             Ok(self.map.insert(hash_key, value).unwrap_or(Value::Nil))
         } else {
             // If a new element does not fit in either the array or map part of the table, we need
             // to grow.  First, we find the total count of array candidate elements across the array
-            // part, the map part, and the newly inserted key.
-
-            const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
-
-            // Count of array-candidate elements based on the highest bit in the index
-            let mut array_counts = [0; USIZE_BITS];
-            // Total count of all array-candidate elements
-            let mut array_total = 0;
-
-            for (i, e) in self.array.iter().enumerate() {
-                if *e != Value::Nil {
-                    array_counts[highest_bit(i)] += 1;
-                    array_total += 1;
-                }
-            }
-
-            for k in self.map.keys() {
-                if let Some(i) = to_array_index(k.0) {
-                    array_counts[highest_bit(i)] += 1;
-                    array_total += 1;
-                }
-            }
-
-            if let Some(i) = index_key {
-                array_counts[highest_bit(i)] += 1;
-                array_total += 1;
-            }
-
-            // Then, we compute the new optimal size for the array by finding the largest array size
-            // such that at least half of the elements in the array would be in use.
-
-            let mut optimal_size = 0;
-            let mut total = 0;
-            for i in 0..USIZE_BITS {
-                if (1 << i) / 2 >= array_total {
-                    break;
-                }
-
-                if array_counts[i] > 0 {
-                    total += array_counts[i];
-                    if total > (1 << i) / 2 {
-                        optimal_size = 1 << i;
-                    }
-                }
+            // part, the map part, and the newly inserted key, and from those the new optimal size
+            // of the array part.
+            let optimal_size = self.optimal_array_size(index_key);
+
+            // Any key may now live in a different part of the table.
+            self.version += 1;
+
+            // A table that has to grow again soon after shrinking is being filled and emptied
+            // repeatedly, so it should wait longer before shrinking again.
+            if self.shrunk {
+                self.shrunk = false;
+                self.shrink_backoff = (self.shrink_backoff + 1).min(MAX_SHRINK_BACKOFF);
             }
 
             let old_array_size = self.array.len();
@@ -196,6 +206,129 @@ impl<'gc> TableState<'gc> {
         }
     }
 
+    /// See `Table::metatable_version`.
+    pub fn metatable_version(&self) -> u64 {
+        self.version
+    }
+
+    /// See `Table::shrink_to_fit`.
+    pub fn shrink_to_fit(&mut self) {
+        let optimal_size = self.optimal_array_size(None);
+        let old_array_size = self.array.len();
+        let old_map_capacity = self.map.capacity();
+
+        if optimal_size < old_array_size {
+            let map = &mut self.map;
+            for (i, v) in self.array.drain(optimal_size..).enumerate() {
+                if v != Value::Nil {
+                    map.insert(TableKey(Value::Integer((optimal_size + i) as i64 + 1)), v);
+                }
+            }
+        } else if optimal_size > old_array_size {
+            self.array.resize(optimal_size, Value::Nil);
+            let array = &mut self.array;
+            self.map.retain(|k, v| {
+                if let Some(i) = to_array_index(k.0) {
+                    if i < array.len() {
+                        array[i] = *v;
+                        return false;
+                    }
+                }
+                true
+            });
+        }
+        self.array.shrink_to_fit();
+        self.map.shrink_to_fit();
+
+        self.removals = 0;
+        if self.array.len() != old_array_size || self.map.capacity() != old_map_capacity {
+            self.version += 1;
+        }
+    }
+
+    /// Iterates over every non-nil entry in the table, array part first in index order, then the map
+    /// part in an unspecified order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (Value<'gc>, Value<'gc>)> + 'a {
+        self.array
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v != Value::Nil)
+            .map(|(i, v)| (Value::Integer(i as i64 + 1), *v))
+            .chain(self.map.iter().map(|(k, v)| (k.0, *v)))
+    }
+
+    // Finds the largest array size such that more than half of the array would be in use, counting
+    // the integer keys in both parts of the table along with `new_key`, if given.
+    fn optimal_array_size(&self, new_key: Option<usize>) -> usize {
+        // Count of array-candidate elements based on the highest bit in the index
+        let mut array_counts = [0; USIZE_BITS];
+        // Total count of all array-candidate elements
+        let mut array_total = 0;
+
+        for (i, e) in self.array.iter().enumerate() {
+            if *e != Value::Nil {
+                array_counts[highest_bit(i)] += 1;
+                array_total += 1;
+            }
+        }
+
+        for k in self.map.keys() {
+            if let Some(i) = to_array_index(k.0) {
+                array_counts[highest_bit(i)] += 1;
+                array_total += 1;
+            }
+        }
+
+        if let Some(i) = new_key {
+            array_counts[highest_bit(i)] += 1;
+            array_total += 1;
+        }
+
+        let mut optimal_size = 0;
+        let mut total = 0;
+        for (i, &count) in array_counts.iter().enumerate() {
+            if (1 << i) / 2 >= array_total {
+                break;
+            }
+
+            if count > 0 {
+                total += count;
+                if total > (1 << i) / 2 {
+                    optimal_size = 1 << i;
+                }
+            }
+        }
+        optimal_size
+    }
+
+    // Called after an entry is removed.  Once enough entries have been removed since the last
+    // check, shrinks the table if less than a quarter of its storage is in use.  The removals
+    // between checks are proportional to the size of the table, so checking takes amortized
+    // constant time.
+    fn note_removal(&mut self) {
+        self.removals += 1;
+        let capacity = self.array.len() + self.map.capacity();
+        if capacity < MIN_SHRINK_CAPACITY
+            || self.removals < (capacity / 2).saturating_mul(1 << self.shrink_backoff)
+        {
+            return;
+        }
+
+        self.removals = 0;
+        let live = self.map.len() + self.array.iter().filter(|v| **v != Value::Nil).count();
+        if live * 4 < capacity {
+            self.shrink_to_fit();
+            self.shrunk = true;
+        }
+    }
+
+    // An estimate of the memory used by this table, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        mem::size_of::<TableState>()
+            + self.array.capacity() * mem::size_of::<Value>()
+            + self.map.capacity() * mem::size_of::<(TableKey, Value)>()
+    }
+
     /// Returns a 'border' for this table.
     ///
     /// A 'border' for a table is any i >= 0 where:
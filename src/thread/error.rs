@@ -3,11 +3,12 @@ use std::fmt;
 
 use gc_arena::Collect;
 
-use crate::{ThreadMode, TypeError};
+use crate::{ThreadMode, TypeError, Value};
 
-#[derive(Debug, Clone, Copy, Collect)]
+/// An operation performed by one of Lua's arithmetic, bitwise or comparison operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
 #[collect(require_static)]
-pub enum BinaryOperatorError {
+pub enum Operation {
     Add,
     Subtract,
     Multiply,
@@ -26,27 +27,144 @@ pub enum BinaryOperatorError {
     LessEqual,
 }
 
+impl Operation {
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            Operation::BitAnd
+                | Operation::BitOr
+                | Operation::BitXor
+                | Operation::BitNot
+                | Operation::ShiftLeft
+                | Operation::ShiftRight
+        )
+    }
+
+    // The verb used for this operation in error messages.
+    fn verb(self) -> &'static str {
+        match self {
+            Operation::Add => "add",
+            Operation::Subtract => "subtract",
+            Operation::Multiply => "multiply",
+            Operation::FloatDivide => "divide",
+            Operation::FloorDivide => "floor divide",
+            Operation::Modulo => "modulo",
+            Operation::Exponentiate => "exponentiate",
+            Operation::UnaryNegate => "negate",
+            Operation::BitAnd => "bitwise AND",
+            Operation::BitOr => "bitwise OR",
+            Operation::BitXor => "bitwise XOR",
+            Operation::BitNot => "bitwise NOT",
+            Operation::ShiftLeft => "shift left",
+            Operation::ShiftRight => "shift right",
+            Operation::LessThan | Operation::LessEqual => "compare",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(require_static)]
+pub enum BinaryOperatorError {
+    /// The operation is not defined for the types of its operands.  `right` is None for unary
+    /// operations.
+    BadOperands {
+        operation: Operation,
+        left: &'static str,
+        right: Option<&'static str>,
+    },
+    /// A bitwise operation on a number (or string converted to one) that is not an integer.
+    NoIntegerRepresentation(Operation),
+    /// Integer floor division by zero, with the numerator.
+    IntegerDivideByZero(i64),
+    /// Integer modulo by zero, with the numerator.
+    IntegerModuloByZero(i64),
+}
+
+impl BinaryOperatorError {
+    /// The error for a failed binary operation on the given operands.
+    pub fn binary<'gc>(
+        operation: Operation,
+        left: Value<'gc>,
+        right: Value<'gc>,
+    ) -> BinaryOperatorError {
+        if operation.is_bitwise() && left.to_number().is_some() && right.to_number().is_some() {
+            BinaryOperatorError::NoIntegerRepresentation(operation)
+        } else {
+            BinaryOperatorError::BadOperands {
+                operation,
+                left: left.type_name(),
+                right: Some(right.type_name()),
+            }
+        }
+    }
+
+    /// The error for a failed unary operation on the given operand.
+    pub fn unary<'gc>(operation: Operation, operand: Value<'gc>) -> BinaryOperatorError {
+        if operation.is_bitwise() && operand.to_number().is_some() {
+            BinaryOperatorError::NoIntegerRepresentation(operation)
+        } else {
+            BinaryOperatorError::BadOperands {
+                operation,
+                left: operand.type_name(),
+                right: None,
+            }
+        }
+    }
+}
+
 impl StdError for BinaryOperatorError {}
 
 impl fmt::Display for BinaryOperatorError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            BinaryOperatorError::Add => write!(fmt, "cannot add values"),
-            BinaryOperatorError::Subtract => write!(fmt, "cannot subtract values"),
-            BinaryOperatorError::Multiply => write!(fmt, "cannot multiply values"),
-            BinaryOperatorError::FloatDivide => write!(fmt, "cannot float divide values"),
-            BinaryOperatorError::FloorDivide => write!(fmt, "cannot floor divide values"),
-            BinaryOperatorError::Modulo => write!(fmt, "cannot modulo values"),
-            BinaryOperatorError::Exponentiate => write!(fmt, "cannot exponentiate values"),
-            BinaryOperatorError::UnaryNegate => write!(fmt, "cannot negate value"),
-            BinaryOperatorError::BitAnd => write!(fmt, "cannot bitwise AND values"),
-            BinaryOperatorError::BitOr => write!(fmt, "cannot bitwise OR values"),
-            BinaryOperatorError::BitXor => write!(fmt, "cannot bitwise XOR values"),
-            BinaryOperatorError::BitNot => write!(fmt, "cannot bitwise NOT value"),
-            BinaryOperatorError::ShiftLeft => write!(fmt, "cannot shift value left"),
-            BinaryOperatorError::ShiftRight => write!(fmt, "cannot shift value right"),
-            BinaryOperatorError::LessThan => write!(fmt, "cannot compare values with <"),
-            BinaryOperatorError::LessEqual => write!(fmt, "cannot compare values with <="),
+            BinaryOperatorError::BadOperands {
+                operation,
+                left,
+                right: Some(right),
+            } => write!(
+                fmt,
+                "attempt to {} a '{}' with a '{}'",
+                operation.verb(),
+                left,
+                right
+            ),
+            BinaryOperatorError::BadOperands {
+                operation,
+                left,
+                right: None,
+            } => write!(fmt, "attempt to {} a '{}'", operation.verb(), left),
+            BinaryOperatorError::NoIntegerRepresentation(_) => {
+                write!(fmt, "number has no integer representation")
+            }
+            BinaryOperatorError::IntegerDivideByZero(n) => {
+                write!(fmt, "attempt to perform 'n//0' (n = {})", n)
+            }
+            BinaryOperatorError::IntegerModuloByZero(n) => {
+                write!(fmt, "attempt to perform 'n%%0' (n = {})", n)
+            }
+        }
+    }
+}
+
+/// An error starting a numeric `for` loop, raised before the first iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum ForLoopError {
+    InitialNotNumber,
+    LimitNotNumber,
+    StepNotNumber,
+    ZeroStep,
+}
+
+impl StdError for ForLoopError {}
+
+impl fmt::Display for ForLoopError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForLoopError::InitialNotNumber => write!(fmt, "'for' initial value must be a number"),
+            ForLoopError::LimitNotNumber => write!(fmt, "'for' limit must be a number"),
+            ForLoopError::StepNotNumber => write!(fmt, "'for' step must be a number"),
+            ForLoopError::ZeroStep => write!(fmt, "'for' step is zero"),
         }
     }
 }
@@ -75,7 +193,11 @@ impl fmt::Display for BadThreadMode {
 pub enum ThreadError {
     ExpectedVariable(bool),
     BadCall(TypeError),
-    BadYield,
+    // A thread that cannot yield tried to, while being run directly by the host.
+    YieldOutsideCoroutine,
+    // A thread that cannot yield tried to, while being run from inside a callback of another
+    // thread.
+    YieldAcrossCallback,
 }
 
 impl StdError for ThreadError {}
@@ -90,7 +212,12 @@ impl fmt::Display for ThreadError {
                 write!(fmt, "operation expects constant lua thread")
             }
             ThreadError::BadCall(type_error) => fmt::Display::fmt(type_error, fmt),
-            ThreadError::BadYield => write!(fmt, "yield from unyieldable function"),
+            ThreadError::YieldOutsideCoroutine => {
+                write!(fmt, "attempt to yield from outside a coroutine")
+            }
+            ThreadError::YieldAcrossCallback => {
+                write!(fmt, "attempt to yield across a callback boundary")
+            }
         }
     }
 }
@@ -1,9 +1,19 @@
 mod error;
 mod thread;
+mod traceback;
 mod vm;
 
-pub use error::{BadThreadMode, BinaryOperatorError, ThreadError};
-pub use thread::{Thread, ThreadMode, ThreadSequence};
+pub use error::{BadThreadMode, BinaryOperatorError, ForLoopError, Operation, ThreadError};
+pub use thread::{
+    CountHook, StackFrame, Thread, ThreadMode, ThreadSequence, DEFAULT_MAX_STRING_LEN,
+};
+pub use traceback::{
+    CallResult, CallSequence, ErrorFormat, StackSnapshot, Traceback, TracebackFrame,
+};
 
-pub(crate) use thread::LuaFrame;
+#[cfg(feature = "log")]
+pub(crate) use thread::caller_line;
+#[cfg(feature = "jit")]
+pub(crate) use thread::LuaRegisters;
+pub(crate) use thread::{is_yieldable, LuaFrame, LuaPosition};
 pub(crate) use vm::run_vm;
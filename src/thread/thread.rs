@@ -1,16 +1,50 @@
+use std::cell::Cell;
 use std::collections::btree_map::Entry as BTreeEntry;
 use std::collections::BTreeMap;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
 
-use gc_arena::{Collect, GcCell, MutationContext};
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
 use gc_sequence::Sequence;
 
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
+#[cfg(feature = "trace")]
+use crate::trace::OpcodeTrace;
+
+thread_local! {
+    static CALLER_LINE: Cell<Option<LineNumber>> = const { Cell::new(None) };
+    // Whether the innermost `Thread` being stepped may yield, or `None` outside of any thread.
+    static YIELDABLE: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+// While a callback sequence is being stepped, returns the source line of the Lua code that called
+// it, if known.
+#[cfg(feature = "log")]
+pub(crate) fn caller_line() -> Option<LineNumber> {
+    CALLER_LINE.with(|c| c.get())
+}
+
+// Returns whether the innermost `Thread` being stepped on the current OS thread may yield, false if
+// no thread is being stepped.
+pub(crate) fn is_yieldable() -> bool {
+    YIELDABLE.with(|c| c.get()).unwrap_or(false)
+}
+
 use crate::{
-    thread::run_vm, BadThreadMode, CallbackResult, CallbackReturn, Closure, Continuation, Error,
-    Function, RegisterIndex, ThreadError, TypeError, UpValue, UpValueState, Value, VarCount,
+    profile::{call_callback, ProfiledFunction, Profiler},
+    thread::{run_vm, StackSnapshot, Traceback, TracebackFrame},
+    BadThreadMode, CallbackResult, CallbackReturn, CharClasses, Closure, Continuation, Error,
+    Function, LineCoverage, LineNumber, RegisterIndex, String, Table, ThreadError, TypeError,
+    TypeFeedback, UpValue, UpValueState, Value, VarCount,
 };
 
+/// The default for `Thread::set_max_string_len`, 256 MiB.
+pub const DEFAULT_MAX_STRING_LEN: usize = 1 << 28;
+
 #[derive(Clone, Copy, Collect)]
 #[collect(require_copy)]
 pub struct Thread<'gc>(pub(crate) GcCell<'gc, ThreadState<'gc>>);
@@ -51,6 +85,48 @@ pub enum ThreadMode {
 #[collect(empty_drop)]
 pub struct ThreadSequence<'gc>(pub Thread<'gc>);
 
+/// A snapshot of an active Lua function call on a thread's stack.
+#[derive(Debug, Clone, Collect)]
+#[collect(empty_drop)]
+pub struct StackFrame<'gc> {
+    pub closure: Closure<'gc>,
+    /// The index of the opcode currently being executed.
+    pub pc: usize,
+    /// The source line of the current opcode, if known.
+    pub line: Option<LineNumber>,
+    /// The contents of every register in the frame, including temporaries.  See `locals` for the
+    /// named local variables.
+    pub registers: Vec<Value<'gc>>,
+    /// The current value of every upvalue of `closure`.
+    pub upvalues: Vec<Value<'gc>>,
+}
+
+impl<'gc> StackFrame<'gc> {
+    /// Returns the name and current value of every local variable in scope at `pc`, in order of
+    /// declaration.  Temporaries have no name and are not included.
+    pub fn locals(&self) -> Vec<(String<'gc>, Value<'gc>)> {
+        self.closure
+            .0
+            .proto
+            .active_locals(self.pc)
+            .filter_map(|local| {
+                let value = self.registers.get(local.register.0 as usize)?;
+                Some((local.name, *value))
+            })
+            .collect()
+    }
+}
+
+// The position of the innermost Lua frame of a thread
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct LuaPosition<'gc> {
+    // The number of Lua frames on the thread, including this one
+    pub depth: usize,
+    pub closure: Closure<'gc>,
+    // The index of the next opcode to be executed
+    pub pc: usize,
+}
+
 #[derive(Collect)]
 #[collect(empty_drop)]
 pub(crate) struct ThreadState<'gc> {
@@ -58,7 +134,53 @@ pub(crate) struct ThreadState<'gc> {
     frames: Vec<Frame<'gc>>,
     open_upvalues: BTreeMap<usize, UpValue<'gc>>,
     result: Option<Result<Vec<Value<'gc>>, Error<'gc>>>,
+    // Where the error being propagated or the error in `result` was raised, if tracebacks are
+    // captured.
+    traceback: Option<Traceback>,
+    capture_tracebacks: bool,
     allow_yield: bool,
+    // Whether the thread is being stepped from inside another thread, as of its last step.
+    nested: bool,
+    hook: Option<CountHook<'gc>>,
+    // The number of instructions left before the count hook is next called
+    hook_remaining: u32,
+    // Whether a hook function is currently running, hooks are not called recursively.
+    in_hook: bool,
+    coverage: Option<LineCoverage<'gc>>,
+    type_feedback: Option<TypeFeedback<'gc>>,
+    profiler: Option<Profiler<'gc>>,
+    string_metatable: Option<Table<'gc>>,
+    // Shared with the threads created from this one, like `string_coercion`.
+    max_string_len: Gc<'gc, Cell<usize>>,
+    char_classes: CharClasses,
+    // Kept outside of the thread state so that the standard library can read the setting of the
+    // main thread while it is running, and so that the threads created from it can share it.
+    string_coercion: Gc<'gc, Cell<bool>>,
+    // The number of VM instructions executed by this thread and every thread sharing its counter,
+    // which `Lua` reads outside of the arena to schedule garbage collection work.
+    executed_instructions: Rc<Cell<u64>>,
+    #[cfg(feature = "trace")]
+    opcode_trace: Option<OpcodeTrace>,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit<'gc>>,
+    // An empty buffer for native code to move values through, see `NativeCode::run`.
+    #[cfg(feature = "jit")]
+    native_moves: Vec<Value<'gc>>,
+    // Empty buffers for the arguments of callbacks and the results passed to continuations, so
+    // that calls between Lua and Rust do not allocate once the thread is warmed up.  Callbacks take
+    // their arguments by value, and the buffers they return their results in are recycled here.
+    scratch: Vec<Vec<Value<'gc>>>,
+}
+
+/// A function called with the single argument "count" after every `count` VM instructions that a
+/// thread executes, as set by `debug.sethook`.
+///
+/// Instructions executed by the hook function itself are not counted.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(require_copy)]
+pub struct CountHook<'gc> {
+    pub function: Function<'gc>,
+    pub count: u32,
 }
 
 pub(crate) struct LuaFrame<'gc, 'a> {
@@ -73,6 +195,17 @@ pub(crate) struct LuaRegisters<'gc, 'a> {
     base: usize,
     open_upvalues: &'a mut BTreeMap<usize, UpValue<'gc>>,
     thread: Thread<'gc>,
+    pub coverage: Option<LineCoverage<'gc>>,
+    pub type_feedback: Option<TypeFeedback<'gc>>,
+    pub string_metatable: Option<Table<'gc>>,
+    pub max_string_len: usize,
+    pub string_coercion: bool,
+    #[cfg(feature = "trace")]
+    pub opcode_trace: &'a mut Option<OpcodeTrace>,
+    #[cfg(feature = "jit")]
+    pub jit: Option<Jit<'gc>>,
+    #[cfg(feature = "jit")]
+    pub native_moves: &'a mut Vec<Value<'gc>>,
 }
 
 impl<'gc> ThreadSequence<'gc> {
@@ -116,11 +249,63 @@ impl<'gc> Thread<'gc> {
                 frames: Vec::new(),
                 open_upvalues: BTreeMap::new(),
                 result: None,
+                traceback: None,
+                capture_tracebacks: true,
                 allow_yield,
+                nested: false,
+                hook: None,
+                hook_remaining: 0,
+                in_hook: false,
+                coverage: None,
+                type_feedback: None,
+                profiler: None,
+                string_metatable: None,
+                max_string_len: Gc::allocate(mc, Cell::new(DEFAULT_MAX_STRING_LEN)),
+                char_classes: CharClasses::default(),
+                string_coercion: Gc::allocate(mc, Cell::new(true)),
+                executed_instructions: Rc::new(Cell::new(0)),
+                #[cfg(feature = "trace")]
+                opcode_trace: None,
+                #[cfg(feature = "jit")]
+                jit: None,
+                #[cfg(feature = "jit")]
+                native_moves: Vec::new(),
+                scratch: Vec::new(),
             },
         ))
     }
 
+    /// Creates a new thread with the settings of `parent`: its line coverage, type feedback,
+    /// profiler, string metatable, string length limit, character classes, string coercion and JIT.
+    /// The new thread also adds the instructions it executes to those of `parent`, see
+    /// `Thread::executed_instructions`.
+    ///
+    /// Every thread the library creates, such as for coroutines and `Executor` tasks, is created
+    /// this way from the main thread, so that Lua code behaves the same whichever thread it runs
+    /// on.
+    pub fn new_inheriting(
+        mc: MutationContext<'gc, '_>,
+        parent: Thread<'gc>,
+        allow_yield: bool,
+    ) -> Thread<'gc> {
+        let thread = Thread::new(mc, allow_yield);
+        thread.set_line_coverage(mc, parent.line_coverage());
+        thread.set_type_feedback(mc, parent.type_feedback());
+        thread.set_profiler(mc, parent.profiler());
+        thread.set_string_metatable(mc, parent.string_metatable());
+        thread.set_char_classes(mc, parent.char_classes());
+        {
+            let parent = parent.0.read();
+            let mut state = thread.0.write(mc);
+            state.max_string_len = parent.max_string_len;
+            state.string_coercion = parent.string_coercion;
+            state.executed_instructions = parent.executed_instructions.clone();
+        }
+        #[cfg(feature = "jit")]
+        thread.set_jit(mc, parent.jit());
+        thread
+    }
+
     pub fn mode(self) -> ThreadMode {
         if let Ok(state) = self.0.try_read() {
             get_mode(&state)
@@ -138,6 +323,7 @@ impl<'gc> Thread<'gc> {
     ) -> Result<(), BadThreadMode> {
         let mut state = self.0.write(mc);
         check_mode(&state, ThreadMode::Stopped)?;
+        state.traceback = None;
         ext_call_function(self, &mut state, mc, function, args);
         Ok(())
     }
@@ -154,6 +340,43 @@ impl<'gc> Thread<'gc> {
         Ok(())
     }
 
+    /// If this thread has finished, because its function returned or raised an error, discards
+    /// any results and traceback and starts a new suspended function on it.  Every setting of the
+    /// thread is kept, and its stack keeps its allocation, so a host that runs many short-lived
+    /// coroutines can reuse one thread for each of them instead of allocating new ones.
+    pub fn reset(
+        self,
+        mc: MutationContext<'gc, '_>,
+        function: Function<'gc>,
+    ) -> Result<(), BadThreadMode> {
+        let mut state = self.0.write(mc);
+        // A thread that has yielded also has results, but is not finished.
+        if !state.frames.is_empty() {
+            return Err(BadThreadMode {
+                expected: Some(ThreadMode::Stopped),
+                found: get_mode(&state),
+            });
+        }
+        state.result = None;
+        state.traceback = None;
+        state.frames.push(Frame::StartCoroutine(function));
+        Ok(())
+    }
+
+    /// Stops this thread wherever it is, discarding every active call and any results, so that it
+    /// is `Stopped` again.  No continuations are called, so unlike an error this cannot be caught
+    /// by `pcall`.  Meant for hosts that stop a script which has exceeded its budget.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn cancel(self, mc: MutationContext<'gc, '_>) {
+        let mut state = self.0.write(mc);
+        close_upvalues(self, &mut state, mc, 0);
+        state.frames.clear();
+        state.values.clear();
+        state.result = None;
+        state.in_hook = false;
+    }
+
     /// Take any results if they are available
     pub fn take_results(
         self,
@@ -162,6 +385,12 @@ impl<'gc> Thread<'gc> {
         self.0.write(mc).result.take()
     }
 
+    /// Takes the traceback captured when the last function started on this thread ended with an
+    /// error, if tracebacks are captured.
+    pub fn take_traceback(self, mc: MutationContext<'gc, '_>) -> Option<Traceback> {
+        self.0.write(mc).traceback.take()
+    }
+
     /// If the thread is in `Suspended` mode, resume it.
     pub fn resume(
         self,
@@ -191,6 +420,9 @@ impl<'gc> Thread<'gc> {
                 Some(Frame::Lua { .. }) => {
                     return_to_lua(&mut state, args);
                 }
+                Some(Frame::Hook { .. }) => {
+                    return_from_hook(&mut state);
+                }
                 None => {
                     state.result = Some(Ok(args.to_vec()));
                 }
@@ -204,13 +436,43 @@ impl<'gc> Thread<'gc> {
     /// If the thread is in `Running` mode, either run the Lua VM for a while or step any callback
     /// that we are waiting on.
     pub fn step(self, mc: MutationContext<'gc, '_>) -> Result<(), BadThreadMode> {
+        const VM_GRANULARITY: u32 = 256;
+        self.step_instructions(mc, VM_GRANULARITY)
+    }
+
+    /// The same as `Thread::step`, but runs the Lua VM for at most the given number of
+    /// instructions.  If the thread is waiting on a callback, the callback is stepped once instead.
+    ///
+    /// Instructions run by a count hook do not count against this limit, instead at most the same
+    /// number of hook instructions may be run in a single call.
+    pub fn step_instructions(
+        self,
+        mc: MutationContext<'gc, '_>,
+        instructions: u32,
+    ) -> Result<(), BadThreadMode> {
+        assert_ne!(instructions, 0);
         let mut state = self.0.write(mc);
         check_mode(&state, ThreadMode::Running)?;
+        let outer_yieldable = YIELDABLE.with(|c| c.replace(Some(state.allow_yield)));
+        state.nested = outer_yieldable.is_some();
         match state.frames.last_mut() {
             Some(Frame::Callback(sequence)) => {
                 let mut sequence = sequence.take().expect("pending callback missing");
+                let caller = state.frames.iter().rev().find_map(|frame| match *frame {
+                    Frame::Lua { bottom, pc, .. } => match state.values[bottom] {
+                        // The caller has already advanced past its call instruction
+                        Value::Function(Function::Closure(c)) => {
+                            c.0.proto.opcode_line(pc.saturating_sub(1))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                });
                 drop(state);
-                match sequence.step(mc) {
+                let outer_caller = CALLER_LINE.with(|c| c.replace(caller));
+                let res = sequence.step(mc);
+                CALLER_LINE.with(|c| c.set(outer_caller));
+                match res {
                     None => {
                         let mut state = self.0.write(mc);
                         match state.frames.last_mut() {
@@ -228,23 +490,46 @@ impl<'gc> Thread<'gc> {
                 }
             }
             Some(Frame::Lua { .. }) => {
-                const VM_GRANULARITY: u32 = 256;
-                let mut instructions = VM_GRANULARITY;
-
+                let mut script_instructions = instructions;
+                let mut hook_instructions = instructions;
                 loop {
+                    let in_hook = state.in_hook;
+                    let budget = if in_hook {
+                        hook_instructions
+                    } else if state.hook.is_some() {
+                        script_instructions.min(state.hook_remaining)
+                    } else {
+                        script_instructions
+                    };
+
                     let lua_frame = LuaFrame {
                         state: &mut state,
                         thread: self,
                     };
-                    match run_vm(mc, lua_frame, instructions) {
+                    match run_vm(mc, lua_frame, budget) {
                         Err(err) => {
                             unwind(self, &mut state, mc, err);
                             break;
                         }
                         Ok(i) => {
+                            let executed = budget - i;
+                            let total = &state.executed_instructions;
+                            total.set(total.get() + executed as u64);
+                            if in_hook {
+                                hook_instructions -= executed;
+                            } else {
+                                script_instructions -= executed;
+                                if let Some(hook) = state.hook {
+                                    state.hook_remaining -= executed;
+                                    if state.hook_remaining == 0 {
+                                        state.hook_remaining = hook.count;
+                                        call_hook(self, &mut state, mc, hook.function);
+                                    }
+                                }
+                            }
+
                             if let Some(Frame::Lua { .. }) = state.frames.last() {
-                                instructions = i;
-                                if instructions == 0 {
+                                if script_instructions == 0 || hook_instructions == 0 {
                                     break;
                                 }
                             } else {
@@ -256,9 +541,305 @@ impl<'gc> Thread<'gc> {
             }
             _ => panic!("no callback or lua frame"),
         }
+        YIELDABLE.with(|c| c.set(outer_yieldable));
 
         Ok(())
     }
+
+    /// Returns the number of VM instructions executed by this thread and every thread created from
+    /// it with `Thread::new_inheriting`.  For the main thread of a `Root`, that is every thread the
+    /// library creates, so `Lua` uses this to pace garbage collection and enforce its instruction
+    /// budget separately for each instance.
+    pub fn executed_instructions(self) -> u64 {
+        self.0.read().executed_instructions.get()
+    }
+
+    // The counter behind `Thread::executed_instructions`, which can be read outside of the arena.
+    pub(crate) fn instruction_counter(self) -> Rc<Cell<u64>> {
+        self.0.read().executed_instructions.clone()
+    }
+
+    /// Sets or clears the count hook for this thread, the hook is first called after `count` more
+    /// instructions.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_count_hook(self, mc: MutationContext<'gc, '_>, hook: Option<CountHook<'gc>>) {
+        let mut state = self.0.write(mc);
+        if let Some(hook) = hook {
+            assert_ne!(hook.count, 0, "count hook count must not be zero");
+            state.hook_remaining = hook.count;
+        }
+        state.hook = hook;
+    }
+
+    pub fn count_hook(self) -> Option<CountHook<'gc>> {
+        self.0.read().hook
+    }
+
+    /// Sets or clears the line coverage recorder for this thread.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_line_coverage(
+        self,
+        mc: MutationContext<'gc, '_>,
+        coverage: Option<LineCoverage<'gc>>,
+    ) {
+        self.0.write(mc).coverage = coverage;
+    }
+
+    pub fn line_coverage(self) -> Option<LineCoverage<'gc>> {
+        self.0.read().coverage
+    }
+
+    /// Sets or clears the type feedback recorder for this thread.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_type_feedback(
+        self,
+        mc: MutationContext<'gc, '_>,
+        type_feedback: Option<TypeFeedback<'gc>>,
+    ) {
+        self.0.write(mc).type_feedback = type_feedback;
+    }
+
+    pub fn type_feedback(self) -> Option<TypeFeedback<'gc>> {
+        self.0.read().type_feedback
+    }
+
+    /// Sets or clears the profiler for this thread.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_profiler(self, mc: MutationContext<'gc, '_>, profiler: Option<Profiler<'gc>>) {
+        self.0.write(mc).profiler = profiler;
+    }
+
+    pub fn profiler(self) -> Option<Profiler<'gc>> {
+        self.0.read().profiler
+    }
+
+    /// Sets whether this thread captures a `Traceback` when an error escapes every frame on it,
+    /// enabled by default.  The traceback of an error that is caught, as by `pcall`, is discarded.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_capture_tracebacks(self, mc: MutationContext<'gc, '_>, capture: bool) {
+        self.0.write(mc).capture_tracebacks = capture;
+    }
+
+    pub fn captures_tracebacks(self) -> bool {
+        self.0.read().capture_tracebacks
+    }
+
+    /// Sets or clears the metatable shared by all strings on this thread.  Indexing a string, as in
+    /// `s:upper()`, looks the key up in the `__index` table of this metatable.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_string_metatable(self, mc: MutationContext<'gc, '_>, metatable: Option<Table<'gc>>) {
+        self.0.write(mc).string_metatable = metatable;
+    }
+
+    pub fn string_metatable(self) -> Option<Table<'gc>> {
+        self.0.read().string_metatable
+    }
+
+    /// Sets the length in bytes of the longest string that concatenation with `..` may create,
+    /// `DEFAULT_MAX_STRING_LEN` by default.  Longer results raise a "resulting string too large"
+    /// error instead.  The limit is shared with every thread created by `Thread::new_inheriting`,
+    /// the same as `Thread::set_string_coercion`, and the standard library functions that build
+    /// strings, such as `string.rep`, `string.format` and `buffer`, follow it too.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_max_string_len(self, _mc: MutationContext<'gc, '_>, max_string_len: usize) {
+        self.0.read().max_string_len.set(max_string_len);
+    }
+
+    pub fn max_string_len(self) -> usize {
+        self.0.read().max_string_len.get()
+    }
+
+    /// Sets the bytes in each character class of patterns, such as `%a`, which is only ASCII by
+    /// default regardless of the host locale.  The `string` library uses the classes of the main
+    /// thread.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_char_classes(self, mc: MutationContext<'gc, '_>, char_classes: CharClasses) {
+        self.0.write(mc).char_classes = char_classes;
+    }
+
+    pub fn char_classes(self) -> CharClasses {
+        self.0.read().char_classes
+    }
+
+    /// Sets whether arithmetic converts strings to numbers and `..` converts numbers to strings on
+    /// this thread, enabled by default as in standard Lua.  When disabled, `"1" + 1` and `1 .. ""`
+    /// are errors, as are strings given as the bounds of a numeric `for` loop.  The setting is
+    /// shared with every thread created by `Thread::new_inheriting`, which includes coroutines and
+    /// `Executor` tasks, so changing it on any of them changes it for all of them, and the `math`
+    /// and `string` libraries follow it too.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn set_string_coercion(self, _mc: MutationContext<'gc, '_>, string_coercion: bool) {
+        self.0.read().string_coercion.set(string_coercion);
+    }
+
+    pub fn string_coercion(self) -> bool {
+        self.0.read().string_coercion.get()
+    }
+
+    // The setting of `Thread::set_string_coercion`, which can be read even while the thread is
+    // running.
+    pub(crate) fn string_coercion_setting(self) -> Gc<'gc, Cell<bool>> {
+        self.0.read().string_coercion
+    }
+
+    /// Sets or clears opcode tracing for this thread, see the `trace` module.
+    ///
+    /// Must not be called while the thread is being stepped.
+    #[cfg(feature = "trace")]
+    pub fn set_opcode_trace(self, mc: MutationContext<'gc, '_>, trace: Option<OpcodeTrace>) {
+        self.0.write(mc).opcode_trace = trace;
+    }
+
+    /// Sets or clears the JIT compiler for this thread, see the `jit` module.
+    ///
+    /// Must not be called while the thread is being stepped.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(self, mc: MutationContext<'gc, '_>, jit: Option<Jit<'gc>>) {
+        self.0.write(mc).jit = jit;
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn jit(self) -> Option<Jit<'gc>> {
+        self.0.read().jit
+    }
+
+    /// Returns a snapshot of every active Lua function call on this thread, from the outermost to
+    /// the innermost.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn stack_frames(self) -> Vec<StackFrame<'gc>> {
+        let state = self.0.read();
+        let lua_frames = state
+            .frames
+            .iter()
+            .filter(|frame| matches!(frame, Frame::Lua { .. }))
+            .count();
+
+        let mut stack_frames = Vec::with_capacity(lua_frames);
+        for frame in &state.frames {
+            if let Frame::Lua {
+                bottom,
+                base,
+                pc,
+                stack_size,
+                ..
+            } = *frame
+            {
+                let closure = match state.values[bottom] {
+                    Value::Function(Function::Closure(c)) => c,
+                    _ => panic!("thread bottom is not a closure"),
+                };
+                // Every frame but the innermost has already advanced past its call instruction.
+                let pc = if stack_frames.len() + 1 == lua_frames {
+                    pc
+                } else {
+                    pc.saturating_sub(1)
+                };
+                let upvalues = closure.0.upvalues.iter().map(|u| u.get()).collect();
+                stack_frames.push(StackFrame {
+                    closure,
+                    pc,
+                    line: closure.0.proto.opcode_line(pc),
+                    registers: state.values[base..base + stack_size].to_vec(),
+                    upvalues,
+                });
+            }
+        }
+        stack_frames
+    }
+
+    /// Returns where this thread currently is: a traceback of every active Lua function call and
+    /// the registers of the innermost one, each formatted to the given table depth.  Meant to be
+    /// called when a host stops a running thread that has exceeded its budget.
+    ///
+    /// Must not be called while the thread is being stepped.
+    pub fn snapshot(self, depth: usize) -> StackSnapshot {
+        let frames = self.stack_frames();
+        let locals = match frames.last() {
+            Some(frame) => frame
+                .registers
+                .iter()
+                .map(|value| value.debug_fmt(depth))
+                .collect(),
+            None => Vec::new(),
+        };
+        let frames = frames
+            .iter()
+            .rev()
+            .map(|frame| TracebackFrame {
+                line: frame.line,
+                function_lines: frame.closure.0.proto.info().lines,
+                chunk_name: frame.closure.0.proto.chunk_name.clone(),
+            })
+            .collect();
+        StackSnapshot {
+            traceback: Traceback { frames },
+            locals,
+        }
+    }
+
+    // Returns the value at the given absolute stack index.
+    pub(crate) fn stack_value(self, index: usize) -> Value<'gc> {
+        self.0.read().values[index]
+    }
+
+    // Returns every value directly referenced by this thread: its stack, any suspended coroutine
+    // function, and any pending results.
+    pub(crate) fn referenced_values(self) -> Vec<Value<'gc>> {
+        let state = self.0.read();
+        let mut values = state.values.clone();
+        for frame in &state.frames {
+            if let Frame::StartCoroutine(function) = frame {
+                values.push(Value::Function(*function));
+            }
+        }
+        if let Some(Ok(results)) = &state.result {
+            values.extend_from_slice(results);
+        }
+        values
+    }
+
+    // An estimate of the memory used by this thread, in bytes.
+    pub(crate) fn heap_size(self) -> usize {
+        let state = self.0.read();
+        mem::size_of::<ThreadState>()
+            + state.values.capacity() * mem::size_of::<Value>()
+            + state.frames.capacity() * mem::size_of::<Frame>()
+            + state.open_upvalues.len() * mem::size_of::<(usize, UpValue)>()
+    }
+
+    // If the innermost frame of this thread is a Lua frame, returns its position.
+    pub(crate) fn lua_position(self) -> Option<LuaPosition<'gc>> {
+        let state = self.0.read();
+        match state.frames.last() {
+            Some(Frame::Lua { bottom, pc, .. }) => {
+                let closure = match state.values[*bottom] {
+                    Value::Function(Function::Closure(c)) => c,
+                    _ => panic!("thread bottom is not a closure"),
+                };
+                let depth = state
+                    .frames
+                    .iter()
+                    .filter(|frame| matches!(frame, Frame::Lua { .. }))
+                    .count();
+                Some(LuaPosition {
+                    depth,
+                    closure,
+                    pc: *pc,
+                })
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<'gc, 'a> LuaFrame<'gc, 'a> {
@@ -285,6 +866,17 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                     base: *base,
                     open_upvalues: &mut self.state.open_upvalues,
                     thread: self.thread,
+                    coverage: self.state.coverage,
+                    type_feedback: self.state.type_feedback,
+                    string_metatable: self.state.string_metatable,
+                    max_string_len: self.state.max_string_len.get(),
+                    string_coercion: self.state.string_coercion.get(),
+                    #[cfg(feature = "trace")]
+                    opcode_trace: &mut self.state.opcode_trace,
+                    #[cfg(feature = "jit")]
+                    jit: self.state.jit,
+                    #[cfg(feature = "jit")]
+                    native_moves: &mut self.state.native_moves,
                 }
             }
             _ => panic!("top frame is not lua frame"),
@@ -374,6 +966,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
                         self.state.values.resize(base + stack_size, Value::Nil);
 
+                        let profile_started = profile_enter(self.state, mc, closure);
                         self.state.frames.push(Frame::Lua {
                             bottom: function_index,
                             base,
@@ -381,14 +974,16 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                             pc: 0,
                             stack_size,
                             expected_returns: None,
+                            profile_started,
                         });
                         Ok(())
                     }
                     Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
+                        let args = scratch_copy(
+                            &mut self.state.scratch,
+                            &self.state.values[function_index + 1..function_index + 1 + arg_count],
                         );
+                        let ret = call_callback(self.state.profiler, mc, callback, args);
                         self.state.values.resize(function_index, Value::Nil);
                         callback_return(self.thread, &mut self.state, mc, ret);
                         Ok(())
@@ -450,6 +1045,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
                         self.state.values.resize(base + stack_size, Value::Nil);
 
+                        let profile_started = profile_enter(self.state, mc, closure);
                         self.state.frames.push(Frame::Lua {
                             bottom: function_index,
                             base,
@@ -457,14 +1053,16 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                             pc: 0,
                             stack_size,
                             expected_returns: None,
+                            profile_started,
                         });
                         Ok(())
                     }
                     Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
+                        let args = scratch_copy(
+                            &mut self.state.scratch,
+                            &self.state.values[function_index + 1..function_index + 1 + arg_count],
                         );
+                        let ret = call_callback(self.state.profiler, mc, callback, args);
                         self.state.values.resize(function_index, Value::Nil);
                         callback_return(self.thread, &mut self.state, mc, ret);
                         Ok(())
@@ -492,11 +1090,13 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 bottom,
                 base,
                 is_variable,
+                profile_started,
                 ..
             }) => {
                 if is_variable != args.is_variable() {
                     return Err(ThreadError::ExpectedVariable(is_variable));
                 }
+                profile_exit(self.state, mc, bottom, profile_started);
 
                 close_upvalues(self.thread, self.state, mc, bottom);
 
@@ -527,6 +1127,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
                         self.state.values.resize(base + stack_size, Value::Nil);
 
+                        let profile_started = profile_enter(self.state, mc, closure);
                         self.state.frames.push(Frame::Lua {
                             bottom,
                             base,
@@ -534,14 +1135,16 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                             pc: 0,
                             stack_size,
                             expected_returns: None,
+                            profile_started,
                         });
                         Ok(())
                     }
                     Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
+                        let args = scratch_copy(
+                            &mut self.state.scratch,
+                            &self.state.values[function_index + 1..function_index + 1 + arg_count],
                         );
+                        let ret = call_callback(self.state.profiler, mc, callback, args);
                         self.state.values.truncate(bottom);
                         callback_return(self.thread, &mut self.state, mc, ret);
                         Ok(())
@@ -568,11 +1171,13 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 bottom,
                 base,
                 is_variable,
+                profile_started,
                 ..
             }) => {
                 if is_variable != count.is_variable() {
                     return Err(ThreadError::ExpectedVariable(is_variable));
                 }
+                profile_exit(self.state, mc, bottom, profile_started);
                 close_upvalues(self.thread, self.state, mc, bottom);
 
                 let start = base + start.0 as usize;
@@ -584,7 +1189,10 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 match self.state.frames.last_mut() {
                     Some(Frame::Continuation { continuation, .. }) => {
                         let continuation = continuation.take().expect("continuation missing");
-                        let ret_vals = self.state.values[start..start + count].to_vec();
+                        let ret_vals = scratch_copy(
+                            &mut self.state.scratch,
+                            &self.state.values[start..start + count],
+                        );
                         self.state.values.truncate(bottom);
                         let ret = continuation.call(Ok(ret_vals));
                         self.state.frames.pop();
@@ -620,6 +1228,9 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                             *is_variable = false;
                         }
                     }
+                    Some(Frame::Hook { .. }) => {
+                        return_from_hook(self.state);
+                    }
                     None => {
                         let ret_vals = self.state.values[start..start + count].to_vec();
                         self.state.result = Some(Ok(ret_vals));
@@ -719,6 +1330,8 @@ enum Frame<'gc> {
         pc: usize,
         stack_size: usize,
         expected_returns: Option<VarCount>,
+        // When the call started, if it is being profiled.
+        profile_started: Option<Duration>,
     },
     Continuation {
         bottom: usize,
@@ -726,6 +1339,11 @@ enum Frame<'gc> {
     },
     StartCoroutine(Function<'gc>),
     ResumeCoroutine,
+    // Marks a call to a hook function, whose results are discarded.  Values above `bottom` belong
+    // to the hook call.
+    Hook {
+        bottom: usize,
+    },
     Callback(
         Option<Box<dyn Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>> + 'gc>>,
     ),
@@ -745,9 +1363,10 @@ fn get_mode<'gc>(state: &ThreadState<'gc>) -> ThreadMode {
                 ThreadMode::Stopped
             }
             Some(frame) => match frame {
-                Frame::Callback(_) | Frame::Continuation { .. } | Frame::Lua { .. } => {
-                    ThreadMode::Running
-                }
+                Frame::Callback(_)
+                | Frame::Continuation { .. }
+                | Frame::Lua { .. }
+                | Frame::Hook { .. } => ThreadMode::Running,
                 Frame::StartCoroutine(_) | Frame::ResumeCoroutine => ThreadMode::Suspended,
             },
         }
@@ -793,9 +1412,10 @@ fn ext_call_function<'gc>(
                 state.values[base + i] = args.get(i).cloned().unwrap_or(Value::Nil);
             }
             for i in 0..var_params {
-                state.values[1 + i] = args[fixed_params + i]
+                state.values[bottom + 1 + i] = args[fixed_params + i]
             }
 
+            let profile_started = profile_enter(state, mc, closure);
             state.frames.push(Frame::Lua {
                 bottom,
                 base,
@@ -803,10 +1423,12 @@ fn ext_call_function<'gc>(
                 pc: 0,
                 stack_size,
                 expected_returns: None,
+                profile_started,
             });
         }
         Function::Callback(callback) => {
-            let ret = callback.call(args.to_vec());
+            let args = scratch_copy(&mut state.scratch, args);
+            let ret = call_callback(state.profiler, mc, callback, args);
             callback_return(thread, state, mc, ret);
         }
     }
@@ -846,6 +1468,42 @@ fn return_to_lua<'gc>(state: &mut ThreadState<'gc>, rets: &[Value<'gc>]) {
     };
 }
 
+// Calls the given count hook function on top of the current frame, if there is still a frame to
+// return to.
+fn call_hook<'gc>(
+    thread: Thread<'gc>,
+    state: &mut ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+    function: Function<'gc>,
+) {
+    match state.frames.last() {
+        Some(Frame::Lua { .. }) | Some(Frame::Callback(_)) => {
+            state.in_hook = true;
+            let bottom = state.values.len();
+            state.frames.push(Frame::Hook { bottom });
+            ext_call_function(
+                thread,
+                state,
+                mc,
+                function,
+                &[Value::String(String::new_static(b"count"))],
+            );
+        }
+        _ => {}
+    }
+}
+
+// Discard the results of a finished hook call and return to the frame it interrupted
+fn return_from_hook<'gc>(state: &mut ThreadState<'gc>) {
+    match state.frames.pop() {
+        Some(Frame::Hook { bottom }) => {
+            state.values.truncate(bottom);
+            state.in_hook = false;
+        }
+        _ => panic!("top frame is not a hook frame"),
+    }
+}
+
 // TODO: `unwind`, `return_ext`, and `callback_return` have to be merged somehow, because otherwise
 // they are a stack overflow risk in pathalogical or malicious cases.
 
@@ -855,7 +1513,22 @@ fn unwind<'gc>(
     mc: MutationContext<'gc, '_>,
     error: Error<'gc>,
 ) {
+    // The traceback is captured where the error is first raised and kept while it propagates
+    // through continuations, in case none of them catch it.
+    if state.capture_tracebacks && state.traceback.is_none() {
+        state.traceback = Some(capture_traceback(state));
+    }
+
     while let Some(mut top_frame) = state.frames.pop() {
+        match top_frame {
+            Frame::Hook { .. } => state.in_hook = false,
+            Frame::Lua {
+                bottom,
+                profile_started,
+                ..
+            } => profile_exit(state, mc, bottom, profile_started),
+            _ => {}
+        }
         if let Frame::Continuation {
             continuation,
             bottom,
@@ -874,12 +1547,65 @@ fn unwind<'gc>(
     state.result = Some(Err(error));
 }
 
+// Counts a call of the given closure and returns its start time, if the thread has a profiler.
+fn profile_enter<'gc>(
+    state: &ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+    closure: Closure<'gc>,
+) -> Option<Duration> {
+    let profiler = state.profiler?;
+    Some(profiler.enter(mc, ProfiledFunction::Lua(closure.0.proto)))
+}
+
+// Records the end of the call in a popped Lua frame, whose closure must still be at `bottom`.
+fn profile_exit<'gc>(
+    state: &ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+    bottom: usize,
+    profile_started: Option<Duration>,
+) {
+    if let (Some(profiler), Some(started)) = (state.profiler, profile_started) {
+        match state.values[bottom] {
+            Value::Function(Function::Closure(c)) => {
+                profiler.exit(mc, ProfiledFunction::Lua(c.0.proto), started)
+            }
+            _ => panic!("thread bottom is not a closure"),
+        }
+    }
+}
+
+// Every Lua frame of an erroring thread has already advanced past the instruction that raised the
+// error or called the function that did.
+fn capture_traceback<'gc>(state: &ThreadState<'gc>) -> Traceback {
+    let frames = state
+        .frames
+        .iter()
+        .rev()
+        .filter_map(|frame| match *frame {
+            Frame::Lua { bottom, pc, .. } => match state.values[bottom] {
+                Value::Function(Function::Closure(c)) => Some(TracebackFrame {
+                    line: c.0.proto.opcode_line(pc.saturating_sub(1)),
+                    function_lines: c.0.proto.info().lines,
+                    chunk_name: c.0.proto.chunk_name.clone(),
+                }),
+                _ => panic!("thread bottom is not a closure"),
+            },
+            _ => None,
+        })
+        .collect();
+    Traceback { frames }
+}
+
 fn return_ext<'gc>(
     thread: Thread<'gc>,
     state: &mut ThreadState<'gc>,
     mc: MutationContext<'gc, '_>,
     res: Result<CallbackResult<'gc>, Error<'gc>>,
 ) {
+    if res.is_ok() {
+        // Any error raised before has been caught.
+        state.traceback = None;
+    }
     match res {
         Err(err) => {
             unwind(thread, state, mc, err);
@@ -888,12 +1614,16 @@ fn return_ext<'gc>(
             if state.allow_yield {
                 state.frames.push(Frame::ResumeCoroutine);
                 state.result = Some(Ok(res));
+            } else if state.nested {
+                unwind(thread, state, mc, ThreadError::YieldAcrossCallback.into());
             } else {
-                unwind(thread, state, mc, ThreadError::BadYield.into());
+                unwind(thread, state, mc, ThreadError::YieldOutsideCoroutine.into());
             }
         }
         Ok(CallbackResult::Return(res)) => match state.frames.last_mut() {
             Some(Frame::Continuation { continuation, .. }) => {
+                // The continuation owns `res` now; whatever it returns is recycled once it reaches
+                // the frame below.
                 let continuation = continuation.take().expect("continuation missing");
                 let ret = continuation.call(Ok(res));
                 state.frames.pop();
@@ -901,9 +1631,16 @@ fn return_ext<'gc>(
             }
             Some(Frame::Lua { .. }) => {
                 return_to_lua(state, &res);
+                recycle_scratch(&mut state.scratch, res);
+            }
+            Some(Frame::Hook { .. }) => {
+                return_from_hook(state);
+                recycle_scratch(&mut state.scratch, res);
             }
             None => {
-                state.result = Some(Ok(res));
+                // The results are handed to the host, so keep the buffer and give it a copy.
+                state.result = Some(Ok(res.to_vec()));
+                recycle_scratch(&mut state.scratch, res);
             }
             _ => panic!("frame above callback must be continuation or lua frame"),
         },
@@ -918,10 +1655,31 @@ fn return_ext<'gc>(
                 bottom,
             });
             ext_call_function(thread, state, mc, function, &args);
+            recycle_scratch(&mut state.scratch, args);
         }
     }
 }
 
+// The most buffers kept in `ThreadState::scratch`, and the largest buffer worth keeping, so that
+// one call with a huge number of arguments does not hold on to its memory.
+const MAX_SCRATCH_BUFFERS: usize = 8;
+const MAX_SCRATCH_CAPACITY: usize = 64;
+
+// Returns a buffer holding a copy of `values`, reusing one from the scratch pool if possible.
+fn scratch_copy<'gc>(scratch: &mut Vec<Vec<Value<'gc>>>, values: &[Value<'gc>]) -> Vec<Value<'gc>> {
+    let mut buffer = scratch.pop().unwrap_or_default();
+    buffer.extend_from_slice(values);
+    buffer
+}
+
+// Returns a buffer to the scratch pool once its contents have been copied out.
+fn recycle_scratch<'gc>(scratch: &mut Vec<Vec<Value<'gc>>>, mut buffer: Vec<Value<'gc>>) {
+    if scratch.len() < MAX_SCRATCH_BUFFERS && buffer.capacity() <= MAX_SCRATCH_CAPACITY {
+        buffer.clear();
+        scratch.push(buffer);
+    }
+}
+
 fn callback_return<'gc>(
     thread: Thread<'gc>,
     state: &mut ThreadState<'gc>,
@@ -0,0 +1,251 @@
+use std::fmt::{self, Write};
+use std::str;
+use std::string::String as StdString;
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence::Sequence;
+
+use crate::{
+    BadThreadMode, ChunkName, Error, Function, InternedStringSet, LineNumber, Thread, ThreadMode,
+    Value,
+};
+
+/// The Lua function calls that were active on a thread when an error escaped every frame of it,
+/// innermost first.  Captured by threads with `Thread::set_capture_tracebacks` enabled, which is
+/// the default.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Collect)]
+#[collect(require_static)]
+pub struct Traceback {
+    pub frames: Vec<TracebackFrame>,
+}
+
+/// One Lua function call in a `Traceback`.
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct TracebackFrame {
+    /// The source line being executed in this call, if known.
+    pub line: Option<LineNumber>,
+    /// The first and last source lines of the called function, as in `FunctionInfo::lines`.
+    pub function_lines: Option<(LineNumber, LineNumber)>,
+    /// The name of the chunk the called function was compiled from, if it was named.
+    pub chunk_name: Option<ChunkName>,
+}
+
+impl fmt::Display for Traceback {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&ErrorFormat::default().traceback(self))
+    }
+}
+
+/// How a host renders script failures for people to read, such as in a terminal or a log window.
+///
+/// The default renders every frame of a traceback without color, which is also how `Traceback`
+/// implements `Display`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ErrorFormat {
+    /// The most traceback frames to show.  Longer tracebacks, such as from runaway recursion, keep
+    /// their innermost and outermost frames and replace the ones in the middle with a line saying
+    /// how many were skipped.
+    pub max_frames: Option<usize>,
+    /// Whether bytes of error messages that are not valid UTF-8 are shown as `\xNN` escapes,
+    /// rather than each invalid sequence being replaced by U+FFFD.
+    pub escape_invalid_utf8: bool,
+    /// Whether to color the output with ANSI escape codes, for terminals that support them.
+    pub color: bool,
+}
+
+const ANSI_RED: &str = "\x1b[1;31m";
+const ANSI_CYAN: &str = "\x1b[36m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+impl ErrorFormat {
+    /// Renders an error value, such as the `value` of a `CallResult::Err`, followed by its
+    /// traceback if there is one.
+    pub fn error<'gc>(&self, error: Value<'gc>, traceback: Option<&Traceback>) -> StdString {
+        let mut message = Vec::new();
+        error
+            .display(&mut message)
+            .expect("writing to a Vec cannot fail");
+
+        let mut out = StdString::new();
+        if self.color {
+            out.push_str(ANSI_RED);
+        }
+        out.push_str("error:");
+        if self.color {
+            out.push_str(ANSI_RESET);
+        }
+        out.push(' ');
+        self.push_bytes(&mut out, &message);
+        if let Some(traceback) = traceback {
+            out.push('\n');
+            out.push_str(&self.traceback(traceback));
+        }
+        out
+    }
+
+    /// Renders a traceback, innermost frame first.
+    pub fn traceback(&self, traceback: &Traceback) -> StdString {
+        let frames = &traceback.frames;
+        let (head, skipped) = match self.max_frames {
+            Some(max) if frames.len() > max => (max - max / 2, frames.len() - max),
+            _ => (frames.len(), 0),
+        };
+
+        let mut out = StdString::from("stack traceback:");
+        for (i, frame) in frames.iter().enumerate() {
+            if i == head && skipped != 0 {
+                write!(out, "\n\t...\t(skipping {} levels)", skipped).unwrap();
+            }
+            if i >= head && i < head + skipped {
+                continue;
+            }
+
+            out.push_str("\n\t");
+            if self.color {
+                out.push_str(ANSI_CYAN);
+            }
+            match (&frame.chunk_name, frame.line) {
+                (Some(chunk_name), Some(line)) => write!(out, "{}:{}", chunk_name, line),
+                (Some(chunk_name), None) => write!(out, "{}:?", chunk_name),
+                (None, Some(line)) => write!(out, "line {}", line),
+                (None, None) => write!(out, "line ?"),
+            }
+            .unwrap();
+            if self.color {
+                out.push_str(ANSI_RESET);
+            }
+            match frame.function_lines {
+                Some((first, _)) => write!(out, " in function starting at line {}", first),
+                None => write!(out, " in function"),
+            }
+            .unwrap();
+        }
+        out
+    }
+
+    // Appends `bytes` to `out` as UTF-8, replacing or escaping invalid sequences.
+    fn push_bytes(&self, out: &mut StdString, mut bytes: &[u8]) {
+        loop {
+            match str::from_utf8(bytes) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    return;
+                }
+                Err(error) => {
+                    let (valid, rest) = bytes.split_at(error.valid_up_to());
+                    out.push_str(str::from_utf8(valid).unwrap());
+                    let invalid_len = error.error_len().unwrap_or(rest.len());
+                    if self.escape_invalid_utf8 {
+                        for b in &rest[..invalid_len] {
+                            write!(out, "\\x{:02x}", b).unwrap();
+                        }
+                    } else {
+                        out.push(char::REPLACEMENT_CHARACTER);
+                    }
+                    bytes = &rest[invalid_len..];
+                }
+            }
+        }
+    }
+}
+
+/// Where a running thread currently is, as returned by `Thread::snapshot`.  Hosts that stop a
+/// script once it has used up its instruction or time budget can capture one before giving up on
+/// the thread, to report where the script was stuck.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Collect)]
+#[collect(require_static)]
+pub struct StackSnapshot {
+    /// Every active Lua function call, innermost first.
+    pub traceback: Traceback,
+    /// The registers of the innermost Lua function call, formatted with `Value::debug_fmt`.  Local
+    /// variables live in the lowest registers in order of declaration.
+    pub locals: Vec<StdString>,
+}
+
+impl fmt::Display for StackSnapshot {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.traceback)?;
+        if !self.locals.is_empty() {
+            write!(fmt, "\nlocals:")?;
+            for (i, local) in self.locals.iter().enumerate() {
+                write!(fmt, "\n\t[{}] = {}", i, local)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of calling a function through a `CallSequence`.
+// Safe, does not implement drop
+#[derive(Debug, Collect)]
+#[collect(unsafe_drop)]
+pub enum CallResult<'gc> {
+    Ok(Vec<Value<'gc>>),
+    Err {
+        /// The error as a Lua value, the same value `pcall` would return.
+        value: Value<'gc>,
+        /// Where the error was raised, if the thread captures tracebacks.
+        traceback: Option<Traceback>,
+    },
+}
+
+impl<'gc> CallResult<'gc> {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, CallResult::Ok(_))
+    }
+}
+
+/// The same as `ThreadSequence`, but producing a `CallResult`.
+#[derive(Collect)]
+#[collect(empty_drop)]
+pub struct CallSequence<'gc> {
+    thread: Thread<'gc>,
+    interned_strings: InternedStringSet<'gc>,
+}
+
+impl<'gc> CallSequence<'gc> {
+    /// Thread must be `Stopped` in order to call a function on it.  `interned_strings` is used to
+    /// turn errors other than runtime errors into Lua strings.
+    pub fn call_function(
+        mc: MutationContext<'gc, '_>,
+        thread: Thread<'gc>,
+        interned_strings: InternedStringSet<'gc>,
+        function: Function<'gc>,
+        args: &[Value<'gc>],
+    ) -> Result<CallSequence<'gc>, BadThreadMode> {
+        thread.start(mc, function, args)?;
+        Ok(CallSequence {
+            thread,
+            interned_strings,
+        })
+    }
+}
+
+impl<'gc> Sequence<'gc> for CallSequence<'gc> {
+    type Output = CallResult<'gc>;
+
+    fn step(&mut self, mc: MutationContext<'gc, '_>) -> Option<Self::Output> {
+        match self.thread.mode() {
+            ThreadMode::Results => Some(match self.thread.take_results(mc)? {
+                Ok(values) => CallResult::Ok(values),
+                Err(error) => CallResult::Err {
+                    value: error.to_value(mc, self.interned_strings),
+                    traceback: self.thread.take_traceback(mc),
+                },
+            }),
+            ThreadMode::Running => {
+                self.thread.step(mc).unwrap();
+                None
+            }
+            mode => Some(CallResult::Err {
+                value: Error::from(BadThreadMode {
+                    expected: None,
+                    found: mode,
+                })
+                .to_value(mc, self.interned_strings),
+                traceback: None,
+            }),
+        }
+    }
+}
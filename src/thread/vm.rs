@@ -1,10 +1,14 @@
 use gc_arena::{Gc, MutationContext};
 
 use crate::{
-    thread::LuaFrame, BinaryOperatorError, Closure, ClosureState, Error, Function, OpCode,
-    RegisterIndex, String, Table, TypeError, UpValueDescriptor, Value, VarCount,
+    thread::LuaFrame, BinaryOperatorError, Closure, ClosureState, Error, ForLoopError, Function,
+    OpCode, Operation, RegisterIndex, String, StringError, Table, TypeError, UpValueDescriptor,
+    Value, VarCount,
 };
 
+#[cfg(feature = "jit")]
+use crate::thread::LuaRegisters;
+
 // Runs the VM for the given number of instructions or until the current LuaFrame may have been
 // changed.  Returns the number of instructions that were not run, or 0 if all requested
 // instructions were run.
@@ -17,10 +21,57 @@ pub(crate) fn run_vm<'gc>(
 
     let current_function = lua_frame.closure();
     let mut registers = lua_frame.registers();
+    let mut current_line = None;
+    #[cfg(feature = "jit")]
+    let native_code = match registers.jit {
+        Some(jit) if !observes_instructions(&registers) => jit.start(mc, current_function.0.proto),
+        _ => None,
+    };
 
     loop {
+        #[cfg(feature = "jit")]
+        {
+            if let Some(native_code) = &native_code {
+                if native_code.compiles(*registers.pc) {
+                    native_code.run(
+                        registers.stack_frame,
+                        registers.native_moves,
+                        registers.pc,
+                        &mut instructions,
+                    );
+                    if instructions == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
         let op = current_function.0.proto.opcodes[*registers.pc];
+        if let Some(coverage) = registers.coverage {
+            let line = current_function.0.proto.opcode_line(*registers.pc);
+            if line != current_line {
+                current_line = line;
+                if let Some(line) = line {
+                    coverage.record(mc, current_function.0.proto, line);
+                }
+            }
+        }
+        if let Some(type_feedback) = registers.type_feedback {
+            type_feedback.record(
+                mc,
+                current_function.0.proto,
+                *registers.pc,
+                registers.stack_frame,
+            );
+        }
+        #[cfg(feature = "trace")]
+        {
+            if let Some(trace) = registers.opcode_trace {
+                trace.opcode(current_function, *registers.pc, op, registers.stack_frame);
+            }
+        }
         *registers.pc += 1;
+        instructions -= 1;
 
         match op {
             OpCode::Move { dest, source } => {
@@ -44,8 +95,10 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::LoadNil { dest, count } => {
-                for i in dest.0..dest.0 + count {
-                    registers.stack_frame[i as usize] = Value::Nil;
+                // Computed in `usize` so that a range past the last register cannot wrap around.
+                let start = dest.0 as usize;
+                for value in &mut registers.stack_frame[start..start + count as usize] {
+                    *value = Value::Nil;
                 }
             }
 
@@ -54,15 +107,19 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::GetTableR { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.stack_frame[table.0 as usize])?
-                        .get(registers.stack_frame[key.0 as usize]);
+                registers.stack_frame[dest.0 as usize] = index(
+                    registers.string_metatable,
+                    registers.stack_frame[table.0 as usize],
+                    registers.stack_frame[key.0 as usize],
+                )?;
             }
 
             OpCode::GetTableC { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.stack_frame[table.0 as usize])?
-                        .get(current_function.0.proto.constants[key.0 as usize].to_value())
+                registers.stack_frame[dest.0 as usize] = index(
+                    registers.string_metatable,
+                    registers.stack_frame[table.0 as usize],
+                    current_function.0.proto.constants[key.0 as usize].to_value(),
+                )?;
             }
 
             OpCode::SetTableRR { table, key, value } => {
@@ -98,16 +155,19 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::GetUpTableR { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] = get_table(
+                registers.stack_frame[dest.0 as usize] = index(
+                    registers.string_metatable,
                     registers.get_upvalue(current_function.0.upvalues[table.0 as usize]),
-                )?
-                .get(registers.stack_frame[key.0 as usize]);
+                    registers.stack_frame[key.0 as usize],
+                )?;
             }
 
             OpCode::GetUpTableC { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                        .get(current_function.0.proto.constants[key.0 as usize].to_value())
+                registers.stack_frame[dest.0 as usize] = index(
+                    registers.string_metatable,
+                    registers.get_upvalue(current_function.0.upvalues[table.0 as usize]),
+                    current_function.0.proto.constants[key.0 as usize].to_value(),
+                )?;
             }
 
             OpCode::SetUpTableRR { table, key, value } => {
@@ -223,9 +283,21 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::NumericForPrep { base, jump } => {
+                check_for_loop(
+                    registers.string_coercion,
+                    registers.stack_frame[base.0 as usize],
+                    registers.stack_frame[base.0 as usize + 1],
+                    registers.stack_frame[base.0 as usize + 2],
+                )?;
                 registers.stack_frame[base.0 as usize] = registers.stack_frame[base.0 as usize]
                     .subtract(registers.stack_frame[base.0 as usize + 2])
-                    .ok_or(BinaryOperatorError::Subtract)?;
+                    .ok_or_else(|| {
+                        BinaryOperatorError::binary(
+                            Operation::Subtract,
+                            registers.stack_frame[base.0 as usize],
+                            registers.stack_frame[base.0 as usize + 2],
+                        )
+                    })?;
                 *registers.pc = add_offset(*registers.pc, jump);
             }
 
@@ -266,7 +338,9 @@ pub(crate) fn run_vm<'gc>(
                                 registers.stack_frame[base.0 as usize + 3] = Value::Number(index);
                             }
                         } else {
-                            return Err(BinaryOperatorError::Add.into());
+                            return Err(
+                                BinaryOperatorError::binary(Operation::Add, index, step).into()
+                            );
                         }
                     }
                 }
@@ -283,7 +357,8 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::GenericForLoop { base, jump } => {
-                if registers.stack_frame[base.0 as usize + 1].to_bool() {
+                // Only nil ends the loop, an iterator may return false as its first value.
+                if registers.stack_frame[base.0 as usize + 1] != Value::Nil {
                     registers.stack_frame[base.0 as usize] =
                         registers.stack_frame[base.0 as usize + 1];
                     *registers.pc = add_offset(*registers.pc, jump);
@@ -292,16 +367,18 @@ pub(crate) fn run_vm<'gc>(
 
             OpCode::SelfR { base, table, key } => {
                 let table = registers.stack_frame[table.0 as usize];
-                let key = current_function.0.proto.constants[key.0 as usize].to_value();
+                let key = registers.stack_frame[key.0 as usize];
                 registers.stack_frame[base.0 as usize + 1] = table;
-                registers.stack_frame[base.0 as usize] = get_table(table)?.get(key);
+                registers.stack_frame[base.0 as usize] =
+                    index(registers.string_metatable, table, key)?;
             }
 
             OpCode::SelfC { base, table, key } => {
                 let table = registers.stack_frame[table.0 as usize];
                 let key = current_function.0.proto.constants[key.0 as usize].to_value();
                 registers.stack_frame[base.0 as usize + 1] = table;
-                registers.stack_frame[base.0 as usize] = get_table(table)?.get(key);
+                registers.stack_frame[base.0 as usize] =
+                    index(registers.string_metatable, table, key)?;
             }
 
             OpCode::Concat {
@@ -309,14 +386,24 @@ pub(crate) fn run_vm<'gc>(
                 source,
                 count,
             } => {
-                registers.stack_frame[dest.0 as usize] = Value::String(
-                    String::concat(
-                        mc,
-                        &registers.stack_frame
-                            [source.0 as usize..source.0 as usize + count as usize],
-                    )
-                    .unwrap(),
-                );
+                let values =
+                    &registers.stack_frame[source.0 as usize..source.0 as usize + count as usize];
+                if !registers.string_coercion {
+                    if let Some(number) = values
+                        .iter()
+                        .find(|v| matches!(v, Value::Integer(_) | Value::Number(_)))
+                    {
+                        return Err(StringError::Concat {
+                            bad_type: number.type_name(),
+                        }
+                        .into());
+                    }
+                }
+                registers.stack_frame[dest.0 as usize] = Value::String(String::concat(
+                    mc,
+                    &registers.stack_frame[source.0 as usize..source.0 as usize + count as usize],
+                    registers.max_string_len,
+                )?);
             }
 
             OpCode::GetUpValue { source, dest } => {
@@ -392,7 +479,11 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                if (left.less_than(right).ok_or(BinaryOperatorError::LessThan)?) == skip_if {
+                if (left
+                    .less_than(right)
+                    .ok_or_else(|| BinaryOperatorError::binary(Operation::LessThan, left, right))?)
+                    == skip_if
+                {
                     *registers.pc += 1;
                 }
             }
@@ -404,7 +495,11 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                if (left.less_than(right).ok_or(BinaryOperatorError::LessThan)?) == skip_if {
+                if (left
+                    .less_than(right)
+                    .ok_or_else(|| BinaryOperatorError::binary(Operation::LessThan, left, right))?)
+                    == skip_if
+                {
                     *registers.pc += 1;
                 }
             }
@@ -416,7 +511,11 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                if (left.less_than(right).ok_or(BinaryOperatorError::LessThan)?) == skip_if {
+                if (left
+                    .less_than(right)
+                    .ok_or_else(|| BinaryOperatorError::binary(Operation::LessThan, left, right))?)
+                    == skip_if
+                {
                     *registers.pc += 1;
                 }
             }
@@ -428,7 +527,11 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                if (left.less_than(right).ok_or(BinaryOperatorError::LessThan)?) == skip_if {
+                if (left
+                    .less_than(right)
+                    .ok_or_else(|| BinaryOperatorError::binary(Operation::LessThan, left, right))?)
+                    == skip_if
+                {
                     *registers.pc += 1;
                 }
             }
@@ -440,10 +543,9 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                if (left
-                    .less_equal(right)
-                    .ok_or(BinaryOperatorError::LessEqual)?)
-                    == skip_if
+                if (left.less_equal(right).ok_or_else(|| {
+                    BinaryOperatorError::binary(Operation::LessEqual, left, right)
+                })?) == skip_if
                 {
                     *registers.pc += 1;
                 }
@@ -456,10 +558,9 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                if (left
-                    .less_equal(right)
-                    .ok_or(BinaryOperatorError::LessEqual)?)
-                    == skip_if
+                if (left.less_equal(right).ok_or_else(|| {
+                    BinaryOperatorError::binary(Operation::LessEqual, left, right)
+                })?) == skip_if
                 {
                     *registers.pc += 1;
                 }
@@ -472,10 +573,9 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                if (left
-                    .less_equal(right)
-                    .ok_or(BinaryOperatorError::LessEqual)?)
-                    == skip_if
+                if (left.less_equal(right).ok_or_else(|| {
+                    BinaryOperatorError::binary(Operation::LessEqual, left, right)
+                })?) == skip_if
                 {
                     *registers.pc += 1;
                 }
@@ -488,10 +588,9 @@ pub(crate) fn run_vm<'gc>(
             } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                if (left
-                    .less_equal(right)
-                    .ok_or(BinaryOperatorError::LessEqual)?)
-                    == skip_if
+                if (left.less_equal(right).ok_or_else(|| {
+                    BinaryOperatorError::binary(Operation::LessEqual, left, right)
+                })?) == skip_if
                 {
                     *registers.pc += 1;
                 }
@@ -505,383 +604,435 @@ pub(crate) fn run_vm<'gc>(
             OpCode::Minus { dest, source } => {
                 let value = registers.stack_frame[source.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    value.negate().ok_or(BinaryOperatorError::UnaryNegate)?;
+                    unary_arithmetic(registers.string_coercion, Operation::UnaryNegate, value)?;
             }
 
             OpCode::BitNot { dest, source } => {
                 let value = registers.stack_frame[source.0 as usize];
-                registers.stack_frame[dest.0 as usize] = value
-                    .bitwise_not()
-                    .ok_or(BinaryOperatorError::BitNot)?;
+                registers.stack_frame[dest.0 as usize] =
+                    unary_arithmetic(registers.string_coercion, Operation::BitNot, value)?;
             }
 
             OpCode::AddRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.add(right).ok_or(BinaryOperatorError::Add)?;
+                    arithmetic(registers.string_coercion, Operation::Add, left, right)?;
             }
 
             OpCode::AddRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.add(right).ok_or(BinaryOperatorError::Add)?;
+                    arithmetic(registers.string_coercion, Operation::Add, left, right)?;
             }
 
             OpCode::AddCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.add(right).ok_or(BinaryOperatorError::Add)?;
+                    arithmetic(registers.string_coercion, Operation::Add, left, right)?;
             }
 
             OpCode::AddCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.add(right).ok_or(BinaryOperatorError::Add)?;
+                    arithmetic(registers.string_coercion, Operation::Add, left, right)?;
             }
 
             OpCode::SubRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.subtract(right).ok_or(BinaryOperatorError::Add)?;
+                    arithmetic(registers.string_coercion, Operation::Subtract, left, right)?;
             }
 
             OpCode::SubRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.subtract(right).ok_or(BinaryOperatorError::Subtract)?;
+                    arithmetic(registers.string_coercion, Operation::Subtract, left, right)?;
             }
 
             OpCode::SubCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.subtract(right).ok_or(BinaryOperatorError::Subtract)?;
+                    arithmetic(registers.string_coercion, Operation::Subtract, left, right)?;
             }
 
             OpCode::SubCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.subtract(right).ok_or(BinaryOperatorError::Subtract)?;
+                    arithmetic(registers.string_coercion, Operation::Subtract, left, right)?;
             }
 
             OpCode::MulRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.multiply(right).ok_or(BinaryOperatorError::Multiply)?;
+                    arithmetic(registers.string_coercion, Operation::Multiply, left, right)?;
             }
 
             OpCode::MulRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.multiply(right).ok_or(BinaryOperatorError::Multiply)?;
+                    arithmetic(registers.string_coercion, Operation::Multiply, left, right)?;
             }
 
             OpCode::MulCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.multiply(right).ok_or(BinaryOperatorError::Multiply)?;
+                    arithmetic(registers.string_coercion, Operation::Multiply, left, right)?;
             }
 
             OpCode::MulCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.multiply(right).ok_or(BinaryOperatorError::Multiply)?;
+                    arithmetic(registers.string_coercion, Operation::Multiply, left, right)?;
             }
 
             OpCode::DivRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .float_divide(right)
-                    .ok_or(BinaryOperatorError::FloatDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloatDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::DivRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .float_divide(right)
-                    .ok_or(BinaryOperatorError::FloatDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloatDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::DivCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .float_divide(right)
-                    .ok_or(BinaryOperatorError::FloatDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloatDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::DivCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .float_divide(right)
-                    .ok_or(BinaryOperatorError::FloatDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloatDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::IDivRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .floor_divide(right)
-                    .ok_or(BinaryOperatorError::FloorDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloorDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::IDivRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .floor_divide(right)
-                    .ok_or(BinaryOperatorError::FloorDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloorDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::IDivCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .floor_divide(right)
-                    .ok_or(BinaryOperatorError::FloorDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloorDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::IDivCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .floor_divide(right)
-                    .ok_or(BinaryOperatorError::FloorDivide)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::FloorDivide,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::ModRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.modulo(right).ok_or(BinaryOperatorError::Modulo)?;
+                    arithmetic(registers.string_coercion, Operation::Modulo, left, right)?;
             }
 
             OpCode::ModRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.modulo(right).ok_or(BinaryOperatorError::Modulo)?;
+                    arithmetic(registers.string_coercion, Operation::Modulo, left, right)?;
             }
 
             OpCode::ModCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.modulo(right).ok_or(BinaryOperatorError::Modulo)?;
+                    arithmetic(registers.string_coercion, Operation::Modulo, left, right)?;
             }
 
             OpCode::ModCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.modulo(right).ok_or(BinaryOperatorError::Modulo)?;
+                    arithmetic(registers.string_coercion, Operation::Modulo, left, right)?;
             }
 
             OpCode::PowRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .exponentiate(right)
-                    .ok_or(BinaryOperatorError::Exponentiate)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::Exponentiate,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::PowRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .exponentiate(right)
-                    .ok_or(BinaryOperatorError::Exponentiate)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::Exponentiate,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::PowCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .exponentiate(right)
-                    .ok_or(BinaryOperatorError::Exponentiate)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::Exponentiate,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::PowCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .exponentiate(right)
-                    .ok_or(BinaryOperatorError::Exponentiate)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::Exponentiate,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::BitAndRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_and(right).ok_or(BinaryOperatorError::BitAnd)?;
+                    arithmetic(registers.string_coercion, Operation::BitAnd, left, right)?;
             }
 
             OpCode::BitAndRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_and(right).ok_or(BinaryOperatorError::BitAnd)?;
+                    arithmetic(registers.string_coercion, Operation::BitAnd, left, right)?;
             }
 
             OpCode::BitAndCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_and(right).ok_or(BinaryOperatorError::BitAnd)?;
+                    arithmetic(registers.string_coercion, Operation::BitAnd, left, right)?;
             }
 
             OpCode::BitAndCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_and(right).ok_or(BinaryOperatorError::BitAnd)?;
+                    arithmetic(registers.string_coercion, Operation::BitAnd, left, right)?;
             }
 
             OpCode::BitOrRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_or(right).ok_or(BinaryOperatorError::BitOr)?;
+                    arithmetic(registers.string_coercion, Operation::BitOr, left, right)?;
             }
 
             OpCode::BitOrRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_or(right).ok_or(BinaryOperatorError::BitOr)?;
+                    arithmetic(registers.string_coercion, Operation::BitOr, left, right)?;
             }
 
             OpCode::BitOrCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_or(right).ok_or(BinaryOperatorError::BitOr)?;
+                    arithmetic(registers.string_coercion, Operation::BitOr, left, right)?;
             }
 
             OpCode::BitOrCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_or(right).ok_or(BinaryOperatorError::BitOr)?;
+                    arithmetic(registers.string_coercion, Operation::BitOr, left, right)?;
             }
 
             OpCode::BitXorRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_xor(right).ok_or(BinaryOperatorError::BitXor)?;
+                    arithmetic(registers.string_coercion, Operation::BitXor, left, right)?;
             }
 
             OpCode::BitXorRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_xor(right).ok_or(BinaryOperatorError::BitXor)?;
+                    arithmetic(registers.string_coercion, Operation::BitXor, left, right)?;
             }
 
             OpCode::BitXorCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_xor(right).ok_or(BinaryOperatorError::BitXor)?;
+                    arithmetic(registers.string_coercion, Operation::BitXor, left, right)?;
             }
 
             OpCode::BitXorCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
                 registers.stack_frame[dest.0 as usize] =
-                    left.bitwise_xor(right).ok_or(BinaryOperatorError::BitXor)?;
+                    arithmetic(registers.string_coercion, Operation::BitXor, left, right)?;
             }
 
             OpCode::ShiftLeftRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_left(right)
-                    .ok_or(BinaryOperatorError::ShiftLeft)?;
+                registers.stack_frame[dest.0 as usize] =
+                    arithmetic(registers.string_coercion, Operation::ShiftLeft, left, right)?;
             }
 
             OpCode::ShiftLeftRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_left(right)
-                    .ok_or(BinaryOperatorError::ShiftLeft)?;
+                registers.stack_frame[dest.0 as usize] =
+                    arithmetic(registers.string_coercion, Operation::ShiftLeft, left, right)?;
             }
 
             OpCode::ShiftLeftCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_left(right)
-                    .ok_or(BinaryOperatorError::ShiftLeft)?;
+                registers.stack_frame[dest.0 as usize] =
+                    arithmetic(registers.string_coercion, Operation::ShiftLeft, left, right)?;
             }
 
             OpCode::ShiftLeftCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_left(right)
-                    .ok_or(BinaryOperatorError::ShiftLeft)?;
+                registers.stack_frame[dest.0 as usize] =
+                    arithmetic(registers.string_coercion, Operation::ShiftLeft, left, right)?;
             }
 
             OpCode::ShiftRightRR { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_right(right)
-                    .ok_or(BinaryOperatorError::ShiftRight)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::ShiftRight,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::ShiftRightRC { dest, left, right } => {
                 let left = registers.stack_frame[left.0 as usize];
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_right(right)
-                    .ok_or(BinaryOperatorError::ShiftRight)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::ShiftRight,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::ShiftRightCR { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = registers.stack_frame[right.0 as usize];
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_right(right)
-                    .ok_or(BinaryOperatorError::ShiftRight)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::ShiftRight,
+                    left,
+                    right,
+                )?;
             }
 
             OpCode::ShiftRightCC { dest, left, right } => {
                 let left = current_function.0.proto.constants[left.0 as usize].to_value();
                 let right = current_function.0.proto.constants[right.0 as usize].to_value();
-                registers.stack_frame[dest.0 as usize] = left
-                    .shift_right(right)
-                    .ok_or(BinaryOperatorError::ShiftRight)?;
+                registers.stack_frame[dest.0 as usize] = arithmetic(
+                    registers.string_coercion,
+                    Operation::ShiftRight,
+                    left,
+                    right,
+                )?;
             }
         }
 
         if instructions == 0 {
             break;
-        } else {
-            instructions -= 1
         }
     }
 
     Ok(instructions)
 }
 
+// Whether anything is set on the thread that needs to see each instruction as it runs, in which
+// case native code is not used.
+#[cfg(feature = "jit")]
+fn observes_instructions(registers: &LuaRegisters) -> bool {
+    #[cfg(feature = "trace")]
+    let traced = registers.opcode_trace.is_some();
+    #[cfg(not(feature = "trace"))]
+    let traced = false;
+    registers.coverage.is_some() || registers.type_feedback.is_some() || traced
+}
+
 fn get_table<'gc>(value: Value<'gc>) -> Result<Table<'gc>, TypeError> {
     match value {
         Value::Table(t) => Ok(t),
@@ -892,6 +1043,119 @@ fn get_table<'gc>(value: Value<'gc>) -> Result<Table<'gc>, TypeError> {
     }
 }
 
+// Indexes a value for reading.  Strings are indexed through the `__index` table of the thread's
+// string metatable, if it has one, and every other type except tables is an error.
+fn index<'gc>(
+    string_metatable: Option<Table<'gc>>,
+    value: Value<'gc>,
+    key: Value<'gc>,
+) -> Result<Value<'gc>, TypeError> {
+    let table = match (value, string_metatable) {
+        (Value::Table(table), _) => table,
+        (Value::String(_), Some(metatable)) => {
+            match metatable.get(String::new_static(b"__index")) {
+                Value::Table(table) => table,
+                _ => {
+                    return Err(TypeError {
+                        expected: "table",
+                        found: value.type_name(),
+                    })
+                }
+            }
+        }
+        (value, _) => get_table(value)?,
+    };
+    Ok(table.get(key))
+}
+
+// Performs an arithmetic or bitwise operation.  Strings are only converted to numbers if
+// `string_coercion` is set, see `Thread::set_string_coercion`, and integer division and modulo by
+// zero are errors which report the numerator.
+fn arithmetic<'gc>(
+    string_coercion: bool,
+    operation: Operation,
+    left: Value<'gc>,
+    right: Value<'gc>,
+) -> Result<Value<'gc>, BinaryOperatorError> {
+    let error = || BinaryOperatorError::binary(operation, left, right);
+    if !string_coercion && (is_string(left) || is_string(right)) {
+        return Err(error());
+    }
+    match operation {
+        Operation::Add => left.add(right),
+        Operation::Subtract => left.subtract(right),
+        Operation::Multiply => left.multiply(right),
+        Operation::FloatDivide => left.float_divide(right),
+        Operation::FloorDivide => left.floor_divide(right),
+        Operation::Modulo => left.modulo(right),
+        Operation::Exponentiate => left.exponentiate(right),
+        Operation::BitAnd => left.bitwise_and(right),
+        Operation::BitOr => left.bitwise_or(right),
+        Operation::BitXor => left.bitwise_xor(right),
+        Operation::ShiftLeft => left.shift_left(right),
+        Operation::ShiftRight => left.shift_right(right),
+        operation => unreachable!("{:?} is not a binary arithmetic operation", operation),
+    }
+    .ok_or_else(|| match (operation, left, right) {
+        (Operation::FloorDivide, Value::Integer(n), Value::Integer(0)) => {
+            BinaryOperatorError::IntegerDivideByZero(n)
+        }
+        (Operation::Modulo, Value::Integer(n), Value::Integer(0)) => {
+            BinaryOperatorError::IntegerModuloByZero(n)
+        }
+        _ => error(),
+    })
+}
+
+fn unary_arithmetic<'gc>(
+    string_coercion: bool,
+    operation: Operation,
+    value: Value<'gc>,
+) -> Result<Value<'gc>, BinaryOperatorError> {
+    if !string_coercion && is_string(value) {
+        return Err(BinaryOperatorError::unary(operation, value));
+    }
+    match operation {
+        Operation::UnaryNegate => value.negate(),
+        Operation::BitNot => value.bitwise_not(),
+        operation => unreachable!("{:?} is not a unary arithmetic operation", operation),
+    }
+    .ok_or_else(|| BinaryOperatorError::unary(operation, value))
+}
+
+fn is_string(value: Value) -> bool {
+    matches!(value, Value::String(_))
+}
+
+// Checks the initial value, limit and step of a numeric for loop before it starts, in the same
+// order as PUC-Rio Lua.
+fn check_for_loop<'gc>(
+    string_coercion: bool,
+    initial: Value<'gc>,
+    limit: Value<'gc>,
+    step: Value<'gc>,
+) -> Result<(), ForLoopError> {
+    // Strings only count as numbers when string coercion is enabled.
+    let to_number = |value: Value<'gc>| {
+        if string_coercion || !is_string(value) {
+            value.to_number()
+        } else {
+            None
+        }
+    };
+    if to_number(limit).is_none() {
+        return Err(ForLoopError::LimitNotNumber);
+    }
+    let step = to_number(step).ok_or(ForLoopError::StepNotNumber)?;
+    if to_number(initial).is_none() {
+        return Err(ForLoopError::InitialNotNumber);
+    }
+    if step == 0.0 {
+        return Err(ForLoopError::ZeroStep);
+    }
+    Ok(())
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
@@ -0,0 +1,85 @@
+//! Opcode execution tracing, emitted as `tracing` events.
+//!
+//! Tracing is configured per thread with `Thread::set_opcode_trace`.  Every sampled instruction
+//! produces one event at `TRACE` level with the target `luster::vm` and the fields:
+//!
+//! * `opcode`: the instruction about to be run, in its `Debug` form
+//! * `pc`: the index of the instruction in its function
+//! * `function`: the address of the function prototype, stable for the life of the prototype
+//! * `line`: the source line of the instruction, or 0 if unknown
+//! * `registers`: the first few registers of the current frame, separated by commas
+//!
+//! Nothing is formatted unless a subscriber is actually interested in the event, but even then
+//! sampling a small fraction of instructions is recommended for anything long running.
+
+use gc_arena::{Collect, Gc};
+
+use crate::{Closure, OpCode, Value};
+
+/// Controls which instructions are traced and how much of each is recorded.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(require_static)]
+pub struct OpcodeTrace {
+    sample_every: u32,
+    registers: u8,
+    counter: u32,
+}
+
+impl OpcodeTrace {
+    /// Traces one out of every `sample_every` instructions executed, starting with the next one.
+    pub fn new(sample_every: u32) -> OpcodeTrace {
+        assert_ne!(sample_every, 0, "sample rate must not be zero");
+        OpcodeTrace {
+            sample_every,
+            registers: 4,
+            counter: 0,
+        }
+    }
+
+    /// Sets the number of registers included in each event, defaults to 4.
+    pub fn with_registers(mut self, registers: u8) -> OpcodeTrace {
+        self.registers = registers;
+        self
+    }
+
+    pub fn sample_every(&self) -> u32 {
+        self.sample_every
+    }
+
+    pub fn registers(&self) -> u8 {
+        self.registers
+    }
+
+    // Called by the VM after fetching every instruction
+    pub(crate) fn opcode<'gc>(
+        &mut self,
+        closure: Closure<'gc>,
+        pc: usize,
+        op: OpCode,
+        stack_frame: &[Value<'gc>],
+    ) {
+        let sampled = self.counter == 0;
+        self.counter += 1;
+        if self.counter == self.sample_every {
+            self.counter = 0;
+        }
+        if !sampled {
+            return;
+        }
+
+        let proto = closure.0.proto;
+        tracing::trace!(
+            target: "luster::vm",
+            opcode = ?op,
+            pc = pc as u64,
+            function = ?Gc::as_ptr(proto),
+            line = proto.opcode_line(pc).map(|l| l.0).unwrap_or(0),
+            registers = %stack_frame
+                .iter()
+                .take(self.registers as usize)
+                .map(|v| v.debug_fmt(0))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+}
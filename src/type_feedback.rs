@@ -0,0 +1,258 @@
+use std::fmt::Write;
+
+use rustc_hash::FxHashMap;
+
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
+
+use crate::{FunctionProto, OpCode, RegisterIndex, Value};
+
+/// Records the types of the values that the instructions of a set of tracked function prototypes
+/// operate on, for performance analysis and as input for specializing compiled code.
+///
+/// Set on a thread with `Thread::set_type_feedback`, threads created by `coroutine.create` share
+/// the recorder of the main thread.  Only register operands whose types affect how an instruction
+/// executes are observed: the operands of arithmetic, bitwise, comparison and unary instructions,
+/// the table and key of table accesses and the value of tests.  The observation is made just
+/// before the instruction runs, so it is counted even if the instruction then raises an error.
+#[derive(Debug, Collect, Clone, Copy)]
+#[collect(require_copy)]
+pub struct TypeFeedback<'gc>(GcCell<'gc, FeedbackState<'gc>>);
+
+#[derive(Debug, Collect)]
+#[collect(empty_drop)]
+struct FeedbackState<'gc> {
+    protos: Vec<Gc<'gc, FunctionProto<'gc>>>,
+    // Keyed by prototype address, the prototypes are kept alive by `protos`.
+    counts: FxHashMap<usize, Vec<[TypeCounts; 2]>>,
+}
+
+/// The number of times each type of value was observed in one operand of an instruction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct TypeCounts {
+    pub integer: u64,
+    pub float: u64,
+    pub string: u64,
+    pub table: u64,
+    pub other: u64,
+}
+
+impl TypeCounts {
+    pub fn total(&self) -> u64 {
+        self.integer + self.float + self.string + self.table + self.other
+    }
+
+    fn observe(&mut self, value: Value) {
+        match value {
+            Value::Integer(_) => self.integer += 1,
+            Value::Number(_) => self.float += 1,
+            Value::String(_) => self.string += 1,
+            Value::Table(_) => self.table += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// The observations for one instruction of a tracked prototype.  `operands` holds the counts for
+/// the left and right operand of binary instructions, or the table and key of table accesses.
+/// Instructions with a single observed operand only use the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionFeedback {
+    pub pc: usize,
+    pub opcode: OpCode,
+    pub operands: [TypeCounts; 2],
+}
+
+impl<'gc> TypeFeedback<'gc> {
+    pub fn new(mc: MutationContext<'gc, '_>) -> TypeFeedback<'gc> {
+        TypeFeedback(GcCell::allocate(
+            mc,
+            FeedbackState {
+                protos: Vec::new(),
+                counts: FxHashMap::default(),
+            },
+        ))
+    }
+
+    /// Starts recording types for the given prototype and every prototype nested inside it.
+    pub fn track(&self, mc: MutationContext<'gc, '_>, proto: Gc<'gc, FunctionProto<'gc>>) {
+        let mut state = self.0.write(mc);
+        let mut stack = vec![proto];
+        while let Some(proto) = stack.pop() {
+            let key = Gc::as_ptr(proto) as usize;
+            if state.counts.contains_key(&key) {
+                continue;
+            }
+            state
+                .counts
+                .insert(key, vec![Default::default(); proto.opcodes.len()]);
+            state.protos.push(proto);
+            stack.extend(proto.prototypes.iter().copied());
+        }
+    }
+
+    /// Returns the tracked prototypes, in the order that `dump` numbers them.
+    pub fn protos(&self) -> Vec<Gc<'gc, FunctionProto<'gc>>> {
+        self.0.read().protos.clone()
+    }
+
+    /// Returns the observations for every instruction of a tracked prototype that has observed
+    /// any values, in order, or None if the prototype is not tracked.
+    pub fn instructions(
+        &self,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+    ) -> Option<Vec<InstructionFeedback>> {
+        let state = self.0.read();
+        let counts = state.counts.get(&(Gc::as_ptr(proto) as usize))?;
+        Some(
+            counts
+                .iter()
+                .enumerate()
+                .filter(|(_, operands)| operands.iter().any(|c| c.total() > 0))
+                .map(|(pc, &operands)| InstructionFeedback {
+                    pc,
+                    opcode: proto.opcodes[pc],
+                    operands,
+                })
+                .collect(),
+        )
+    }
+
+    /// Formats every observation as tab separated text, with a header line followed by one line
+    /// per observed operand:
+    ///
+    /// `function pc line opcode operand integer float string table other`
+    ///
+    /// `function` is the index of the prototype in `protos`, `line` is empty if the instruction
+    /// has no line information, `opcode` is the name of the instruction and `operand` is 0 or 1.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "function\tpc\tline\topcode\toperand\tinteger\tfloat\tstring\ttable\tother"
+        )
+        .unwrap();
+        for (index, &proto) in self.0.read().protos.iter().enumerate() {
+            for feedback in self.instructions(proto).unwrap() {
+                let line = proto
+                    .opcode_line(feedback.pc)
+                    .map(|line| line.to_string())
+                    .unwrap_or_default();
+                let opcode = format!("{:?}", feedback.opcode);
+                let name = opcode.split(' ').next().unwrap();
+                for (operand, counts) in feedback.operands.iter().enumerate() {
+                    if counts.total() == 0 {
+                        continue;
+                    }
+                    writeln!(
+                        out,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        index,
+                        feedback.pc,
+                        line,
+                        name,
+                        operand,
+                        counts.integer,
+                        counts.float,
+                        counts.string,
+                        counts.table,
+                        counts.other
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    // Called by the VM before executing the instruction at `pc`, with the registers of the
+    // current frame.
+    pub(crate) fn record(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        proto: Gc<'gc, FunctionProto<'gc>>,
+        pc: usize,
+        registers: &[Value<'gc>],
+    ) {
+        let observed = observed_registers(proto.opcodes[pc]);
+        if observed == [None, None] {
+            return;
+        }
+        let key = Gc::as_ptr(proto) as usize;
+        if !self.0.read().counts.contains_key(&key) {
+            return;
+        }
+        let mut state = self.0.write(mc);
+        let counts = &mut state.counts.get_mut(&key).unwrap()[pc];
+        for (counts, register) in counts.iter_mut().zip(&observed) {
+            if let Some(register) = register {
+                counts.observe(registers[register.0 as usize]);
+            }
+        }
+    }
+}
+
+// The registers whose types are recorded for an instruction.
+fn observed_registers(op: OpCode) -> [Option<RegisterIndex>; 2] {
+    match op {
+        OpCode::AddRR { left, right, .. }
+        | OpCode::SubRR { left, right, .. }
+        | OpCode::MulRR { left, right, .. }
+        | OpCode::DivRR { left, right, .. }
+        | OpCode::IDivRR { left, right, .. }
+        | OpCode::ModRR { left, right, .. }
+        | OpCode::PowRR { left, right, .. }
+        | OpCode::BitAndRR { left, right, .. }
+        | OpCode::BitOrRR { left, right, .. }
+        | OpCode::BitXorRR { left, right, .. }
+        | OpCode::ShiftLeftRR { left, right, .. }
+        | OpCode::ShiftRightRR { left, right, .. }
+        | OpCode::EqRR { left, right, .. }
+        | OpCode::LessRR { left, right, .. }
+        | OpCode::LessEqRR { left, right, .. } => [Some(left), Some(right)],
+        OpCode::AddRC { left, .. }
+        | OpCode::SubRC { left, .. }
+        | OpCode::MulRC { left, .. }
+        | OpCode::DivRC { left, .. }
+        | OpCode::IDivRC { left, .. }
+        | OpCode::ModRC { left, .. }
+        | OpCode::PowRC { left, .. }
+        | OpCode::BitAndRC { left, .. }
+        | OpCode::BitOrRC { left, .. }
+        | OpCode::BitXorRC { left, .. }
+        | OpCode::ShiftLeftRC { left, .. }
+        | OpCode::ShiftRightRC { left, .. }
+        | OpCode::EqRC { left, .. }
+        | OpCode::LessRC { left, .. }
+        | OpCode::LessEqRC { left, .. } => [Some(left), None],
+        OpCode::AddCR { right, .. }
+        | OpCode::SubCR { right, .. }
+        | OpCode::MulCR { right, .. }
+        | OpCode::DivCR { right, .. }
+        | OpCode::IDivCR { right, .. }
+        | OpCode::ModCR { right, .. }
+        | OpCode::PowCR { right, .. }
+        | OpCode::BitAndCR { right, .. }
+        | OpCode::BitOrCR { right, .. }
+        | OpCode::BitXorCR { right, .. }
+        | OpCode::ShiftLeftCR { right, .. }
+        | OpCode::ShiftRightCR { right, .. }
+        | OpCode::EqCR { right, .. }
+        | OpCode::LessCR { right, .. }
+        | OpCode::LessEqCR { right, .. } => [None, Some(right)],
+        OpCode::Not { source, .. }
+        | OpCode::Minus { source, .. }
+        | OpCode::BitNot { source, .. }
+        | OpCode::Length { source, .. } => [Some(source), None],
+        OpCode::GetTableR { table, key, .. }
+        | OpCode::SetTableRR { table, key, .. }
+        | OpCode::SetTableRC { table, key, .. }
+        | OpCode::SelfR { table, key, .. } => [Some(table), Some(key)],
+        OpCode::GetTableC { table, .. }
+        | OpCode::SetTableCR { table, .. }
+        | OpCode::SetTableCC { table, .. }
+        | OpCode::SelfC { table, .. } => [Some(table), None],
+        OpCode::Test { value, .. } | OpCode::TestSet { value, .. } => [Some(value), None],
+        _ => [None, None],
+    }
+}
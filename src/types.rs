@@ -1,4 +1,7 @@
+use std::convert::TryFrom;
 use std::fmt::{self, Debug};
+use std::string::String as StdString;
+use std::sync::Arc;
 
 use gc_arena::Collect;
 
@@ -27,6 +30,123 @@ pub struct UpValueIndex(pub u8);
 #[collect(require_static)]
 pub struct PrototypeIndex(pub u8);
 
+// Checked constructors for the index newtypes, for tools that build opcodes from indexes computed
+// as `usize`.
+macro_rules! index_constructors {
+    ($($name:ident($inner:ty)),* $(,)?) => {
+        $(
+            impl $name {
+                /// Returns the index, or `None` if it does not fit in this type.
+                pub fn new(index: usize) -> Option<$name> {
+                    <$inner>::try_from(index).ok().map($name)
+                }
+
+                pub fn to_usize(self) -> usize {
+                    self.0 as usize
+                }
+            }
+        )*
+    };
+}
+
+index_constructors!(
+    RegisterIndex(u8),
+    ConstantIndex8(u8),
+    ConstantIndex16(u16),
+    UpValueIndex(u8),
+    PrototypeIndex(u8),
+);
+
+/// A line number in Lua source code, starting from 1.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Collect)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[collect(require_static)]
+pub struct LineNumber(pub u64);
+
+impl fmt::Display for LineNumber {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+/// The name of a chunk of Lua source, which says where the chunk came from in error messages and
+/// tracebacks, as with the `chunkname` argument of `load`.
+///
+/// By convention, a name starting with `@` is a file name and a name starting with `=` is shown
+/// as it is, such as `=stdin`.  Any other name is the source of the chunk itself, which is how
+/// chunks loaded from strings are named, and is shown as `[string "..."]` with only the start of
+/// its first line.  The `Display` implementation shows the name this way, shortened to fit in 60
+/// bytes like `luaO_chunkid` does.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Collect)]
+#[collect(require_static)]
+pub struct ChunkName(Arc<str>);
+
+impl ChunkName {
+    pub fn new(name: &str) -> ChunkName {
+        ChunkName(name.into())
+    }
+
+    /// The name of a chunk read from the file at `path`.
+    pub fn file(path: &str) -> ChunkName {
+        ChunkName(format!("@{}", path).into())
+    }
+
+    /// The name of a chunk loaded from a string, which is the source itself.  Only as much of the
+    /// source is kept as is ever shown.
+    pub fn from_source(source: &[u8]) -> ChunkName {
+        let end = source
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(source.len(), |i| i + 1)
+            .min(CHUNK_ID_SIZE);
+        ChunkName(StdString::from_utf8_lossy(&source[..end]).into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// The most bytes the displayed form of a `ChunkName` may take, `LUA_IDSIZE` less its nul byte.
+const CHUNK_ID_SIZE: usize = 59;
+
+impl fmt::Display for ChunkName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Shortens `s` to at most `len` bytes, on a character boundary.
+        fn prefix(s: &str, len: usize) -> &str {
+            let mut end = len.min(s.len());
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            &s[..end]
+        }
+
+        let name = &*self.0;
+        if let Some(name) = name.strip_prefix('=') {
+            fmt.write_str(prefix(name, CHUNK_ID_SIZE))
+        } else if let Some(name) = name.strip_prefix('@') {
+            if name.len() <= CHUNK_ID_SIZE {
+                fmt.write_str(name)
+            } else {
+                // Keep the end of long file names, which is the most specific part.
+                let mut start = name.len() - (CHUNK_ID_SIZE - 3);
+                while !name.is_char_boundary(start) {
+                    start += 1;
+                }
+                write!(fmt, "...{}", &name[start..])
+            }
+        } else {
+            let line = name.split('\n').next().unwrap_or("");
+            let max_len = CHUNK_ID_SIZE - "[string \"...\"]".len();
+            if line.len() == name.len() && name.len() < max_len {
+                write!(fmt, "[string \"{}\"]", name)
+            } else {
+                write!(fmt, "[string \"{}...\"]", prefix(line, max_len))
+            }
+        }
+    }
+}
+
 /// A one byte Option value that can either be Some(0-254) or None
 #[derive(Copy, Clone, Eq, PartialEq, Collect)]
 #[collect(require_static)]
@@ -107,6 +227,12 @@ impl VarCount {
         Opt254::try_some(constant).map(VarCount)
     }
 
+    /// Returns a constant count for `Some`, or the variable count for `None`.  Fails if the
+    /// constant is 255, which is reserved for the variable count.
+    pub fn try_new(count: Option<u8>) -> Option<VarCount> {
+        Opt254::try_new(count).map(VarCount)
+    }
+
     pub fn is_variable(self) -> bool {
         self.0.is_none()
     }
@@ -0,0 +1,259 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Continuation, RuntimeError, String, Table, Value};
+
+/// The type of a single argument in a `Signature`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Nil,
+    Boolean,
+    Number,
+    Integer,
+    String,
+    Table,
+    Function,
+    Thread,
+    Any,
+}
+
+impl ArgType {
+    fn from_name(name: &str) -> Option<ArgType> {
+        Some(match name {
+            "nil" => ArgType::Nil,
+            "boolean" => ArgType::Boolean,
+            "number" => ArgType::Number,
+            "integer" => ArgType::Integer,
+            "string" => ArgType::String,
+            "table" => ArgType::Table,
+            "function" => ArgType::Function,
+            "thread" => ArgType::Thread,
+            "any" => ArgType::Any,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ArgType::Nil => "nil",
+            ArgType::Boolean => "boolean",
+            ArgType::Number => "number",
+            ArgType::Integer => "integer",
+            ArgType::String => "string",
+            ArgType::Table => "table",
+            ArgType::Function => "function",
+            ArgType::Thread => "thread",
+            ArgType::Any => "value",
+        }
+    }
+
+    fn matches(self, value: Value) -> bool {
+        match (self, value) {
+            (ArgType::Any, _) => true,
+            (ArgType::Nil, Value::Nil) => true,
+            (ArgType::Boolean, Value::Boolean(_)) => true,
+            (ArgType::Number, Value::Integer(_)) | (ArgType::Number, Value::Number(_)) => true,
+            (ArgType::Integer, Value::Integer(_)) => true,
+            (ArgType::Integer, Value::Number(n)) => n.fract() == 0.0,
+            (ArgType::String, Value::String(_)) => true,
+            (ArgType::Table, Value::Table(_)) => true,
+            (ArgType::Function, Value::Function(_)) => true,
+            (ArgType::Thread, Value::Thread(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A single parameter of a `Signature`, accepting any of `types`, or nil if it is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub types: Vec<ArgType>,
+    pub optional: bool,
+}
+
+/// The expected arguments of a function, parsed from a comma separated list of parameter types
+/// such as `"string, number|boolean, table?, ..."`.
+///
+/// Each parameter is one or more of `nil`, `boolean`, `number`, `integer`, `string`, `table`,
+/// `function`, `thread` or `any` separated by `|`, with a trailing `?` if it may be nil or
+/// missing.  A final `...` accepts any number of extra arguments, which are otherwise an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Param>,
+    pub has_varargs: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    UnknownType(StdString),
+    MisplacedVarargs,
+    NotAFunction(StdString),
+    Unlisted(StdString),
+}
+
+impl StdError for SchemaError {}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::UnknownType(name) => write!(fmt, "unknown argument type {:?}", name),
+            SchemaError::MisplacedVarargs => write!(fmt, "'...' must be the last parameter"),
+            SchemaError::NotAFunction(name) => write!(fmt, "{:?} is not a function", name),
+            SchemaError::Unlisted(name) => {
+                write!(fmt, "function {:?} has no signature in the schema", name)
+            }
+        }
+    }
+}
+
+impl Signature {
+    pub fn parse(signature: &str) -> Result<Signature, SchemaError> {
+        let mut params = Vec::new();
+        let mut has_varargs = false;
+
+        let signature = signature.trim();
+        if !signature.is_empty() {
+            for param in signature.split(',').map(str::trim) {
+                if has_varargs {
+                    return Err(SchemaError::MisplacedVarargs);
+                }
+                if param == "..." {
+                    has_varargs = true;
+                    continue;
+                }
+
+                let (types, optional) = match param.strip_suffix('?') {
+                    Some(types) => (types, true),
+                    None => (param, false),
+                };
+                let types = types
+                    .split('|')
+                    .map(str::trim)
+                    .map(|name| {
+                        ArgType::from_name(name)
+                            .ok_or_else(|| SchemaError::UnknownType(name.to_owned()))
+                    })
+                    .collect::<Result<_, _>>()?;
+                params.push(Param { types, optional });
+            }
+        }
+
+        Ok(Signature {
+            params,
+            has_varargs,
+        })
+    }
+
+    /// Checks `args` against this signature, returning an error message in the same form as the
+    /// standard library's ("bad argument #1 to 'name' (string expected, got nil)") if they do not
+    /// match.
+    pub fn check(&self, name: &str, args: &[Value]) -> Result<(), StdString> {
+        for (i, param) in self.params.iter().enumerate() {
+            // `any` accepts nil, but like every other type other than `nil` it still requires the
+            // argument to be present.
+            let arg = args.get(i).cloned();
+            let matches = match arg {
+                Some(arg) => param.types.iter().any(|&t| t.matches(arg)),
+                None => param.types.contains(&ArgType::Nil),
+            };
+            if matches || (param.optional && arg.unwrap_or(Value::Nil) == Value::Nil) {
+                continue;
+            }
+
+            let expected = param
+                .types
+                .iter()
+                .map(|t| t.name())
+                .collect::<Vec<_>>()
+                .join(" or ");
+            let found = arg.map(|arg| arg.type_name()).unwrap_or("no value");
+            return Err(format!(
+                "bad argument #{} to '{}' ({} expected, got {})",
+                i + 1,
+                name,
+                expected,
+                found
+            ));
+        }
+
+        if !self.has_varargs && args.len() > self.params.len() {
+            return Err(format!(
+                "bad argument #{} to '{}' (no value expected)",
+                self.params.len() + 1,
+                name
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces every function in the host API table `api` with a wrapper that checks its arguments
+/// against the signature given for it in `schema` before calling it, so that the functions
+/// themselves can assume well typed arguments.
+///
+/// `schema` pairs each field name of `api` with its signature (see `Signature`).  Every function
+/// in `api` must be listed, and every listed name must be a function.  Nothing in `api` is
+/// changed if the schema is invalid.
+pub fn validate_api<'gc>(
+    mc: MutationContext<'gc, '_>,
+    api: Table<'gc>,
+    schema: &[(&str, &str)],
+) -> Result<(), SchemaError> {
+    let mut wrapped = Vec::new();
+    for &(name, signature) in schema {
+        let signature = Signature::parse(signature)?;
+        match api.get(String::new(mc, name.as_bytes())) {
+            Value::Function(function) => wrapped.push((name, function, signature)),
+            _ => return Err(SchemaError::NotAFunction(name.to_owned())),
+        }
+    }
+
+    for (key, value) in api.0.read().iter() {
+        if let (Value::String(key), Value::Function(_)) = (key, value) {
+            if !wrapped
+                .iter()
+                .any(|&(name, _, _)| name.as_bytes() == key.as_bytes())
+            {
+                return Err(SchemaError::Unlisted(
+                    StdString::from_utf8_lossy(key.as_bytes()).into_owned(),
+                ));
+            }
+        }
+    }
+
+    for (name, function, signature) in wrapped {
+        let check = Rc::new((name.to_owned(), signature));
+        let wrapper = Callback::new_sequence_with(mc, function, move |&function, args| {
+            let checked = check.1.check(&check.0, &args);
+            Ok(sequence::from_fn_with(
+                (function, args),
+                move |mc, (function, args)| {
+                    if let Err(message) = checked {
+                        return Err(RuntimeError(Value::String(String::new(
+                            mc,
+                            message.as_bytes(),
+                        )))
+                        .into());
+                    }
+                    Ok(CallbackResult::TailCall {
+                        function,
+                        args,
+                        continuation: Continuation::new_immediate(|res| {
+                            res.map(CallbackResult::Return)
+                        }),
+                    })
+                },
+            ))
+        });
+        api.set(mc, String::new(mc, name.as_bytes()), wrapper)
+            .expect("function names are valid keys");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,541 @@
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::string::String as StdString;
+use std::{f64, i64, io};
+
+use gc_arena::{Collect, Gc, GcCell};
+use num_traits::cast;
+
+use crate::{
+    lexer::{read_float, read_hex_float, read_hex_integer, read_integer},
+    Callback, Closure, FunctionInfo, String, Table, Thread,
+};
+
+pub mod walk;
+
+use self::walk::VisitedSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Collect)]
+#[collect(require_copy)]
+pub enum Function<'gc> {
+    Closure(Closure<'gc>),
+    Callback(Callback<'gc>),
+}
+
+impl<'gc> Function<'gc> {
+    /// Returns the signature and debug information of a Lua function, or `None` for a callback,
+    /// which has none.
+    pub fn info(self) -> Option<FunctionInfo<'gc>> {
+        match self {
+            Function::Closure(closure) => Some(closure.0.proto.info()),
+            Function::Callback(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(require_copy)]
+pub enum Value<'gc> {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String<'gc>),
+    Table(Table<'gc>),
+    Function(Function<'gc>),
+    Thread(Thread<'gc>),
+}
+
+impl<'gc> PartialEq for Value<'gc> {
+    fn eq(&self, other: &Value<'gc>) -> bool {
+        match (*self, *other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Nil, _) => false,
+
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Boolean(_), _) => false,
+
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(a), Value::Number(b)) => float_to_integer(b) == Some(a),
+            (Value::Integer(_), _) => false,
+
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Number(a), Value::Integer(b)) => float_to_integer(a) == Some(b),
+            (Value::Number(_), _) => false,
+
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::String(_), _) => false,
+
+            (Value::Table(a), Value::Table(b)) => a == b,
+            (Value::Table(_), _) => false,
+
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Function(_), _) => false,
+
+            (Value::Thread(a), Value::Thread(b)) => a == b,
+            (Value::Thread(_), _) => false,
+        }
+    }
+}
+
+impl<'gc> Value<'gc> {
+    pub fn type_name(self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::Thread(_) => "thread",
+        }
+    }
+
+    /// Lua `nil` and `false` are false, anything else is true.
+    pub fn to_bool(self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Boolean(false) => false,
+            _ => true,
+        }
+    }
+
+    /// Interprets Numbers, Integers, and Strings as a Number, if possible.
+    pub fn to_number(self) -> Option<f64> {
+        match self {
+            Value::Integer(a) => Some(a as f64),
+            Value::Number(a) => Some(a),
+            Value::String(a) => match read_numeral(&a)? {
+                Value::Integer(i) => Some(i as f64),
+                n => n.to_number(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Interprets Numbers, Integers, and Strings as an Integer, if possible.
+    pub fn to_integer(self) -> Option<i64> {
+        match self {
+            Value::Integer(a) => Some(a),
+            Value::Number(a) => float_to_integer(a),
+            Value::String(a) => read_numeral(&a)?.to_integer(),
+            _ => None,
+        }
+    }
+
+    pub fn not(self) -> Value<'gc> {
+        Value::Boolean(!self.to_bool())
+    }
+
+    // Mathematical operators
+
+    pub fn add(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            Some(Value::Integer(a.wrapping_add(b)))
+        } else {
+            Some(Value::Number(self.to_number()? + other.to_number()?))
+        }
+    }
+
+    pub fn subtract(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            Some(Value::Integer(a.wrapping_sub(b)))
+        } else {
+            Some(Value::Number(self.to_number()? - other.to_number()?))
+        }
+    }
+
+    pub fn multiply(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            Some(Value::Integer(a.wrapping_mul(b)))
+        } else {
+            Some(Value::Number(self.to_number()? * other.to_number()?))
+        }
+    }
+
+    /// This operation always returns a Number, even when called with Integer arguments.
+    pub fn float_divide(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Number(self.to_number()? / other.to_number()?))
+    }
+
+    /// This operation returns an Integer only if both arguments are Integers.  Rounding is towards
+    /// negative infinity.
+    pub fn floor_divide(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            if b == 0 {
+                None
+            } else {
+                let quotient = a.wrapping_div(b);
+                if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) {
+                    Some(Value::Integer(quotient - 1))
+                } else {
+                    Some(Value::Integer(quotient))
+                }
+            }
+        } else {
+            Some(Value::Number(
+                (self.to_number()? / other.to_number()?).floor(),
+            ))
+        }
+    }
+
+    /// Computes the Lua modulus (`%`) operator.  This is unlike Rust's `%` operator which computes
+    /// the remainder.
+    pub fn modulo(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            if b == 0 {
+                None
+            } else {
+                // The remainder and `b` have different signs only when the remainder is non-zero
+                // and smaller in magnitude than `b`, so this cannot overflow.
+                let remainder = a.wrapping_rem(b);
+                if remainder != 0 && (remainder < 0) != (b < 0) {
+                    Some(Value::Integer(remainder + b))
+                } else {
+                    Some(Value::Integer(remainder))
+                }
+            }
+        } else {
+            let (a, b) = (self.to_number()?, other.to_number()?);
+            Some(Value::Number(((a % b) + b) % b))
+        }
+    }
+
+    /// This operation always returns a Number, even when called with Integer arguments.
+    pub fn exponentiate(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Number(self.to_number()?.powf(other.to_number()?)))
+    }
+
+    pub fn negate(self) -> Option<Value<'gc>> {
+        match self {
+            Value::Integer(a) => Some(Value::Integer(a.wrapping_neg())),
+            Value::Number(a) => Some(Value::Number(-a)),
+            _ => None,
+        }
+    }
+
+    // Bitwise operators
+
+    pub fn bitwise_not(self) -> Option<Value<'gc>> {
+        Some(Value::Integer(!self.to_integer()?))
+    }
+
+    pub fn bitwise_and(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Integer(self.to_integer()? & other.to_integer()?))
+    }
+
+    pub fn bitwise_or(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Integer(self.to_integer()? | other.to_integer()?))
+    }
+
+    pub fn bitwise_xor(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Integer(self.to_integer()? ^ other.to_integer()?))
+    }
+
+    /// Shifts are logical, a negative shift shifts the other way and shifting by 64 bits or more
+    /// results in zero.
+    pub fn shift_left(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Integer(shift_left(
+            self.to_integer()?,
+            other.to_integer()?,
+        )))
+    }
+
+    /// See `Value::shift_left`.
+    pub fn shift_right(self, other: Value<'gc>) -> Option<Value<'gc>> {
+        Some(Value::Integer(shift_left(
+            self.to_integer()?,
+            other.to_integer()?.wrapping_neg(),
+        )))
+    }
+
+    // Comparison operators
+
+    pub fn less_than(self, other: Value<'gc>) -> Option<bool> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            Some(a < b)
+        } else if let (Value::String(a), Value::String(b)) = (self, other) {
+            Some(a.as_bytes() < b.as_bytes())
+        } else {
+            Some(self.to_number()? < other.to_number()?)
+        }
+    }
+
+    pub fn less_equal(self, other: Value<'gc>) -> Option<bool> {
+        if let (Value::Integer(a), Value::Integer(b)) = (self, other) {
+            Some(a <= b)
+        } else if let (Value::String(a), Value::String(b)) = (self, other) {
+            Some(a.as_bytes() <= b.as_bytes())
+        } else {
+            Some(self.to_number()? <= other.to_number()?)
+        }
+    }
+
+    pub fn display<W: io::Write>(self, mut w: W) -> Result<(), io::Error> {
+        match self {
+            Value::Nil => write!(w, "nil"),
+            Value::Boolean(b) => write!(w, "{}", b),
+            Value::Integer(i) => write!(w, "{}", i),
+            Value::Number(f) => write!(w, "{}", f),
+            Value::String(s) => w.write_all(s.as_bytes()),
+            Value::Table(t) => write!(w, "<table {:?}>", t.0.as_ptr()),
+            Value::Function(Function::Closure(c)) => write!(w, "<function {:?}>", Gc::as_ptr(c.0)),
+            Value::Function(Function::Callback(c)) => write!(w, "<function {:?}>", Gc::as_ptr(c.0)),
+            Value::Thread(t) => write!(w, "<thread {:?}>", GcCell::as_ptr(t.0)),
+        }
+    }
+
+    /// Formats this value for debugging.  Unlike `display`, tables are printed along with their
+    /// contents (keys in sorted order) up to `depth_limit` levels deep, strings are quoted and
+    /// escaped, and a table which contains itself is printed as `<cycle>` rather than recursing
+    /// forever.
+    pub fn debug_fmt(self, depth_limit: usize) -> StdString {
+        fn fmt_value<'gc>(
+            out: &mut StdString,
+            value: Value<'gc>,
+            depth: usize,
+            parents: &mut VisitedSet<Table<'gc>>,
+        ) {
+            match value {
+                Value::String(s) => fmt_string(out, s.as_bytes()),
+                Value::Table(t) => {
+                    if parents.contains(&t) {
+                        out.push_str("<cycle>");
+                        return;
+                    }
+                    let mut entries = t.0.read().iter().collect::<Vec<_>>();
+                    if entries.is_empty() {
+                        out.push_str("{}");
+                        return;
+                    } else if depth == 0 {
+                        out.push_str("{...}");
+                        return;
+                    }
+                    entries.sort_by(|(a, _), (b, _)| debug_key_order(*a, *b));
+
+                    parents.insert(t);
+                    out.push('{');
+                    for (i, (key, value)) in entries.into_iter().enumerate() {
+                        if i != 0 {
+                            out.push_str(", ");
+                        }
+                        match key {
+                            Value::String(s) if is_identifier(s.as_bytes()) => {
+                                out.push_str(&StdString::from_utf8_lossy(s.as_bytes()));
+                            }
+                            key => {
+                                out.push('[');
+                                fmt_value(out, key, depth - 1, parents);
+                                out.push(']');
+                            }
+                        }
+                        out.push_str(" = ");
+                        fmt_value(out, value, depth - 1, parents);
+                    }
+                    out.push('}');
+                    parents.remove(&t);
+                }
+                value => {
+                    let mut buf = Vec::new();
+                    value.display(&mut buf).unwrap();
+                    out.push_str(&StdString::from_utf8_lossy(&buf));
+                }
+            }
+        }
+
+        fn fmt_string(out: &mut StdString, s: &[u8]) {
+            out.push('"');
+            for &b in s {
+                match b {
+                    b'"' => out.push_str("\\\""),
+                    b'\\' => out.push_str("\\\\"),
+                    b'\n' => out.push_str("\\n"),
+                    b'\r' => out.push_str("\\r"),
+                    b'\t' => out.push_str("\\t"),
+                    b' '..=b'~' => out.push(b as char),
+                    // Always three digits, so that a digit following the escape is not read as
+                    // part of it.
+                    b => write!(out, "\\{:03}", b).unwrap(),
+                }
+            }
+            out.push('"');
+        }
+
+        fn is_identifier(s: &[u8]) -> bool {
+            match s.first() {
+                Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+                    s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+                }
+                _ => false,
+            }
+        }
+
+        let mut out = StdString::new();
+        fmt_value(&mut out, self, depth_limit, &mut VisitedSet::new());
+        out
+    }
+}
+
+/// Compares two values structurally: tables are equal if they have the same set of keys (compared
+/// with regular Lua equality) and every corresponding value is itself deeply equal.  Tables that
+/// refer back to themselves are handled by assuming that any pair of tables already being compared
+/// is equal.
+pub fn values_deep_equal<'gc>(a: Value<'gc>, b: Value<'gc>) -> bool {
+    fn deep_equal<'gc>(
+        a: Value<'gc>,
+        b: Value<'gc>,
+        comparing: &mut VisitedSet<(Table<'gc>, Table<'gc>)>,
+    ) -> bool {
+        match (a, b) {
+            (Value::Table(a), Value::Table(b)) => {
+                if a == b || !comparing.insert((a, b)) {
+                    return true;
+                }
+
+                let a_entries = a.0.read().iter().collect::<Vec<_>>();
+                if a_entries.len() != b.0.read().iter().count() {
+                    return false;
+                }
+                a_entries.into_iter().all(|(key, a_value)| {
+                    let b_value = b.get(key);
+                    b_value != Value::Nil && deep_equal(a_value, b_value, comparing)
+                })
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    deep_equal(a, b, &mut VisitedSet::new())
+}
+
+/// Asserts that two `Value`s are equal according to `values_deep_equal`, printing both with
+/// `Value::debug_fmt` if they are not.
+#[macro_export]
+macro_rules! assert_deep_eq {
+    ($left:expr, $right:expr) => {{
+        let (left, right): ($crate::Value, $crate::Value) = ($left, $right);
+        if !$crate::values_deep_equal(left, right) {
+            panic!(
+                "assertion failed: values are not deeply equal\n  left: {}\n right: {}",
+                left.debug_fmt(8),
+                right.debug_fmt(8),
+            );
+        }
+    }};
+}
+
+// Converts a string to an Integer or a Number by the same rules as numerals in source code, so hex
+// integers wrap around and decimal integers too large for an i64 become Numbers.
+fn read_numeral<'gc>(s: &[u8]) -> Option<Value<'gc>> {
+    if let Some(i) = read_hex_integer(s).or_else(|| read_integer(s)) {
+        Some(Value::Integer(i))
+    } else {
+        Some(Value::Number(read_hex_float(s).or_else(|| read_float(s))?))
+    }
+}
+
+// The integer equal to the given float, if there is one.
+fn float_to_integer(f: f64) -> Option<i64> {
+    let i = cast::<_, i64>(f)?;
+    if i as f64 == f {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+fn shift_left(a: i64, shift: i64) -> i64 {
+    if shift <= -64 || shift >= 64 {
+        0
+    } else if shift >= 0 {
+        ((a as u64) << shift) as i64
+    } else {
+        ((a as u64) >> -shift) as i64
+    }
+}
+
+// A total order over table keys for `Value::debug_fmt`: booleans, then numbers, then strings, then
+// everything else by address.
+fn debug_key_order<'gc>(a: Value<'gc>, b: Value<'gc>) -> Ordering {
+    fn rank<'gc>(v: Value<'gc>) -> u8 {
+        match v {
+            Value::Nil => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Table(_) => 4,
+            Value::Function(_) => 5,
+            Value::Thread(_) => 6,
+        }
+    }
+
+    fn address<'gc>(v: Value<'gc>) -> usize {
+        match v {
+            Value::Table(t) => t.0.as_ptr() as usize,
+            Value::Function(Function::Closure(c)) => Gc::as_ptr(c.0) as usize,
+            Value::Function(Function::Callback(c)) => Gc::as_ptr(c.0) as *const u8 as usize,
+            Value::Thread(t) => GcCell::as_ptr(t.0) as usize,
+            _ => 0,
+        }
+    }
+
+    match (a, b) {
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(&b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(&b),
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (a, b) if rank(a) == 2 && rank(b) == 2 => a
+            .to_number()
+            .unwrap()
+            .partial_cmp(&b.to_number().unwrap())
+            .unwrap_or(Ordering::Equal),
+        (a, b) => rank(a).cmp(&rank(b)).then(address(a).cmp(&address(b))),
+    }
+}
+
+impl<'gc> From<bool> for Value<'gc> {
+    fn from(v: bool) -> Value<'gc> {
+        Value::Boolean(v)
+    }
+}
+
+impl<'gc> From<i64> for Value<'gc> {
+    fn from(v: i64) -> Value<'gc> {
+        Value::Integer(v)
+    }
+}
+
+impl<'gc> From<f64> for Value<'gc> {
+    fn from(v: f64) -> Value<'gc> {
+        Value::Number(v)
+    }
+}
+
+impl<'gc> From<String<'gc>> for Value<'gc> {
+    fn from(v: String<'gc>) -> Value<'gc> {
+        Value::String(v)
+    }
+}
+
+impl<'gc> From<Table<'gc>> for Value<'gc> {
+    fn from(v: Table<'gc>) -> Value<'gc> {
+        Value::Table(v)
+    }
+}
+
+impl<'gc> From<Function<'gc>> for Value<'gc> {
+    fn from(v: Function<'gc>) -> Value<'gc> {
+        Value::Function(v)
+    }
+}
+
+impl<'gc> From<Closure<'gc>> for Value<'gc> {
+    fn from(v: Closure<'gc>) -> Value<'gc> {
+        Value::Function(Function::Closure(v))
+    }
+}
+
+impl<'gc> From<Callback<'gc>> for Value<'gc> {
+    fn from(v: Callback<'gc>) -> Value<'gc> {
+        Value::Function(Function::Callback(v))
+    }
+}
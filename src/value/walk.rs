@@ -0,0 +1,82 @@
+//! Utilities for code that recursively walks Lua values, such as `Value::debug_fmt`,
+//! `values_deep_equal` and `OwnedValue::from_value`.
+//!
+//! Tables can contain themselves, directly or through other tables, so every walk over table
+//! contents must keep track of the tables it has seen to terminate.  Walks that produce a tree
+//! (formatting, copying) track the tables they are currently inside of, by inserting a table into
+//! a `VisitedSet` before walking its contents and removing it afterwards, so a table that is merely
+//! reachable twice is walked twice but a cycle is detected.  Walks that only need to see every
+//! table once, like `walk`, never remove tables from the set.
+
+use std::hash::Hash;
+
+use rustc_hash::FxHashSet;
+
+use crate::{Table, Value};
+
+/// A set of tables (or of pairs of tables, for walks over two values at once) compared by
+/// identity.
+#[derive(Debug, Clone)]
+pub struct VisitedSet<T> {
+    set: FxHashSet<T>,
+}
+
+impl<T: Eq + Hash> Default for VisitedSet<T> {
+    fn default() -> VisitedSet<T> {
+        VisitedSet {
+            set: FxHashSet::default(),
+        }
+    }
+}
+
+impl<T: Eq + Hash> VisitedSet<T> {
+    pub fn new() -> VisitedSet<T> {
+        VisitedSet::default()
+    }
+
+    /// Adds an entry to the set, returning false if it was already present, which means the walk
+    /// has found a cycle (or a table it has already walked).
+    pub fn insert(&mut self, entry: T) -> bool {
+        self.set.insert(entry)
+    }
+
+    /// Removes an entry when a walk has finished with it.
+    pub fn remove(&mut self, entry: &T) -> bool {
+        self.set.remove(entry)
+    }
+
+    pub fn contains(&self, entry: &T) -> bool {
+        self.set.contains(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+}
+
+/// Calls `visit` with `value` and then every key and value reachable from it through tables, in
+/// depth first order.  Every table is visited (and walked) only once, no matter how many times it
+/// is referenced.
+pub fn walk<'gc>(value: Value<'gc>, mut visit: impl FnMut(Value<'gc>)) {
+    let mut visited = VisitedSet::<Table<'gc>>::new();
+    let mut stack = vec![value];
+    while let Some(value) = stack.pop() {
+        if let Value::Table(table) = value {
+            if !visited.insert(table) {
+                continue;
+            }
+            let mut entries = table.0.read().iter().collect::<Vec<_>>();
+            // Reversed so that the stack pops entries in iteration order.
+            entries.reverse();
+            for (key, value) in entries {
+                stack.push(value);
+                stack.push(key);
+            }
+        }
+        visit(value);
+    }
+}
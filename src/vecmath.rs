@@ -0,0 +1,557 @@
+//! Small fixed-size vector and matrix types for game hosts, with matching `vec2`, `vec3` and
+//! `mat4` Lua libraries.
+//!
+//! The VM has no userdata or metatables yet, so on the Lua side vectors are plain tables with `x`,
+//! `y` (and `z`) fields and matrices are 16-element arrays in column-major order.  Arithmetic is
+//! done through library functions such as `vec3.add(a, b)` rather than operators, with all of the
+//! math itself implemented by the Rust types in this module.
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Error, Root, String, Table, TypeError, Value};
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Vec2 {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Vec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// A 4x4 matrix stored in column-major order, so `m.0[col * 4 + row]` is the element at `row` and
+/// `col`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4(pub [f64; 16]);
+
+impl Vec2 {
+    pub fn new(x: f64, y: f64) -> Vec2 {
+        Vec2 { x, y }
+    }
+
+    pub fn dot(self, other: Vec2) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    /// Returns the vector scaled to length 1, or the zero vector unchanged.
+    pub fn normalize(self) -> Vec2 {
+        let length = self.length();
+        if length == 0.0 {
+            self
+        } else {
+            self * (1.0 / length)
+        }
+    }
+
+    pub fn lerp(self, other: Vec2, t: f64) -> Vec2 {
+        self + (other - self) * t
+    }
+}
+
+impl Vec3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    pub fn dot(self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    /// Returns the vector scaled to length 1, or the zero vector unchanged.
+    pub fn normalize(self) -> Vec3 {
+        let length = self.length();
+        if length == 0.0 {
+            self
+        } else {
+            self * (1.0 / length)
+        }
+    }
+
+    pub fn lerp(self, other: Vec3, t: f64) -> Vec3 {
+        self + (other - self) * t
+    }
+}
+
+macro_rules! impl_vector_ops {
+    ($ty:ident, $($field:ident),*) => {
+        impl Add for $ty {
+            type Output = $ty;
+
+            fn add(self, other: $ty) -> $ty {
+                $ty { $($field: self.$field + other.$field),* }
+            }
+        }
+
+        impl Sub for $ty {
+            type Output = $ty;
+
+            fn sub(self, other: $ty) -> $ty {
+                $ty { $($field: self.$field - other.$field),* }
+            }
+        }
+
+        impl Mul for $ty {
+            type Output = $ty;
+
+            fn mul(self, other: $ty) -> $ty {
+                $ty { $($field: self.$field * other.$field),* }
+            }
+        }
+
+        impl Mul<f64> for $ty {
+            type Output = $ty;
+
+            fn mul(self, scale: f64) -> $ty {
+                $ty { $($field: self.$field * scale),* }
+            }
+        }
+
+        impl Neg for $ty {
+            type Output = $ty;
+
+            fn neg(self) -> $ty {
+                $ty { $($field: -self.$field),* }
+            }
+        }
+    };
+}
+
+impl_vector_ops!(Vec2, x, y);
+impl_vector_ops!(Vec3, x, y, z);
+
+impl Default for Mat4 {
+    fn default() -> Mat4 {
+        Mat4::identity()
+    }
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4::scale(Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut m = Mat4::identity();
+        m.0[12] = offset.x;
+        m.0[13] = offset.y;
+        m.0[14] = offset.z;
+        m
+    }
+
+    pub fn scale(scale: Vec3) -> Mat4 {
+        let mut m = Mat4([0.0; 16]);
+        m.0[0] = scale.x;
+        m.0[5] = scale.y;
+        m.0[10] = scale.z;
+        m.0[15] = 1.0;
+        m
+    }
+
+    /// A rotation of `angle` radians counter-clockwise around the x axis.
+    pub fn rotation_x(angle: f64) -> Mat4 {
+        let (s, c) = angle.sin_cos();
+        let mut m = Mat4::identity();
+        m.0[5] = c;
+        m.0[6] = s;
+        m.0[9] = -s;
+        m.0[10] = c;
+        m
+    }
+
+    /// A rotation of `angle` radians counter-clockwise around the y axis.
+    pub fn rotation_y(angle: f64) -> Mat4 {
+        let (s, c) = angle.sin_cos();
+        let mut m = Mat4::identity();
+        m.0[0] = c;
+        m.0[2] = -s;
+        m.0[8] = s;
+        m.0[10] = c;
+        m
+    }
+
+    /// A rotation of `angle` radians counter-clockwise around the z axis.
+    pub fn rotation_z(angle: f64) -> Mat4 {
+        let (s, c) = angle.sin_cos();
+        let mut m = Mat4::identity();
+        m.0[0] = c;
+        m.0[1] = s;
+        m.0[4] = -s;
+        m.0[5] = c;
+        m
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.0[col * 4 + row]
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = Mat4([0.0; 16]);
+        for col in 0..4 {
+            for row in 0..4 {
+                m.0[row * 4 + col] = self.get(row, col);
+            }
+        }
+        m
+    }
+
+    /// Transforms a point, treating it as having a `w` component of 1.
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3 {
+            x: m[0] * p.x + m[4] * p.y + m[8] * p.z + m[12],
+            y: m[1] * p.x + m[5] * p.y + m[9] * p.z + m[13],
+            z: m[2] * p.x + m[6] * p.y + m[10] * p.z + m[14],
+        }
+    }
+
+    /// Transforms a direction, treating it as having a `w` component of 0.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3 {
+            x: m[0] * v.x + m[4] * v.y + m[8] * v.z,
+            y: m[1] * v.x + m[5] * v.y + m[9] * v.z,
+            z: m[2] * v.x + m[6] * v.y + m[10] * v.z,
+        }
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        // Each result column is a linear combination of the columns of `self`, written so that
+        // the inner loop runs over four contiguous lanes and vectorizes.
+        let mut m = [0.0; 16];
+        for col in 0..4 {
+            let mut column = [0.0; 4];
+            for k in 0..4 {
+                let scale = other.0[col * 4 + k];
+                for (lane, c) in column.iter_mut().enumerate() {
+                    *c += self.0[k * 4 + lane] * scale;
+                }
+            }
+            m[col * 4..col * 4 + 4].copy_from_slice(&column);
+        }
+        Mat4(m)
+    }
+}
+
+impl Mul<Vec3> for Mat4 {
+    type Output = Vec3;
+
+    fn mul(self, p: Vec3) -> Vec3 {
+        self.transform_point(p)
+    }
+}
+
+impl Vec2 {
+    pub fn from_value(value: Value) -> Result<Vec2, TypeError> {
+        match value {
+            Value::Table(t) => Ok(Vec2 {
+                x: field(t, b"x", "vec2")?,
+                y: field(t, b"y", "vec2")?,
+            }),
+            value => Err(TypeError {
+                expected: "vec2",
+                found: value.type_name(),
+            }),
+        }
+    }
+
+    pub fn to_table<'gc>(self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let t = Table::new(mc);
+        t.set(mc, String::new_static(b"x"), self.x).unwrap();
+        t.set(mc, String::new_static(b"y"), self.y).unwrap();
+        t
+    }
+}
+
+impl Vec3 {
+    pub fn from_value(value: Value) -> Result<Vec3, TypeError> {
+        match value {
+            Value::Table(t) => Ok(Vec3 {
+                x: field(t, b"x", "vec3")?,
+                y: field(t, b"y", "vec3")?,
+                z: field(t, b"z", "vec3")?,
+            }),
+            value => Err(TypeError {
+                expected: "vec3",
+                found: value.type_name(),
+            }),
+        }
+    }
+
+    pub fn to_table<'gc>(self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let t = Table::new(mc);
+        t.set(mc, String::new_static(b"x"), self.x).unwrap();
+        t.set(mc, String::new_static(b"y"), self.y).unwrap();
+        t.set(mc, String::new_static(b"z"), self.z).unwrap();
+        t
+    }
+}
+
+impl Mat4 {
+    pub fn from_value(value: Value) -> Result<Mat4, TypeError> {
+        match value {
+            Value::Table(t) => {
+                let mut m = [0.0; 16];
+                for (i, e) in m.iter_mut().enumerate() {
+                    *e = number(t.get(i as i64 + 1), "mat4")?;
+                }
+                Ok(Mat4(m))
+            }
+            value => Err(TypeError {
+                expected: "mat4",
+                found: value.type_name(),
+            }),
+        }
+    }
+
+    pub fn to_table<'gc>(self, mc: MutationContext<'gc, '_>) -> Table<'gc> {
+        let t = Table::new(mc);
+        for (i, &e) in self.0.iter().enumerate() {
+            t.set(mc, i as i64 + 1, e).unwrap();
+        }
+        t
+    }
+}
+
+fn field(table: Table, name: &'static [u8], expected: &'static str) -> Result<f64, TypeError> {
+    number(table.get(String::new_static(name)), expected)
+}
+
+fn number(value: Value, expected: &'static str) -> Result<f64, TypeError> {
+    match value {
+        Value::Integer(i) => Ok(i as f64),
+        Value::Number(n) => Ok(n),
+        _ => Err(TypeError {
+            expected,
+            found: value.type_name(),
+        }),
+    }
+}
+
+/// Loads the `vec2`, `vec3` and `mat4` libraries.
+///
+/// `vec2` and `vec3` have `new`, `add`, `sub`, `mul` (by a number or componentwise by a vector),
+/// `neg`, `dot`, `length`, `normalize` and `lerp`, and `vec3` also has `cross`.  `mat4` has
+/// `identity`, `translation`, `scale`, `rotation_x`, `rotation_y`, `rotation_z`, `mul`,
+/// `transpose`, `transform_point` and `transform_vector`.
+pub fn load_vecmath<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let vec2 = Table::new(mc);
+    set_fn(mc, vec2, b"new", |args| {
+        Ok(Vec2::new(arg_number(&args, 0)?, arg_number(&args, 1)?).into())
+    });
+    set_fn(mc, vec2, b"add", |args| {
+        Ok((arg_vec2(&args, 0)? + arg_vec2(&args, 1)?).into())
+    });
+    set_fn(mc, vec2, b"sub", |args| {
+        Ok((arg_vec2(&args, 0)? - arg_vec2(&args, 1)?).into())
+    });
+    set_fn(mc, vec2, b"mul", |args| {
+        let a = arg_vec2(&args, 0)?;
+        Ok(match arg(&args, 1) {
+            Value::Integer(_) | Value::Number(_) => a * arg_number(&args, 1)?,
+            _ => a * arg_vec2(&args, 1)?,
+        }
+        .into())
+    });
+    set_fn(mc, vec2, b"neg", |args| Ok((-arg_vec2(&args, 0)?).into()));
+    set_fn(mc, vec2, b"dot", |args| {
+        Ok(arg_vec2(&args, 0)?.dot(arg_vec2(&args, 1)?).into())
+    });
+    set_fn(mc, vec2, b"length", |args| {
+        Ok(arg_vec2(&args, 0)?.length().into())
+    });
+    set_fn(mc, vec2, b"normalize", |args| {
+        Ok(arg_vec2(&args, 0)?.normalize().into())
+    });
+    set_fn(mc, vec2, b"lerp", |args| {
+        Ok(arg_vec2(&args, 0)?
+            .lerp(arg_vec2(&args, 1)?, arg_number(&args, 2)?)
+            .into())
+    });
+    env.set(mc, String::new_static(b"vec2"), vec2).unwrap();
+
+    let vec3 = Table::new(mc);
+    set_fn(mc, vec3, b"new", |args| {
+        Ok(Vec3::new(
+            arg_number(&args, 0)?,
+            arg_number(&args, 1)?,
+            arg_number(&args, 2)?,
+        )
+        .into())
+    });
+    set_fn(mc, vec3, b"add", |args| {
+        Ok((arg_vec3(&args, 0)? + arg_vec3(&args, 1)?).into())
+    });
+    set_fn(mc, vec3, b"sub", |args| {
+        Ok((arg_vec3(&args, 0)? - arg_vec3(&args, 1)?).into())
+    });
+    set_fn(mc, vec3, b"mul", |args| {
+        let a = arg_vec3(&args, 0)?;
+        Ok(match arg(&args, 1) {
+            Value::Integer(_) | Value::Number(_) => a * arg_number(&args, 1)?,
+            _ => a * arg_vec3(&args, 1)?,
+        }
+        .into())
+    });
+    set_fn(mc, vec3, b"neg", |args| Ok((-arg_vec3(&args, 0)?).into()));
+    set_fn(mc, vec3, b"dot", |args| {
+        Ok(arg_vec3(&args, 0)?.dot(arg_vec3(&args, 1)?).into())
+    });
+    set_fn(mc, vec3, b"cross", |args| {
+        Ok(arg_vec3(&args, 0)?.cross(arg_vec3(&args, 1)?).into())
+    });
+    set_fn(mc, vec3, b"length", |args| {
+        Ok(arg_vec3(&args, 0)?.length().into())
+    });
+    set_fn(mc, vec3, b"normalize", |args| {
+        Ok(arg_vec3(&args, 0)?.normalize().into())
+    });
+    set_fn(mc, vec3, b"lerp", |args| {
+        Ok(arg_vec3(&args, 0)?
+            .lerp(arg_vec3(&args, 1)?, arg_number(&args, 2)?)
+            .into())
+    });
+    env.set(mc, String::new_static(b"vec3"), vec3).unwrap();
+
+    let mat4 = Table::new(mc);
+    set_fn(mc, mat4, b"identity", |_| Ok(Mat4::identity().into()));
+    set_fn(mc, mat4, b"translation", |args| {
+        Ok(Mat4::translation(arg_xyz(&args, 0)?).into())
+    });
+    set_fn(mc, mat4, b"scale", |args| {
+        Ok(Mat4::scale(arg_xyz(&args, 0)?).into())
+    });
+    set_fn(mc, mat4, b"rotation_x", |args| {
+        Ok(Mat4::rotation_x(arg_number(&args, 0)?).into())
+    });
+    set_fn(mc, mat4, b"rotation_y", |args| {
+        Ok(Mat4::rotation_y(arg_number(&args, 0)?).into())
+    });
+    set_fn(mc, mat4, b"rotation_z", |args| {
+        Ok(Mat4::rotation_z(arg_number(&args, 0)?).into())
+    });
+    set_fn(mc, mat4, b"mul", |args| {
+        Ok((arg_mat4(&args, 0)? * arg_mat4(&args, 1)?).into())
+    });
+    set_fn(mc, mat4, b"transpose", |args| {
+        Ok(arg_mat4(&args, 0)?.transpose().into())
+    });
+    set_fn(mc, mat4, b"transform_point", |args| {
+        Ok(arg_mat4(&args, 0)?
+            .transform_point(arg_vec3(&args, 1)?)
+            .into())
+    });
+    set_fn(mc, mat4, b"transform_vector", |args| {
+        Ok(arg_mat4(&args, 0)?
+            .transform_vector(arg_vec3(&args, 1)?)
+            .into())
+    });
+    env.set(mc, String::new_static(b"mat4"), mat4).unwrap();
+}
+
+// The result of a library function, computed without allocating and converted into a Lua value
+// once a `MutationContext` is available.
+#[derive(Collect)]
+#[collect(require_static)]
+enum MathResult {
+    Number(f64),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Mat4(Mat4),
+}
+
+impl From<f64> for MathResult {
+    fn from(n: f64) -> MathResult {
+        MathResult::Number(n)
+    }
+}
+
+impl From<Vec2> for MathResult {
+    fn from(v: Vec2) -> MathResult {
+        MathResult::Vec2(v)
+    }
+}
+
+impl From<Vec3> for MathResult {
+    fn from(v: Vec3) -> MathResult {
+        MathResult::Vec3(v)
+    }
+}
+
+impl From<Mat4> for MathResult {
+    fn from(m: Mat4) -> MathResult {
+        MathResult::Mat4(m)
+    }
+}
+
+fn set_fn<'gc, F>(mc: MutationContext<'gc, '_>, lib: Table<'gc>, name: &'static [u8], f: F)
+where
+    F: 'static + Fn(Vec<Value<'gc>>) -> Result<MathResult, Error<'gc>>,
+{
+    let callback = Callback::new_sequence(mc, move |args| {
+        Ok(sequence::from_fn_with(f(args)?, |mc, result| {
+            let value = match result {
+                MathResult::Number(n) => Value::Number(n),
+                MathResult::Vec2(v) => Value::Table(v.to_table(mc)),
+                MathResult::Vec3(v) => Value::Table(v.to_table(mc)),
+                MathResult::Mat4(m) => Value::Table(m.to_table(mc)),
+            };
+            Ok(CallbackResult::Return(vec![value]))
+        }))
+    });
+    lib.set(mc, String::new_static(name), callback).unwrap();
+}
+
+fn arg<'gc>(args: &[Value<'gc>], i: usize) -> Value<'gc> {
+    args.get(i).cloned().unwrap_or(Value::Nil)
+}
+
+fn arg_number<'gc>(args: &[Value<'gc>], i: usize) -> Result<f64, Error<'gc>> {
+    Ok(number(arg(args, i), "number")?)
+}
+
+fn arg_xyz<'gc>(args: &[Value<'gc>], i: usize) -> Result<Vec3, Error<'gc>> {
+    Ok(Vec3::new(
+        arg_number(args, i)?,
+        arg_number(args, i + 1)?,
+        arg_number(args, i + 2)?,
+    ))
+}
+
+fn arg_vec2<'gc>(args: &[Value<'gc>], i: usize) -> Result<Vec2, Error<'gc>> {
+    Ok(Vec2::from_value(arg(args, i))?)
+}
+
+fn arg_vec3<'gc>(args: &[Value<'gc>], i: usize) -> Result<Vec3, Error<'gc>> {
+    Ok(Vec3::from_value(arg(args, i))?)
+}
+
+fn arg_mat4<'gc>(args: &[Value<'gc>], i: usize) -> Result<Mat4, Error<'gc>> {
+    Ok(Mat4::from_value(arg(args, i))?)
+}
@@ -0,0 +1,197 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use gc_arena::Collect;
+
+use crate::{FunctionProto, OpCode, Operand, RegisterIndex, UpValueDescriptor, VarCount};
+
+/// A reason that a prototype was rejected by `verify`, with the index of the offending opcode
+/// where there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum VerifyError {
+    MissingReturn,
+    TooManyParameters,
+    StackTooLarge,
+    RegisterOutOfRange { pc: usize },
+    ConstantOutOfRange { pc: usize },
+    UpValueOutOfRange { pc: usize },
+    PrototypeOutOfRange { pc: usize },
+    JumpOutOfRange { pc: usize },
+    BadUpValue { index: usize },
+    BadLineInfo,
+    BadLocalInfo,
+}
+
+impl StdError for VerifyError {}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::MissingReturn => write!(fmt, "function does not end with a return"),
+            VerifyError::TooManyParameters => {
+                write!(fmt, "function has more parameters than registers")
+            }
+            VerifyError::StackTooLarge => {
+                write!(fmt, "function has more registers than can be addressed")
+            }
+            VerifyError::RegisterOutOfRange { pc } => {
+                write!(fmt, "register out of range at opcode {}", pc)
+            }
+            VerifyError::ConstantOutOfRange { pc } => {
+                write!(fmt, "constant out of range at opcode {}", pc)
+            }
+            VerifyError::UpValueOutOfRange { pc } => {
+                write!(fmt, "upvalue out of range at opcode {}", pc)
+            }
+            VerifyError::PrototypeOutOfRange { pc } => {
+                write!(fmt, "prototype out of range at opcode {}", pc)
+            }
+            VerifyError::JumpOutOfRange { pc } => {
+                write!(fmt, "jump target out of range at opcode {}", pc)
+            }
+            VerifyError::BadUpValue { index } => write!(fmt, "invalid upvalue {}", index),
+            VerifyError::BadLineInfo => write!(fmt, "invalid line information"),
+            VerifyError::BadLocalInfo => write!(fmt, "invalid local variable information"),
+        }
+    }
+}
+
+/// Checks that a prototype and all of its nested prototypes only refer to registers, constants,
+/// upvalues, prototypes and jump targets that exist, and that every range of registers an opcode
+/// operates on fits in a stack frame of at most 256 registers, so that running it cannot index out
+/// of bounds.  These are the invariants described on `OpCode`.
+///
+/// Every prototype produced by the compiler passes, this is for prototypes from elsewhere, such as
+/// loaded bytecode.  It does not check that registers are initialized before they are read, which
+/// the VM does not rely on.
+pub fn verify(proto: &FunctionProto) -> Result<(), VerifyError> {
+    verify_proto(proto, None)
+}
+
+fn verify_proto(proto: &FunctionProto, parent: Option<&FunctionProto>) -> Result<(), VerifyError> {
+    let stack_size = proto.stack_size as usize;
+    // Registers are addressed by a byte, so a larger stack frame could never be used and is only
+    // a way to make every call allocate more.
+    if stack_size > 256 {
+        return Err(VerifyError::StackTooLarge);
+    }
+    if proto.fixed_params as usize > stack_size {
+        return Err(VerifyError::TooManyParameters);
+    }
+    match proto.opcodes.last() {
+        Some(OpCode::Return { .. }) => {}
+        _ => return Err(VerifyError::MissingReturn),
+    }
+
+    for (pc, &opcode) in proto.opcodes.iter().enumerate() {
+        // The only register operand of these is the start of a range which may be empty, and is
+        // checked below.
+        let ranged = matches!(
+            opcode,
+            OpCode::LoadNil { .. }
+                | OpCode::Call { .. }
+                | OpCode::TailCall { .. }
+                | OpCode::Return { .. }
+                | OpCode::VarArgs { .. }
+        );
+
+        for operand in opcode.operands() {
+            let in_range = match operand {
+                Operand::Register(r) => ranged || (r.0 as usize) < stack_size,
+                Operand::Constant8(c) => (c.0 as usize) < proto.constants.len(),
+                Operand::Constant16(c) => (c.0 as usize) < proto.constants.len(),
+                Operand::UpValue(u) => (u.0 as usize) < proto.upvalues.len(),
+                Operand::Prototype(p) => (p.0 as usize) < proto.prototypes.len(),
+                Operand::CloseUpValues(r) => r.to_u8().map_or(true, |r| (r as usize) < stack_size),
+                Operand::Jump(offset) => {
+                    let target = pc as isize + 1 + offset as isize;
+                    target >= 0 && (target as usize) < proto.opcodes.len()
+                }
+                Operand::Count(_) | Operand::Bool(_) | Operand::Byte(_) => true,
+            };
+            if !in_range {
+                return Err(match operand {
+                    Operand::Constant8(_) | Operand::Constant16(_) => {
+                        VerifyError::ConstantOutOfRange { pc }
+                    }
+                    Operand::UpValue(_) => VerifyError::UpValueOutOfRange { pc },
+                    Operand::Prototype(_) => VerifyError::PrototypeOutOfRange { pc },
+                    Operand::Jump(_) => VerifyError::JumpOutOfRange { pc },
+                    _ => VerifyError::RegisterOutOfRange { pc },
+                });
+            }
+        }
+
+        // Opcodes that use a range of registers starting at one operand must fit the whole range
+        // in the stack frame.  Variable counts extend the stack as needed, so only their start must
+        // be in range.
+        let range = |start: RegisterIndex, count: usize| start.0 as usize + count <= stack_size;
+        let var_range = |start: RegisterIndex, extra: usize, count: VarCount| {
+            range(start, extra + count.to_constant().unwrap_or(0) as usize)
+        };
+        let in_range = match opcode {
+            OpCode::LoadNil { dest, count } => range(dest, count as usize),
+            OpCode::Call {
+                func,
+                args,
+                returns,
+            } => var_range(func, 1, args) && var_range(func, 0, returns),
+            OpCode::TailCall { func, args } => var_range(func, 1, args),
+            OpCode::Return { start, count } => var_range(start, 0, count),
+            OpCode::VarArgs { dest, count } => var_range(dest, 0, count),
+            OpCode::NumericForPrep { base, .. } => range(base, 3),
+            OpCode::NumericForLoop { base, .. } => range(base, 4),
+            OpCode::GenericForCall { base, var_count } => range(base, 3 + var_count as usize),
+            OpCode::GenericForLoop { base, .. } => range(base, 2),
+            OpCode::SelfR { base, .. } | OpCode::SelfC { base, .. } => range(base, 2),
+            OpCode::Concat { source, count, .. } => range(source, count as usize),
+            _ => true,
+        };
+        if !in_range {
+            return Err(VerifyError::RegisterOutOfRange { pc });
+        }
+    }
+
+    if proto.upvalue_names.len() != proto.upvalues.len() {
+        return Err(VerifyError::BadUpValue {
+            index: proto.upvalues.len().min(proto.upvalue_names.len()),
+        });
+    }
+    for (index, &upvalue) in proto.upvalues.iter().enumerate() {
+        let valid = match (upvalue, parent) {
+            (UpValueDescriptor::Environment, _) => true,
+            (UpValueDescriptor::ParentLocal(r), Some(parent)) => {
+                (r.0 as usize) < parent.stack_size as usize
+            }
+            (UpValueDescriptor::Outer(u), Some(parent)) => (u.0 as usize) < parent.upvalues.len(),
+            (_, None) => false,
+        };
+        if !valid {
+            return Err(VerifyError::BadUpValue { index });
+        }
+    }
+
+    let mut last_pc = None;
+    for &(pc, _) in &proto.opcode_lines {
+        if pc >= proto.opcodes.len() || last_pc.is_some_and(|last| pc <= last) {
+            return Err(VerifyError::BadLineInfo);
+        }
+        last_pc = Some(pc);
+    }
+
+    for local in &proto.local_variables {
+        if (local.register.0 as usize) >= stack_size
+            || local.start_pc > local.end_pc
+            || local.end_pc > proto.opcodes.len()
+        {
+            return Err(VerifyError::BadLocalInfo);
+        }
+    }
+
+    for child in &proto.prototypes {
+        verify_proto(child, Some(proto))?;
+    }
+
+    Ok(())
+}
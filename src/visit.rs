@@ -0,0 +1,568 @@
+//! Traversal of the AST produced by the `parser` module.
+//!
+//! A `Visitor` has one method for each kind of node, which by default calls the matching `walk_*`
+//! function to visit the children of the node in source order.  An implementation overrides the
+//! methods for the nodes it is interested in, and calls the `walk_*` function itself to continue
+//! into their children (or does not, to skip them).  `VisitorMut` is the same, but visits the nodes
+//! mutably so that they can be rewritten in place.
+//!
+//! ```
+//! use luster::parse_chunk;
+//! use luster::parser::CallSuffix;
+//! use luster::visit::{walk_call_suffix, Visitor};
+//!
+//! // Counts calls of functions and methods.
+//! struct Calls(usize);
+//!
+//! impl<S> Visitor<S> for Calls {
+//!     fn visit_call_suffix(&mut self, suffix: &CallSuffix<S>) {
+//!         self.0 += 1;
+//!         walk_call_suffix(self, suffix);
+//!     }
+//! }
+//!
+//! let chunk = parse_chunk(&b"f(g(1), t:m())"[..], |s| s.to_vec()).unwrap();
+//! let mut calls = Calls(0);
+//! calls.visit_chunk(&chunk);
+//! assert_eq!(calls.0, 3);
+//! ```
+
+use crate::parser::{
+    AssignmentTarget, Block, CallSuffix, Chunk, ConstructorField, Expression, FieldSuffix,
+    ForStatement, FunctionCallStatement, FunctionDefinition, HeadExpression, PrimaryExpression,
+    RecordKey, ReturnStatement, SimpleExpression, Spanned, Statement, SuffixPart,
+    SuffixedExpression, TableConstructor,
+};
+
+/// Visits the nodes of an AST immutably, see the module documentation.
+pub trait Visitor<S> {
+    fn visit_chunk(&mut self, chunk: &Chunk<S>) {
+        walk_chunk(self, chunk);
+    }
+
+    fn visit_block(&mut self, block: &Block<S>) {
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, statement: &Spanned<Statement<S>>) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_return_statement(&mut self, statement: &Spanned<ReturnStatement<S>>) {
+        walk_return_statement(self, statement);
+    }
+
+    fn visit_function_call(&mut self, call: &FunctionCallStatement<S>) {
+        walk_function_call(self, call);
+    }
+
+    fn visit_assignment_target(&mut self, target: &AssignmentTarget<S>) {
+        walk_assignment_target(self, target);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<S>) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_simple_expression(&mut self, expression: &SimpleExpression<S>) {
+        walk_simple_expression(self, expression);
+    }
+
+    fn visit_suffixed_expression(&mut self, expression: &SuffixedExpression<S>) {
+        walk_suffixed_expression(self, expression);
+    }
+
+    fn visit_primary_expression(&mut self, expression: &Spanned<PrimaryExpression<S>>) {
+        walk_primary_expression(self, expression);
+    }
+
+    fn visit_field_suffix(&mut self, suffix: &FieldSuffix<S>) {
+        walk_field_suffix(self, suffix);
+    }
+
+    fn visit_call_suffix(&mut self, suffix: &CallSuffix<S>) {
+        walk_call_suffix(self, suffix);
+    }
+
+    fn visit_function_definition(&mut self, definition: &FunctionDefinition<S>) {
+        walk_function_definition(self, definition);
+    }
+
+    fn visit_table_constructor(&mut self, constructor: &TableConstructor<S>) {
+        walk_table_constructor(self, constructor);
+    }
+}
+
+pub fn walk_chunk<S, V: Visitor<S> + ?Sized>(visitor: &mut V, chunk: &Chunk<S>) {
+    visitor.visit_block(&chunk.block);
+}
+
+pub fn walk_block<S, V: Visitor<S> + ?Sized>(visitor: &mut V, block: &Block<S>) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+    if let Some(return_statement) = &block.return_statement {
+        visitor.visit_return_statement(return_statement);
+    }
+}
+
+pub fn walk_statement<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    statement: &Spanned<Statement<S>>,
+) {
+    match &statement.node {
+        Statement::If(if_statement) => {
+            let (condition, block) = &if_statement.if_part;
+            visitor.visit_expression(condition);
+            visitor.visit_block(block);
+            for (condition, block) in &if_statement.else_if_parts {
+                visitor.visit_expression(condition);
+                visitor.visit_block(block);
+            }
+            if let Some(block) = &if_statement.else_part {
+                visitor.visit_block(block);
+            }
+        }
+        Statement::While(while_statement) => {
+            visitor.visit_expression(&while_statement.condition);
+            visitor.visit_block(&while_statement.block);
+        }
+        Statement::Do(block) => visitor.visit_block(block),
+        Statement::For(ForStatement::Numeric {
+            initial,
+            limit,
+            step,
+            body,
+            ..
+        }) => {
+            visitor.visit_expression(initial);
+            visitor.visit_expression(limit);
+            if let Some(step) = step {
+                visitor.visit_expression(step);
+            }
+            visitor.visit_block(body);
+        }
+        Statement::For(ForStatement::Generic {
+            arguments, body, ..
+        }) => {
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+            visitor.visit_block(body);
+        }
+        Statement::Repeat(repeat_statement) => {
+            visitor.visit_block(&repeat_statement.body);
+            visitor.visit_expression(&repeat_statement.until);
+        }
+        Statement::Function(function_statement) => {
+            visitor.visit_function_definition(&function_statement.definition);
+        }
+        Statement::LocalFunction(local_function) => {
+            visitor.visit_function_definition(&local_function.definition);
+        }
+        Statement::LocalStatement(local_statement) => {
+            for value in &local_statement.values {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Label(_) | Statement::Break | Statement::Goto(_) => {}
+        Statement::FunctionCall(call) => visitor.visit_function_call(call),
+        Statement::Assignment(assignment) => {
+            for target in &assignment.targets {
+                visitor.visit_assignment_target(target);
+            }
+            for value in &assignment.values {
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+pub fn walk_return_statement<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    statement: &Spanned<ReturnStatement<S>>,
+) {
+    for value in &statement.node.returns {
+        visitor.visit_expression(value);
+    }
+}
+
+pub fn walk_function_call<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    call: &FunctionCallStatement<S>,
+) {
+    visitor.visit_suffixed_expression(&call.head);
+    visitor.visit_call_suffix(&call.call);
+}
+
+pub fn walk_assignment_target<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    target: &AssignmentTarget<S>,
+) {
+    match target {
+        AssignmentTarget::Name(_) => {}
+        AssignmentTarget::Field(head, field) => {
+            visitor.visit_suffixed_expression(head);
+            visitor.visit_field_suffix(field);
+        }
+    }
+}
+
+pub fn walk_expression<S, V: Visitor<S> + ?Sized>(visitor: &mut V, expression: &Expression<S>) {
+    match &*expression.head {
+        HeadExpression::Simple(simple) => visitor.visit_simple_expression(simple),
+        HeadExpression::UnaryOperator(_, operand) => visitor.visit_expression(operand),
+    }
+    for (_, right) in &expression.tail {
+        visitor.visit_expression(right);
+    }
+}
+
+pub fn walk_simple_expression<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &SimpleExpression<S>,
+) {
+    match expression {
+        SimpleExpression::TableConstructor(constructor) => {
+            visitor.visit_table_constructor(constructor)
+        }
+        SimpleExpression::Function(definition) => visitor.visit_function_definition(definition),
+        SimpleExpression::Suffixed(suffixed) => visitor.visit_suffixed_expression(suffixed),
+        _ => {}
+    }
+}
+
+pub fn walk_suffixed_expression<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &SuffixedExpression<S>,
+) {
+    visitor.visit_primary_expression(&expression.primary);
+    for suffix in &expression.suffixes {
+        match &suffix.node {
+            SuffixPart::Field(field) => visitor.visit_field_suffix(field),
+            SuffixPart::Call(call) => visitor.visit_call_suffix(call),
+        }
+    }
+}
+
+pub fn walk_primary_expression<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &Spanned<PrimaryExpression<S>>,
+) {
+    match &expression.node {
+        PrimaryExpression::Name(_) => {}
+        PrimaryExpression::GroupedExpression(inner) => visitor.visit_expression(inner),
+    }
+}
+
+pub fn walk_field_suffix<S, V: Visitor<S> + ?Sized>(visitor: &mut V, suffix: &FieldSuffix<S>) {
+    match suffix {
+        FieldSuffix::Named(_) => {}
+        FieldSuffix::Indexed(key) => visitor.visit_expression(key),
+    }
+}
+
+pub fn walk_call_suffix<S, V: Visitor<S> + ?Sized>(visitor: &mut V, suffix: &CallSuffix<S>) {
+    let arguments = match suffix {
+        CallSuffix::Method(_, arguments) => arguments,
+        CallSuffix::Function(arguments) => arguments,
+    };
+    for argument in arguments {
+        visitor.visit_expression(argument);
+    }
+}
+
+pub fn walk_function_definition<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    definition: &FunctionDefinition<S>,
+) {
+    visitor.visit_block(&definition.body);
+}
+
+pub fn walk_table_constructor<S, V: Visitor<S> + ?Sized>(
+    visitor: &mut V,
+    constructor: &TableConstructor<S>,
+) {
+    for field in &constructor.fields {
+        match field {
+            ConstructorField::Array(value) => visitor.visit_expression(value),
+            ConstructorField::Record(key, value) => {
+                if let RecordKey::Indexed(key) = key {
+                    visitor.visit_expression(key);
+                }
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+/// The same as `Visitor`, but visits the nodes mutably.
+pub trait VisitorMut<S> {
+    fn visit_chunk_mut(&mut self, chunk: &mut Chunk<S>) {
+        walk_chunk_mut(self, chunk);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block<S>) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Spanned<Statement<S>>) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_return_statement_mut(&mut self, statement: &mut Spanned<ReturnStatement<S>>) {
+        walk_return_statement_mut(self, statement);
+    }
+
+    fn visit_function_call_mut(&mut self, call: &mut FunctionCallStatement<S>) {
+        walk_function_call_mut(self, call);
+    }
+
+    fn visit_assignment_target_mut(&mut self, target: &mut AssignmentTarget<S>) {
+        walk_assignment_target_mut(self, target);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression<S>) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_simple_expression_mut(&mut self, expression: &mut SimpleExpression<S>) {
+        walk_simple_expression_mut(self, expression);
+    }
+
+    fn visit_suffixed_expression_mut(&mut self, expression: &mut SuffixedExpression<S>) {
+        walk_suffixed_expression_mut(self, expression);
+    }
+
+    fn visit_primary_expression_mut(&mut self, expression: &mut Spanned<PrimaryExpression<S>>) {
+        walk_primary_expression_mut(self, expression);
+    }
+
+    fn visit_field_suffix_mut(&mut self, suffix: &mut FieldSuffix<S>) {
+        walk_field_suffix_mut(self, suffix);
+    }
+
+    fn visit_call_suffix_mut(&mut self, suffix: &mut CallSuffix<S>) {
+        walk_call_suffix_mut(self, suffix);
+    }
+
+    fn visit_function_definition_mut(&mut self, definition: &mut FunctionDefinition<S>) {
+        walk_function_definition_mut(self, definition);
+    }
+
+    fn visit_table_constructor_mut(&mut self, constructor: &mut TableConstructor<S>) {
+        walk_table_constructor_mut(self, constructor);
+    }
+}
+
+pub fn walk_chunk_mut<S, V: VisitorMut<S> + ?Sized>(visitor: &mut V, chunk: &mut Chunk<S>) {
+    visitor.visit_block_mut(&mut chunk.block);
+}
+
+pub fn walk_block_mut<S, V: VisitorMut<S> + ?Sized>(visitor: &mut V, block: &mut Block<S>) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+    if let Some(return_statement) = &mut block.return_statement {
+        visitor.visit_return_statement_mut(return_statement);
+    }
+}
+
+pub fn walk_statement_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    statement: &mut Spanned<Statement<S>>,
+) {
+    match &mut statement.node {
+        Statement::If(if_statement) => {
+            let (condition, block) = &mut if_statement.if_part;
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_mut(block);
+            for (condition, block) in &mut if_statement.else_if_parts {
+                visitor.visit_expression_mut(condition);
+                visitor.visit_block_mut(block);
+            }
+            if let Some(block) = &mut if_statement.else_part {
+                visitor.visit_block_mut(block);
+            }
+        }
+        Statement::While(while_statement) => {
+            visitor.visit_expression_mut(&mut while_statement.condition);
+            visitor.visit_block_mut(&mut while_statement.block);
+        }
+        Statement::Do(block) => visitor.visit_block_mut(block),
+        Statement::For(ForStatement::Numeric {
+            initial,
+            limit,
+            step,
+            body,
+            ..
+        }) => {
+            visitor.visit_expression_mut(initial);
+            visitor.visit_expression_mut(limit);
+            if let Some(step) = step {
+                visitor.visit_expression_mut(step);
+            }
+            visitor.visit_block_mut(body);
+        }
+        Statement::For(ForStatement::Generic {
+            arguments, body, ..
+        }) => {
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+            visitor.visit_block_mut(body);
+        }
+        Statement::Repeat(repeat_statement) => {
+            visitor.visit_block_mut(&mut repeat_statement.body);
+            visitor.visit_expression_mut(&mut repeat_statement.until);
+        }
+        Statement::Function(function_statement) => {
+            visitor.visit_function_definition_mut(&mut function_statement.definition);
+        }
+        Statement::LocalFunction(local_function) => {
+            visitor.visit_function_definition_mut(&mut local_function.definition);
+        }
+        Statement::LocalStatement(local_statement) => {
+            for value in &mut local_statement.values {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Statement::Label(_) | Statement::Break | Statement::Goto(_) => {}
+        Statement::FunctionCall(call) => visitor.visit_function_call_mut(call),
+        Statement::Assignment(assignment) => {
+            for target in &mut assignment.targets {
+                visitor.visit_assignment_target_mut(target);
+            }
+            for value in &mut assignment.values {
+                visitor.visit_expression_mut(value);
+            }
+        }
+    }
+}
+
+pub fn walk_return_statement_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    statement: &mut Spanned<ReturnStatement<S>>,
+) {
+    for value in &mut statement.node.returns {
+        visitor.visit_expression_mut(value);
+    }
+}
+
+pub fn walk_function_call_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    call: &mut FunctionCallStatement<S>,
+) {
+    visitor.visit_suffixed_expression_mut(&mut call.head);
+    visitor.visit_call_suffix_mut(&mut call.call);
+}
+
+pub fn walk_assignment_target_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    target: &mut AssignmentTarget<S>,
+) {
+    match target {
+        AssignmentTarget::Name(_) => {}
+        AssignmentTarget::Field(head, field) => {
+            visitor.visit_suffixed_expression_mut(head);
+            visitor.visit_field_suffix_mut(field);
+        }
+    }
+}
+
+pub fn walk_expression_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &mut Expression<S>,
+) {
+    match &mut *expression.head {
+        HeadExpression::Simple(simple) => visitor.visit_simple_expression_mut(simple),
+        HeadExpression::UnaryOperator(_, operand) => visitor.visit_expression_mut(operand),
+    }
+    for (_, right) in &mut expression.tail {
+        visitor.visit_expression_mut(right);
+    }
+}
+
+pub fn walk_simple_expression_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &mut SimpleExpression<S>,
+) {
+    match expression {
+        SimpleExpression::TableConstructor(constructor) => {
+            visitor.visit_table_constructor_mut(constructor)
+        }
+        SimpleExpression::Function(definition) => visitor.visit_function_definition_mut(definition),
+        SimpleExpression::Suffixed(suffixed) => visitor.visit_suffixed_expression_mut(suffixed),
+        _ => {}
+    }
+}
+
+pub fn walk_suffixed_expression_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &mut SuffixedExpression<S>,
+) {
+    visitor.visit_primary_expression_mut(&mut expression.primary);
+    for suffix in &mut expression.suffixes {
+        match &mut suffix.node {
+            SuffixPart::Field(field) => visitor.visit_field_suffix_mut(field),
+            SuffixPart::Call(call) => visitor.visit_call_suffix_mut(call),
+        }
+    }
+}
+
+pub fn walk_primary_expression_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    expression: &mut Spanned<PrimaryExpression<S>>,
+) {
+    match &mut expression.node {
+        PrimaryExpression::Name(_) => {}
+        PrimaryExpression::GroupedExpression(inner) => visitor.visit_expression_mut(inner),
+    }
+}
+
+pub fn walk_field_suffix_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    suffix: &mut FieldSuffix<S>,
+) {
+    match suffix {
+        FieldSuffix::Named(_) => {}
+        FieldSuffix::Indexed(key) => visitor.visit_expression_mut(key),
+    }
+}
+
+pub fn walk_call_suffix_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    suffix: &mut CallSuffix<S>,
+) {
+    let arguments = match suffix {
+        CallSuffix::Method(_, arguments) => arguments,
+        CallSuffix::Function(arguments) => arguments,
+    };
+    for argument in arguments {
+        visitor.visit_expression_mut(argument);
+    }
+}
+
+pub fn walk_function_definition_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    definition: &mut FunctionDefinition<S>,
+) {
+    visitor.visit_block_mut(&mut definition.body);
+}
+
+pub fn walk_table_constructor_mut<S, V: VisitorMut<S> + ?Sized>(
+    visitor: &mut V,
+    constructor: &mut TableConstructor<S>,
+) {
+    for field in &mut constructor.fields {
+        match field {
+            ConstructorField::Array(value) => visitor.visit_expression_mut(value),
+            ConstructorField::Record(key, value) => {
+                if let RecordKey::Indexed(key) = key {
+                    visitor.visit_expression_mut(key);
+                }
+                visitor.visit_expression_mut(value);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use luster::{Callback, CallbackResult, Continuation, Function, Lua, OwnedValue, String};
+
+// Counts the allocations made by the current thread, so that tests running in parallel do not
+// disturb each other.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+// Runs `body` in a loop `iterations` times, returning how many allocations that took.
+fn count_allocations(lua: &mut Lua, body: &str, iterations: usize) -> usize {
+    let source = format!(
+        "local n = 0 for i = 1, {} do {} end return n",
+        iterations, body
+    );
+    let before = allocations();
+    lua.run_string(source.as_bytes()).unwrap();
+    allocations() - before
+}
+
+#[test]
+fn calls_do_not_allocate() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let echo = Callback::new_immediate(mc, |args| Ok(CallbackResult::Return(args)));
+        root.globals
+            .set(mc, String::new_static(b"echo"), echo)
+            .unwrap();
+        // Tail calls `echo`, passing its results through a continuation.
+        let tail = Callback::new_immediate_with(mc, echo, |echo, args| {
+            Ok(CallbackResult::TailCall {
+                function: Function::Callback(*echo),
+                args,
+                continuation: Continuation::new_immediate(|res| Ok(CallbackResult::Return(res?))),
+            })
+        });
+        root.globals
+            .set(mc, String::new_static(b"tail"), tail)
+            .unwrap();
+    });
+    lua.run_string(
+        br#"
+            function add(a, b) return a + b end
+            function forward(a, b, c) return echo(a, b, c) end
+            function sum(...) local a, b, c = ... return a + b + c end
+        "#,
+    )
+    .unwrap();
+
+    for body in &[
+        "n = n + echo(i)",
+        "n = n + select(2, echo(i, 1, 2, 3))",
+        "n = add(n, i)",
+        "n = n + forward(i, 2, 3)",
+        "n = n + sum(i, 2, 3)",
+        "n = n + tail(i)",
+    ] {
+        // Warm up the thread's stack and scratch buffers first.
+        count_allocations(&mut lua, body, 100);
+        let few = count_allocations(&mut lua, body, 100);
+        let many = count_allocations(&mut lua, body, 100_000);
+        assert!(
+            many <= few + few / 2,
+            "`{}` made {} allocations in 100 iterations but {} in 100000",
+            body,
+            few,
+            many
+        );
+    }
+
+    assert_eq!(
+        lua.run_string(b"return forward(1, 2, 3)").unwrap(),
+        vec![
+            OwnedValue::Integer(1),
+            OwnedValue::Integer(2),
+            OwnedValue::Integer(3)
+        ]
+    );
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn native_code_does_not_allocate() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        root.main_thread
+            .set_jit(mc, Some(luster::jit::Jit::new(mc, 0)))
+    });
+    lua.run_string(b"t = {1}").unwrap();
+
+    // Indexing a table is not compiled, so native code is entered again on every iteration.
+    let body = "local a, b = t, i n = n + b * 2 + a[1]";
+    count_allocations(&mut lua, body, 100);
+    let few = count_allocations(&mut lua, body, 100);
+    let many = count_allocations(&mut lua, body, 100_000);
+    assert!(
+        many <= few + few / 2,
+        "native code made {} allocations in 100 iterations but {} in 100000",
+        few,
+        many
+    );
+}
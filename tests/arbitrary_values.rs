@@ -0,0 +1,300 @@
+use gc_arena::MutationContext;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use luster::{values_deep_equal, InvalidTableKey, Lua, OwnedValue, String, Table, Value};
+
+// A value generated outside of the arena, which `build` turns into a `Value`.
+#[derive(Debug, Clone)]
+enum Arbitrary {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(Arbitrary, Arbitrary)>),
+    // The table this appears in, so that generated tables can contain themselves.
+    Parent,
+}
+
+fn integer() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        Just(i64::MIN),
+        Just(i64::MAX),
+        Just(0),
+        Just(-1),
+        -70i64..70,
+        any::<i64>(),
+    ]
+}
+
+fn number() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        Just(f64::NAN),
+        Just(f64::INFINITY),
+        Just(f64::NEG_INFINITY),
+        Just(-0.0),
+        Just(9223372036854775808.0),
+        Just(-9223372036854775808.0),
+        Just(f64::MIN_POSITIVE),
+        (-70i64..70).prop_map(|i| i as f64),
+        -1e3f64..1e3,
+        any::<f64>(),
+    ]
+}
+
+fn string() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => vec(any::<u8>(), 0..40),
+        2 => integer().prop_map(|i| i.to_string().into_bytes()),
+        2 => number().prop_map(|n| format!(" {} ", n).into_bytes()),
+        1 => "-?0[xX][0-9a-fA-F]{0,20}(\\.[0-9a-f]{0,4})?([pP]-?[0-9]{1,4})?"
+            .prop_map(|s| s.into_bytes()),
+        1 => (1usize << 16..1 << 20).prop_map(|len| vec![b'9'; len]),
+    ]
+}
+
+fn arbitrary() -> impl Strategy<Value = Arbitrary> {
+    let leaf = prop_oneof![
+        Just(Arbitrary::Nil),
+        any::<bool>().prop_map(Arbitrary::Boolean),
+        integer().prop_map(Arbitrary::Integer),
+        number().prop_map(Arbitrary::Number),
+        string().prop_map(Arbitrary::String),
+    ];
+    let tables = leaf.prop_recursive(8, 256, 8, |inner| {
+        let entry = prop_oneof![inner, Just(Arbitrary::Parent)];
+        vec((entry.clone(), entry), 0..8).prop_map(Arbitrary::Table)
+    });
+    prop_oneof![
+        8 => tables,
+        // A long chain of nested tables.
+        1 => (0usize..512).prop_map(|depth| {
+            (0..depth).fold(Arbitrary::Nil, |inner, _| {
+                Arbitrary::Table(vec![(Arbitrary::Integer(1), inner)])
+            })
+        }),
+    ]
+}
+
+fn build<'gc>(
+    mc: MutationContext<'gc, '_>,
+    arbitrary: &Arbitrary,
+    parent: Option<Table<'gc>>,
+) -> Value<'gc> {
+    match arbitrary {
+        Arbitrary::Nil => Value::Nil,
+        Arbitrary::Boolean(b) => Value::Boolean(*b),
+        Arbitrary::Integer(i) => Value::Integer(*i),
+        Arbitrary::Number(n) => Value::Number(*n),
+        Arbitrary::String(s) => Value::String(String::new(mc, s)),
+        Arbitrary::Table(entries) => {
+            let table = Table::new(mc);
+            for (key, value) in entries {
+                let key = build(mc, key, Some(table));
+                let value = build(mc, value, Some(table));
+                // Nil and NaN keys are rejected, which is checked by `table_operations`.
+                let _ = table.set(mc, key, value);
+            }
+            Value::Table(table)
+        }
+        Arbitrary::Parent => parent.map(Value::Table).unwrap_or(Value::Nil),
+    }
+}
+
+fn contains_nan(arbitrary: &Arbitrary) -> bool {
+    match arbitrary {
+        Arbitrary::Number(n) => n.is_nan(),
+        Arbitrary::Table(entries) => entries
+            .iter()
+            .any(|(key, value)| contains_nan(key) || contains_nan(value)),
+        _ => false,
+    }
+}
+
+fn has_table_keys(arbitrary: &Arbitrary) -> bool {
+    match arbitrary {
+        Arbitrary::Table(entries) => entries.iter().any(|(key, value)| {
+            matches!(key, Arbitrary::Table(_) | Arbitrary::Parent) || has_table_keys(value)
+        }),
+        _ => false,
+    }
+}
+
+fn is_nan(value: Value) -> bool {
+    match value {
+        Value::Number(n) => n.is_nan(),
+        _ => false,
+    }
+}
+
+// Equality that also holds between a NaN and itself.
+fn same<'gc>(a: Value<'gc>, b: Value<'gc>) -> bool {
+    a == b || is_nan(a) && is_nan(b)
+}
+
+proptest! {
+    #[test]
+    fn value_operations(a in arbitrary(), b in arbitrary(), max_len in 0usize..64) {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, _| {
+            let (x, y) = (build(mc, &a, None), build(mc, &b, None));
+            for &(x, y) in &[(x, y), (y, x), (x, x)] {
+                assert_eq!(x.to_bool(), !matches!(x, Value::Nil | Value::Boolean(false)));
+                assert_eq!(x.not(), Value::Boolean(!x.to_bool()));
+                if let Some(i) = x.to_integer() {
+                    assert_eq!(x.to_number(), Some(i as f64));
+                }
+
+                let numbers = x.to_number().is_some() && y.to_number().is_some();
+                for result in &[
+                    x.add(y),
+                    x.subtract(y),
+                    x.multiply(y),
+                    x.float_divide(y),
+                    x.exponentiate(y),
+                ] {
+                    assert_eq!(result.is_some(), numbers);
+                }
+                assert_eq!(
+                    x.negate().is_some(),
+                    matches!(x, Value::Integer(_) | Value::Number(_))
+                );
+                for result in [
+                    x.bitwise_not(),
+                    x.bitwise_and(y),
+                    x.bitwise_or(y),
+                    x.bitwise_xor(y),
+                    x.shift_left(y),
+                    x.shift_right(y),
+                ]
+                .iter()
+                .flatten()
+                {
+                    assert!(matches!(result, Value::Integer(_)));
+                }
+
+                let (quotient, remainder) = (x.floor_divide(y), x.modulo(y));
+                if let (Value::Integer(a), Value::Integer(b)) = (x, y) {
+                    if b == 0 {
+                        assert_eq!((quotient, remainder), (None, None));
+                    } else {
+                        let (q, r) = match (quotient, remainder) {
+                            (Some(Value::Integer(q)), Some(Value::Integer(r))) => (q, r),
+                            results => panic!("non-integer results {:?}", results),
+                        };
+                        assert_eq!(q.wrapping_mul(b).wrapping_add(r), a);
+                        assert!(r == 0 || (r < 0) == (b < 0));
+                        assert!((r as i128).abs() < (b as i128).abs());
+                    }
+                    assert_eq!(x.shift_left(y), y.negate().and_then(|y| x.shift_right(y)));
+                }
+
+                if x.less_than(y) == Some(true) {
+                    assert_ne!(y.less_than(x), Some(true));
+                    assert_eq!(x.less_equal(y), Some(true));
+                }
+
+                let mut displayed = Vec::new();
+                x.display(&mut displayed).unwrap();
+                for depth in 0..3 {
+                    x.debug_fmt(depth);
+                }
+                assert_eq!(OwnedValue::from_value(x).type_name(), x.type_name());
+
+                if let Ok(concatenated) = String::concat(mc, &[x, y], max_len) {
+                    assert!(concatenated.len() <= max_len);
+                    if let Value::String(x) = x {
+                        assert!(concatenated.starts_with(&x));
+                    }
+                }
+            }
+
+            // Two separately built copies of the same value are deeply equal, unless a NaN or a
+            // table key (which is only equal to itself) makes them unequal.
+            let copy = build(mc, &a, None);
+            if !contains_nan(&a) && !has_table_keys(&a) {
+                assert!(values_deep_equal(x, copy));
+            }
+        });
+    }
+
+    #[test]
+    fn table_operations(
+        entries in vec((arbitrary(), arbitrary()), 0..64),
+        probes in vec(arbitrary(), 0..16),
+    ) {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, _| {
+            let table = Table::new(mc);
+            // The expected contents of the table, in insertion order.
+            let mut model: Vec<(Value, Value)> = Vec::new();
+            let mut version = table.metatable_version();
+
+            for (key, value) in &entries {
+                let (key, value) = (build(mc, key, Some(table)), build(mc, value, Some(table)));
+                let position = model.iter().position(|&(k, _)| k == key);
+                match (key, table.set(mc, key, value)) {
+                    (Value::Nil, result) => assert!(matches!(result, Err(InvalidTableKey::IsNil))),
+                    (key, result) if is_nan(key) => {
+                        assert!(matches!(result, Err(InvalidTableKey::IsNaN)))
+                    }
+                    (key, result) => {
+                        let previous = position.map_or(Value::Nil, |i| model[i].1);
+                        assert!(same(result.unwrap(), previous));
+                        match position {
+                            Some(i) if value == Value::Nil => {
+                                model.remove(i);
+                            }
+                            Some(i) => model[i].1 = value,
+                            None if value == Value::Nil => {}
+                            None => model.push((key, value)),
+                        }
+                    }
+                }
+                assert!(table.metatable_version() >= version);
+                version = table.metatable_version();
+            }
+
+            for &(key, value) in &model {
+                assert!(same(table.get(key), value));
+            }
+            for probe in &probes {
+                let probe = build(mc, probe, Some(table));
+                let expected = model.iter().find(|&&(k, _)| k == probe).map_or(Value::Nil, |e| e.1);
+                assert!(same(table.get(probe), expected));
+            }
+
+            let contents = table.0.read().iter().collect::<Vec<_>>();
+            assert_eq!(contents.len(), model.len());
+            for (key, value) in contents {
+                assert!(model.iter().any(|&(k, v)| k == key && same(v, value)));
+            }
+
+            // The length is a border.
+            let length = table.length();
+            assert!(length >= 0);
+            assert!(length == 0 || table.get(length) != Value::Nil);
+            if length < i64::MAX {
+                assert_eq!(table.get(length + 1), Value::Nil);
+            }
+        });
+    }
+
+    #[test]
+    fn string_operations(a in string(), b in string()) {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, root| {
+            let (x, y) = (String::new(mc, &a), String::new(mc, &b));
+            assert_eq!(x.as_bytes(), &a[..]);
+            assert_eq!(x == y, a == b);
+            assert_eq!(root.interned_strings.new_string(mc, &a), x);
+
+            let concatenated =
+                String::concat(mc, &[Value::String(x), Value::String(y)], usize::MAX).unwrap();
+            assert_eq!(concatenated.as_bytes(), &[&a[..], &b[..]].concat()[..]);
+            assert!(String::concat(mc, &[Value::String(x)], a.len().saturating_sub(1)).is_err()
+                || a.is_empty());
+        });
+    }
+}
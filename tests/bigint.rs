@@ -0,0 +1,56 @@
+#![cfg(feature = "bigint")]
+
+use luster::bigint::load_bigint;
+use luster::{Lua, OwnedValue, StaticError};
+
+fn run(source: &str) -> Result<Vec<OwnedValue>, Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| load_bigint(mc, root, root.globals));
+    Ok(lua.run_string(source.as_bytes())?)
+}
+
+fn string(s: &str) -> OwnedValue {
+    OwnedValue::String(s.as_bytes().to_vec())
+}
+
+#[test]
+fn bigint_arithmetic() -> Result<(), Box<StaticError>> {
+    let values = run(r#"
+        local max = 9223372036854775807
+        local big = bigint.add(max, 1)
+        return big,
+            bigint.mul(big, big),
+            bigint.pow(2, 100),
+            bigint.sub(1, "-0010"),
+            bigint.div(-7, 2),
+            bigint.mod(-7, 2),
+            bigint.cmp(big, max),
+            bigint.tointeger(bigint.sub(big, 1)),
+            bigint.tointeger(big)
+    "#)?;
+    assert_eq!(
+        values,
+        vec![
+            string("9223372036854775808"),
+            string("85070591730234615865843651857942052864"),
+            string("1267650600228229401496703205376"),
+            string("11"),
+            string("-4"),
+            string("1"),
+            OwnedValue::Integer(1),
+            OwnedValue::Integer(i64::MAX),
+            OwnedValue::Nil,
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn bigint_errors() -> Result<(), Box<StaticError>> {
+    let values = run(r#"
+        return pcall(bigint.div, 1, 0), pcall(bigint.add, "12a", 1), pcall(bigint.pow, 2, -1)
+    "#)?;
+    assert_eq!(values.len(), 4);
+    assert!(values[..3].iter().all(|v| *v == OwnedValue::Boolean(false)));
+    Ok(())
+}
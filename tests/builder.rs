@@ -0,0 +1,347 @@
+use gc_arena::ArenaParameters;
+use luster::{
+    compile, CharClass, CharClasses, Closure, Dialect, Function, Lua, OwnedValue, StdLib, String,
+    Value,
+};
+
+#[test]
+fn builder_stdlib() {
+    let mut lua = Lua::builder()
+        .arena_parameters(ArenaParameters::default().set_pause_factor(0.0))
+        .collector_granularity(0.0)
+        .stdlib(StdLib {
+            math: false,
+            ..StdLib::all()
+        })
+        .build();
+
+    lua.mutate(|_, root| {
+        assert!(matches!(
+            root.globals.get(String::new_static(b"print")),
+            Value::Function(_)
+        ));
+        assert!(matches!(
+            root.globals.get(String::new_static(b"math")),
+            Value::Nil
+        ));
+    });
+
+    let mut lua = Lua::builder().stdlib(StdLib::none()).build();
+    lua.mutate(|_, root| {
+        assert!(matches!(
+            root.globals.get(String::new_static(b"print")),
+            Value::Nil
+        ));
+    });
+}
+
+#[test]
+fn builder_max_string_len() {
+    let mut lua = Lua::builder().max_string_len(10).build();
+    let results = lua
+        .run_string(
+            &br#"
+                local function rep(...)
+                    local ok, r = pcall(string.rep, ...)
+                    return ok and string.len(r) or r
+                end
+                local co = coroutine.create(function() return rep("ab", 6) end)
+                local _, in_coroutine = coroutine.resume(co)
+                return rep("ab", 5), rep("a", 4, "-"), rep("ab", 6), in_coroutine
+            "#[..],
+        )
+        .unwrap();
+    let too_large = OwnedValue::String(b"resulting string too large".to_vec());
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::Integer(10),
+            OwnedValue::Integer(7),
+            too_large.clone(),
+            too_large
+        ]
+    );
+}
+
+#[test]
+fn builder_max_string_len_growth() {
+    let mut lua = Lua::builder().max_string_len(10).build();
+    let results = lua
+        .run_string(
+            &br#"
+                local function len(f, ...)
+                    local ok, r = pcall(f, ...)
+                    return ok and string.len(r) or r
+                end
+                local buf = buffer.new():put("abcdef")
+                local put_ok, put_err = pcall(buf.put, buf, "ghijk")
+                local s = "ab"
+                local concat_ok, concat_err = pcall(function()
+                    while true do s = s .. s end
+                end)
+                return
+                    len(string.format, "%5d%5d", 1, 2), len(string.format, "%11d", 1),
+                    put_ok, put_err, buf:len(), string.len(s), concat_ok, concat_err
+            "#[..],
+        )
+        .unwrap();
+    let too_large = OwnedValue::String(b"resulting string too large".to_vec());
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::Integer(10),
+            too_large.clone(),
+            OwnedValue::Boolean(false),
+            too_large,
+            OwnedValue::Integer(6),
+            OwnedValue::Integer(8),
+            OwnedValue::Boolean(false),
+            OwnedValue::String(b"string error: resulting string too large".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn max_string_len_in_executor_tasks() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &br#"
+                    local s = "aaaaa"
+                    concat = pcall(function() return s .. s end)
+                    rep = pcall(string.rep, s, 2)
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.executor.spawn(mc, Function::Closure(closure));
+        // The limit is shared, so it applies to tasks that were spawned before it was changed.
+        root.main_thread.set_max_string_len(mc, 8);
+        assert!(root.executor.run(mc, 100).is_empty());
+        for name in &["concat", "rep"] {
+            assert_eq!(
+                root.globals.get(String::new(mc, name.as_bytes())),
+                Value::Boolean(false),
+                "{}",
+                name
+            );
+        }
+    });
+}
+
+#[test]
+fn builder_string_coercion() {
+    let mut lua = Lua::new();
+    let values = lua
+        .run_string(&br#"return "1" + 1, 1 .. "", math.abs("-2"), string.len(12)"#[..])
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::Number(2.0),
+            OwnedValue::String(b"1".to_vec()),
+            OwnedValue::Number(2.0),
+            OwnedValue::Integer(2),
+        ]
+    );
+
+    let mut lua = Lua::builder().string_coercion(false).build();
+    for source in &[
+        "return '1' + 1",
+        "return 2 * '3'",
+        "local s = '5' return -s",
+        "return 1 .. ''",
+        "return math.abs('-2')",
+        "return string.len(12)",
+        "return string.sub('abc', '2')",
+        "for i = '1', 2 do end",
+        "for i = 1, '2' do end",
+        "for i = 1, 2, '1' do end",
+        "local co = coroutine.create(function() return '1' // 1 end) \
+         local ok = coroutine.resume(co) if not ok then error('failed') end",
+    ] {
+        assert!(lua.run_string(source.as_bytes()).is_err(), "{}", source);
+    }
+    let values = lua
+        .run_string(&br#"return 1 + 2.5, "a" .. "b", math.tointeger("3"), ("x"):rep(2)"#[..])
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::Number(3.5),
+            OwnedValue::String(b"ab".to_vec()),
+            OwnedValue::Nil,
+            OwnedValue::String(b"xx".to_vec()),
+        ]
+    );
+
+    lua.mutate(|mc, root| {
+        assert!(!root.main_thread.string_coercion());
+        root.main_thread.set_string_coercion(mc, true);
+    });
+    assert!(lua.run_string(&b"return '1' + string.len(1)"[..]).is_ok());
+}
+
+#[test]
+fn string_coercion_in_executor_tasks() {
+    let mut lua = Lua::builder().string_coercion(false).build();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &b"ok = pcall(function() return '1' + 1 end)"[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.executor.spawn(mc, Function::Closure(closure));
+        assert!(root.executor.run(mc, 100).is_empty());
+        assert_eq!(
+            root.globals.get(String::new_static(b"ok")),
+            Value::Boolean(false)
+        );
+    });
+}
+
+#[test]
+fn string_coercion_shared_with_coroutines() {
+    let mut lua = Lua::new();
+    lua.run_string(
+        &br#"
+            co = coroutine.create(function()
+                while true do
+                    local vm = pcall(function() return "1" + 1 end)
+                    local math = pcall(math.abs, "-2")
+                    coroutine.yield(vm, math)
+                end
+            end)
+        "#[..],
+    )
+    .unwrap();
+    let resume = |lua: &mut Lua| lua.run_string(b"return coroutine.resume(co)").unwrap();
+    let results = |vm, math| {
+        vec![
+            OwnedValue::Boolean(true),
+            OwnedValue::Boolean(vm),
+            OwnedValue::Boolean(math),
+        ]
+    };
+    assert_eq!(resume(&mut lua), results(true, true));
+
+    // The coroutine follows the main thread, in the VM and the `math` library alike.
+    lua.mutate(|mc, root| root.main_thread.set_string_coercion(mc, false));
+    assert_eq!(resume(&mut lua), results(false, false));
+
+    // And the main thread follows the coroutine.
+    lua.mutate(
+        |mc, root| match root.globals.get(String::new_static(b"co")) {
+            Value::Thread(co) => co.set_string_coercion(mc, true),
+            co => panic!("unexpected coroutine {:?}", co),
+        },
+    );
+    assert_eq!(
+        lua.run_string(b"return '1' + 1, math.abs('-2')").unwrap(),
+        vec![OwnedValue::Number(2.0), OwnedValue::Number(2.0)]
+    );
+    assert_eq!(resume(&mut lua), results(true, true));
+}
+
+#[test]
+fn builder_char_classes() {
+    let source: &[u8] =
+        br#"return ("caf\233"):match("%a+"), ("x\215y"):find("%p"), ("\201t\233"):match("%u%l+")"#;
+    let mut lua = Lua::new();
+    assert_eq!(
+        lua.run_string(source).unwrap(),
+        vec![
+            OwnedValue::String(b"caf".to_vec()),
+            OwnedValue::Nil,
+            OwnedValue::Nil,
+        ]
+    );
+
+    let mut lua = Lua::builder().char_classes(CharClasses::latin1()).build();
+    assert_eq!(
+        lua.run_string(source).unwrap(),
+        vec![
+            OwnedValue::String(b"caf\xe9".to_vec()),
+            OwnedValue::Integer(2),
+            OwnedValue::String(b"\xc9t\xe9".to_vec()),
+        ]
+    );
+
+    let classes = CharClasses::default().extend(CharClass::Lower, 0x80..=0xff);
+    assert!(classes.contains(CharClass::Lower, 0xe9));
+    assert!(!classes.contains(CharClass::Upper, 0xe9));
+    lua.mutate(|mc, root| root.main_thread.set_char_classes(mc, classes));
+    let values = lua
+        .run_string(&br#"return ("\201t\233"):match("%l+")"#[..])
+        .unwrap();
+    assert_eq!(values, vec![OwnedValue::String(b"\xc9t\xe9".to_vec())]);
+}
+
+#[test]
+fn builder_limits() {
+    let mut lua = Lua::builder().instruction_budget(10_000).build();
+    assert_eq!(
+        lua.run_string(b"local n = 0 for i = 1, 100 do n = n + i end return n")
+            .unwrap(),
+        vec![OwnedValue::Integer(5050)]
+    );
+    // The budget cannot be caught by the script.
+    let err = lua
+        .run_string(b"return pcall(function() while true do end end)")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "runtime error: instruction budget exceeded"
+    );
+    // The stopped chunk is discarded, and each chunk gets the whole budget.
+    for _ in 0..3 {
+        assert_eq!(
+            lua.run_string(b"local n = 0 for i = 1, 1000 do n = n + 1 end return n")
+                .unwrap(),
+            vec![OwnedValue::Integer(1000)]
+        );
+    }
+
+    let mut lua = Lua::builder().memory_limit(1 << 20).build();
+    // Garbage does not count towards the limit.
+    assert_eq!(
+        lua.run_string(b"for i = 1, 100000 do local t = {i} end return true")
+            .unwrap(),
+        vec![OwnedValue::Boolean(true)]
+    );
+    let err = lua
+        .run_string(b"local t = {} for i = 1, 1000000 do t[i] = {} end")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "runtime error: memory limit exceeded");
+    lua.collect_all();
+    assert!(lua.total_allocated() < 1 << 20);
+    assert_eq!(
+        lua.run_string(b"return 1").unwrap(),
+        vec![OwnedValue::Integer(1)]
+    );
+}
+
+#[test]
+fn builder_dialect() {
+    let source: &[u8] = b"local function add(a: number, b: number): number return a + b end \
+                          return add(1, 2)";
+    assert!(Lua::new().run_string(source).is_err());
+
+    let mut lua = Lua::builder().dialect(Dialect::Typed).build();
+    assert_eq!(
+        lua.run_string(source).unwrap(),
+        vec![OwnedValue::Integer(3)]
+    );
+}
@@ -0,0 +1,270 @@
+use std::fs::{read_dir, File};
+
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, compile_named, dump_compiled, dump_proto, dump_stripped_proto, io, load_compiled,
+    load_file, load_proto, verify, BytecodeError, ChunkName, Closure, ConstantIndex16, Error,
+    Function, Lua, OpCode, Opt254, RegisterIndex, ThreadSequence, Value, VarCount, VerifyError,
+};
+
+#[test]
+fn suite_round_trips() {
+    for entry in read_dir("./tests/running").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "lua") {
+            continue;
+        }
+
+        let file = io::buffered_read(File::open(&path).unwrap()).unwrap();
+        let mut lua = Lua::new();
+        let r = lua.sequence(|root| {
+            sequence::from_fn_with(root, move |mc, root| {
+                let proto = compile(mc, root.interned_strings, file)?;
+                verify(&proto).expect("compiled prototypes always verify");
+                let bytes = dump_proto(&proto);
+                let loaded = load_proto(mc, root.interned_strings, &bytes).unwrap();
+                assert_eq!(dump_proto(&loaded), bytes);
+                Ok(Closure::new(mc, loaded, Some(root.globals))?)
+            })
+            .and_chain_with(root, move |mc, root, closure| {
+                Ok(ThreadSequence::call_function(
+                    mc,
+                    root.main_thread,
+                    Function::Closure(closure),
+                    &[],
+                )?)
+            })
+            .map_ok(|r| r == [Value::Boolean(true)])
+            .map_err(Error::to_static)
+            .boxed()
+        });
+        assert!(r.unwrap(), "{:?} failed after loading", path);
+    }
+}
+
+#[test]
+fn rejects_bad_bytecode() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = compile(
+            mc,
+            root.interned_strings,
+            &b"local a, b = 1, 2 return function() return a + b end"[..],
+        )
+        .unwrap();
+        let bytes = dump_proto(&proto);
+
+        assert_eq!(
+            load_proto(mc, root.interned_strings, b"return 1").unwrap_err(),
+            BytecodeError::BadHeader
+        );
+        assert_eq!(
+            load_proto(mc, root.interned_strings, &bytes[..bytes.len() - 1]).unwrap_err(),
+            BytecodeError::Truncated
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            load_proto(mc, root.interned_strings, &trailing).unwrap_err(),
+            BytecodeError::TrailingData
+        );
+
+        let mut bad = compile(mc, root.interned_strings, &b"local a = 1"[..]).unwrap();
+        let stack_size = bad.stack_size as u8;
+        bad.opcodes.insert(
+            0,
+            OpCode::Move {
+                dest: RegisterIndex(stack_size),
+                source: RegisterIndex(0),
+            },
+        );
+        assert_eq!(
+            load_proto(mc, root.interned_strings, &dump_proto(&bad)).unwrap_err(),
+            BytecodeError::Invalid(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+
+        let mut bad = compile(mc, root.interned_strings, &b"local a = 1"[..]).unwrap();
+        bad.opcodes.pop();
+        assert_eq!(verify(&bad), Err(VerifyError::MissingReturn));
+
+        let mut bad = compile(mc, root.interned_strings, &b""[..]).unwrap();
+        bad.opcodes.insert(
+            0,
+            OpCode::Jump {
+                offset: 10,
+                close_upvalues: Opt254::none(),
+            },
+        );
+        assert_eq!(verify(&bad), Err(VerifyError::JumpOutOfRange { pc: 0 }));
+    });
+}
+
+#[test]
+fn rejects_bad_register_ranges() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let source = &b"local a, b, c = 1, 2, 3 print(a .. b .. c)"[..];
+        let proto = compile(mc, root.interned_strings, source).unwrap();
+        let stack_size = proto.stack_size as u8;
+        let with_first = |opcode| {
+            let mut bad = compile(mc, root.interned_strings, source).unwrap();
+            bad.opcodes.insert(0, opcode);
+            // Loading goes through the same checks.
+            assert_eq!(
+                load_proto(mc, root.interned_strings, &dump_proto(&bad)).unwrap_err(),
+                BytecodeError::Invalid(VerifyError::RegisterOutOfRange { pc: 0 })
+            );
+            verify(&bad)
+        };
+
+        // A range ending one past the last register is fine, one further is not.
+        let mut good = compile(mc, root.interned_strings, source).unwrap();
+        good.opcodes.insert(
+            0,
+            OpCode::LoadNil {
+                dest: RegisterIndex(0),
+                count: stack_size,
+            },
+        );
+        assert_eq!(verify(&good), Ok(()));
+        assert_eq!(
+            with_first(OpCode::LoadNil {
+                dest: RegisterIndex(1),
+                count: stack_size,
+            }),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+        // The end of this range does not fit in a byte.
+        assert_eq!(
+            with_first(OpCode::LoadNil {
+                dest: RegisterIndex(250),
+                count: 10,
+            }),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+        assert_eq!(
+            with_first(OpCode::Concat {
+                dest: RegisterIndex(0),
+                source: RegisterIndex(stack_size - 1),
+                count: 2,
+            }),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+        // The function and its arguments must all be in the frame.
+        assert_eq!(
+            with_first(OpCode::Call {
+                func: RegisterIndex(stack_size - 1),
+                args: VarCount::constant(1),
+                returns: VarCount::constant(0),
+            }),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+        assert_eq!(
+            with_first(OpCode::Call {
+                func: RegisterIndex(stack_size),
+                args: VarCount::variable(),
+                returns: VarCount::variable(),
+            }),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+
+        let mut bad = compile(mc, root.interned_strings, source).unwrap();
+        bad.stack_size = 257;
+        assert_eq!(verify(&bad), Err(VerifyError::StackTooLarge));
+        assert_eq!(
+            load_proto(mc, root.interned_strings, &dump_proto(&bad)).unwrap_err(),
+            BytecodeError::Invalid(VerifyError::StackTooLarge)
+        );
+    });
+}
+
+#[test]
+fn checked_indexes() {
+    assert_eq!(RegisterIndex::new(255), Some(RegisterIndex(255)));
+    assert_eq!(RegisterIndex::new(256), None);
+    assert_eq!(
+        ConstantIndex16::new(65535).map(|c| c.to_usize()),
+        Some(65535)
+    );
+    assert_eq!(ConstantIndex16::new(65536), None);
+    assert_eq!(VarCount::try_new(Some(3)), Some(VarCount::constant(3)));
+    assert_eq!(VarCount::try_new(None), Some(VarCount::variable()));
+    assert_eq!(VarCount::try_new(Some(255)), None);
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let mut proto = compile(mc, root.interned_strings, &b"local f = print f()"[..]).unwrap();
+        let top = RegisterIndex::new(proto.stack_size as usize - 1).unwrap();
+        proto.opcodes.insert(
+            0,
+            OpCode::Call {
+                func: top,
+                args: VarCount::constant(0),
+                returns: VarCount::constant(2),
+            },
+        );
+        assert_eq!(
+            verify(&proto),
+            Err(VerifyError::RegisterOutOfRange { pc: 0 })
+        );
+    });
+}
+
+#[test]
+fn compiled_files() {
+    let dir = std::env::temp_dir().join(format!("luster-compiled-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("script.lua");
+    std::fs::write(&path, "local a = 1\nreturn a + 1").unwrap();
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let source = &b"local a = 1\nreturn a + 1"[..];
+        let proto = compile(mc, root.interned_strings, source).unwrap();
+        let compiled = dump_compiled(&proto, source, false);
+        let loaded = load_compiled(mc, root.interned_strings, &compiled, source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(dump_proto(&loaded), dump_proto(&proto));
+        assert!(
+            load_compiled(mc, root.interned_strings, &compiled, b"return 3")
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            load_compiled(mc, root.interned_strings, &dump_proto(&proto), source).unwrap_err(),
+            BytecodeError::BadHeader
+        );
+
+        let stripped = load_proto(mc, root.interned_strings, &dump_stripped_proto(&proto)).unwrap();
+        assert!(stripped.opcode_lines.is_empty());
+        assert_eq!(stripped.opcodes, proto.opcodes);
+
+        // The `.lusterc` file is preferred while the source is unchanged, which a different
+        // program standing in for it makes visible.
+        let other = compile(mc, root.interned_strings, &b"return 'compiled'"[..]).unwrap();
+        std::fs::write(
+            path.with_extension("lusterc"),
+            dump_compiled(&other, source, false),
+        )
+        .unwrap();
+        let loaded = load_file(mc, root.interned_strings, &path).unwrap();
+        assert_eq!(dump_proto(&loaded), dump_proto(&other));
+
+        std::fs::write(&path, "return 'changed'").unwrap();
+        let loaded = load_file(mc, root.interned_strings, &path).unwrap();
+        // Compiling the source names the chunk after the file.
+        let chunk_name = ChunkName::file(&path.to_string_lossy());
+        assert_eq!(loaded.chunk_name, Some(chunk_name.clone()));
+        let changed = compile_named(
+            mc,
+            root.interned_strings,
+            chunk_name,
+            &b"return 'changed'"[..],
+        )
+        .unwrap();
+        assert_eq!(dump_proto(&loaded), dump_proto(&changed));
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
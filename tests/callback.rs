@@ -1,7 +1,7 @@
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 use luster::{
-    compile, Callback, CallbackResult, Closure, Error, Function, Lua, StaticError, String,
-    ThreadSequence, Value,
+    compile, Callback, CallbackResult, Closure, Continuation, Error, Function, Lua, OwnedValue,
+    RuntimeError, StaticError, String, Thread, ThreadSequence, Value,
 };
 
 #[test]
@@ -95,3 +95,199 @@ fn tail_call_trivial_callback() -> Result<(), Box<StaticError>> {
 
     Ok(())
 }
+
+#[test]
+fn iterator_callback() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        // Every item is converted when the loop asks for it, so an endless iterator is fine.
+        let squares = Callback::new_iterator(mc, (1..).map(|i: i64| i * i), |mc, i| {
+            vec![
+                Value::Integer(i),
+                Value::String(String::new(mc, i.to_string().as_bytes())),
+            ]
+        });
+        root.globals
+            .set(mc, String::new_static(b"squares"), squares)
+            .unwrap();
+        let words = Callback::new_iterator(mc, vec!["a", "b"].into_iter(), |mc, w| {
+            vec![Value::String(String::new(mc, w.as_bytes()))]
+        });
+        root.globals
+            .set(mc, String::new_static(b"words"), words)
+            .unwrap();
+    });
+
+    let results = lua.run_string(
+        &br#"
+            local sum, text = 0, ""
+            for i, s in squares do
+                if i > 20 then break end
+                sum = sum + i
+                text = text .. s .. ","
+            end
+            local count = 0
+            for w in words do count = count + 1 end
+            local after = words()
+            return sum, text, count, after
+        "#[..],
+    )?;
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::Integer(30),
+            OwnedValue::String(b"1,4,9,16,".to_vec()),
+            OwnedValue::Integer(2),
+            OwnedValue::Nil,
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn compile_in_callback() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            // Evaluates an expression, compiling it while the calling script is running.
+            let eval = Callback::new_sequence_with(
+                mc,
+                (root.interned_strings, root.globals),
+                |&(interned_strings, globals), args| {
+                    let source = match args.get(0) {
+                        Some(Value::String(source)) => *source,
+                        _ => return Err(RuntimeError(Value::Nil).into()),
+                    };
+                    Ok(sequence::from_fn_with(
+                        (interned_strings, globals, source),
+                        |mc, (interned_strings, globals, source)| {
+                            let mut expression = b"return ".to_vec();
+                            expression.extend_from_slice(source.as_bytes());
+                            let proto = compile(mc, interned_strings, &expression[..])?;
+                            Ok(CallbackResult::TailCall {
+                                function: Function::Closure(Closure::new(
+                                    mc,
+                                    proto,
+                                    Some(globals),
+                                )?),
+                                args: Vec::new(),
+                                continuation: Continuation::new_immediate(|res| {
+                                    Ok(CallbackResult::Return(res?))
+                                }),
+                            })
+                        },
+                    ))
+                },
+            );
+            root.globals.set(mc, String::new_static(b"eval"), eval)?;
+            Ok(())
+        })
+        .and_then_with(root, |mc, root, _| {
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &br#"
+                        local x = 20
+                        y = 2
+                        local a = eval("y * 21")
+                        local b = eval("eval('y') + 1")
+                        return a == 42 and b == 3 and not pcall(eval, "+")
+                    "#[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|b| assert_eq!(b, vec![Value::Boolean(true)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn yield_boundaries() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            // Runs a function to completion on a new thread that cannot yield.
+            let callback = Callback::new_sequence(mc, |args| {
+                let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Function(function) => function,
+                    _ => panic!("expected function"),
+                };
+                Ok(sequence::from_fn_with(function, |mc, function| {
+                    Ok(ThreadSequence::call_function(
+                        mc,
+                        Thread::new(mc, false),
+                        function,
+                        &[],
+                    )?)
+                })
+                .flatten_ok()
+                .map_ok(CallbackResult::Return))
+            });
+            root.globals.set(mc, String::new_static(b"run"), callback)?;
+            Ok(())
+        })
+        .and_then_with(root, |mc, root, _| {
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &br#"
+                        local y1 = coroutine.isyieldable()
+                        local ok1, err1 = pcall(coroutine.yield, 1)
+
+                        local y2, y3
+                        local co = coroutine.create(function()
+                            y2 = coroutine.isyieldable()
+                            coroutine.yield()
+                            run(function()
+                                y3 = coroutine.isyieldable()
+                                coroutine.yield()
+                            end)
+                        end)
+                        local ok2 = coroutine.resume(co)
+                        local ok3, err3 = coroutine.resume(co)
+
+                        return
+                            y1 == false and ok1 == false and
+                            err1 == "thread error: attempt to yield from outside a coroutine" and
+                            y2 == true and ok2 == true and
+                            y3 == false and ok3 == false and
+                            err3 == "thread error: attempt to yield across a callback boundary" and
+                            coroutine.status(co) == "dead" and
+                            coroutine.isyieldable() == false
+                    "#[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|b| assert_eq!(b, vec![Value::Boolean(true)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "compat")]
+
+use luster::{Lua, Value};
+
+#[test]
+fn compat_context() {
+    let mut lua = Lua::new();
+
+    lua.context(|ctx| {
+        let globals = ctx.globals();
+        globals.set(ctx.create_string(b"x"), 5).unwrap();
+
+        ctx.load(
+            r#"
+                function add(a, b)
+                    return a + b
+                end
+                y = x * 2
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        assert!(matches!(
+            globals.get(ctx.create_string(b"y")),
+            Value::Integer(10)
+        ));
+    });
+
+    let sum = lua.context(|ctx| {
+        let add = match ctx.globals().get(ctx.create_string(b"add")) {
+            Value::Function(add) => luster::compat::Function::new(ctx, add),
+            _ => panic!("add is not a function"),
+        };
+        match add.call(&[Value::Integer(1), Value::Integer(2)]).unwrap()[..] {
+            [Value::Integer(i)] => i,
+            _ => panic!("wrong return values"),
+        }
+    });
+    assert_eq!(sum, 3);
+
+    lua.context(|ctx| {
+        let values = ctx.load("return 1, 'two', {}").eval().unwrap();
+        assert_eq!(values.len(), 3);
+        assert!(ctx.load("error('oops')").exec().is_err());
+    });
+}
+
+#[test]
+fn compat_thread_settings() {
+    let mut lua = Lua::builder().instruction_budget(10_000).build();
+    lua.context(|ctx| {
+        let values = ctx.load("return ('ab'):upper()").eval().unwrap();
+        assert!(matches!(values[..], [Value::String(s)] if s.as_bytes() == b"AB"));
+
+        let err = ctx.load("while true do end").exec().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runtime error: instruction budget exceeded"
+        );
+    });
+}
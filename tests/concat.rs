@@ -0,0 +1,50 @@
+use luster::{compile, CompilerError, Error, Lua, OwnedValue, StaticError};
+
+fn locals(count: usize) -> String {
+    let names = (0..count).map(|i| format!("a{}", i)).collect::<Vec<_>>();
+    format!("local {}\n", names.join(", "))
+}
+
+#[test]
+fn concat_evaluation_order() -> Result<(), Box<StaticError>> {
+    // Enough operands to run out of registers, so some are concatenated early.  Every tenth
+    // operand is a call, which records the order the operands are evaluated in.
+    let group = format!("(f(){})", " .. '-'".repeat(9));
+    let source = format!(
+        "local n = 0
+        local function f() n = n + 1 return n end
+        local s = {}
+        return string.len(s), string.sub(s, 1, 12), string.sub(s, -12)",
+        vec![group; 30].join(" .. ")
+    );
+    let mut lua = Lua::new();
+    assert_eq!(
+        lua.run_string(source.as_bytes())?,
+        vec![
+            OwnedValue::Integer(321),
+            OwnedValue::String(b"1---------2-".to_vec()),
+            OwnedValue::String(b"-30---------".to_vec()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn concat_in_last_registers() {
+    let mut lua = Lua::new();
+    // The operands fill the last two registers.
+    let source = format!("{}a0 = 'x' .. 'y' return a0", locals(254));
+    assert_eq!(
+        lua.run_string(source.as_bytes()).unwrap(),
+        vec![OwnedValue::String(b"xy".to_vec())]
+    );
+
+    // There is only room for the first operand.
+    let source = format!("{}a0 = 'x' .. 'y'", locals(255));
+    lua.mutate(|mc, root| {
+        assert!(matches!(
+            compile(mc, root.interned_strings, source.as_bytes()),
+            Err(Error::CompilerError(CompilerError::Registers))
+        ));
+    });
+}
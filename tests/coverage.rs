@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use gc_arena::Gc;
+use luster::{
+    compile, lcov_record, Closure, Function, LineCoverage, LineNumber, Lua, ThreadMode, Value,
+};
+
+const SCRIPT: &[u8] = br#"local function pick(x)
+    if x then
+        return 1
+    else
+        return 2
+    end
+end
+local co = coroutine.create(function()
+    coroutine.yield(pick(true))
+end)
+local _, v = coroutine.resume(co)
+return v
+"#;
+
+#[test]
+fn line_coverage() {
+    let mut lua = Lua::new();
+    let lines = lua.mutate(|mc, root| {
+        let proto = Gc::allocate(mc, compile(mc, root.interned_strings, SCRIPT).unwrap());
+        let coverage = LineCoverage::new(mc);
+        coverage.track(mc, proto);
+        assert!(coverage.lines().values().all(|&count| count == 0));
+
+        let closure = Closure::new_with_proto(mc, proto, Some(root.globals)).unwrap();
+        root.main_thread.set_line_coverage(mc, Some(coverage));
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+        while root.main_thread.mode() == ThreadMode::Running {
+            root.main_thread.step(mc).unwrap();
+        }
+        assert_eq!(
+            root.main_thread.take_results(mc).unwrap().unwrap(),
+            vec![Value::Integer(1)]
+        );
+        coverage.lines()
+    });
+
+    let covered = |line| lines.get(&LineNumber(line)).map(|&count| count > 0);
+    // Lines 3 and 9 only run inside the coroutine
+    for line in &[1, 2, 3, 8, 9, 11, 12] {
+        assert_eq!(covered(*line), Some(true), "line {}", line);
+    }
+    assert_eq!(covered(5), Some(false));
+    assert_eq!(covered(4), None);
+}
+
+#[test]
+fn lcov_format() {
+    let mut lines = BTreeMap::new();
+    lines.insert(LineNumber(1), 2);
+    lines.insert(LineNumber(3), 0);
+    assert_eq!(
+        lcov_record("a_test.lua", &lines),
+        "SF:a_test.lua\nDA:1,2\nDA:3,0\nLF:2\nLH:1\nend_of_record\n"
+    );
+}
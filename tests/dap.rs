@@ -0,0 +1,205 @@
+#![cfg(feature = "dap")]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value as Json};
+
+use luster::dap::{DapServer, DapStatus};
+use luster::{compile_named, ChunkName, Closure, Function, Lua, Thread};
+
+struct TestClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    seq: i64,
+}
+
+impl TestClient {
+    fn request(&mut self, command: &str, arguments: Json) {
+        let content = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        self.seq += 1;
+        write!(
+            self.stream,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )
+        .unwrap();
+    }
+
+    fn try_read(&mut self) -> Option<Json> {
+        let mut buf = [0; 4096];
+        if let Ok(n) = self.stream.read(&mut buf) {
+            self.buffer.extend_from_slice(&buf[..n]);
+        }
+        let header_end = self.buffer.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let header = std::str::from_utf8(&self.buffer[..header_end]).unwrap();
+        let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+        let end = header_end + 4 + length;
+        if self.buffer.len() < end {
+            return None;
+        }
+        let message = serde_json::from_slice(&self.buffer[header_end + 4..end]).unwrap();
+        self.buffer.drain(..end);
+        Some(message)
+    }
+}
+
+#[test]
+fn dap_breakpoint() {
+    let mut lua = Lua::new();
+    let mut server = DapServer::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut client = TestClient {
+        stream,
+        buffer: Vec::new(),
+        seq: 1,
+    };
+
+    client.request("initialize", json!({ "adapterID": "luster" }));
+    client.request("attach", json!({ "program": "test.lua" }));
+    client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": "test.lua" }, "breakpoints": [{ "line": 3 }] }),
+    );
+    client.request("configurationDone", json!({}));
+
+    let mut messages = Vec::new();
+    let mut stack_requested = false;
+    let start = Instant::now();
+    let finished = lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile_named(
+                mc,
+                root.interned_strings,
+                ChunkName::file("test.lua"),
+                &b"local a = 1\nlocal b = 2\nlocal c = a + b\nreturn c"[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.start(mc, Function::Closure(closure), &[]).unwrap();
+
+        while start.elapsed() < Duration::from_secs(10) {
+            if server.poll(mc, thread).unwrap() == DapStatus::Finished {
+                while let Some(message) = client.try_read() {
+                    messages.push(message);
+                }
+                return true;
+            }
+            while let Some(message) = client.try_read() {
+                if message["event"] == "stopped" {
+                    client.request("stackTrace", json!({ "threadId": 1 }));
+                    client.request("variables", json!({ "variablesReference": 1 }));
+                    stack_requested = true;
+                } else if message["command"] == "variables" {
+                    client.request("continue", json!({ "threadId": 1 }));
+                }
+                messages.push(message);
+            }
+        }
+        false
+    });
+
+    assert!(finished && stack_requested);
+
+    let stopped = messages.iter().find(|m| m["event"] == "stopped").unwrap();
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+    let stack = messages
+        .iter()
+        .find(|m| m["command"] == "stackTrace")
+        .unwrap();
+    assert_eq!(stack["body"]["stackFrames"][0]["line"], 3);
+    assert_eq!(
+        stack["body"]["stackFrames"][0]["source"]["path"],
+        "test.lua"
+    );
+
+    let variables = messages
+        .iter()
+        .find(|m| m["command"] == "variables")
+        .unwrap();
+    // `c` is not in scope until line 3 has run.
+    let variables = variables["body"]["variables"].as_array().unwrap();
+    assert_eq!(variables.len(), 2);
+    assert_eq!(variables[0]["name"], "a");
+    assert_eq!(variables[0]["value"], "1");
+    assert_eq!(variables[1]["name"], "b");
+    assert_eq!(variables[1]["value"], "2");
+
+    assert!(messages.iter().any(|m| m["event"] == "terminated"));
+}
+
+#[test]
+fn dap_malformed_messages() {
+    let mut lua = Lua::new();
+    let mut server = DapServer::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut client = TestClient {
+        stream,
+        buffer: Vec::new(),
+        seq: 1,
+    };
+
+    // A message that is not JSON is skipped, and the requests after it are still handled.
+    client
+        .stream
+        .write_all(b"Content-Length: 5\r\n\r\nnope!")
+        .unwrap();
+    client.request("initialize", json!({ "adapterID": "luster" }));
+
+    let start = Instant::now();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile_named(
+                mc,
+                root.interned_strings,
+                ChunkName::file("test.lua"),
+                &b"return 1"[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.start(mc, Function::Closure(closure), &[]).unwrap();
+
+        let mut initialized = false;
+        while !initialized && start.elapsed() < Duration::from_secs(10) {
+            assert_eq!(server.poll(mc, thread).unwrap(), DapStatus::Waiting);
+            while let Some(message) = client.try_read() {
+                if message["command"] == "initialize" {
+                    assert_eq!(message["success"], true);
+                    initialized = true;
+                }
+            }
+        }
+        assert!(initialized);
+
+        // A header without a content length loses track of where messages start, so the client
+        // is detached and the thread runs on.
+        client.stream.write_all(b"nonsense\r\n\r\n").unwrap();
+        while server.is_attached() && start.elapsed() < Duration::from_secs(10) {
+            server.poll(mc, thread).unwrap();
+        }
+        assert!(!server.is_attached());
+    });
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use luster::{
+    load_data_table, values_deep_equal, Closure, DataError, Function, Lua, String, ThreadMode,
+    Value,
+};
+
+const DATA: &str = r#"
+    return {
+        "first",
+        "second",
+        nil,
+        -4,
+        name = "items",
+        ratio = -0.5,
+        enabled = true,
+        [10] = false,
+        ["with space"] = ("grouped"),
+        nested = { { id = 1 }, { id = 2, tags = { "a", "b" } } },
+        [1] = "overwritten first",
+    }
+"#;
+
+#[test]
+fn matches_vm() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let loaded = load_data_table(mc, root.interned_strings, DATA.as_bytes()).unwrap();
+
+        let closure = Closure::new(
+            mc,
+            luster::compile(mc, root.interned_strings, DATA.as_bytes()).unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+        while root.main_thread.mode() == ThreadMode::Running {
+            root.main_thread.step(mc).unwrap();
+        }
+        let expected = root.main_thread.take_results(mc).unwrap().unwrap();
+
+        assert!(values_deep_equal(Value::Table(loaded), expected[0]));
+        assert_eq!(
+            loaded.get(1),
+            Value::String(String::new_static(b"overwritten first"))
+        );
+        assert_eq!(loaded.get(3), Value::Nil);
+    });
+}
+
+#[test]
+fn large_table() {
+    let mut source = "return {\n".to_owned();
+    for i in 0..20_000 {
+        writeln!(
+            source,
+            "  {{ id = {}, name = \"item{}\", weight = {}.5 }},",
+            i, i, i
+        )
+        .unwrap();
+    }
+    source.push('}');
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let table = load_data_table(mc, root.interned_strings, source.as_bytes()).unwrap();
+        assert_eq!(table.length(), 20_000);
+        match table.get(20_000) {
+            Value::Table(last) => {
+                assert_eq!(last.get(String::new_static(b"id")), Value::Integer(19_999));
+                assert_eq!(
+                    last.get(String::new_static(b"weight")),
+                    Value::Number(19_999.5)
+                );
+            }
+            v => panic!("expected a table, found {:?}", v),
+        }
+    });
+}
+
+#[test]
+fn rejects_non_literals() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let load = |source: &str| {
+            load_data_table(mc, root.interned_strings, source.as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            load("local x = 1 return { x }"),
+            DataError::NotATable.to_string()
+        );
+        assert_eq!(load("return 1"), DataError::NotATable.to_string());
+        assert_eq!(load("return {}, {}"), DataError::NotATable.to_string());
+        assert_eq!(
+            load("return { x }"),
+            DataError::NotLiteral("variable or call").to_string()
+        );
+        assert_eq!(
+            load("return { 1 + 2 }"),
+            DataError::NotLiteral("binary operator").to_string()
+        );
+        assert_eq!(
+            load("return { f = function() end }"),
+            DataError::NotLiteral("function").to_string()
+        );
+        assert_eq!(
+            load("return { [0/0] = 1 }"),
+            DataError::NotLiteral("binary operator").to_string()
+        );
+        assert!(load("return { [nil] = 1 }").starts_with("invalid table key"));
+    });
+}
@@ -0,0 +1,265 @@
+use gc_arena::MutationContext;
+use luster::{
+    compile_named, ChunkName, Closure, DebugStep, Debugger, Function, LineNumber, Lua, PauseReason,
+    Paused, Root, StepCommand, Thread, Value,
+};
+
+const SCRIPT: &[u8] = br#"
+local function add(a, b)
+    local sum = a + b
+    return sum
+end
+local x = 1
+local y = add(x, 2)
+local z = add(y, 3)
+return z
+"#;
+
+fn start<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>) -> Thread<'gc> {
+    let closure = Closure::new(
+        mc,
+        compile_named(
+            mc,
+            root.interned_strings,
+            ChunkName::file("script.lua"),
+            SCRIPT,
+        )
+        .unwrap(),
+        Some(root.globals),
+    )
+    .unwrap();
+    let thread = Thread::new(mc, false);
+    thread.start(mc, Function::Closure(closure), &[]).unwrap();
+    thread
+}
+
+fn next_pause<'gc>(
+    mc: MutationContext<'gc, '_>,
+    debugger: &mut Debugger,
+    thread: Thread<'gc>,
+) -> Option<Paused<'gc>> {
+    loop {
+        match debugger.step(mc, thread).unwrap() {
+            DebugStep::Running => {}
+            DebugStep::Paused(paused) => return Some(paused),
+            DebugStep::Finished => return None,
+        }
+    }
+}
+
+#[test]
+fn step_into_and_out() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let thread = start(mc, root);
+        let mut debugger = Debugger::new();
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.reason, PauseReason::Step);
+        assert_eq!(paused.line, LineNumber(2));
+
+        let mut lines = Vec::new();
+        for _ in 0..4 {
+            debugger.resume(StepCommand::StepInto);
+            let paused = next_pause(mc, &mut debugger, thread).unwrap();
+            lines.push((paused.line, paused.frames.len()));
+        }
+        assert_eq!(
+            lines,
+            vec![
+                (LineNumber(6), 1),
+                (LineNumber(7), 1),
+                (LineNumber(3), 2),
+                (LineNumber(4), 2),
+            ]
+        );
+
+        debugger.resume(StepCommand::StepOut);
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(8));
+        assert_eq!(paused.frames.len(), 1);
+
+        debugger.resume(StepCommand::StepOver);
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(9));
+
+        debugger.resume(StepCommand::Continue);
+        assert!(next_pause(mc, &mut debugger, thread).is_none());
+        assert_eq!(
+            thread.take_results(mc).unwrap().unwrap(),
+            vec![Value::Integer(6)]
+        );
+    });
+}
+
+#[test]
+fn breakpoints() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let thread = start(mc, root);
+        let script = ChunkName::file("script.lua");
+        let other = ChunkName::file("other.lua");
+        let mut debugger = Debugger::new();
+        assert!(debugger.set_breakpoint(script.clone(), LineNumber(4)));
+        assert!(!debugger.set_breakpoint(script.clone(), LineNumber(4)));
+        // Only the line of the named chunk pauses, not the same line of another chunk.
+        assert!(debugger.set_breakpoint(other.clone(), LineNumber(3)));
+        assert_eq!(
+            debugger.breakpoints().collect::<Vec<_>>(),
+            vec![(&other, LineNumber(3)), (&script, LineNumber(4))]
+        );
+        debugger.resume(StepCommand::Continue);
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.reason, PauseReason::Breakpoint);
+        assert_eq!(paused.line, LineNumber(4));
+        assert_eq!(paused.frames.len(), 2);
+        // a, b and sum are the first three registers of `add`
+        assert_eq!(
+            &paused.current_frame().registers[0..3],
+            &[Value::Integer(1), Value::Integer(2), Value::Integer(3)]
+        );
+        assert_eq!(paused.frame(1).unwrap().line, Some(LineNumber(7)));
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(4));
+        assert_eq!(
+            &paused.current_frame().registers[0..3],
+            &[Value::Integer(3), Value::Integer(3), Value::Integer(6)]
+        );
+
+        assert!(debugger.clear_breakpoint(&script, LineNumber(4)));
+        assert!(!debugger.clear_breakpoint(&script, LineNumber(4)));
+        assert!(next_pause(mc, &mut debugger, thread).is_none());
+    });
+}
+
+#[test]
+fn upvalues() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let chunk = ChunkName::new("=upvalues");
+        let closure = Closure::new(
+            mc,
+            compile_named(
+                mc,
+                root.interned_strings,
+                chunk.clone(),
+                &br#"
+                    local counter = 10
+                    local function inc()
+                        counter = counter + 1
+                    end
+                    inc()
+                    return counter
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.start(mc, Function::Closure(closure), &[]).unwrap();
+
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(chunk, LineNumber(4));
+        debugger.resume(StepCommand::Continue);
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.current_frame().upvalues, vec![Value::Integer(10)]);
+
+        debugger.resume(StepCommand::StepOut);
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(7));
+        assert_eq!(paused.current_frame().registers[0], Value::Integer(11));
+    });
+}
+
+#[test]
+fn locals() {
+    fn locals<'gc>(paused: &Paused<'gc>) -> Vec<(Vec<u8>, Value<'gc>)> {
+        paused
+            .current_frame()
+            .locals()
+            .into_iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value))
+            .collect()
+    }
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let chunk = ChunkName::new("=locals");
+        let closure = Closure::new(
+            mc,
+            compile_named(
+                mc,
+                root.interned_strings,
+                chunk.clone(),
+                &br#"
+                    local x = 1
+                    do
+                        local y = x + 1
+                        x = y
+                    end
+                    for i = 1, 2 do
+                        local z = i * x
+                        x = z
+                    end
+                    return x
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.start(mc, Function::Closure(closure), &[]).unwrap();
+
+        let mut debugger = Debugger::new();
+        for &line in &[5, 9, 11] {
+            debugger.set_breakpoint(chunk.clone(), LineNumber(line));
+        }
+        debugger.resume(StepCommand::Continue);
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(5));
+        assert_eq!(
+            locals(&paused),
+            vec![
+                (b"x".to_vec(), Value::Integer(1)),
+                (b"y".to_vec(), Value::Integer(2)),
+            ]
+        );
+
+        // The loop is entered through its `NumericForLoop` at the end of line 9, before `i` or `z`
+        // are in scope.
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(9));
+        assert_eq!(locals(&paused), vec![(b"x".to_vec(), Value::Integer(2))]);
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(9));
+        assert_eq!(
+            locals(&paused),
+            vec![
+                (b"x".to_vec(), Value::Integer(2)),
+                (b"i".to_vec(), Value::Integer(1)),
+                (b"z".to_vec(), Value::Integer(2)),
+            ]
+        );
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(9));
+        assert_eq!(
+            locals(&paused),
+            vec![
+                (b"x".to_vec(), Value::Integer(2)),
+                (b"i".to_vec(), Value::Integer(2)),
+                (b"z".to_vec(), Value::Integer(4)),
+            ]
+        );
+
+        let paused = next_pause(mc, &mut debugger, thread).unwrap();
+        assert_eq!(paused.line, LineNumber(11));
+        assert_eq!(locals(&paused), vec![(b"x".to_vec(), Value::Integer(4))]);
+    });
+}
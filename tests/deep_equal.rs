@@ -0,0 +1,77 @@
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    assert_deep_eq, compile, values_deep_equal, Closure, Error, Function, Lua, StaticError, String,
+    Table, ThreadSequence, Value,
+};
+
+#[test]
+fn deep_equal_tables() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let a = Table::new(mc);
+        let b = Table::new(mc);
+        assert!(values_deep_equal(Value::Table(a), Value::Table(b)));
+
+        a.set(mc, 1, String::new_static(b"one")).unwrap();
+        assert!(!values_deep_equal(Value::Table(a), Value::Table(b)));
+        b.set(mc, 1, String::new_static(b"one")).unwrap();
+        assert_deep_eq!(Value::Table(a), Value::Table(b));
+
+        a.set(mc, 2, 2.0).unwrap();
+        b.set(mc, 2, 2).unwrap();
+        assert_deep_eq!(Value::Table(a), Value::Table(b));
+
+        a.set(mc, String::new_static(b"self"), a).unwrap();
+        b.set(mc, String::new_static(b"self"), b).unwrap();
+        assert_deep_eq!(Value::Table(a), Value::Table(b));
+
+        let c = Table::new(mc);
+        let d = Table::new(mc);
+        c.set(mc, String::new_static(b"other"), d).unwrap();
+        d.set(mc, String::new_static(b"other"), c).unwrap();
+        assert_deep_eq!(Value::Table(c), Value::Table(d));
+
+        d.set(mc, 1, false).unwrap();
+        assert!(!values_deep_equal(Value::Table(c), Value::Table(d)));
+        assert!(!values_deep_equal(Value::Table(a), Value::Integer(1)));
+    });
+}
+
+#[test]
+fn deep_equal_script_results() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &br#"
+                        local function make()
+                            return {1, 2, {x = "y", z = {}}}
+                        end
+                        return make(), make(), {1, 2, {x = "y"}}
+                    "#[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|values| {
+            assert_deep_eq!(values[0], values[1]);
+            assert!(!values_deep_equal(values[0], values[2]));
+        })
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
-use luster::{compile, Closure, Error, Function, Lua, StaticError, ThreadSequence};
+use luster::{
+    compile, compile_named, CallResult, CallSequence, ChunkName, Closure, Error, ErrorFormat,
+    Function, LineNumber, Lua, OwnedValue, StaticError, ThreadSequence, Traceback, TracebackFrame,
+    Value,
+};
 
 #[test]
 fn error_unwind() -> Result<(), Box<StaticError>> {
@@ -52,3 +56,267 @@ fn error_unwind() -> Result<(), Box<StaticError>> {
 
     Ok(())
 }
+
+// Calls `source` through a `CallSequence`, returning whether it succeeded, the `code` field of a
+// table error value and the traceback.
+fn call_result(
+    source: &'static [u8],
+    capture_tracebacks: bool,
+) -> (bool, Option<i64>, Option<Traceback>) {
+    let mut lua = Lua::new();
+    lua.sequence(move |root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            root.main_thread
+                .set_capture_tracebacks(mc, capture_tracebacks);
+            let closure = Closure::new(
+                mc,
+                compile(mc, root.interned_strings, source).unwrap(),
+                Some(root.globals),
+            )
+            .unwrap();
+            CallSequence::call_function(
+                mc,
+                root.main_thread,
+                root.interned_strings,
+                Function::Closure(closure),
+                &[],
+            )
+            .unwrap()
+        })
+        .flatten()
+        .map(|result| match result {
+            CallResult::Ok(_) => (true, None, None),
+            CallResult::Err { value, traceback } => {
+                let code = match value {
+                    Value::Table(t) => t.get(Value::String(luster::String::new_static(b"code"))),
+                    _ => Value::Nil,
+                };
+                (false, code.to_integer(), traceback)
+            }
+        })
+        .boxed()
+    })
+}
+
+#[test]
+fn call_result_traceback() {
+    let source = &br#"
+        local function inner()
+            error({code = 7})
+        end
+        local function outer()
+            local ok = pcall(inner)
+            inner()
+        end
+        outer()
+    "#[..];
+
+    let (ok, code, traceback) = call_result(source, true);
+    assert!(!ok);
+    assert_eq!(code, Some(7));
+    let frames = traceback.unwrap().frames;
+    let lines = frames.iter().map(|f| f.line).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            Some(LineNumber(3)),
+            Some(LineNumber(7)),
+            Some(LineNumber(9))
+        ]
+    );
+    assert_eq!(
+        frames[0].function_lines.map(|(first, _)| first),
+        Some(LineNumber(3))
+    );
+
+    let (ok, code, traceback) = call_result(source, false);
+    assert!(!ok);
+    assert_eq!(code, Some(7));
+    assert_eq!(traceback, None);
+
+    // Caught errors leave no traceback behind.
+    let (ok, _, traceback) = call_result(
+        &br#"
+            pcall(error, "caught")
+            return pcall(function() local x = nil + 1 end)
+        "#[..],
+        true,
+    );
+    assert!(ok);
+    assert_eq!(traceback, None);
+}
+
+#[test]
+fn traceback_display() {
+    let (_, _, traceback) = call_result(
+        &br#"
+            local function fail()
+                local x = {} + 1
+            end
+            fail()
+        "#[..],
+        true,
+    );
+    assert_eq!(
+        traceback.unwrap().to_string(),
+        "stack traceback:\n\
+         \tline 3 in function starting at line 3\n\
+         \tline 5 in function starting at line 2"
+    );
+}
+
+#[test]
+fn integer_divide_by_zero() {
+    let mut lua = Lua::new();
+    let err = lua
+        .run_string(&b"local n = 7 return n // 0"[..])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "operator error: attempt to perform 'n//0' (n = 7)"
+    );
+    let err = lua
+        .run_string(&b"local n, d = -3, 0 return n % d"[..])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "operator error: attempt to perform 'n%%0' (n = -3)"
+    );
+
+    // The errors are catchable, and float division by zero is still allowed.
+    let values = lua
+        .run_string(
+            &br#"
+                local ok, err = pcall(function() local z = 0 return 5 // z end)
+                return ok, err == "operator error: attempt to perform 'n//0' (n = 5)", 5.0 // 0
+            "#[..],
+        )
+        .unwrap();
+    assert_eq!(values.len(), 3);
+    assert_eq!(values[0], OwnedValue::Boolean(false));
+    assert_eq!(values[1], OwnedValue::Boolean(true));
+    assert_eq!(values[2], OwnedValue::Number(f64::INFINITY));
+}
+
+#[test]
+fn operator_error_types() {
+    let mut lua = Lua::new();
+    for (source, message) in &[
+        ("return {} + 1", "attempt to add a 'table' with a 'number'"),
+        (
+            "local s = 'a' return 1 - s",
+            "attempt to subtract a 'number' with a 'string'",
+        ),
+        (
+            "return nil < 1",
+            "attempt to compare a 'nil' with a 'number'",
+        ),
+        ("local t = {} return -t", "attempt to negate a 'table'"),
+        (
+            "local f = 1.5 return f & 1",
+            "number has no integer representation",
+        ),
+        (
+            "local b = true return ~b",
+            "attempt to bitwise NOT a 'boolean'",
+        ),
+    ] {
+        let err = lua.run_string(source.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), format!("operator error: {}", message));
+    }
+}
+
+#[test]
+fn error_format() {
+    let frame = |line| TracebackFrame {
+        line: Some(LineNumber(line)),
+        function_lines: Some((LineNumber(1), LineNumber(20))),
+        chunk_name: None,
+    };
+    let traceback = Traceback {
+        frames: (1..=5).map(frame).collect(),
+    };
+
+    let full = ErrorFormat::default().traceback(&traceback);
+    assert_eq!(full, traceback.to_string());
+    assert_eq!(full.lines().count(), 6);
+
+    let short = ErrorFormat {
+        max_frames: Some(3),
+        ..ErrorFormat::default()
+    };
+    assert_eq!(
+        short.traceback(&traceback),
+        "stack traceback:\
+         \n\tline 1 in function starting at line 1\
+         \n\tline 2 in function starting at line 1\
+         \n\t...\t(skipping 2 levels)\
+         \n\tline 5 in function starting at line 1"
+    );
+
+    let message = Value::String(luster::String::new_static(b"bad \xff byte"));
+    assert_eq!(
+        ErrorFormat::default().error(message, None),
+        "error: bad \u{fffd} byte"
+    );
+    let escaped = ErrorFormat {
+        escape_invalid_utf8: true,
+        color: true,
+        ..ErrorFormat::default()
+    };
+    assert_eq!(
+        escaped.error(
+            message,
+            Some(&Traceback {
+                frames: vec![frame(7)]
+            })
+        ),
+        "\x1b[1;31merror:\x1b[0m bad \\xff byte\nstack traceback:\
+         \n\t\x1b[36mline 7\x1b[0m in function starting at line 1"
+    );
+}
+
+#[test]
+fn chunk_names() {
+    let displayed = |name: &str| ChunkName::new(name).to_string();
+    assert_eq!(displayed("@scripts/main.lua"), "scripts/main.lua");
+    assert_eq!(displayed("=stdin"), "stdin");
+    assert_eq!(displayed("return 1"), r#"[string "return 1"]"#);
+    assert_eq!(displayed("x = 1\nreturn x"), r#"[string "x = 1..."]"#);
+    let long_file = format!("@{}.lua", "d/".repeat(40));
+    assert_eq!(displayed(&long_file), format!("...{}.lua", "d/".repeat(26)));
+    assert_eq!(
+        ChunkName::from_source("f()\n".repeat(100).as_bytes()).to_string(),
+        r#"[string "f()..."]"#
+    );
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let chunk_name = ChunkName::file("main.lua");
+        let source = &b"local x = 1\nx = = 2"[..];
+        let err = compile_named(mc, root.interned_strings, chunk_name.clone(), source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "parser error: main.lua:2:5: found \"Assign\", expected grouped expression or name"
+        );
+
+        let source = &b"local function f() end\nreturn f"[..];
+        let proto = compile_named(mc, root.interned_strings, chunk_name.clone(), source).unwrap();
+        assert_eq!(proto.chunk_name, Some(chunk_name.clone()));
+        assert_eq!(proto.prototypes[0].chunk_name, Some(chunk_name));
+    });
+
+    let (_, _, traceback) = call_result(
+        &br#"
+            local f = load("local x = {}\nx = x + 1", "@script.lua")
+            f()
+        "#[..],
+        true,
+    );
+    assert_eq!(
+        traceback.unwrap().to_string(),
+        "stack traceback:\n\
+         \tscript.lua:2 in function starting at line 1\n\
+         \tline 3 in function starting at line 2"
+    );
+}
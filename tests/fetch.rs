@@ -0,0 +1,113 @@
+#![cfg(feature = "fetch")]
+
+use std::cell::RefCell;
+use std::future::poll_fn;
+use std::rc::Rc;
+use std::task::Poll;
+
+use luster::fetch::{load_fetch, FetchRequest, FetchResponse};
+use luster::{compile, Closure, Function, Lua, StdLib, String, Value};
+
+#[test]
+fn fetch_suspends_until_complete() {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            timer: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    let requests: Rc<RefCell<Vec<FetchRequest>>> = Rc::new(RefCell::new(Vec::new()));
+    let response: Rc<RefCell<Option<FetchResponse>>> = Rc::new(RefCell::new(None));
+
+    lua.mutate(|mc, root| {
+        let requests = requests.clone();
+        let response = response.clone();
+        load_fetch(mc, root.globals, move |request| {
+            let failed = request.url == b"bad://";
+            requests.borrow_mut().push(request);
+            let response = response.clone();
+            async move {
+                if failed {
+                    return Err("unsupported scheme".to_owned());
+                }
+                poll_fn(|_| match response.borrow_mut().take() {
+                    Some(response) => Poll::Ready(Ok(response)),
+                    None => Poll::Pending,
+                })
+                .await
+            }
+        });
+
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &br#"
+                    ticks = 0
+                    timer.every(0, function()
+                        ticks = ticks + 1
+                    end)
+                    local response = fetch("https://example.com/", {
+                        method = "POST",
+                        headers = {accept = "text/plain"},
+                        body = "hello",
+                    })
+                    status = response.status
+                    body = response.body
+                    content_type = response.headers["content-type"]
+                    local response, err = fetch("bad://")
+                    missing = response
+                    message = err
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.executor.spawn(mc, Function::Closure(closure));
+    });
+
+    for _ in 0..3 {
+        lua.mutate(|mc, root| assert!(root.executor.run(mc, 10).is_empty()));
+    }
+
+    lua.mutate(|_, root| {
+        assert_eq!(root.globals.get(String::new_static(b"status")), Value::Nil);
+        assert_eq!(
+            root.globals.get(String::new_static(b"ticks")),
+            Value::Integer(2)
+        );
+    });
+    assert_eq!(
+        requests.borrow()[0],
+        FetchRequest {
+            url: b"https://example.com/".to_vec(),
+            method: b"POST".to_vec(),
+            headers: vec![(b"accept".to_vec(), b"text/plain".to_vec())],
+            body: Some(b"hello".to_vec()),
+        }
+    );
+
+    *response.borrow_mut() = Some(FetchResponse {
+        status: 200,
+        headers: vec![(b"content-type".to_vec(), b"text/plain".to_vec())],
+        body: b"world".to_vec(),
+    });
+    lua.mutate(|mc, root| {
+        assert!(root.executor.run(mc, 10).is_empty());
+        let get = |name: &'static [u8]| root.globals.get(String::new_static(name));
+        assert_eq!(get(b"status"), Value::Integer(200));
+        assert_eq!(get(b"body"), Value::String(String::new(mc, b"world")));
+        assert_eq!(
+            get(b"content_type"),
+            Value::String(String::new(mc, b"text/plain"))
+        );
+        assert_eq!(get(b"missing"), Value::Nil);
+        assert_eq!(
+            get(b"message"),
+            Value::String(String::new(mc, b"unsupported scheme"))
+        );
+    });
+}
@@ -0,0 +1,55 @@
+use luster::{Callback, CallbackResult, Function, LineNumber, Lua, String, Value};
+
+#[test]
+fn closure_info() {
+    let mut lua = Lua::new();
+    lua.run_string(
+        &br#"
+            local prefix = "> "
+            function handler(event, payload)
+                print(prefix .. event)
+                return payload
+            end
+
+            function variadic(...)
+            end
+        "#[..],
+    )
+    .unwrap();
+
+    lua.mutate(|_, root| {
+        let handler = match root.globals.get(String::new_static(b"handler")) {
+            Value::Function(f) => f.info().unwrap(),
+            v => panic!("handler is not a function: {:?}", v),
+        };
+        assert_eq!(handler.fixed_params, 2);
+        assert!(!handler.has_varargs);
+        assert_eq!(handler.lines, Some((LineNumber(4), LineNumber(5))));
+        assert_eq!(
+            handler
+                .upvalue_names
+                .iter()
+                .map(|n| n.as_bytes())
+                .collect::<Vec<_>>(),
+            vec![&b"prefix"[..], &b"_ENV"[..]]
+        );
+
+        let variadic = match root.globals.get(String::new_static(b"variadic")) {
+            Value::Function(f) => f.info().unwrap(),
+            v => panic!("variadic is not a function: {:?}", v),
+        };
+        assert_eq!(variadic.fixed_params, 0);
+        assert!(variadic.has_varargs);
+        assert_eq!(variadic.lines, None);
+        assert!(variadic.upvalue_names.is_empty());
+    });
+}
+
+#[test]
+fn callback_info() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let callback = Callback::new_immediate(mc, |_| Ok(CallbackResult::Return(vec![])));
+        assert_eq!(Function::Callback(callback).info(), None);
+    });
+}
@@ -0,0 +1,36 @@
+use luster::{Lua, Table};
+
+const GARBAGE: &[u8] = b"for i = 1, 100000 do local t = {i, i} end";
+
+#[test]
+fn gc_time_slice() {
+    // Without any collection slices, every table is still allocated at the end.
+    let mut lua = Lua::builder().gc_time_slice(u32::MAX, 1.0).build();
+    let start = lua.total_allocated();
+    lua.run_string(GARBAGE).unwrap();
+    let uncollected = lua.total_allocated() - start;
+
+    let mut lua = Lua::builder().gc_time_slice(1000, 64.0 * 1024.0).build();
+    let start = lua.total_allocated();
+    lua.run_string(GARBAGE).unwrap();
+    let sliced = lua.total_allocated() - start;
+
+    assert!(sliced * 10 < uncollected);
+}
+
+#[test]
+fn instances_are_paced_separately() {
+    let mut idle = Lua::builder().gc_time_slice(1_000_000, f64::MAX).build();
+    idle.mutate(|mc, _| {
+        for _ in 0..1000 {
+            Table::new(mc);
+        }
+    });
+    let garbage = idle.total_allocated();
+
+    // The instructions run by another instance do not make collection work due in this one.
+    let mut busy = Lua::new();
+    busy.run_string(b"for i = 1, 2000000 do end").unwrap();
+    idle.mutate(|_, _| {});
+    assert_eq!(idle.total_allocated(), garbage);
+}
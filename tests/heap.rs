@@ -0,0 +1,88 @@
+use luster::{
+    compile, heap_census, reference_path, Closure, Function, Lua, String, Table, ThreadMode, Value,
+};
+
+#[test]
+fn census() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let before = heap_census(root, 0);
+
+        let big = Table::new(mc);
+        for i in 1..=100 {
+            big.set(mc, i, i).unwrap();
+        }
+        let holder = Table::new(mc);
+        holder.set(mc, String::new_static(b"big"), big).unwrap();
+        root.globals
+            .set(mc, String::new_static(b"holder"), holder)
+            .unwrap();
+
+        let after = heap_census(root, 1);
+        assert_eq!(after.tables.count, before.tables.count + 2);
+        assert!(after.tables.bytes > before.tables.bytes);
+        assert!(after.total().bytes > before.total().bytes);
+
+        let largest = &after.largest_tables[0];
+        assert_eq!(largest.path, vec!["globals", r#"["holder"]"#, r#"["big"]"#]);
+        assert_eq!(largest.entries, 100);
+
+        let json = after.to_json();
+        assert!(json.starts_with("{\"tables\":{\"count\":"));
+        assert!(json.contains(r#""path":["globals","[\"holder\"]","[\"big\"]"],"entries":100"#));
+
+        let table = after.to_table(mc);
+        let tables = match table.get(String::new_static(b"tables")) {
+            Value::Table(t) => t,
+            _ => panic!("tables field is not a table"),
+        };
+        assert_eq!(
+            tables.get(String::new_static(b"count")),
+            Value::Integer(after.tables.count as i64)
+        );
+    });
+}
+
+#[test]
+fn paths_through_closures() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &br#"
+                    local hidden = {}
+                    function get_hidden()
+                        return hidden
+                    end
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+        while root.main_thread.mode() == ThreadMode::Running {
+            root.main_thread.step(mc).unwrap();
+        }
+        root.main_thread.take_results(mc).unwrap().unwrap();
+    });
+
+    lua.mutate(|mc, root| {
+        let get_hidden = root.globals.get(String::new_static(b"get_hidden"));
+        let hidden = match get_hidden {
+            Value::Function(Function::Closure(c)) => c.0.upvalues[0].get(),
+            _ => panic!("get_hidden is not a closure"),
+        };
+        assert_eq!(
+            reference_path(root, hidden).unwrap(),
+            vec!["globals", r#"["get_hidden"]"#, "upvalue 0"]
+        );
+        assert_eq!(reference_path(root, Value::Table(Table::new(mc))), None);
+        assert_eq!(reference_path(root, Value::Integer(1)), None);
+    });
+}
@@ -0,0 +1,141 @@
+use gc_arena::MutationContext;
+use luster::{
+    compile, Closure, CountHook, Function, Lua, OwnedValue, Root, StaticError, StdLib, String,
+    Thread, ThreadMode, Value,
+};
+
+const SCRIPT: &[u8] = br#"
+local sum = 0
+for i = 1, 10 do
+    sum = sum + i
+end
+return sum
+"#;
+
+fn start<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>) -> Thread<'gc> {
+    let closure = Closure::new(
+        mc,
+        compile(mc, root.interned_strings, SCRIPT).unwrap(),
+        Some(root.globals),
+    )
+    .unwrap();
+    let thread = Thread::new(mc, false);
+    thread.start(mc, Function::Closure(closure), &[]).unwrap();
+    thread
+}
+
+#[test]
+fn count_hook_budget() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.run_string(&b"hits = 0 function count_hook(event) hits = hits + 1 end"[..])?;
+
+    let instructions = lua.mutate(|mc, root| {
+        let thread = start(mc, root);
+        let mut instructions = 0;
+        while thread.mode() == ThreadMode::Running {
+            thread.step_instructions(mc, 1).unwrap();
+            instructions += 1;
+        }
+        assert_eq!(
+            thread.take_results(mc).unwrap().unwrap(),
+            vec![Value::Integer(55)]
+        );
+        instructions
+    });
+
+    let count = 7;
+    lua.mutate(|mc, root| {
+        let function = match root.globals.get(String::new_static(b"count_hook")) {
+            Value::Function(function) => function,
+            _ => panic!("count_hook is not a function"),
+        };
+        let thread = start(mc, root);
+        thread.set_count_hook(mc, Some(CountHook { function, count }));
+
+        // Instructions run by the hook do not use up the budget, so the script still finishes in
+        // one call.
+        thread.step_instructions(mc, instructions).unwrap();
+        assert_eq!(thread.mode(), ThreadMode::Results);
+        assert_eq!(
+            thread.take_results(mc).unwrap().unwrap(),
+            vec![Value::Integer(55)]
+        );
+
+        // The hook is not called after the final return instruction.
+        assert_eq!(
+            root.globals.get(String::new_static(b"hits")),
+            Value::Integer(((instructions - 1) / count) as i64)
+        );
+    });
+
+    Ok(())
+}
+
+#[test]
+fn budget_snapshot() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let source =
+            &b"local function spin(n)\n  local t = {n}\n  while true do end\nend\nspin(3)"[..];
+        let closure = Closure::new(
+            mc,
+            compile(mc, root.interned_strings, source).unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.start(mc, Function::Closure(closure), &[]).unwrap();
+        thread.step_instructions(mc, 100).unwrap();
+        assert_eq!(thread.mode(), ThreadMode::Running);
+
+        let snapshot = thread.snapshot(1);
+        assert_eq!(
+            snapshot.to_string(),
+            "stack traceback:\n\tline 3 in function starting at line 2\n\t\
+             line 5 in function starting at line 1\nlocals:\n\t[0] = 3\n\t[1] = {[1] = 3}"
+        );
+    });
+}
+
+#[test]
+fn debug_sethook() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            debug: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    let values = lua.run_string(
+        &br#"
+            local hits = 0
+            local events = true
+            local function hook(event)
+                events = events and event == "count"
+                hits = hits + 1
+            end
+            debug.sethook(hook, "", 10)
+            local f, mask, count = debug.gethook()
+            local installed = f == hook and mask == "" and count == 10
+
+            for i = 1, 100 do end
+            debug.sethook()
+            local after = hits
+            for i = 1, 100 do end
+
+            return installed and events, after > 0 and hits == after, debug.gethook() == nil,
+                pcall(debug.sethook, hook, "l")
+        "#[..],
+    )?;
+    assert_eq!(
+        &values[..4],
+        &[
+            OwnedValue::Boolean(true),
+            OwnedValue::Boolean(true),
+            OwnedValue::Boolean(true),
+            OwnedValue::Boolean(false)
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use luster::{Lua, OwnedValue, StaticError, StdLib, String, Table, Value};
+
+#[test]
+fn debug_fmt() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let t = Table::new(mc);
+        t.set(mc, 2, 2).unwrap();
+        t.set(mc, 1, String::new_static(b"a\n\"b\"")).unwrap();
+        t.set(mc, String::new_static(b"key"), true).unwrap();
+        t.set(mc, String::new_static(b"not ident"), 1.5).unwrap();
+        t.set(mc, String::new_static(b"self"), t).unwrap();
+
+        assert_eq!(
+            Value::Table(t).debug_fmt(4),
+            r#"{[1] = "a\n\"b\"", [2] = 2, key = true, ["not ident"] = 1.5, self = <cycle>}"#
+        );
+
+        let outer = Table::new(mc);
+        outer.set(mc, 1, t).unwrap();
+        outer.set(mc, 2, Table::new(mc)).unwrap();
+        assert_eq!(Value::Table(outer).debug_fmt(1), "{[1] = {...}, [2] = {}}");
+
+        // The escape is padded so that the digit after it is not read as part of it.
+        assert_eq!(
+            Value::String(String::new_static(b"\x012")).debug_fmt(1),
+            r#""\0012""#
+        );
+    });
+}
+
+#[test]
+fn inspect() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            inspect: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    let values = lua.run_string(
+        &br#"
+            local t = {1, {2, {3}}}
+            return inspect(t), inspect(t, 1), inspect("s")
+        "#[..],
+    )?;
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::String(b"{[1] = 1, [2] = {[1] = 2, [2] = {[1] = 3}}}".to_vec()),
+            OwnedValue::String(b"{[1] = 1, [2] = {...}}".to_vec()),
+            OwnedValue::String(b"\"s\"".to_vec()),
+        ]
+    );
+
+    assert!(Lua::new().run_string(&b"return inspect(1)"[..]).is_err());
+
+    Ok(())
+}
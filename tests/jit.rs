@@ -0,0 +1,185 @@
+#![cfg(feature = "jit")]
+
+use std::fs::{read_dir, File};
+
+use gc_arena::Gc;
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, io, jit::Jit, Closure, Error, Function, Lua, StaticError, ThreadMode, ThreadSequence,
+    Value,
+};
+
+fn run_with_jit(lua: &mut Lua, source: Vec<u8>, threshold: u32) -> Result<bool, StaticError> {
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            root.main_thread.set_jit(mc, Some(Jit::new(mc, threshold)));
+            Ok(Closure::new(
+                mc,
+                compile(mc, root.interned_strings, &source[..])?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, move |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|r| r == [Value::Boolean(true)])
+        .map_err(Error::to_static)
+        .boxed()
+    })
+}
+
+#[test]
+fn suite_jit() {
+    for entry in read_dir("./tests/running").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "lua") {
+            continue;
+        }
+
+        let mut source = Vec::new();
+        std::io::Read::read_to_end(
+            &mut io::buffered_read(File::open(&path).unwrap()).unwrap(),
+            &mut source,
+        )
+        .unwrap();
+        for &threshold in &[0, 2] {
+            let mut lua = Lua::new();
+            assert!(
+                run_with_jit(&mut lua, source.clone(), threshold).unwrap(),
+                "{:?} failed under the JIT with threshold {}",
+                path,
+                threshold
+            );
+        }
+    }
+}
+
+const NUMERIC: &[u8] = br#"
+local function mix(a, b)
+    local r = a * 2 + b - 1
+    if a < b then
+        r = r / 2
+    elseif a == b then
+        r = -r
+    end
+    return r
+end
+
+local results = {}
+local n = 0
+local function push(v)
+    n = n + 1
+    if v == true then
+        v = "true"
+    elseif v == false then
+        v = "false"
+    end
+    results[n] = v
+end
+
+local sum, fsum = 0, 0.0
+for i = 1, 100 do
+    sum = sum + i * i
+    fsum = fsum + i / 4
+end
+push(sum)
+push(fsum)
+
+for i = 10, 1, -3 do
+    push(i)
+end
+for x = 0.5, 2, 0.5 do
+    push(x)
+end
+for i = 1, 3 do
+    push(mix(i, 2))
+    push(mix(i + 0.5, 2))
+end
+
+-- Operands the native code does not handle fall back to the VM.
+push("10" + 5)
+push(2 ^ 10)
+push(7 // 2)
+push(7 % 3)
+push(1 < 2.5 and 2.5 <= 2.5 and not (3 < 1))
+push(nil == false)
+push(1 == 1.0)
+-- An integer is only equal to a float that converts to it exactly.
+local big, rounded = 9007199254740993, 9007199254740992.0
+push(big == rounded)
+push(rounded == big)
+push(math.maxinteger == 2 ^ 63)
+push(-0.0 == 0)
+push(math.maxinteger + 1 == math.mininteger)
+local t = {}
+push(t == t)
+push(not nil)
+local s = "a"
+push(s == "a")
+-- Values of types native code does not operate on are only moved between registers.
+local x, y = t, s
+for i = 1, 3 do
+    x, y = y, x
+end
+push(x == s and y == t)
+
+local ok = pcall(function()
+    local x = {} + 1
+end)
+push(ok)
+
+local out = ""
+for i = 1, n do
+    out = out .. results[i] .. ","
+end
+return out
+"#;
+
+fn run_numeric(jit: bool) -> (String, usize, bool) {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = Gc::allocate(mc, compile(mc, root.interned_strings, NUMERIC).unwrap());
+        let closure = Closure::new_with_proto(mc, proto, Some(root.globals)).unwrap();
+        let jit = if jit { Some(Jit::new(mc, 0)) } else { None };
+        root.main_thread.set_jit(mc, jit);
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+
+        // Step one instruction at a time, native code must stop after each one too.
+        let mut steps = 0;
+        while root.main_thread.mode() == ThreadMode::Running {
+            root.main_thread.step_instructions(mc, 1).unwrap();
+            steps += 1;
+        }
+        let result = match &root.main_thread.take_results(mc).unwrap().unwrap()[..] {
+            [Value::String(s)] => std::str::from_utf8(s.as_bytes()).unwrap().to_owned(),
+            r => panic!("unexpected results {:?}", r),
+        };
+        let compiled = jit.is_some_and(|jit| {
+            let compiled = jit.compiled();
+            let is_compiled = |p| compiled.iter().any(|&c| Gc::ptr_eq(c, p));
+            is_compiled(proto) && is_compiled(proto.prototypes[0])
+        });
+        (result, steps, compiled)
+    })
+}
+
+#[test]
+fn jit_matches_vm() {
+    let (expected, expected_steps, _) = run_numeric(false);
+    let (result, steps, compiled) = run_numeric(true);
+    assert_eq!(result, expected);
+    assert_eq!(steps, expected_steps);
+    assert!(compiled);
+    assert_eq!(
+        expected,
+        "338350,1262.5,10,7,4,1,0.5,1,1.5,2,1.5,2,-5,6,7,8,\
+         15,1024,3,1,true,false,true,false,false,false,true,true,true,true,true,true,false,"
+    );
+}
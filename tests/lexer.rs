@@ -1,6 +1,9 @@
 use std::f64;
 
-use luster::{Lexer, Token};
+use luster::{
+    owned_tokens, Lexer, LexerError, LexerLimit, LexerLimits, LineNumber, OwnedToken, ParserError,
+    PushInput, Span, Token,
+};
 
 fn test_tokens(source: &str, tokens: &[Token<Box<[u8]>>]) {
     let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
@@ -115,6 +118,12 @@ fn numerals() {
             0x99999999999999999999999999999999p999999999999999999999999999999
             9223372036854775807
             9223372036854775808
+            0xffffffffffffffff
+            0x8000000000000000
+            0x10000000000000001
+            0x1p-2
+            1e2
+            3 .. 4
         "#,
         &[
             Token::Integer(0xdeadbeef),
@@ -128,10 +137,30 @@ fn numerals() {
             Token::Float(f64::INFINITY),
             Token::Integer(9223372036854775807),
             Token::Float(9223372036854775808.0),
+            Token::Integer(-1),
+            Token::Integer(i64::MIN),
+            Token::Integer(1),
+            Token::Float(0.25),
+            Token::Float(100.0),
+            Token::Integer(3),
+            Token::Concat,
+            Token::Integer(4),
         ],
     );
 }
 
+#[test]
+fn malformed_numerals() {
+    for source in &["0x", "3x", "0x1g", "1e", "1e+", "1.2.3", "3..4", "0x1p"] {
+        let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+        assert!(
+            lexer.read_token().is_err(),
+            "{} should be malformed",
+            source
+        );
+    }
+}
+
 #[test]
 fn words() {
     test_tokens(
@@ -210,3 +239,472 @@ fn ops() {
         ],
     );
 }
+
+#[test]
+fn token_spans() {
+    let source = "local x = 0x10 -- comment\n  print(\"a\\n\", [[lo\nng]])";
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    let mut spans = Vec::new();
+    while let Some((token, span)) = lexer.read_spanned_token().unwrap() {
+        assert_eq!(span.start, lexer.byte_offset() - (span.end - span.start));
+        spans.push((token, span));
+    }
+    let span = |line, column, start, end| Span {
+        line_number: LineNumber(line),
+        column,
+        start,
+        end,
+    };
+    assert_eq!(
+        spans,
+        vec![
+            (Token::Local, span(1, 1, 0, 5)),
+            (name_token("x"), span(1, 7, 6, 7)),
+            (Token::Assign, span(1, 9, 8, 9)),
+            (Token::Integer(16), span(1, 11, 10, 14)),
+            (name_token("print"), span(2, 3, 28, 33)),
+            (Token::LeftParen, span(2, 8, 33, 34)),
+            (str_token("a\n"), span(2, 9, 34, 39)),
+            (Token::Comma, span(2, 14, 39, 40)),
+            (str_token("lo\nng"), span(2, 16, 41, 50)),
+            (Token::RightParen, span(3, 5, 50, 51)),
+        ]
+    );
+    assert_eq!(&source[41..50], "[[lo\nng]]");
+}
+
+#[test]
+fn token_columns() {
+    let source = "a\tb\n\"\u{e9}\u{e9}\" c\n  \t\"\u{1f600}\"d";
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    let mut positions = Vec::new();
+    while let Some((_, span)) = lexer.read_spanned_token().unwrap() {
+        positions.push((span.line_number.0, span.column));
+    }
+    assert_eq!(
+        positions,
+        vec![(1, 1), (1, 9), (2, 1), (2, 6), (3, 9), (3, 12)]
+    );
+}
+
+#[test]
+fn shebang() {
+    test_tokens_lines(
+        "#!/usr/bin/env lua\nreturn 1",
+        &[(Token::Return, 1), (Token::Integer(1), 1)],
+    );
+    test_tokens("#\n", &[]);
+    test_tokens("x #y", &[name_token("x"), Token::Len, name_token("y")]);
+}
+
+#[test]
+fn comment_tokens() {
+    let source = "-- short\nlocal x = 1 --[==[ long\ncomment ]==] - 2 --";
+    let mut lexer = Lexer::with_comments(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    let mut tokens = Vec::new();
+    while let Some((token, span)) = lexer.read_spanned_token().unwrap() {
+        tokens.push((token, span.line_number.0));
+    }
+    let comment = |s: &str| Token::Comment(s.as_bytes().to_vec().into_boxed_slice());
+    assert_eq!(
+        tokens,
+        vec![
+            (comment(" short"), 1),
+            (Token::Local, 2),
+            (name_token("x"), 2),
+            (Token::Assign, 2),
+            (Token::Integer(1), 2),
+            (comment(" long\ncomment "), 2),
+            (Token::Minus, 3),
+            (Token::Integer(2), 3),
+            (comment(""), 3),
+        ]
+    );
+
+    test_tokens(
+        source,
+        &[
+            Token::Local,
+            name_token("x"),
+            Token::Assign,
+            Token::Integer(1),
+            Token::Minus,
+            Token::Integer(2),
+        ],
+    );
+}
+
+#[test]
+fn token_iterator() {
+    let tokens = owned_tokens(&b"local s = 'a' .. b"[..])
+        .map(|res| res.map(|(token, _)| token))
+        .collect::<Result<Vec<OwnedToken>, _>>()
+        .unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Local,
+            name_token("s"),
+            Token::Assign,
+            str_token("a"),
+            Token::Concat,
+            name_token("b"),
+        ]
+    );
+
+    let names = Lexer::new(&b"x = y + 1\nz"[..], |s| s.to_vec())
+        .into_tokens()
+        .filter_map(|res| match res {
+            Ok((Token::Name(name), span)) => Some((name, span.line_number.0)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![(b"x".to_vec(), 1), (b"y".to_vec(), 1), (b"z".to_vec(), 2)]
+    );
+
+    // Iteration ends after the first error.
+    let mut tokens = owned_tokens(&b"a $ b"[..]);
+    assert!(matches!(tokens.next(), Some(Ok((Token::Name(_), _)))));
+    assert!(matches!(
+        tokens.next(),
+        Some(Err(LexerError::UnexpectedCharacter(b'$')))
+    ));
+    assert!(tokens.next().is_none());
+}
+
+#[test]
+fn lookahead() {
+    let mut lexer = Lexer::new(&b"local x = f(1)"[..], |s| s.to_vec().into_boxed_slice());
+    assert_eq!(lexer.peek(2).unwrap(), Some(&Token::Assign));
+    assert_eq!(lexer.peek(0).unwrap(), Some(&Token::Local));
+    assert_eq!(lexer.peek(9).unwrap(), None);
+
+    assert_eq!(lexer.expect(&Token::Local).unwrap().column, 1);
+    assert!(matches!(
+        lexer.expect(&Token::Assign),
+        Err(ParserError::Unexpected { .. })
+    ));
+    // A failed expect does not consume the token.
+    assert_eq!(lexer.read_token().unwrap(), Some(name_token("x")));
+    assert_eq!(lexer.expect(&Token::Assign).unwrap().start, 8);
+    let (token, span) = lexer.read_spanned_token().unwrap().unwrap();
+    assert_eq!((token, span.column), (name_token("f"), 11));
+
+    for token in &[Token::LeftParen, Token::Integer(1), Token::RightParen] {
+        lexer.expect(token).unwrap();
+    }
+    assert!(matches!(
+        lexer.expect(&Token::End),
+        Err(ParserError::EndOfStream { .. })
+    ));
+}
+
+#[test]
+fn error_recovery() {
+    let source = "local a = 1 $$x; b = \"open\nc = 0x 3\nd = 'ok'";
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    lexer.set_error_recovery(true);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.read_token().unwrap() {
+        tokens.push(token);
+    }
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Local,
+            name_token("a"),
+            Token::Assign,
+            Token::Integer(1),
+            Token::SemiColon,
+            name_token("b"),
+            Token::Assign,
+            name_token("c"),
+            Token::Assign,
+            Token::Integer(3),
+            name_token("d"),
+            Token::Assign,
+            str_token("ok"),
+        ]
+    );
+
+    let errors = lexer.take_errors();
+    assert_eq!(errors.len(), 3);
+    assert!(matches!(errors[0].0, LexerError::UnexpectedCharacter(b'$')));
+    assert_eq!(
+        (errors[0].1.column, errors[0].1.start, errors[0].1.end),
+        (13, 12, 15)
+    );
+    assert!(matches!(
+        errors[1].0,
+        LexerError::UnfinishedShortString(b'"')
+    ));
+    assert_eq!(errors[1].1.line_number, LineNumber(1));
+    assert!(matches!(errors[2].0, LexerError::BadNumber));
+    assert_eq!(errors[2].1.line_number, LineNumber(2));
+    assert!(lexer.errors().is_empty());
+
+    // Without recovery, the first error ends lexing.
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    for _ in 0..4 {
+        lexer.read_token().unwrap();
+    }
+    assert!(lexer.read_token().is_err());
+    assert_eq!(lexer.read_token().unwrap(), None);
+}
+
+#[test]
+fn unexpected_eof() {
+    for source in &["'abc", "\"abc\\", "[==[ abc ]]", "--[[ abc", "x = \"a\\\n"] {
+        let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+        let err = loop {
+            match lexer.read_token() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error for {:?}", source),
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, LexerError::UnexpectedEof), "{:?}", source);
+        assert!(ParserError::LexerError(err).is_incomplete());
+    }
+
+    let mut lexer = Lexer::new(&b"'abc\n'"[..], |s| s.to_vec().into_boxed_slice());
+    assert!(matches!(
+        lexer.read_token(),
+        Err(LexerError::UnfinishedShortString(b'\''))
+    ));
+}
+
+#[test]
+fn byte_order_mark() {
+    test_tokens("\u{feff}local x", &[Token::Local, name_token("x")]);
+    test_tokens_lines("\u{feff}#!/usr/bin/env lua\nreturn", &[(Token::Return, 1)]);
+
+    let mut lexer = Lexer::new("\u{feff}x".as_bytes(), |s| s.to_vec().into_boxed_slice());
+    let (_, span) = lexer.read_spanned_token().unwrap().unwrap();
+    assert_eq!((span.column, span.start), (1, 3));
+
+    // Only at the very start of the source.
+    let mut lexer = Lexer::new("x \u{feff}".as_bytes(), |s| s.to_vec().into_boxed_slice());
+    lexer.read_token().unwrap();
+    assert!(matches!(
+        lexer.read_token(),
+        Err(LexerError::UnexpectedCharacter(0xef))
+    ));
+}
+
+#[test]
+fn strict_utf8() {
+    let sources: &[&[u8]] = &[b"'a\xffb'", b"[[\xc3]]", b"'\xc3\\x41\xa9'"];
+    for &source in sources {
+        let mut lexer = Lexer::new(source, |s| s.to_vec().into_boxed_slice());
+        assert!(lexer.read_token().is_ok());
+
+        let mut lexer = Lexer::new(source, |s| s.to_vec().into_boxed_slice());
+        lexer.set_strict_utf8(true);
+        assert!(
+            matches!(lexer.read_token(), Err(LexerError::InvalidUtf8)),
+            "{:?}",
+            source
+        );
+    }
+
+    // Comments are not checked.
+    let source = &b"'h\xc3\xa9\\xff' [[\xf0\x9f\x98\x80]] -- \xff"[..];
+    let mut lexer = Lexer::new(source, |s| s.to_vec().into_boxed_slice());
+    lexer.set_strict_utf8(true);
+    assert_eq!(
+        lexer.read_token().unwrap(),
+        Some(Token::String(b"h\xc3\xa9\xff".to_vec().into_boxed_slice()))
+    );
+    assert_eq!(lexer.read_token().unwrap(), Some(str_token("\u{1f600}")));
+    assert_eq!(lexer.read_token().unwrap(), None);
+}
+
+#[test]
+fn lexer_limits() {
+    fn first_error(source: &str, limits: LexerLimits) -> Option<LexerLimit> {
+        let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+        lexer.set_limits(limits);
+        // Limits are never recovered from.
+        lexer.set_error_recovery(true);
+        loop {
+            match lexer.read_token() {
+                Ok(Some(_)) => {}
+                Ok(None) => return None,
+                Err(LexerError::LimitExceeded(limit)) => {
+                    assert!(lexer.read_token().unwrap().is_none());
+                    return Some(limit);
+                }
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+    }
+
+    let limits = LexerLimits {
+        max_token_len: 8,
+        ..LexerLimits::default()
+    };
+    assert_eq!(first_error("abcdefgh 12345678", limits), None);
+    assert_eq!(
+        first_error("abcdefghi", limits),
+        Some(LexerLimit::TokenLength)
+    );
+    assert_eq!(
+        first_error("x = '1234567'", limits),
+        Some(LexerLimit::TokenLength)
+    );
+    assert_eq!(
+        first_error(&format!("[[{}", "x".repeat(10_000)), limits),
+        Some(LexerLimit::TokenLength)
+    );
+
+    let limits = LexerLimits {
+        max_string_len: 3,
+        ..LexerLimits::default()
+    };
+    assert_eq!(first_error("'abc' [[abc]] '\\65\\66\\67'", limits), None);
+    assert_eq!(
+        first_error("'abcd'", limits),
+        Some(LexerLimit::StringLength)
+    );
+    assert_eq!(
+        first_error("[[abcd]]", limits),
+        Some(LexerLimit::StringLength)
+    );
+
+    let limits = LexerLimits {
+        max_long_bracket_level: 1,
+        ..LexerLimits::default()
+    };
+    assert_eq!(first_error("[=[ a ]=] --[=[ b ]=]", limits), None);
+    assert_eq!(
+        first_error("--[==[ a ]==]", limits),
+        Some(LexerLimit::LongBracketLevel)
+    );
+
+    let limits = LexerLimits {
+        max_tokens: 3,
+        ..LexerLimits::default()
+    };
+    assert_eq!(first_error("local x = -- 1", limits), None);
+    assert_eq!(
+        first_error("local x = 1", limits),
+        Some(LexerLimit::TokenCount)
+    );
+}
+
+#[test]
+fn extended_unicode_escapes() {
+    let source = &br"'\u{48}\u{E9}\u{10FFFF}' '\u{D800}' '\u{110000}' '\u{7FFFFFFF}'"[..];
+    let mut lexer = Lexer::new(source, |s| s.to_vec().into_boxed_slice());
+    assert_eq!(
+        lexer.read_token().unwrap(),
+        Some(str_token("H\u{e9}\u{10ffff}"))
+    );
+    assert!(matches!(
+        lexer.read_token(),
+        Err(LexerError::EscapeUnicodeInvalid)
+    ));
+
+    let mut lexer = Lexer::new(source, |s| s.to_vec().into_boxed_slice());
+    lexer.set_extended_unicode_escapes(true);
+    let expected: &[&[u8]] = &[
+        b"H\xc3\xa9\xf4\x8f\xbf\xbf",
+        b"\xed\xa0\x80",
+        b"\xf4\x90\x80\x80",
+        b"\xfd\xbf\xbf\xbf\xbf\xbf",
+    ];
+    for &bytes in expected {
+        assert_eq!(
+            lexer.read_token().unwrap(),
+            Some(Token::String(bytes.to_vec().into_boxed_slice()))
+        );
+    }
+
+    let mut lexer = Lexer::new(&br"'\u{80000000}'"[..], |s| s.to_vec().into_boxed_slice());
+    lexer.set_extended_unicode_escapes(true);
+    assert!(matches!(
+        lexer.read_token(),
+        Err(LexerError::EscapeUnicodeInvalid)
+    ));
+}
+
+#[test]
+fn raw_text() {
+    let source = "local s = 'a\\x41\\z\n  b' .. [==[long]==] + 0x1P4 --[[c]] 1e+2";
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    lexer.set_raw_text(true);
+    assert_eq!(lexer.peek(3).unwrap(), Some(&str_token("aAb")));
+
+    let mut raw_tokens = Vec::new();
+    while let Some((token, span, raw)) = lexer.read_raw_token().unwrap() {
+        assert_eq!(&source.as_bytes()[span.start..span.end], &*raw);
+        raw_tokens.push((token, String::from_utf8(raw.into()).unwrap()));
+    }
+    assert_eq!(
+        raw_tokens,
+        vec![
+            (Token::Local, "local".to_owned()),
+            (name_token("s"), "s".to_owned()),
+            (Token::Assign, "=".to_owned()),
+            (str_token("aAb"), "'a\\x41\\z\n  b'".to_owned()),
+            (Token::Concat, "..".to_owned()),
+            (str_token("long"), "[==[long]==]".to_owned()),
+            (Token::Add, "+".to_owned()),
+            (Token::Float(16.0), "0x1P4".to_owned()),
+            (Token::Float(100.0), "1e+2".to_owned()),
+        ]
+    );
+
+    let mut lexer = Lexer::new(&b"x"[..], |s| s.to_vec().into_boxed_slice());
+    let (_, _, raw) = lexer.read_raw_token().unwrap().unwrap();
+    assert!(raw.is_empty());
+}
+
+#[test]
+fn push_input() {
+    let source = b"local s = 'a\\tb' --[[ long\ncomment ]] x = 1 -- end\ny = 0x1p4";
+    let expected = {
+        let mut lexer = Lexer::new(&source[..], |s| s.to_vec().into_boxed_slice());
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.read_spanned_token().unwrap() {
+            tokens.push(token);
+        }
+        tokens
+    };
+
+    // Push the source one byte at a time, reading every token available after each byte.
+    let mut lexer = Lexer::new(PushInput::new(), |s| s.to_vec().into_boxed_slice());
+    let mut tokens = Vec::new();
+    let mut blocked = 0;
+    for &b in source.iter() {
+        lexer.fill(&[b]);
+        loop {
+            match lexer.read_spanned_token() {
+                Ok(Some(token)) => tokens.push(token),
+                Ok(None) => panic!("unexpected end of source"),
+                Err(LexerError::WouldBlock) => {
+                    blocked += 1;
+                    break;
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+    // The last numeral may still continue.
+    assert_eq!(tokens.len(), expected.len() - 1);
+    lexer.finish();
+    while let Some(token) = lexer.read_spanned_token().unwrap() {
+        tokens.push(token);
+    }
+    assert_eq!(tokens, expected);
+    assert_eq!(blocked, source.len());
+
+    let mut lexer = Lexer::new(PushInput::new(), |s| s.to_vec().into_boxed_slice());
+    lexer.fill(b"'open");
+    assert!(matches!(lexer.read_token(), Err(LexerError::WouldBlock)));
+    lexer.finish();
+    assert!(matches!(lexer.read_token(), Err(LexerError::UnexpectedEof)));
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "log")]
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use luster::log::load_log;
+use luster::Lua;
+
+type Fields = Vec<(&'static str, std::string::String)>;
+
+#[derive(Default)]
+struct Recorder(Arc<Mutex<Vec<(Level, Fields)>>>);
+
+struct FieldVisitor(Fields);
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "luster::script"
+    }
+
+    fn new_span(&self, _: &Attributes) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut visitor = FieldVisitor(Vec::new());
+        event.record(&mut visitor);
+        self.0
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), visitor.0));
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn log_records() {
+    let recorder = Recorder::default();
+    let events = recorder.0.clone();
+
+    tracing::subscriber::with_default(recorder, || {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, root| load_log(mc, root, root.globals));
+        lua.run_string(
+            &br#"
+                local function spawn(name)
+                    log.info("spawned " .. name)
+                end
+                spawn("goblin")
+                log.warn("low health", {player = "ann", health = 3})
+                pcall(log.error, "unreachable", 5)
+            "#[..],
+        )
+        .unwrap();
+    });
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+
+    let (level, fields) = &events[0];
+    assert_eq!(*level, Level::INFO);
+    assert!(fields.contains(&("message", "spawned goblin".to_owned())));
+    assert!(fields.contains(&("line", "3".to_owned())));
+    assert!(fields.contains(&("fields", "\"\"".to_owned())));
+
+    let (level, fields) = &events[1];
+    assert_eq!(*level, Level::WARN);
+    assert!(fields.contains(&("line", "6".to_owned())));
+    assert!(fields.contains(&("fields", "\"health=3 player=ann\"".to_owned())));
+}
@@ -0,0 +1,62 @@
+use gc_arena::Gc;
+use luster::{CompiledModule, Constant, Function, Lua, ThreadMode, Value};
+
+#[test]
+fn compiled_module_shares_constants() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let mut module = CompiledModule::new(mc, root.interned_strings);
+        let a = module
+            .add_chunk(
+                mc,
+                "a",
+                &b"local function greet(n) return 'hello ' .. n end\nreturn greet('a'), 1.5"[..],
+            )
+            .unwrap();
+        let b = module
+            .add_chunk(
+                mc,
+                "b",
+                &b"local function greet(n) return 'hello ' .. n end\nreturn greet('b'), 1.5"[..],
+            )
+            .unwrap();
+        assert!(module.add_chunk(mc, "c", &b"return +"[..]).is_err());
+
+        assert_eq!(module.chunk_names(), vec!["a", "b"]);
+        assert!(Gc::ptr_eq(a.prototypes[0], b.prototypes[0]));
+        let hello = module
+            .constants()
+            .iter()
+            .filter(|c| match c {
+                Constant::String(s) => s.as_bytes() == b"hello ",
+                _ => false,
+            })
+            .count();
+        assert_eq!(hello, 1);
+        assert_eq!(
+            module
+                .constants()
+                .iter()
+                .filter(|&&c| c == Constant::Number(1.5))
+                .count(),
+            1
+        );
+
+        for (name, expected) in &[("a", &b"hello a"[..]), ("b", &b"hello b"[..])] {
+            let closure = module
+                .closure(mc, name, Some(root.globals))
+                .unwrap()
+                .unwrap();
+            root.main_thread
+                .start(mc, Function::Closure(closure), &[])
+                .unwrap();
+            while root.main_thread.mode() == ThreadMode::Running {
+                root.main_thread.step(mc).unwrap();
+            }
+            let results = root.main_thread.take_results(mc).unwrap().unwrap();
+            assert!(matches!(results[0], Value::String(s) if s.as_bytes() == *expected));
+            assert_eq!(results[1], Value::Number(1.5));
+        }
+        assert!(module.closure(mc, "c", None).is_none());
+    });
+}
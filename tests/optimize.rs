@@ -0,0 +1,308 @@
+use std::fs::{read_dir, File};
+
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, compile_optimized, compile_tokens, io, optimize, verify, Closure, Constant, Error,
+    Function, FunctionProto, Lexer, Lua, OpCode, Optimizations, StaticError, String,
+    ThreadSequence, UpValueDescriptor, Value,
+};
+
+fn run_optimized(lua: &mut Lua, source: Vec<u8>) -> Result<bool, StaticError> {
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            let proto =
+                compile_optimized(mc, root.interned_strings, &source[..], Optimizations::all())?;
+            verify(&proto).expect("optimized prototypes must verify");
+            Ok(Closure::new(mc, proto, Some(root.globals))?)
+        })
+        .and_chain_with(root, move |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|r| r == [Value::Boolean(true)])
+        .map_err(Error::to_static)
+        .boxed()
+    })
+}
+
+#[test]
+fn suite_optimized() {
+    for entry in read_dir("./tests/running").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "lua") {
+            continue;
+        }
+
+        let mut source = Vec::new();
+        std::io::Read::read_to_end(
+            &mut io::buffered_read(File::open(&path).unwrap()).unwrap(),
+            &mut source,
+        )
+        .unwrap();
+        let mut lua = Lua::new();
+        assert!(
+            run_optimized(&mut lua, source).unwrap(),
+            "{:?} failed when optimized",
+            path
+        );
+    }
+}
+
+fn global_lookups(opcodes: &[OpCode]) -> usize {
+    opcodes
+        .iter()
+        .filter(|op| matches!(op, OpCode::GetUpTableC { .. }))
+        .count()
+}
+
+#[test]
+fn hoists_global_lookups() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let source = &b"
+            local sum = 0
+            for i = 1, 10 do
+                sum = sum + scale * i + scale + offset
+            end
+            return sum
+        "[..];
+        let plain = compile(mc, root.interned_strings, source).unwrap();
+        let optimized =
+            compile_optimized(mc, root.interned_strings, source, Optimizations::all()).unwrap();
+
+        assert_eq!(global_lookups(&plain.opcodes), 3);
+        assert_eq!(global_lookups(&optimized.opcodes), 2);
+        assert_eq!(optimized.stack_size, plain.stack_size + 2);
+        let prep = optimized
+            .opcodes
+            .iter()
+            .position(|op| matches!(op, OpCode::NumericForPrep { .. }))
+            .unwrap();
+        assert_eq!(global_lookups(&optimized.opcodes[..prep]), 2);
+    });
+
+    let mut lua = Lua::new();
+    assert!(run_optimized(
+        &mut lua,
+        b"
+            scale, offset, limit = 2, 1, 5
+            local sum = 0
+            for i = 1, 10 do
+                sum = sum + scale * i + scale + offset
+            end
+            local n = 0
+            while n < limit do
+                n = n + 1
+            end
+            return sum == 140 and n == 5
+        "
+        .to_vec()
+    )
+    .unwrap());
+}
+
+#[test]
+fn keeps_lookups_in_loops_with_side_effects() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        for source in &[
+            &b"for i = 1, 3 do print(x) end"[..],
+            &b"for i = 1, 3 do x = x + 1 end"[..],
+            &b"local t = {} for i = 1, 3 do t[i] = x end"[..],
+            &b"for k, v in pairs(t) do local y = x end"[..],
+        ] {
+            let plain = compile(mc, root.interned_strings, *source).unwrap();
+            let optimized =
+                compile_optimized(mc, root.interned_strings, *source, Optimizations::all())
+                    .unwrap();
+            assert_eq!(plain.opcodes, optimized.opcodes);
+        }
+    });
+}
+
+#[test]
+fn lifts_local_functions() {
+    let lifted = |source: &[u8]| {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, root| {
+            let optimizations = Optimizations {
+                lift_local_functions: true,
+                ..Optimizations::default()
+            };
+            let proto =
+                compile_optimized(mc, root.interned_strings, source, optimizations).unwrap();
+            let function = &proto.prototypes[0];
+            let captures_locals = function
+                .upvalues
+                .iter()
+                .any(|u| matches!(u, UpValueDescriptor::ParentLocal(_)));
+            (!captures_locals, function.fixed_params)
+        })
+    };
+
+    assert_eq!(
+        lifted(b"local a, b = 1, 2 local function f(x) return a + b + x end return f(3)"),
+        (true, 3)
+    );
+    assert_eq!(
+        lifted(b"for i = 1, 3 do local function f() print(i) end f() f() end"),
+        (true, 1)
+    );
+    for source in &[
+        &b"local a = 1 local function f() return a end return f"[..],
+        &b"local a = 1 local function f() return a end return f:m()"[..],
+        &b"local a = 1 local function f() return a end return function() return f() end"[..],
+        &b"local a = 1 local function f() a = 2 end f()"[..],
+        &b"local a = 1 local function f() return a end local function g() a = 2 end f() g()"[..],
+        &b"local a = 1 local function f() return function() return a end end f()"[..],
+        &b"local a = 1 local function f() return a, f end f()"[..],
+    ] {
+        assert_eq!(
+            lifted(source),
+            (false, 0),
+            "{}",
+            std::str::from_utf8(source).unwrap()
+        );
+    }
+}
+
+#[test]
+fn inlines_local_functions() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let source = &b"local function get(p)
+            return p.x
+        end
+        local t = {x = 1}
+        return get(t) + get(t)"[..];
+        let optimizations = Optimizations {
+            inline_local_functions: true,
+            ..Optimizations::default()
+        };
+        let plain = compile(mc, root.interned_strings, source).unwrap();
+        let inlined = compile_optimized(mc, root.interned_strings, source, optimizations).unwrap();
+        verify(&inlined).unwrap();
+
+        let calls = |opcodes: &[OpCode]| {
+            opcodes
+                .iter()
+                .filter(|op| matches!(op, OpCode::Call { .. } | OpCode::TailCall { .. }))
+                .count()
+        };
+        assert_eq!(calls(&plain.opcodes), 2);
+        assert_eq!(calls(&inlined.opcodes), 0);
+
+        let lines = |op: fn(&OpCode) -> bool| {
+            inlined
+                .opcodes
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| op(o))
+                .map(|(pc, _)| inlined.opcode_line(pc).unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(|op| matches!(op, OpCode::GetTableC { .. })),
+            vec![2, 2]
+        );
+        assert_eq!(lines(|op| matches!(op, OpCode::Return { .. })), vec![5, 5]);
+    });
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        for source in &[
+            &b"local function f(...) return ... end return f(1)"[..],
+            &b"local function f() return ... end return f()"[..],
+            &b"local function f(n) if n then return 1 end end return f()"[..],
+            &b"local function f() goto done ::done:: end f()"[..],
+            &b"local function f() return f end f()"[..],
+            &b"local function f() end local g = f f()"[..],
+            &b"local function f() return 1 end print(f())"[..],
+        ] {
+            let optimizations = Optimizations {
+                inline_local_functions: true,
+                ..Optimizations::default()
+            };
+            let plain = compile(mc, root.interned_strings, *source).unwrap();
+            let optimized =
+                compile_optimized(mc, root.interned_strings, *source, optimizations).unwrap();
+            assert_eq!(
+                plain.opcodes,
+                optimized.opcodes,
+                "{}",
+                std::str::from_utf8(source).unwrap()
+            );
+        }
+    });
+}
+
+#[test]
+fn deduplicates_constants() {
+    let optimizations = Optimizations {
+        deduplicate_constants: true,
+        ..Optimizations::default()
+    };
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        // Every string in these tokens is allocated separately.
+        let tokens = Lexer::new(
+            &b"local function f() return 'shared' end return f(), 'shared'"[..],
+            |s| String::new(mc, s),
+        )
+        .into_tokens()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let plain = compile_tokens(mc, tokens).unwrap();
+        let optimized = optimize(mc, &plain, optimizations);
+        let string = |proto: &FunctionProto| {
+            proto
+                .constants
+                .iter()
+                .find_map(|c| match c {
+                    Constant::String(s) if s.as_bytes() == b"shared" => Some(s.as_bytes().as_ptr()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_ne!(string(&plain), string(&plain.prototypes[0]));
+        assert_eq!(string(&optimized), string(&optimized.prototypes[0]));
+
+        // A prototype that loads the same number from two constant entries.
+        let mut proto = compile(mc, root.interned_strings, &b"return 'a', 1.5, 1.5"[..]).unwrap();
+        assert_eq!(proto.constants.len(), 2);
+        proto.constants.push(Constant::Number(1.5));
+        let last = proto
+            .opcodes
+            .iter()
+            .rposition(|op| matches!(op, OpCode::LoadConstant { .. }))
+            .unwrap();
+        if let OpCode::LoadConstant { constant, .. } = &mut proto.opcodes[last] {
+            constant.0 = 2;
+        }
+        let optimized = optimize(mc, &proto, optimizations);
+        verify(&optimized).unwrap();
+        assert_eq!(optimized.constants.len(), 2);
+        let loaded = optimized
+            .opcodes
+            .iter()
+            .filter_map(|op| match op {
+                OpCode::LoadConstant { constant, .. } => {
+                    Some(optimized.constants[constant.0 as usize])
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            loaded,
+            vec![
+                Constant::String(String::new_static(b"a")),
+                Constant::Number(1.5),
+                Constant::Number(1.5)
+            ]
+        );
+    });
+}
@@ -0,0 +1,61 @@
+use luster::{Lua, Output, StaticError, Warnings};
+
+#[test]
+fn capture_output() -> Result<(), Box<StaticError>> {
+    let output = Output::capture();
+    let mut lua = Lua::builder().output(output.clone()).build();
+    lua.run_string(&b"print(1, 'two', nil) print()"[..])?;
+    assert_eq!(output.take_captured(), b"1\ttwo\tnil\n\n".to_vec());
+    assert_eq!(output.written(), 11);
+    assert!(output.take_captured().is_empty());
+    Ok(())
+}
+
+#[test]
+fn output_limit() {
+    let output = Output::capture().with_limit(24);
+    let mut lua = Lua::builder().output(output.clone()).build();
+
+    let err = lua
+        .run_string(&b"while true do print('spam') end"[..])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "runtime error: output limit exceeded");
+    assert_eq!(output.take_captured(), b"spam\nspam\nspam\nspam\n".to_vec());
+
+    // The quota stays exhausted, but the error can be caught.
+    let values = lua.run_string(&b"return pcall(print, 'x')"[..]).unwrap();
+    assert_eq!(values.len(), 2);
+
+    let err = lua
+        .run_string(&b"error('a very long error message that goes on')"[..])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "runtime error: a very long error messag..."
+    );
+}
+
+#[test]
+fn warnings() -> Result<(), Box<StaticError>> {
+    let warnings = Warnings::capture();
+    let output = Output::capture().with_warnings(warnings.clone());
+    let mut lua = Lua::builder().output(output.clone()).build();
+
+    lua.run_string(&b"warn('ignored') warn('@on') warn('a', 'b', 'c') warn('@unknown')"[..])?;
+    assert!(warnings.enabled());
+    lua.run_string(&b"warn('@x', 'y') warn('@off') warn('ignored')"[..])?;
+    assert!(!warnings.enabled());
+    assert_eq!(
+        warnings.take_captured(),
+        vec![b"abc".to_vec(), b"@xy".to_vec()]
+    );
+    assert!(output.take_captured().is_empty());
+
+    warnings.set_enabled(true);
+    warnings.warn(b"from the host");
+    assert_eq!(warnings.take_captured(), vec![b"from the host".to_vec()]);
+
+    assert!(lua.run_string(&b"warn('a', 1)"[..]).is_err());
+    assert!(lua.run_string(&b"warn()"[..]).is_err());
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use luster::{Lua, OwnedValue, StaticError, MAX_SNAPSHOT_DEPTH};
+
+#[test]
+fn run_string_returns() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    let values = lua.run_string(
+        &br#"
+            local t = {1, 2, x = "y"}
+            local r = {}
+            r.r = r
+            return nil, true, 3, 4.5, "five", t, r
+        "#[..],
+    )?;
+
+    assert_eq!(values.len(), 7);
+    assert_eq!(values[0], OwnedValue::Nil);
+    assert_eq!(values[1], OwnedValue::Boolean(true));
+    assert_eq!(values[2], OwnedValue::Integer(3));
+    assert_eq!(values[3], OwnedValue::Number(4.5));
+    assert_eq!(values[4], OwnedValue::String(b"five".to_vec()));
+
+    let t = &values[5];
+    assert_eq!(
+        t.get(&OwnedValue::Integer(1)),
+        Some(&OwnedValue::Integer(1))
+    );
+    assert_eq!(
+        t.get(&OwnedValue::Integer(2)),
+        Some(&OwnedValue::Integer(2))
+    );
+    assert_eq!(
+        t.get(&OwnedValue::String(b"x".to_vec())),
+        Some(&OwnedValue::String(b"y".to_vec()))
+    );
+
+    assert_eq!(
+        values[6].get(&OwnedValue::String(b"r".to_vec())),
+        Some(&OwnedValue::RecursiveTable)
+    );
+
+    assert!(lua.run_string(&b"error('fail')"[..]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn deeply_nested_tables() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    let values = lua.run_string(
+        &br#"
+            local t = {}
+            for i = 1, 1000000 do
+                local u = {}
+                u[1] = t
+                t = u
+            end
+            return t
+        "#[..],
+    )?;
+
+    let mut value = &values[0];
+    for _ in 0..MAX_SNAPSHOT_DEPTH {
+        value = value.get(&OwnedValue::Integer(1)).unwrap();
+    }
+    assert_eq!(value, &OwnedValue::DeepTable);
+
+    Ok(())
+}
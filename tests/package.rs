@@ -0,0 +1,149 @@
+use luster::{
+    compile, dump_proto, load_package_with_loader, path_candidates, search_path, ChunkSource, Lua,
+    OwnedValue, StdLib, String, Table, Value,
+};
+
+#[test]
+fn path_templates() {
+    assert_eq!(
+        path_candidates("a.b", "./?.lua;;lib/?/init.lua;?", ".", "/"),
+        vec!["./a/b.lua", "lib/a/b/init.lua", "a/b"]
+    );
+    assert_eq!(path_candidates("a.b", "?.lua", "", "/"), vec!["a.b.lua"]);
+    assert_eq!(path_candidates("a_b", "?-?", "_", "::"), vec!["a::b-a::b"]);
+}
+
+#[test]
+fn search_paths() {
+    let dir = std::env::temp_dir().join(format!("luster-package-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("mod/sub")).unwrap();
+    std::fs::write(dir.join("mod/sub/init.lua"), "return 1").unwrap();
+    let dir = dir.to_str().unwrap().to_owned();
+    let path = format!("{0}/?.lua;{0}/?/init.lua", dir);
+
+    assert_eq!(
+        search_path("mod.sub", &path, ".", "/"),
+        Ok(format!("{}/mod/sub/init.lua", dir))
+    );
+    // Directories are not modules.
+    assert_eq!(
+        search_path("mod", &format!("{}/?", dir), ".", "/"),
+        Err(vec![format!("{}/mod", dir)])
+    );
+
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            package: true,
+            ..StdLib::default()
+        })
+        .build();
+    let results = lua
+        .run_string(
+            format!(
+                r#"
+                    local path = "{0}/?.lua;{0}/?/init.lua"
+                    local missing, message = package.searchpath("missing", path)
+                    return
+                        package.searchpath("mod.sub", path),
+                        package.searchpath("mod-sub", path, "-"),
+                        missing,
+                        message,
+                        package.path
+                "#,
+                dir
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::String(format!("{}/mod/sub/init.lua", dir).into_bytes()),
+            OwnedValue::String(format!("{}/mod/sub/init.lua", dir).into_bytes()),
+            OwnedValue::Nil,
+            OwnedValue::String(
+                format!(
+                    "no file '{0}/missing.lua'\n\tno file '{0}/missing/init.lua'",
+                    dir
+                )
+                .into_bytes()
+            ),
+            OwnedValue::String(b"./?.lua;./?/init.lua".to_vec()),
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn require_host_modules() {
+    let dir = std::env::temp_dir().join(format!("luster-require-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("disk.lua"), "return ...").unwrap();
+    std::fs::write(dir.join("source.lua"), "return 'from disk'").unwrap();
+    let dir = dir.to_str().unwrap().to_owned();
+
+    let mut lua = Lua::new();
+    let bytecode = lua.mutate(|mc, root| {
+        dump_proto(&compile(mc, root.interned_strings, &b"return 'bytecode'"[..]).unwrap())
+    });
+    lua.mutate(move |mc, root| {
+        load_package_with_loader(mc, root, root.globals, move |name| match name {
+            "source" => Some(ChunkSource::Source(
+                b"count = (count or 0) + 1; return { name = ... }".to_vec(),
+            )),
+            "bytecode" => Some(ChunkSource::Bytecode(bytecode.clone())),
+            "rust" => Some(ChunkSource::Rust(|mc, _| {
+                let module = Table::new(mc);
+                module
+                    .set(mc, String::new_static(b"answer"), Value::Integer(42))
+                    .unwrap();
+                Value::Table(module)
+            })),
+            "empty" => Some(ChunkSource::Source(Vec::new())),
+            _ => None,
+        });
+    });
+
+    let results = lua
+        .run_string(
+            format!(
+                r#"
+                    package.path = "{0}/?.lua"
+                    local source = require("source")
+                    local again = require("source")
+                    local ok, message = pcall(require, "missing")
+                    return
+                        source.name, source == again, count, package.loaded.source == source,
+                        require("bytecode"), require("rust").answer, require("empty"),
+                        require("disk"), ok, message
+                "#,
+                dir
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::String(b"source".to_vec()),
+            OwnedValue::Boolean(true),
+            OwnedValue::Integer(1),
+            OwnedValue::Boolean(true),
+            OwnedValue::String(b"bytecode".to_vec()),
+            OwnedValue::Integer(42),
+            OwnedValue::Boolean(true),
+            OwnedValue::String(b"disk".to_vec()),
+            OwnedValue::Boolean(false),
+            OwnedValue::String(
+                format!(
+                    "module 'missing' not found:\n\tno file '{}/missing.lua'",
+                    dir
+                )
+                .into_bytes()
+            ),
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
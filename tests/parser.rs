@@ -1,11 +1,38 @@
+use std::thread;
+
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 use luster::parser::{
-    parse_chunk, Block, CallSuffix, Chunk, ConstructorField, Expression, FunctionCallStatement,
-    HeadExpression, PrimaryExpression, SimpleExpression, Statement, SuffixedExpression,
+    parse_chunk, parse_lexer, parse_lexer_located, parse_lexer_recovering,
+    parse_lexer_with_comments, parse_repl_line, parse_typed_chunk, Block, CallSuffix, Chunk,
+    ConstructorField, Expression, FunctionCallStatement, GotoStatement, HeadExpression,
+    LabelStatement, PrimaryExpression, SimpleExpression, Spanned, Statement, SuffixedExpression,
     TableConstructor,
 };
+use luster::{
+    compile_typed, parse_lexer_with_limits, Closure, CommentPlacement, Error, Function, Lexer,
+    LexerError, LexerLimit, LexerLimits, LineNumber, Lua, ParserError, ParserLimits, Span,
+    StatementStream, StreamedStatement, SyntaxError, ThreadSequence, Value,
+};
 
 #[test]
 fn test_function_call() {
+    let first_line = |start, end| Span {
+        line_number: LineNumber(1),
+        column: start as u64 + 1,
+        start,
+        end,
+    };
+    let print = |start| {
+        Spanned::new(
+            first_line(start, start + 5),
+            PrimaryExpression::Name("print".as_bytes().to_vec().into_boxed_slice()),
+        )
+    };
+    let simple = |start, end, simple| Expression {
+        span: first_line(start, end),
+        head: Box::new(HeadExpression::Simple(simple)),
+        tail: vec![],
+    };
     assert_eq!(
         parse_chunk("print(10, 20);print'foo';print{30.0}".as_bytes(), |s| s
             .to_vec()
@@ -14,66 +41,579 @@ fn test_function_call() {
         Chunk {
             block: Block {
                 statements: vec![
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![
-                            Expression {
-                                head: Box::new(HeadExpression::Simple(SimpleExpression::Integer(
-                                    10,
-                                ))),
-                                tail: vec![],
+                    Spanned::new(
+                        first_line(0, 13),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: print(0),
+                                suffixes: vec![],
                             },
-                            Expression {
-                                head: Box::new(HeadExpression::Simple(SimpleExpression::Integer(
-                                    20,
-                                ))),
-                                tail: vec![],
+                            call: CallSuffix::Function(vec![
+                                simple(6, 8, SimpleExpression::Integer(10)),
+                                simple(10, 12, SimpleExpression::Integer(20)),
+                            ]),
+                        })
+                    ),
+                    Spanned::new(
+                        first_line(14, 24),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: print(14),
+                                suffixes: vec![],
                             },
-                        ]),
-                    }),
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![Expression {
-                            head: Box::new(HeadExpression::Simple(SimpleExpression::String(
-                                "foo".as_bytes().to_vec().into_boxed_slice(),
-                            ))),
-                            tail: vec![],
-                        },]),
-                    }),
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![Expression {
-                            head: Box::new(HeadExpression::Simple(
+                            call: CallSuffix::Function(vec![simple(
+                                19,
+                                24,
+                                SimpleExpression::String(
+                                    "foo".as_bytes().to_vec().into_boxed_slice()
+                                ),
+                            )]),
+                        })
+                    ),
+                    Spanned::new(
+                        first_line(25, 36),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: print(25),
+                                suffixes: vec![],
+                            },
+                            call: CallSuffix::Function(vec![simple(
+                                30,
+                                36,
                                 SimpleExpression::TableConstructor(TableConstructor {
-                                    fields: vec![ConstructorField::Array(Expression {
-                                        head: Box::new(HeadExpression::Simple(
-                                            SimpleExpression::Float(30.0),
-                                        )),
-                                        tail: vec![],
-                                    }),],
+                                    span: first_line(30, 36),
+                                    fields: vec![ConstructorField::Array(simple(
+                                        31,
+                                        35,
+                                        SimpleExpression::Float(30.0),
+                                    ))],
                                 }),
-                            )),
-                            tail: vec![],
-                        },]),
-                    }),
+                            )]),
+                        })
+                    ),
                 ],
                 return_statement: None,
             },
+            chunk_name: None,
         }
     );
 }
+
+#[test]
+fn expression_spans() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let chunk = parse_chunk(
+        &b"local f = function(a)\n  return a.b:c(1) + 2\nend"[..],
+        to_vec,
+    )
+    .unwrap();
+    let values = match &chunk.block.statements[0].node {
+        Statement::LocalStatement(local) => &local.values,
+        statement => panic!("unexpected statement {:?}", statement),
+    };
+    assert_eq!(values[0].span.start, 10);
+    assert_eq!(values[0].span.end, 47);
+    let definition = match &*values[0].head {
+        HeadExpression::Simple(SimpleExpression::Function(definition)) => definition,
+        head => panic!("unexpected expression {:?}", head),
+    };
+    assert_eq!(definition.span, values[0].span);
+
+    let returned = &definition
+        .body
+        .return_statement
+        .as_ref()
+        .unwrap()
+        .node
+        .returns[0];
+    assert_eq!(returned.span.line_number, LineNumber(2));
+    assert_eq!(
+        (returned.span.column, returned.span.start, returned.span.end),
+        (10, 31, 43)
+    );
+    let suffixed = match &*returned.head {
+        HeadExpression::Simple(SimpleExpression::Suffixed(suffixed)) => suffixed,
+        head => panic!("unexpected expression {:?}", head),
+    };
+    assert_eq!(
+        (suffixed.primary.span.start, suffixed.primary.span.end),
+        (31, 32)
+    );
+    let suffixes = suffixed
+        .suffixes
+        .iter()
+        .map(|suffix| (suffix.span.start, suffix.span.end))
+        .collect::<Vec<_>>();
+    assert_eq!(suffixes, [(32, 34), (34, 39)]);
+    assert_eq!((suffixed.span().start, suffixed.span().end), (31, 39));
+}
+
+#[test]
+fn typed_chunk() {
+    let source = &br#"
+        local function iter<T>(list: {T}): ((({T}, number) -> (number?, T?)), {T}, number)
+            return function(l: {T}, i: number): (number?, T?)
+                if i < #l then
+                    return i + 1, l[i + 1]
+                end
+            end, list, 0
+        end
+        local function map<T, U>(list: {T}, f: (T) -> U?): {U}
+            local result: {U} = {}
+            for i: number, v: T in iter(list) do
+                result[i] = f(v) :: U
+            end
+            return result
+        end
+        local point: {x: number, [string]: any} = {x = 1}
+        local names: Map<string, Array<number>>, count: number? = nil, (2 :: any) :: number
+        local function sum(a: number, ...: number): (number, string | nil)
+            return a + (select(1, ...) :: number), nil
+        end
+        local doubled = map({1, 2, 3}, function(n: number): number return n * 2 end)
+        local total = 0
+        for i: number = 1, 3 do
+            total = total + doubled[i]
+        end
+        return sum(total, point.x, count) == 13
+    "#[..];
+
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    assert!(parse_chunk(source, to_vec).is_err());
+    assert!(parse_typed_chunk(source, to_vec).is_ok());
+    assert!(parse_typed_chunk(&b"local x: = 1"[..], to_vec).is_err());
+    assert!(parse_typed_chunk(&b"local x: {number"[..], to_vec).is_err());
+    // Annotations do not change how plain Lua parses.
+    assert!(parse_typed_chunk(&b"::top:: local t = {} t:insert(1) goto top"[..], to_vec).is_ok());
+
+    let mut lua = Lua::new();
+    let result = lua.sequence(|root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            Ok(Closure::new(
+                mc,
+                compile_typed(mc, root.interned_strings, source)?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|values| values == [Value::Boolean(true)])
+        .map_err(Error::to_static)
+        .boxed()
+    });
+    assert!(result.unwrap());
+}
+
+#[test]
+fn parse_configured_lexer() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let source = &b"local a = 'abcdef' return a"[..];
+    assert_eq!(
+        parse_lexer(Lexer::new(source, to_vec)).unwrap(),
+        parse_chunk(source, to_vec).unwrap()
+    );
+
+    let mut lexer = Lexer::new(source, to_vec);
+    lexer.set_limits(LexerLimits {
+        max_string_len: 4,
+        ..LexerLimits::default()
+    });
+    assert!(matches!(
+        parse_lexer(lexer),
+        Err(ParserError::LexerError(LexerError::LimitExceeded(
+            LexerLimit::StringLength
+        )))
+    ));
+
+    let mut lexer = Lexer::new(source, to_vec);
+    lexer.peek(3).unwrap();
+    assert_eq!(
+        parse_lexer(lexer).unwrap(),
+        parse_chunk(source, to_vec).unwrap()
+    );
+}
+
+// Renders an expression with every binary and unary operation in parentheses, and grouped
+// expressions without their own parentheses, so that two expressions render the same exactly when
+// they parse to the same operations.
+fn parenthesize(expression: &Expression<Box<[u8]>>) -> String {
+    let mut rendered = match &*expression.head {
+        HeadExpression::Simple(SimpleExpression::Integer(i)) => i.to_string(),
+        HeadExpression::Simple(SimpleExpression::Suffixed(suffixed)) => {
+            assert!(suffixed.suffixes.is_empty());
+            match &suffixed.primary.node {
+                PrimaryExpression::Name(name) => String::from_utf8_lossy(name).into_owned(),
+                PrimaryExpression::GroupedExpression(inner) => parenthesize(inner),
+            }
+        }
+        HeadExpression::UnaryOperator(op, operand) => {
+            format!("({:?} {})", op, parenthesize(operand))
+        }
+        head => panic!("unexpected expression {:?}", head),
+    };
+    for (op, right) in &expression.tail {
+        rendered = format!("({} {:?} {})", rendered, op, parenthesize(right));
+    }
+    rendered
+}
+
+fn parse_returned(source: &str) -> String {
+    let chunk = parse_chunk(format!("return {}", source).as_bytes(), |s| {
+        s.to_vec().into_boxed_slice()
+    })
+    .unwrap();
+    parenthesize(&chunk.block.return_statement.unwrap().node.returns[0])
+}
+
+#[test]
+fn operator_precedence() {
+    // The examples of the reference manual, along with the other precedence levels and both
+    // right-associative operators.
+    let equivalent = [
+        ("a+i < b/2+1", "(a+i) < ((b/2)+1)"),
+        ("5+x^2*8", "5+((x^2)*8)"),
+        ("a < y and y <= z", "(a < y) and (y <= z)"),
+        ("-x^2", "-(x^2)"),
+        ("x^y^z", "x^(y^z)"),
+        ("a .. b .. c", "a .. (b .. c)"),
+        ("a .. b + c .. d", "a .. ((b + c) .. d)"),
+        ("a - b - c", "(a - b) - c"),
+        ("a ~= b + c", "a ~= (b + c)"),
+        ("a < b ~= c == d", "((a < b) ~= c) == d"),
+        ("not a == b", "(not a) == b"),
+        ("-a ^ -b ^ c", "-(a ^ (-(b ^ c)))"),
+        ("#a .. b", "(#a) .. b"),
+        ("a or b and c or d", "(a or (b and c)) or d"),
+        ("a | b ~ c & d << e .. f", "a | (b ~ (c & (d << (e .. f))))"),
+        ("a << b >> c", "(a << b) >> c"),
+        ("~a & b", "(~a) & b"),
+        ("a // b * c % d", "((a // b) * c) % d"),
+    ];
+    for &(source, parenthesized) in &equivalent {
+        assert_eq!(
+            parse_returned(source),
+            parse_returned(parenthesized),
+            "{}",
+            source
+        );
+    }
+
+    assert_eq!(parse_returned("x^y^z"), "(x Pow (y Pow z))");
+    assert_eq!(parse_returned("-x^2 .. y"), "((Minus (x Pow 2)) Concat y)");
+}
+
+#[test]
+fn locate_syntax_errors() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let locate = |source: &'static str| {
+        let error = parse_lexer_located(Lexer::new(source.as_bytes(), to_vec)).unwrap_err();
+        (
+            error.span.line_number,
+            error.span.column,
+            error.span.start,
+            error.to_string(),
+        )
+    };
+
+    assert_eq!(
+        locate("local a = 1\nlocal = 2"),
+        (
+            LineNumber(2),
+            7,
+            18,
+            "2:7: found \"Assign\", expected name".to_owned()
+        )
+    );
+    assert_eq!(locate("x = 1\n  y = 'abc").0, LineNumber(2));
+    assert!(matches!(
+        parse_lexer_located(Lexer::new(&b"x = 1\n  y = 'abc"[..], to_vec))
+            .unwrap_err()
+            .error,
+        ParserError::LexerError(_)
+    ));
+    assert!(parse_lexer_located(Lexer::new(&b"return 1"[..], to_vec)).is_ok());
+}
+
+#[test]
+fn recover_from_syntax_errors() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let source = "local a = 1\nlocal = 2\nlocal b = 2\nif then x = 1 end\nwhile true do\n  local c = )\nend\nreturn a";
+    let (chunk, errors) = parse_lexer_recovering(Lexer::new(source.as_bytes(), to_vec));
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| e.span.line_number)
+            .collect::<Vec<_>>(),
+        vec![LineNumber(2), LineNumber(4), LineNumber(6)]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "2:7: found \"Assign\", expected name"
+    );
+    // The statements around the errors are kept.
+    assert_eq!(
+        chunk
+            .block
+            .statements
+            .iter()
+            .map(|s| s.span.line_number)
+            .collect::<Vec<_>>(),
+        vec![LineNumber(1), LineNumber(3), LineNumber(5)]
+    );
+    assert!(chunk.block.return_statement.is_some());
+
+    // Errors from the lexer and the parser are reported together, in source order.
+    let (_, errors) = parse_lexer_recovering(Lexer::new(&b"x = 1 $$\nlocal = 2"[..], to_vec));
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0].error, ParserError::LexerError(_)));
+    assert_eq!(errors[1].span.line_number, LineNumber(2));
+
+    // A stray `end` is reported, and a valid chunk has no errors at all.
+    let (_, errors) = parse_lexer_recovering(Lexer::new(&b"x = 1 end y = 2"[..], to_vec));
+    assert_eq!(errors.len(), 1);
+    let (chunk, errors) = parse_lexer_recovering(Lexer::new(&b"::a:: ::a:: return 1"[..], to_vec));
+    assert!(matches!(
+        errors[..],
+        [SyntaxError {
+            error: ParserError::DuplicateLabel,
+            ..
+        }]
+    ));
+    assert_eq!(chunk.block.statements.len(), 2);
+    assert!(parse_lexer_recovering(Lexer::new(&b"return 1"[..], to_vec))
+        .1
+        .is_empty());
+}
+
+#[test]
+fn attach_comments() {
+    let source = br#"-- Returns one.
+-- Really.
+local function f()
+    return 1 -- always
+end
+x = f() -- the result
+if x then -- checked
+    y = 2
+    -- nothing else
+end
+--[[ the end ]]"#;
+    let (chunk, comments) =
+        parse_lexer_with_comments(Lexer::with_comments(&source[..], |s| s.to_vec())).unwrap();
+    assert_eq!(chunk.block.statements.len(), 3);
+
+    let attached = comments
+        .iter()
+        .map(|comment| {
+            let statement = comment.statement.unwrap();
+            let statement = std::str::from_utf8(&source[statement.start..statement.end]).unwrap();
+            (
+                std::str::from_utf8(&comment.text).unwrap(),
+                statement.lines().next().unwrap(),
+                comment.placement,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attached,
+        [
+            (
+                " Returns one.",
+                "local function f()",
+                CommentPlacement::Leading
+            ),
+            (" Really.", "local function f()", CommentPlacement::Leading),
+            (" always", "return 1", CommentPlacement::Trailing),
+            (" the result", "x = f()", CommentPlacement::Trailing),
+            (
+                " checked",
+                "if x then -- checked",
+                CommentPlacement::Trailing
+            ),
+            (" nothing else", "y = 2", CommentPlacement::Trailing),
+            (
+                " the end ",
+                "if x then -- checked",
+                CommentPlacement::Trailing
+            ),
+        ]
+    );
+
+    let (_, comments) =
+        parse_lexer_with_comments(Lexer::with_comments(&b"-- empty"[..], |s| s.to_vec())).unwrap();
+    assert_eq!(comments[0].statement, None);
+}
+
+#[test]
+fn goto_and_labels() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let chunk = parse_chunk(&b"::top:: do ::top:: goto top end goto top"[..], to_vec).unwrap();
+    let statements = chunk
+        .block
+        .statements
+        .iter()
+        .map(|statement| statement.node.clone())
+        .collect::<Vec<_>>();
+    let name = || b"top".to_vec().into_boxed_slice();
+    assert_eq!(
+        statements,
+        [
+            Statement::Label(LabelStatement { name: name() }),
+            Statement::Do(Block {
+                statements: vec![
+                    Spanned::new(
+                        Span {
+                            line_number: LineNumber(1),
+                            column: 12,
+                            start: 11,
+                            end: 18,
+                        },
+                        Statement::Label(LabelStatement { name: name() })
+                    ),
+                    Spanned::new(
+                        Span {
+                            line_number: LineNumber(1),
+                            column: 20,
+                            start: 19,
+                            end: 27,
+                        },
+                        Statement::Goto(GotoStatement { name: name() })
+                    ),
+                ],
+                return_statement: None,
+            }),
+            Statement::Goto(GotoStatement { name: name() }),
+        ]
+    );
+
+    assert!(matches!(
+        parse_chunk(&b"::a:: ::b:: do ::a:: end ::a::"[..], to_vec),
+        Err(ParserError::DuplicateLabel)
+    ));
+    assert!(matches!(
+        parse_chunk(&b"::a:: ::a"[..], to_vec),
+        Err(ParserError::EndOfStream { .. })
+    ));
+}
+
+#[test]
+fn nesting_limit() {
+    // Parsing deeply nested expressions takes more stack in debug builds than test threads have.
+    thread::Builder::new()
+        .stack_size(16 << 20)
+        .spawn(check_nesting_limit)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn check_nesting_limit() {
+    let to_vec = |s: &[u8]| s.to_vec().into_boxed_slice();
+    let nested = |depth| format!("return {}1{}", "(".repeat(depth), ")".repeat(depth)).into_bytes();
+    let parse = |depth, max_depth| {
+        parse_lexer_with_limits(
+            Lexer::new(&nested(depth)[..], to_vec),
+            ParserLimits { max_depth },
+        )
+    };
+
+    assert!(parse_chunk(&nested(150)[..], to_vec).is_ok());
+    assert!(matches!(
+        parse_chunk(&nested(250)[..], to_vec),
+        Err(ParserError::RecursionLimit)
+    ));
+    assert!(parse(250, 400).is_ok());
+    assert!(parse(5, 10).is_ok());
+    assert!(matches!(parse(20, 10), Err(ParserError::RecursionLimit)));
+    assert!(matches!(
+        parse_lexer_with_limits(
+            Lexer::new(&b"if x then if y then end end"[..], to_vec),
+            ParserLimits { max_depth: 1 },
+        ),
+        Err(ParserError::RecursionLimit)
+    ));
+}
+
+#[test]
+fn repl_lines() {
+    let parse = |line: &str| parse_repl_line(line.as_bytes(), |s| s.to_vec().into_boxed_slice());
+
+    // Expressions are returned, spanning the whole line.
+    let chunk = parse("1 + 2, f(x)").unwrap();
+    assert!(chunk.block.statements.is_empty());
+    let return_statement = chunk.block.return_statement.unwrap();
+    assert_eq!(
+        (return_statement.span.start, return_statement.span.end),
+        (0, 11)
+    );
+    assert_eq!(return_statement.node.returns.len(), 2);
+
+    // Statements are parsed as usual.
+    let chunk = parse("x = 1").unwrap();
+    assert_eq!(chunk.block.statements.len(), 1);
+    assert!(chunk.block.return_statement.is_none());
+    assert!(parse("print(1) print(2)").unwrap().block.statements.len() == 2);
+    assert!(parse("return 1").unwrap().block.return_statement.is_some());
+    assert!(parse("").unwrap().block.statements.is_empty());
+
+    // Errors come from parsing the line as statements.
+    assert!(parse("x = {").unwrap_err().is_incomplete());
+    assert!(parse("if x then").unwrap_err().is_incomplete());
+    assert!(!parse("x = = 1").unwrap_err().is_incomplete());
+}
+
+#[test]
+fn statement_stream() {
+    let mut stream = StatementStream::new(|s: &[u8]| s.to_vec().into_boxed_slice());
+    let next = |stream: &mut StatementStream<_, _>| match stream.next_statement().unwrap() {
+        StreamedStatement::Statement(statement) => Some((statement.span.start, statement.span.end)),
+        StreamedStatement::Return(statement) => Some((statement.span.start, statement.span.end)),
+        StreamedStatement::Incomplete => None,
+        StreamedStatement::End => Some((0, 0)),
+    };
+
+    // Nothing is yielded until a statement can no longer be continued.
+    stream.fill(b"x = f");
+    assert_eq!(next(&mut stream), None);
+    stream.fill(b" ; if y then\n");
+    assert_eq!(next(&mut stream), Some((0, 5)));
+    assert_eq!(next(&mut stream), None);
+    stream.fill(b"  g(function() return 1 end)\n");
+    assert_eq!(next(&mut stream), None);
+    stream.fill(b"end ::top:: ");
+    assert_eq!(next(&mut stream), Some((8, 50)));
+    // A label cannot be continued, so it needs no token after it.
+    assert_eq!(next(&mut stream), Some((51, 58)));
+    assert_eq!(next(&mut stream), None);
+    stream.fill(b"return x");
+    assert_eq!(next(&mut stream), None);
+    stream.finish();
+    assert_eq!(next(&mut stream), Some((59, 67)));
+    assert_eq!(next(&mut stream), Some((0, 0)));
+    assert_eq!(next(&mut stream), Some((0, 0)));
+
+    let parse = |source: &[u8]| {
+        let mut stream = StatementStream::new(|s: &[u8]| s.to_vec().into_boxed_slice());
+        stream.fill(source);
+        stream.finish();
+        loop {
+            match stream.next_statement() {
+                Ok(StreamedStatement::End) => return Ok(()),
+                Ok(_) => {}
+                Err(error) => return Err(error),
+            }
+        }
+    };
+    assert!(parse(b"local a = 1 return a;").is_ok());
+    assert!(parse(b"return 1 x = 2").is_err());
+    assert!(parse(b"x = 1 end").is_err());
+    assert!(parse(b"::a:: ::a::").is_err());
+    assert!(parse(b"if x then").unwrap_err().error.is_incomplete());
+    assert!(!parse(b"x = = 1").unwrap_err().error.is_incomplete());
+}
@@ -0,0 +1,49 @@
+use luster::prelude::*;
+
+#[test]
+fn prelude_embedding() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::builder().stdlib(StdLib::all()).build();
+    let results = lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            let length = Callback::new_immediate(mc, |args| match args.first() {
+                Some(Value::String(s)) => Ok(CallbackResult::Return(vec![
+                    Value::Integer(s.as_bytes().len() as i64),
+                    true.into(),
+                ])),
+                _ => Ok(CallbackResult::Return(vec![Value::Nil])),
+            });
+            root.globals
+                .set(mc, LuaString::new_static(b"length"), length)?;
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &b"local n, ok = length('lua') return n * 2, ok, 0.5"[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|values| values.into_iter().map(OwnedValue::from).collect::<Vec<_>>())
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::Integer(6),
+            OwnedValue::Boolean(true),
+            OwnedValue::Number(0.5)
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use luster::parser::{Chunk, Expression, HeadExpression, PrimaryExpression, SimpleExpression};
+use luster::print::{print_chunk, PrintOptions, Quote};
+use luster::visit::{walk_expression_mut, VisitorMut};
+use luster::{parse_chunk, Lua};
+
+fn parse(source: &[u8]) -> Chunk<Vec<u8>> {
+    parse_chunk(source, |s| s.to_vec()).unwrap()
+}
+
+fn print(chunk: &Chunk<Vec<u8>>) -> String {
+    String::from_utf8(print_chunk(chunk, &PrintOptions::default())).unwrap()
+}
+
+#[test]
+fn print_round_trip() {
+    let source = br#"
+        local t, u = { 1, 2.5, "a\n\"b\"\0", x = 1e100, ["y z"] = -0.0, g = {} }, nil
+        local function f(a, b, ...) return a, b, #{ ... } end
+        function t.g.h(self) return (f(1, 2, 3)) end
+        function t:m() return self end
+        t.g = { h = t.g.h }
+        local s = 0
+        for i = 10, 1, -1 do s = s + i end
+        local function iter(_, i) if i < 3 then return i + 1, i * 2 end end
+        for k, v in iter, nil, 0 do s = s + k * v end
+        while s > 100 do s = s - 1 break end
+        repeat s = s + 1 until s % 7 == 0
+        do local s = "shadowed" end
+        goto skip
+        s = nil
+        ::skip::
+        if s == nil then s = 0 elseif not (s > 1) then s = 1 else s = s .. "" end
+        local a = 2 ^ 3 ^ 2 .. 1 .. 2
+        local b = (1 + 2) * 3 - - -4 // 2 % 3
+        local c = 1 < 2 and not (3 ~= 3) or 5 & 6 | 7 ~ 8 << 1 >> 1
+        local d = ~5 + #{ 1, 2, 3 } - (-2) ^ 2
+        ;(t):m()
+        return s, a, b, c, d, t.g.h(0) == 0, t:m() == t, f(1)
+    "#;
+
+    let printed = print(&parse(source));
+    // Printing is a fixed point after the first time.
+    assert_eq!(print(&parse(printed.as_bytes())), printed);
+
+    let mut lua = Lua::new();
+    let expected = lua.run_string(source).unwrap();
+    let mut lua = Lua::new();
+    assert_eq!(lua.run_string(printed.as_bytes()).unwrap(), expected);
+}
+
+#[test]
+fn print_options() {
+    let chunk = parse(b"if x then while y do f('a', \"b'c\") end end");
+    assert_eq!(
+        print(&chunk),
+        "if x then\n    while y do\n        f(\"a\", \"b'c\")\n    end\nend\n"
+    );
+    assert_eq!(
+        print_chunk(
+            &chunk,
+            &PrintOptions {
+                indent: "\t".to_owned(),
+                quote: Quote::Single,
+            }
+        ),
+        b"if x then\n\twhile y do\n\t\tf('a', 'b\\'c')\n\tend\nend\n".to_vec()
+    );
+    assert_eq!(
+        print_chunk(
+            &chunk,
+            &PrintOptions {
+                quote: Quote::Fewest,
+                ..PrintOptions::default()
+            }
+        ),
+        b"if x then\n    while y do\n        f(\"a\", \"b'c\")\n    end\nend\n".to_vec()
+    );
+}
+
+// Replaces every use of the variable `x` with the expression `a + b`, and negates every integer
+// literal.  An `x` at the head of a chain of operators becomes the head of the sum, followed by the
+// rest of the chain.
+struct Substitute(Expression<Vec<u8>>);
+
+impl VisitorMut<Vec<u8>> for Substitute {
+    fn visit_expression_mut(&mut self, expression: &mut Expression<Vec<u8>>) {
+        if let HeadExpression::Simple(SimpleExpression::Suffixed(suffixed)) = &*expression.head {
+            if suffixed.primary.node == PrimaryExpression::Name(b"x".to_vec())
+                && suffixed.suffixes.is_empty()
+            {
+                let tail = std::mem::take(&mut expression.tail);
+                *expression = self.0.clone();
+                expression.tail.extend(tail);
+            }
+        }
+        if let HeadExpression::Simple(SimpleExpression::Integer(i)) = &mut *expression.head {
+            *i = -*i;
+        }
+        walk_expression_mut(self, expression);
+    }
+}
+
+#[test]
+fn print_rewritten_expressions() {
+    let sum = match parse(b"return a + b").block.return_statement {
+        Some(return_statement) => return_statement.node.returns[0].clone(),
+        None => unreachable!(),
+    };
+    let mut chunk = parse(b"return 2 * x, x ^ 2, -x, -x ^ 2, x .. x, x + x, x * 3 - 4");
+    Substitute(sum).visit_chunk_mut(&mut chunk);
+    assert_eq!(
+        print(&chunk),
+        "return (-2) * (a + b), (a + b) ^ (-2), -(a + b), -(a + b) ^ (-2), a + b .. a + b, \
+         a + b + (a + b), (a + b) * (-3) - (-4)\n"
+    );
+}
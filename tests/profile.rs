@@ -0,0 +1,154 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, CallStats, Closure, Error, Function, Lua, ProfileEntry, ProfiledFunction, Profiler,
+    StaticError, String, ThreadSequence, Value,
+};
+
+// Runs `source` with a profiler whose clock advances by a millisecond every time it is read, so
+// that every call takes one millisecond per clock reading made during it.
+fn profile(source: &'static [u8]) -> Result<Vec<ProfileEntry>, StaticError> {
+    let mut lua = Lua::new();
+    lua.sequence(move |root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            let millis = Rc::new(Cell::new(0));
+            let profiler = Profiler::with_clock(mc, move || {
+                millis.set(millis.get() + 1);
+                Duration::from_millis(millis.get() - 1)
+            });
+            root.main_thread.set_profiler(mc, Some(profiler));
+            Ok(Closure::new(
+                mc,
+                compile(mc, root.interned_strings, source)?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .and_then_with(root, |_, root, _| {
+            Ok(root.main_thread.profiler().unwrap().report(root.globals))
+        })
+        .map_err(Error::to_static)
+        .boxed()
+    })
+}
+
+fn entry(name: &str, calls: u64, millis: u64) -> ProfileEntry {
+    ProfileEntry {
+        name: name.to_owned(),
+        calls,
+        time: Duration::from_millis(millis),
+    }
+}
+
+#[test]
+fn profile_calls() -> Result<(), StaticError> {
+    let report = profile(
+        &br#"local function leaf(x) return x + 1 end
+            function work(n)
+                local s = 0
+                for i = 1, n do
+                    s = leaf(s)
+                end
+                return s
+            end
+            work(3)
+            return string.len("abc")
+        "#[..],
+    )?;
+    assert_eq!(
+        report,
+        vec![
+            // The main chunk ends when it tail calls `string.len`.
+            entry("function at line 1", 1, 9),
+            entry("work", 1, 7),
+            entry("function at line 1", 3, 3),
+            entry("string.len", 1, 1),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn profile_errors_and_sequences() -> Result<(), StaticError> {
+    let report = profile(
+        &br#"local function fail() error("fail") end
+            pcall(fail)
+            local co = coroutine.create(function() coroutine.yield() end)
+            coroutine.resume(co)
+        "#[..],
+    )?;
+    // The coroutine function never returns, so its time is not counted.
+    assert_eq!(
+        report,
+        vec![
+            entry("function at line 1", 1, 14),
+            entry("coroutine.resume", 1, 4),
+            entry("function at line 1", 1, 3),
+            entry("coroutine.create", 1, 1),
+            entry("coroutine.yield", 1, 1),
+            entry("error", 1, 1),
+            entry("pcall", 1, 1),
+            entry("function at line 3", 1, 0),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn profiler_stats() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let profiler = Profiler::new(mc);
+        root.main_thread.set_profiler(mc, Some(profiler));
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &b"for i = 1, 5 do print() end"[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+        while root.main_thread.take_results(mc).is_none() {
+            root.main_thread.step(mc).unwrap();
+        }
+
+        let print = match root.globals.get(String::new_static(b"print")) {
+            Value::Function(Function::Callback(print)) => print,
+            _ => panic!("print is not a callback"),
+        };
+        assert_eq!(
+            profiler
+                .stats(ProfiledFunction::Callback(print))
+                .map(|s| s.calls),
+            Some(5)
+        );
+        assert_eq!(
+            profiler
+                .stats(ProfiledFunction::Lua(closure.0.proto))
+                .map(|s| s.calls),
+            Some(1)
+        );
+        assert_eq!(profiler.entries().len(), 2);
+        let CallStats { calls, .. } = profiler.entries()[0].1;
+        assert_eq!(calls, 1);
+        assert!(profiler
+            .dump(root.globals)
+            .starts_with("name\tcalls\tmicroseconds\n"));
+    });
+}
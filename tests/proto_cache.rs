@@ -0,0 +1,69 @@
+use gc_arena::Gc;
+use luster::{compile, Closure, Function, Lua, PrototypeCache, ThreadMode, Value};
+
+#[test]
+fn shares_nested_prototypes() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let cache = PrototypeCache::new(mc);
+        let chunks = (1..=3)
+            .map(|i| {
+                let source = format!("local function inc(x) return x + 1 end\nreturn inc({})", i);
+                let proto = compile(mc, root.interned_strings, source.as_bytes()).unwrap();
+                cache.share(mc, root.interned_strings, Gc::allocate(mc, proto))
+            })
+            .collect::<Vec<_>>();
+
+        assert!(Gc::ptr_eq(chunks[0].prototypes[0], chunks[1].prototypes[0]));
+        assert!(Gc::ptr_eq(chunks[0].prototypes[0], chunks[2].prototypes[0]));
+        assert!(!Gc::ptr_eq(chunks[0], chunks[1]));
+        assert_eq!(cache.len(), 4);
+
+        let again = compile(
+            mc,
+            root.interned_strings,
+            &b"local function inc(x) return x + 1 end\nreturn inc(2)"[..],
+        )
+        .unwrap();
+        let again = cache.share(mc, root.interned_strings, Gc::allocate(mc, again));
+        assert!(Gc::ptr_eq(again, chunks[1]));
+        assert_eq!(cache.len(), 4);
+
+        for (i, &proto) in chunks.iter().enumerate() {
+            let closure = Closure::new_with_proto(mc, proto, Some(root.globals)).unwrap();
+            root.main_thread
+                .start(mc, Function::Closure(closure), &[])
+                .unwrap();
+            while root.main_thread.mode() == ThreadMode::Running {
+                root.main_thread.step(mc).unwrap();
+            }
+            assert_eq!(
+                root.main_thread.take_results(mc).unwrap().unwrap(),
+                vec![Value::Integer(i as i64 + 2)]
+            );
+        }
+    });
+}
+
+#[test]
+fn line_information_is_not_shared() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let cache = PrototypeCache::new(mc);
+        let a = compile(
+            mc,
+            root.interned_strings,
+            &b"return function() return 'a' end"[..],
+        )
+        .unwrap();
+        let b = compile(
+            mc,
+            root.interned_strings,
+            &b"\nreturn function() return 'a' end"[..],
+        )
+        .unwrap();
+        let a = cache.share(mc, root.interned_strings, Gc::allocate(mc, a));
+        let b = cache.share(mc, root.interned_strings, Gc::allocate(mc, b));
+        assert!(!Gc::ptr_eq(a.prototypes[0], b.prototypes[0]));
+    });
+}
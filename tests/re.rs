@@ -0,0 +1,83 @@
+#![cfg(feature = "re")]
+
+use luster::re::load_re;
+use luster::{Lua, OwnedValue, StaticError};
+
+fn run(source: &str) -> Result<Vec<OwnedValue>, Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| load_re(mc, root, root.globals));
+    Ok(lua.run_string(source.as_bytes())?)
+}
+
+fn string(s: &str) -> OwnedValue {
+    OwnedValue::String(s.as_bytes().to_vec())
+}
+
+#[test]
+fn re_match() -> Result<(), Box<StaticError>> {
+    let values = run(r#"
+        local date = re.compile([[(\d{4})-(\d{2})-(\d{2})]])
+        local y, m, d = date:match("released on 2019-03-14")
+        return y, m, d,
+            re.match("[a-z]+", "123 abc def", 8),
+            re.match("[a-z]+", "abc def", -2),
+            re.match("x(y)?", "x"),
+            re.match("z", "abc")
+    "#)?;
+    assert_eq!(
+        values,
+        vec![
+            string("2019"),
+            string("03"),
+            string("14"),
+            string("def"),
+            string("ef"),
+            OwnedValue::Nil,
+            OwnedValue::Nil,
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn re_gsub() -> Result<(), Box<StaticError>> {
+    let values = run(r#"
+        local s1, n1 = re.gsub("(?P<key>\\w+)=(\\w+)", "a=1, b=2, c=3", "$2=${key}")
+        local s2, n2 = re.compile("o"):gsub("foo boo", "0", 3)
+        return s1, n1, s2, n2, pcall(re.compile, "(unclosed")
+    "#)?;
+    assert_eq!(
+        values[..5],
+        [
+            string("1=a, 2=b, 3=c"),
+            OwnedValue::Integer(3),
+            string("f00 b0o"),
+            OwnedValue::Integer(3),
+            OwnedValue::Boolean(false),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn re_gsub_max_string_len() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        load_re(mc, root, root.globals);
+        root.main_thread.set_max_string_len(mc, 8);
+    });
+    let values = lua.run_string(
+        &br#"
+            return re.gsub("o", "foo", "00"), pcall(re.gsub, "o", "foooo", "00")
+        "#[..],
+    )?;
+    assert_eq!(
+        values,
+        [
+            string("f0000"),
+            OwnedValue::Boolean(false),
+            string("resulting string too large"),
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,134 @@
+#![cfg(feature = "remote")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use luster::remote::{RemoteRepl, RemoteReplConfig, Sandbox};
+use luster::{Lua, String, Value};
+
+fn read_line(
+    lua: &mut Lua,
+    repl: &mut RemoteRepl,
+    reader: &mut BufReader<TcpStream>,
+) -> std::string::String {
+    let mut line = std::string::String::new();
+    for _ in 0..1000 {
+        lua.mutate(|mc, root| repl.poll(mc, root, root.globals).unwrap());
+        if reader.read_line(&mut line).is_ok() && line.ends_with('\n') {
+            line.pop();
+            return line;
+        }
+    }
+    panic!("no response from remote repl");
+}
+
+#[test]
+fn remote_repl() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        root.globals
+            .set(mc, String::new_static(b"health"), 100)
+            .unwrap();
+    });
+
+    let mut config = RemoteReplConfig::new("secret");
+    config.sandbox = Sandbox::Live;
+    config.max_steps = 4;
+    let mut repl = RemoteRepl::bind("127.0.0.1:0", config).unwrap();
+
+    let mut stream = TcpStream::connect(repl.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    stream
+        .write_all(b"secret\nhealth\nhealth = health - 1\nhealth, {1, 2}\n")
+        .unwrap();
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "100");
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "");
+    assert_eq!(
+        read_line(&mut lua, &mut repl, &mut reader),
+        "99\t{[1] = 1, [2] = 2}"
+    );
+
+    stream.write_all(b"return {\n3 }\n").unwrap();
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "{[1] = 3}");
+
+    // Unfinished long strings and comments are continued on the next line.
+    stream.write_all(b"--[[ a\nb ]] [[x\ny]]\n").unwrap();
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "\"x\\ny\"");
+
+    stream
+        .write_all(b"local n = 7 while true do end\n")
+        .unwrap();
+    assert_eq!(
+        read_line(&mut lua, &mut repl, &mut reader),
+        "error: runtime error: remote chunk exceeded its instruction budget at line 1 (locals: 7)"
+    );
+
+    stream.write_all(b"health + \n\n").unwrap();
+    assert!(read_line(&mut lua, &mut repl, &mut reader).starts_with("error: "));
+
+    lua.mutate(|_, root| {
+        assert_eq!(
+            root.globals.get(String::new_static(b"health")),
+            Value::Integer(99)
+        );
+    });
+}
+
+#[test]
+fn remote_repl_sandbox_and_auth() {
+    let mut lua = Lua::new();
+    let mut repl = RemoteRepl::bind("127.0.0.1:0", RemoteReplConfig::new("secret")).unwrap();
+
+    let mut stream = TcpStream::connect(repl.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"secret\nx = 1\nx\n").unwrap();
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "");
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "nil");
+
+    let mut stream = TcpStream::connect(repl.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"wrong\n1 + 1\n").unwrap();
+    assert_eq!(
+        read_line(&mut lua, &mut repl, &mut reader),
+        "error: bad auth token"
+    );
+}
+
+#[test]
+fn remote_repl_line_limit() {
+    let mut lua = Lua::new();
+    let mut config = RemoteReplConfig::new("secret");
+    config.max_line_len = 16;
+    let mut repl = RemoteRepl::bind("127.0.0.1:0", config).unwrap();
+
+    // The limit applies before the client has authenticated, even to unfinished lines.
+    let mut stream = TcpStream::connect(repl.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(&[b'a'; 17]).unwrap();
+    assert_eq!(
+        read_line(&mut lua, &mut repl, &mut reader),
+        "error: line too long"
+    );
+
+    let mut stream = TcpStream::connect(repl.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"secret\n12345 + 6789\n").unwrap();
+    assert_eq!(read_line(&mut lua, &mut repl, &mut reader), "19134");
+}
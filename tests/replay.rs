@@ -0,0 +1,100 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use luster::{Lua, OwnedValue, Replay, ReplayError, ReplayEvent, RuntimeError, String, Value};
+
+const SCRIPT: &[u8] = br#"
+    local rolls = {}
+    for i = 1, 4 do
+        rolls[i] = math.random(1, 1000000)
+    end
+    local ok, err = pcall(sensor)
+    return rolls[1], rolls[2], rolls[3], rolls[4], sensor(), sensor(), ok, err
+"#;
+
+// Runs `SCRIPT` with a `sensor` host callback whose results depend on `readings`.
+fn run(replay: Replay, readings: Rc<Cell<i64>>) -> Vec<OwnedValue> {
+    let mut lua = Lua::builder().replay(replay.clone()).build();
+    lua.mutate(move |mc, root| {
+        let sensor = replay.callback(mc, move |mc, _| {
+            let reading = readings.get();
+            readings.set(reading + 1);
+            if reading == 0 {
+                return Err(RuntimeError(Value::String(String::new(mc, b"warming up"))).into());
+            }
+            Ok(vec![
+                Value::Integer(reading),
+                Value::String(String::new(mc, format!("reading {}", reading).as_bytes())),
+            ])
+        });
+        root.globals
+            .set(mc, String::new_static(b"sensor"), sensor)
+            .unwrap();
+    });
+    lua.run_string(SCRIPT).unwrap()
+}
+
+#[test]
+fn record_and_replay() {
+    let recording = Replay::record();
+    let recorded = run(recording.clone(), Rc::new(Cell::new(0)));
+    assert_eq!(
+        &recorded[4..],
+        &[
+            OwnedValue::Integer(1),
+            OwnedValue::Integer(2),
+            OwnedValue::Boolean(false),
+            OwnedValue::String(b"warming up".to_vec()),
+        ]
+    );
+
+    let events = recording.events();
+    assert_eq!(events.len(), 4);
+    assert!(matches!(events[0], ReplayEvent::RandomSeed(_)));
+    assert_eq!(
+        events[1],
+        ReplayEvent::Callback {
+            sequence: 0,
+            results: Err(OwnedValue::String(b"warming up".to_vec())),
+        }
+    );
+
+    // The sensor now reads differently, but is never called while replaying.
+    let readings = Rc::new(Cell::new(100));
+    let replay = Replay::load(&recording.to_bytes()).unwrap();
+    assert_eq!(replay.events(), events);
+    assert_eq!(run(replay, readings.clone()), recorded);
+    assert_eq!(readings.get(), 100);
+}
+
+#[test]
+fn replay_divergence() {
+    let replay = Replay::from_events(vec![ReplayEvent::Time(1.5)]);
+    assert_eq!(
+        replay.time(|| panic!("clock read while replaying")),
+        Ok(1.5)
+    );
+    assert_eq!(replay.time(|| 0.0), Err(ReplayError::Exhausted));
+
+    let recording = Replay::record();
+    assert_eq!(recording.time(|| 2.0), Ok(2.0));
+    let mut lua = Lua::builder()
+        .replay(Replay::load(&recording.to_bytes()).unwrap())
+        .build();
+    let err = lua.run_string(b"return math.random()").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "replay error: replay diverged, expected a random seed input but the recording has a \
+         time input"
+    );
+
+    assert_eq!(
+        Replay::load(b"not a replay").unwrap_err(),
+        ReplayError::BadHeader
+    );
+    let bytes = recording.to_bytes();
+    assert_eq!(
+        Replay::load(&bytes[..bytes.len() - 1]).unwrap_err(),
+        ReplayError::Truncated
+    );
+}
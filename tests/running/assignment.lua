@@ -0,0 +1,104 @@
+local function test_local_swap()
+    local a, b = 1, 2
+    a, b = b, a
+    return a == 2 and b == 1
+end
+
+local function test_global_swap()
+    ga, gb = 1, 2
+    ga, gb = gb, ga
+    local result = ga == 2 and gb == 1
+    ga, gb = nil, nil
+    return result
+end
+
+local function test_upvalue_swap()
+    local a, b = 1, 2
+    local function swap()
+        a, b = b, a
+    end
+    swap()
+    return a == 2 and b == 1
+end
+
+local function test_table_swap()
+    local t = {1, 2, 3}
+    local i, j = 1, 3
+    t[i], t[j] = t[j], t[i]
+    return t[1] == 3 and t[2] == 2 and t[3] == 1
+end
+
+local function test_rotate()
+    local a, b, c = 1, 2, 3
+    a, b, c = b, c, a
+    return a == 2 and b == 3 and c == 1
+end
+
+local function test_index_before_assign()
+    -- The index expressions use the values `i` had before the statement.
+    local t = {}
+    local i = 1
+    i, t[i] = i + 1, 20
+    return i == 2 and t[1] == 20 and t[2] == nil
+end
+
+local function test_table_before_assign()
+    local t = {}
+    local u = {}
+    local a = t
+    a, a.x = u, 1
+    return a == u and t.x == 1 and u.x == nil
+end
+
+local function test_side_effecting_indexes()
+    local log = {}
+    local function key(k)
+        log[#log + 1] = k
+        return k
+    end
+    local t = {}
+    t[key(1)], t[key(2)] = key(3), key(4)
+    return log[1] == 1 and log[2] == 2 and log[3] == 3 and log[4] == 4 and
+        t[1] == 3 and t[2] == 4
+end
+
+local function test_multiple_returns()
+    local function f()
+        return 1, 2, 3
+    end
+    local t = {}
+    local a
+    a, t.b, t.c = f()
+    return a == 1 and t.b == 2 and t.c == 3
+end
+
+local function test_missing_values()
+    local a, b, c = 1, 2, 3
+    local t = {x = 1}
+    a, b, c, t.x = 4
+    return a == 4 and b == nil and c == nil and t.x == nil
+end
+
+local function test_extra_values()
+    local calls = 0
+    local function f()
+        calls = calls + 1
+        return calls
+    end
+    local a, b
+    a, b = f(), f(), f(), f()
+    return a == 1 and b == 2 and calls == 4
+end
+
+return
+    test_local_swap() and
+    test_global_swap() and
+    test_upvalue_swap() and
+    test_table_swap() and
+    test_rotate() and
+    test_index_before_assign() and
+    test_table_before_assign() and
+    test_side_effecting_indexes() and
+    test_multiple_returns() and
+    test_missing_values() and
+    test_extra_values()
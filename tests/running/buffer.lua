@@ -0,0 +1,46 @@
+local function test1()
+    local b = buffer.new()
+    b:put("a", 1, "b"):put(2.5)
+    return b:tostring() == "a1b2.5" and b:len() == 6
+end
+
+local function test2()
+    local b = buffer.new()
+    for i = 1, 1000 do
+        b:put("x")
+    end
+    local n = b:len()
+    b:reset()
+    return n == 1000 and b:len() == 0 and b:tostring() == ""
+end
+
+local function test3()
+    local b = buffer.new()
+    b:putf("%d|%5d|%-5d|%05d|%+d|%.3d", 42, 42, 42, -42, 7, 5)
+    b:putf("|%x|%X|%#x|%o|%c", 255, 255, 255, 8, 65)
+    b:putf("|%.2f|%8.3f|%e|%g|%g|%g", 3.14159, -2.5, 12345.678, 0.0001, 1e20, 100)
+    b:putf("|%s|%.2s|%5s|%%", "str", "abc", 1)
+    return b:tostring() ==
+        "42|   42|42   |-0042|+7|005" ..
+        "|ff|FF|0xff|10|A" ..
+        "|3.14|  -2.500|1.234568e+04|0.0001|1e+20|100" ..
+        "|str|ab|    1|%"
+end
+
+local function test4()
+    local b = buffer.new()
+    return not pcall(b.put, b, {}) and
+        not pcall(b.putf, b, "%d", 1.5) and
+        not pcall(b.putf, b, "%d") and
+        not pcall(b.putf, b, "%y", 1)
+end
+
+local function test5()
+    local a = buffer.new()
+    local b = buffer.new()
+    a:put("a")
+    b:put("b")
+    return a:tostring() == "a" and b:tostring() == "b"
+end
+
+return test1() and test2() and test3() and test4() and test5()
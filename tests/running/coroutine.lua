@@ -41,6 +41,26 @@ function test2()
         e2 == false and r2 == 'test error' and s2 == "dead"
 end
 
+function test3()
+    local co = coroutine.create(function(a) coroutine.yield(a) error('test error') end)
+    local e1 = pcall(coroutine.reset, co, print)
+    coroutine.resume(co, 1)
+    local e2 = pcall(coroutine.reset, co, print)
+    coroutine.resume(co)
+
+    local co2 = coroutine.reset(co, function(a, b) coroutine.yield(a + b) return a * b end)
+    local e3, r3 = coroutine.resume(co, 3, 4)
+    local s3 = coroutine.status(co)
+    local e4, r4 = coroutine.resume(co)
+    local s4 = coroutine.status(co)
+
+    return
+        e1 == false and e2 == false and co2 == co and
+        e3 == true and r3 == 7 and s3 == "suspended" and
+        e4 == true and r4 == 12 and s4 == "dead"
+end
+
 return
     test1() and
-    test2()
+    test2() and
+    test3()
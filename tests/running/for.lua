@@ -81,9 +81,29 @@ function test_break_scope()
     return true
 end
 
+function test_numeric_errors()
+    local function loop_error(initial, limit, step)
+        local ok, err = pcall(function()
+            for i = initial, limit, step do
+                return "looped"
+            end
+        end)
+        return not ok and err
+    end
+
+    return
+        loop_error(1, 10, 0) == "for loop error: 'for' step is zero" and
+        loop_error(1.0, 10, 0.0) == "for loop error: 'for' step is zero" and
+        loop_error(1, {}, 1) == "for loop error: 'for' limit must be a number" and
+        loop_error(1, 10, "x") == "for loop error: 'for' step must be a number" and
+        loop_error(nil, 10, 1) == "for loop error: 'for' initial value must be a number" and
+        loop_error(1, 10, -1) == false
+end
+
 return
     test_generic() and
     test_numeric() and
     test_numeric_closure() and
     test_generic_closure() and
-    test_break_scope()
+    test_break_scope() and
+    test_numeric_errors()
@@ -0,0 +1,106 @@
+local function test_extra_returns()
+    local function five(_, c)
+        if c < 3 then
+            return c + 1, c + 10, c + 20, c + 30, c + 40
+        end
+    end
+    local after = "untouched"
+    local sum = 0
+    for i, j in five, nil, 0 do
+        sum = sum + i + j
+    end
+    local sum3 = 0
+    for a, b, c in five, nil, 0 do
+        local guard = "guard"
+        sum3 = sum3 + a + b + c
+        if guard ~= "guard" then return false end
+    end
+    return sum == 6 + 33 and sum3 == 6 + 33 + 63 and after == "untouched"
+end
+
+local function test_missing_returns()
+    local function one(_, c)
+        if c < 3 then
+            return c + 1
+        end
+    end
+    local count = 0
+    for i, j, k in one, nil, 0 do
+        if j ~= nil or k ~= nil then
+            return false
+        end
+        count = count + i
+    end
+    return count == 6
+end
+
+local function test_no_returns()
+    local function none()
+    end
+    local ran = false
+    for i, j in none do
+        ran = true
+    end
+    return not ran
+end
+
+local function test_callback_iterator()
+    -- `pcall` is a callback, and returns one more value than the function it calls.
+    local function five()
+        return 1, 2, 3, 4, 5
+    end
+    local function none()
+    end
+    local a1, b1, c1, a2, b2, c2
+    for ok, b, c in pcall, five do
+        a1, b1, c1 = ok, b, c
+        break
+    end
+    for ok, b, c in pcall, none do
+        a2, b2, c2 = ok, b, c
+        break
+    end
+    return a1 == true and b1 == 1 and c1 == 2 and a2 == true and b2 == nil and c2 == nil
+end
+
+local function test_control_variable()
+    -- The value passed back to the iterator is the first value it returned, even if the loop
+    -- variable is assigned in the body.
+    local seen = {}
+    local function iter(_, c)
+        seen[#seen + 1] = c
+        if c < 3 then
+            return c + 1
+        end
+    end
+    for i in iter, nil, 0 do
+        i = i * 100
+    end
+    return seen[1] == 0 and seen[2] == 1 and seen[3] == 2 and seen[4] == 3
+end
+
+local function test_false_control()
+    -- Only nil ends the loop, an iterator may return false as its first value.
+    local function falses(_, c)
+        if c == nil then
+            return false, 1
+        end
+    end
+    local n = 0
+    for f, c in falses, nil, nil do
+        if f ~= false then
+            return false
+        end
+        n = c
+        break
+    end
+    return n == 1
+end
+
+return
+    test_extra_returns() and
+    test_missing_returns() and
+    test_no_returns() and
+    test_callback_iterator() and
+    test_control_variable() and
+    test_false_control()
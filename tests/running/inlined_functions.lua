@@ -0,0 +1,91 @@
+local function test_accessors()
+    local function get_x(p)
+        return p.x
+    end
+    local function set_x(p, x)
+        p.x = x
+    end
+    local p = {x = 1}
+    local sum = 0
+    for i = 1, 3 do
+        set_x(p, get_x(p) + i)
+        sum = sum + get_x(p)
+    end
+    return p.x == 7 and sum == 13
+end
+
+local function test_results_and_arguments()
+    local function swap(a, b)
+        return b, a
+    end
+    local function count(...)
+        local a, b, c = ...
+        return c == nil and b ~= nil and 2 or -1
+    end
+    local x, y, z = swap(1, 2)
+    local calls = 0
+    local function side_effect()
+        calls = calls + 1
+        return calls
+    end
+    local only = swap(side_effect(), side_effect(), side_effect())
+    local missing, given = swap(nil, 3)
+    return x == 2 and y == 1 and z == nil and count(swap(1, 2)) == 2 and only == 2 and
+        calls == 3 and missing == 3 and given == nil
+end
+
+local function test_statements()
+    local count = 0
+    local function bump(by)
+        count = count + by
+    end
+    local function first_even(t)
+        local found
+        for i = 1, #t do
+            if t[i] % 2 == 0 then
+                found = t[i]
+                break
+            end
+        end
+        return found
+    end
+    for i = 1, 4 do
+        bump(i)
+    end
+    return count == 10 and first_even({1, 3, 4, 6}) == 4 and first_even({1}) == nil
+end
+
+local function test_scoping()
+    local x = 1
+    local function get_x()
+        return x
+    end
+    local function inc(x)
+        x = x + 1
+        return x
+    end
+    local ok = get_x() == 1 and inc(x) == 2 and x == 1
+    do
+        local x = 2
+        ok = ok and get_x() == 1 and inc(x) == 3
+    end
+    x = 5
+    return ok and get_x() == 5
+end
+
+local function test_nested()
+    local function double(n)
+        return n * 2
+    end
+    local function quadruple(n)
+        return double(double(n))
+    end
+    return quadruple(3) == 12
+end
+
+return
+    test_accessors() and
+    test_results_and_arguments() and
+    test_statements() and
+    test_scoping() and
+    test_nested()
@@ -0,0 +1,102 @@
+local function test_captured_locals()
+    local total = 0
+    local scale = 2
+    for i = 1, 5 do
+        local offset = i * 10
+        local function add(x)
+            return x * scale + offset
+        end
+        total = total + add(i)
+        scale = scale + 1
+    end
+    -- 1*2+10 + 2*3+20 + 3*4+30 + 4*5+40 + 5*6+50
+    return total == 220
+end
+
+local function test_locals_changed_between_calls()
+    local a = 1
+    local function get()
+        return a
+    end
+    local first = get()
+    a = 2
+    return first == 1 and get() == 2
+end
+
+local function test_locals_changed_by_closures()
+    local a = 1
+    local function set(v)
+        a = v
+    end
+    local function get_after(v)
+        set(v)
+        return a
+    end
+    return get_after(5) == 5 and a == 5
+end
+
+local function test_varargs_and_multiple_returns()
+    local prefix = "x"
+    local function join(first, ...)
+        local second, third = ...
+        return prefix .. first .. (second or "") .. (third or ""), second
+    end
+    local function three()
+        return "a", "b", "c"
+    end
+    local s, b = join(three())
+    local t, d = join(three(), "d")
+    local u, n = join("e")
+    return s == "xabc" and b == "b" and t == "xad" and d == "d" and u == "xe" and n == nil
+end
+
+local function test_shadowing()
+    local a = 1
+    local function f()
+        return a
+    end
+    local r1 = f()
+    do
+        local a = 2
+        if f() ~= 1 then
+            return false
+        end
+    end
+    local f = function()
+        return a + 10
+    end
+    return r1 == 1 and f() == 11
+end
+
+local function test_escaping()
+    local a = 1
+    local function f()
+        return a
+    end
+    local g = f
+    a = 3
+    local t = {f}
+    return g() == 3 and t[1]() == 3
+end
+
+local function test_repeat()
+    local n = 0
+    local limit = 3
+    repeat
+        local step = 1
+        local function next_value()
+            return n + step
+        end
+        n = next_value()
+    until next_value() > limit
+    return n == 3
+end
+
+return
+    test_captured_locals() and
+    test_locals_changed_between_calls() and
+    test_locals_changed_by_closures() and
+    test_varargs_and_multiple_returns() and
+    test_shadowing() and
+    test_escaping() and
+    test_repeat()
@@ -0,0 +1,40 @@
+function test1()
+    local f = load("local a, b = ... return a + b")
+    local g, err = load("return +")
+    return f(1, 2) == 3 and g == nil and type(err) == "string"
+end
+
+function test2()
+    -- Chunks loaded from a running chunk can load chunks themselves.
+    local f = load([[
+        local g = load("return 'inner', ...")
+        return g(...)
+    ]])
+    local a, b = f(10)
+    return a == "inner" and b == 10
+end
+
+function test3()
+    local env = {x = 5}
+    local f = load("x = x + 1 return x", "chunk", "t", env)
+    local g, err = load("return 1", "chunk", "b")
+    return f() == 6 and env.x == 6 and x == nil and g == nil and
+        err == "attempt to load a text chunk (mode is 'b')"
+end
+
+function test4()
+    local co = coroutine.create(function(source)
+        local f = load(source)
+        coroutine.yield(f())
+        return pcall(load("error('loaded error')"))
+    end)
+    local ok1, v = coroutine.resume(co, "return 7")
+    local ok2, ok3, err = coroutine.resume(co)
+    return ok1 and v == 7 and ok2 and not ok3 and err == "loaded error"
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4()
@@ -23,6 +23,24 @@ function test2()
     return t:method(42) == 42
 end
 
+function test3()
+    local t = {}
+    function t:values()
+        return 1, 2, 3
+    end
+
+    local a, b, c = t:values()
+    local function third(_, _, c)
+        return c
+    end
+    local function forward()
+        return t:values()
+    end
+
+    return a == 1 and b == 2 and c == 3 and third(t:values()) == 3 and third(forward()) == 3
+end
+
 return
     test1() and
-    test2()
+    test2() and
+    test3()
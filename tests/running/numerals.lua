@@ -0,0 +1,39 @@
+function test1()
+    -- Decimal integers too large for an integer become floats.
+    return
+        math.type(9223372036854775807) == "integer" and
+        9223372036854775807 == math.maxinteger and
+        math.type(9223372036854775808) == "float" and
+        9223372036854775808 == 2^63 and
+        math.type(-9223372036854775808) == "float" and
+        -9223372036854775808 == -2^63 and
+        -9223372036854775807 - 1 == math.mininteger and
+        math.type(18446744073709551616) == "float"
+end
+
+function test2()
+    -- Hex integers wrap around modulo 2^64.
+    return
+        0x7fffffffffffffff == math.maxinteger and
+        0x8000000000000000 == math.mininteger and
+        0xffffffffffffffff == -1 and
+        0x10000000000000000 == 0 and
+        0x1ffffffffffffffff == -1 and
+        -0x8000000000000000 == math.mininteger and
+        math.type(0xffffffffffffffff) == "integer"
+end
+
+function test3()
+    -- Strings are converted by the same rules.
+    return
+        "9223372036854775807" | 0 == math.maxinteger and
+        "9223372036854775808" + 0 == 2^63 and
+        "0xffffffffffffffff" | 0 == -1 and
+        "0x10000000000000000" + 0 == 0 and
+        pcall(function() return "9223372036854775808" | 0 end) == false
+end
+
+return
+    test1() and
+    test2() and
+    test3()
@@ -0,0 +1,59 @@
+local function test_find()
+    local s, e = string.find("hello world", "o w")
+    local s2, e2 = ("hello"):find("l+")
+    local s3, e3, cap = ("key = value"):find("(%w+)%s*=")
+    return s == 5 and e == 7 and
+        s2 == 3 and e2 == 4 and
+        s3 == 1 and e3 == 5 and cap == "key" and
+        ("a.b"):find(".", 1, true) == 2 and
+        ("abc"):find("", 10) == nil and
+        ("abc"):find("", 4) == 4 and
+        ("abc"):find("c", -1) == 3 and
+        ("abc"):find("^b") == nil and
+        ("abc"):find("b", 3) == nil
+end
+
+local function test_match()
+    local k, v = ("name=luster"):match("(%a+)=(%a+)")
+    local p1, word, p2 = ("  word  "):match("()(%S+)()")
+    return k == "name" and v == "luster" and
+        p1 == 3 and word == "word" and p2 == 7 and
+        ("2024-01-31"):match("%d+%-(%d+)") == "01" and
+        ("[[x]]"):match("%b[]") == "[[x]]" and
+        ("THE (quick) fox"):match("%f[%a]%a+", 5) == "quick" and
+        ("abcabc"):match("(a.-)c") == "ab" and
+        ("abcabc"):match("(a.*)c") == "abcab" and
+        ("xyyx"):match("(.)(.)%2%1") == "x" and
+        ("a]b"):match("[]]") == "]" and
+        ("a-b"):match("[%a-]+") == "a-b" and
+        ("f(a, b)"):match("^(%w+)%(") == "f" and
+        ("end"):match("d$") == "d" and
+        ("abc"):match("x?a?b?") == "ab"
+end
+
+local function test_classes()
+    return ("\v\t x"):match("^%s+") == "\v\t " and
+        ("a1_!"):match("%w+") == "a1" and
+        ("a1_!"):match("%p+") == "_!" and
+        ("\1A"):match("%c") == "\1" and
+        ("DEADbeefG"):match("%x+") == "DEADbeef" and
+        ("Hello"):match("%u%l+") == "Hello" and
+        ("caf\233"):match("%a+$") == nil and
+        ("caf\233"):match("%A") == "\233"
+end
+
+local function test_errors()
+    return not pcall(string.find, "a", "%") and
+        not pcall(string.find, "a", "[a") and
+        not pcall(string.match, "a", "(a") and
+        not pcall(string.match, "a", "a)") and
+        not pcall(string.match, "a", "%1") and
+        not pcall(string.match, "a", "%f") and
+        not pcall(string.match, "a", "%b")
+end
+
+return
+    test_find() and
+    test_match() and
+    test_classes() and
+    test_errors()
@@ -0,0 +1,3 @@
+#!/usr/bin/env lua
+local x = 1 + 2
+return x == 3
@@ -0,0 +1,65 @@
+local function test_format()
+    return ("%d"):format(1) == "1" and
+        string.format("%s-%5.2f", "x", 1.5) == "x- 1.50"
+end
+
+local function test_methods()
+    local s = "Hello"
+    local upper = "upper"
+    return s:upper() == "HELLO" and
+        s:lower() == "hello" and
+        s:len() == 5 and
+        s[upper](s) == "HELLO" and
+        s.len == string.len
+end
+
+local function test_sub()
+    local s = "abcdef"
+    return s:sub(2, 4) == "bcd" and
+        s:sub(-3) == "def" and
+        s:sub(0) == "abcdef" and
+        s:sub(5, 2) == "" and
+        s:sub(-100, 100) == "abcdef"
+end
+
+local function test_byte_char()
+    local a, b = ("AB"):byte(1, 2)
+    return a == 65 and b == 66 and string.char(72, 105) == "Hi"
+end
+
+local function test_rep()
+    local ok, err = pcall(string.rep, "x", math.maxinteger)
+    local sep_ok = pcall(string.rep, "", math.maxinteger, "ab")
+    return ("ab"):rep(3) == "ababab" and
+        string.rep("a", 3, ", ") == "a, a, a" and
+        string.rep("a", 1, ", ") == "a" and
+        string.rep("a", 0) == "" and
+        string.rep("a", -1, ", ") == "" and
+        string.rep(5, 2) == "55" and
+        string.rep("", math.maxinteger) == "" and
+        not ok and err == "resulting string too large" and
+        not sep_ok and
+        not pcall(string.rep, "a")
+end
+
+local function test_coroutine()
+    local co = coroutine.create(function()
+        return ("%d!"):format(3)
+    end)
+    local _, r = coroutine.resume(co)
+    return r == "3!"
+end
+
+local function test_missing()
+    local s = "abc"
+    return s.missing == nil and not pcall(function() return (1).x end)
+end
+
+return
+    test_format() and
+    test_methods() and
+    test_sub() and
+    test_byte_char() and
+    test_rep() and
+    test_coroutine() and
+    test_missing()
@@ -0,0 +1,34 @@
+#![cfg(feature = "serialize")]
+
+use serde_json::json;
+
+use luster::Lexer;
+
+#[test]
+fn serialize_tokens() {
+    let mut lexer = Lexer::new(&b"local x = 1\nprint(x, 'two')"[..], |s| {
+        String::from_utf8_lossy(s).into_owned()
+    });
+    let mut tokens = Vec::new();
+    while let Some((token, span)) = lexer.read_spanned_token().unwrap() {
+        tokens.push(json!({ "token": token, "span": span }));
+    }
+
+    assert_eq!(tokens.len(), 10);
+    assert_eq!(
+        tokens[0],
+        json!({
+            "token": "Local",
+            "span": { "line_number": 1, "column": 1, "start": 0, "end": 5 },
+        })
+    );
+    assert_eq!(tokens[1]["token"], json!({ "Name": "x" }));
+    assert_eq!(tokens[3]["token"], json!({ "Integer": 1 }));
+    assert_eq!(
+        tokens[8],
+        json!({
+            "token": { "String": "two" },
+            "span": { "line_number": 2, "column": 10, "start": 21, "end": 26 },
+        })
+    );
+}
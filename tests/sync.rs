@@ -0,0 +1,112 @@
+use std::thread;
+
+use luster::sync::{channel, Message};
+use luster::{Lua, OwnedValue, String};
+
+#[test]
+fn channels_between_threads() {
+    let (to_worker, worker_inbox) = channel();
+    let (worker_outbox, from_worker) = channel();
+
+    let worker = thread::spawn(move || {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, root| {
+            root.globals
+                .set(
+                    mc,
+                    String::new_static(b"inbox"),
+                    worker_inbox.into_table(mc),
+                )
+                .unwrap();
+            root.globals
+                .set(
+                    mc,
+                    String::new_static(b"outbox"),
+                    worker_outbox.to_table(mc),
+                )
+                .unwrap();
+        });
+        lua.run_string(
+            &br#"
+                while true do
+                    local ok, job = inbox:recv()
+                    if not ok then
+                        return job
+                    end
+                    outbox:send({name = job.name, result = job.value * 2})
+                end
+            "#[..],
+        )
+        .unwrap()
+    });
+
+    for i in 1..=3 {
+        to_worker
+            .send(Message::Table(vec![
+                (
+                    Message::String(b"name".to_vec()),
+                    Message::String(b"job".to_vec()),
+                ),
+                (Message::String(b"value".to_vec()), Message::Integer(i)),
+            ]))
+            .unwrap();
+    }
+    drop(to_worker);
+
+    let mut results = Vec::new();
+    while let Some(message) = from_worker.recv() {
+        results.push(message);
+    }
+    assert_eq!(
+        worker.join().unwrap(),
+        vec![OwnedValue::String(b"closed".to_vec())]
+    );
+    assert_eq!(results.len(), 3);
+    match &results[2] {
+        Message::Table(entries) => {
+            assert!(entries.contains(&(Message::String(b"result".to_vec()), Message::Integer(6))))
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn unsendable_values() {
+    let (sender, receiver) = channel();
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        root.globals
+            .set(mc, String::new_static(b"sender"), sender.to_table(mc))
+            .unwrap();
+        root.globals
+            .set(mc, String::new_static(b"receiver"), receiver.into_table(mc))
+            .unwrap();
+    });
+    let values = lua
+        .run_string(
+            &br#"
+                local t = {}
+                t.t = t
+                local a = pcall(sender.send, sender, print)
+                local b = pcall(sender.send, sender, t)
+                local c = sender:send("hello")
+                local _, value = receiver:try_recv()
+                local empty, reason = receiver:try_recv()
+                local _, timeout = receiver:recv(0.001)
+                return a, b, c, value, empty, reason, timeout
+            "#[..],
+        )
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::Boolean(false),
+            OwnedValue::Boolean(false),
+            OwnedValue::Boolean(true),
+            OwnedValue::String(b"hello".to_vec()),
+            OwnedValue::Boolean(false),
+            OwnedValue::String(b"empty".to_vec()),
+            OwnedValue::String(b"timeout".to_vec()),
+        ]
+    );
+}
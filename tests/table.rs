@@ -0,0 +1,102 @@
+use luster::{heap_census, Lua, OwnedValue, String, Table, Value};
+
+#[test]
+fn metatable_version() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let table = Table::new(mc);
+        let mut version = table.metatable_version();
+
+        // The first entry has to allocate storage.
+        table.set(mc, String::new_static(b"a"), 1).unwrap();
+        assert!(table.metatable_version() > version);
+        version = table.metatable_version();
+
+        // Overwriting and removing entries keeps the layout.
+        table.set(mc, String::new_static(b"a"), 2).unwrap();
+        table.set(mc, String::new_static(b"a"), Value::Nil).unwrap();
+        assert_eq!(table.metatable_version(), version);
+
+        // Growing the array part eventually moves entries.
+        let mut bumps = 0;
+        for i in 1..=64 {
+            table.set(mc, i, true).unwrap();
+            let new_version = table.metatable_version();
+            assert!(new_version >= version);
+            if new_version > version {
+                bumps += 1;
+            }
+            version = new_version;
+        }
+        assert!(bumps > 1);
+        assert_eq!(table.length(), 64);
+    });
+}
+
+// The estimated size of the global table `t`.
+fn table_bytes(lua: &mut Lua) -> usize {
+    lua.mutate(|_, root| {
+        heap_census(root, usize::MAX)
+            .largest_tables
+            .into_iter()
+            .find(|table| table.path == ["globals", r#"["t"]"#])
+            .unwrap()
+            .bytes
+    })
+}
+
+#[test]
+fn shrink_tables() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let table = Table::new(mc);
+        for i in 1..=1000 {
+            table.set(mc, i, i).unwrap();
+            table.set(mc, -i, i).unwrap();
+        }
+        root.globals
+            .set(mc, String::new_static(b"t"), table)
+            .unwrap();
+    });
+    let full = table_bytes(&mut lua);
+
+    // Removing most entries eventually shrinks the table on its own.
+    lua.mutate(|mc, root| {
+        let table = match root.globals.get(String::new_static(b"t")) {
+            Value::Table(table) => table,
+            _ => unreachable!(),
+        };
+        for i in 1..=990 {
+            table.set(mc, i, Value::Nil).unwrap();
+            table.set(mc, -i, Value::Nil).unwrap();
+        }
+    });
+    let shrunk = table_bytes(&mut lua);
+    assert!(shrunk < full / 4);
+
+    let results = lua
+        .run_string(
+            br#"
+                local present = true
+                for i = 991, 1000 do
+                    present = present and t[i] == i and t[-i] == i
+                    t[i] = nil
+                end
+                t.x = 1
+                table.compact(t)
+                return present, #t, t.x, t[-1000]
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        results,
+        [
+            OwnedValue::Boolean(true),
+            OwnedValue::Integer(0),
+            OwnedValue::Integer(1),
+            OwnedValue::Integer(1000),
+        ]
+    );
+    let compacted = table_bytes(&mut lua);
+    assert!(compacted < shrunk);
+}
@@ -0,0 +1,50 @@
+use luster::{Lua, OwnedValue, StaticError, StdLib};
+
+#[test]
+fn test_framework() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            test: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    lua.run_string(
+        &br#"
+            local t = luster.test
+            t.describe("math", function()
+                t.it("adds", function()
+                    t.assert_eq(1 + 1, 2)
+                end)
+                t.it("compares tables", function()
+                    t.assert_eq({1, {2}}, {1, {2}})
+                end)
+                t.it("fails", function()
+                    t.assert_eq({1, 2}, {1, 3}, "tables differ")
+                end)
+                t.it("raises", function()
+                    t.assert_error(error, "boom")
+                end)
+                t.it("does not raise", function()
+                    t.assert_error(function() end)
+                end)
+            end)
+        "#[..],
+    )?;
+
+    let values = lua.run_string(&b"return luster.test.report()"[..])?;
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::Integer(3),
+            OwnedValue::Integer(2),
+            OwnedValue::String(
+                b"math fails: tables differ, expected {[1] = 1, [2] = 3} but got {[1] = 1, [2] = 2}\n\
+                  math does not raise: expected an error but none was raised\n"
+                    .to_vec()
+            ),
+        ]
+    );
+
+    Ok(())
+}
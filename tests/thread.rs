@@ -0,0 +1,46 @@
+use luster::{compile, Closure, Function, Lua, Thread, ThreadMode, Value};
+
+#[test]
+fn reset_finished_thread() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &b"local a = ... return a * 2"[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let thread = Thread::new(mc, false);
+        thread.set_max_string_len(mc, 64);
+
+        // Running threads cannot be reset.
+        thread
+            .start(mc, Function::Closure(closure), &[Value::Integer(1)])
+            .unwrap();
+        assert!(thread.reset(mc, Function::Closure(closure)).is_err());
+
+        for i in 0..3 {
+            while thread.mode() == ThreadMode::Running {
+                thread.step(mc).unwrap();
+            }
+            // Results that were never taken are discarded.
+            assert_eq!(thread.mode(), ThreadMode::Results);
+            thread.reset(mc, Function::Closure(closure)).unwrap();
+            assert_eq!(thread.mode(), ThreadMode::Suspended);
+            thread.resume(mc, &[Value::Integer(i)]).unwrap();
+        }
+        while thread.mode() == ThreadMode::Running {
+            thread.step(mc).unwrap();
+        }
+        assert_eq!(
+            thread.take_results(mc).unwrap().unwrap(),
+            vec![Value::Integer(4)]
+        );
+        assert_eq!(thread.max_string_len(), 64);
+    });
+}
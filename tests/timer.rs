@@ -0,0 +1,125 @@
+use luster::{compile, Closure, Function, Lua, StdLib, String, Value};
+
+#[test]
+fn timers() {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            timer: true,
+            ..StdLib::default()
+        })
+        .build();
+
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &br#"
+                    log = ""
+                    timer.after(1.5, function()
+                        log = log .. "after@" .. timer.now() .. " "
+                    end)
+                    local ticks = 0
+                    timer.every(1, function()
+                        ticks = ticks + 1
+                        log = log .. "tick" .. ticks .. " "
+                        return ticks < 3
+                    end)
+                    local cancelled = timer.after(1, function()
+                        log = log .. "never "
+                    end)
+                    timer.cancel(cancelled)
+                    timer.after(0, function()
+                        log = log .. "sleeping "
+                        timer.sleep(2)
+                        log = log .. "woke@" .. timer.now() .. " "
+                    end)
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.executor.spawn(mc, Function::Closure(closure));
+    });
+
+    for _ in 0..5 {
+        lua.mutate(|mc, root| {
+            assert!(root.executor.run(mc, 100).is_empty());
+            root.executor.advance(mc, 0.5);
+        });
+    }
+    lua.mutate(|mc, root| {
+        assert!(root.executor.run(mc, 100).is_empty());
+    });
+
+    lua.mutate(|mc, root| {
+        assert_eq!(
+            root.globals.get(String::new_static(b"log")),
+            Value::String(String::new(mc, b"sleeping tick1 after@1.5 tick2 woke@2.5 "))
+        );
+        assert_eq!(root.executor.task_count(), 1);
+        root.executor.advance(mc, 1.0);
+        assert!(root.executor.run(mc, 100).is_empty());
+        assert_eq!(root.executor.task_count(), 0);
+    });
+}
+
+#[test]
+fn task_errors() {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            timer: true,
+            ..StdLib::default()
+        })
+        .build();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(mc, root.interned_strings, &b"error('boom')"[..]).unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        let task = root.executor.spawn(mc, Function::Closure(closure));
+        let errors = root.executor.run(mc, 100);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].task, task);
+        assert_eq!(
+            errors[0].to_string(),
+            format!("task {} failed: runtime error: boom", task.0)
+        );
+        assert_eq!(root.executor.task_count(), 0);
+    });
+}
+
+#[test]
+fn tasks_inherit_thread_settings() {
+    let mut lua = Lua::builder()
+        .stdlib(StdLib {
+            timer: true,
+            ..StdLib::default()
+        })
+        .build();
+    lua.mutate(|mc, root| {
+        let closure = Closure::new(
+            mc,
+            compile(
+                mc,
+                root.interned_strings,
+                &br#"
+                    upper = ("ab"):upper()
+                "#[..],
+            )
+            .unwrap(),
+            Some(root.globals),
+        )
+        .unwrap();
+        root.executor.spawn(mc, Function::Closure(closure));
+        assert!(root.executor.run(mc, 100).is_empty());
+        assert_eq!(
+            root.globals.get(String::new_static(b"upper")),
+            Value::String(String::new(mc, b"AB"))
+        );
+    });
+}
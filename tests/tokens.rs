@@ -0,0 +1,75 @@
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile_tokens, parse_tokens, Closure, Error, Function, Lexer, LineNumber, Lua, ParserError,
+    Span, StaticError, ThreadSequence, Token, Value,
+};
+
+#[test]
+fn compile_rewritten_tokens() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    let result = lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            // A tiny macro system: every `twice(x)` becomes `(x) * 2`.
+            let mut lexer = Lexer::new(&b"local a = 20 return twice(a + 1)"[..], |s| {
+                root.interned_strings.new_string(mc, s)
+            });
+            let mut tokens = Vec::new();
+            let mut in_macro = false;
+            while let Some((token, span)) = lexer
+                .read_spanned_token()
+                .map_err(ParserError::LexerError)?
+            {
+                match token {
+                    Token::Name(name) if name.as_bytes() == b"twice" => in_macro = true,
+                    Token::RightParen if in_macro => {
+                        in_macro = false;
+                        tokens.push((Token::RightParen, span));
+                        tokens.push((Token::Mul, span));
+                        tokens.push((Token::Integer(2), span));
+                    }
+                    token => tokens.push((token, span)),
+                }
+            }
+            Ok(Closure::new(
+                mc,
+                compile_tokens(mc, tokens)?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|values| matches!(values[..], [Value::Integer(42)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+    assert!(result);
+    Ok(())
+}
+
+#[test]
+fn parse_synthesized_tokens() {
+    let span = Span {
+        line_number: LineNumber(1),
+        column: 1,
+        start: 0,
+        end: 0,
+    };
+    let tokens = vec![Token::<&str>::Return, Token::Integer(1), Token::Add];
+    assert!(parse_tokens(tokens.into_iter().map(|t| (t, span))).is_err());
+
+    let tokens = vec![
+        Token::Local,
+        Token::Name("x"),
+        Token::Assign,
+        Token::Integer(1),
+    ];
+    let chunk = parse_tokens(tokens.into_iter().map(|t| (t, span))).unwrap();
+    assert_eq!(chunk.block.statements.len(), 1);
+    assert_eq!(chunk.block.statements[0].span, span);
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "trace")]
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use luster::trace::OpcodeTrace;
+use luster::{compile, Closure, Function, Lua, ThreadMode, Value};
+
+type Fields = Vec<(&'static str, std::string::String)>;
+
+#[derive(Default)]
+struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+struct FieldVisitor(Fields);
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "luster::vm"
+    }
+
+    fn new_span(&self, _: &Attributes) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut visitor = FieldVisitor(Vec::new());
+        event.record(&mut visitor);
+        self.0.lock().unwrap().push(visitor.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+fn traced_events(trace: OpcodeTrace) -> (u32, Vec<Fields>) {
+    let recorder = Recorder::default();
+    let events = recorder.0.clone();
+
+    let instructions = tracing::subscriber::with_default(recorder, || {
+        let mut lua = Lua::new();
+        lua.mutate(|mc, root| {
+            let closure = Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &b"local a = 1\nlocal b = a + 2\nreturn b"[..],
+                )
+                .unwrap(),
+                Some(root.globals),
+            )
+            .unwrap();
+            let thread = root.main_thread;
+            thread.set_opcode_trace(mc, Some(trace));
+            thread.start(mc, Function::Closure(closure), &[]).unwrap();
+            let mut instructions = 0;
+            while thread.mode() == ThreadMode::Running {
+                thread.step_instructions(mc, 1).unwrap();
+                instructions += 1;
+            }
+            assert_eq!(
+                thread.take_results(mc).unwrap().unwrap(),
+                vec![Value::Integer(3)]
+            );
+            instructions
+        })
+    });
+
+    let events = events.lock().unwrap().clone();
+    (instructions, events)
+}
+
+#[test]
+fn trace_every_opcode() {
+    let (instructions, events) = traced_events(OpcodeTrace::new(1).with_registers(2));
+    assert_eq!(events.len(), instructions as usize);
+
+    let first = &events[0];
+    let names = first.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    assert_eq!(names, ["opcode", "pc", "function", "line", "registers"]);
+    assert!(first[0].1.starts_with("LoadConstant"));
+    assert_eq!(first[1].1, "0");
+    assert_eq!(first[3].1, "1");
+    assert_eq!(first[4].1, "nil, nil");
+
+    let second = &events[1];
+    assert_eq!(second[3].1, "2");
+    assert_eq!(second[4].1, "1, nil");
+}
+
+#[test]
+fn trace_sampling() {
+    let (instructions, events) = traced_events(OpcodeTrace::new(2));
+    assert_eq!(events.len(), (instructions as usize + 1) / 2);
+    assert_eq!(events[1][1].1, "2");
+}
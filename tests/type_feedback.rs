@@ -0,0 +1,79 @@
+use gc_arena::Gc;
+use luster::{compile, Closure, Function, Lua, OpCode, ThreadMode, TypeCounts, TypeFeedback};
+
+const SCRIPT: &[u8] = br#"local function add(a, b)
+    return a + b
+end
+local t = {x = 1}
+for i = 1, 3 do
+    add(i, 1)
+end
+add(1.5, 2)
+local co = coroutine.create(function()
+    return add(t.x, 0.5)
+end)
+coroutine.resume(co)
+return t.x
+"#;
+
+#[test]
+fn type_feedback() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = Gc::allocate(mc, compile(mc, root.interned_strings, SCRIPT).unwrap());
+        let type_feedback = TypeFeedback::new(mc);
+        type_feedback.track(mc, proto);
+
+        let closure = Closure::new_with_proto(mc, proto, Some(root.globals)).unwrap();
+        root.main_thread.set_type_feedback(mc, Some(type_feedback));
+        root.main_thread
+            .start(mc, Function::Closure(closure), &[])
+            .unwrap();
+        while root.main_thread.mode() == ThreadMode::Running {
+            root.main_thread.step(mc).unwrap();
+        }
+
+        let add = proto.prototypes[0];
+        let instructions = type_feedback.instructions(add).unwrap();
+        assert_eq!(instructions.len(), 1);
+        assert!(matches!(instructions[0].opcode, OpCode::AddRR { .. }));
+        // The last call is made from inside the coroutine.
+        assert_eq!(
+            instructions[0].operands,
+            [
+                TypeCounts {
+                    integer: 4,
+                    float: 1,
+                    ..TypeCounts::default()
+                },
+                TypeCounts {
+                    integer: 4,
+                    float: 1,
+                    ..TypeCounts::default()
+                },
+            ]
+        );
+
+        let table_reads = type_feedback
+            .protos()
+            .into_iter()
+            .flat_map(|proto| type_feedback.instructions(proto).unwrap())
+            .filter(|i| matches!(i.opcode, OpCode::GetTableC { .. }))
+            .map(|i| i.operands[0].table)
+            .sum::<u64>();
+        // `coroutine.create`, `coroutine.resume` and `t.x` in the main chunk, `t` is an upvalue
+        // in the coroutine.
+        assert_eq!(table_reads, 3);
+
+        let dump = type_feedback.dump();
+        let mut lines = dump.lines();
+        assert_eq!(
+            lines.next(),
+            Some("function\tpc\tline\topcode\toperand\tinteger\tfloat\tstring\ttable\tother")
+        );
+        assert!(dump.contains("\tAddRR\t1\t4\t1\t0\t0\t0\n"));
+
+        let untracked = Gc::allocate(mc, compile(mc, root.interned_strings, &b""[..]).unwrap());
+        assert_eq!(type_feedback.instructions(untracked), None);
+    });
+}
@@ -0,0 +1,148 @@
+use std::string::String as StdString;
+
+use luster::{
+    validate_api, Callback, CallbackResult, Lua, OwnedValue, SchemaError, Signature, String, Table,
+    Value,
+};
+
+fn api_lua() -> Lua {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let api = Table::new(mc);
+        api.set(
+            mc,
+            String::new_static(b"spawn"),
+            Callback::new_immediate(mc, |args| {
+                Ok(CallbackResult::Return(vec![
+                    args[0],
+                    Value::Integer(args.len() as i64),
+                ]))
+            }),
+        )
+        .unwrap();
+        api.set(
+            mc,
+            String::new_static(b"log"),
+            Callback::new_immediate(mc, |args| {
+                Ok(CallbackResult::Return(vec![Value::Integer(
+                    args.len() as i64
+                )]))
+            }),
+        )
+        .unwrap();
+        validate_api(
+            mc,
+            api,
+            &[
+                ("spawn", "string, integer|boolean, table?"),
+                ("log", "any, ..."),
+            ],
+        )
+        .unwrap();
+        root.globals
+            .set(mc, String::new_static(b"api"), api)
+            .unwrap();
+    });
+    lua
+}
+
+#[test]
+fn valid_calls() {
+    let mut lua = api_lua();
+    let results = lua
+        .run_string(
+            &br#"
+                local name1, count1 = api.spawn("worker", 3)
+                local name2, count2 = api.spawn("worker", true, {})
+                local name3 = api.spawn("worker", 2.0, nil)
+                return name1, count1, name2, count2, name3, api.log(nil, 1, 2, 3)
+            "#[..],
+        )
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            OwnedValue::String(b"worker".to_vec()),
+            OwnedValue::Integer(2),
+            OwnedValue::String(b"worker".to_vec()),
+            OwnedValue::Integer(3),
+            OwnedValue::String(b"worker".to_vec()),
+            OwnedValue::Integer(4),
+        ]
+    );
+}
+
+#[test]
+fn invalid_calls() {
+    let mut lua = api_lua();
+    let errors = lua
+        .run_string(
+            &br#"
+                local errors = {}
+                local function check(i, f, ...)
+                    local ok, err = pcall(f, ...)
+                    if not ok then
+                        errors[i] = err
+                    end
+                end
+                check(1, api.spawn)
+                check(2, api.spawn, "worker", 1.5)
+                check(3, api.spawn, "worker", 1, 2)
+                check(4, api.spawn, "worker", 1, {}, 4)
+                check(5, api.log)
+                return errors[1], errors[2], errors[3], errors[4], errors[5]
+            "#[..],
+        )
+        .unwrap();
+    let errors = errors
+        .into_iter()
+        .map(|e| match e {
+            OwnedValue::String(e) => StdString::from_utf8(e).unwrap(),
+            e => panic!("error is not a string: {:?}", e),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        vec![
+            "bad argument #1 to 'spawn' (string expected, got no value)",
+            "bad argument #2 to 'spawn' (integer or boolean expected, got number)",
+            "bad argument #3 to 'spawn' (table expected, got number)",
+            "bad argument #4 to 'spawn' (no value expected)",
+            "bad argument #1 to 'log' (value expected, got no value)",
+        ]
+    );
+}
+
+#[test]
+fn invalid_schemas() {
+    assert_eq!(
+        Signature::parse("string, int"),
+        Err(SchemaError::UnknownType("int".to_owned()))
+    );
+    assert_eq!(
+        Signature::parse("..., string"),
+        Err(SchemaError::MisplacedVarargs)
+    );
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let api = Table::new(mc);
+        api.set(
+            mc,
+            String::new_static(b"f"),
+            Callback::new_immediate(mc, |_| Ok(CallbackResult::Return(vec![]))),
+        )
+        .unwrap();
+        api.set(mc, String::new_static(b"version"), 3).unwrap();
+
+        assert_eq!(
+            validate_api(mc, api, &[]),
+            Err(SchemaError::Unlisted("f".to_owned()))
+        );
+        assert_eq!(
+            validate_api(mc, api, &[("f", ""), ("version", "")]),
+            Err(SchemaError::NotAFunction("version".to_owned()))
+        );
+        assert!(validate_api(mc, api, &[("f", "")]).is_ok());
+    });
+}
@@ -0,0 +1,55 @@
+#![cfg(feature = "vecmath")]
+
+use std::f64::consts::FRAC_PI_2;
+
+use luster::vecmath::{load_vecmath, Mat4, Vec2, Vec3};
+use luster::{Lua, OwnedValue, StaticError};
+
+fn approx_eq(a: Vec3, b: Vec3) -> bool {
+    (a - b).length() < 1e-9
+}
+
+#[test]
+fn vector_math() {
+    let a = Vec3::new(1.0, 0.0, 0.0);
+    let b = Vec3::new(0.0, 1.0, 0.0);
+    assert_eq!(a.cross(b), Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(a.dot(b), 0.0);
+    assert_eq!((a + b) * 2.0, Vec3::new(2.0, 2.0, 0.0));
+    assert_eq!(Vec2::new(3.0, 4.0).length(), 5.0);
+    assert_eq!(Vec2::new(0.0, 0.0).normalize(), Vec2::new(0.0, 0.0));
+
+    let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0)) * Mat4::rotation_z(FRAC_PI_2);
+    assert!(approx_eq(m * a, Vec3::new(1.0, 3.0, 3.0)));
+    assert!(approx_eq(m.transform_vector(a), b));
+    assert_eq!(m * Mat4::identity(), m);
+    assert_eq!(m.transpose().transpose(), m);
+}
+
+#[test]
+fn vecmath_library() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| load_vecmath(mc, root, root.globals));
+    let values = lua.run_string(
+        &br#"
+            local v = vec3.add(vec3.new(1, 2, 3), {x = 1, y = 1, z = 1})
+            local m = mat4.mul(mat4.translation(10, 0, 0), mat4.scale(2, 2, 2))
+            local p = mat4.transform_point(m, v)
+            local n = vec2.normalize(vec2.mul(vec2.new(3, 4), {x = 0, y = 2}))
+            return p.x, p.y, p.z, vec3.dot(v, v), n.x, #m, pcall(vec2.add, 1, 2)
+        "#[..],
+    )?;
+    assert_eq!(
+        values[..7],
+        [
+            OwnedValue::Number(14.0),
+            OwnedValue::Number(6.0),
+            OwnedValue::Number(8.0),
+            OwnedValue::Number(29.0),
+            OwnedValue::Number(0.0),
+            OwnedValue::Integer(16),
+            OwnedValue::Boolean(false),
+        ]
+    );
+    Ok(())
+}
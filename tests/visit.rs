@@ -0,0 +1,95 @@
+use luster::parse_chunk;
+use luster::parser::{
+    Expression, FunctionDefinition, HeadExpression, PrimaryExpression, SimpleExpression, Spanned,
+};
+use luster::visit::{
+    walk_expression_mut, walk_function_definition, walk_primary_expression, Visitor, VisitorMut,
+};
+
+fn parse(source: &str) -> luster::parser::Chunk<String> {
+    parse_chunk(source.as_bytes(), |s| {
+        String::from_utf8(s.to_vec()).unwrap()
+    })
+    .unwrap()
+}
+
+// Records every variable read, along with how deeply nested in functions it is.
+#[derive(Default)]
+struct Names {
+    depth: usize,
+    names: Vec<(String, usize)>,
+}
+
+impl Visitor<String> for Names {
+    fn visit_primary_expression(&mut self, expression: &Spanned<PrimaryExpression<String>>) {
+        if let PrimaryExpression::Name(name) = &expression.node {
+            self.names.push((name.clone(), self.depth));
+        }
+        walk_primary_expression(self, expression);
+    }
+
+    fn visit_function_definition(&mut self, definition: &FunctionDefinition<String>) {
+        self.depth += 1;
+        walk_function_definition(self, definition);
+        self.depth -= 1;
+    }
+}
+
+#[test]
+fn visit_names() {
+    let chunk = parse(
+        r#"
+            local x = a + (b)
+            if c then
+                d.e[f] = function() return g(h, { i, [j] = k }) end
+            elseif l then
+                for m = n, o do end
+            end
+            for _ in p do repeat until q end
+            r:s(t)
+            return u
+        "#,
+    );
+    let mut names = Names::default();
+    names.visit_chunk(&chunk);
+    let expected = [
+        "a", "b", "c", "d", "f", "g", "h", "i", "j", "k", "l", "n", "o", "p", "q", "r", "t", "u",
+    ];
+    assert_eq!(
+        names
+            .names
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>(),
+        expected
+    );
+    assert_eq!(
+        names
+            .names
+            .iter()
+            .filter(|(_, depth)| *depth == 1)
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>(),
+        ["g", "h", "i", "j", "k"]
+    );
+}
+
+// Doubles every integer literal.
+struct Double;
+
+impl VisitorMut<String> for Double {
+    fn visit_expression_mut(&mut self, expression: &mut Expression<String>) {
+        if let HeadExpression::Simple(SimpleExpression::Integer(i)) = &mut *expression.head {
+            *i *= 2;
+        }
+        walk_expression_mut(self, expression);
+    }
+}
+
+#[test]
+fn rewrite_literals() {
+    let mut chunk = parse("local t = { 1, f(2 + 3) } while -4 do end");
+    Double.visit_chunk_mut(&mut chunk);
+    // The doubled literals are the same length, so even the spans match.
+    assert_eq!(chunk, parse("local t = { 2, f(4 + 6) } while -8 do end"));
+}
@@ -0,0 +1,54 @@
+use luster::walk::{walk, VisitedSet};
+use luster::{Lua, OwnedValue, String, Table, Value};
+
+#[test]
+fn walk_self_referential() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let a = Table::new(mc);
+        a.set(mc, String::new_static(b"self"), a).unwrap();
+        a.set(mc, 1, 10).unwrap();
+
+        let mut visited = Vec::new();
+        walk(Value::Table(a), |value| visited.push(value));
+        assert_eq!(visited.len(), 4);
+        assert_eq!(visited[0], Value::Table(a));
+        assert!(visited.contains(&Value::Integer(10)));
+        assert_eq!(visited.iter().filter(|&&v| v == Value::Table(a)).count(), 1);
+
+        assert_eq!(Value::Table(a).debug_fmt(8), "{[1] = 10, self = <cycle>}");
+        assert_eq!(
+            OwnedValue::from_value(Value::Table(a)).get(&OwnedValue::String(b"self".to_vec())),
+            Some(&OwnedValue::RecursiveTable)
+        );
+    });
+}
+
+#[test]
+fn walk_mutually_referential() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, _| {
+        let a = Table::new(mc);
+        let b = Table::new(mc);
+        a.set(mc, String::new_static(b"b"), b).unwrap();
+        b.set(mc, String::new_static(b"a"), a).unwrap();
+        // Reachable twice without forming a cycle.
+        let shared = Table::new(mc);
+        a.set(mc, 1, shared).unwrap();
+        b.set(mc, 1, shared).unwrap();
+
+        let mut tables = VisitedSet::new();
+        walk(Value::Table(a), |value| {
+            if let Value::Table(t) = value {
+                assert!(tables.insert(t), "table visited twice");
+            }
+        });
+        assert_eq!(tables.len(), 3);
+        assert!(tables.contains(&a) && tables.contains(&b) && tables.contains(&shared));
+
+        assert_eq!(
+            Value::Table(a).debug_fmt(8),
+            "{[1] = {}, b = {[1] = {}, a = <cycle>}}"
+        );
+    });
+}
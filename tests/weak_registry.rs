@@ -0,0 +1,43 @@
+use luster::{Callback, CallbackResult, Lua, String, Table, Value, WeakRegistryKey};
+
+#[test]
+fn weak_registry_keys() {
+    let mut lua = Lua::new();
+    let (kept, dropped, callback, name) = lua.mutate(|mc, root| {
+        let kept = Table::new(mc);
+        root.globals
+            .set(mc, String::new_static(b"kept"), kept)
+            .unwrap();
+        let callback = Callback::new_immediate(mc, |_| Ok(CallbackResult::Return(vec![])));
+        (
+            WeakRegistryKey::new(mc, Value::Table(kept)),
+            WeakRegistryKey::new(mc, Value::Table(Table::new(mc))),
+            WeakRegistryKey::new(mc, callback.into()),
+            WeakRegistryKey::new(mc, Value::String(String::new(mc, b"entity 1"))),
+        )
+    });
+    assert!(kept.is_alive());
+
+    lua.collect_all();
+    assert!(kept.is_alive());
+    assert!(!dropped.is_alive());
+    assert!(!callback.is_alive());
+    // Strings are copied into their keys, and so never die.
+    assert!(name.is_alive());
+    lua.mutate(|mc, root| {
+        assert_eq!(
+            kept.upgrade(mc),
+            Some(root.globals.get(String::new_static(b"kept")))
+        );
+        assert_eq!(dropped.upgrade(mc), None);
+        assert_eq!(
+            name.upgrade(mc),
+            Some(Value::String(String::new_static(b"entity 1")))
+        );
+    });
+
+    lua.run_string(b"kept = nil").unwrap();
+    lua.collect_all();
+    assert!(!kept.is_alive());
+    assert!(lua.mutate(move |mc, _| kept.upgrade(mc).is_none()));
+}